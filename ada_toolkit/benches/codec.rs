@@ -0,0 +1,197 @@
+//! Criterion benchmarks for the core codec: compression and decompression of
+//! each generated profile at a few sizes, reported in MiB/s, plus the RLE
+//! run scanner and literal path on their own, both ways, encoding with and
+//! without checksums, decoding a high-ratio frame into output sized up
+//! front and grown as it goes, and streaming into a writer that is slow
+//! per call with and without [`BatchWriter`].
+//!
+//! Run with `cargo bench --bench codec`; add a filter such as
+//! `compress/text` to run one group or input.
+
+use std::io::{self, IoSlice, Write};
+use std::time::Duration;
+
+use ada_toolkit::stream::AapcWriter;
+use ada_toolkit::{
+    compress, compress_with_options, copy_encode, copy_encode_pipelined, copy_encode_slice, decompress, decompress_into,
+    decompress_to_writer, BatchWriter, CompressOptions,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+#[path = "../src/generate.rs"]
+mod generate;
+
+use generate::{generate, Profile};
+
+/// Seed for every corpus, so that runs measure the same bytes.
+const SEED: u64 = 0xADA;
+
+const SIZES: [(usize, &str); 3] = [(64 << 10, "64KiB"), (1 << 20, "1MiB"), (16 << 20, "16MiB")];
+
+const PROFILES: [(Profile, &str); 4] =
+    [(Profile::Runs, "runs"), (Profile::Random, "random"), (Profile::Text, "text"), (Profile::Mixed, "mixed")];
+
+fn corpus(profile: Profile, size: usize) -> Vec<u8> {
+    generate(profile, size, &mut StdRng::seed_from_u64(SEED))
+}
+
+fn codec(c: &mut Criterion) {
+    let mut compress_group = c.benchmark_group("compress");
+    compress_group.sample_size(20);
+    for (profile, name) in PROFILES {
+        for (size, size_name) in SIZES {
+            let data = corpus(profile, size);
+            compress_group.throughput(Throughput::Bytes(size as u64));
+            compress_group.bench_with_input(BenchmarkId::new(name, size_name), &data, |b, data| {
+                b.iter(|| compress(data))
+            });
+        }
+    }
+    compress_group.finish();
+
+    let mut decompress_group = c.benchmark_group("decompress");
+    decompress_group.sample_size(20);
+    for (profile, name) in PROFILES {
+        for (size, size_name) in SIZES {
+            let compressed = compress(&corpus(profile, size));
+            decompress_group.throughput(Throughput::Bytes(size as u64));
+            decompress_group.bench_with_input(BenchmarkId::new(name, size_name), &compressed, |b, compressed| {
+                b.iter(|| decompress(compressed).unwrap())
+            });
+        }
+    }
+    decompress_group.finish();
+}
+
+/// The RLE pass alone: checksums are off, so that coding a long run of one
+/// byte times the run scanner, and coding text, which has few runs, times
+/// the literal path.
+fn rle(c: &mut Criterion) {
+    let opts = CompressOptions { block_checksums: false, content_checksum: false, ..CompressOptions::default() };
+    let size = 1 << 20;
+    let inputs = [("run_scanner", vec![0u8; size]), ("literals", corpus(Profile::Text, size))];
+    let mut group = c.benchmark_group("rle");
+    group.throughput(Throughput::Bytes(size as u64));
+    for (name, data) in &inputs {
+        group.bench_function(*name, |b| b.iter(|| compress_with_options(data, &opts).unwrap()));
+        let compressed = compress_with_options(data, &opts).unwrap();
+        group.bench_function(format!("{name}_decode"), |b| b.iter(|| decompress(&compressed).unwrap()));
+    }
+    group.finish();
+}
+
+/// Mixed data encoded with both checksums and with neither, in memory and
+/// by each streaming path the command line takes, on one thread: the
+/// difference should be about what one CRC-32 pass over the input costs,
+/// `crc32_alone`, as each byte is hashed once for its block and folded
+/// into the content checksum, which the totals hand back. `two_passes`
+/// hashes the input again after encoding it, for comparison.
+fn checksums(c: &mut Criterion) {
+    let data = corpus(Profile::Mixed, 16 << 20);
+    let mut group = c.benchmark_group("checksums");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    for (name, on) in [("on", true), ("off", false)] {
+        let opts =
+            CompressOptions { block_checksums: on, content_checksum: on, threads: 1, ..CompressOptions::default() };
+        group.bench_function(name, |b| b.iter(|| compress_with_options(&data, &opts).unwrap()));
+        group.bench_function(format!("{name}_read"), |b| {
+            b.iter(|| copy_encode(data.as_slice(), io::sink(), &opts, None).unwrap())
+        });
+        group.bench_function(format!("{name}_pipelined"), |b| {
+            b.iter(|| copy_encode_pipelined(data.as_slice(), io::sink(), &opts, None).unwrap())
+        });
+        group.bench_function(format!("{name}_mapped"), |b| {
+            b.iter(|| copy_encode_slice(&data, io::sink(), &opts, None).unwrap())
+        });
+    }
+    group.bench_function("two_passes", |b| {
+        b.iter(|| {
+            let stats = copy_encode_slice(&data, io::sink(), &CompressOptions::default(), None).unwrap();
+            (stats, ada_toolkit::checksum::crc32(&data))
+        })
+    });
+    group.bench_function("crc32_alone", |b| b.iter(|| ada_toolkit::checksum::crc32(&data)));
+    group.finish();
+}
+
+/// A sparse image, 64 MiB that is nearly all zeros, decoded by
+/// [`decompress`], which sizes its output from the block headers, into a
+/// buffer reused across iterations, and into a `Vec` that starts at twice
+/// the frame and grows, as `decompress` did before reading the sizes.
+fn high_ratio(c: &mut Criterion) {
+    let mut image = vec![0u8; 64 << 20];
+    for (i, chunk) in image.chunks_mut(1 << 20).enumerate() {
+        chunk[..4096].copy_from_slice(&corpus(Profile::Random, 4096)[..]);
+        chunk[4096] = i as u8;
+    }
+    let frame = compress(&image);
+    let mut group = c.benchmark_group("high_ratio");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(image.len() as u64));
+    group.bench_function("sized", |b| b.iter(|| decompress(&frame).unwrap()));
+    let mut reused = Vec::new();
+    group.bench_function("reused", |b| b.iter(|| decompress_into(&frame, &mut reused).unwrap()));
+    group.bench_function("grown", |b| {
+        b.iter(|| decompress_to_writer(&frame, Vec::with_capacity(frame.len() * 2)).unwrap())
+    });
+    group.finish();
+}
+
+/// Stands in for a network filesystem: each call pays a fixed latency,
+/// however many bytes it carries, and is counted.
+struct SlowWriter {
+    calls: u64,
+}
+
+impl SlowWriter {
+    const LATENCY: Duration = Duration::from_micros(200);
+}
+
+impl Write for SlowWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.calls += 1;
+        std::thread::sleep(SlowWriter::LATENCY);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.calls += 1;
+        std::thread::sleep(SlowWriter::LATENCY);
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams 16 MiB of mixed data through [`AapcWriter`] into a
+/// [`SlowWriter`], directly and through a [`BatchWriter`]; the write calls
+/// each makes are printed once up front.
+fn batching(c: &mut Criterion) {
+    let data = corpus(Profile::Mixed, 16 << 20);
+    let direct = || {
+        let mut writer = AapcWriter::new(SlowWriter { calls: 0 });
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap().calls
+    };
+    let batched = || {
+        let mut writer = AapcWriter::new(BatchWriter::new(SlowWriter { calls: 0 }));
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap().into_inner().unwrap().calls
+    };
+    println!("io/slow_writer: {} write calls direct, {} batched", direct(), batched());
+
+    let mut group = c.benchmark_group("io");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("slow_writer_direct", |b| b.iter(direct));
+    group.bench_function("slow_writer_batched", |b| b.iter(batched));
+    group.finish();
+}
+
+criterion_group!(benches, codec, rle, checksums, high_ratio, batching);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+//! Round-trip target: compresses the input, decompresses the frame and
+//! checks the content comes back unchanged, so encoder bugs show up as well
+//! as decoder ones.
+//!
+//! The first three bytes pick the options, and the rest is the content:
+//! - byte 0, bit 0: block checksums; bit 1: content checksum; bit 2:
+//!   store only; bit 3: no small frames; bit 4: a filename; bit 5: a
+//!   comment; bits 6-7: encoder threads, 0 to 3.
+//! - bytes 1-2: the block size less one, little-endian, so that short
+//!   inputs still span many blocks.
+//!
+//! Inputs shorter than that are compressed with the default options.
+//! Besides the round trip, the frame must fit in [`compress_bound`], be the
+//! same whatever the thread count and from the streaming encoder, and decode
+//! the same through the streaming decoder.
+//!
+//! This target is not wired up: there is no `fuzz/Cargo.toml`, as the crate
+//! itself has no manifest in this tree, so neither `cargo fuzz` nor the
+//! build compiles it. Once there is one, it needs `libfuzzer-sys` and this
+//! crate as dependencies and a `[[bin]]` named `round_trip`, and then
+//! `cargo fuzz run round_trip` starts from the seeds in `corpus/round_trip`,
+//! one per option combination above.
+
+#![no_main]
+
+use ada_toolkit::{compress_bound, compress_with_options, copy_decode, copy_encode_slice, decompress, CompressOptions};
+use libfuzzer_sys::fuzz_target;
+
+/// The options the input's first bytes pick, and the content after them.
+fn options(input: &[u8]) -> (CompressOptions, &[u8]) {
+    let &[flags, low, high, ref data @ ..] = input else {
+        return (CompressOptions::default(), input);
+    };
+    let opts = CompressOptions {
+        block_size: 1 + usize::from(u16::from_le_bytes([low, high])),
+        block_checksums: flags & 0x01 != 0,
+        content_checksum: flags & 0x02 != 0,
+        store_only: flags & 0x04 != 0,
+        small_frames: flags & 0x08 == 0,
+        filename: (flags & 0x10 != 0).then(|| "fuzz.bin".to_string()),
+        comment: (flags & 0x20 != 0).then(|| "round trip".to_string()),
+        threads: usize::from(flags >> 6),
+        ..CompressOptions::default()
+    };
+    (opts, data)
+}
+
+fuzz_target!(|input: &[u8]| {
+    let (opts, data) = options(input);
+    let frame = compress_with_options(data, &opts).expect("options are valid");
+    let bound = compress_bound(data.len(), &opts);
+    assert!(frame.len() <= bound, "{} byte frame for {} bytes, bound {}", frame.len(), data.len(), bound);
+    assert_eq!(decompress(&frame).expect("own frame decodes"), data);
+
+    if opts.threads > 1 {
+        let sequential = CompressOptions { threads: 0, ..opts.clone() };
+        assert_eq!(compress_with_options(data, &sequential).expect("options are valid"), frame);
+    }
+    let mut streamed = Vec::new();
+    copy_encode_slice(data, &mut streamed, &opts, None).expect("options are valid");
+    assert_eq!(streamed, frame);
+
+    let mut decoded = Vec::new();
+    let written = copy_decode(frame.as_slice(), &mut decoded, None, None).expect("own frame decodes");
+    assert_eq!(written, data.len() as u64);
+    assert_eq!(decoded, data);
+});
@@ -0,0 +1,46 @@
+/* C interface to the AAPC codec (Rust crate built with the `ffi` feature). */
+#ifndef AAPC_H
+#define AAPC_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define AAPC_MODE_COMPRESS 0
+#define AAPC_MODE_DECOMPRESS 1
+
+#define AAPC_OK 0
+#define AAPC_NEEDS_OUTPUT 1
+#define AAPC_ERR_INVALID (-1)
+#define AAPC_ERR_CORRUPT (-2)
+#define AAPC_ERR_TRUNCATED (-3)
+#define AAPC_ERR_CHECKSUM (-4)
+
+typedef struct AapcOptions {
+    size_t block_size; /* 0 = default */
+    int checksums;     /* non-zero = store CRC-32 checksums */
+} AapcOptions;
+
+typedef struct AapcCtx AapcCtx;
+
+/* Returns NULL if mode or options are invalid. options may be NULL. */
+AapcCtx *aapc_ctx_new(int mode, const AapcOptions *options);
+
+/* Consumes input and produces output. On AAPC_NEEDS_OUTPUT, call again with
+ * the unconsumed input (input + *consumed) and a fresh output buffer. */
+int aapc_ctx_update(AapcCtx *ctx, const unsigned char *input, size_t input_len,
+                    unsigned char *output, size_t output_cap,
+                    size_t *consumed, size_t *produced);
+
+/* Ends the frame. Repeat while it returns AAPC_NEEDS_OUTPUT. */
+int aapc_ctx_finish(AapcCtx *ctx, unsigned char *output, size_t output_cap, size_t *produced);
+
+void aapc_ctx_free(AapcCtx *ctx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,671 @@
+//! Archives: many named members packed into one file.
+//!
+//! ```text
+//! archive: magic "AAPA" | version u8 | member count u32 | members | crc32 u32
+//!          | member frames
+//! member:  kind u8 | mode u32 | mtime i64 | size u64 | checksum u32
+//!          | frame offset u64 | frame len u64 | path len u16 | path
+//! ```
+//!
+//! The CRC-32 covers every table byte before it. Each file member's content
+//! is a complete AAPC frame, so it decodes like any other; a symlink's frame
+//! holds its UTF-8 target, and directories have no frame. Offsets count
+//! from the first byte of the archive. Paths are UTF-8, relative and
+//! `/`-separated, with no empty, `.` or `..` parts.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use crate::checksum::Crc32;
+use crate::error::{CompressError, DecompressError};
+use crate::options::{archive_threads_within, CompressOptions, ARCHIVE_QUEUE};
+use crate::pool;
+use crate::stream::{copy_decode, copy_encode};
+
+/// Identifies an archive.
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"AAPA";
+/// Current archive format version.
+pub const ARCHIVE_VERSION: u8 = 1;
+
+const MEMBER_FIXED_LEN: usize = 43;
+
+/// What a member restores to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MemberKind {
+    File,
+    Directory,
+    /// A symbolic link, restored pointing at the target its content holds.
+    Symlink,
+}
+
+/// One entry of an archive's member table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Member {
+    /// Relative `/`-separated path.
+    pub path: String,
+    pub kind: MemberKind,
+    /// Unix permission bits, or 0 where the platform has none.
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: i64,
+    /// Uncompressed size; for a symlink, its target's length.
+    pub size: u64,
+    /// CRC-32 of the uncompressed content; for a symlink, of its target.
+    pub checksum: u32,
+    /// Position of the member's frame in the archive.
+    pub offset: u64,
+    /// Length of the member's frame; 0 for directories.
+    pub compressed_size: u64,
+}
+
+impl Member {
+    /// A member with no content yet; [`write_archive`] fills in the sizes,
+    /// checksum and offset.
+    pub fn new(path: impl Into<String>, kind: MemberKind, mode: u32, mtime: i64) -> Member {
+        Member { path: path.into(), kind, mode, mtime, size: 0, checksum: 0, offset: 0, compressed_size: 0 }
+    }
+}
+
+/// Whether `path` is safe to join onto an extraction directory.
+pub fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains(['\\', '\0'])
+        && path.split('/').all(|part| !matches!(part, "" | "." | ".."))
+        && !path.split('/').next().is_some_and(|first| first.ends_with(':'))
+}
+
+/// Writes an archive of `members` to `out`, reading the content of each
+/// file member, and the target of each symlink, from `open(index, member)`
+/// and compressing it with `opts`.
+///
+/// The table is written first with placeholder fields and rewritten once
+/// every frame is in place, so `out` must be seekable. On return `members`
+/// holds the final table and `out` is positioned at the archive's end.
+pub fn write_archive<W, R, F>(
+    mut out: W,
+    members: &mut [Member],
+    mut open: F,
+    opts: &CompressOptions,
+) -> Result<(), CompressError>
+where
+    W: Write + Seek,
+    R: Read,
+    F: FnMut(usize, &Member) -> io::Result<R>,
+{
+    opts.validate()?;
+    check_paths(members)?;
+    let start = out.stream_position()?;
+    out.write_all(&encode_table(members))?;
+
+    for (index, member) in members.iter_mut().enumerate() {
+        if member.kind == MemberKind::Directory {
+            continue;
+        }
+        let mut reader = Hashing::new(open(index, member)?);
+        let offset = out.stream_position()? - start;
+        let stats = copy_encode(&mut reader, &mut out, opts, None)?;
+        member.size = stats.input_bytes;
+        member.checksum = reader.crc.finish();
+        member.offset = offset;
+        member.compressed_size = stats.output_bytes;
+    }
+
+    let end = out.stream_position()?;
+    out.seek(SeekFrom::Start(start))?;
+    out.write_all(&encode_table(members))?;
+    out.seek(SeekFrom::Start(end))?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Like [`write_archive`], but compresses up to `opts.threads` members at
+/// once, each on a thread of its own, as many as fit in `opts.max_memory` by
+/// [`memory_for_archive`](crate::options::memory_for_archive). `open` is
+/// called on those threads.
+///
+/// Frames are still written in table order, each as soon as the ones
+/// before it are, so the archive is byte for byte the one [`write_archive`]
+/// writes, whichever member finishes first. A member that gets ahead waits
+/// once it has a chunk of output queued. With one thread, or fewer than two
+/// members with content, this is [`write_archive`], whose `opts.threads`
+/// then split each member's blocks instead.
+pub fn write_archive_concurrent<W, R, F>(
+    mut out: W,
+    members: &mut [Member],
+    open: F,
+    opts: &CompressOptions,
+) -> Result<(), CompressError>
+where
+    W: Write + Seek,
+    R: Read,
+    F: Fn(usize, &Member) -> io::Result<R> + Sync,
+{
+    let content: Vec<usize> = (0..members.len()).filter(|&i| members[i].kind != MemberKind::Directory).collect();
+    let threads = archive_threads_within(opts.max_memory, opts.block_size, opts.threads).min(content.len());
+    if threads <= 1 {
+        return write_archive(out, members, open, opts);
+    }
+    let member_opts = CompressOptions { threads: 1, ..opts.clone() };
+    member_opts.validate()?;
+    check_paths(members)?;
+    let start = out.stream_position()?;
+    out.write_all(&encode_table(members))?;
+
+    let (senders, receivers): (Vec<_>, Vec<_>) = content.iter().map(|_| mpsc::sync_channel(ARCHIVE_QUEUE)).unzip();
+    let jobs = Mutex::new(content.iter().copied().zip(senders));
+    let stop = AtomicBool::new(false);
+    let shared: &[Member] = members;
+    let written = thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let job = jobs.lock().unwrap_or_else(PoisonError::into_inner).next();
+                let Some((index, sender)) = job else { break };
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let piece = open(index, &shared[index])
+                    .map_err(CompressError::from)
+                    .and_then(|reader| encode_member(reader, &sender, &member_opts))
+                    .map(Piece::Done);
+                // The caller has stopped reading if this fails.
+                let _ = sender.send(piece);
+            });
+        }
+
+        let mut written = Vec::with_capacity(content.len());
+        let append = || -> Result<(), CompressError> {
+            for receiver in receivers {
+                let offset = out.stream_position()? - start;
+                // Empty only if the worker panicked, which the scope rethrows.
+                while let Ok(piece) = receiver.recv() {
+                    match piece? {
+                        Piece::Chunk(chunk) => {
+                            out.write_all(&chunk)?;
+                            pool::give(chunk);
+                        }
+                        Piece::Done(done) => {
+                            written.push((offset, done));
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        };
+        // Dropping the receivers that are left unblocks every worker.
+        let appended = append();
+        if appended.is_err() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        appended.map(|()| written)
+    })?;
+
+    for (&index, (offset, done)) in content.iter().zip(written) {
+        let member = &mut members[index];
+        member.size = done.size;
+        member.checksum = done.checksum;
+        member.offset = offset;
+        member.compressed_size = done.compressed_size;
+    }
+    let end = out.stream_position()?;
+    out.seek(SeekFrom::Start(start))?;
+    out.write_all(&encode_table(members))?;
+    out.seek(SeekFrom::Start(end))?;
+    out.flush()?;
+    Ok(())
+}
+
+/// What a [`write_archive_concurrent`] worker sends for its member: chunks
+/// of its frame, then the sizes and checksum for its table entry.
+enum Piece {
+    Chunk(Vec<u8>),
+    Done(Encoded),
+}
+
+struct Encoded {
+    size: u64,
+    checksum: u32,
+    compressed_size: u64,
+}
+
+/// Compresses one member's content with `opts`, sending its frame to
+/// `sender` a block-sized chunk at a time.
+fn encode_member<R: Read>(
+    reader: R,
+    sender: &SyncSender<Result<Piece, CompressError>>,
+    opts: &CompressOptions,
+) -> Result<Encoded, CompressError> {
+    let mut reader = Hashing::new(reader);
+    let mut chunks = Chunks { sender, chunk: pool::take(opts.block_size), len: opts.block_size };
+    let stats = copy_encode(&mut reader, &mut chunks, opts, None)?;
+    chunks.flush()?;
+    Ok(Encoded { size: stats.input_bytes, checksum: reader.crc.finish(), compressed_size: stats.output_bytes })
+}
+
+/// Collects written bytes into chunks of about `len` and sends each on.
+struct Chunks<'a> {
+    sender: &'a SyncSender<Result<Piece, CompressError>>,
+    chunk: Vec<u8>,
+    len: usize,
+}
+
+impl Write for Chunks<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.chunk.is_empty() && self.chunk.len() + buf.len() > self.len {
+            self.flush()?;
+        }
+        self.chunk.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.chunk, pool::take(self.len));
+        self.sender
+            .send(Ok(Piece::Chunk(chunk)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "archive writing stopped"))
+    }
+}
+
+impl Drop for Chunks<'_> {
+    fn drop(&mut self) {
+        pool::give(std::mem::take(&mut self.chunk));
+    }
+}
+
+fn check_paths(members: &[Member]) -> Result<(), CompressError> {
+    for member in members {
+        if !is_safe_path(&member.path) {
+            return Err(CompressError::InvalidPath(member.path.clone()));
+        }
+        if member.path.len() > u16::MAX as usize {
+            return Err(CompressError::MetadataTooLong { field: "member path", len: member.path.len() });
+        }
+    }
+    // Refused here as well, so that no archive [`read_members`] rejects is written.
+    match tree_conflict(members) {
+        Some((index, _)) => Err(CompressError::InvalidPath(members[index].path.clone())),
+        None => Ok(()),
+    }
+}
+
+fn encode_table(members: &[Member]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&ARCHIVE_MAGIC);
+    buf.push(ARCHIVE_VERSION);
+    buf.extend_from_slice(&(members.len() as u32).to_be_bytes());
+    for member in members {
+        buf.push(match member.kind {
+            MemberKind::File => 0,
+            MemberKind::Directory => 1,
+            MemberKind::Symlink => 2,
+        });
+        buf.extend_from_slice(&member.mode.to_be_bytes());
+        buf.extend_from_slice(&member.mtime.to_be_bytes());
+        buf.extend_from_slice(&member.size.to_be_bytes());
+        buf.extend_from_slice(&member.checksum.to_be_bytes());
+        buf.extend_from_slice(&member.offset.to_be_bytes());
+        buf.extend_from_slice(&member.compressed_size.to_be_bytes());
+        buf.extend_from_slice(&(member.path.len() as u16).to_be_bytes());
+        buf.extend_from_slice(member.path.as_bytes());
+    }
+    let crc = crate::checksum::crc32(&buf);
+    buf.extend_from_slice(&crc.to_be_bytes());
+    buf
+}
+
+/// Reads the member table at the start of an archive, without touching any
+/// member's data.
+pub fn read_members<R: Read>(mut reader: R) -> Result<Vec<Member>, DecompressError> {
+    let mut table = Vec::new();
+    let mut field = |table: &mut Vec<u8>, n: usize, name: &'static str| -> Result<usize, DecompressError> {
+        let at = table.len();
+        table.resize(at + n, 0);
+        reader.read_exact(&mut table[at..]).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DecompressError::TruncatedField { field: name, offset: at },
+            _ => DecompressError::Io(e),
+        })?;
+        Ok(at)
+    };
+
+    field(&mut table, 4, "archive magic")?;
+    if table[..4] != ARCHIVE_MAGIC {
+        return Err(DecompressError::BadMagic);
+    }
+    field(&mut table, 1, "archive version")?;
+    if table[4] != ARCHIVE_VERSION {
+        return Err(DecompressError::UnsupportedVersion(table[4]));
+    }
+    let at = field(&mut table, 4, "member count")?;
+    let count = u32::from_be_bytes(table[at..at + 4].try_into().unwrap());
+
+    let mut members = Vec::new();
+    let mut paths_at = Vec::new();
+    for _ in 0..count {
+        let at = field(&mut table, MEMBER_FIXED_LEN, "member")?;
+        let raw = &table[at..at + MEMBER_FIXED_LEN];
+        let u32_at = |i: usize| u32::from_be_bytes(raw[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_be_bytes(raw[i..i + 8].try_into().unwrap());
+        let kind = match raw[0] {
+            0 => MemberKind::File,
+            1 => MemberKind::Directory,
+            2 => MemberKind::Symlink,
+            _ => return Err(DecompressError::Corrupt { offset: at, reason: "unknown member kind" }),
+        };
+        let mut member = Member {
+            path: String::new(),
+            kind,
+            mode: u32_at(1),
+            mtime: u64_at(5) as i64,
+            size: u64_at(13),
+            checksum: u32_at(21),
+            offset: u64_at(25),
+            compressed_size: u64_at(33),
+        };
+        let path_len = u16::from_be_bytes(raw[41..43].try_into().unwrap()) as usize;
+        let path_at = field(&mut table, path_len, "member path")?;
+        member.path = match std::str::from_utf8(&table[path_at..]) {
+            Ok(path) if is_safe_path(path) => path.to_string(),
+            _ => return Err(DecompressError::Corrupt { offset: path_at, reason: "unsafe member path" }),
+        };
+        members.push(member);
+        paths_at.push(path_at);
+    }
+
+    let body_len = table.len();
+    let at = field(&mut table, 4, "archive checksum")?;
+    let stored = u32::from_be_bytes(table[at..at + 4].try_into().unwrap());
+    let actual = crate::checksum::crc32(&table[..body_len]);
+    if stored != actual {
+        return Err(DecompressError::FrameChecksumMismatch { expected: stored, actual });
+    }
+    if let Some((index, reason)) = tree_conflict(&members) {
+        return Err(DecompressError::Corrupt { offset: paths_at[index], reason });
+    }
+    Ok(members)
+}
+
+/// The first member whose path another member already has, or that sits
+/// under a symlink member, which extracting would follow out of the
+/// destination; with why it was refused.
+fn tree_conflict(members: &[Member]) -> Option<(usize, &'static str)> {
+    let mut paths = HashSet::with_capacity(members.len());
+    if let Some(index) = members.iter().position(|member| !paths.insert(member.path.as_str())) {
+        return Some((index, "duplicate member path"));
+    }
+    let links: HashSet<&str> =
+        members.iter().filter(|member| member.kind == MemberKind::Symlink).map(|member| member.path.as_str()).collect();
+    let index = members.iter().position(|member| {
+        let path = member.path.as_str();
+        path.match_indices('/').any(|(at, _)| links.contains(&path[..at]))
+    })?;
+    Some((index, "member path passes through a symlink member"))
+}
+
+/// An archive opened for extraction.
+pub struct Archive<R> {
+    reader: R,
+    start: u64,
+    members: Vec<Member>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Reads the member table of the archive starting at the reader's position.
+    pub fn open(mut reader: R) -> Result<Archive<R>, DecompressError> {
+        let start = reader.stream_position()?;
+        let members = read_members(&mut reader)?;
+        Ok(Archive { reader, start, members })
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Decompresses member `index` into `out`, checking its size and
+    /// checksum against the table, and returns the bytes written.
+    /// Directories write nothing and symlinks their target.
+    pub fn extract<W: Write>(&mut self, index: usize, out: W) -> Result<u64, DecompressError> {
+        let member = &self.members[index];
+        if member.kind == MemberKind::Directory {
+            return Ok(0);
+        }
+        self.reader.seek(SeekFrom::Start(self.start + member.offset))?;
+        let frame = (&mut self.reader).take(member.compressed_size);
+        let mut out = Hashing::new(out);
+        let written = copy_decode(frame, &mut out, None, None)?;
+        if written != member.size {
+            let offset = member.offset as usize;
+            return Err(DecompressError::Corrupt { offset, reason: "member size does not match table" });
+        }
+        let actual = out.crc.finish();
+        if actual != member.checksum {
+            return Err(DecompressError::FrameChecksumMismatch { expected: member.checksum, actual });
+        }
+        Ok(written)
+    }
+
+    /// The target of symlink member `index`.
+    pub fn link_target(&mut self, index: usize) -> Result<String, DecompressError> {
+        let mut target = Vec::new();
+        self.extract(index, &mut target)?;
+        let offset = self.members[index].offset as usize;
+        String::from_utf8(target).map_err(|_| DecompressError::Corrupt { offset, reason: "symlink target is not UTF-8" })
+    }
+}
+
+/// Passes bytes through to `inner`, keeping their CRC-32.
+struct Hashing<T> {
+    inner: T,
+    crc: Crc32,
+}
+
+impl<T> Hashing<T> {
+    fn new(inner: T) -> Hashing<T> {
+        Hashing { inner, crc: Crc32::new() }
+    }
+}
+
+impl<T: Read> Read for Hashing<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Hashing<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn members(entries: &[(MemberKind, &str)]) -> Vec<Member> {
+        entries.iter().map(|&(kind, path)| Member::new(path, kind, 0o644, 0)).collect()
+    }
+
+    fn contents(index: usize, _: &Member) -> io::Result<Cursor<Vec<u8>>> {
+        Ok(Cursor::new(format!("member {}", index).into_bytes()))
+    }
+
+    #[test]
+    fn written_members_read_back() {
+        let mut table = members(&[(MemberKind::Directory, "d"), (MemberKind::File, "d/f"), (MemberKind::Symlink, "d/l")]);
+        let mut out = Cursor::new(Vec::new());
+        write_archive(&mut out, &mut table, contents, &CompressOptions::default()).unwrap();
+        let mut archive = Archive::open(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(archive.members(), &table[..]);
+        let mut file = Vec::new();
+        assert_eq!(archive.extract(1, &mut file).unwrap(), 8);
+        assert_eq!(file, b"member 1");
+        assert_eq!(archive.link_target(2).unwrap(), "member 2");
+    }
+
+    #[test]
+    fn concurrent_writer_makes_the_same_members() {
+        let entries: Vec<(MemberKind, String)> = (0..6).map(|i| (MemberKind::File, format!("f{}", i))).collect();
+        let entries: Vec<(MemberKind, &str)> = entries.iter().map(|(kind, path)| (*kind, path.as_str())).collect();
+        let opts = CompressOptions { threads: 3, ..CompressOptions::default() };
+        let (mut one, mut many) = (members(&entries), members(&entries));
+        let (mut a, mut b) = (Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+        write_archive(&mut a, &mut one, contents, &opts).unwrap();
+        write_archive_concurrent(&mut b, &mut many, contents, &opts).unwrap();
+        let mut archive = Archive::open(Cursor::new(b.into_inner())).unwrap();
+        for (index, member) in one.iter().enumerate() {
+            assert_eq!((member.size, member.checksum), (many[index].size, many[index].checksum));
+            let mut out = Vec::new();
+            archive.extract(index, &mut out).unwrap();
+            assert_eq!(out, format!("member {}", index).into_bytes());
+        }
+    }
+
+    #[test]
+    fn members_finishing_out_of_order_give_the_same_bytes() {
+        let entries: Vec<(MemberKind, String)> = (0..8).map(|i| (MemberKind::File, format!("f{}", i))).collect();
+        let mut entries: Vec<(MemberKind, &str)> = entries.iter().map(|(kind, path)| (*kind, path.as_str())).collect();
+        entries.insert(3, (MemberKind::Directory, "d"));
+        // Later members are quicker to open, so they finish first.
+        let slow = |index: usize, member: &Member| {
+            std::thread::sleep(std::time::Duration::from_millis(10 * (9 - index) as u64));
+            let data: Vec<u8> = (0..20_000u32).map(|i| if i % 900 < 500 { index as u8 } else { i as u8 }).collect();
+            contents(index, member).map(|head| Cursor::new([head.into_inner(), data].concat()))
+        };
+        let mut expected = Cursor::new(Vec::new());
+        let opts = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        write_archive(&mut expected, &mut members(&entries), slow, &opts).unwrap();
+        for threads in [2, 3, 8] {
+            let mut out = Cursor::new(Vec::new());
+            let opts = CompressOptions { threads, ..opts.clone() };
+            write_archive_concurrent(&mut out, &mut members(&entries), slow, &opts).unwrap();
+            assert!(out.get_ref() == expected.get_ref(), "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn a_failed_open_ends_the_concurrent_writer() {
+        let entries: Vec<(MemberKind, String)> = (0..10).map(|i| (MemberKind::File, format!("f{}", i))).collect();
+        let entries: Vec<(MemberKind, &str)> = entries.iter().map(|(kind, path)| (*kind, path.as_str())).collect();
+        let failing = |index: usize, member: &Member| match index {
+            5 => Err(io::Error::new(io::ErrorKind::NotFound, "gone")),
+            _ => contents(index, member),
+        };
+        let opts = CompressOptions { threads: 4, ..CompressOptions::default() };
+        let result = write_archive_concurrent(Cursor::new(Vec::new()), &mut members(&entries), failing, &opts);
+        match result {
+            Err(CompressError::Io(e)) => assert_eq!(e.to_string(), "gone"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    /// Counts the bytes read through it.
+    struct Counting<R> {
+        inner: R,
+        read: u64,
+    }
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn extracting_one_member_reads_only_its_frame() {
+        let entries: Vec<(MemberKind, String)> = (0..40).map(|i| (MemberKind::File, format!("f{:02}", i))).collect();
+        let entries: Vec<(MemberKind, &str)> = entries.iter().map(|(kind, path)| (*kind, path.as_str())).collect();
+        let noise = |index: usize, _: &Member| {
+            let mut state = 0x9E37_79B9u32 ^ index as u32;
+            let data: Vec<u8> = (0..64 * 1024)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect();
+            Ok(Cursor::new(data))
+        };
+        let mut table = members(&entries);
+        let mut out = Cursor::new(Vec::new());
+        write_archive(&mut out, &mut table, noise, &CompressOptions::default()).unwrap();
+        let whole = out.get_ref().len() as u64;
+
+        let mut archive = Archive::open(Counting { inner: Cursor::new(out.into_inner()), read: 0 }).unwrap();
+        let table_read = archive.reader.read;
+        let last = archive.members().len() - 1;
+        let mut content = Vec::new();
+        archive.extract(last, &mut content).unwrap();
+        assert_eq!(content, noise(last, &table[last]).unwrap().into_inner());
+        let member_read = archive.reader.read - table_read;
+        assert_eq!(member_read, table[last].compressed_size);
+        assert!(table_read + member_read < whole / 20, "read {} + {} of {}", table_read, member_read, whole);
+    }
+
+    #[test]
+    fn writers_refuse_tables_readers_would_reject() {
+        for entries in [
+            &[(MemberKind::File, "x"), (MemberKind::File, "x")][..],
+            &[(MemberKind::Symlink, "a"), (MemberKind::File, "a/b")][..],
+            &[(MemberKind::File, "../up")][..],
+        ] {
+            let mut table = members(entries);
+            let result = write_archive(Cursor::new(Vec::new()), &mut table, contents, &CompressOptions::default());
+            assert!(matches!(result, Err(CompressError::InvalidPath(_))), "{:?}", entries);
+        }
+    }
+
+    #[test]
+    fn reader_rejects_duplicates_and_members_under_links() {
+        for (entries, expected) in [
+            (&[(MemberKind::File, "x"), (MemberKind::Directory, "x")][..], "duplicate member path"),
+            (
+                &[(MemberKind::File, "a/b/c"), (MemberKind::Symlink, "a/b")][..],
+                "member path passes through a symlink member",
+            ),
+        ] {
+            let table = encode_table(&members(entries));
+            match read_members(&table[..]) {
+                Err(DecompressError::Corrupt { reason, .. }) => assert_eq!(reason, expected),
+                other => panic!("{:?} read as {:?}", entries, other),
+            }
+        }
+        // A link beside a directory of a similar name is fine.
+        let table = encode_table(&members(&[(MemberKind::Symlink, "a"), (MemberKind::File, "ab/c")]));
+        assert_eq!(read_members(&table[..]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        for path in ["", "/abs", "a//b", "./a", "a/..", "c:/x", "a\\b"] {
+            assert!(!is_safe_path(path), "{:?}", path);
+        }
+        assert!(is_safe_path("a/b.c/d"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Tokio adapters, built with the `async` feature.
+//!
+//! These drive the same [`BlockEncoder`]/[`FrameDecoder`] state machines as
+//! [`AapcWriter`](crate::stream::AapcWriter) and [`AapcReader`](crate::stream::AapcReader),
+//! so both produce and accept identical frames.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::error::CompressError;
+use crate::options::CompressOptions;
+use crate::stream::{BlockEncoder, FrameDecoder};
+
+/// Async streaming compressor. `poll_flush` ends the current block like
+/// [`AapcWriter::flush`](crate::stream::AapcWriter); `poll_shutdown` writes the
+/// trailer, then shuts down `inner`.
+pub struct AsyncAapcWriter<W> {
+    inner: W,
+    encoder: BlockEncoder,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncAapcWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_options(inner, &CompressOptions::default()).expect("default options are valid")
+    }
+
+    pub fn with_options(inner: W, opts: &CompressOptions) -> Result<Self, CompressError> {
+        Ok(AsyncAapcWriter { inner, encoder: BlockEncoder::new(opts)? })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes queued compressed bytes to `inner` until the queue is empty.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.encoder.pending().is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, self.encoder.pending()))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.encoder.consume(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncAapcWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Only accept new input once the previous block is fully on its way,
+        // so at most one encoded block is ever queued.
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(Ok(this.encoder.push(buf)?))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // Same block boundary as the sync writer; a repeat poll finds the block empty.
+        this.encoder.flush_block()?;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.encoder.finish()?;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Async streaming decompressor. Reports EOF after the trailer; input that ends
+/// mid-frame is an `UnexpectedEof` error.
+pub struct AsyncAapcReader<R> {
+    inner: R,
+    decoder: FrameDecoder,
+    buf: Box<[u8]>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncAapcReader<R> {
+    pub fn new(inner: R) -> Self {
+        AsyncAapcReader { inner, decoder: FrameDecoder::new(), buf: vec![0; 64 * 1024].into_boxed_slice() }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncAapcReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, out: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let available = this.decoder.output();
+            if !available.is_empty() {
+                let n = available.len().min(out.remaining());
+                out.put_slice(&available[..n]);
+                this.decoder.consume(n);
+                return Poll::Ready(Ok(()));
+            }
+            this.decoder.resume()?;
+            if !this.decoder.output().is_empty() {
+                continue;
+            }
+            if this.decoder.is_done() {
+                return Poll::Ready(Ok(()));
+            }
+            let mut read_buf = ReadBuf::new(&mut this.buf);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+            let filled = read_buf.filled();
+            if filled.is_empty() {
+                return Poll::Ready(Err(this.decoder.truncated().into()));
+            }
+            this.decoder.push(filled)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::AapcWriter;
+    use std::io::Write;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    fn data() -> Vec<u8> {
+        (0..50_000u32).map(|i| if i % 3000 < 1800 { 0 } else { (i * 31 / 7) as u8 }).collect()
+    }
+
+    fn opts() -> CompressOptions {
+        CompressOptions { block_size: 4096, ..CompressOptions::default() }
+    }
+
+    /// Writes `data` to `writer` in `chunk`-byte pieces, then shuts it down.
+    async fn write_chunked<W: AsyncWrite + Unpin>(mut writer: W, data: &[u8], chunk: usize) -> io::Result<()> {
+        for piece in data.chunks(chunk) {
+            writer.write_all(piece).await?;
+        }
+        writer.shutdown().await
+    }
+
+    /// Reads `reader` to the end `chunk` bytes at a time.
+    async fn read_chunked<R: AsyncRead + Unpin>(mut reader: R, chunk: usize) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            match reader.read(&mut buf).await? {
+                0 => return Ok(out),
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn duplex_round_trip_in_small_chunks() {
+        let data = data();
+        for (pipe, chunk) in [(64, 7), (512, 100), (8192, 5000)] {
+            let (near, far) = duplex(pipe);
+            let writer = AsyncAapcWriter::with_options(near, &opts()).unwrap();
+            let (written, read) = tokio::join!(
+                write_chunked(writer, &data, chunk),
+                read_chunked(AsyncAapcReader::new(far), chunk)
+            );
+            written.unwrap();
+            assert_eq!(read.unwrap(), data, "{}-byte pipe, {}-byte chunks", pipe, chunk);
+        }
+    }
+
+    #[tokio::test]
+    async fn async_writer_matches_sync_writer() {
+        let data = data();
+        let mut sync = AapcWriter::with_options(Vec::new(), &opts()).unwrap();
+        sync.write_all(&data).unwrap();
+        let expected = sync.finish().unwrap();
+
+        let mut writer = AsyncAapcWriter::with_options(Vec::new(), &opts()).unwrap();
+        writer.write_all(&data).await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.into_inner(), expected);
+    }
+
+    #[tokio::test]
+    async fn flush_makes_everything_written_so_far_readable() {
+        let (near, far) = duplex(1 << 20);
+        let mut writer = AsyncAapcWriter::new(near);
+        let mut reader = AsyncAapcReader::new(far);
+        let parts: [&[u8]; 3] = [b"first part", &[0; 3000], b"third"];
+        let mut received = Vec::new();
+        for part in parts {
+            writer.write_all(part).await.unwrap();
+            writer.flush().await.unwrap();
+            let mut buf = vec![0; part.len()];
+            reader.read_exact(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf);
+        }
+        writer.shutdown().await.unwrap();
+        assert_eq!(read_chunked(reader, 1024).await.unwrap(), b"");
+        assert_eq!(received, parts.concat());
+    }
+
+    #[tokio::test]
+    async fn stream_cut_mid_block_is_unexpected_eof() {
+        let frame = crate::compress_with_options(&data(), &opts()).unwrap();
+        for cut in [3, frame.len() / 2, frame.len() - 1] {
+            let (mut near, far) = duplex(256);
+            let sending = async {
+                near.write_all(&frame[..cut]).await.unwrap();
+                drop(near);
+            };
+            let (_, read) = tokio::join!(sending, read_chunked(AsyncAapcReader::new(far), 333));
+            let err = read.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "cut at {}: {}", cut, err);
+        }
+    }
+
+    #[tokio::test]
+    async fn corrupt_block_is_invalid_data() {
+        let mut frame = crate::compress_with_options(&data(), &opts()).unwrap();
+        let at = frame.len() / 2;
+        frame[at] ^= 0x55;
+        let err = read_chunked(AsyncAapcReader::new(frame.as_slice()), 1000).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+    }
+
+    #[tokio::test]
+    async fn write_error_reaches_the_caller() {
+        let (near, far) = duplex(64);
+        drop(far);
+        let err = write_chunked(AsyncAapcWriter::new(near), &data(), 1000).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe, "{}", err);
+    }
+}
@@ -0,0 +1,262 @@
+//! Batched output: [`BatchWriter`] collects what is written to it and hands
+//! it on in few, large writes, for destinations where each write costs far
+//! more than copying the bytes does, such as network filesystems.
+
+use std::io::{self, IoSlice, Seek, SeekFrom, Write};
+
+use crate::pool;
+
+/// Bytes a [`BatchWriter`] collects by default before writing them out.
+pub const DEFAULT_IO_SIZE: usize = 4 << 20;
+
+/// Size of the buffers written bytes are collected in, several of which make
+/// up one write.
+const CHUNK: usize = 256 * 1024;
+
+/// Like [`io::BufWriter`], but holds up to `io_size` bytes in a list of
+/// block-sized buffers, not one contiguous one, and writes them all out with
+/// a single [`Write::write_vectored`] where the inner writer supports it.
+/// A write at least `io_size` long, with nothing collected before it, goes
+/// straight through.
+///
+/// Collected bytes are written out when dropped, ignoring any error; call
+/// [`flush`](Write::flush) to see one.
+pub struct BatchWriter<W: Write> {
+    /// Only `None` once [`BatchWriter::into_inner`] has taken it.
+    inner: Option<W>,
+    chunks: Vec<Vec<u8>>,
+    queued: usize,
+    io_size: usize,
+}
+
+impl<W: Write> BatchWriter<W> {
+    /// Collects up to [`DEFAULT_IO_SIZE`] bytes at a time.
+    pub fn new(inner: W) -> BatchWriter<W> {
+        BatchWriter::with_io_size(DEFAULT_IO_SIZE, inner)
+    }
+
+    /// Collects up to `io_size` bytes at a time; 0 passes every write
+    /// straight through.
+    pub fn with_io_size(io_size: usize, inner: W) -> BatchWriter<W> {
+        BatchWriter { inner: Some(inner), chunks: Vec::new(), queued: 0, io_size }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("inner writer is only taken by into_inner")
+    }
+
+    /// The inner writer; writing to it directly skips whatever is still
+    /// collected.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("inner writer is only taken by into_inner")
+    }
+
+    /// Writes out what is collected and returns the inner writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.write_out()?;
+        Ok(self.inner.take().expect("inner writer is only taken by into_inner"))
+    }
+
+    /// Bytes collected and not yet written out.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Writes every collected byte to the inner writer, as few writes as it
+    /// takes. On an error, the bytes already written are dropped from the
+    /// collection and the rest kept.
+    fn write_out(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let result = {
+            let mut slices: Vec<IoSlice<'_>> = self.chunks.iter().map(|chunk| IoSlice::new(chunk)).collect();
+            let mut slices = &mut slices[..];
+            let inner = self.inner.as_mut().expect("inner writer is only taken by into_inner");
+            loop {
+                if slices.is_empty() {
+                    break Ok(());
+                }
+                match inner.write_vectored(slices) {
+                    Ok(0) => break Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write collected output")),
+                    Ok(n) => {
+                        written += n;
+                        IoSlice::advance_slices(&mut slices, n);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => break Err(e),
+                }
+            }
+        };
+        self.discard(written);
+        result
+    }
+
+    /// Drops the first `n` collected bytes.
+    fn discard(&mut self, mut n: usize) {
+        self.queued -= n;
+        let whole = self.chunks.iter().take_while(|chunk| chunk.len() <= n).count();
+        for chunk in self.chunks.drain(..whole) {
+            n -= chunk.len();
+            pool::give(chunk);
+        }
+        if n > 0 {
+            self.chunks[0].drain(..n);
+        }
+    }
+}
+
+impl<W: Write> Write for BatchWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.queued > 0 && self.queued + buf.len() > self.io_size {
+            self.write_out()?;
+        }
+        if self.queued == 0 && buf.len() >= self.io_size {
+            return self.get_mut().write(buf);
+        }
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if self.chunks.last().is_none_or(|chunk| chunk.len() == CHUNK) {
+                self.chunks.push(pool::take(CHUNK));
+            }
+            let chunk = self.chunks.last_mut().unwrap();
+            let n = rest.len().min(CHUNK - chunk.len());
+            chunk.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+        self.queued += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write + Seek> Seek for BatchWriter<W> {
+    /// Writes out what is collected, then seeks the inner writer.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.write_out()?;
+        self.get_mut().seek(pos)
+    }
+}
+
+impl<W: Write> Drop for BatchWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && !std::thread::panicking() {
+            let _ = self.write_out();
+        }
+        for chunk in self.chunks.drain(..) {
+            pool::give(chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Records the bytes each call was given, taking at most `limit` of
+    /// them per call, and fails the calls numbered in `failing`.
+    #[derive(Default)]
+    struct Recording {
+        out: Vec<u8>,
+        calls: Vec<usize>,
+        limit: Option<usize>,
+        failing: Vec<usize>,
+    }
+
+    impl Write for Recording {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            if self.failing.contains(&self.calls.len()) {
+                self.calls.push(0);
+                return Err(io::Error::other("refused"));
+            }
+            let mut n = 0;
+            for buf in bufs {
+                let take = buf.len().min(self.limit.map_or(usize::MAX, |limit| limit - n));
+                self.out.extend_from_slice(&buf[..take]);
+                n += take;
+            }
+            self.calls.push(n);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn small_writes_go_out_together() {
+        let input = data(100_000);
+        let mut writer = BatchWriter::with_io_size(40_000, Recording::default());
+        for piece in input.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+        assert_eq!(writer.queued(), 20_000);
+        let inner = writer.into_inner().unwrap();
+        assert_eq!(inner.out, input);
+        assert_eq!(inner.calls, [40_000, 40_000, 20_000]);
+    }
+
+    #[test]
+    fn a_long_write_goes_straight_through() {
+        let input = data(600_000);
+        let mut writer = BatchWriter::with_io_size(CHUNK, Recording::default());
+        writer.write_all(&input[..10]).unwrap();
+        writer.write_all(&input[10..]).unwrap();
+        assert_eq!(writer.queued(), 0);
+        assert_eq!(writer.get_ref().calls, [10, 599_990]);
+
+        let mut unbatched = BatchWriter::with_io_size(0, Recording::default());
+        for piece in input.chunks(7) {
+            unbatched.write_all(piece).unwrap();
+        }
+        let inner = unbatched.into_inner().unwrap();
+        assert_eq!((inner.out, inner.calls.len()), (input, 600_000usize.div_ceil(7)));
+    }
+
+    #[test]
+    fn short_and_failed_writes_keep_the_rest() {
+        let input = data(3 * CHUNK + 100);
+        let mut writer = BatchWriter::with_io_size(1 << 20, Recording {
+            limit: Some(100_000),
+            failing: vec![2],
+            ..Recording::default()
+        });
+        writer.write_all(&input).unwrap();
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.queued(), input.len() - 200_000);
+        writer.flush().unwrap();
+        let inner = writer.into_inner().unwrap();
+        assert_eq!(inner.out, input);
+        assert_eq!(inner.calls.iter().filter(|&&n| n == 0).count(), 1);
+    }
+
+    #[test]
+    fn seeking_writes_out_first() {
+        let mut writer = BatchWriter::with_io_size(1 << 20, Cursor::new(Vec::new()));
+        writer.write_all(b"0123456789").unwrap();
+        writer.seek(SeekFrom::Start(2)).unwrap();
+        writer.write_all(b"ab").unwrap();
+        assert_eq!(writer.into_inner().unwrap().into_inner(), b"01ab456789");
+    }
+
+    #[test]
+    fn dropping_writes_out_what_is_collected() {
+        let mut out = Vec::new();
+        let mut writer = BatchWriter::new(&mut out);
+        writer.write_all(b"kept").unwrap();
+        drop(writer);
+        assert_eq!(out, b"kept");
+    }
+}
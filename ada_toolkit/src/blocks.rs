@@ -0,0 +1,251 @@
+use std::io::{self, Read};
+
+use crate::decompression::decode_payload;
+use crate::error::DecompressError;
+use crate::checksum::Crc32;
+use crate::frame::{self, FrameStart, Header, SmallFrame, BLOCK_END, BLOCK_HEADER_LEN};
+
+enum Source<'a> {
+    Slice(&'a [u8]),
+    Reader(Box<dyn Read + 'a>),
+}
+
+/// Iterator over the uncompressed blocks of a frame, one `Vec` per block.
+///
+/// The first error ends iteration; every later call returns `None`. Use
+/// [`DecodedBlocks::next_into`] to decode into a caller-owned buffer instead.
+pub struct DecodedBlocks<'a> {
+    source: Source<'a>,
+    offset: usize,
+    scratch: Vec<u8>,
+    header: Option<Header>,
+    block_count: u32,
+    content_size: u64,
+    content_crc: Crc32,
+    finished: bool,
+}
+
+impl<'a> DecodedBlocks<'a> {
+    /// Iterates over a frame held in memory.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::with_source(Source::Slice(data))
+    }
+
+    /// Iterates over a frame read incrementally; only one block is buffered at a time.
+    pub fn from_reader<R: Read + 'a>(reader: R) -> Self {
+        Self::with_source(Source::Reader(Box::new(reader)))
+    }
+
+    fn with_source(source: Source<'a>) -> Self {
+        DecodedBlocks {
+            source,
+            offset: 0,
+            scratch: Vec::new(),
+            header: None,
+            block_count: 0,
+            content_size: 0,
+            content_crc: Crc32::new(),
+            finished: false,
+        }
+    }
+
+    /// Decodes the next block into `out`, replacing its contents.
+    ///
+    /// Returns `None` once the trailer has been read or an error was returned.
+    pub fn next_into(&mut self, out: &mut Vec<u8>) -> Option<Result<(), DecompressError>> {
+        if self.finished {
+            return None;
+        }
+        out.clear();
+        match self.decode_next(out) {
+            Ok(true) => Some(Ok(())),
+            Ok(false) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// Reads `n` bytes at the current offset.
+    fn take(&mut self, n: usize) -> Result<&[u8], DecompressError> {
+        let offset = self.offset;
+        let bytes = match &mut self.source {
+            Source::Slice(data) => {
+                let data: &'a [u8] = data;
+                data.get(offset..offset + n).ok_or(DecompressError::Truncated { offset: data.len() })?
+            }
+            Source::Reader(reader) => {
+                self.scratch.resize(n, 0);
+                reader.read_exact(&mut self.scratch).map_err(|e| match e.kind() {
+                    io::ErrorKind::UnexpectedEof => DecompressError::Truncated { offset },
+                    _ => DecompressError::from(e),
+                })?;
+                &self.scratch[..]
+            }
+        };
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    /// Returns `Ok(false)` after validating the trailer.
+    fn decode_next(&mut self, out: &mut Vec<u8>) -> Result<bool, DecompressError> {
+        let header = match self.header.take() {
+            Some(header) => header,
+            None => match self.read_start()? {
+                FrameStart::Full(header) => header,
+                FrameStart::Small(small) => return self.decode_small(&small, out),
+            },
+        };
+        let result = self.decode_block(&header, out);
+        self.header = Some(header);
+        result
+    }
+
+    fn read_start(&mut self) -> Result<FrameStart, DecompressError> {
+        let start = match &mut self.source {
+            Source::Slice(data) => frame::parse_start(data)?,
+            Source::Reader(reader) => frame::read_start(reader, &mut self.scratch)?,
+        };
+        if let FrameStart::Full(header) = &start {
+            self.offset = header.len;
+        }
+        Ok(start)
+    }
+
+    /// Decodes the one block of a small frame, which `read_start` has read
+    /// whole, so that nothing is left to iterate over after it.
+    fn decode_small(&mut self, small: &SmallFrame, out: &mut Vec<u8>) -> Result<bool, DecompressError> {
+        self.finished = true;
+        let data = match &self.source {
+            Source::Slice(data) => data,
+            Source::Reader(_) => &self.scratch[..],
+        };
+        let block = small.block;
+        out.reserve(block.raw_len);
+        decode_payload(&block, 0, &data[small.payload_at..][..block.comp_len], small.payload_at, out)?;
+        block.verify(0, out)?;
+        Ok(block.raw_len > 0)
+    }
+
+    fn decode_block(&mut self, header: &Header, out: &mut Vec<u8>) -> Result<bool, DecompressError> {
+        let start = self.offset;
+        let block_type = self.take(1)?[0];
+        if block_type == BLOCK_END {
+            let trailer = self.take(header.trailer_len())?.to_vec();
+            let trailer = frame::parse_trailer(&trailer, 0, header)?;
+            let content_crc = header.content_checksum().then(|| self.content_crc.finish());
+            trailer.verify(self.block_count, self.content_size, content_crc, start + 1)?;
+            return Ok(false);
+        }
+
+        let mut raw = [0u8; BLOCK_HEADER_LEN + 4];
+        let raw = &mut raw[..header.block_header_len()];
+        raw[0] = block_type;
+        raw[1..].copy_from_slice(self.take(header.block_header_len() - 1)?);
+        let block = frame::parse_block_header(raw, 0, header)
+            .map_err(|e| e.shifted(start))?
+            .expect("not an end marker");
+
+        let base = self.offset;
+        let index = self.block_count;
+        let payload = self.take(block.comp_len)?;
+        out.reserve(block.raw_len);
+        decode_payload(&block, index, payload, base, out)?;
+        block.verify(self.block_count, out)?;
+        self.content_crc.update(out);
+        self.block_count += 1;
+        self.content_size += block.raw_len as u64;
+        Ok(true)
+    }
+}
+
+impl Iterator for DecodedBlocks<'_> {
+    type Item = Result<Vec<u8>, DecompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut block = Vec::new();
+        self.next_into(&mut block).map(|result| result.map(|()| block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_with_options, decompress, CompressOptions};
+
+    fn data() -> Vec<u8> {
+        (0..100_000u32).map(|i| if i % 3000 < 1800 { 4 } else { (i * 31 / 7) as u8 }).collect()
+    }
+
+    fn frame(data: &[u8], block_size: usize) -> Vec<u8> {
+        let opts = CompressOptions { block_size, ..CompressOptions::default() };
+        compress_with_options(data, &opts).unwrap()
+    }
+
+    #[test]
+    fn blocks_concatenate_to_the_content() {
+        let data = data();
+        for block_size in [1000, 4096, 1 << 20] {
+            let frame = frame(&data, block_size);
+            let blocks: Vec<Vec<u8>> = DecodedBlocks::new(&frame).collect::<Result<_, _>>().unwrap();
+            assert_eq!(blocks.len(), data.len().div_ceil(block_size), "{} byte blocks", block_size);
+            assert!(blocks.iter().rev().skip(1).all(|b| b.len() == block_size));
+            assert_eq!(blocks.concat(), decompress(&frame).unwrap());
+
+            let read: Vec<Vec<u8>> = DecodedBlocks::from_reader(frame.as_slice()).collect::<Result<_, _>>().unwrap();
+            assert_eq!(read, blocks);
+        }
+    }
+
+    #[test]
+    fn next_into_reuses_the_buffer() {
+        let data = data();
+        let frame = frame(&data, 8192);
+        let mut blocks = DecodedBlocks::from_reader(frame.as_slice());
+        let mut buf = Vec::new();
+        let mut all = Vec::new();
+        while let Some(result) = blocks.next_into(&mut buf) {
+            result.unwrap();
+            assert!(buf.len() <= 8192);
+            all.extend_from_slice(&buf);
+        }
+        assert_eq!(all, data);
+        assert!(blocks.next_into(&mut buf).is_none());
+    }
+
+    #[test]
+    fn small_and_empty_frames() {
+        let small = frame(b"tiny", 4096);
+        for blocks in [DecodedBlocks::new(&small), DecodedBlocks::from_reader(small.as_slice())] {
+            assert_eq!(blocks.map(Result::unwrap).collect::<Vec<_>>(), [b"tiny".to_vec()]);
+        }
+        let empty = frame(b"", 4096);
+        assert_eq!(DecodedBlocks::new(&empty).count(), 0);
+        assert_eq!(DecodedBlocks::from_reader(empty.as_slice()).count(), 0);
+    }
+
+    #[test]
+    fn iteration_stops_after_an_error() {
+        let data = data();
+        let whole = frame(&data, 4096);
+        let cut = &whole[..whole.len() / 2];
+        let mut corrupt = whole.clone();
+        corrupt[whole.len() - 40] ^= 0x10;
+        for frame in [cut, &corrupt] {
+            for mut blocks in [DecodedBlocks::new(frame), DecodedBlocks::from_reader(frame)] {
+                let results: Vec<_> = blocks.by_ref().collect();
+                let (last, ok) = results.split_last().unwrap();
+                assert!(ok.iter().all(Result::is_ok));
+                assert!(last.is_err());
+                assert!(blocks.next().is_none());
+                assert!(blocks.next_into(&mut Vec::new()).is_none());
+            }
+        }
+        assert!(matches!(DecodedBlocks::new(cut).last(), Some(Err(DecompressError::Truncated { .. }))));
+        assert!(matches!(DecodedBlocks::new(b"nope").next(), Some(Err(DecompressError::BadMagic))));
+    }
+}
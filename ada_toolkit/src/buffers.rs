@@ -0,0 +1,191 @@
+//! Helpers over [`bytes::Bytes`], built with the `bytes` feature.
+//!
+//! [`decompress_blocks_bytes`] hands out stored blocks as slices of the
+//! input buffer, so incompressible data is never copied.
+
+use bytes::Bytes;
+
+use crate::checksum::Crc32;
+use crate::compression::compress;
+use crate::decompression::{decode_payload, decompress};
+use crate::error::DecompressError;
+use crate::frame::{self, BlockHeader, FrameStart, Header, BLOCK_STORED};
+
+/// Like [`compress`], taking and returning `Bytes`.
+pub fn compress_bytes(src: &Bytes) -> Bytes {
+    Bytes::from(compress(src))
+}
+
+/// Like [`decompress`], taking and returning `Bytes`.
+pub fn decompress_bytes(src: &Bytes) -> Result<Bytes, DecompressError> {
+    decompress(src).map(Bytes::from)
+}
+
+/// Iterates over the blocks of the frame in `src`, one `Bytes` per block.
+///
+/// Stored blocks share `src`'s allocation; RLE blocks are decoded into a new
+/// buffer each.
+pub fn decompress_blocks_bytes(src: &Bytes) -> BytesBlocks {
+    BytesBlocks {
+        src: src.clone(),
+        header: None,
+        offset: 0,
+        block_count: 0,
+        content_size: 0,
+        content_crc: Crc32::new(),
+        finished: false,
+    }
+}
+
+/// Iterator returned by [`decompress_blocks_bytes`].
+///
+/// Like [`DecodedBlocks`](crate::DecodedBlocks), the first error ends iteration.
+pub struct BytesBlocks {
+    src: Bytes,
+    header: Option<Header>,
+    offset: usize,
+    block_count: u32,
+    content_size: u64,
+    content_crc: Crc32,
+    finished: bool,
+}
+
+impl BytesBlocks {
+    /// Returns `Ok(None)` after validating the trailer.
+    fn decode_next(&mut self) -> Result<Option<Bytes>, DecompressError> {
+        let data = &self.src[..];
+        let header = match &self.header {
+            Some(header) => header,
+            None => match frame::parse_start(data)? {
+                FrameStart::Full(header) => {
+                    self.offset = header.len;
+                    self.header.insert(header)
+                }
+                // Its one block is all there is to check.
+                FrameStart::Small(small) => {
+                    self.finished = true;
+                    let out = self.decode_block(&small.block, 0, small.payload_at)?;
+                    return Ok((small.block.raw_len > 0).then_some(out));
+                }
+            },
+        };
+
+        let block = match frame::parse_block_header(data, self.offset, header)? {
+            Some(block) => block,
+            None => {
+                let idx = self.offset + 1;
+                let trailer = frame::parse_trailer(data, idx, header)?;
+                let content_crc = header.content_checksum().then(|| self.content_crc.finish());
+                trailer.verify(self.block_count, self.content_size, content_crc, idx)?;
+                return Ok(None);
+            }
+        };
+        let start = self.offset + header.block_header_len();
+        let end = start + block.comp_len;
+        if end > data.len() {
+            return Err(DecompressError::Truncated { offset: data.len() });
+        }
+        let out = self.decode_block(&block, self.block_count, start)?;
+        self.content_crc.update(&out);
+        self.block_count += 1;
+        self.content_size += block.raw_len as u64;
+        self.offset = end;
+        Ok(Some(out))
+    }
+
+    /// Block `index`, whose payload starts at `start`, decoded and checked.
+    fn decode_block(&self, block: &BlockHeader, index: u32, start: usize) -> Result<Bytes, DecompressError> {
+        let end = start + block.comp_len;
+        let out = match block.block_type {
+            BLOCK_STORED => self.src.slice(start..end),
+            _ => {
+                let mut decoded = Vec::with_capacity(block.raw_len);
+                decode_payload(block, index, &self.src[start..end], start, &mut decoded)?;
+                Bytes::from(decoded)
+            }
+        };
+        block.verify(index, &out)?;
+        Ok(out)
+    }
+}
+
+impl Iterator for BytesBlocks {
+    type Item = Result<Bytes, DecompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.decode_next().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.finished = true;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_with_options, CompressOptions};
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x9E37_79B9u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Whether `part` lies inside `whole`'s allocation.
+    fn is_within(part: &Bytes, whole: &Bytes) -> bool {
+        let range = whole.as_ptr_range();
+        range.start <= part.as_ptr() && part.as_ptr_range().end <= range.end
+    }
+
+    #[test]
+    fn stored_blocks_share_the_input() {
+        // Noise, runs, noise: stored, RLE, stored.
+        let mut data = noise(4096);
+        data.extend(vec![0; 4096]);
+        data.extend(noise(4000));
+        let opts = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        let src = Bytes::from(compress_with_options(&data, &opts).unwrap());
+
+        let blocks: Vec<Bytes> = decompress_blocks_bytes(&src).collect::<Result<_, _>>().unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert!(is_within(&blocks[0], &src) && is_within(&blocks[2], &src));
+        assert!(!is_within(&blocks[1], &src));
+        assert_eq!(blocks.concat(), data);
+    }
+
+    #[test]
+    fn a_stored_small_frame_is_a_slice_too() {
+        let src = Bytes::from(compress_with_options(&noise(100), &CompressOptions::default()).unwrap());
+        let blocks: Vec<Bytes> = decompress_blocks_bytes(&src).collect::<Result<_, _>>().unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert!(is_within(&blocks[0], &src));
+        assert_eq!(blocks[0], noise(100));
+        assert_eq!(decompress_blocks_bytes(&compress_bytes(&Bytes::new())).count(), 0);
+    }
+
+    #[test]
+    fn round_trip_and_errors() {
+        let data = Bytes::from([vec![5; 10_000], noise(10_000)].concat());
+        let src = compress_bytes(&data);
+        assert_eq!(decompress_bytes(&src).unwrap(), data);
+
+        let mut corrupt = src.to_vec();
+        let last = corrupt.len() - 100;
+        corrupt[last] ^= 1;
+        let corrupt = Bytes::from(corrupt);
+        assert!(decompress_bytes(&corrupt).is_err());
+        let mut blocks = decompress_blocks_bytes(&corrupt);
+        assert!(blocks.by_ref().any(|block| block.is_err()));
+        assert!(blocks.next().is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! Cooperative cancellation for long-running operations.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag that stops an encoder or decoder at its next block boundary.
+///
+/// Clones share the same flag, so one clone can be handed to the operation
+/// and another kept by whoever decides to stop it (a Stop button, a signal
+/// handler). Two tokens compare equal only if they share a flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation. Operations already past their last check still finish.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl From<Arc<AtomicBool>> for CancelToken {
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        CancelToken(flag)
+    }
+}
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancelToken {}
+
+/// True if `token` is set and has been cancelled.
+pub(crate) fn is_cancelled(token: Option<&CancelToken>) -> bool {
+    token.is_some_and(CancelToken::is_cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{CompressError, DecompressError};
+    use crate::options::{CompressOptions, DecompressOptions};
+    use crate::{compress_with_options, compress_with_progress, copy_decode_with_options, copy_encode, decompress};
+    use std::io::{self, Read};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Generated input that ends only after a gigabyte, far more than the
+    /// tests below should ever read.
+    struct Endless(u64);
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min((1 << 30) - self.0 as usize);
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                let at = self.0 + i as u64;
+                *byte = if at % 1000 < 600 { 0 } else { (at * 7) as u8 };
+            }
+            self.0 += n as u64;
+            Ok(n)
+        }
+    }
+
+    fn generated(len: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        Endless(0).take(len).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// A token, and a sender that cancels it from another thread once sent to.
+    fn canceller() -> (CancelToken, mpsc::Sender<()>) {
+        let token = CancelToken::new();
+        let (tx, rx) = mpsc::channel();
+        let remote = token.clone();
+        thread::spawn(move || {
+            if rx.recv().is_ok() {
+                remote.cancel();
+            }
+        });
+        (token, tx)
+    }
+
+    #[test]
+    fn clones_share_the_flag() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert_eq!(token, clone);
+        assert_ne!(token, CancelToken::new());
+        assert!(!is_cancelled(Some(&clone)) && !is_cancelled(None));
+        token.cancel();
+        assert!(clone.is_cancelled() && is_cancelled(Some(&clone)));
+    }
+
+    #[test]
+    fn streaming_encode_stops_and_leaves_no_trailer() {
+        for threads in [0, 4] {
+            let token = CancelToken::new();
+            let remote = token.clone();
+            let timer = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                remote.cancel();
+                Instant::now()
+            });
+            let opts =
+                CompressOptions { block_size: 16 * 1024, threads, cancel: Some(token), ..CompressOptions::default() };
+            let mut out = Vec::new();
+            let err = copy_encode(Endless(0), &mut out, &opts, None).unwrap_err();
+            let cancelled_at = timer.join().unwrap();
+            assert!(matches!(err, CompressError::Cancelled), "{:?}", err);
+            assert!(cancelled_at.elapsed() < Duration::from_secs(2), "{} threads took too long", threads);
+            assert!(!out.is_empty());
+            assert!(matches!(decompress(&out), Err(DecompressError::Truncated { .. })));
+        }
+    }
+
+    #[test]
+    fn one_shot_encode_stops_partway() {
+        let data = generated(16 << 20);
+        let total = data.len().div_ceil(16 * 1024) as u32;
+        let (token, tx) = canceller();
+        let opts = CompressOptions { block_size: 16 * 1024, cancel: Some(token), ..CompressOptions::default() };
+        let mut blocks = 0;
+        let err = compress_with_progress(&data, &opts, &mut |progress| {
+            blocks = progress.blocks;
+            let _ = tx.send(());
+        })
+        .unwrap_err();
+        assert!(matches!(err, CompressError::Cancelled), "{:?}", err);
+        assert!(blocks < total, "all {} blocks were encoded", total);
+    }
+
+    #[test]
+    fn decode_stops_partway() {
+        let data = generated(16 << 20);
+        let frame = compress_with_options(&data, &CompressOptions { block_size: 16 * 1024, ..CompressOptions::default() })
+            .unwrap();
+        for threads in [1, 4] {
+            let (token, tx) = canceller();
+            let opts = DecompressOptions { threads, cancel: Some(token), ..DecompressOptions::default() };
+            let mut out = Vec::new();
+            let mut progress = |_| {
+                let _ = tx.send(());
+            };
+            let err = copy_decode_with_options(frame.as_slice(), &mut out, &opts, Some(&mut progress)).unwrap_err();
+            assert!(matches!(err, DecompressError::Cancelled), "{:?}", err);
+            assert!(out.len() < data.len(), "{} threads decoded everything", threads);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! `--checkpoint` and `--resume`: compressing into a partial output that
+//! records how far it got, so an interrupted run can carry on from there.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use ada_toolkit::checksum::{crc32, Crc32};
+use ada_toolkit::stream::AapcWriter;
+use ada_toolkit::{CompressOptions, CompressionStats, PhaseTimes, Progress, ResumePoint};
+
+use crate::context;
+use crate::commands::compress::FileRun;
+use crate::output::{batched, check_overwrite, AtomicFile, OutputLock};
+use crate::report::json_string;
+
+/// Blocks written between two saves of a `--checkpoint` file.
+const CHECKPOINT_BLOCKS: u32 = 16;
+
+/// Where `--checkpoint` or `--resume` saves a compression's progress.
+pub struct Checkpointing {
+    pub state: PathBuf,
+    /// Carry on from the saved progress rather than start afresh.
+    pub resume: bool,
+}
+
+/// A compression's saved progress: the frame's resume point, plus where
+/// the last block before it starts in the output and its CRC-32, so that a
+/// resumed run can check the partial output still ends with that block.
+struct Checkpoint {
+    point: ResumePoint,
+    last_block_offset: u64,
+    last_block_crc: u32,
+}
+
+impl Checkpoint {
+    /// The state file's JSON. `identity` names the run; see [`run_identity`].
+    fn to_json(&self, identity: &str, partial: &Path) -> String {
+        format!(
+            "{{\"version\":1,{},\"partial\":{},\"input_offset\":{},\"output_offset\":{},\"blocks\":{},\
+             \"stored_blocks\":{},\"content_crc\":{},\"last_block_offset\":{},\"last_block_crc\":{}}}\n",
+            identity, json_string(&partial.to_string_lossy()), self.point.input_bytes, self.point.output_bytes,
+            self.point.blocks, self.point.stored_blocks, self.point.content_crc, self.last_block_offset,
+            self.last_block_crc,
+        )
+    }
+
+    /// Reads back a state file written by [`Checkpoint::to_json`] for the
+    /// run named by `identity`.
+    fn load(state: &Path, identity: &str) -> io::Result<Checkpoint> {
+        let display = state.to_string_lossy();
+        let text = fs::read_to_string(state).map_err(|e| context(e, "reading checkpoint", &display))?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", display, msg));
+        if !text.contains(identity) {
+            return Err(invalid("saved for another input, output or --block-size, or the input has changed since"));
+        }
+        let number = |key: &str| -> io::Result<u64> {
+            let field = format!("\"{}\":", key);
+            let at = text.find(&field).ok_or_else(|| invalid("not a checkpoint file"))? + field.len();
+            let digits = text[at..].split(|c: char| !c.is_ascii_digit()).next().unwrap_or("");
+            digits.parse().map_err(|_| invalid("not a checkpoint file"))
+        };
+        let small = |key: &str| number(key)?.try_into().map_err(|_| invalid("not a checkpoint file"));
+        Ok(Checkpoint {
+            point: ResumePoint {
+                input_bytes: number("input_offset")?,
+                output_bytes: number("output_offset")?,
+                blocks: small("blocks")?,
+                stored_blocks: small("stored_blocks")?,
+                content_crc: small("content_crc")?,
+            },
+            last_block_offset: number("last_block_offset")?,
+            last_block_crc: small("last_block_crc")?,
+        })
+    }
+
+    /// Checks that `partial` still holds the last block this checkpoint
+    /// saw, and cuts off anything written after it.
+    fn restore(&self, partial: &mut File) -> io::Result<()> {
+        let end = self.point.output_bytes;
+        let start = self.last_block_offset.min(end);
+        let mut last_block = Vec::new();
+        partial.seek(SeekFrom::Start(start))?;
+        (&mut *partial).take(end - start).read_to_end(&mut last_block)?;
+        if last_block.len() as u64 != end - start || crc32(&last_block) != self.last_block_crc {
+            let msg = "the partial output does not match its checkpoint; start again with --checkpoint";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        partial.set_len(end)?;
+        partial.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+/// What a checkpoint must have been saved for to be resumed: the same
+/// input, unchanged, the same output and the same block size, which with
+/// a single codec level is all that decides the compressed bytes.
+fn run_identity(input: &str, output: &str, meta: &fs::Metadata, block_size: usize) -> String {
+    let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_nanos());
+    format!("\"input\":{},\"output\":{},\"input_size\":{},\"input_mtime\":{},\"block_size\":{}",
+            json_string(input), json_string(output), meta.len(), mtime, block_size)
+}
+
+/// Where a checkpointed compression writes until it is complete:
+/// `.name.partial` next to the output. Unlike an [`AtomicFile`] it outlives
+/// a failed or interrupted run, for `--resume` to carry on.
+fn partial_path(output: &Path) -> io::Result<PathBuf> {
+    let Some(name) = output.file_name() else {
+        let msg = format!("{} does not name a file", output.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    };
+    let mut partial = OsString::from(".");
+    partial.push(name);
+    partial.push(".partial");
+    Ok(output.parent().unwrap_or(Path::new("")).join(partial))
+}
+
+/// Passes a frame through to the partial output, keeping the offset and
+/// CRC-32 of the bytes since the last block boundary.
+struct BlockTail<W> {
+    inner: W,
+    offset: u64,
+    start: u64,
+    crc: Crc32,
+}
+
+impl<W> BlockTail<W> {
+    /// Where the block just completed starts, and its CRC-32; the next one
+    /// starts here.
+    fn take_block(&mut self) -> (u64, u32) {
+        let block = (self.start, self.crc.finish());
+        self.start = self.offset;
+        self.crc = Crc32::new();
+        block
+    }
+}
+
+impl<W: Write> Write for BlockTail<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.offset += n as u64;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compresses `input` into `output` through its partial file, saving a
+/// checkpoint every [`CHECKPOINT_BLOCKS`] blocks once the partial output is
+/// on disk. The partial file and checkpoint are kept if this fails, and the
+/// checkpoint removed once the output is complete; the result is
+/// byte-identical to an uninterrupted run. Returns the totals and the
+/// partial file, still locked, for the caller to commit over `output`.
+pub fn compress_checkpointed(
+    input: &str,
+    output: &str,
+    opts: &CompressOptions,
+    checkpointing: &Checkpointing,
+    run: &FileRun,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<(CompressionStats, AtomicFile)> {
+    let started = Instant::now();
+    let lock = OutputLock::acquire(Path::new(output), run.wait)?;
+    check_overwrite(Path::new(output), run.force)?;
+    let state = &checkpointing.state;
+    let partial = partial_path(Path::new(output))?;
+    let mut reader = File::open(input).map_err(|e| context(e, "reading input", input))?;
+    let identity = run_identity(input, output, &reader.metadata()?, opts.block_size);
+    let resumed = if checkpointing.resume {
+        Some(Checkpoint::load(state, &identity)?)
+    } else if state.exists() {
+        let msg = format!("{} already holds a checkpoint; carry on with --resume or delete it", state.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+    } else {
+        None
+    };
+    let partial_display = partial.to_string_lossy().into_owned();
+    let file = match &resumed {
+        Some(checkpoint) => {
+            let mut file = OpenOptions::new().read(true).write(true).open(&partial)
+                .map_err(|e| context(e, "resuming", &partial_display))?;
+            checkpoint.restore(&mut file)?;
+            reader.seek(SeekFrom::Start(checkpoint.point.input_bytes))?;
+            log::info!("Resuming {} at {} of input, {} of output", input, checkpoint.point.input_bytes,
+                       checkpoint.point.output_bytes);
+            file
+        }
+        None => File::create(&partial).map_err(|e| context(e, "writing output", &partial_display))?,
+    };
+    let offset = resumed.as_ref().map_or(0, |checkpoint| checkpoint.point.output_bytes);
+    let tail = BlockTail { inner: batched(file), offset, start: offset, crc: Crc32::new() };
+    let mut writer = match &resumed {
+        Some(checkpoint) => AapcWriter::resume(tail, opts, &checkpoint.point)?,
+        None => AapcWriter::with_options(tail, opts)?,
+    };
+    let mut report = |stats: CompressionStats| progress(Progress {
+        input_bytes: stats.input_bytes,
+        output_bytes: stats.output_bytes,
+        blocks: stats.blocks,
+        stored_blocks: stats.stored_blocks,
+    });
+    if resumed.is_some() {
+        report(writer.stats());
+    }
+    let mut saved = resumed.as_ref().map_or(0, |checkpoint| checkpoint.point.blocks);
+    let mut buf = vec![0; 64 * 1024];
+    let mut read = Duration::ZERO;
+    let result = (|| -> io::Result<()> {
+        loop {
+            let reading = Instant::now();
+            let n = reader.read(&mut buf);
+            read += reading.elapsed();
+            let n = match n {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let mut chunk = &buf[..n];
+            while !chunk.is_empty() {
+                chunk = &chunk[writer.write(chunk)?..];
+                let Some(point) = writer.resume_point() else { continue };
+                let (last_block_offset, last_block_crc) = writer.get_mut().take_block();
+                if point.blocks >= saved + CHECKPOINT_BLOCKS {
+                    let out = &mut writer.get_mut().inner;
+                    out.flush()?;
+                    out.get_ref().sync_data()?;
+                    let checkpoint = Checkpoint { point, last_block_offset, last_block_crc };
+                    let mut file = AtomicFile::create(state)?;
+                    file.write_all(checkpoint.to_json(&identity, &partial).as_bytes())?;
+                    file.commit().map_err(|e| context(e, "writing checkpoint", &state.to_string_lossy()))?;
+                    log::debug!("Saved checkpoint at block {}", point.blocks);
+                    saved = point.blocks;
+                }
+                report(writer.stats());
+            }
+        }
+    })();
+    if let Err(e) = result {
+        if state.exists() {
+            log::warn!("Progress is saved in {}; carry on with --resume {}", state.display(), state.display());
+        }
+        return Err(e);
+    }
+    writer.flush()?;
+    let stats = writer.stats();
+    report(stats);
+    let tail = writer.finish()?;
+    let output_bytes = tail.offset;
+    let file = tail.inner.into_inner()?;
+    file.sync_all()?;
+    if let Err(e) = fs::remove_file(state) {
+        log::warn!("Could not remove checkpoint {}: {}", state.display(), e);
+    }
+    let mut file = AtomicFile::adopt(file, partial, Path::new(output));
+    file.lock = Some(lock);
+    let phases = PhaseTimes { wall: started.elapsed(), read: stats.phases.read + read, ..stats.phases };
+    Ok((CompressionStats { output_bytes, phases, ..stats }, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn checkpoint(partial: &[u8], last_block_offset: usize) -> Checkpoint {
+        Checkpoint {
+            point: ResumePoint {
+                input_bytes: 65_536,
+                output_bytes: partial.len() as u64,
+                blocks: 16,
+                stored_blocks: 3,
+                content_crc: 0xdead_beef,
+            },
+            last_block_offset: last_block_offset as u64,
+            last_block_crc: crc32(&partial[last_block_offset..]),
+        }
+    }
+
+    #[test]
+    fn a_saved_checkpoint_loads_back_for_its_run_only() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let input = dir.join("in.bin");
+        fs::write(&input, "data").unwrap();
+        let identity = run_identity("in.bin", "in.bin.aapc", &fs::metadata(&input).unwrap(), 4096);
+        let saved = checkpoint(b"header and some blocks", 10);
+        let state = dir.join("state.json");
+        fs::write(&state, saved.to_json(&identity, Path::new(".in.bin.aapc.partial"))).unwrap();
+
+        let loaded = Checkpoint::load(&state, &identity).unwrap();
+        assert_eq!(loaded.point, saved.point);
+        assert_eq!((loaded.last_block_offset, loaded.last_block_crc), (10, saved.last_block_crc));
+
+        for other in [run_identity("in.bin", "in.bin.aapc", &fs::metadata(&input).unwrap(), 8192),
+                      run_identity("in.bin", "other.aapc", &fs::metadata(&input).unwrap(), 4096)] {
+            let err = Checkpoint::load(&state, &other).err().unwrap();
+            assert!(err.to_string().contains("saved for another input"), "{}", err);
+        }
+        fs::write(&state, "{}").unwrap();
+        assert_eq!(Checkpoint::load(&state, "{}").err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn restoring_cuts_off_the_tail_after_the_last_block() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let path = dir.join("partial");
+        let kept = b"header, block one, block two";
+        let saved = checkpoint(kept, 18);
+        fs::write(&path, [&kept[..], b"half of block three"].concat()).unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        saved.restore(&mut file).unwrap();
+        assert_eq!(file.stream_position().unwrap(), kept.len() as u64);
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), kept);
+    }
+
+    #[test]
+    fn a_partial_output_that_changed_is_not_restored() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let path = dir.join("partial");
+        let saved = checkpoint(b"header, block one, block two", 18);
+        for partial in [&b"header, block one, block TWO"[..], b"header, block one"] {
+            fs::write(&path, partial).unwrap();
+            let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+            let err = saved.restore(&mut file).unwrap_err();
+            assert!(err.to_string().contains("does not match its checkpoint"), "{}", err);
+            drop(file);
+            assert_eq!(fs::read(&path).unwrap(), partial);
+        }
+    }
+
+    #[test]
+    fn the_partial_file_sits_hidden_next_to_the_output() {
+        assert_eq!(partial_path(Path::new("out/data.aapc")).unwrap(), Path::new("out/.data.aapc.partial"));
+        assert_eq!(partial_path(Path::new("data.aapc")).unwrap(), Path::new(".data.aapc.partial"));
+        assert!(partial_path(Path::new("out/..")).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! CRC-32 (IEEE 802.3, the zlib/PNG polynomial).
+
+/// `TABLE[0]` is the byte-at-a-time table; `TABLE[n]` carries an entry on
+/// through `n` more zero bytes, so that `update` can take eight bytes a step.
+const TABLE: [[u32; 256]; 8] = build_table();
+
+const fn build_table() -> [[u32; 256]; 8] {
+    let mut table = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { POLY ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[0][i] = crc;
+        i += 1;
+    }
+    let mut n = 1;
+    while n < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = table[n - 1][i];
+            table[n][i] = table[0][(prev & 0xFF) as usize] ^ (prev >> 8);
+            i += 1;
+        }
+        n += 1;
+    }
+    table
+}
+
+/// Incremental CRC-32.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        let mut words = data.chunks_exact(8);
+        for word in &mut words {
+            let lo = crc ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            let hi = u32::from_le_bytes([word[4], word[5], word[6], word[7]]);
+            crc = TABLE[7][(lo & 0xFF) as usize]
+                ^ TABLE[6][((lo >> 8) & 0xFF) as usize]
+                ^ TABLE[5][((lo >> 16) & 0xFF) as usize]
+                ^ TABLE[4][(lo >> 24) as usize]
+                ^ TABLE[3][(hi & 0xFF) as usize]
+                ^ TABLE[2][((hi >> 8) & 0xFF) as usize]
+                ^ TABLE[1][((hi >> 16) & 0xFF) as usize]
+                ^ TABLE[0][(hi >> 24) as usize];
+        }
+        for &byte in words.remainder() {
+            crc = TABLE[0][((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+        }
+        self.state = crc;
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+
+    /// Carries on a checksum whose `finish` returned `crc`.
+    pub fn resume(crc: u32) -> Self {
+        Crc32 { state: !crc }
+    }
+
+    /// Carries on as if `update` had been called with `len` bytes whose own
+    /// CRC-32 is `crc`, without reading them again: the state so far is
+    /// multiplied by x^(8 * len) modulo the polynomial, and `crc` added.
+    pub fn combine(&mut self, crc: u32, len: u64) {
+        self.state = !(multiply(shift_for(len), self.finish()) ^ crc);
+    }
+}
+
+/// The polynomial, in the reflected bit order the table uses.
+const POLY: u32 = 0xEDB8_8320;
+
+/// x^(2^k) modulo the polynomial. They repeat with a period of 32, so
+/// `shift_for` wraps around the table.
+const POWERS: [u32; 32] = build_powers();
+
+const fn build_powers() -> [u32; 32] {
+    let mut powers = [0u32; 32];
+    // x^1, as bits run from x^0 at the top down to x^31.
+    let mut power = 1 << 30;
+    let mut k = 0;
+    while k < 32 {
+        powers[k] = power;
+        power = multiply(power, power);
+        k += 1;
+    }
+    powers
+}
+
+/// `a` times `b` modulo the polynomial; `a` must not be 0.
+const fn multiply(a: u32, mut b: u32) -> u32 {
+    let mut m = 1u32 << 31;
+    let mut product = 0;
+    loop {
+        if a & m != 0 {
+            product ^= b;
+            if a & (m - 1) == 0 {
+                return product;
+            }
+        }
+        m >>= 1;
+        b = if b & 1 != 0 { (b >> 1) ^ POLY } else { b >> 1 };
+    }
+}
+
+/// x^(8 * len) modulo the polynomial, from the powers for the bits of
+/// `len` shifted up three, for the bits of a byte.
+fn shift_for(mut len: u64) -> u32 {
+    let mut shift = 1 << 31;
+    let mut k = 3;
+    while len != 0 {
+        if len & 1 != 0 {
+            shift = multiply(POWERS[k & 31], shift);
+        }
+        len >>= 1;
+        k += 1;
+    }
+    shift
+}
+
+/// CRC-32 of `data` in one call.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CRC-32 a bit at a time, straight from the polynomial.
+    fn bitwise(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eight_bytes_a_step_match_one_bit_a_step() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let data = data(200);
+        for start in 0..8 {
+            for end in start..data.len() {
+                assert_eq!(crc32(&data[start..end]), bitwise(&data[start..end]), "{}..{}", start, end);
+            }
+        }
+        let mut pieces = Crc32::new();
+        for piece in data.chunks(13) {
+            pieces.update(piece);
+        }
+        assert_eq!(pieces.finish(), bitwise(&data));
+    }
+
+    #[test]
+    fn combining_matches_hashing_the_whole() {
+        let data = data(70_000);
+        for split in [0, 1, 7, 8, 9, 4096, 65_537, data.len()] {
+            let (head, tail) = data.split_at(split);
+            let mut crc = Crc32::new();
+            crc.update(head);
+            crc.combine(crc32(tail), tail.len() as u64);
+            assert_eq!(crc.finish(), crc32(&data), "split at {}", split);
+
+            let mut resumed = Crc32::resume(crc32(head));
+            resumed.update(tail);
+            assert_eq!(resumed.finish(), crc32(&data), "resumed at {}", split);
+        }
+        let mut blocks = Crc32::new();
+        for block in data.chunks(4096) {
+            blocks.combine(crc32(block), block.len() as u64);
+        }
+        assert_eq!(blocks.finish(), crc32(&data));
+    }
+}
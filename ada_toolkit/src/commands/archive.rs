@@ -0,0 +1,492 @@
+//! `archive create`, `list` and `extract`.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::time::{Duration, UNIX_EPOCH};
+
+use ada_toolkit::{read_members, write_archive_concurrent, Archive, CompressOptions, Member, MemberKind};
+
+use crate::{cancel_on_interrupt, context, usage_error, Failure, Format, Global, Incompressible, Tuning, WriteCheck};
+use crate::commands::compress::already_compressed;
+use crate::commands::dir::{Filter, Walk};
+use crate::output::{check_overwrite, verify_archive, AtomicFile, OutputLock, STDIO};
+use crate::progress::{Metered, Meters};
+use crate::report::json_string;
+
+/// Packs `paths` into a new archive. Directories are walked in sorted
+/// order, storing the symlinks in them as symlinks and skipping special
+/// files; the archive itself is never added.
+pub fn create_archive(
+    archive: &str,
+    paths: &[PathBuf],
+    force: bool,
+    filter: &Filter,
+    skip_compressed: bool,
+    check: WriteCheck,
+    global: &Global,
+) -> Result<(), Failure> {
+    if archive == STDIO {
+        usage_error("archives must be written to a file, not stdout")
+    }
+    let opts = CompressOptions {
+        cancel: Some(cancel_on_interrupt()),
+        ..Tuning::default().threaded_options(global.max_memory, global.threads())?
+    };
+    let lock = OutputLock::acquire(Path::new(archive), global.wait)?;
+    check_overwrite(Path::new(archive), force)?;
+    let mut out = AtomicFile::create(Path::new(archive)).map_err(|e| context(e, "writing output", archive))?;
+    out.lock = Some(lock);
+    let (mut filtered, mut skipped) = (0, 0);
+    // Neither the archive being written nor the one it replaces is packed.
+    let written: Vec<PathBuf> = [out.temp.as_path(), Path::new(archive)].iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+    let collected = collect_members(paths, &written, filter);
+    let result = collected.and_then(|(members, sources, left_out)| {
+        filtered = left_out;
+        let (mut members, sources): (Vec<Member>, Vec<PathBuf>) = match skip_compressed {
+            false => (members, sources),
+            true => {
+                let mut kept = (Vec::new(), Vec::new());
+                for (member, source) in members.into_iter().zip(sources) {
+                    let source_name = source.to_string_lossy();
+                    let kind = match member.kind {
+                        MemberKind::File => already_compressed(&source_name)
+                            .map_err(|e| context(e, "reading", &source_name))?,
+                        MemberKind::Directory | MemberKind::Symlink => None,
+                    };
+                    match kind {
+                        Some(kind) => {
+                            log::info!("Skipping {}: already compressed ({})", member.path, kind);
+                            skipped += 1;
+                        }
+                        None => {
+                            kept.0.push(member);
+                            kept.1.push(source);
+                        }
+                    }
+                }
+                kept
+            }
+        };
+        let files = members.iter().filter(|member| member.kind == MemberKind::File).count();
+        let meters = Meters::new(global.progress_mode()).with_overall(files);
+        let open = |index: usize, member: &Member| -> io::Result<Box<dyn Read>> {
+            log::info!("Adding {}", member.path);
+            if member.kind == MemberKind::Symlink {
+                let target = fs::read_link(&sources[index])?;
+                let target = target.into_os_string().into_string().map_err(|target| {
+                    let msg = format!("the target of {} is not valid UTF-8: {}", member.path, Path::new(&target).display());
+                    io::Error::new(io::ErrorKind::InvalidData, msg)
+                })?;
+                return Ok(Box::new(io::Cursor::new(target.into_bytes())));
+            }
+            let file = File::open(&sources[index])?;
+            let meter = meters.file(&member.path, file.metadata().ok().map(|meta| meta.len()));
+            Ok(Box::new(Metered::new(BufReader::new(file), meter)))
+        };
+        let result = write_archive_concurrent(&mut out, &mut members, open, &opts);
+        meters.finish();
+        result?;
+        Ok(members)
+    });
+    let members = result?;
+    let verified = check.commit_file(out, archive, |file| verify_archive(file, &members))?;
+    let (files, others): (Vec<&Member>, Vec<&Member>) =
+        members.iter().partition(|member| member.kind == MemberKind::File);
+    let links = others.iter().filter(|member| member.kind == MemberKind::Symlink).count();
+    let units = global.units();
+    writeln!(global.status(false),
+             "Archived {} files, {} directories and {} symlinks ({}) into {} ({}); {} filtered out{}{}",
+             files.len(), others.len() - links, links, units.size(files.iter().map(|member| member.size).sum::<u64>()),
+             archive, units.size(fs::metadata(archive)?.len()), filtered,
+             Incompressible { skip_compressed, ..Incompressible::default() }.summary(skipped, 0),
+             if verified { "; verified after writing" } else { "" })?;
+    Ok(())
+}
+
+/// The archive members for `paths`, with the file each file member is read
+/// from or the link each symlink member is (directories have an empty
+/// source), and how many entries under the directories among them `filter`
+/// left out. Paths named directly are always kept, and followed if they
+/// are symlinks.
+fn collect_members(
+    paths: &[PathBuf],
+    archive: &[PathBuf],
+    filter: &Filter,
+) -> io::Result<(Vec<Member>, Vec<PathBuf>, usize)> {
+    let mut members = Vec::new();
+    let mut sources = Vec::new();
+    let mut add = |rel: &Path, kind: MemberKind, source: PathBuf| -> io::Result<()> {
+        let meta = match kind {
+            MemberKind::Symlink => fs::symlink_metadata(&source),
+            _ => fs::metadata(&source),
+        };
+        let meta = meta.map_err(|e| context(e, "reading", &source.to_string_lossy()))?;
+        let path = rel.to_str().map(|path| path.replace(MAIN_SEPARATOR, "/")).ok_or_else(|| {
+            let msg = format!("{} is not valid UTF-8", rel.display());
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+        members.push(Member::new(path, kind, mode_of(&meta), mtime_of(&meta)));
+        sources.push(if kind == MemberKind::Directory { PathBuf::new() } else { source });
+        Ok(())
+    };
+    let mut filtered = 0;
+    for path in paths {
+        let base = match path.file_name() {
+            Some(name) => PathBuf::from(name),
+            None => match fs::canonicalize(path)?.file_name() {
+                Some(name) => PathBuf::from(name),
+                None => usage_error(&format!("cannot name an archive member after {}", path.display())),
+            },
+        };
+        let meta = fs::metadata(path).map_err(|e| context(e, "reading", &path.to_string_lossy()))?;
+        if !meta.is_dir() {
+            add(&base, MemberKind::File, path.clone())?;
+            continue;
+        }
+        let mut walk = Walk::new(filter);
+        let mut seen = HashSet::from([fs::canonicalize(path)?]);
+        walk.visit(path, Path::new(""), false, Path::new(""), &mut seen);
+        if let Some(failure) = walk.errors.into_iter().next() {
+            return Err(failure.error);
+        }
+        filtered += walk.filtered.len();
+        add(&base, MemberKind::Directory, path.clone())?;
+        for dir in &walk.dirs {
+            add(&base.join(dir), MemberKind::Directory, path.join(dir))?;
+        }
+        for file in &walk.files {
+            let source = path.join(file);
+            if fs::canonicalize(&source).is_ok_and(|source| archive.contains(&source)) {
+                continue;
+            }
+            add(&base.join(file), MemberKind::File, source)?;
+        }
+        for link in &walk.links {
+            add(&base.join(link), MemberKind::Symlink, path.join(link))?;
+        }
+    }
+    let mut names = HashSet::new();
+    if let Some(duplicate) = members.iter().find(|member| !names.insert(&member.path)) {
+        let msg = format!("two inputs would both be stored as {}", duplicate.path);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    Ok((members, sources, filtered))
+}
+
+/// Restores the members of `archive` selected by `names` and `globs` (all
+/// of them if both are empty) under `dir`, then sets modification times and
+/// permissions, directories last so their contents don't disturb them.
+/// Symlinks are made after every file, so that no file of the archive is
+/// written through one, and those with an absolute target only with
+/// `absolute_symlinks`. A relative target that climbs out of `dir` is
+/// skipped, and fails the extraction once the rest is done. Nothing is
+/// made, removed or changed through a symlink under `dir`, whether the
+/// archive made it or it was there before. Only the selected members'
+/// frames are read. Fails after extracting if a name or pattern matched
+/// nothing.
+pub fn extract_archive(
+    archive: &str,
+    dir: &Path,
+    names: &[String],
+    globs: &[String],
+    force: bool,
+    absolute_symlinks: bool,
+    global: &Global,
+) -> Result<(), Failure> {
+    let file = File::open(archive).map_err(|e| context(e, "reading input", archive))?;
+    let mut reader = Archive::open(BufReader::new(file)).map_err(|e| Failure::from(e).context("reading", archive))?;
+    let names: Vec<&str> = names.iter().map(|name| name.trim_end_matches('/')).collect();
+    let mut name_used = vec![false; names.len()];
+    let mut glob_used = vec![false; globs.len()];
+    let mut members = Vec::new();
+    for (index, member) in reader.members().iter().enumerate() {
+        let mut selected = names.is_empty() && globs.is_empty();
+        for (name, used) in names.iter().zip(&mut name_used) {
+            let under = member.path.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+            *used |= under;
+            selected |= under;
+        }
+        for (glob, used) in globs.iter().zip(&mut glob_used) {
+            let matched = glob_match(glob.as_bytes(), member.path.as_bytes());
+            *used |= matched;
+            selected |= matched;
+        }
+        if selected {
+            members.push((index, member.clone()));
+        }
+    }
+
+    fs::create_dir_all(dir).map_err(|e| context(e, "creating output directory", &dir.to_string_lossy()))?;
+    let files = members.iter().filter(|(_, member)| member.kind == MemberKind::File).count();
+    let meters = Meters::new(global.progress_mode()).with_overall(files);
+    let mut bytes = 0;
+    let mut links = Vec::new();
+    for (index, member) in &members {
+        let index = *index;
+        let dest = dir.join(member.path.split('/').collect::<PathBuf>());
+        match member.kind {
+            MemberKind::Directory => {
+                log::info!("Extracting {}", dest.display());
+                refuse_symlinks(dir, &dest, true)?;
+                fs::create_dir_all(&dest)?;
+                continue;
+            }
+            MemberKind::Symlink => {
+                links.push((index, dest, member));
+                continue;
+            }
+            MemberKind::File => log::info!("Extracting {}", dest.display()),
+        }
+        refuse_symlinks(dir, &dest, false)?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        check_overwrite(&dest, force)?;
+        // Renaming into place replaces a symlink rather than writing through it.
+        let mut out = AtomicFile::create(&dest)?;
+        let meter = meters.file(&member.path, Some(member.size));
+        let result = reader.extract(index, Metered::new(&mut out, meter)).map_err(io::Error::from).and_then(|written| {
+            out.commit()?;
+            Ok(written)
+        });
+        bytes += result.map_err(|e| Failure::from(e).context("extracting", &member.path))?;
+        refuse_symlinks(dir, &dest, true)?;
+        restore_metadata(&dest, member)?;
+    }
+    meters.finish();
+    let mut linked = 0;
+    let mut escaping = Vec::new();
+    let link_paths: HashSet<&str> = links.iter().map(|(_, _, member)| member.path.as_str()).collect();
+    for &(index, ref dest, member) in &links {
+        let target = reader.link_target(index).map_err(|e| Failure::from(e).context("extracting", &member.path))?;
+        if Path::new(&target).is_absolute() && !absolute_symlinks {
+            log::warn!("Skipping symlink {} -> {}: its target is absolute; use --absolute-symlinks to make it",
+                       dest.display(), target);
+            continue;
+        }
+        if !Path::new(&target).is_absolute() && !stays_inside(&member.path, &target, &link_paths) {
+            log::warn!("Skipping symlink {} -> {}: its target leaves {}", dest.display(), target, dir.display());
+            escaping.push(member.path.as_str());
+            continue;
+        }
+        log::info!("Extracting {} -> {}", dest.display(), target);
+        refuse_symlinks(dir, dest, false)?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        check_overwrite(dest, force)?;
+        // An old entry there, even a link, is removed rather than followed.
+        if fs::symlink_metadata(dest).is_ok() {
+            fs::remove_file(dest).map_err(|e| context(e, "replacing", &dest.to_string_lossy()))?;
+        }
+        make_symlink(&target, dest).map_err(|e| context(e, "extracting", &member.path))?;
+        linked += 1;
+    }
+    for (_, member) in members.iter().rev().filter(|(_, member)| member.kind == MemberKind::Directory) {
+        let dest = dir.join(member.path.split('/').collect::<PathBuf>());
+        refuse_symlinks(dir, &dest, true)?;
+        restore_metadata(&dest, member)?;
+    }
+    let dirs = members.iter().filter(|(_, member)| member.kind == MemberKind::Directory).count();
+    writeln!(global.status(false), "Extracted {} files, {} directories and {} symlinks ({}) from {} into {}",
+             files, dirs, linked, global.units().size(bytes), archive, dir.display())?;
+
+    let missing: Vec<&str> = names.iter().copied().zip(name_used)
+        .chain(globs.iter().map(String::as_str).zip(glob_used))
+        .filter(|(_, used)| !used)
+        .map(|(name, _)| name)
+        .collect();
+    if !missing.is_empty() {
+        let msg = format!("not found in {}: {}", archive, missing.join(", "));
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+    }
+    if !escaping.is_empty() {
+        let msg = format!("skipped symlinks whose target leaves {}: {}", dir.display(), escaping.join(", "));
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+    }
+    Ok(())
+}
+
+/// Whether the relative `target` of the symlink member `link` stays under
+/// the extraction directory, resolved against the link's parent without
+/// following anything. A `..` after a component that is one of `links` is
+/// taken to leave, as where it ends up depends on that link.
+fn stays_inside(link: &str, target: &str, links: &HashSet<&str>) -> bool {
+    let mut resolved: Vec<&str> = link.split('/').collect();
+    resolved.pop();
+    let mut through_link = false;
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if through_link || resolved.pop().is_none() {
+                    return false;
+                }
+            }
+            part => {
+                resolved.push(part);
+                through_link |= links.contains(resolved.join("/").as_str());
+            }
+        }
+    }
+    true
+}
+
+/// Fails if a path between `dir` and `dest`, below `dir` and down to
+/// `dest`'s parent, or to `dest` itself with `itself`, is a symlink, which
+/// extracting would otherwise follow, perhaps out of `dir`.
+fn refuse_symlinks(dir: &Path, dest: &Path, itself: bool) -> io::Result<()> {
+    let rel = dest.strip_prefix(dir).expect("members extract under the directory");
+    let mut path = dir.to_path_buf();
+    let mut parts = rel.components().peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() && !itself {
+            break;
+        }
+        path.push(part);
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let msg = format!("{} is a symlink; not extracting through it", path.display());
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+            Ok(_) => {}
+            // Nothing below a missing path exists either.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Matches an archive path against a glob: `*` and `?` never match `/`,
+/// `**` matches anything including `/`, and `**/` also matches nothing.
+pub fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_match(rest, path)
+                || (1..=path.len()).any(|i| path[i - 1] == b'/' && glob_match(rest, &path[i..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let segment = path.iter().position(|&b| b == b'/').unwrap_or(path.len());
+            (0..=segment).any(|i| glob_match(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => path.first().is_some_and(|&b| b != b'/') && glob_match(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && glob_match(rest, &path[1..]),
+    }
+}
+
+/// Prints the member table of `archive`, one row per member.
+pub fn list_archive(archive: &str, long: bool, global: &Global) -> Result<(), Failure> {
+    let file = File::open(archive).map_err(|e| context(e, "reading input", archive))?;
+    let members = read_members(BufReader::new(file)).map_err(|e| Failure::from(e).context("reading", archive))?;
+    let mut out = global.status(false);
+    if global.format == Format::Json {
+        let rows: Vec<String> = members
+            .iter()
+            .map(|member| {
+                let kind = match member.kind {
+                    MemberKind::File => "file",
+                    MemberKind::Directory => "directory",
+                    MemberKind::Symlink => "symlink",
+                };
+                format!(
+                    "{{\"path\":{},\"kind\":\"{}\",\"size\":{},\"compressed_size\":{},\"mtime\":{},\
+                     \"mode\":{},\"checksum\":\"{:08x}\"}}",
+                    json_string(&member.path), kind, member.size, member.compressed_size, member.mtime,
+                    member.mode, member.checksum
+                )
+            })
+            .collect();
+        writeln!(out, "{{\"archive\":{},\"members\":[{}]}}", json_string(archive), rows.join(","))?;
+        return Ok(());
+    }
+
+    if long {
+        write!(out, "{:<6} {:<8} ", "Mode", "CRC32")?;
+    }
+    writeln!(out, "{:>12} {:>12} {:>6} {:<19} Path", "Size", "Compressed", "Ratio", "Modified (UTC)")?;
+    for member in &members {
+        if long {
+            write!(out, "{:<6} {:<8} ", format!("{:04o}", member.mode), format!("{:08x}", member.checksum))?;
+        }
+        let (ratio, suffix) = match member.kind {
+            MemberKind::File if member.size > 0 => {
+                (format!("{:.2}", member.compressed_size as f64 / member.size as f64), "")
+            }
+            MemberKind::File => ("-".to_string(), ""),
+            MemberKind::Directory => ("-".to_string(), "/"),
+            MemberKind::Symlink => ("-".to_string(), "@"),
+        };
+        writeln!(out, "{:>12} {:>12} {:>6} {:<19} {}{}", member.size, member.compressed_size, ratio,
+                 format_utc(member.mtime), member.path, suffix)?;
+    }
+    Ok(())
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` in UTC.
+fn format_utc(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+#[cfg(unix)]
+fn make_symlink(target: &str, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn make_symlink(_target: &str, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symlinks can only be restored on Unix"))
+}
+
+#[cfg(unix)]
+fn mode_of(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(_meta: &fs::Metadata) -> u32 {
+    0
+}
+
+/// Modification time in seconds since the Unix epoch, or 0 if unknown.
+fn mtime_of(meta: &fs::Metadata) -> i64 {
+    match meta.modified().map(|time| time.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(since)) => since.as_secs() as i64,
+        Ok(Err(before)) => -(before.duration().as_secs() as i64),
+        Err(_) => 0,
+    }
+}
+
+/// Applies a member's modification time and, on Unix, permission bits.
+/// Both go to the file opened once, so a symlink swapped in afterwards is
+/// not followed a second time; [`refuse_symlinks`] checks `path` first.
+fn restore_metadata(path: &Path, member: &Member) -> io::Result<()> {
+    let offset = Duration::from_secs(member.mtime.unsigned_abs());
+    let mtime = if member.mtime >= 0 { UNIX_EPOCH + offset } else { UNIX_EPOCH - offset };
+    let file = File::open(path)?;
+    file.set_modified(mtime)?;
+    #[cfg(unix)]
+    if member.mode != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(member.mode))?;
+    }
+    Ok(())
+}
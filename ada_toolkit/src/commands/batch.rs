@@ -0,0 +1,215 @@
+//! `batch`: a manifest of input and output pairs.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{cancel_on_interrupt, context, BatchArgs, Failure, Format, Global};
+use crate::commands::compress::{compress_file, FileReport, FileRun, Sniffed, Writing};
+use crate::progress::Meters;
+use crate::report::{csv_fields, dry_run_note, file_json, file_line, json_string, write_failures, BatchReport};
+use crate::workers::{file_workers, run_in_order, SharedOutputs};
+
+/// Reads the jobs of a `batch` manifest as (source, destination) pairs,
+/// with relative paths joined onto the manifest's directory.
+fn read_manifest(manifest: &Path) -> io::Result<Vec<(String, String)>> {
+    let display = manifest.to_string_lossy();
+    let text = fs::read_to_string(manifest).map_err(|e| context(e, "reading manifest", &display))?;
+    let csv = manifest.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let base = manifest.parent().unwrap_or(Path::new(""));
+    let resolve = |path: &str| base.join(path).to_string_lossy().into_owned();
+    let mut jobs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = match csv {
+            true => csv_fields(line),
+            false => Some(line.split('\t').map(str::to_string).collect()),
+        };
+        let header = |src: &str, dst: &str| {
+            ["src", "source"].iter().any(|name| src.eq_ignore_ascii_case(name))
+                && ["dst", "destination"].iter().any(|name| dst.eq_ignore_ascii_case(name))
+        };
+        match fields.as_deref() {
+            Some([src, dst]) if csv && jobs.is_empty() && header(src, dst) => {}
+            Some([src, dst]) if !src.is_empty() && !dst.is_empty() => jobs.push((resolve(src), resolve(dst))),
+            _ => {
+                let expected = if csv { "source,destination" } else { "source<TAB>destination" };
+                let msg = format!("{}:{}: expected {}", display, number + 1, expected);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        }
+    }
+    Ok(jobs)
+}
+
+/// Runs the jobs of a `batch` manifest in order, stopping at the first
+/// failure unless `--continue-on-error` is set.
+pub fn run_manifest(args: &BatchArgs, global: &Global) -> Result<(), Failure> {
+    let opts = args.tuning.options(global.max_memory)?;
+    let jobs = read_manifest(&args.manifest)?;
+    let format = global.format;
+    let units = global.units();
+    let mut report = args.report.as_deref().map(|path| {
+        let options = format!(
+            "{{\"manifest\":{},\"block_size\":{},\"force\":{},\"parents\":{},\"continue_on_error\":{},\
+             \"dry_run\":{},\"skip_compressed\":{},\"store_incompressible\":{},\"max_memory\":{}}}",
+            json_string(&args.manifest.to_string_lossy()), opts.block_size, args.force,
+            args.parents, args.continue_on_error, args.dry_run, args.incompressible.skip_compressed,
+            args.incompressible.store_incompressible,
+            global.max_memory.map_or("null".to_string(), |limit| limit.to_string()),
+        );
+        BatchReport::new(path, true, jobs.len(), options)
+    });
+    let cancel = cancel_on_interrupt();
+    let meters = Meters::new(global.progress_mode()).with_overall(jobs.len());
+    let (workers, threads) = file_workers(jobs.len(), global.threads(), opts.block_size, global.max_memory);
+    let run = FileRun {
+        force: args.force,
+        rm: false,
+        tuning: &args.tuning,
+        incompressible: args.incompressible,
+        writing: Writing { dry_run: args.dry_run, ..Writing::default() },
+        max_memory: global.max_memory,
+        threads,
+        workers,
+        wait: global.wait,
+        lone_input: false,
+        cancel: &cancel,
+        meters: &meters,
+    };
+    let mut status = meters.above(global.status(false));
+    global.report_threads(true);
+    let mut results = Vec::new();
+    let shared = SharedOutputs::new(jobs.iter().map(|(_, dst)| Some(dst.as_str())).collect());
+    run_in_order(&jobs, workers, &cancel, |index, (src, dst)| shared.run(index, src, || {
+        let parent = Path::new(dst).parent().filter(|parent| !parent.as_os_str().is_empty());
+        match parent.filter(|parent| !parent.is_dir()) {
+            Some(parent) => match args.parents {
+                true if args.dry_run => compress_file(src, dst, &run),
+                true => fs::create_dir_all(parent)
+                    .map_err(|e| context(e, "creating directory for", dst).into())
+                    .and_then(|()| compress_file(src, dst, &run)),
+                false => {
+                    let msg = format!("directory {} does not exist; use --parents to create it", parent.display());
+                    Err(io::Error::new(io::ErrorKind::NotFound, msg).into())
+                }
+            },
+            None => compress_file(src, dst, &run),
+        }
+    }), |index, result| {
+        let (src, dst) = &jobs[index];
+        // Recorded first, so that a report cut short by a failing status
+        // line still lists the job it was about.
+        if let Some(report) = &mut report {
+            report.add(src, dst, &result);
+        }
+        match &result {
+            Ok(FileReport { sniffed: Some(Sniffed { kind, stored: false }), input_bytes, .. }) => {
+                if format == Format::Text {
+                    writeln!(status, "Skipped {} ({}): already compressed ({})", src, units.size(*input_bytes), kind)?;
+                }
+            }
+            Ok(file) => {
+                if format == Format::Text {
+                    writeln!(status, "{}", file_line(src, dst, file, true, args.dry_run, units))?;
+                }
+            }
+            Err(failure) if format == Format::Text => {
+                writeln!(meters.above(io::stderr()), "Error: {}", failure.error)?
+            }
+            Err(_) => {}
+        }
+        let stop = result.is_err() && !args.continue_on_error;
+        results.push(result);
+        Ok(stop)
+    })?;
+    meters.finish();
+    if let Some(report) = report {
+        report.finish(cancel.is_cancelled())?;
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    let skipped = results.iter()
+        .filter(|result| matches!(result, Ok(FileReport { sniffed: Some(Sniffed { stored: false, .. }), .. })))
+        .count();
+    let errors: Vec<String> = results.iter()
+        .filter_map(|result| result.as_ref().err().map(|failure| failure.error.to_string()))
+        .collect();
+    match format {
+        Format::Text => {
+            writeln!(status, "{}{} of {} jobs done; {} failed, {} not run",
+                     dry_run_note(args.dry_run), results.len() - failed - skipped, jobs.len(), failed,
+                     jobs.len() - results.len())?;
+            write_failures(&mut status, &errors)?;
+        }
+        Format::Json => {
+            let files: Vec<String> = jobs.iter().zip(&results)
+                .map(|((src, dst), result)| file_json(src, dst, result, true))
+                .collect();
+            writeln!(status, "{{\"operation\":\"batch\",\"manifest\":{},\"dry_run\":{},\"succeeded\":{},\
+                              \"failed\":{},\"skipped\":{},\"skipped_compressed\":{},\"files\":[{}]}}",
+                     json_string(&args.manifest.to_string_lossy()), args.dry_run, results.len() - failed - skipped, failed,
+                     jobs.len() - results.len(), skipped, files.join(","))?;
+        }
+    }
+    match results.into_iter().find_map(Result::err) {
+        Some(first) => {
+            let msg = format!("{} of {} jobs failed", failed, jobs.len());
+            Err(Failure { code: first.code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// The jobs of a manifest `name` holding `text`, with paths given
+    /// relative to its directory.
+    fn jobs(name: &str, text: &str) -> io::Result<Vec<(String, String)>> {
+        let tmp = TempDir::new();
+        let jobs = read_manifest(&tmp.write(name, text))?;
+        let base = format!("{}/", tmp.path().display());
+        let relative = |path: String| path.strip_prefix(&base).map(str::to_string).unwrap_or(path);
+        Ok(jobs.into_iter().map(|(src, dst)| (relative(src), relative(dst))).collect())
+    }
+
+    fn owned(jobs: &[(&str, &str)]) -> Vec<(String, String)> {
+        jobs.iter().map(|(src, dst)| (src.to_string(), dst.to_string())).collect()
+    }
+
+    #[test]
+    fn tab_manifests_skip_blanks_and_comments() {
+        let text = "# exports\nin/a.csv\tout/a.csv.aapc\r\n\n   \n/abs/b.csv\tb.aapc\n";
+        assert_eq!(jobs("jobs.txt", text).unwrap(),
+                   owned(&[("in/a.csv", "out/a.csv.aapc"), ("/abs/b.csv", "b.aapc")]));
+    }
+
+    #[test]
+    fn csv_manifests_may_have_a_header_and_quoting() {
+        let text = "Source,Destination\n\"a, b.txt\",\"say \"\"hi\"\".aapc\"\nc.txt,c.aapc\n";
+        assert_eq!(jobs("jobs.csv", text).unwrap(),
+                   owned(&[("a, b.txt", "say \"hi\".aapc"), ("c.txt", "c.aapc")]));
+        // A header is only a header on the first line.
+        assert!(jobs("late.csv", "a,a.aapc\nsrc,dst\n").unwrap().len() == 2);
+    }
+
+    #[test]
+    fn a_malformed_line_names_its_number() {
+        for (name, text, expected) in [
+            ("jobs.txt", "a\ta.aapc\nonly-a-source\n", ":2: expected source<TAB>destination"),
+            ("jobs.txt", "a\ta.aapc\tand more\n", ":1: expected source<TAB>destination"),
+            ("jobs.txt", "\ta.aapc\n", ":1: expected"),
+            ("jobs.csv", "src,dst\n\"open,a.aapc\n", ":2: expected source,destination"),
+        ] {
+            let err = jobs(name, text).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(expected), "{:?}: {}", text, err);
+        }
+    }
+}
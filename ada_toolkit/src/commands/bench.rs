@@ -0,0 +1,303 @@
+//! `bench`, against other codecs, and `corpus-bench`.
+
+use std::fs::{self, read};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+#[cfg(feature = "bench")]
+use std::io::Read;
+
+use ada_toolkit::{compression, decompression};
+use clap::ValueEnum;
+
+use crate::{context, usage_error, Codec, Corpus, Failure, Format, Global};
+use crate::commands::dir::{folder_files, Filter};
+use crate::commands::test::describe_mismatch;
+use crate::commands::test_folder::{round_trip_files, FolderRow, Summary};
+use crate::progress::saved;
+use crate::report::{json_string, json_strings, json_timing, ratio, write_failures};
+
+/// Round-trips the files of `corpus` in `dir` in memory on up to
+/// `--threads` threads and reports each one and the totals as test-folder
+/// does, adding the size-weighted and mean per-file ratios that corpus
+/// results are usually quoted as. A standard corpus is checked file by
+/// file first, so that a partial or altered copy cannot pass for it.
+pub fn run_corpus_bench(dir: &Path, corpus: Corpus, global: &Global) -> Result<(), Failure> {
+    let mut status = global.status(false);
+    if !dir.is_dir() {
+        let msg = format!("'{}' does not exist or is not a directory", dir.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+    }
+    let mut summary = Summary::default();
+    let files: Vec<PathBuf> = match corpus {
+        Corpus::Dir => {
+            let filter = Filter::default();
+            let mut walk = folder_files(dir, false, false, &filter);
+            if let Some(failure) = walk.errors.pop() {
+                return Err(failure);
+            }
+            summary.skipped = walk.skipped.len();
+            walk.files
+        }
+        _ => {
+            let problems: Vec<String> = corpus.files().iter().filter_map(|&(name, size)| {
+                match fs::metadata(dir.join(name)) {
+                    Ok(meta) if meta.is_file() && meta.len() == size => None,
+                    Ok(meta) if meta.is_file() => Some(format!("{} is {} bytes, not {}", name, meta.len(), size)),
+                    _ => Some(format!("{} is missing", name)),
+                }
+            }).collect();
+            if !problems.is_empty() {
+                let msg = format!("{} does not hold the {} corpus as published: {}",
+                                  dir.display(), corpus.name(), problems.join("; "));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+            }
+            corpus.files().iter().map(|(name, _)| PathBuf::from(name)).collect()
+        }
+    };
+    if files.is_empty() {
+        let msg = format!("no files found in '{}'", dir.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+    }
+    let paths: Vec<PathBuf> = files.iter().map(|rel| dir.join(rel)).collect();
+    let threads = global.threads().min(paths.len()).max(1);
+    log::debug!("Benchmarking {} files of the {} corpus on {} threads", paths.len(), corpus.name(), threads);
+
+    let wall_start = Instant::now();
+    let results = round_trip_files(&paths, threads, false);
+    let wall_time = wall_start.elapsed();
+
+    let units = global.units();
+    let folder = dir.to_string_lossy();
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    let mut first_failure = None;
+    let mut ratios = Vec::new();
+    for ((rel, path), result) in files.iter().zip(&paths).zip(results) {
+        let file_name = rel.to_string_lossy();
+        match result.expect("every file is tested without --fail-fast") {
+            Ok((test, _)) => {
+                summary.add(path, &test);
+                if test.original > 0 {
+                    ratios.push(test.ratio());
+                }
+                let row = FolderRow { folder: &folder, file: Some(&file_name), files: 1, test };
+                match global.format {
+                    Format::Text => writeln!(status, "{}: {} to {}. Ratio: {:.2} ({}). Compress {}, decompress {}",
+                        file_name, units.size(test.original), units.size(test.compressed), test.ratio(),
+                        saved(test.ratio()), units.speed(test.original, test.compress_time),
+                        units.speed(test.original, test.decompress_time))?,
+                    Format::Json => rows.push(row.json()),
+                }
+            }
+            Err(error) => {
+                let failure = Failure::from(error);
+                first_failure.get_or_insert(failure.code);
+                summary.files += 1;
+                summary.failed += 1;
+                errors.push(format!("{}: {}", path.display(), failure.error));
+                match global.format {
+                    Format::Text => writeln!(status, "FAILED {}: {}", file_name, failure.error)?,
+                    Format::Json => rows.push(format!(
+                        "{{\"input\":{},\"status\":\"failed\",\"folder\":{},\"exit_code\":{},\"error\":{}}}",
+                        json_string(&path.to_string_lossy()), json_string(&folder), failure.code,
+                        json_string(&failure.error.to_string()))),
+                }
+            }
+        }
+    }
+
+    // The ratio of the totals weighs each file by its size; published
+    // tables often give the plain mean of the per-file ratios as well.
+    let weighted = summary.total.ratio();
+    let mean = match ratios.len() {
+        0 => 0.0,
+        n => ratios.iter().sum::<f64>() / n as f64,
+    };
+    match global.format {
+        Format::Text => {
+            writeln!(status, "{}", summary.text(units, wall_time, threads))?;
+            writeln!(status, "{} corpus: weighted average ratio {:.4} ({:.3} bits per byte), mean per-file ratio {:.4}",
+                     corpus.name(), weighted, weighted * 8.0, mean)?;
+            write_failures(&mut status, &errors)?;
+        }
+        Format::Json => writeln!(status,
+            "{{\"operation\":\"corpus-bench\",\"corpus\":\"{}\",\"path\":{},\"weighted_ratio\":{},\
+             \"mean_ratio\":{},\"bits_per_byte\":{},\"files\":[{}],\"failures\":[{}],\"summary\":{}}}",
+            corpus.to_possible_value().expect("no corpus is hidden").get_name(), json_string(&folder), weighted, mean,
+            weighted * 8.0, rows.join(","),
+            json_strings(&errors), summary.json(wall_time, threads))?,
+    }
+
+    match first_failure {
+        Some(code) => {
+            let msg = format!("{} of {} files failed", summary.failed, summary.files);
+            Err(Failure { code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
+
+impl Codec {
+    const ALL: [Codec; 4] = [Codec::Aapc, Codec::Gzip, Codec::Zstd, Codec::Lz4];
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Aapc => "aapc",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    fn available(self) -> bool {
+        self == Codec::Aapc || cfg!(feature = "bench")
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Aapc => Ok(compression::compress(data)),
+            #[cfg(feature = "bench")]
+            Codec::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "bench")]
+            Codec::Zstd => zstd::bulk::compress(data, 3),
+            #[cfg(feature = "bench")]
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[cfg(not(feature = "bench"))]
+            _ => unreachable!("only AAPC is built without the bench feature"),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Aapc => Ok(decompression::decompress(data)?),
+            #[cfg(feature = "bench")]
+            Codec::Gzip => {
+                let mut out = Vec::new();
+                flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            #[cfg(feature = "bench")]
+            Codec::Zstd => zstd::stream::decode_all(data),
+            #[cfg(feature = "bench")]
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            #[cfg(not(feature = "bench"))]
+            _ => unreachable!("only AAPC is built without the bench feature"),
+        }
+    }
+}
+
+/// One codec's results: its output size and the median times of the timed
+/// runs.
+struct BenchResult {
+    codec: Codec,
+    compressed: u64,
+    compress_time: Duration,
+    decompress_time: Duration,
+}
+
+/// Runs every codec in `codecs` (all built-in ones if empty) over `file`:
+/// one warm-up round trip, then `iterations` timed ones, each checked
+/// against the input before anything is reported.
+pub fn run_bench(file: &str, codecs: &[Codec], iterations: u32, global: &Global) -> io::Result<()> {
+    let codecs: Vec<Codec> = match codecs {
+        [] => Codec::ALL.into_iter().filter(|codec| codec.available()).collect(),
+        chosen => chosen.to_vec(),
+    };
+    if let Some(missing) = codecs.iter().find(|codec| !codec.available()) {
+        usage_error(&format!("{} needs a build with the bench feature", missing.name()))
+    }
+    let data = read(file).map_err(|e| context(e, "reading input", file))?;
+
+    let mut results = Vec::new();
+    for &codec in &codecs {
+        log::debug!("Running {}", codec.name());
+        let mut compress_times = Vec::new();
+        let mut decompress_times = Vec::new();
+        let mut compressed = 0;
+        for run in 0..=iterations {
+            let start = Instant::now();
+            let packed = codec.compress(&data)?;
+            let compress_time = start.elapsed();
+            let start = Instant::now();
+            let unpacked = codec.decompress(&packed)?;
+            let decompress_time = start.elapsed();
+            if unpacked != data {
+                let msg = format!("{} round trip mismatch: {}", codec.name(), describe_mismatch(&data, &unpacked));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            // The first run only warms caches and the allocator.
+            if run > 0 {
+                compress_times.push(compress_time);
+                decompress_times.push(decompress_time);
+            }
+            compressed = packed.len() as u64;
+        }
+        let median = |times: &mut Vec<Duration>| {
+            times.sort();
+            times[times.len() / 2]
+        };
+        results.push(BenchResult {
+            codec,
+            compressed,
+            compress_time: median(&mut compress_times),
+            decompress_time: median(&mut decompress_times),
+        });
+    }
+
+    let original = data.len() as u64;
+    let mut status = global.status(false);
+    match global.format {
+        Format::Text => {
+            let units = global.units();
+            writeln!(status, "{} ({}), median of {} runs after a warm-up:", file, units.size(original), iterations)?;
+            writeln!(status, "{:<6} {:>14} {:>7} {:>16} {:>16}", "Codec", "Compressed", "Ratio", "Compress", "Decompress")?;
+            for result in &results {
+                writeln!(status, "{:<6} {:>14} {:>7.3} {:>16} {:>16}",
+                         result.codec.name(), units.size(result.compressed), ratio(result.compressed, original),
+                         units.speed(original, result.compress_time), units.speed(original, result.decompress_time))?;
+            }
+        }
+        Format::Json => {
+            let rows: Vec<String> = results.iter().map(|result| format!(
+                "{{\"codec\":\"{}\",\"compressed_bytes\":{},\"ratio\":{},{},{}}}",
+                result.codec.name(), result.compressed, ratio(result.compressed, original),
+                json_timing("compress_", original, result.compress_time),
+                json_timing("decompress_", original, result.decompress_time))).collect();
+            writeln!(status, "{{\"operation\":\"bench\",\"input\":{},\"input_bytes\":{},\"iterations\":{},\
+                              \"codecs\":[{}]}}",
+                     json_string(file), original, iterations, rows.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_built_in_codec_round_trips() {
+        let text = b"the quick brown fox jumps over the lazy dog ".repeat(500);
+        for codec in Codec::ALL.into_iter().filter(|codec| codec.available()) {
+            for data in [&[][..], b"x", &text, &[0; 100_000]] {
+                let packed = codec.compress(data).unwrap();
+                assert_eq!(codec.decompress(&packed).unwrap(), data, "{}", codec.name());
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_input_is_an_error() {
+        for codec in Codec::ALL.into_iter().filter(|codec| codec.available()) {
+            let mut packed = codec.compress(&[7; 5000]).unwrap();
+            packed.truncate(packed.len() / 2);
+            assert!(codec.decompress(&packed).is_err(), "{} took half a stream", codec.name());
+        }
+    }
+}
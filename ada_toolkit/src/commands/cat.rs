@@ -0,0 +1,49 @@
+//! `cat`: decompressing frames to stdout.
+
+use std::io;
+use std::time::Instant;
+
+use ada_toolkit::{copy_decode_with_options, DecompressOptions, Progress};
+
+use crate::{cancel_on_interrupt, Failure, Global, EXIT_BROKEN_PIPE, EXIT_CANCELLED};
+use crate::output::{batched, open_input};
+
+/// Streams each file's decompressed content to stdout, like zcat. A file
+/// that fails is reported and skipped; Ctrl+C or a closed stdout stops at
+/// once. Statistics are logged at info level.
+pub fn cat_files(files: &[String], global: &Global) -> Result<(), Failure> {
+    global.report_threads(true);
+    let opts = DecompressOptions {
+        max_memory: global.max_memory,
+        threads: global.threads(),
+        cancel: Some(cancel_on_interrupt()),
+    };
+    let mut stdout = batched(io::stdout().lock());
+    let mut failures = Vec::new();
+    for file in files {
+        let start = Instant::now();
+        let mut totals = Progress::default();
+        let result = open_input(file).map_err(Failure::from).and_then(|input| {
+            copy_decode_with_options(input, &mut stdout, &opts, Some(&mut |p| totals = p))
+                .map_err(|e| Failure::from(e).context("decompressing", file))
+        });
+        match result {
+            Ok(_) => log::info!("Decompressed {} ({} bytes) to {} bytes in {:?}.",
+                                file, totals.input_bytes, totals.output_bytes, start.elapsed()),
+            Err(failure) if matches!(failure.code, EXIT_BROKEN_PIPE | EXIT_CANCELLED) => return Err(failure),
+            Err(failure) if files.len() == 1 => return Err(failure),
+            Err(failure) => {
+                eprintln!("Error: {}", failure.error);
+                failures.push(failure);
+            }
+        }
+    }
+    let failed = failures.len();
+    match failures.into_iter().next() {
+        Some(first) => {
+            let msg = format!("{} of {} files could not be decompressed", failed, files.len());
+            Err(Failure { code: first.code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,195 @@
+//! `compare`: whether two files, compressed or not, hold the same content.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+
+use ada_toolkit::FrameInfo;
+use ada_toolkit::stream::AapcReader;
+
+use crate::{context, Failure, Format, Global, EXIT_DIFFERENT, EXIT_IO, EXIT_USAGE};
+use crate::commands::test::first_difference;
+use crate::report::json_string;
+
+/// How two compressed files' contents compare.
+struct Comparison {
+    /// Where the contents first differ, possibly where the shorter ends;
+    /// `None` if they are equal or only checksums were compared.
+    first_difference: Option<u64>,
+    equal: bool,
+    /// Decoded sizes, or the sizes stored in the trailers with `--quick`.
+    a_bytes: u64,
+    b_bytes: u64,
+    /// "checksum" when decided from the trailers alone, else "decoded".
+    method: &'static str,
+}
+
+/// Compares what `a` and `b` decode to, 64 KiB of each at a time.
+fn compare_contents(a: &str, b: &str, quick: bool) -> Result<Comparison, Failure> {
+    let open = |path: &str| File::open(path).map_err(|e| Failure::from(context(e, "reading input", path)));
+    if quick {
+        let info = |path: &str| FrameInfo::from_reader(open(path)?).map_err(|e| Failure::from(e).context("reading", path));
+        let (info_a, info_b) = (info(a)?, info(b)?);
+        if let (Some(crc_a), Some(crc_b)) = (info_a.content_checksum, info_b.content_checksum) {
+            let equal = (info_a.content_size, crc_a) == (info_b.content_size, crc_b);
+            return Ok(Comparison {
+                first_difference: None,
+                equal,
+                a_bytes: info_a.content_size,
+                b_bytes: info_b.content_size,
+                method: "checksum",
+            });
+        }
+        log::info!("Decoding both files: {} has no content checksum",
+                   if info_a.content_checksum.is_none() { a } else { b });
+    }
+    let mut reader_a = AapcReader::new(BufReader::new(open(a)?));
+    let mut reader_b = AapcReader::new(BufReader::new(open(b)?));
+    let (mut buf_a, mut buf_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    let mut offset = 0u64;
+    loop {
+        let n_a = fill(&mut reader_a, &mut buf_a).map_err(|e| Failure::from(e).context("decompressing", a))?;
+        let n_b = fill(&mut reader_b, &mut buf_b).map_err(|e| Failure::from(e).context("decompressing", b))?;
+        if buf_a[..n_a] != buf_b[..n_b] {
+            let first_difference = offset + first_difference(&buf_a[..n_a], &buf_b[..n_b]) as u64;
+            // Decode the rest of both anyway, so that a corrupt file is
+            // reported as such rather than as a difference.
+            let rest = |reader: &mut dyn Read, path: &str| {
+                io::copy(reader, &mut io::sink()).map_err(|e| Failure::from(e).context("decompressing", path))
+            };
+            let a_bytes = offset + n_a as u64 + rest(&mut reader_a, a)?;
+            let b_bytes = offset + n_b as u64 + rest(&mut reader_b, b)?;
+            let first_difference = Some(first_difference);
+            return Ok(Comparison { first_difference, equal: false, a_bytes, b_bytes, method: "decoded" });
+        }
+        offset += n_a as u64;
+        if n_a == 0 {
+            return Ok(Comparison { first_difference: None, equal: true, a_bytes: offset, b_bytes: offset,
+                                   method: "decoded" });
+        }
+    }
+}
+
+/// Reads from `reader` until `buf` is full or the input ends, returning
+/// how much was read.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+pub fn compare(a: &str, b: &str, quick: bool, global: &Global) -> Result<(), Failure> {
+    let comparison = compare_contents(a, b, quick).map_err(|failure| match failure.code {
+        EXIT_IO => Failure { code: EXIT_USAGE, ..failure },
+        _ => failure,
+    })?;
+    let mut status = global.status(false);
+    let difference = match comparison.first_difference {
+        Some(offset) => format!("{} and {} differ at byte {} ({} and {} bytes)",
+                                a, b, offset, comparison.a_bytes, comparison.b_bytes),
+        None => format!("{} and {} differ ({} and {} bytes, by checksum)",
+                        a, b, comparison.a_bytes, comparison.b_bytes),
+    };
+    match global.format {
+        Format::Text if comparison.equal => writeln!(status, "{} and {} have the same content ({} bytes{})",
+            a, b, comparison.a_bytes, if comparison.method == "checksum" { ", by checksum" } else { "" })?,
+        // The difference is the error message.
+        Format::Text => {}
+        Format::Json => writeln!(status,
+            "{{\"operation\":\"compare\",\"a\":{},\"b\":{},\"equal\":{},\"first_difference\":{},\
+             \"a_bytes\":{},\"b_bytes\":{},\"method\":\"{}\"}}",
+            json_string(a), json_string(b), comparison.equal,
+            comparison.first_difference.map_or("null".to_string(), |offset| offset.to_string()),
+            comparison.a_bytes, comparison.b_bytes, comparison.method)?,
+    }
+    match comparison.equal {
+        true => Ok(()),
+        false => Err(Failure { code: EXIT_DIFFERENT, error: io::Error::other(difference) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ada_toolkit::{compress_with_options, CompressOptions};
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    /// A fresh directory holding `data` compressed as each of `frames`.
+    fn frames(frames: &[(&str, &[u8], CompressOptions)]) -> TempDir {
+        let dir = TempDir::new();
+        for (file, data, opts) in frames {
+            dir.write(file, compress_with_options(data, opts).unwrap());
+        }
+        dir
+    }
+
+    fn contents(dir: &std::path::Path, a: &str, b: &str, quick: bool) -> Result<Comparison, Failure> {
+        compare_contents(&dir.join(a).to_string_lossy(), &dir.join(b).to_string_lossy(), quick)
+    }
+
+    fn compared(dir: &std::path::Path, a: &str, b: &str, quick: bool) -> Comparison {
+        contents(dir, a, b, quick).unwrap_or_else(|failure| panic!("{}", failure.error))
+    }
+
+    fn data() -> Vec<u8> {
+        (0..300_000u32).map(|i| if i % 5000 < 3000 { 0 } else { (i * 7) as u8 }).collect()
+    }
+
+    #[test]
+    fn frames_of_the_same_content_are_equal_whatever_their_blocks() {
+        let small = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        let dir = frames(&[("a", &data(), CompressOptions::default()), ("b", &data(), small)]);
+        assert_ne!(fs::read(dir.join("a")).unwrap(), fs::read(dir.join("b")).unwrap());
+        for quick in [false, true] {
+            let comparison = compared(dir.path(), "a", "b", quick);
+            assert!(comparison.equal);
+            assert_eq!((comparison.a_bytes, comparison.b_bytes), (300_000, 300_000));
+            assert_eq!(comparison.method, if quick { "checksum" } else { "decoded" });
+        }
+    }
+
+    #[test]
+    fn the_first_difference_is_found_across_reads() {
+        let mut changed = data();
+        changed[200_001] ^= 0x40;
+        let dir = frames(&[("a", &data(), CompressOptions::default()),
+                               ("b", &changed, CompressOptions::default()),
+                               ("short", &data()[..150_000], CompressOptions::default())]);
+        let comparison = compared(dir.path(), "a", "b", false);
+        assert!(!comparison.equal);
+        assert_eq!(comparison.first_difference, Some(200_001));
+        let comparison = compared(dir.path(), "short", "a", false);
+        assert_eq!(comparison.first_difference, Some(150_000));
+        assert_eq!((comparison.a_bytes, comparison.b_bytes), (150_000, 300_000));
+        let quick = compared(dir.path(), "a", "b", true);
+        assert_eq!((quick.equal, quick.first_difference, quick.method), (false, None, "checksum"));
+    }
+
+    #[test]
+    fn quick_decodes_when_a_frame_has_no_content_checksum() {
+        let bare = CompressOptions { content_checksum: false, ..CompressOptions::default() };
+        let dir = frames(&[("a", &data(), CompressOptions::default()), ("b", &data(), bare)]);
+        let comparison = compared(dir.path(), "a", "b", true);
+        assert_eq!((comparison.equal, comparison.method), (true, "decoded"));
+    }
+
+    #[test]
+    fn a_corrupt_frame_is_an_error_not_a_difference() {
+        let dir = frames(&[("a", &data(), CompressOptions::default()),
+                               ("b", &data(), CompressOptions::default())]);
+        let mut frame = fs::read(dir.join("b")).unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 0xff;
+        fs::write(dir.join("b"), frame).unwrap();
+        let failure = contents(dir.path(), "a", "b", false).err().unwrap();
+        assert!(failure.code > 1, "{}", failure.error);
+        assert!(failure.error.to_string().contains("decompressing"), "{}", failure.error);
+    }
+}
@@ -0,0 +1,641 @@
+//! `compress`, and compressing one file for every command that does:
+//! spotting inputs that are already compressed, mapping inputs and logging
+//! blocks.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use ada_toolkit::{
+    copy_encode, copy_encode_pipelined, copy_encode_slice, frame, read_members, CancelToken, CompressOptions,
+    CompressionStats, DecompressError, FrameInfo, PhaseTimes, Progress,
+};
+use memmap2::Mmap;
+
+use crate::{cancel_on_interrupt, usage_error, Failure, Format, Global, Incompressible, Paths, Tuning, WriteCheck};
+use crate::checkpoint::{compress_checkpointed, Checkpointing};
+use crate::commands::decompress::decompress_file;
+use crate::output::{create_output, ensure_distinct, open_input, remove_input, verify_frame, Output, STDIO};
+use crate::progress::{input_size, Meters};
+use crate::report::{dry_run_note, file_json, file_line, phases_line, ratio, BatchReport};
+use crate::workers::{file_workers, run_in_order, SharedOutputs};
+
+/// Totals for one file of a compress or decompress run.
+pub struct FileReport {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub duration: Duration,
+    /// CRC-32 of the uncompressed content; `None` for a skipped input.
+    pub checksum: Option<u32>,
+    /// Set when `--skip-compressed` found the input already compressed.
+    pub sniffed: Option<Sniffed>,
+    /// Read back and checked by `--verify-after-write`.
+    pub verified: bool,
+    /// Where the coding time went, as the stream layer measured it.
+    pub phases: PhaseTimes,
+}
+
+/// An input found already compressed, and what was done about it.
+#[derive(Clone, Copy)]
+pub struct Sniffed {
+    /// The format recognised, or why the data looked random.
+    pub kind: &'static str,
+    /// Stored without compression, rather than left out with no output.
+    pub stored: bool,
+}
+
+/// Bytes read from the start of an input to decide whether it is already
+/// compressed.
+const SNIFF_BYTES: u64 = 64 * 1024;
+
+/// Formats recognised by their magic number, all of them compressed.
+const COMPRESSED_MAGIC: &[(&[u8], &str)] = &[
+    (&[0x1f, 0x8b], "gzip"),
+    (b"PK\x03\x04", "zip"),
+    (b"PK\x05\x06", "zip"),
+    (b"BZh", "bzip2"),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], "xz"),
+    (&[0x28, 0xb5, 0x2f, 0xfd], "zstd"),
+    (&[0x04, 0x22, 0x4d, 0x18], "lz4"),
+    (&[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c], "7z"),
+    (b"Rar!\x1a\x07", "RAR"),
+    (&[0xff, 0xd8, 0xff], "JPEG"),
+    (&[0x89, b'P', b'N', b'G'], "PNG"),
+    (b"GIF8", "GIF"),
+    (b"OggS", "Ogg"),
+    (b"fLaC", "FLAC"),
+    (b"ID3", "MP3"),
+];
+
+/// Above this many bits per byte of Shannon entropy a sample is taken to be
+/// compressed or encrypted.
+const RANDOM_ENTROPY: f64 = 7.5;
+
+/// The kind of compressed data `path` starts with, from its magic number,
+/// or from the entropy of the first `SNIFF_BYTES` when there is enough of
+/// them to judge; `None` when it may still compress.
+pub fn already_compressed(path: &str) -> io::Result<Option<&'static str>> {
+    let head = read_head(path)?;
+    if let Some(kind) = aapc_kind(path, &head)? {
+        return Ok(Some(kind));
+    }
+    if let Some((_, kind)) = COMPRESSED_MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Ok(Some(kind));
+    }
+    if head.get(4..8) == Some(b"ftyp") {
+        return Ok(Some("MP4"));
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        return Ok(Some("WebP"));
+    }
+    if head.len() < 4096 {
+        return Ok(None);
+    }
+    let mut counts = [0usize; 256];
+    for &byte in &head {
+        counts[byte as usize] += 1;
+    }
+    let entropy: f64 = counts.iter().filter(|&&n| n > 0).map(|&n| {
+        let p = n as f64 / head.len() as f64;
+        -p * p.log2()
+    }).sum();
+    Ok((entropy > RANDOM_ENTROPY).then_some("high-entropy data"))
+}
+
+/// The first `SNIFF_BYTES` of `path`, or all of it if shorter.
+pub fn read_head(path: &str) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    File::open(path)?.take(SNIFF_BYTES).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Whether `head`, the start of `path`, begins an AAPC frame or archive.
+/// The four bytes of magic alone are not taken as proof: a frame also needs
+/// a header that parses and a known first block type after it, and an
+/// archive a member table whose checksum matches. A small frame's one byte
+/// of marker is less still, so it must parse and be the whole of `path`.
+fn aapc_kind(path: &str, head: &[u8]) -> io::Result<Option<&'static str>> {
+    if frame::is_small_frame(head) {
+        let whole = FrameInfo::parse(head).is_ok_and(|info| info.compressed_size == head.len() as u64);
+        return Ok(whole.then_some("AAPC"));
+    }
+    match frame::parse_header(head) {
+        Ok(header) if matches!(head.get(header.len),
+                               Some(&(frame::BLOCK_END | frame::BLOCK_RLE | frame::BLOCK_STORED))) => {
+            return Ok(Some("AAPC"));
+        }
+        // A filename or comment longer than the sample; the fixed fields
+        // before it were sound.
+        Err(DecompressError::TruncatedField { .. }) if head.len() as u64 == SNIFF_BYTES => return Ok(Some("AAPC")),
+        _ => {}
+    }
+    if head.starts_with(&ada_toolkit::archive::ARCHIVE_MAGIC) && read_members(BufReader::new(File::open(path)?)).is_ok() {
+        return Ok(Some("AAPC archive"));
+    }
+    Ok(None)
+}
+
+/// Compresses (as `tuning` says) or decompresses every input, carrying on
+/// past files that fail (but not past Ctrl+C), then summarises. A lone
+/// input in text mode fails with its own error; otherwise the exit status
+/// is that of the first failure.
+pub fn run_batch(
+    paths: &Paths,
+    compressing: bool,
+    tuning: &Tuning,
+    incompressible: Incompressible,
+    writing: Writing,
+    global: &Global,
+) -> Result<(), Failure> {
+    paths.check_inputs();
+    let format = global.format;
+    let units = global.units();
+    let opts = if compressing { Some(tuning.options(global.max_memory)?) } else { None };
+    let mut report = paths.report.as_deref().map(|path| {
+        let options = format!(
+            "{{\"block_size\":{},\"force\":{},\"rm\":{},\"skip_compressed\":{},\"store_incompressible\":{},\
+             \"sparse\":{},\"checkpoint\":{},\"dry_run\":{},\"verify_after_write\":{},\"max_memory\":{}}}",
+            opts.as_ref().map_or("null".to_string(), |opts| opts.block_size.to_string()),
+            paths.force, paths.rm, incompressible.skip_compressed, incompressible.store_incompressible,
+            writing.sparse, writing.checkpointing.is_some(), writing.dry_run, writing.check.verify_after_write,
+            global.max_memory.map_or("null".to_string(), |limit| limit.to_string()),
+        );
+        BatchReport::new(path, compressing, paths.inputs.len(), options)
+    });
+    if writing.checkpointing.is_some() && (paths.inputs.len() != 1 || paths.inputs[0] == STDIO) {
+        usage_error("--checkpoint and --resume take a single input file")
+    }
+    // A discarded output has no name, so any input will do.
+    let discarding = writing.dry_run && !compressing;
+    let outputs = match discarding {
+        true => vec![String::new(); paths.inputs.len()],
+        false => paths.outputs(compressing),
+    };
+    paths.prepare_output_dir(writing.dry_run)?;
+    let meters = Meters::new(global.progress_mode()).with_overall(paths.inputs.len());
+    let to_stdout = outputs.iter().any(|output| output == STDIO);
+    let mut status = meters.above(global.status(to_stdout));
+    global.report_threads(true);
+    // Frames written to stdout one after another must keep their order.
+    let (workers, threads) = match opts.as_ref() {
+        Some(opts) if !to_stdout => file_workers(paths.inputs.len(), global.threads(), opts.block_size, global.max_memory),
+        _ => (1, global.threads()),
+    };
+    let cancel = cancel_on_interrupt();
+    let run = FileRun {
+        force: paths.force,
+        rm: paths.rm,
+        tuning,
+        incompressible,
+        writing,
+        max_memory: global.max_memory,
+        threads,
+        workers,
+        wait: global.wait,
+        lone_input: paths.inputs.len() == 1,
+        cancel: &cancel,
+        meters: &meters,
+    };
+    let single = paths.inputs.len() == 1 && format == Format::Text;
+    let mut results = Vec::new();
+    let shared = SharedOutputs::new(outputs.iter()
+        .map(|output| Some(output.as_str()).filter(|output| !discarding && *output != STDIO))
+        .collect());
+    let jobs: Vec<(&String, &String)> = paths.inputs.iter().zip(&outputs).collect();
+    run_in_order(&jobs, workers, &cancel, |index, &(input, output)| {
+        shared.run(index, input, || match compressing {
+            true => compress_file(input, output, &run),
+            false => decompress_file(input, output, &run),
+        })
+    }, |index, result| {
+        let (input, output) = jobs[index];
+        // Recorded first, so that a report cut short by a failing status
+        // line still lists the file it was about.
+        if let Some(report) = &mut report {
+            report.add(input, output, &result);
+        }
+        match &result {
+            Ok(FileReport { sniffed: Some(Sniffed { kind, stored: false }), input_bytes, .. })
+                if format == Format::Text =>
+            {
+                writeln!(status, "Skipped {} ({}): already compressed ({})", input, units.size(*input_bytes), kind)?;
+            }
+            Ok(report) if format == Format::Text => {
+                writeln!(status, "{}", file_line(input, output, report, compressing, writing.dry_run, units))?
+            }
+            Err(failure) if !single && format == Format::Text => {
+                writeln!(meters.above(io::stderr()), "Error: {}", failure.error)?
+            }
+            _ => {}
+        }
+        results.push(result);
+        Ok(false)
+    })?;
+    meters.finish();
+    if let Some(report) = report {
+        report.finish(cancel.is_cancelled())?;
+    }
+    if single {
+        return results.pop().expect("one input was processed").map(|_| ());
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    let sniffed = |stored: bool| {
+        results.iter().filter(|result| {
+            result.as_ref().is_ok_and(|report| report.sniffed.is_some_and(|sniffed| sniffed.stored == stored))
+        }).count()
+    };
+    let (skipped, stored) = (sniffed(false), sniffed(true));
+    match format {
+        Format::Text => writeln!(status, "{}{} of {} files {}; {} failed{}",
+                                 dry_run_note(writing.dry_run), results.len() - failed - skipped, paths.inputs.len(),
+                                 if compressing { "compressed" } else { "decompressed" }, failed,
+                                 incompressible.summary(skipped, stored))?,
+        Format::Json => {
+            let files: Vec<String> = paths.inputs.iter().zip(&outputs).zip(&results)
+                .map(|((input, output), result)| file_json(input, output, result, compressing))
+                .collect();
+            writeln!(status, "{{\"operation\":\"{}\",\"dry_run\":{},\"succeeded\":{},\"failed\":{},\
+                              \"skipped\":{},\"skipped_compressed\":{},\"stored\":{},\"files\":[{}]}}",
+                     if compressing { "compress" } else { "decompress" }, writing.dry_run,
+                     results.len() - failed - skipped, failed,
+                     paths.inputs.len() - results.len(), skipped, stored, files.join(","))?;
+        }
+    }
+    match results.into_iter().find_map(Result::err) {
+        Some(first) => {
+            let msg = format!("{} of {} files failed", failed, paths.inputs.len());
+            Err(Failure { code: first.code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
+
+/// How a compress or decompress run reads its inputs and writes its outputs.
+#[derive(Clone, Copy, Default)]
+pub struct Writing<'a> {
+    /// Leave holes for runs of zeros; decompress only.
+    pub sparse: bool,
+    /// Save progress for `--resume`; compress only.
+    pub checkpointing: Option<&'a Checkpointing>,
+    /// Encode or decode without writing anything: `--dry-run` when
+    /// compressing, `--discard` when decompressing.
+    pub dry_run: bool,
+    /// Map input files into memory rather than reading them; compress only.
+    pub mmap: bool,
+    /// Read each output back once written; compress only.
+    pub check: WriteCheck,
+}
+
+/// Settings shared by every file of a compress or decompress run.
+pub struct FileRun<'a> {
+    pub force: bool,
+    pub rm: bool,
+    /// Used when compressing.
+    pub tuning: &'a Tuning,
+    pub incompressible: Incompressible,
+    pub writing: Writing<'a>,
+    pub max_memory: Option<usize>,
+    /// Threads to encode each file's blocks on.
+    pub threads: usize,
+    /// Files compressed at once; with more than one, each is read and
+    /// encoded on its worker's thread alone.
+    pub workers: usize,
+    /// How long to wait for another process writing the same output.
+    pub wait: Option<Duration>,
+    /// A run over one named input, which is compressed again with a warning
+    /// if it is already AAPC rather than skipped.
+    pub lone_input: bool,
+    pub cancel: &'a CancelToken,
+    pub meters: &'a Meters,
+}
+
+pub fn compress_file(input: &str, output: &str, run: &FileRun) -> Result<FileReport, Failure> {
+    log::debug!("Reading input file {}", input);
+    let reader = open_input(input)?;
+    // Only a regular file can be looked at and then read from the start;
+    // what a pipe or device gives up to a look is gone.
+    let regular = input != STDIO && fs::metadata(input).is_ok_and(|meta| meta.is_file());
+    let aapc = match input {
+        _ if !regular || run.incompressible.recompress => None,
+        path => read_head(path).and_then(|head| aapc_kind(path, &head))
+            .map_err(|e| Failure::from(e).context("reading", input))?,
+    };
+    match aapc {
+        Some(kind) if !run.lone_input => {
+            log::warn!("Skipping {}: already compressed ({}); use --recompress to compress it again", input, kind);
+            let input_bytes = input_size(input).unwrap_or(0);
+            let sniffed = Some(Sniffed { kind, stored: false });
+            return Ok(FileReport {
+                input_bytes, output_bytes: 0, duration: Duration::ZERO, checksum: None, sniffed, verified: false,
+                phases: PhaseTimes::default(),
+            });
+        }
+        Some(kind) => log::warn!("{} is already compressed ({}); compressing it again", input, kind),
+        None => {}
+    }
+    let sniffed = match input {
+        _ if !regular || !run.incompressible.skip_compressed => None,
+        path => already_compressed(path).map_err(|e| Failure::from(e).context("reading", input))?,
+    };
+    let sniffed = sniffed.map(|kind| Sniffed { kind, stored: run.incompressible.store_incompressible });
+    if let Some(Sniffed { kind, stored: false }) = sniffed {
+        log::info!("Skipping {}: already compressed ({})", input, kind);
+        let input_bytes = input_size(input).unwrap_or(0);
+        return Ok(FileReport {
+            input_bytes, output_bytes: 0, duration: Duration::ZERO, checksum: None, sniffed, verified: false,
+            phases: PhaseTimes::default(),
+        });
+    }
+    ensure_distinct(input, output)?;
+    let opts = CompressOptions {
+        store_only: sniffed.is_some(),
+        cancel: Some(run.cancel.clone()),
+        ..run.tuning.threaded_options(run.max_memory, run.threads)?
+    };
+    let opts = CompressOptions { filename: stored_name(input, &opts), ..opts };
+    match sniffed {
+        Some(Sniffed { kind, .. }) => log::info!("Storing {} in {} uncompressed: already compressed ({})",
+                                                 input, output, kind),
+        None if run.writing.dry_run => log::info!("Compressing {} without writing {}", input, output),
+        None => log::info!("Writing compressed output to {}", output),
+    }
+    let start = Instant::now();
+    let mut meter = run.meters.file(input, input_size(input));
+    let mut block_log = BlockLog::new(&opts, input_size(input));
+    let mut progress = |p: Progress| {
+        meter.update(p.input_bytes);
+        block_log.update(p);
+    };
+    let mapped = match input {
+        STDIO => None,
+        _ if !run.writing.mmap => None,
+        path => MappedInput::open(path),
+    };
+    // A worker's thread is its file's only one, as is the one thread
+    // `--threads 1` allows.
+    let sequential = run.workers > 1 || opts.threads == 1;
+    // The output is only committed once its totals are known, to read it
+    // back against them first.
+    let result = match run.writing.checkpointing {
+        _ if run.writing.dry_run => encode_input(reader, mapped.as_ref(), io::sink(), &opts, sequential, &mut progress)
+            .map(|stats| (stats, None)),
+        Some(checkpointing) => {
+            drop(reader);
+            compress_checkpointed(input, output, &opts, checkpointing, run, &mut progress)
+                .map(|(stats, file)| (stats, Some(Output::File(file))))
+        }
+        None => {
+            let mut writer = create_output(output, run.force, run.wait)?;
+            encode_input(reader, mapped.as_ref(), &mut writer, &opts, sequential, &mut progress)
+                .map(|stats| (stats, Some(writer)))
+        }
+    };
+    drop(meter);
+    block_log.finish();
+    let (stats, writer) = result.map_err(|e| Failure::from(e).context("compressing", input))?;
+    let checksum = stats.content_crc.expect("the command line's frames always have checksums");
+    log::debug!("Phases for {}: {}", input, phases_line(&stats.phases, true));
+    let verify = |file: File| verify_frame(file, stats.input_bytes, checksum);
+    let verified = match writer {
+        Some(writer) => run.writing.check.commit(writer, output, verify)?,
+        None => false,
+    };
+    let duration = start.elapsed();
+    if run.rm {
+        remove_input(input, output, true, stats.input_bytes)?;
+    }
+    Ok(FileReport {
+        input_bytes: stats.input_bytes,
+        output_bytes: stats.output_bytes,
+        duration,
+        checksum: Some(checksum),
+        sniffed,
+        verified,
+        phases: stats.phases,
+    })
+}
+
+/// The name to record in the frame for `input` compressed with `opts`: its
+/// file name, except for stdin and for a file small enough for a small
+/// frame, which has no room for one and which would be many times larger
+/// with it.
+pub fn stored_name(input: &str, opts: &CompressOptions) -> Option<String> {
+    match input {
+        STDIO => None,
+        _ if input_size(input).is_some_and(|size| opts.small_frame_fits(size as usize)) => None,
+        path => Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()),
+    }
+}
+
+/// Compresses one input onto `out`, from its mapping if it has one and
+/// otherwise read through the pipeline, or on this thread alone if
+/// `sequential`. The input's CRC-32 is the encoder's, in the totals.
+fn encode_input(
+    reader: Box<dyn Read + Send>,
+    mapped: Option<&MappedInput>,
+    out: impl Write,
+    opts: &CompressOptions,
+    sequential: bool,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<CompressionStats> {
+    match mapped {
+        Some(mapped) => {
+            let stats = copy_encode_slice(&mapped.map, out, opts, Some(progress))?;
+            mapped.check_unchanged()?;
+            Ok(stats)
+        }
+        None if sequential => Ok(copy_encode(reader, out, opts, Some(progress))?),
+        None => Ok(copy_encode_pipelined(reader, out, opts, Some(progress))?),
+    }
+}
+
+/// An input file mapped into memory for `--mmap`, with what it looked like
+/// when mapped.
+struct MappedInput {
+    map: Mmap,
+    file: File,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl MappedInput {
+    /// Maps the regular file at `path`, or `None`, logged, if it is not one
+    /// or cannot be mapped, so that it is read instead.
+    fn open(path: &str) -> Option<MappedInput> {
+        // Checked before opening, since opening a FIFO a second time waits
+        // for another writer.
+        let regular = fs::metadata(path).and_then(|metadata| match metadata.is_file() {
+            true => Ok(()),
+            false => Err(io::Error::new(io::ErrorKind::Unsupported, "not a regular file")),
+        });
+        let mapped = regular.and_then(|()| File::open(path)).and_then(|file| {
+            let metadata = file.metadata()?;
+            // SAFETY: the mapping is only read, and a change to the file
+            // while it is mapped is caught by `check_unchanged` unless it
+            // shrinks the file under a page that is then touched.
+            let map = unsafe { Mmap::map(&file)? };
+            Ok(MappedInput { map, file, len: metadata.len(), modified: metadata.modified().ok() })
+        });
+        match mapped {
+            Ok(mapped) => {
+                log::debug!("Mapped {} ({} bytes) into memory", path, mapped.len);
+                Some(mapped)
+            }
+            Err(e) => {
+                log::debug!("Reading {} instead of mapping it: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Fails if the file's size or modification time has changed since it
+    /// was mapped, when what was encoded may be a mix of old and new.
+    fn check_unchanged(&self) -> io::Result<()> {
+        let metadata = self.file.metadata()?;
+        if metadata.len() != self.len || metadata.modified().ok() != self.modified {
+            return Err(io::Error::other("input changed while it was being compressed"));
+        }
+        Ok(())
+    }
+}
+
+/// Most lines `--verbose` logs about the blocks of one file; a bigger file
+/// gets a line per group of blocks instead.
+const BLOCK_LOG_LINES: u64 = 1000;
+
+/// Logs the blocks of one compression at debug level as its progress
+/// reports come in: each block's (or group of blocks') sizes, ratio and
+/// codec, then the running totals.
+struct BlockLog {
+    /// Frame header bytes, counted in the output of the first block; none
+    /// for a small frame.
+    header_len: u64,
+    /// Blocks per line.
+    every: u32,
+    logged: Progress,
+    latest: Progress,
+}
+
+impl BlockLog {
+    fn new(opts: &CompressOptions, input_size: Option<u64>) -> BlockLog {
+        let blocks = input_size.map_or(0, |size| size.div_ceil(opts.block_size as u64));
+        BlockLog {
+            header_len: match input_size {
+                Some(size) if opts.small_frame_fits(size as usize) => 0,
+                _ => frame::Header::for_options(opts).len as u64,
+            },
+            every: blocks.div_ceil(BLOCK_LOG_LINES).clamp(1, u32::MAX as u64) as u32,
+            logged: Progress::default(),
+            latest: Progress::default(),
+        }
+    }
+
+    /// Takes a progress report. Reports that add no block, such as the
+    /// final one that only adds the trailer, are left out.
+    fn update(&mut self, progress: Progress) {
+        if progress.blocks <= self.latest.blocks {
+            return;
+        }
+        self.latest = progress;
+        if self.latest.blocks - self.logged.blocks >= self.every {
+            self.log();
+        }
+    }
+
+    /// Logs the blocks of a last, partly filled group.
+    fn finish(&mut self) {
+        if self.latest.blocks > self.logged.blocks {
+            self.log();
+        }
+    }
+
+    fn log(&mut self) {
+        let (from, to) = (self.logged, self.latest);
+        let framing = if from.blocks == 0 { self.header_len } else { 0 };
+        let input = to.input_bytes - from.input_bytes;
+        // An input of unknown size may still have made a small frame.
+        let output = (to.output_bytes - from.output_bytes).saturating_sub(framing);
+        let (blocks, stored) = (to.blocks - from.blocks, to.stored_blocks - from.stored_blocks);
+        let which = match blocks {
+            1 => format!("Block {}", from.blocks),
+            _ => format!("Blocks {}-{}", from.blocks, to.blocks - 1),
+        };
+        let codec = match stored {
+            0 => "rle".to_string(),
+            _ if stored == blocks => "stored".to_string(),
+            _ => format!("{} rle, {} stored", blocks - stored, stored),
+        };
+        log::debug!("{}: {} -> {} bytes (ratio {:.3}, {}); total {} -> {} bytes (ratio {:.3})",
+                    which, input, output, ratio(output, input), codec,
+                    to.input_bytes, to.output_bytes, ratio(to.output_bytes, to.input_bytes));
+        self.logged = to;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use ada_toolkit::compress;
+
+    fn kind(name: &str, content: &[u8]) -> Option<&'static str> {
+        let tmp = TempDir::new();
+        tmp.write(name, content);
+        let path = tmp.name(name);
+        aapc_kind(&path, &read_head(&path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn frames_are_recognised_by_more_than_their_magic() {
+        let frame = compress(&[7u8; 20_000]);
+        assert!(frame.starts_with(&frame::MAGIC));
+        assert_eq!(kind("frame", &frame), Some("AAPC"));
+        let small = compress(b"hi\n");
+        assert!(frame::is_small_frame(&small));
+        assert_eq!(kind("small", &small), Some("AAPC"));
+
+        // Text that merely starts like a frame, and a small frame's marker
+        // with more after it.
+        assert_eq!(kind("text", b"AAPC is how this text starts, and nothing more\n"), None);
+        assert_eq!(kind("magic", &frame::MAGIC), None);
+        let mut longer = small.clone();
+        longer.extend_from_slice(b"more");
+        assert_eq!(kind("longer", &longer), None);
+        assert_eq!(kind("archive", b"AAPA but not an archive"), None);
+    }
+
+    #[test]
+    fn an_aapc_frame_is_already_compressed() {
+        let tmp = TempDir::new();
+        tmp.write("sniffed", compress(&[1u8; 9_000]));
+        let path = tmp.name("sniffed");
+        assert_eq!(already_compressed(&path).unwrap(), Some("AAPC"));
+        std::fs::write(&path, b"AAPC and plain text").unwrap();
+        assert_eq!(already_compressed(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn a_mapped_input_fails_once_the_file_changes() {
+        let tmp = TempDir::new();
+        tmp.write("mapped", b"mapped contents");
+        let path = tmp.name("mapped");
+        let mapped = MappedInput::open(&path).expect("a regular file maps");
+        assert_eq!(&mapped.map[..], b"mapped contents");
+        mapped.check_unchanged().unwrap();
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b" and more").unwrap();
+        let err = mapped.check_unchanged().unwrap_err();
+        assert_eq!(err.to_string(), "input changed while it was being compressed");
+    }
+
+    #[test]
+    fn what_cannot_be_mapped_is_read_instead() {
+        let tmp = TempDir::new();
+        assert!(MappedInput::open(&tmp.name("")).is_none());
+        assert!(MappedInput::open("/nonexistent/input").is_none());
+        tmp.write("empty", b"");
+        let path = tmp.name("empty");
+        assert!(MappedInput::open(&path).is_none_or(|mapped| mapped.map.is_empty()));
+    }
+}
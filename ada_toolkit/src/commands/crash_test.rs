@@ -0,0 +1,211 @@
+//! `crash-test`: decoding mutated frames, which must fail cleanly.
+
+use std::fs::read;
+use std::io::{self, Write};
+
+use ada_toolkit::{compression, decompression, CompressOptions};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{context, CrashArgs, Failure, Format, Global};
+use crate::commands::test::mismatch_report;
+use crate::generate::{generate, Profile};
+use crate::report::json_string;
+
+/// A way `crash-test` damages compressed data.
+#[derive(Clone, Copy)]
+enum Mutation {
+    /// Flip 1 to 8 random bits.
+    BitFlip,
+    /// Cut the data short at a random length.
+    Truncate,
+    /// Insert 1 to 16 random bytes at a random offset.
+    Insert,
+}
+
+impl Mutation {
+    const ALL: [Mutation; 3] = [Mutation::BitFlip, Mutation::Truncate, Mutation::Insert];
+
+    fn name(self) -> &'static str {
+        match self {
+            Mutation::BitFlip => "bit-flip",
+            Mutation::Truncate => "truncate",
+            Mutation::Insert => "insert",
+        }
+    }
+
+    fn apply(self, data: &mut Vec<u8>, rng: &mut StdRng) {
+        match self {
+            Mutation::BitFlip => {
+                for _ in 0..rng.gen_range(1..=8) {
+                    let bit = rng.gen_range(0..data.len() * 8);
+                    data[bit / 8] ^= 1 << (bit % 8);
+                }
+            }
+            Mutation::Truncate => data.truncate(rng.gen_range(0..data.len())),
+            Mutation::Insert => {
+                let at = rng.gen_range(0..=data.len());
+                let bytes: Vec<u8> = (0..rng.gen_range(1..=16)).map(|_| rng.gen()).collect();
+                data.splice(at..at, bytes);
+            }
+        }
+    }
+}
+
+/// What became of the damaged copies of one kind.
+#[derive(Default)]
+struct Outcomes {
+    tried: u32,
+    /// Failed to decode, as they should.
+    rejected: u32,
+    /// Decoded to the original anyway, as when only padding was hit.
+    unchanged: u32,
+    /// Decoded without error to something else.
+    undetected: u32,
+    panicked: u32,
+}
+
+/// Compresses the input, then decodes `args.mutations` damaged copies of
+/// it, each hit by one mutation picked at random. A copy must either fail
+/// to decode or decode to the original; a panic or wrong output without an
+/// error fails the run.
+pub fn run_crash_test(args: &CrashArgs, global: &Global) -> Result<(), Failure> {
+    let mut status = global.status(false);
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (name, data) = match &args.file {
+        Some(file) => (file.clone(), read(file).map_err(|e| context(e, "reading input", file))?),
+        None => (format!("{} bytes of generated data", args.size), generate(Profile::Mixed, args.size, &mut rng)),
+    };
+    let compressed = compression::compress(&data);
+    if global.format == Format::Text {
+        writeln!(status, "Trying {} damaged copies of {} ({} compressed bytes; repeat with --seed {})",
+                 args.mutations, name, compressed.len(), seed)?;
+    }
+
+    // Panics are counted, not printed; the hook goes back once done.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let mut outcomes: Vec<Outcomes> = Mutation::ALL.iter().map(|_| Outcomes::default()).collect();
+    let mut first_bad = None;
+    for index in 0..args.mutations {
+        let kind = rng.gen_range(0..Mutation::ALL.len());
+        let mut damaged = compressed.clone();
+        Mutation::ALL[kind].apply(&mut damaged, &mut rng);
+        let result = std::panic::catch_unwind(|| decompression::decompress(&damaged));
+        let counts = &mut outcomes[kind];
+        counts.tried += 1;
+        let bad = match result {
+            Err(_) => {
+                counts.panicked += 1;
+                Some("decoder panicked")
+            }
+            Ok(Err(_)) => {
+                counts.rejected += 1;
+                None
+            }
+            Ok(Ok(output)) if output == data => {
+                counts.unchanged += 1;
+                None
+            }
+            Ok(Ok(output)) => {
+                if first_bad.is_none() {
+                    let block_size = CompressOptions::default().block_size;
+                    eprint!("{}", mismatch_report(&data, &output, block_size, global.colored(&io::stderr())));
+                }
+                counts.undetected += 1;
+                Some("damage decoded without an error")
+            }
+        };
+        if let Some(what) = bad {
+            log::warn!("Mutation {} ({}): {}", index, Mutation::ALL[kind].name(), what);
+            first_bad.get_or_insert((index, Mutation::ALL[kind].name(), what));
+        }
+    }
+    std::panic::set_hook(hook);
+
+    match global.format {
+        Format::Text => {
+            writeln!(status, "{:<9} {:>8} {:>9} {:>10} {:>11} {:>9}",
+                     "Mutation", "Tried", "Rejected", "Unchanged", "Undetected", "Panicked")?;
+            for (mutation, counts) in Mutation::ALL.iter().zip(&outcomes) {
+                writeln!(status, "{:<9} {:>8} {:>9} {:>10} {:>11} {:>9}", mutation.name(), counts.tried,
+                         counts.rejected, counts.unchanged, counts.undetected, counts.panicked)?;
+            }
+        }
+        Format::Json => {
+            let rows: Vec<String> = Mutation::ALL.iter().zip(&outcomes).map(|(mutation, counts)| format!(
+                "{{\"mutation\":\"{}\",\"tried\":{},\"rejected\":{},\"unchanged\":{},\"undetected\":{},\
+                 \"panicked\":{}}}",
+                mutation.name(), counts.tried, counts.rejected, counts.unchanged, counts.undetected,
+                counts.panicked)).collect();
+            writeln!(status, "{{\"operation\":\"crash-test\",\"input\":{},\"seed\":{},\"mutations\":{},\
+                              \"compressed_bytes\":{},\"status\":\"{}\",\"results\":[{}]}}",
+                     match &args.file {
+                         Some(file) => json_string(file),
+                         None => "null".to_string(),
+                     },
+                     seed, args.mutations, compressed.len(), if first_bad.is_some() { "failed" } else { "ok" },
+                     rows.join(","))?;
+        }
+    }
+
+    match first_bad {
+        Some((index, mutation, what)) => {
+            let bad: u32 = outcomes.iter().map(|counts| counts.undetected + counts.panicked).sum();
+            let msg = format!("{} of {} mutations were not handled safely; the first was mutation {} ({}): {} \
+                               (seed {})", bad, args.mutations, index, mutation, what, seed);
+            Err(io::Error::new(io::ErrorKind::InvalidData, msg).into())
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits_changed(before: &[u8], after: &[u8]) -> u32 {
+        before.iter().zip(after).map(|(a, b)| (a ^ b).count_ones()).sum()
+    }
+
+    #[test]
+    fn each_mutation_does_what_it_says() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..200 {
+            let mut flipped = data.clone();
+            Mutation::BitFlip.apply(&mut flipped, &mut rng);
+            assert_eq!(flipped.len(), data.len());
+            // The same bit may be hit twice and flip back.
+            assert!(bits_changed(&data, &flipped) <= 8);
+
+            let mut cut = data.clone();
+            Mutation::Truncate.apply(&mut cut, &mut rng);
+            assert!(cut.len() < data.len());
+            assert_eq!(cut, data[..cut.len()]);
+
+            let mut grown = data.clone();
+            Mutation::Insert.apply(&mut grown, &mut rng);
+            let added = grown.len() - data.len();
+            assert!((1..=16).contains(&added), "{} bytes inserted", added);
+            let at = grown.iter().zip(&data).position(|(a, b)| a != b).unwrap_or(data.len());
+            assert_eq!(grown[..at], data[..at]);
+            assert_eq!(grown[at + added..], data[at..]);
+        }
+    }
+
+    #[test]
+    fn mutations_reach_every_part_of_the_data() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut hit = [false; 16];
+        for _ in 0..500 {
+            let mut data = vec![0u8; 16];
+            Mutation::BitFlip.apply(&mut data, &mut rng);
+            for (i, &byte) in data.iter().enumerate() {
+                hit[i] |= byte != 0;
+            }
+        }
+        assert!(hit.iter().all(|&hit| hit), "{:?}", hit);
+    }
+}
@@ -0,0 +1,68 @@
+//! Decompressing one file, for `decompress` and the directory commands.
+
+use std::io::{self, Write};
+use std::time::Instant;
+
+use ada_toolkit::{copy_decode_with_phases, DecompressOptions, Progress};
+
+use crate::Failure;
+use crate::commands::compress::{FileReport, FileRun};
+use crate::output::{create_output, ensure_distinct, open_input, remove_input, Checksummed, Output};
+use crate::progress::input_size;
+use crate::report::phases_line;
+
+pub fn decompress_file(input: &str, output: &str, run: &FileRun) -> Result<FileReport, Failure> {
+    log::debug!("Reading compressed input {}", input);
+    let reader = open_input(input)?;
+    let mut writer = match run.writing.dry_run {
+        true => None,
+        false => {
+            ensure_distinct(input, output)?;
+            Some(create_output(output, run.force, run.wait)?)
+        }
+    };
+    match &mut writer {
+        Some(writer) => {
+            if run.writing.sparse {
+                writer.make_sparse();
+            }
+            log::info!("Writing decompressed output to {}", output);
+        }
+        None => log::info!("Decompressing {} and discarding the output", input),
+    }
+    let start = Instant::now();
+    let mut totals = Progress::default();
+    let mut meter = run.meters.file(input, input_size(input));
+    let opts = DecompressOptions { max_memory: run.max_memory, threads: run.threads, cancel: Some(run.cancel.clone()) };
+    let mut sink = io::sink();
+    let mut out = Checksummed::new(match &mut writer {
+        Some(writer) => writer as &mut dyn Write,
+        None => &mut sink,
+    });
+    let result = copy_decode_with_phases(reader, &mut out, &opts, Some(&mut |p| {
+        totals = p;
+        meter.update(p.input_bytes);
+    }));
+    let checksum = out.crc.finish();
+    let crc_time = out.spent;
+    drop(meter);
+    let (_, mut phases) = result.map_err(io::Error::from)
+        .and_then(|done| writer.map_or(Ok(()), Output::commit).map(|()| done))
+        .map_err(|e| Failure::from(e).context("decompressing", input))?;
+    // `out` timed its CRC as part of writing.
+    phases.write = phases.write.saturating_sub(crc_time);
+    phases.checksum += crc_time;
+    log::debug!("Phases for {}: {}", input, phases_line(&phases, false));
+    if run.rm {
+        remove_input(input, output, false, totals.output_bytes)?;
+    }
+    Ok(FileReport {
+        input_bytes: totals.input_bytes,
+        output_bytes: totals.output_bytes,
+        duration: start.elapsed(),
+        checksum: Some(checksum),
+        sniffed: None,
+        verified: false,
+        phases,
+    })
+}
@@ -0,0 +1,549 @@
+//! Walking directory trees, for `compress-dir`, `decompress --recursive`
+//! and the commands that test or pack folders.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+use ada_toolkit::frame;
+use clap::Args;
+
+use crate::{
+    cancel_on_interrupt, context, usage_error, DecompressArgs, DirArgs, Failure, Format, Global, Incompressible, Tuning,
+    SUFFIX,
+};
+use crate::commands::archive::glob_match;
+use crate::commands::compress::{compress_file, read_head, FileReport, FileRun, Sniffed, Writing};
+use crate::commands::decompress::decompress_file;
+use crate::progress::{saved, Meters};
+use crate::report::{dry_run_note, json_string, json_strings, ratio, write_failures};
+use crate::workers::{file_workers, run_in_order};
+
+/// Which paths under a walked directory to keep.
+#[derive(Args, Default)]
+pub struct Filter {
+    /// Only take files matching PATTERN; may be repeated
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+    /// Leave out files and whole directories matching PATTERN, even if they
+    /// match --include; may be repeated
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Leave out files and directories whose name starts with a dot
+    #[arg(long)]
+    skip_hidden: bool,
+}
+
+impl Filter {
+    /// Whether to keep `rel`, a path relative to the walked root. Patterns
+    /// are case-sensitive globs; one without a `/` is matched against the
+    /// file name alone, any other against the whole `/`-separated path. A
+    /// directory is only checked against the excludes, as `dir` and `dir/`,
+    /// so `tmp/**` prunes `tmp` itself.
+    pub fn keeps(&self, rel: &Path, is_dir: bool) -> bool {
+        let name = rel.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        if self.skip_hidden && name.starts_with('.') {
+            return false;
+        }
+        let path = rel.to_string_lossy().replace(MAIN_SEPARATOR, "/");
+        let matches = |pattern: &String| match pattern.contains('/') {
+            true => glob_match(pattern.as_bytes(), path.as_bytes())
+                || (is_dir && glob_match(pattern.as_bytes(), format!("{}/", path).as_bytes())),
+            false => glob_match(pattern.as_bytes(), name.as_bytes()),
+        };
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        is_dir || self.include.is_empty() || self.include.iter().any(matches)
+    }
+}
+
+/// Entries found under a directory being walked, relative to it, in sorted
+/// walk order.
+pub struct Walk<'a> {
+    filter: &'a Filter,
+    /// Whether to enter subdirectories; without it they are left out.
+    descend: bool,
+    pub files: Vec<PathBuf>,
+    /// Directories entered, parents before their children.
+    pub dirs: Vec<PathBuf>,
+    /// Symlinks not followed, for the caller to store or skip.
+    pub links: Vec<PathBuf>,
+    /// Sockets, FIFOs, devices and already visited directories, each
+    /// warned about as it is found.
+    pub skipped: Vec<PathBuf>,
+    /// Files and directories left out by `filter`; a directory's contents
+    /// are not walked, so not counted.
+    pub filtered: Vec<PathBuf>,
+    pub errors: Vec<Failure>,
+}
+
+impl Walk<'_> {
+    pub fn new(filter: &Filter) -> Walk<'_> {
+        Walk {
+            filter,
+            descend: true,
+            files: Vec::new(),
+            dirs: Vec::new(),
+            links: Vec::new(),
+            skipped: Vec::new(),
+            filtered: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Walks `root/rel`, never entering `exclude` (the destination, which may
+    /// sit inside the source) or a directory in `seen`.
+    pub fn visit(&mut self, root: &Path, rel: &Path, follow: bool, exclude: &Path, seen: &mut HashSet<PathBuf>) {
+        let dir = root.join(rel);
+        let mut names = match fs::read_dir(&dir).and_then(|entries| {
+            entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>()
+        }) {
+            Ok(names) => names,
+            Err(e) => {
+                self.errors.push(context(e, "reading directory", &dir.to_string_lossy()).into());
+                return;
+            }
+        };
+        names.sort();
+        for name in names {
+            let rel = rel.join(name);
+            let path = root.join(&rel);
+            let meta = match follow {
+                true => fs::metadata(&path),
+                false => fs::symlink_metadata(&path),
+            };
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(e) => {
+                    self.errors.push(context(e, "reading", &path.to_string_lossy()).into());
+                    continue;
+                }
+            };
+            let is_link = meta.file_type().is_symlink();
+            if meta.is_dir() && !self.descend {
+                continue;
+            }
+            if (meta.is_dir() || meta.is_file() || is_link) && !self.filter.keeps(&rel, meta.is_dir()) {
+                self.filtered.push(rel);
+            } else if meta.is_dir() {
+                let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if canonical == exclude {
+                    continue;
+                }
+                if !seen.insert(canonical) {
+                    log::warn!("Skipping {}: already visited, as through a symlink loop", path.display());
+                    self.skipped.push(rel);
+                    continue;
+                }
+                self.dirs.push(rel.clone());
+                self.visit(root, &rel, follow, exclude, seen);
+            } else if meta.is_file() {
+                self.files.push(rel);
+            } else if is_link {
+                self.links.push(rel);
+            } else {
+                log::warn!("Skipping {}: {}", path.display(), special_kind(meta.file_type()));
+                self.skipped.push(rel);
+            }
+        }
+    }
+}
+
+impl Walk<'_> {
+    /// Moves the symlinks found under `root` to `skipped`, with a notice
+    /// for each, for a walk that neither follows nor stores them.
+    fn skip_links(&mut self, root: &Path) {
+        for link in self.links.drain(..) {
+            log::warn!("Skipping symlink {}; use --follow-symlinks to follow it", root.join(&link).display());
+            self.skipped.push(link);
+        }
+    }
+}
+
+/// Decompresses each `.aapc` file in a directory input (and its
+/// subdirectories with `--recursive`) into the same relative place under
+/// the output directory, named as its frame header says or else with the
+/// suffix removed, then summarises the whole tree. The output directory is
+/// a second input, `-o` or `--output-dir`, or the input directory itself.
+pub fn decompress_dir(args: &DecompressArgs, global: &Global) -> Result<(), Failure> {
+    let paths = &args.paths;
+    let src = Path::new(&paths.inputs[0]);
+    if paths.stdout || paths.report.is_some() {
+        usage_error("--stdout and --report do not apply to a directory input")
+    }
+    let dst = match (&paths.inputs[1..], &paths.output, &paths.output_dir) {
+        ([], None, None) => src.to_path_buf(),
+        ([], Some(dir), None) => PathBuf::from(dir),
+        ([], None, Some(dir)) => dir.clone(),
+        ([dir], None, None) => PathBuf::from(dir),
+        _ => usage_error("a directory input takes one output directory, given as a second path, -o or --output-dir"),
+    };
+    let src_canonical = fs::canonicalize(src).map_err(|e| context(e, "reading directory", &src.to_string_lossy()))?;
+    if !args.discard {
+        fs::create_dir_all(&dst).map_err(|e| context(e, "creating output directory", &dst.to_string_lossy()))?;
+    }
+    let dst_canonical = fs::canonicalize(&dst).unwrap_or_else(|_| dst.clone());
+    // The output directory is only left out of the walk when it is not the
+    // input directory itself.
+    let exclude = if dst_canonical == src_canonical { Path::new("") } else { dst_canonical.as_path() };
+
+    let filter = Filter::default();
+    let mut walk = Walk { descend: args.recursive, ..Walk::new(&filter) };
+    let mut seen = HashSet::from([src_canonical.clone()]);
+    walk.visit(src, Path::new(""), false, exclude, &mut seen);
+    walk.skip_links(src);
+    let mut failures = std::mem::take(&mut walk.errors);
+    for failure in &failures {
+        eprintln!("Error: {}", failure.error);
+    }
+    let (inputs, others): (Vec<PathBuf>, Vec<PathBuf>) = std::mem::take(&mut walk.files)
+        .into_iter()
+        .partition(|rel| rel.to_string_lossy().ends_with(SUFFIX));
+    for rel in &others {
+        log::info!("Skipping {}: not a {} file", src.join(rel).display(), SUFFIX);
+    }
+
+    let cancel = cancel_on_interrupt();
+    let meters = Meters::new(global.progress_mode()).with_overall(inputs.len());
+    let run = FileRun {
+        force: paths.force,
+        rm: paths.rm,
+        tuning: &Tuning::default(),
+        incompressible: Incompressible::default(),
+        writing: Writing { sparse: args.sparse, dry_run: args.discard, ..Writing::default() },
+        max_memory: global.max_memory,
+        threads: global.threads(),
+        workers: 1,
+        wait: global.wait,
+        lone_input: false,
+        cancel: &cancel,
+        meters: &meters,
+    };
+    let mut status = meters.above(global.status(false));
+    global.report_threads(true);
+    let (mut decompressed, mut input_bytes, mut output_bytes) = (0usize, 0u64, 0u64);
+    // Outputs written so far, so that two inputs storing the same name
+    // cannot overwrite each other's result even with --force.
+    let mut written = HashSet::new();
+    for rel in &inputs {
+        if cancel.is_cancelled() || (args.on_error.stops() && !failures.is_empty()) {
+            break;
+        }
+        let input = src.join(rel);
+        let output = original_name(&input).map(|name| dst.join(rel).with_file_name(name));
+        let result = match (input.to_str(), output.as_ref().map(|output| output.to_str())) {
+            (_, None) => {
+                let msg = format!("cannot name the output for {} (expected a {} suffix)", input.display(), SUFFIX);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+            }
+            (Some(input), Some(Some(_))) if args.discard => decompress_file(input, "", &run),
+            (Some(input), Some(Some(output))) if !written.insert(output.to_string()) => {
+                let msg = format!("{} was already written by this run; not overwriting it with {}", output, input);
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, msg).into())
+            }
+            (Some(input), Some(Some(output))) => Path::new(output)
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(|e| context(e, "creating directory for", output).into())
+                .and_then(|()| decompress_file(input, output, &run)),
+            _ => {
+                let msg = format!("{} is not valid UTF-8; skipping it", input.display());
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+            }
+        };
+        match result {
+            Ok(report) => {
+                decompressed += 1;
+                input_bytes += report.input_bytes;
+                output_bytes += report.output_bytes;
+            }
+            Err(failure) => {
+                writeln!(meters.above(io::stderr()), "Error: {}", failure.error)?;
+                failures.push(failure);
+            }
+        }
+    }
+    meters.finish();
+
+    let units = global.units();
+    let errors: Vec<String> = failures.iter().map(|failure| failure.error.to_string()).collect();
+    match global.format {
+        Format::Text => {
+            let to = match args.discard {
+                true => "discarding the output".to_string(),
+                false => format!("to {}", dst.display()),
+            };
+            writeln!(status,
+                "Decompressed {} files from {} {}: {} to {}. {} failed, {} skipped, {} not {} files",
+                decompressed, src.display(), to, units.size(input_bytes), units.size(output_bytes),
+                failures.len(), walk.skipped.len(), others.len(), SUFFIX)?;
+            write_failures(&mut status, &errors)?;
+        }
+        Format::Json => writeln!(status,
+            "{{\"operation\":\"decompress-dir\",\"discard\":{},\"src\":{},\"dst\":{},\"decompressed\":{},\
+             \"failed\":{},\"skipped\":{},\"not_aapc\":{},\"input_bytes\":{},\"output_bytes\":{},\"failures\":[{}]}}",
+            args.discard, json_string(&src.to_string_lossy()),
+            if args.discard { "null".to_string() } else { json_string(&dst.to_string_lossy()) },
+            decompressed, failures.len(), walk.skipped.len(), others.len(), input_bytes, output_bytes,
+            json_strings(&errors))?,
+    }
+    let failed = failures.len();
+    match failures.into_iter().next() {
+        Some(first) => {
+            let msg = format!("{} files under {} failed", failed, src.display());
+            Err(Failure { code: first.code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
+
+/// The name `input` was compressed from: the filename in its frame header
+/// when it has a usable one, else its own name without the suffix.
+fn original_name(input: &Path) -> Option<String> {
+    let stored = input.to_str()
+        .and_then(|path| read_head(path).ok())
+        .and_then(|head| frame::parse_header(&head).ok())
+        .and_then(|header| header.filename)
+        .filter(|name| ada_toolkit::archive::is_safe_path(name) && !name.contains('/'));
+    stored.or_else(|| {
+        let name = input.file_name()?.to_str()?;
+        name.strip_suffix(SUFFIX).filter(|rest| !rest.is_empty()).map(str::to_string)
+    })
+}
+
+/// What kind of special file skipped by a walk is, for its warning.
+#[cfg(unix)]
+fn special_kind(file_type: fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        "a FIFO"
+    } else if file_type.is_socket() {
+        "a socket"
+    } else if file_type.is_block_device() {
+        "a block device"
+    } else if file_type.is_char_device() {
+        "a character device"
+    } else {
+        "not a regular file or directory"
+    }
+}
+
+#[cfg(not(unix))]
+fn special_kind(_file_type: fs::FileType) -> &'static str {
+    "not a regular file or directory"
+}
+
+/// Compresses each regular file under `src` to `dst/<relative path>.aapc`,
+/// creating directories as needed, then summarises the whole tree.
+pub fn compress_dir(args: &DirArgs, global: &Global) -> Result<(), Failure> {
+    let (src, dst) = (args.src.as_path(), args.dst.as_path());
+    let opts = Tuning::default().options(global.max_memory)?;
+    let src_canonical = fs::canonicalize(src).map_err(|e| context(e, "reading directory", &src.to_string_lossy()))?;
+    if !args.dry_run {
+        fs::create_dir_all(dst).map_err(|e| context(e, "creating output directory", &dst.to_string_lossy()))?;
+    }
+    // A DST that does not exist yet cannot be found in the walk.
+    let dst_canonical = fs::canonicalize(dst).unwrap_or_else(|_| dst.to_path_buf());
+
+    let mut walk = Walk::new(&args.filter);
+    let mut seen = HashSet::from([src_canonical]);
+    walk.visit(src, Path::new(""), args.follow_symlinks, &dst_canonical, &mut seen);
+    walk.skip_links(src);
+    let mut failures = std::mem::take(&mut walk.errors);
+    for failure in &failures {
+        eprintln!("Error: {}", failure.error);
+    }
+
+    let cancel = cancel_on_interrupt();
+    let meters = Meters::new(global.progress_mode()).with_overall(walk.files.len());
+    let (workers, threads) = file_workers(walk.files.len(), global.threads(), opts.block_size, global.max_memory);
+    let run = FileRun {
+        force: args.force,
+        rm: false,
+        tuning: &Tuning::default(),
+        incompressible: args.incompressible,
+        writing: Writing { dry_run: args.dry_run, ..Writing::default() },
+        max_memory: global.max_memory,
+        threads,
+        workers,
+        wait: global.wait,
+        lone_input: false,
+        cancel: &cancel,
+        meters: &meters,
+    };
+    let mut status = meters.above(global.status(false));
+    global.report_threads(true);
+    let (mut compressed, mut input_bytes, mut output_bytes) = (0usize, 0u64, 0u64);
+    let (mut skipped, mut stored) = (0usize, 0usize);
+    // Entries the walk could not read are failures already.
+    let stops = args.on_error.stops();
+    let files = if stops && !failures.is_empty() { &[][..] } else { &walk.files[..] };
+    run_in_order(files, workers, &cancel, |_, rel| {
+        let input = src.join(rel);
+        let mut output = dst.join(rel).into_os_string();
+        output.push(SUFFIX);
+        match (input.to_str(), output.to_str()) {
+            (Some(input), Some(output)) if args.dry_run => compress_file(input, output, &run),
+            (Some(input), Some(output)) => Path::new(output)
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(|e| context(e, "creating directory for", output).into())
+                .and_then(|()| compress_file(input, output, &run)),
+            _ => {
+                let msg = format!("{} is not valid UTF-8; skipping it", input.display());
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+            }
+        }
+    }, |_, result| {
+        match result {
+            Ok(FileReport { sniffed: Some(Sniffed { stored: false, .. }), .. }) => skipped += 1,
+            Ok(report) => {
+                compressed += 1;
+                stored += report.sniffed.is_some() as usize;
+                input_bytes += report.input_bytes;
+                output_bytes += report.output_bytes;
+            }
+            Err(failure) => {
+                writeln!(meters.above(io::stderr()), "Error: {}", failure.error)?;
+                failures.push(failure);
+            }
+        }
+        Ok(stops && !failures.is_empty())
+    })?;
+    meters.finish();
+
+    let ratio = ratio(output_bytes, input_bytes);
+    let units = global.units();
+    let errors: Vec<String> = failures.iter().map(|failure| failure.error.to_string()).collect();
+    match global.format {
+        Format::Text => {
+            writeln!(status,
+                "{}Compressed {} files from {} to {}: {} to {}. Ratio: {:.2} ({}). {} failed, {} skipped, \
+                 {} filtered out{}",
+                dry_run_note(args.dry_run), compressed, src.display(), dst.display(), units.size(input_bytes),
+                units.size(output_bytes), ratio,
+                saved(ratio), failures.len(), walk.skipped.len(), walk.filtered.len(),
+                args.incompressible.summary(skipped, stored))?;
+            write_failures(&mut status, &errors)?;
+        }
+        Format::Json => writeln!(status,
+            "{{\"operation\":\"compress-dir\",\"dry_run\":{},\"src\":{},\"dst\":{},\"compressed\":{},\
+             \"failed\":{},\"skipped\":{},\"filtered\":{},\"skipped_compressed\":{},\"stored\":{},\
+             \"input_bytes\":{},\"output_bytes\":{},\"ratio\":{},\"failures\":[{}]}}",
+            args.dry_run, json_string(&src.to_string_lossy()), json_string(&dst.to_string_lossy()), compressed,
+            failures.len(), walk.skipped.len(), walk.filtered.len(), skipped, stored, input_bytes, output_bytes,
+            ratio, json_strings(&errors))?,
+    }
+    let failed = failures.len();
+    match failures.into_iter().next() {
+        Some(first) => {
+            let msg = format!("{} files under {} failed", failed, src.display());
+            Err(Failure { code: first.code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
+
+/// The files to test under `folder` and, with `recursive`, its
+/// subdirectories. Directories and entries that cannot be read end up in
+/// the walk's errors and it carries on without them.
+pub fn folder_files<'a>(folder: &Path, recursive: bool, follow: bool, filter: &'a Filter) -> Walk<'a> {
+    let mut walk = Walk { descend: recursive, ..Walk::new(filter) };
+    let mut seen = HashSet::from([fs::canonicalize(folder).unwrap_or_else(|_| folder.to_path_buf())]);
+    walk.visit(folder, Path::new(""), follow, Path::new(""), &mut seen);
+    walk.skip_links(folder);
+    walk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn filter(include: &[&str], exclude: &[&str], skip_hidden: bool) -> Filter {
+        Filter {
+            include: include.iter().map(|pattern| pattern.to_string()).collect(),
+            exclude: exclude.iter().map(|pattern| pattern.to_string()).collect(),
+            skip_hidden,
+        }
+    }
+
+    fn kept<'a>(filter: &Filter, files: &[&'a str]) -> Vec<&'a str> {
+        files.iter().copied().filter(|file| filter.keeps(Path::new(file), false)).collect()
+    }
+
+    #[test]
+    fn excludes_win_over_overlapping_includes() {
+        let files = ["a.log", "b.log.gz", "c.gz", "sub/d.log", "tmp/e.log", "sub/tmp/f.log"];
+        assert_eq!(kept(&filter(&[], &[], false), &files), files);
+        assert_eq!(kept(&filter(&["*.log"], &[], false), &files), ["a.log", "sub/d.log", "tmp/e.log", "sub/tmp/f.log"]);
+        assert_eq!(kept(&filter(&["*.log", "*.gz"], &["*.gz"], false), &files),
+                   ["a.log", "sub/d.log", "tmp/e.log", "sub/tmp/f.log"]);
+        assert_eq!(kept(&filter(&["*.log"], &["a.*", "tmp/**"], false), &files), ["sub/d.log", "sub/tmp/f.log"]);
+        assert_eq!(kept(&filter(&["sub/*"], &["*/tmp/*"], false), &files), ["sub/d.log"]);
+    }
+
+    #[test]
+    fn patterns_are_case_sensitive() {
+        let files = ["a.log", "B.LOG", "c.Log"];
+        assert_eq!(kept(&filter(&["*.log"], &[], false), &files), ["a.log"]);
+        assert_eq!(kept(&filter(&["*.LOG"], &[], false), &files), ["B.LOG"]);
+        assert_eq!(kept(&filter(&[], &["*.log"], false), &files), ["B.LOG", "c.Log"]);
+    }
+
+    #[test]
+    fn directories_only_meet_the_excludes() {
+        let only_logs = filter(&["*.log"], &[], false);
+        assert!(only_logs.keeps(Path::new("tmp"), true));
+        for exclude in ["tmp", "tmp/**", "tmp/"] {
+            assert!(!filter(&["*.log"], &[exclude], false).keeps(Path::new("tmp"), true), "{}", exclude);
+        }
+        assert!(filter(&[], &["tmp/**"], false).keeps(Path::new("sub/tmp"), true));
+        assert!(!filter(&[], &["tmp"], false).keeps(Path::new("sub/tmp"), true));
+    }
+
+    #[test]
+    fn hidden_files_and_directories_can_be_skipped() {
+        let skip = filter(&[], &[], true);
+        assert!(!skip.keeps(Path::new(".git"), true));
+        assert!(!skip.keeps(Path::new("sub/.env"), false));
+        assert!(skip.keeps(Path::new("sub/a.txt"), false));
+        assert!(filter(&[], &[], false).keeps(Path::new(".env"), false));
+    }
+
+    #[test]
+    fn an_excluded_directory_is_not_walked() {
+        let tmp = TempDir::new();
+        for file in ["a.log", "keep/b.log", "tmp/c.log", "tmp/deep/d.log", "tmp/deep/e.log"] {
+            tmp.write(file, file);
+        }
+        let root = tmp.path();
+        let excludes = filter(&[], &["tmp/**"], false);
+        let walk = folder_files(root, true, false, &excludes);
+        assert_eq!(walk.files, [PathBuf::from("a.log"), Path::new("keep").join("b.log")]);
+        assert_eq!(walk.dirs, [PathBuf::from("keep")]);
+        // The pruned directory counts once, not once per file under it.
+        assert_eq!(walk.filtered, [PathBuf::from("tmp")]);
+    }
+
+    #[test]
+    fn outputs_take_the_stored_name_or_drop_the_suffix() {
+        use ada_toolkit::{compression, CompressOptions};
+
+        let root = TempDir::new();
+        let frame = |name: &str, filename: Option<&str>| {
+            let opts = CompressOptions { filename: filename.map(str::to_string), ..CompressOptions::default() };
+            root.write(name, compression::compress_with_options(b"named", &opts).unwrap())
+        };
+        assert_eq!(original_name(&frame("renamed.aapc", Some("report.txt"))).as_deref(), Some("report.txt"));
+        assert_eq!(original_name(&frame("plain.txt.aapc", None)).as_deref(), Some("plain.txt"));
+        // A stored name that would leave the output directory is ignored.
+        assert_eq!(original_name(&frame("evil.aapc", Some("../evil"))).as_deref(), Some("evil"));
+        assert_eq!(original_name(&frame("sub.aapc", Some("a/b"))).as_deref(), Some("sub"));
+        assert_eq!(original_name(&frame(".aapc", None)), None);
+        fs::write(root.join("junk.aapc"), b"not a frame").unwrap();
+        assert_eq!(original_name(&root.join("junk.aapc")).as_deref(), Some("junk"));
+    }
+}
@@ -0,0 +1,223 @@
+//! `info` and `inspect`: a frame's metadata and its blocks.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use ada_toolkit::index::BlockEntry;
+use ada_toolkit::{count_tokens, frame, BlockTable, ChecksumType, FrameInfo, TokenCounts};
+
+use crate::{context, Failure, Format, Global};
+use crate::report::{json_string, ratio};
+
+/// Prints the block table of `file`, and with `opcodes` what each RLE
+/// block's payload is made of.
+pub fn inspect(file: &str, opcodes: bool, global: &Global) -> Result<(), Failure> {
+    let mut input = File::open(file).map_err(|e| context(e, "reading input", file))?;
+    let table = BlockTable::build(BufReader::new(&mut input)).map_err(|e| Failure::from(e).context("reading", file))?;
+    let mut counts = Vec::new();
+    if opcodes {
+        let mut payload = Vec::new();
+        for entry in table.entries() {
+            if entry.block_type == frame::BLOCK_STORED {
+                counts.push(None);
+                continue;
+            }
+            payload.clear();
+            input.seek(SeekFrom::Start(entry.payload_offset))?;
+            (&mut input).take(entry.comp_len as u64).read_to_end(&mut payload)?;
+            let tokens = count_tokens(&payload, entry.payload_offset as usize)
+                .map_err(|e| Failure::from(e).context("reading", file))?;
+            counts.push(Some(tokens));
+        }
+    }
+    let mut total = TokenCounts::default();
+    for tokens in counts.iter().flatten() {
+        total.add(tokens);
+    }
+    let (raw, comp) = table.entries().iter()
+        .fold((0u64, 0u64), |(raw, comp), entry| (raw + entry.raw_len as u64, comp + entry.comp_len as u64));
+    let checksum = |entry: &BlockEntry| entry.checksum.map(|crc| format!("{:08x}", crc));
+
+    let mut status = global.status(false);
+    match global.format {
+        Format::Text => {
+            write!(status, "{:>6} {:>12} {:>12} {:>10} {:>10} {:>7} {:<10} {:<8}",
+                   "block", "offset", "raw offset", "raw", "compressed", "ratio", "type", "checksum")?;
+            if opcodes {
+                write!(status, " {:>8} {:>10} {:>10} {:>8}", "runs", "run bytes", "literals", "escapes")?;
+            }
+            writeln!(status)?;
+            for (i, entry) in table.entries().iter().enumerate() {
+                write!(status, "{:>6} {:>12} {:>12} {:>10} {:>10} {:>7.3} {:<10} {:<8}",
+                       i, entry.payload_offset, entry.raw_offset, entry.raw_len, entry.comp_len,
+                       ratio(entry.comp_len as u64, entry.raw_len as u64),
+                       format!("{} ({})", frame::block_type_name(entry.block_type), entry.block_type),
+                       checksum(entry).unwrap_or_else(|| "-".to_string()))?;
+                match counts.get(i) {
+                    Some(Some(t)) => write!(status, " {:>8} {:>10} {:>10} {:>8}",
+                                            t.runs, t.run_bytes, t.literals, t.escapes)?,
+                    Some(None) => write!(status, " {:>8} {:>10} {:>10} {:>8}", "-", "-", "-", "-")?,
+                    None => {}
+                }
+                writeln!(status)?;
+            }
+            write!(status, "{} blocks, {} bytes uncompressed in {} bytes of payload. Ratio: {:.3}",
+                   table.entries().len(), raw, comp, ratio(comp, raw))?;
+            if opcodes {
+                write!(status, "; {} runs ({} bytes), {} literals, {} escapes",
+                       total.runs, total.run_bytes, total.literals, total.escapes)?;
+            }
+            writeln!(status)?;
+        }
+        Format::Json => {
+            let tokens_json = |t: &TokenCounts| format!(
+                "{{\"runs\":{},\"run_bytes\":{},\"literals\":{},\"escapes\":{}}}",
+                t.runs, t.run_bytes, t.literals, t.escapes);
+            let blocks: Vec<String> = table.entries().iter().enumerate()
+                .map(|(i, entry)| {
+                    let tokens = match counts.get(i) {
+                        Some(Some(t)) => format!(",\"tokens\":{}", tokens_json(t)),
+                        Some(None) => ",\"tokens\":null".to_string(),
+                        None => String::new(),
+                    };
+                    format!("{{\"index\":{},\"payload_offset\":{},\"raw_offset\":{},\"raw_len\":{},\
+                             \"comp_len\":{},\"ratio\":{},\"block_type\":{},\"type\":\"{}\",\"checksum\":{}{}}}",
+                            i, entry.payload_offset, entry.raw_offset, entry.raw_len, entry.comp_len,
+                            ratio(entry.comp_len as u64, entry.raw_len as u64), entry.block_type,
+                            frame::block_type_name(entry.block_type),
+                            checksum(entry).map_or("null".to_string(), |crc| json_string(&crc)), tokens)
+                })
+                .collect();
+            let tokens = if opcodes { format!(",\"tokens\":{}", tokens_json(&total)) } else { String::new() };
+            writeln!(status, "{{\"operation\":\"inspect\",\"input\":{},\"blocks\":[{}],\
+                              \"totals\":{{\"blocks\":{},\"raw_bytes\":{},\"comp_bytes\":{},\"ratio\":{}{}}}}}",
+                     json_string(file), blocks.join(","), table.entries().len(), raw, comp, ratio(comp, raw), tokens)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints frame metadata for `file` from its header, block headers and
+/// trailer, without decoding any payload. When the blocks or trailer cannot
+/// be read the header fields are still shown, then the command fails with
+/// the reason.
+pub fn show_info(file: &str, global: &Global) -> Result<(), Failure> {
+    let mut input = File::open(file).map_err(|e| context(e, "reading input", file))?;
+    // Walking the block headers catches a truncated file whose last bytes
+    // merely look like a trailer.
+    let checked = FrameInfo::from_reader(&mut input).and_then(|info| {
+        input.seek(SeekFrom::Start(0))?;
+        BlockTable::build(BufReader::new(&mut input)).map(|_| info)
+    });
+    let (info, error) = match checked {
+        Ok(info) => (info, None),
+        Err(err) => {
+            // The longest possible header: both optional fields at full length.
+            let mut buf = Vec::new();
+            input.seek(SeekFrom::Start(0))?;
+            (&mut input).take((frame::HEADER_LEN + 2 * (2 + u16::MAX as usize)) as u64).read_to_end(&mut buf)?;
+            let Ok(header) = frame::parse_header(&buf) else {
+                return Err(Failure::from(err).context("reading", file));
+            };
+            let info = FrameInfo {
+                version: header.version,
+                flags: header.flags,
+                block_size: header.block_size,
+                checksum_type: match header.block_checksums() || header.content_checksum() {
+                    true => ChecksumType::Crc32,
+                    false => ChecksumType::None,
+                },
+                block_checksums: header.block_checksums(),
+                filename: header.filename,
+                comment: header.comment,
+                ..FrameInfo::default()
+            };
+            (info, Some(err))
+        }
+    };
+    let mut out = global.status(false);
+    let index = format!("{}.idx", file);
+    let index = Path::new(&index).is_file().then_some(index);
+    let flag_names: Vec<&str> = [
+        (frame::FLAG_BLOCK_CHECKSUM, "block-checksums"),
+        (frame::FLAG_CONTENT_CHECKSUM, "content-checksum"),
+        (frame::FLAG_FILENAME, "filename"),
+        (frame::FLAG_COMMENT, "comment"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| info.flags & flag != 0)
+    .map(|(_, name)| name)
+    .collect();
+    let ratio = ratio(info.compressed_size, info.content_size);
+    let checksum_type = match info.checksum_type {
+        ChecksumType::None => "none",
+        ChecksumType::Crc32 => "crc32",
+    };
+
+    if global.format == Format::Json {
+        let opt_str = |value: Option<&str>| value.map_or("null".to_string(), json_string);
+        let totals = match &error {
+            None => format!(
+                "\"block_count\":{},\"content_size\":{},\"compressed_size\":{},\"ratio\":{},\
+                 \"content_checksum\":{},\"error\":null",
+                info.block_count, info.content_size, info.compressed_size, ratio,
+                opt_str(info.content_checksum.map(|crc| format!("{:08x}", crc)).as_deref())
+            ),
+            Some(err) => format!(
+                "\"block_count\":null,\"content_size\":null,\"compressed_size\":null,\"ratio\":null,\
+                 \"content_checksum\":null,\"error\":{}",
+                json_string(&err.to_string())
+            ),
+        };
+        writeln!(
+            out,
+            "{{\"operation\":\"info\",\"input\":{},\"complete\":{},\"version\":{},\"small\":{},\"flags\":{},\
+             \"flag_names\":[{}],\"codec\":\"rle\",\"block_size\":{},\"checksum_type\":\"{}\",\
+             \"block_checksums\":{},\"filename\":{},\"comment\":{},\"index\":{},{}}}",
+            json_string(file), error.is_none(), info.version, info.small, info.flags,
+            flag_names.iter().map(|name| json_string(name)).collect::<Vec<_>>().join(","),
+            info.block_size, checksum_type, info.block_checksums,
+            opt_str(info.filename.as_deref()), opt_str(info.comment.as_deref()), opt_str(index.as_deref()), totals
+        )?;
+    } else {
+        writeln!(out, "File: {}", file)?;
+        writeln!(out, "Format version: {}", info.version)?;
+        if info.small {
+            writeln!(out, "Layout: small frame (one block, no header fields or trailer)")?;
+        }
+        writeln!(out, "Codec: RLE (incompressible blocks stored)")?;
+        match flag_names.is_empty() {
+            true => writeln!(out, "Flags: {:#04x}", info.flags)?,
+            false => writeln!(out, "Flags: {:#04x} ({})", info.flags, flag_names.join(", "))?,
+        }
+        writeln!(out, "Block size: {} bytes", info.block_size)?;
+        if error.is_none() {
+            writeln!(out, "Blocks: {}", info.block_count)?;
+            writeln!(out, "Original size: {} bytes", info.content_size)?;
+            writeln!(out, "Compressed size: {} bytes", info.compressed_size)?;
+            writeln!(out, "Ratio: {:.2}", ratio)?;
+        }
+        let content = match (&error, info.content_checksum) {
+            (None, Some(crc)) => format!("{:#010x}", crc),
+            (None, None) => "none".to_string(),
+            (Some(_), _) => "unknown".to_string(),
+        };
+        let per_block = if info.block_checksums { "yes" } else { "no" };
+        writeln!(out, "Checksum: {} (per block: {}, content: {})", checksum_type, per_block, content)?;
+        if let Some(name) = &info.filename {
+            writeln!(out, "Stored filename: {}", name)?;
+        }
+        if let Some(comment) = &info.comment {
+            writeln!(out, "Comment: {}", comment)?;
+        }
+        writeln!(out, "Index: {}", index.as_deref().unwrap_or("none"))?;
+        if let Some(err) = &error {
+            writeln!(out, "Trailer: unreadable ({})", err)?;
+        }
+    }
+    match error {
+        Some(err) => Err(Failure::from(err).context("reading", file)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,18 @@
+//! One module per subcommand, or per family of them, each with the
+//! function `run` dispatches to.
+
+pub mod archive;
+pub mod batch;
+pub mod bench;
+pub mod cat;
+pub mod compare;
+pub mod compress;
+pub mod crash_test;
+pub mod decompress;
+pub mod dir;
+pub mod info;
+pub mod tar;
+pub mod test;
+pub mod test_folder;
+pub mod verify;
+pub mod watch;
@@ -0,0 +1,88 @@
+//! `compress --tar` and `decompress --untar`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::time::Instant;
+
+use ada_toolkit::CompressOptions;
+use ada_toolkit::stream::{AapcReader, AapcWriter};
+
+use crate::{cancel_on_interrupt, context, usage_error, Failure, Global, Paths, Tuning, SUFFIX};
+use crate::output::{check_overwrite, create_output, open_input, STDIO};
+use crate::progress::saved;
+use crate::report::ratio;
+
+/// Streams a tar of the single input directory through the compressor, with
+/// no intermediate .tar on disk. Symlinks are stored as symlinks.
+pub fn compress_tar(paths: &Paths, tuning: &Tuning, global: &Global) -> Result<(), Failure> {
+    let [input] = paths.inputs.as_slice() else {
+        usage_error("--tar takes a single directory")
+    };
+    let dir = Path::new(input);
+    let Some(name) = fs::canonicalize(dir).ok().and_then(|dir| dir.file_name().map(PathBuf::from)) else {
+        usage_error(&format!("--tar needs an existing directory, not {}", input))
+    };
+    let output = match &paths.output {
+        Some(output) => output.clone(),
+        None if paths.stdout => STDIO.to_string(),
+        None => format!("{}.tar{}", input.trim_end_matches(MAIN_SEPARATOR), SUFFIX),
+    };
+    let opts = CompressOptions { cancel: Some(cancel_on_interrupt()), ..tuning.options(global.max_memory)? };
+    let mut writer = create_output(&output, paths.force, global.wait)?;
+    log::info!("Writing tar of {} to {}", input, output);
+    let start = Instant::now();
+    let result = AapcWriter::with_options(&mut writer, &opts).map_err(io::Error::from).and_then(|encoder| {
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(false);
+        builder.append_dir_all(&name, dir)?;
+        let (_, stats) = builder.into_inner()?.finish_with_stats()?;
+        Ok(stats)
+    });
+    let stats = result.and_then(|stats| writer.commit().map(|()| stats))
+        .map_err(|e| Failure::from(e).context("compressing", input))?;
+    let mut status = global.status(output == STDIO);
+    let units = global.units();
+    let ratio = ratio(stats.output_bytes, stats.input_bytes);
+    writeln!(status, "Compressed {} as tar ({}) to {} ({}) in {:?}. Ratio: {:.2} ({})",
+             input, units.size(stats.input_bytes), output, units.size(stats.output_bytes), start.elapsed(), ratio,
+             saved(ratio))?;
+    Ok(())
+}
+
+/// Decompresses a .tar.aapc and unpacks the tar stream under `dir`. Entries
+/// whose paths escape `dir` are skipped by the tar layer; existing files
+/// follow the usual overwrite rules.
+pub fn decompress_tar(paths: &Paths, dir: &Path, global: &Global) -> Result<(), Failure> {
+    let [input] = paths.inputs.as_slice() else {
+        usage_error("--untar takes a single input")
+    };
+    let start = Instant::now();
+    fs::create_dir_all(dir).map_err(|e| context(e, "creating output directory", &dir.to_string_lossy()))?;
+    let mut archive = tar::Archive::new(AapcReader::new(open_input(input)?));
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_permissions(true);
+    let mut count = 0;
+    let unpacked = archive.entries().and_then(|entries| {
+        for entry in entries {
+            let mut entry = entry?;
+            let dest = dir.join(entry.path()?);
+            log::info!("Extracting {}", dest.display());
+            if !entry.header().entry_type().is_dir() {
+                check_overwrite(&dest, paths.force)?;
+            }
+            if entry.unpack_in(dir)? {
+                count += 1;
+            }
+        }
+        Ok(())
+    });
+    unpacked.map_err(|e| Failure::from(e).context("unpacking", input))?;
+    // Read on past the tar end-of-archive blocks so the frame trailer and
+    // its checksum are checked too.
+    let mut reader = archive.into_inner();
+    io::copy(&mut reader, &mut io::sink()).map_err(|e| Failure::from(e).context("decompressing", input))?;
+    writeln!(global.status(false), "Unpacked {} entries from {} into {} in {:?}.",
+             count, input, dir.display(), start.elapsed())?;
+    Ok(())
+}
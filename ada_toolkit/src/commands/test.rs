@@ -0,0 +1,378 @@
+//! `test`: round trips of generated data or of one file.
+
+use std::fs::read;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use ada_toolkit::{compression, decompression, CompressOptions, DecompressOptions};
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Format, Global, Tuning};
+use crate::generate::{generate, Profile};
+use crate::progress::{saved, Units};
+use crate::report::{json_string, json_timing, ratio};
+
+pub fn run_generated_test(
+    seed: Option<u64>,
+    size: usize,
+    profile: Profile,
+    iterations: u32,
+    global: &Global,
+) -> io::Result<()> {
+    let mut status = global.status(false);
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let profile_name = profile.to_possible_value().expect("no skipped variants").get_name().to_string();
+    if global.format == Format::Text {
+        if global.decorated(&io::stdout()) {
+            writeln!(status, "Continuing our symphony of compression, courtesy of Ada Lovelace!")?;
+        }
+        writeln!(status, "Profile: {}, {} (seed {}; repeat with --seed {})",
+                 profile_name, global.units().size(size as u64), seed, seed)?;
+    }
+
+    let test_data = generate(profile, size, &mut StdRng::seed_from_u64(seed));
+    log::debug!("Generated test data of {} bytes", test_data.len());
+    let source = Source::Generated { seed, profile: &profile_name };
+    round_trip(&test_data, &source, iterations, global, &mut status)
+}
+
+/// Where round-tripped test data came from.
+enum Source<'a> {
+    File(&'a str),
+    Generated { seed: u64, profile: &'a str },
+}
+
+pub fn run_file_test(input_path: &str, iterations: u32, global: &Global) -> io::Result<()> {
+    let mut status = global.status(false);
+    if global.format == Format::Text {
+        writeln!(status, "Testing with real file: {}", input_path)?;
+    }
+    let test_data = read(input_path)?;
+
+    log::debug!("Loaded file of {} bytes", test_data.len());
+    round_trip(&test_data, &Source::File(input_path), iterations, global, &mut status)
+}
+
+/// The first offset at which `actual` departs from `expected`, which may be
+/// where the shorter one ends.
+pub fn first_difference(expected: &[u8], actual: &[u8]) -> usize {
+    expected.iter().zip(actual).position(|(e, a)| e != a).unwrap_or(expected.len().min(actual.len()))
+}
+
+/// How many offsets hold different bytes, counting those only one holds.
+fn differing_bytes(expected: &[u8], actual: &[u8]) -> usize {
+    let common = expected.iter().zip(actual).filter(|(e, a)| e != a).count();
+    common + expected.len().abs_diff(actual.len())
+}
+
+/// Where `actual` first departs from `expected`, with the bytes around that
+/// offset in hex and the first differing one in brackets.
+pub fn describe_mismatch(expected: &[u8], actual: &[u8]) -> String {
+    let offset = first_difference(expected, actual);
+    let around = |data: &[u8]| {
+        let start = offset.saturating_sub(8);
+        let mut bytes: Vec<String> = data[start.min(data.len())..(offset + 9).min(data.len())]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if start + i == offset { format!("[{:02x}]", b) } else { format!("{:02x}", b) })
+            .collect();
+        if offset >= data.len() {
+            bytes.push("[end]".to_string());
+        }
+        bytes.join(" ")
+    };
+    let mut msg = format!("first difference at byte {} ({} bytes differ): expected {}, got {}",
+                          offset, differing_bytes(expected, actual), around(expected), around(actual));
+    if expected.len() != actual.len() {
+        msg.push_str(&format!(" ({} bytes expected, {} decompressed)", expected.len(), actual.len()));
+    }
+    msg
+}
+
+/// A few lines showing where `actual` departs from `expected`: the first
+/// differing offset and the block of `block_size` bytes holding it, how many
+/// bytes differ, and 32 bytes of each in hex around the offset. Differing
+/// bytes are coloured with `color` and marked with carets otherwise.
+pub fn mismatch_report(expected: &[u8], actual: &[u8], block_size: usize, color: bool) -> String {
+    let offset = first_difference(expected, actual);
+    let mut report = format!(
+        "Mismatch at byte {} (block {}); {} bytes differ, {} expected, {} decompressed\n",
+        offset, offset / block_size, differing_bytes(expected, actual), expected.len(), actual.len()
+    );
+    let differs = |i: usize| expected.get(i) != actual.get(i);
+    let row_start = (offset / 16).saturating_sub(1) * 16;
+    for row in [row_start, row_start + 16] {
+        let end = (row + 16).min(expected.len().max(actual.len()));
+        if row >= end {
+            break;
+        }
+        for (label, data, colour) in [("expected", expected, "32"), ("actual", actual, "31")] {
+            let bytes: Vec<String> = (row..end).map(|i| match data.get(i) {
+                Some(b) if color && differs(i) => format!("\x1b[{}m{:02x}\x1b[0m", colour, b),
+                Some(b) => format!("{:02x}", b),
+                None => "--".to_string(),
+            }).collect();
+            report.push_str(&format!("  {:<8} {:08x}  {}\n", label, row, bytes.join(" ")));
+        }
+        if !color && (row..end).any(differs) {
+            let marks: Vec<&str> = (row..end).map(|i| if differs(i) { "^^" } else { "  " }).collect();
+            report.push_str(&format!("{:21}{}\n", "", marks.join(" ").trim_end()));
+        }
+    }
+    report
+}
+
+/// Compresses and decompresses `test_data` in memory, reporting sizes and
+/// timings, and fails if the result differs. With more than one of
+/// `iterations`, the first run of each phase is a warm-up and the timings
+/// come from that many further runs.
+fn round_trip(
+    test_data: &[u8],
+    source: &Source,
+    iterations: u32,
+    global: &Global,
+    status: &mut dyn Write,
+) -> io::Result<()> {
+    let text = global.format == Format::Text;
+
+    // Compress
+    let opts = Tuning::default().threaded_options(global.max_memory, global.threads())?;
+    let start = Instant::now();
+    let compressed = compression::compress_with_options(test_data, &opts).map_err(io::Error::from)?;
+    let compress_time = Timings::measure(start.elapsed(), iterations, || {
+        compression::compress_with_options(test_data, &opts).map_err(io::Error::from)?;
+        Ok(())
+    })?;
+    let ratio = ratio(compressed.len() as u64, test_data.len() as u64);
+
+    let units = global.units();
+    let size = test_data.len() as u64;
+    if text {
+        writeln!(status, "Original size: {}", units.size(size))?;
+        writeln!(status, "Compressed size: {} (ratio: {:.2}, {})",
+                 units.size(compressed.len() as u64), ratio, saved(ratio))?;
+        writeln!(status, "Compression time: {}", compress_time.describe(size, units))?;
+    }
+
+    // Decompress
+    let decode_opts = DecompressOptions { threads: opts.threads, ..DecompressOptions::default() };
+    let start = Instant::now();
+    let decompressed = decompression::decompress_with_options(&compressed, &decode_opts)?;
+    let decompress_time = Timings::measure(start.elapsed(), iterations, || {
+        decompression::decompress_with_options(&compressed, &decode_opts)?;
+        Ok(())
+    })?;
+
+    if text {
+        writeln!(status, "Decompressed size: {}", units.size(decompressed.len() as u64))?;
+        writeln!(status, "Decompression time: {}", decompress_time.describe(size, units))?;
+    }
+
+    // Verify
+    if test_data != decompressed {
+        let block_size = CompressOptions::default().block_size;
+        eprint!("{}", mismatch_report(test_data, &decompressed, block_size, global.colored(&io::stderr())));
+        let msg = match source {
+            Source::Generated { seed, profile } => format!("decompression mismatch ({} data, seed {}): {}",
+                                                           profile, seed, describe_mismatch(test_data, &decompressed)),
+            Source::File(_) => format!("decompression mismatch: {}", describe_mismatch(test_data, &decompressed)),
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    match text {
+        true => writeln!(status, "Harmony restored: Data is identical."),
+        false => writeln!(status,
+            "{{\"operation\":\"test\",{},\"status\":\"ok\",\"input_bytes\":{},\"compressed_bytes\":{},\
+             \"ratio\":{},{},{},\"iterations\":{},\"compress_timing\":{},\"decompress_timing\":{}}}",
+            match source {
+                Source::File(input) => format!("\"input\":{},\"seed\":null,\"profile\":null", json_string(input)),
+                Source::Generated { seed, profile } => format!("\"input\":null,\"seed\":{},\"profile\":{}",
+                                                               seed, json_string(profile)),
+            },
+            test_data.len(), compressed.len(), ratio,
+            json_timing("compress_", test_data.len() as u64, compress_time.median),
+            json_timing("decompress_", test_data.len() as u64, decompress_time.median),
+            iterations, compress_time.json(), decompress_time.json()),
+    }
+}
+
+/// The spread of the timed runs of one phase of `test`.
+#[derive(Clone, Copy)]
+struct Timings {
+    runs: usize,
+    min: Duration,
+    median: Duration,
+    mean: Duration,
+    max: Duration,
+    /// Sample standard deviation; zero for a single run.
+    stddev: Duration,
+}
+
+impl Timings {
+    /// Times `iterations` further calls of `run`, or with a single
+    /// iteration just takes `first`, the time of the run already made.
+    fn measure(first: Duration, iterations: u32, mut run: impl FnMut() -> io::Result<()>) -> io::Result<Timings> {
+        if iterations == 1 {
+            return Ok(Timings::of(vec![first]));
+        }
+        let mut times = Vec::with_capacity(iterations as usize);
+        for _ in 0..iterations {
+            let start = Instant::now();
+            run()?;
+            times.push(start.elapsed());
+        }
+        Ok(Timings::of(times))
+    }
+
+    fn of(mut times: Vec<Duration>) -> Timings {
+        times.sort();
+        let runs = times.len();
+        let mean = times.iter().sum::<Duration>() / runs as u32;
+        let variance = match runs {
+            1 => 0.0,
+            _ => times.iter().map(|t| (t.as_secs_f64() - mean.as_secs_f64()).powi(2)).sum::<f64>() / (runs - 1) as f64,
+        };
+        Timings {
+            runs,
+            min: times[0],
+            median: times[runs / 2],
+            mean,
+            max: times[runs - 1],
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+        }
+    }
+
+    /// The time and speed of a single run, or the median and spread of several.
+    fn describe(&self, bytes: u64, units: Units) -> String {
+        match self.runs {
+            1 => format!("{:?} ({})", self.median, units.speed(bytes, self.median)),
+            runs => format!("median {:?} of {} runs ({}); min {:?} ({}), mean {:?}, max {:?}, stddev {:?}",
+                            self.median, runs, units.speed(bytes, self.median), self.min, units.speed(bytes, self.min),
+                            self.mean, self.max, self.stddev),
+        }
+    }
+
+    fn json(&self) -> String {
+        let ms = |t: Duration| t.as_secs_f64() * 1000.0;
+        format!("{{\"runs\":{},\"min_ms\":{:.3},\"median_ms\":{:.3},\"mean_ms\":{:.3},\"max_ms\":{:.3},\
+                 \"stddev_ms\":{:.3}}}",
+                self.runs, ms(self.min), ms(self.median), ms(self.mean), ms(self.max), ms(self.stddev))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_difference_is_where_the_shorter_ends_when_one_is_a_prefix() {
+        assert_eq!(first_difference(b"abcdef", b"abXdeY"), 2);
+        assert_eq!(first_difference(b"abcdef", b"abc"), 3);
+        assert_eq!(first_difference(b"abc", b"abcdef"), 3);
+        assert_eq!(first_difference(b"abc", b"abc"), 3);
+    }
+
+    #[test]
+    fn a_short_output_is_marked_where_it_ends() {
+        let expected: Vec<u8> = (0..40).collect();
+        assert_eq!(describe_mismatch(&expected, &expected[..20]),
+                   "first difference at byte 20 (20 bytes differ): \
+                    expected 0c 0d 0e 0f 10 11 12 13 [14] 15 16 17 18 19 1a 1b 1c, \
+                    got 0c 0d 0e 0f 10 11 12 13 [end] (40 bytes expected, 20 decompressed)");
+        let mut actual = expected.clone();
+        actual[0] ^= 0xff;
+        actual[39] ^= 0xff;
+        assert_eq!(describe_mismatch(&expected, &actual),
+                   "first difference at byte 0 (2 bytes differ): \
+                    expected [00] 01 02 03 04 05 06 07 08, got [ff] 01 02 03 04 05 06 07 08");
+    }
+
+    #[test]
+    fn the_report_names_the_block_and_marks_differing_bytes() {
+        let expected = vec![0u8; 100];
+        let mut actual = expected.clone();
+        actual[35] = 0xaa;
+        actual.truncate(40);
+        let report = mismatch_report(&expected, &actual, 16, false);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Mismatch at byte 35 (block 2); 61 bytes differ, 100 expected, 40 decompressed");
+        assert_eq!(lines[1], format!("  expected 00000010  {}", ["00"; 16].join(" ")));
+        assert_eq!(lines[3], format!("  expected 00000020  {}", ["00"; 16].join(" ")));
+        assert_eq!(lines[4], "  actual   00000020  00 00 00 aa 00 00 00 00 -- -- -- -- -- -- -- --");
+        assert_eq!(lines[5], format!("{:21}         ^^             ^^ ^^ ^^ ^^ ^^ ^^ ^^ ^^", ""));
+        assert_eq!(lines.len(), 6, "{}", report);
+        assert!(!report.contains('\x1b'));
+
+        let coloured = mismatch_report(&expected, &actual, 16, true);
+        assert!(coloured.contains("\x1b[31maa\x1b[0m") && !coloured.contains("^^"), "{}", coloured);
+    }
+
+    #[test]
+    fn the_whole_report_for_a_known_offset() {
+        let expected: Vec<u8> = (0..=255).collect();
+        let mut actual = expected.clone();
+        actual[100] = 0xee;
+        actual[103] = 0xef;
+        let report = mismatch_report(&expected, &actual, 64, false);
+        let row = |start: u8| (start..start + 16).map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+        assert_eq!(report, format!(
+            "Mismatch at byte 100 (block 1); 2 bytes differ, 256 expected, 256 decompressed\n\
+             \x20 expected 00000050  {}\n\
+             \x20 actual   00000050  {}\n\
+             \x20 expected 00000060  {}\n\
+             \x20 actual   00000060  60 61 62 63 ee 65 66 ef 68 69 6a 6b 6c 6d 6e 6f\n\
+             {:21}            ^^       ^^\n",
+            row(0x50), row(0x50), row(0x60), ""));
+    }
+
+    fn ms(times: &[u64]) -> Vec<Duration> {
+        times.iter().map(|&t| Duration::from_millis(t)).collect()
+    }
+
+    #[test]
+    fn timings_are_sorted_and_spread() {
+        let timings = Timings::of(ms(&[30, 10, 20, 40, 50]));
+        assert_eq!(timings.runs, 5);
+        assert_eq!([timings.min, timings.median, timings.mean, timings.max], *ms(&[10, 30, 30, 50]));
+        // Sample standard deviation: sqrt(1000 ms² / 4).
+        assert!((timings.stddev.as_secs_f64() * 1000.0 - 250f64.sqrt()).abs() < 1e-6, "{:?}", timings.stddev);
+        assert_eq!(timings.median, Timings::of(ms(&[40, 10, 30, 20])).median, "the upper of the middle two");
+
+        let single = Timings::of(ms(&[7]));
+        assert_eq!([single.min, single.median, single.mean, single.max], *ms(&[7, 7, 7, 7]));
+        assert_eq!(single.stddev, Duration::ZERO);
+        let units = Units { raw: true };
+        assert_eq!(single.describe(7000, units), "7ms (1000000 bytes/s)");
+        let several = timings.describe(1000, units);
+        assert!(several.starts_with("median 30ms of 5 runs ("), "{}", several);
+        assert_eq!(single.json(), "{\"runs\":1,\"min_ms\":7.000,\"median_ms\":7.000,\"mean_ms\":7.000,\
+                                   \"max_ms\":7.000,\"stddev_ms\":0.000}");
+    }
+
+    #[test]
+    fn one_iteration_takes_the_run_already_made() {
+        let mut calls = 0;
+        let timings = Timings::measure(Duration::from_millis(5), 1, || {
+            calls += 1;
+            Ok(())
+        }).unwrap();
+        assert_eq!((calls, timings.runs, timings.median), (0, 1, Duration::from_millis(5)));
+
+        // With more, the first run is only a warm-up.
+        let timings = Timings::measure(Duration::from_secs(60), 3, || {
+            calls += 1;
+            Ok(())
+        }).unwrap();
+        assert_eq!((calls, timings.runs), (3, 3));
+        assert!(timings.max < Duration::from_secs(60));
+
+        let mut calls = 0;
+        let failed = Timings::measure(Duration::ZERO, 3, || {
+            calls += 1;
+            Err(io::Error::new(io::ErrorKind::InvalidData, "bad"))
+        });
+        assert_eq!((failed.is_err(), calls), (true, 1));
+    }
+}
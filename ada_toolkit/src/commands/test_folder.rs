@@ -0,0 +1,610 @@
+//! `test-folder`: round trips of every file in a folder.
+
+use std::fs::{self, read, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ada_toolkit::{compression, decompression};
+use rand::Rng;
+
+use crate::{context, Failure, FolderArgs, Format, Global, LogFormat, EXIT_USAGE};
+use crate::commands::dir::folder_files;
+use crate::commands::test::describe_mismatch;
+use crate::output::AtomicFile;
+use crate::progress::{saved, Units};
+use crate::report::{bytes_per_second, csv_field, json_string, json_strings, json_timing, ratio, write_failures};
+
+/// Sizes and timings of an in-memory round trip, or their sums over several.
+#[derive(Clone, Copy, Default)]
+pub struct FileTest {
+    pub original: u64,
+    pub compressed: u64,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+}
+
+impl FileTest {
+    fn add(&mut self, other: &FileTest) {
+        self.original += other.original;
+        self.compressed += other.compressed;
+        self.compress_time += other.compress_time;
+        self.decompress_time += other.decompress_time;
+    }
+
+    pub fn ratio(&self) -> f64 {
+        ratio(self.compressed, self.original)
+    }
+}
+
+/// How one file, or one directory's subtotal, appears in the console, the
+/// log and the JSON document.
+pub struct FolderRow<'a> {
+    pub folder: &'a str,
+    /// The file relative to `folder`, or `None` for a subtotal.
+    pub file: Option<&'a str>,
+    /// Files summed; 1 for a file.
+    pub files: usize,
+    pub test: FileTest,
+}
+
+impl FolderRow<'_> {
+    fn log_entry(&self, log_format: LogFormat, run: &str, timestamp: u64, units: Units) -> String {
+        let t = &self.test;
+        match (log_format, self.file) {
+            (LogFormat::Text, file) => format!(
+                "Timestamp: {}s\nFolder: {}\n{}\nOriginal Size: {}\nCompressed Size: {}\nRatio: {:.2} ({})\n\
+                 Compress Time: {:?}\nCompress Speed: {}\nDecompress Time: {:?}\nDecompress Speed: {}\n---",
+                timestamp, self.folder,
+                match file {
+                    Some(file) => format!("File: {}", file),
+                    None => format!("Subtotal: {} files", self.files),
+                },
+                units.size(t.original), units.size(t.compressed), t.ratio(), saved(t.ratio()),
+                t.compress_time, units.speed(t.original, t.compress_time), t.decompress_time,
+                units.speed(t.original, t.decompress_time)
+            ),
+            (LogFormat::Csv, file) => {
+                let status = if file.is_some() { "ok" } else { "subtotal" };
+                csv_row(timestamp, file.unwrap_or(""), t, status, self.folder)
+            }
+            (LogFormat::Jsonl, file) => {
+                jsonl_line(run, timestamp, if file.is_some() { "file" } else { "subtotal" }, &self.json())
+            }
+        }
+    }
+
+    pub fn json(&self) -> String {
+        let t = &self.test;
+        let name = match self.file {
+            Some(file) => format!(
+                "\"input\":{},\"status\":\"ok\"",
+                json_string(&Path::new(self.folder).join(file).to_string_lossy())
+            ),
+            None => format!("\"files\":{}", self.files),
+        };
+        format!("{{{},\"folder\":{},\"input_bytes\":{},\"compressed_bytes\":{},\"ratio\":{},{},{}}}",
+                name, json_string(self.folder), t.original, t.compressed, t.ratio(),
+                json_timing("compress_", t.original, t.compress_time),
+                json_timing("decompress_", t.original, t.decompress_time))
+    }
+}
+
+/// One line of the JSON-lines log: `object` with the run ID, timestamp and
+/// kind of record put in front of its own fields.
+fn jsonl_line(run: &str, timestamp: u64, record: &str, object: &str) -> String {
+    format!("{{\"run\":{},\"timestamp\":{},\"record\":\"{}\",{}",
+            json_string(run), timestamp, record, &object[1..])
+}
+
+/// One row of the CSV log.
+fn csv_row(timestamp: u64, file: &str, t: &FileTest, status: &str, folder: &str) -> String {
+    let secs = |d: Duration| d.as_secs_f64();
+    let mbps = |d: Duration| if secs(d) > 0.0 { t.original as f64 / secs(d) / 1e6 } else { 0.0 };
+    format!(
+        "{},{},{},{},{:.4},{:.3},{:.2},{:.3},{:.2},{},{}",
+        timestamp, csv_field(file), t.original, t.compressed, t.ratio(),
+        secs(t.compress_time) * 1000.0, mbps(t.compress_time),
+        secs(t.decompress_time) * 1000.0, mbps(t.decompress_time),
+        status, csv_field(folder)
+    )
+}
+
+/// Totals over every file of a test-folder run.
+#[derive(Default)]
+pub struct Summary {
+    pub files: usize,
+    pub failed: usize,
+    /// Entries left out by --include, --exclude and --skip-hidden.
+    filtered: usize,
+    /// Symlinks not followed and special files.
+    pub skipped: usize,
+    pub total: FileTest,
+    /// Sums of the per-file speeds in bytes per second, and how many
+    /// non-empty files they cover, for the per-file averages.
+    compress_speeds: f64,
+    decompress_speeds: f64,
+    speeds: usize,
+    /// The non-empty files with the lowest and highest ratio; the first
+    /// one wins a tie.
+    lowest: Option<(String, FileTest)>,
+    highest: Option<(String, FileTest)>,
+}
+
+impl Summary {
+    pub fn add(&mut self, path: &Path, test: &FileTest) {
+        self.files += 1;
+        self.total.add(test);
+        if test.original == 0 {
+            return;
+        }
+        self.speeds += 1;
+        self.compress_speeds += bytes_per_second(test.original, test.compress_time);
+        self.decompress_speeds += bytes_per_second(test.original, test.decompress_time);
+        let name = || path.to_string_lossy().into_owned();
+        if self.lowest.as_ref().is_none_or(|(_, low)| test.ratio() < low.ratio()) {
+            self.lowest = Some((name(), *test));
+        }
+        if self.highest.as_ref().is_none_or(|(_, high)| test.ratio() > high.ratio()) {
+            self.highest = Some((name(), *test));
+        }
+    }
+
+    /// Mean per-file compress and decompress speeds, in bytes per second.
+    fn average_speeds(&self) -> (f64, f64) {
+        match self.speeds {
+            0 => (0.0, 0.0),
+            n => (self.compress_speeds / n as f64, self.decompress_speeds / n as f64),
+        }
+    }
+
+    pub fn text(&self, units: Units, wall_time: Duration, threads: usize) -> String {
+        let t = &self.total;
+        let (compress_avg, decompress_avg) = self.average_speeds();
+        format!(
+            "Summary: {} files tested, {} failed, {} filtered out, {} skipped. {} to {}. Ratio: {:.2} ({})\n\
+             Compress: {} overall, {} average per file. Decompress: {} overall, {} average per file\n\
+             Lowest ratio: {}. Highest ratio: {}\n\
+             Took {:?} wall-clock on {} threads ({:?} summed per-file time)",
+            self.files, self.failed, self.filtered, self.skipped, units.size(t.original), units.size(t.compressed),
+            t.ratio(), saved(t.ratio()),
+            units.speed(t.original, t.compress_time), units.rate(compress_avg),
+            units.speed(t.original, t.decompress_time), units.rate(decompress_avg),
+            describe(&self.lowest), describe(&self.highest),
+            wall_time, threads, t.compress_time + t.decompress_time
+        )
+    }
+
+    fn log_entry(&self, log_format: LogFormat, run: &str, timestamp: u64, units: Units, wall_time: Duration,
+                 threads: usize) -> String {
+        let t = &self.total;
+        match log_format {
+            LogFormat::Text => {
+                let (compress_avg, decompress_avg) = self.average_speeds();
+                format!(
+                    "Summary\nTimestamp: {}s\nFiles: {}\nFailed: {}\nFiltered Out: {}\nSkipped: {}\n\
+                     Original Size: {}\nCompressed Size: {}\nRatio: {:.2} ({})\nCompress Time: {:?}\n\
+                     Compress Speed: {} overall, {} average per file\nDecompress Time: {:?}\n\
+                     Decompress Speed: {} overall, {} average per file\nLowest Ratio: {}\nHighest Ratio: {}\n\
+                     Wall-Clock Time: {:?}\n---",
+                    timestamp, self.files, self.failed, self.filtered, self.skipped, units.size(t.original),
+                    units.size(t.compressed),
+                    t.ratio(), saved(t.ratio()), t.compress_time, units.speed(t.original, t.compress_time),
+                    units.rate(compress_avg), t.decompress_time, units.speed(t.original, t.decompress_time),
+                    units.rate(decompress_avg), describe(&self.lowest), describe(&self.highest), wall_time
+                )
+            }
+            LogFormat::Csv => {
+                let mut rows = vec![csv_row(timestamp, "", t, "summary", "")];
+                for (status, file) in [("lowest_ratio", &self.lowest), ("highest_ratio", &self.highest)] {
+                    if let Some((name, test)) = file {
+                        rows.push(csv_row(timestamp, name, test, status, ""));
+                    }
+                }
+                rows.join("\n")
+            }
+            LogFormat::Jsonl => jsonl_line(run, timestamp, "summary", &self.json(wall_time, threads)),
+        }
+    }
+
+    pub fn json(&self, wall_time: Duration, threads: usize) -> String {
+        let t = &self.total;
+        let (compress_avg, decompress_avg) = self.average_speeds();
+        let entry = |file: &Option<(String, FileTest)>| match file {
+            Some((name, test)) => format!("{{\"input\":{},\"ratio\":{}}}", json_string(name), test.ratio()),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"files\":{},\"failed\":{},\"filtered\":{},\"skipped\":{},\"input_bytes\":{},\
+             \"compressed_bytes\":{},\"ratio\":{},{},{},\"average_compress_bytes_per_second\":{:.0},\
+             \"average_decompress_bytes_per_second\":{:.0},\"lowest_ratio\":{},\"highest_ratio\":{},\
+             \"threads\":{},\"wall_duration_ms\":{:.3}}}",
+            self.files, self.failed, self.filtered, self.skipped, t.original, t.compressed, t.ratio(),
+            json_timing("compress_", t.original, t.compress_time),
+            json_timing("decompress_", t.original, t.decompress_time),
+            compress_avg, decompress_avg, entry(&self.lowest), entry(&self.highest),
+            threads, wall_time.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// The ratio and name of a summary's lowest or highest file.
+pub fn describe(file: &Option<(String, FileTest)>) -> String {
+    match file {
+        Some((name, test)) => format!("{:.2} ({})", test.ratio(), name),
+        None => "none".to_string(),
+    }
+}
+
+/// Round-trips every file in `args.folders` in memory on up to
+/// `--threads` threads, and writes a log entry per file in sorted order
+/// whatever order they finish in. A file that fails, or a directory that
+/// cannot be read, is marked FAILED and the rest are still tested unless
+/// `--fail-fast` is given; the run fails at the end if any did. With
+/// `--recursive`, subdirectories are tested too and each directory gets a
+/// subtotal of the files directly in it.
+pub fn run_folder_test(args: &FolderArgs, global: &Global) -> Result<(), Failure> {
+    let mut status = global.status(false);
+    if let Some(missing) = args.folders.iter().find(|folder| !folder.is_dir()) {
+        let msg = format!("'{}' folder does not exist or is not a directory", missing.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+    }
+    let mut summary = Summary::default();
+    let mut jobs = Vec::new();
+    let mut unreadable = Vec::new();
+    for folder_path in &args.folders {
+        let mut walk = folder_files(folder_path, args.recursive, args.follow_symlinks, &args.filter);
+        unreadable.append(&mut walk.errors);
+        jobs.extend(walk.files.into_iter().map(|rel| (folder_path, rel)));
+        summary.filtered += walk.filtered.len();
+        summary.skipped += walk.skipped.len();
+    }
+    let mut first_failure = None;
+    let mut errors = Vec::new();
+    for failure in &unreadable {
+        first_failure.get_or_insert(failure.code);
+        summary.failed += 1;
+        errors.push(failure.error.to_string());
+        if global.format == Format::Text {
+            writeln!(status, "FAILED {}", failure.error)?;
+        }
+    }
+    // With --fail-fast, an unreadable directory stops the run before any file is tested.
+    let stopped = args.on_error.stops() && !unreadable.is_empty();
+    let dropped = if stopped { jobs.drain(..).count() } else { 0 };
+    let paths: Vec<PathBuf> = jobs.iter().map(|(folder_path, rel)| folder_path.join(rel)).collect();
+    let mut threads = global.threads().min(jobs.len()).max(1);
+    if let Some(limit) = global.max_memory {
+        // A round trip holds the file, its compressed form and the
+        // decompressed copy at once, so the biggest file sets the cost of
+        // each thread.
+        let largest = paths.iter().filter_map(|path| fs::metadata(path).ok()).map(|meta| meta.len()).max();
+        let per_thread = usize::try_from(largest.unwrap_or(0).saturating_mul(3)).unwrap_or(usize::MAX).max(1);
+        if per_thread > limit {
+            let msg = format!("testing the largest file needs {} bytes of memory, more than the --max-memory limit of {}",
+                              per_thread, limit);
+            return Err(Failure { code: EXIT_USAGE, error: io::Error::other(msg) });
+        }
+        threads = threads.min(limit / per_thread).max(1);
+    }
+    log::debug!("Testing {} files on {} threads (limit {})", jobs.len(), threads, global.threads());
+
+    let wall_start = Instant::now();
+    let results = round_trip_files(&paths, threads, args.on_error.stops());
+    let wall_time = wall_start.elapsed();
+
+    let units = global.units();
+    let run = format!("{:016x}", rand::thread_rng().gen::<u64>());
+    let mut log_entries = Vec::new();
+    let mut rows = Vec::new();
+    let mut subtotals: Vec<(PathBuf, usize, FileTest)> = Vec::new();
+    let mut not_run = dropped;
+    for (((folder_path, rel), path), result) in jobs.iter().zip(&paths).zip(results) {
+        let file_name = rel.to_string_lossy();
+        let folder = folder_path.to_string_lossy();
+        let Some(result) = result else {
+            not_run += 1;
+            continue;
+        };
+        let (test, timestamp) = match result {
+            Ok(tested) => tested,
+            Err(error) => {
+                let failure = Failure::from(error);
+                first_failure.get_or_insert(failure.code);
+                summary.files += 1;
+                summary.failed += 1;
+                errors.push(format!("{}: {}", path.display(), failure.error));
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                let json = format!(
+                    "{{\"input\":{},\"status\":\"failed\",\"folder\":{},\"exit_code\":{},\"error\":{}}}",
+                    json_string(&path.to_string_lossy()), json_string(&folder), failure.code,
+                    json_string(&failure.error.to_string()));
+                log_entries.push(match args.log_format {
+                    LogFormat::Text => format!("Timestamp: {}s\nFolder: {}\nFile: {}\nStatus: FAILED\nError: {}\n---",
+                                               timestamp, folder, file_name, failure.error),
+                    LogFormat::Csv => csv_row(timestamp, &file_name, &FileTest::default(), "failed", &folder),
+                    LogFormat::Jsonl => jsonl_line(&run, timestamp, "file", &json),
+                });
+                match global.format {
+                    Format::Text => writeln!(status, "FAILED {}: {}", file_name, failure.error)?,
+                    Format::Json => rows.push(json),
+                }
+                continue;
+            }
+        };
+        log::info!("Processed file {} ({} bytes)", file_name, test.original);
+        summary.add(path, &test);
+
+        let row = FolderRow {
+            folder: &folder,
+            file: Some(&file_name),
+            files: 1,
+            test,
+        };
+        log_entries.push(row.log_entry(args.log_format, &run, timestamp, units));
+        match global.format {
+            Format::Text => writeln!(status, "Tested {} successfully.", file_name)?,
+            Format::Json => rows.push(row.json()),
+        }
+
+        let dir = path.parent().unwrap_or(folder_path.as_path());
+        match subtotals.iter_mut().find(|(seen, _, _)| seen == dir) {
+            Some((_, files, total)) => {
+                *files += 1;
+                total.add(&test);
+            }
+            None => subtotals.push((dir.to_path_buf(), 1, test)),
+        }
+    }
+
+    let mut directories = Vec::new();
+    if args.recursive {
+        // Parents before their children, whatever order the files came in
+        subtotals.sort_by(|a, b| a.0.cmp(&b.0));
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        for (dir, files, total) in &subtotals {
+            let folder = dir.to_string_lossy();
+            let row = FolderRow { folder: &folder, file: None, files: *files, test: *total };
+            log_entries.push(row.log_entry(args.log_format, &run, timestamp, units));
+            match global.format {
+                Format::Text => writeln!(status,
+                    "Subtotal for {}: {} files, {} to {}. Ratio: {:.2} ({}). Compress {}, decompress {}",
+                    folder, files, units.size(total.original), units.size(total.compressed), total.ratio(),
+                    saved(total.ratio()), units.speed(total.original, total.compress_time),
+                    units.speed(total.original, total.decompress_time))?,
+                Format::Json => directories.push(row.json()),
+            }
+        }
+    }
+
+    if !jobs.is_empty() || !unreadable.is_empty() {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        log_entries.push(summary.log_entry(args.log_format, &run, timestamp, units, wall_time, threads));
+        if global.format == Format::Text {
+            writeln!(status, "{}", summary.text(units, wall_time, threads))?;
+            if stopped || not_run > 0 {
+                writeln!(status, "Stopped at the first failure (--fail-fast); {} files not tested", not_run)?;
+            }
+            write_failures(&mut status, &errors)?;
+        }
+    }
+
+    let log = !rows.is_empty() || !log_entries.is_empty();
+    if log {
+        write_log(&args.log_file, args.log_format, &log_entries, args.log_append)
+            .map_err(|e| context(e, "writing log", &args.log_file.to_string_lossy()))?;
+    }
+    let log_name = args.log_file.to_string_lossy();
+    let names: Vec<String> = args.folders.iter().map(|folder| format!("'{}'", folder.display())).collect();
+    match (global.format, log) {
+        (Format::Text, false) => writeln!(status, "No files found in {} ({} filtered out).", names.join(", "),
+                                          summary.filtered)?,
+        (Format::Text, true) => writeln!(status, "All tests complete. Log {} '{}'.",
+                                         if args.log_append { "appended to" } else { "written to" }, log_name)?,
+        (Format::Json, _) => writeln!(status,
+            "{{\"operation\":\"test-folder\",\"folders\":[{}],\"tested\":{},\"not_tested\":{},\"log\":{},\
+             \"files\":[{}],\"directories\":[{}],\"failures\":[{}],\"summary\":{}}}",
+            args.folders.iter().map(|folder| json_string(&folder.to_string_lossy())).collect::<Vec<_>>().join(","),
+            rows.len(), not_run, if log { json_string(&log_name) } else { "null".to_string() }, rows.join(","),
+            directories.join(","), json_strings(&errors), summary.json(wall_time, threads))?,
+    }
+
+    match first_failure {
+        Some(code) if summary.files == 0 => {
+            let msg = format!("nothing was tested; {} paths could not be read", summary.failed);
+            Err(Failure { code, error: io::Error::other(msg) })
+        }
+        Some(code) => {
+            // Paths that could not be read count as failed but were never tested.
+            let msg = format!("{} of {} files failed", summary.failed, summary.files + unreadable.len());
+            Err(Failure { code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Writes a test-folder run's log entries to `path`, replacing it or, with
+/// `append`, adding to its end. Appending opens the file in append mode and
+/// hands each JSON line, or a whole text or CSV run, to a single write, so
+/// runs logging to the same file at once never split each other's lines.
+/// A CSV header is only written to an empty file.
+fn write_log(path: &Path, log_format: LogFormat, entries: &[String], append: bool) -> io::Result<()> {
+    let header = "timestamp,file,original_size,compressed_size,ratio,compress_ms,compress_mbps,\
+                  decompress_ms,decompress_mbps,status,folder";
+    if !append {
+        let content = match log_format {
+            LogFormat::Text => entries.join("\n\n"),
+            LogFormat::Csv => format!("{}\n{}\n", header, entries.join("\n")),
+            LogFormat::Jsonl => entries.iter().map(|line| format!("{}\n", line)).collect(),
+        };
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(content.as_bytes())?;
+        return file.commit();
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    match log_format {
+        LogFormat::Text if empty => file.write_all(entries.join("\n\n").as_bytes()),
+        LogFormat::Text => file.write_all(format!("\n\n{}", entries.join("\n\n")).as_bytes()),
+        LogFormat::Csv if empty => file.write_all(format!("{}\n{}\n", header, entries.join("\n")).as_bytes()),
+        LogFormat::Csv => file.write_all(format!("{}\n", entries.join("\n")).as_bytes()),
+        LogFormat::Jsonl => entries.iter().try_for_each(|line| file.write_all(format!("{}\n", line).as_bytes())),
+    }
+}
+
+/// A file's round trip and the Unix second it finished.
+type Tested = io::Result<(FileTest, u64)>;
+
+/// Round-trips `paths` on `threads` workers, returning each file's result
+/// and finishing time in the order of `paths`. A failed file does not
+/// stop the others unless `fail_fast` is set, when files not yet started
+/// are left untested and come back as `None`.
+pub fn round_trip_files(paths: &[PathBuf], threads: usize, fail_fast: bool) -> Vec<Option<Tested>> {
+    round_trip_files_with(paths, threads, fail_fast, round_trip_file)
+}
+
+/// [`round_trip_files`] with `round_trip` testing each file, so tests can
+/// swap in one that damages the data on the way.
+fn round_trip_files_with(
+    paths: &[PathBuf],
+    threads: usize,
+    fail_fast: bool,
+    round_trip: impl Fn(&Path) -> io::Result<FileTest> + Sync,
+) -> Vec<Option<Tested>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let done: Vec<Vec<(usize, Tested)>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else { break };
+                let result = round_trip(path).map(|test| {
+                    // Timestamp for log (basic Unix seconds)
+                    (test, SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+                });
+                if fail_fast && result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                done.push((index, result));
+            }
+            done
+        })).collect();
+        workers.into_iter().map(|worker| worker.join().expect("test worker panicked")).collect()
+    });
+
+    let mut results: Vec<Option<Tested>> = paths.iter().map(|_| None).collect();
+    for (index, result) in done.into_iter().flatten() {
+        results[index] = Some(result);
+    }
+    results
+}
+
+/// Compresses and decompresses the file at `path` in memory, failing if
+/// the result differs.
+fn round_trip_file(path: &Path) -> io::Result<FileTest> {
+    let data = read(path)?;
+
+    // Compress
+    let compress_start = Instant::now();
+    let compressed = compression::compress(&data);
+    let compress_time = compress_start.elapsed();
+
+    // Decompress
+    let decompress_start = Instant::now();
+    let decompressed = decompression::decompress(&compressed)?;
+    let decompress_time = decompress_start.elapsed();
+
+    verify(&data, &decompressed)?;
+    Ok(FileTest {
+        original: data.len() as u64,
+        compressed: compressed.len() as u64,
+        compress_time,
+        decompress_time,
+    })
+}
+
+/// Fails with where `decompressed` first departs from `data`, if it does.
+fn verify(data: &[u8], decompressed: &[u8]) -> io::Result<()> {
+    if data != decompressed {
+        let msg = format!("decompression mismatch: {}", describe_mismatch(data, decompressed));
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use ada_toolkit::CompressOptions;
+
+    /// A round trip through a stored frame with no checksums and one byte
+    /// of it flipped, which decodes without complaint to the wrong data.
+    fn damaged_round_trip(path: &Path) -> io::Result<FileTest> {
+        let data = read(path)?;
+        let opts = CompressOptions { store_only: true, block_checksums: false, content_checksum: false,
+                                     ..CompressOptions::default() };
+        let mut compressed = compression::compress_with_options(&data, &opts).map_err(io::Error::from)?;
+        let middle = compressed.len() / 2;
+        compressed[middle] ^= 0x40;
+        let decompressed = decompression::decompress(&compressed)?;
+        verify(&data, &decompressed)?;
+        Ok(FileTest { original: data.len() as u64, compressed: compressed.len() as u64, ..FileTest::default() })
+    }
+
+    fn files(names: &[&str]) -> (TempDir, Vec<PathBuf>) {
+        let dir = TempDir::new();
+        let content: Vec<u8> = (0..4000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let paths = names.iter().map(|name| dir.write(name, &content)).collect();
+        (dir, paths)
+    }
+
+    #[test]
+    fn a_mismatch_is_an_error_naming_the_first_difference() {
+        let data = b"0123456789abcdef".to_vec();
+        let mut decompressed = data.clone();
+        decompressed[10] = b'A';
+        let err = verify(&data, &decompressed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "decompression mismatch: first difference at byte 10 (1 bytes differ): \
+                                     expected 32 33 34 35 36 37 38 39 [61] 62 63 64 65 66, \
+                                     got 32 33 34 35 36 37 38 39 [41] 62 63 64 65 66");
+        assert!(verify(&data, &data).is_ok());
+    }
+
+    #[test]
+    fn a_damaged_file_fails_and_the_others_are_still_tested() {
+        let (_dir, paths) = files(&["a", "b", "c", "d"]);
+        let damaged = paths[1].clone();
+        let round_trip = |path: &Path| if path == damaged { damaged_round_trip(path) } else { round_trip_file(path) };
+        for threads in [1, 3] {
+            let results = round_trip_files_with(&paths, threads, false, round_trip);
+            assert_eq!(results.len(), 4);
+            for (i, result) in results.iter().enumerate() {
+                let result = result.as_ref().expect("every file is tested");
+                match (i, result) {
+                    (1, Err(err)) => {
+                        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                        assert!(err.to_string().starts_with("decompression mismatch: first difference at byte "),
+                                "{}", err);
+                    }
+                    (1, Ok(_)) => panic!("the damaged file passed"),
+                    (_, result) => assert_eq!(result.as_ref().map(|(test, _)| test.original).ok(), Some(4000)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fail_fast_leaves_later_files_untested() {
+        let (_dir, paths) = files(&["e", "f", "g"]);
+        let results = round_trip_files_with(&paths, 1, true, damaged_round_trip);
+        assert!(results[0].as_ref().unwrap().is_err());
+        assert!(results[1..].iter().all(Option::is_none));
+    }
+}
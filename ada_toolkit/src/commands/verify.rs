@@ -0,0 +1,58 @@
+//! `verify`: checking frames without writing their content.
+
+use std::io::{self, Write};
+use std::time::Instant;
+
+use ada_toolkit::validate_reader;
+
+use crate::{Failure, Format, Global};
+use crate::output::open_input;
+use crate::report::{json_string, json_timing, ratio};
+
+/// Checks every block and frame checksum of each file, one block in memory
+/// at a time, printing OK or FAILED per file. Fails with the first failure's
+/// status if any file is bad.
+pub fn verify_files(files: &[String], global: &Global) -> Result<(), Failure> {
+    let mut status = global.status(false);
+    let mut results = Vec::new();
+    let mut durations = Vec::new();
+    for file in files {
+        let start = Instant::now();
+        let result = open_input(file).map_err(Failure::from).and_then(|input| {
+            validate_reader(input).map_err(|e| Failure::from(e).context("verifying", file))
+        });
+        durations.push(start.elapsed());
+        if global.format == Format::Text {
+            match &result {
+                Ok(info) => writeln!(status, "{}: OK ({} blocks, {} bytes uncompressed, checksum: {:?}) in {:?}",
+                                     file, info.block_count, info.content_size, info.checksum_type, start.elapsed())?,
+                Err(failure) => writeln!(status, "{}: FAILED ({})", file, failure.error)?,
+            }
+        }
+        results.push(result);
+    }
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if global.format == Format::Json {
+        let rows: Vec<String> = files.iter().zip(&results).zip(&durations)
+            .map(|((file, result), duration)| match result {
+                Ok(info) => format!(
+                    "{{\"input\":{},\"status\":\"ok\",\"blocks\":{},\"content_size\":{},\
+                     \"compressed_size\":{},\"ratio\":{},{}}}",
+                    json_string(file), info.block_count, info.content_size, info.compressed_size,
+                    ratio(info.compressed_size, info.content_size), json_timing("", info.content_size, *duration)),
+                Err(failure) => format!(
+                    "{{\"input\":{},\"status\":\"failed\",\"exit_code\":{},\"error\":{}}}",
+                    json_string(file), failure.code, json_string(&failure.error.to_string())),
+            })
+            .collect();
+        writeln!(status, "{{\"operation\":\"verify\",\"ok\":{},\"failed\":{},\"files\":[{}]}}",
+                 results.len() - failed, failed, rows.join(","))?;
+    }
+    match results.into_iter().find_map(Result::err) {
+        Some(first) => {
+            let msg = format!("{} of {} files failed verification", failed, files.len());
+            Err(Failure { code: first.code, error: io::Error::other(msg) })
+        }
+        None => Ok(()),
+    }
+}
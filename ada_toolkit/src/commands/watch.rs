@@ -0,0 +1,171 @@
+//! `watch`: compressing files as they appear in a directory.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant, SystemTime};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::{cancel_on_interrupt, context, Failure, Format, Global, WatchArgs, SUFFIX};
+use crate::commands::compress::{compress_file, FileReport, FileRun, Sniffed, Writing};
+use crate::commands::dir::{folder_files, Filter};
+use crate::progress::Meters;
+use crate::report::{file_json, file_line, json_string};
+
+/// How often `watch` checks whether changed files have settled.
+const WATCH_TICK: Duration = Duration::from_millis(100);
+
+/// A changed file `watch` is waiting on: when it last changed, and its
+/// size and modification time then.
+struct Settling {
+    since: Instant,
+    stamp: Option<(u64, Option<SystemTime>)>,
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    fs::metadata(path).ok().filter(|meta| meta.is_file()).map(|meta| (meta.len(), meta.modified().ok()))
+}
+
+/// Compresses each file created or modified under `args.dir` once it has
+/// stopped changing for `args.debounce`, until Ctrl+C. A failed file is
+/// reported and watching carries on.
+pub fn watch(args: &WatchArgs, global: &Global) -> Result<(), Failure> {
+    let (dir, out) = (args.dir.as_path(), args.output_dir.as_path());
+    args.tuning.options(global.max_memory)?;
+    let dir_canonical = fs::canonicalize(dir).map_err(|e| context(e, "watching", &dir.to_string_lossy()))?;
+    fs::create_dir_all(out).map_err(|e| context(e, "creating output directory", &out.to_string_lossy()))?;
+    let out_canonical = fs::canonicalize(out)?;
+
+    let (events, changes) = std::sync::mpsc::channel();
+    let notify_error = |e: notify::Error| context(io::Error::other(e), "watching", &dir.to_string_lossy());
+    let mut watcher = notify::recommended_watcher(events).map_err(notify_error)?;
+    watcher.watch(&dir_canonical, RecursiveMode::Recursive).map_err(notify_error)?;
+
+    let cancel = cancel_on_interrupt();
+    let meters = Meters::new(global.progress_mode());
+    let run = FileRun {
+        force: true,
+        rm: false,
+        tuning: &args.tuning,
+        incompressible: args.incompressible,
+        writing: Writing::default(),
+        max_memory: global.max_memory,
+        threads: global.threads(),
+        workers: 1,
+        wait: global.wait,
+        lone_input: false,
+        cancel: &cancel,
+        meters: &meters,
+    };
+    let mut status = meters.above(global.status(false));
+    let units = global.units();
+    global.report_threads(true);
+    log::info!("Watching {} for new and changed files", dir.display());
+
+    let mut pending: HashMap<PathBuf, Settling> = HashMap::new();
+    // Outputs this run wrote, which a changed input may replace without -f.
+    let mut written = HashSet::new();
+    let (mut compressed, mut failed) = (0usize, 0usize);
+    while !cancel.is_cancelled() {
+        match changes.recv_timeout(WATCH_TICK) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                let created = matches!(event.kind, EventKind::Create(_));
+                for path in event.paths {
+                    if path.starts_with(&out_canonical) {
+                        continue;
+                    }
+                    if created && path.is_dir() {
+                        // Files made in a new directory before it was being
+                        // watched raise no events of their own.
+                        for file in folder_files(&path, true, false, &Filter::default()).files {
+                            let file = path.join(file);
+                            let stamp = file_stamp(&file);
+                            pending.insert(file, Settling { since: Instant::now(), stamp });
+                        }
+                        continue;
+                    }
+                    let stamp = file_stamp(&path);
+                    pending.insert(path, Settling { since: Instant::now(), stamp });
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => log::warn!("Watching {}: {}", dir.display(), e),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let now = Instant::now();
+        let settled: Vec<PathBuf> = pending.iter()
+            .filter(|(_, settling)| now.duration_since(settling.since) >= args.debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            let settling = pending.remove(&path).expect("settled paths are pending");
+            let stamp = file_stamp(&path);
+            if stamp.is_none() {
+                continue;
+            }
+            if stamp != settling.stamp {
+                // Still being written: wait for it to settle again.
+                pending.insert(path, Settling { since: now, stamp });
+                continue;
+            }
+            let Ok(rel) = path.strip_prefix(&dir_canonical) else { continue };
+            let parents_kept = rel.ancestors().skip(1).filter(|parent| !parent.as_os_str().is_empty())
+                .all(|parent| args.filter.keeps(parent, true));
+            if !parents_kept || !args.filter.keeps(rel, false) {
+                log::debug!("Ignoring {}: filtered out", rel.display());
+                continue;
+            }
+            let input = dir.join(rel);
+            let mut output = out.join(rel).into_os_string();
+            output.push(SUFFIX);
+            let output = PathBuf::from(output);
+            if !args.force && !written.contains(&output) && fs::symlink_metadata(&output).is_ok() {
+                log::warn!("Leaving {} alone: {} already exists; use -f to overwrite", input.display(),
+                           output.display());
+                continue;
+            }
+            let result = match (input.to_str(), output.to_str()) {
+                (Some(input), Some(output)) => Path::new(output)
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .map_err(|e| context(e, "creating directory for", output).into())
+                    .and_then(|()| compress_file(input, output, &run)),
+                _ => {
+                    let msg = format!("{} is not valid UTF-8; skipping it", input.display());
+                    Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+                }
+            };
+            let (input, output) = (input.to_string_lossy(), output.to_string_lossy());
+            match &result {
+                Err(_) if cancel.is_cancelled() => break,
+                Ok(FileReport { sniffed: Some(Sniffed { kind, stored: false }), .. }) => {
+                    log::info!("Skipped {}: already compressed ({})", input, kind);
+                    continue;
+                }
+                Ok(_) => {
+                    compressed += 1;
+                    written.insert(PathBuf::from(output.as_ref()));
+                }
+                Err(_) => failed += 1,
+            }
+            match (&result, global.format) {
+                (_, Format::Json) => writeln!(status, "{}", file_json(&input, &output, &result, true))?,
+                (Ok(report), Format::Text) => {
+                    writeln!(status, "{}", file_line(&input, &output, report, true, false, units))?
+                }
+                (Err(failure), Format::Text) => writeln!(meters.above(io::stderr()), "Error: {}", failure.error)?,
+            }
+        }
+    }
+    match global.format {
+        Format::Text => writeln!(status, "Stopped watching {}: {} files compressed, {} failed",
+                                 dir.display(), compressed, failed)?,
+        Format::Json => writeln!(status, "{{\"operation\":\"watch\",\"dir\":{},\"compressed\":{},\"failed\":{}}}",
+                                 json_string(&dir.to_string_lossy()), compressed, failed)?,
+    }
+    Ok(())
+}
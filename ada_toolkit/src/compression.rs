@@ -1,43 +1,534 @@
-/// Compresses input data using Ada's Adaptive Pattern Compressor (AAPC) - RLE-only variant.
-///
-/// Breaks data into 256KB blocks, applies adaptive RLE for runs >=3.
-/// Literals conflicting with flags (254, 255) are escaped with 255.
-/// No dictionary in this version for simplicity and reliability.
-pub fn compress(data: &[u8]) -> Vec<u8> {
-    const BLOCK_SIZE: usize = 256 * 1024;
-    const MIN_RUN: usize = 3;
-
-    let mut output = Vec::with_capacity(data.len() / 2);
-    let block_count = (data.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
-    output.extend_from_slice(&(block_count as u32).to_be_bytes());
-
-    for block in data.chunks(BLOCK_SIZE) {
-        let mut i = 0;
-        let mut encoded = Vec::new();
-        while i < block.len() {
-            let mut run_len = 1;
-            let byte = block[i];
-            while i + run_len < block.len() && block[i + run_len] == byte && run_len < 255 {
-                run_len += 1;
-            }
-            if run_len >= MIN_RUN {
-                encoded.push(254);
-                encoded.push(run_len as u8);
-                encoded.push(byte);
-                i += run_len;
-            } else {
-                if byte == 254 || byte == 255 {
-                    encoded.push(255);
-                    encoded.push(byte);
-                } else {
-                    encoded.push(byte);
-                }
-                i += 1;
-            }
-        }
-        let comp_len = encoded.len() as u32;
-        output.extend_from_slice(&comp_len.to_be_bytes());
-        output.extend(encoded);
-    }
-    output
-}
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::mpsc;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use crate::cancel::{is_cancelled, CancelToken};
+use crate::checksum::{crc32, Crc32};
+use crate::error::CompressError;
+use crate::frame::{self, Header, Trailer, BLOCK_RLE, BLOCK_STORED};
+use crate::options::{blocks_in_flight, CompressOptions};
+use crate::pool::{self, PoolMark, Recycle};
+use crate::stats::{self, timed, CompressionStats, PhaseTimes, Progress, ProgressFn};
+
+const MIN_RUN: usize = 3;
+/// Longest run one token can hold.
+const MAX_RUN: usize = 255;
+
+/// Compresses input data using Ada's Adaptive Pattern Compressor (AAPC) - RLE-only variant.
+///
+/// Breaks data into 256KB blocks, applies adaptive RLE for runs >=3.
+/// Literals conflicting with flags (254, 255) are escaped with 255.
+/// No dictionary in this version for simplicity and reliability.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    encode_frame(data, &CompressOptions::default(), None).expect("default options never cancel").0
+}
+
+/// Like [`compress`], with caller-chosen settings.
+pub fn compress_with_options(data: &[u8], opts: &CompressOptions) -> Result<Vec<u8>, CompressError> {
+    Ok(compress_with_stats(data, opts)?.0)
+}
+
+/// Like [`compress_with_options`], also returning frame totals.
+pub fn compress_with_stats(
+    data: &[u8],
+    opts: &CompressOptions,
+) -> Result<(Vec<u8>, CompressionStats), CompressError> {
+    opts.validate()?;
+    encode_frame(data, opts, None)
+}
+
+/// Like [`compress_with_stats`], writing the frame into `output` in place
+/// of what it held, so that one buffer can be reused, with its capacity,
+/// across many calls.
+pub fn compress_into(
+    data: &[u8],
+    opts: &CompressOptions,
+    output: &mut Vec<u8>,
+) -> Result<CompressionStats, CompressError> {
+    opts.validate()?;
+    output.clear();
+    encode_frame_into(data, opts, output, None)
+}
+
+/// The most bytes [`compress_with_options`] writes for `len` bytes of input
+/// under `opts`, which must be valid. A block RLE would not shrink is
+/// stored as is, so a frame is never larger than its content and framing.
+pub fn compress_bound(len: usize, opts: &CompressOptions) -> usize {
+    if opts.small_frame_fits(len) {
+        // An RLE payload a byte shorter than the content may need a second
+        // byte for its length.
+        return len + frame::small_frame_overhead(len, opts.block_checksums || opts.content_checksum) + 1;
+    }
+    let header = Header::for_options(opts);
+    let blocks = len.div_ceil(opts.block_size);
+    header.len + blocks * header.block_header_len() + len + 1 + header.trailer_len()
+}
+
+/// Like [`compress_with_stats`], calling `progress` after every block.
+pub fn compress_with_progress(
+    data: &[u8],
+    opts: &CompressOptions,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<(Vec<u8>, CompressionStats), CompressError> {
+    opts.validate()?;
+    encode_frame(data, opts, Some(progress))
+}
+
+fn encode_frame(
+    data: &[u8],
+    opts: &CompressOptions,
+    progress: ProgressFn<'_>,
+) -> Result<(Vec<u8>, CompressionStats), CompressError> {
+    let mut output = Vec::with_capacity(data.len() / 2);
+    let stats = encode_frame_into(data, opts, &mut output, progress)?;
+    Ok((output, stats))
+}
+
+/// Appends the frame for `data` to `output`.
+fn encode_frame_into(
+    data: &[u8],
+    opts: &CompressOptions,
+    output: &mut Vec<u8>,
+    mut progress: ProgressFn<'_>,
+) -> Result<CompressionStats, CompressError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("encode_frame", input = data.len(), block_size = opts.block_size).entered();
+    if opts.small_frame_fits(data.len()) {
+        if is_cancelled(opts.cancel.as_ref()) {
+            return Err(CompressError::Cancelled);
+        }
+        let stats = encode_small_frame(data, opts, output);
+        stats::report(&mut progress, stats.input_bytes, stats.output_bytes, stats.blocks, stats.stored_blocks);
+        return Ok(stats);
+    }
+    let started = Instant::now();
+    let mut phases = PhaseTimes::default();
+    let pool_mark = PoolMark::now();
+    let header = Header::for_options(opts);
+    let start = output.len();
+    header.write(output);
+
+    let mut block_count = 0u32;
+    let mut stored_blocks = 0u32;
+    let mut consumed = 0u64;
+    let mut content_crc = Crc32::new();
+    let mut blocks = data.chunks(opts.block_size);
+    if opts.threads > 1 {
+        let emit = |block: &[u8], block_type: u8, crc: u32, encoded: &[u8]| {
+            frame::write_block_header(output, &header, block_type, encoded.len(), block.len(), crc);
+            output.extend_from_slice(encoded);
+            content_crc.combine(crc, block.len() as u64);
+            block_count += 1;
+            stored_blocks += u32::from(block_type == BLOCK_STORED);
+            consumed += block.len() as u64;
+            stats::report(&mut progress, consumed, (output.len() - start) as u64, block_count, stored_blocks);
+            Ok(())
+        };
+        let next = || Ok(blocks.next());
+        let in_flight = blocks_in_flight(opts.threads);
+        let crc = header.needs_block_crc();
+        phases += encode_in_order(opts.threads, in_flight, crc, opts.store_only, opts.cancel.as_ref(), next, emit)?;
+    } else {
+        for block in blocks {
+            if is_cancelled(opts.cancel.as_ref()) {
+                return Err(CompressError::Cancelled);
+            }
+            let crc = timed(&mut phases.checksum, || header.block_crc(block));
+            let block_type = timed(&mut phases.code, || {
+                encode_block_into(output, &header, block, crc, block_count, opts.store_only)
+            });
+            content_crc.combine(crc, block.len() as u64);
+            block_count += 1;
+            stored_blocks += u32::from(block_type == BLOCK_STORED);
+            consumed += block.len() as u64;
+            stats::report(&mut progress, consumed, (output.len() - start) as u64, block_count, stored_blocks);
+        }
+    }
+    let trailer = Trailer {
+        block_count,
+        content_size: data.len() as u64,
+        content_checksum: header.content_checksum().then(|| content_crc.finish()),
+    };
+    frame::write_trailer(output, &header, &trailer);
+    stats::report(&mut progress, consumed, (output.len() - start) as u64, block_count, stored_blocks);
+    Ok(CompressionStats {
+        input_bytes: data.len() as u64,
+        output_bytes: (output.len() - start) as u64,
+        blocks: block_count,
+        stored_blocks,
+        content_crc: header.needs_block_crc().then(|| content_crc.finish()),
+        pool: pool_mark.since(),
+        phases: PhaseTimes { wall: started.elapsed(), ..phases },
+    })
+}
+
+/// Appends `data`, which [`CompressOptions::small_frame_fits`], to `output`
+/// as a small frame.
+pub(crate) fn encode_small_frame(data: &[u8], opts: &CompressOptions, output: &mut Vec<u8>) -> CompressionStats {
+    let started = Instant::now();
+    let mut phases = PhaseTimes::default();
+    let pool_mark = PoolMark::now();
+    let start = output.len();
+    let mut payload = pool::take(data.len());
+    let block_type = timed(&mut phases.code, || encode_payload(data, 0, opts.store_only, &mut payload));
+    let crc = timed(&mut phases.checksum, || (opts.block_checksums || opts.content_checksum).then(|| crc32(data)));
+    frame::write_small_frame(output, block_type, data.len(), &payload, crc);
+    pool::give(payload);
+    let blocks = u32::from(!data.is_empty());
+    CompressionStats {
+        input_bytes: data.len() as u64,
+        output_bytes: (output.len() - start) as u64,
+        blocks,
+        stored_blocks: blocks * u32::from(block_type == BLOCK_STORED),
+        content_crc: crc,
+        pool: pool_mark.since(),
+        phases: PhaseTimes { wall: started.elapsed(), ..phases },
+    }
+}
+
+/// Appends block `index`, whose [`Header::block_crc`] is `crc`, header and
+/// payload, to `out`: the payload is encoded straight after a header whose
+/// type and length are filled in once it is done, so no buffer is needed
+/// for it. Returns the block type.
+pub(crate) fn encode_block_into(
+    out: &mut Vec<u8>,
+    header: &Header,
+    block: &[u8],
+    crc: u32,
+    index: u32,
+    store_only: bool,
+) -> u8 {
+    let at = out.len();
+    frame::write_block_header(out, header, BLOCK_RLE, 0, block.len(), crc);
+    let payload_at = out.len();
+    let block_type = encode_payload(block, index, store_only, out);
+    let comp_len = out.len() - payload_at;
+    frame::patch_block_header(out, at, block_type, comp_len);
+    block_type
+}
+
+/// Encodes block `index`, appending the payload to `encoded`, and returns
+/// the block type chosen: RLE, or stored when RLE would not make the block
+/// smaller or `store_only` is set.
+#[cfg_attr(not(any(feature = "tracing", feature = "log")), allow(unused_variables))]
+pub(crate) fn encode_payload(block: &[u8], index: u32, store_only: bool, encoded: &mut Vec<u8>) -> u8 {
+    #[cfg(feature = "tracing")]
+    let span = tracing::trace_span!(
+        "encode_block",
+        index,
+        input = block.len(),
+        output = tracing::field::Empty,
+        codec = tracing::field::Empty
+    )
+    .entered();
+
+    let start = encoded.len();
+    if !store_only {
+        encode_block(block, encoded);
+    }
+    let block_type = if !store_only && encoded.len() - start < block.len() {
+        BLOCK_RLE
+    } else {
+        #[cfg(feature = "tracing")]
+        if !store_only {
+            tracing::debug!(index, input = block.len(), "RLE did not shrink block; storing it");
+        }
+        encoded.truncate(start);
+        encoded.extend_from_slice(block);
+        BLOCK_STORED
+    };
+    #[cfg(feature = "tracing")]
+    span.record("output", encoded.len() - start).record("codec", frame::block_type_name(block_type));
+    #[cfg(feature = "log")]
+    log::debug!("encoded block {}: {} -> {} bytes ({})", index, block.len(), encoded.len() - start,
+                frame::block_type_name(block_type));
+    block_type
+}
+
+/// Encodes the blocks `next_block` yields on `threads` worker threads and
+/// hands each to `emit` with its block type, CRC-32 and payload, in the
+/// order they were read, so the frame comes out exactly as the sequential
+/// encoder writes it. The CRC is taken on the worker, and only with `crc`
+/// set; otherwise it is 0. At most `in_flight` blocks are read ahead of the
+/// last one emitted. `cancel` is checked before each block is read.
+/// Returns the time the workers spent encoding and taking checksums.
+pub(crate) fn encode_in_order<B, N, E>(
+    threads: usize,
+    in_flight: usize,
+    crc: bool,
+    store_only: bool,
+    cancel: Option<&CancelToken>,
+    mut next_block: N,
+    mut emit: E,
+) -> Result<PhaseTimes, CompressError>
+where
+    B: AsRef<[u8]> + Recycle + Send,
+    N: FnMut() -> Result<Option<B>, CompressError>,
+    E: FnMut(&[u8], u8, u32, &[u8]) -> Result<(), CompressError>,
+{
+    let (job_tx, job_rx) = mpsc::channel::<(u32, B)>();
+    let job_rx = Mutex::new(job_rx);
+    let (done_tx, done_rx) = mpsc::channel::<(u32, B, u8, u32, Vec<u8>, PhaseTimes)>();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let (job_rx, done_tx) = (&job_rx, done_tx.clone());
+            scope.spawn(move || loop {
+                // Ends once the sender is dropped and the queue is empty.
+                let job = job_rx.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                let Ok((index, block)) = job else { break };
+                let mut phases = PhaseTimes::default();
+                let mut encoded = pool::take(block.as_ref().len());
+                let block_type =
+                    timed(&mut phases.code, || encode_payload(block.as_ref(), index, store_only, &mut encoded));
+                let block_crc = timed(&mut phases.checksum, || if crc { crc32(block.as_ref()) } else { 0 });
+                if done_tx.send((index, block, block_type, block_crc, encoded, phases)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Only the workers' senders are left, so the channel closes if
+        // they all stop, as they do when one panics.
+        drop(done_tx);
+        let mut phases = PhaseTimes::default();
+        let mut run = || {
+            let mut read = 0u32;
+            let mut emitted = 0u32;
+            let mut input_done = false;
+            // Blocks encoded out of turn, until the ones before them are.
+            let mut waiting = BTreeMap::new();
+            loop {
+                while !input_done && ((read - emitted) as usize) < in_flight {
+                    if is_cancelled(cancel) {
+                        return Err(CompressError::Cancelled);
+                    }
+                    match next_block()? {
+                        Some(block) => {
+                            job_tx.send((read, block)).map_err(|_| worker_stopped())?;
+                            read += 1;
+                        }
+                        None => input_done = true,
+                    }
+                }
+                if read == emitted {
+                    return Ok(());
+                }
+                let (index, block, block_type, block_crc, encoded, worked) =
+                    done_rx.recv().map_err(|_| worker_stopped())?;
+                phases += worked;
+                waiting.insert(index, (block, block_type, block_crc, encoded));
+                while let Some((block, block_type, block_crc, encoded)) = waiting.remove(&emitted) {
+                    emit(block.as_ref(), block_type, block_crc, &encoded)?;
+                    block.recycle();
+                    pool::give(encoded);
+                    emitted += 1;
+                }
+            }
+        };
+        let result = run();
+        drop(job_tx);
+        result.map(|()| phases)
+    })
+}
+
+/// Error for the channel to or from the workers closing early. That only
+/// happens once a worker has panicked, and the scope rethrows the panic in
+/// its place.
+fn worker_stopped() -> CompressError {
+    CompressError::Io(io::Error::other("an encoder thread stopped"))
+}
+
+/// How many bytes `span`, which is not empty, starts with that equal its
+/// first. Eight bytes are compared at a time, by XOR with the first byte
+/// repeated, and the first differing byte of a word found from its trailing
+/// zero bits; the tail shorter than a word is compared byte by byte.
+fn run_length(span: &[u8]) -> usize {
+    let byte = span[0];
+    let pattern = u64::from_le_bytes([byte; 8]);
+    let mut words = span.chunks_exact(8);
+    let mut len = 0;
+    for word in &mut words {
+        let diff = u64::from_le_bytes(word.try_into().unwrap()) ^ pattern;
+        if diff != 0 {
+            return len + (diff.trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    len + words.remainder().iter().take_while(|&&b| b == byte).count()
+}
+
+/// How many bytes `block` starts with that encode as themselves: bytes
+/// below the 254 and 255 flags, up to the first that starts a run long
+/// enough to be a run token.
+fn literal_span(block: &[u8]) -> usize {
+    let mut run_start = 0;
+    for (i, &byte) in block.iter().enumerate() {
+        if byte >= 254 {
+            return i;
+        }
+        if byte != block[run_start] {
+            run_start = i;
+        } else if i + 1 - run_start == MIN_RUN {
+            return run_start;
+        }
+    }
+    block.len()
+}
+
+/// RLE-encodes one block, appending the payload to `encoded`.
+pub(crate) fn encode_block(block: &[u8], encoded: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < block.len() {
+        let literals = literal_span(&block[i..]);
+        if literals > 0 {
+            encoded.extend_from_slice(&block[i..i + literals]);
+            i += literals;
+            continue;
+        }
+        let byte = block[i];
+        let run_len = run_length(&block[i..(i + MAX_RUN).min(block.len())]);
+        if run_len >= MIN_RUN {
+            encoded.push(254);
+            encoded.push(run_len as u8);
+            encoded.push(byte);
+            i += run_len;
+        } else {
+            // A flag byte, the only other place a literal span stops
+            encoded.push(255);
+            encoded.push(byte);
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+    use crate::stream::AapcWriter;
+    use std::io::Write;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::thread::{self, ThreadId};
+
+    /// A block that panics when a worker thread reads it.
+    struct PanicsOnWorker(Vec<u8>, ThreadId);
+
+    impl AsRef<[u8]> for PanicsOnWorker {
+        fn as_ref(&self) -> &[u8] {
+            assert_eq!(thread::current().id(), self.1, "block read on a worker");
+            &self.0
+        }
+    }
+
+    impl Recycle for PanicsOnWorker {
+        fn recycle(self) {}
+    }
+
+    /// `encode_block` as it was before `run_length`, scanning a byte at a
+    /// time.
+    fn encode_bytewise(block: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut i = 0;
+        while i < block.len() {
+            let mut run_len = 1;
+            let byte = block[i];
+            while i + run_len < block.len() && block[i + run_len] == byte && run_len < MAX_RUN {
+                run_len += 1;
+            }
+            if run_len >= MIN_RUN {
+                encoded.extend([254, run_len as u8, byte]);
+                i += run_len;
+            } else {
+                if byte >= 254 {
+                    encoded.push(255);
+                }
+                encoded.push(byte);
+                i += 1;
+            }
+        }
+        encoded
+    }
+
+    #[test]
+    fn runs_end_at_the_first_different_byte() {
+        for len in 1..40 {
+            for end in 1..=len {
+                let mut span = vec![254u8; len];
+                span[end..].fill(7);
+                assert_eq!(run_length(&span), end, "a run of {} in {} bytes", end, len);
+            }
+        }
+        assert_eq!(run_length(&[0; MAX_RUN]), MAX_RUN);
+    }
+
+    #[test]
+    fn blocks_encode_as_a_byte_at_a_time() {
+        // Runs of every length up to past the longest token, of bytes that
+        // need escaping and not, broken by single bytes.
+        let mut state = 1u32;
+        let mut block = Vec::new();
+        for len in (1..600).chain((0..200).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as usize % 20 + 1
+        })) {
+            let byte = [0, 7, 254, 255][len % 4];
+            block.extend(std::iter::repeat_n(byte, len));
+            block.push(len as u8);
+        }
+        for start in 0..8 {
+            let mut encoded = Vec::new();
+            encode_block(&block[start..], &mut encoded);
+            assert_eq!(encoded, encode_bytewise(&block[start..]), "from byte {}", start);
+        }
+    }
+
+    #[test]
+    fn threaded_frame_matches_sequential() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| if i % 1000 < 600 { 0 } else { (i * 7) as u8 }).collect();
+        let opts = CompressOptions { block_size: 16 * 1024, ..CompressOptions::default() };
+        let sequential = compress_with_options(&data, &opts).unwrap();
+        for threads in [2, 3, 8] {
+            let threaded = compress_with_options(&data, &CompressOptions { threads, ..opts.clone() }).unwrap();
+            assert_eq!(threaded, sequential, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn shared_options_from_eight_threads() {
+        let opts = Arc::new(CompressOptions {
+            block_size: 8 * 1024,
+            filename: Some("shared.bin".to_string()),
+            ..CompressOptions::default()
+        });
+        let inputs: Vec<Vec<u8>> = (0..8u32)
+            .map(|t| (0..60_000u32).map(|i| if i % (700 + t * 50) < 400 { t as u8 } else { (i * (t + 3)) as u8 }).collect())
+            .collect();
+        let expected: Vec<Vec<u8>> = inputs.iter().map(|data| compress_with_options(data, &opts).unwrap()).collect();
+        thread::scope(|scope| {
+            for (data, frame) in inputs.iter().zip(&expected) {
+                let opts = Arc::clone(&opts);
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        assert_eq!(&compress_with_options(data, &opts).unwrap(), frame);
+                        let mut writer = AapcWriter::with_options(Vec::new(), &opts).unwrap();
+                        writer.write_all(data).unwrap();
+                        assert_eq!(&writer.finish().unwrap(), frame);
+                    }
+                    assert_eq!(&decompress(frame).unwrap(), data);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn worker_panic_is_rethrown() {
+        let main = thread::current().id();
+        let mut blocks = (0..8).map(|_| PanicsOnWorker(vec![7; 100], main));
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            encode_in_order(2, 4, true, false, None, || Ok(blocks.next()), |_, _, _, _| Ok(()))
+        }));
+        assert!(result.is_err(), "a panicking worker must not leave the encoder waiting");
+    }
+}
@@ -1,38 +1,731 @@
-/// Decompresses data compressed with AAPC - RLE-only variant.
-///
-/// Reverses per-block RLE and escaped literals.
-pub fn decompress(compressed: &[u8]) -> Vec<u8> {
-    let mut output = Vec::with_capacity(compressed.len() * 2);
-    let mut idx = 0;
-    let block_count = u32::from_be_bytes(compressed[idx..idx + 4].try_into().unwrap());
-    idx += 4;
-
-    for _ in 0..block_count {
-        let comp_len = u32::from_be_bytes(compressed[idx..idx + 4].try_into().unwrap()) as usize;
-        idx += 4;
-        let block_end = idx + comp_len;
-
-        while idx < block_end {
-            let flag = compressed[idx];
-            idx += 1;
-
-            if flag == 255 {
-                // Escaped literal
-                let byte = compressed[idx];
-                idx += 1;
-                output.push(byte);
-            } else if flag == 254 {
-                // RLE
-                let run_len = compressed[idx] as usize;
-                idx += 1;
-                let byte = compressed[idx];
-                idx += 1;
-                output.resize(output.len() + run_len, byte);
-            } else {
-                // Normal literal
-                output.push(flag);
-            }
-        }
-    }
-    output
-}
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::sync::{Mutex, PoisonError};
+
+use crate::cancel::{is_cancelled, CancelToken};
+use crate::checksum::{crc32, Crc32};
+use crate::error::DecompressError;
+use crate::frame::{self, BlockHeader, FrameInfo, FrameStart, Header, SmallFrame, BLOCK_STORED};
+use crate::options::DecompressOptions;
+use crate::pool;
+use crate::stats::{self, Progress, ProgressFn};
+
+/// Most bytes one RLE payload byte can decode to: a three-byte run token
+/// expands to 255.
+const MAX_EXPANSION: usize = 255 / 3;
+
+/// Decompresses data compressed with AAPC - RLE-only variant.
+///
+/// Reverses per-block RLE and escaped literals.
+pub fn decompress(compressed: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    decompress_limited(compressed, usize::MAX)
+}
+
+/// Like [`decompress`], but fails with `LimitExceeded` instead of producing
+/// more than `max_size` bytes.
+pub fn decompress_limited(compressed: &[u8], max_size: usize) -> Result<Vec<u8>, DecompressError> {
+    decode_frame(compressed, max_size, None, None)
+}
+
+/// Like [`decompress`], writing the output into `output` in place of what
+/// it held, and returning its length. Room for the sizes the block headers
+/// declare is reserved before anything is decoded, so a buffer reused
+/// across calls with enough capacity is never reallocated.
+pub fn decompress_into(compressed: &[u8], output: &mut Vec<u8>) -> Result<usize, DecompressError> {
+    output.clear();
+    decode_frame_into(compressed, usize::MAX, None, None, output)?;
+    Ok(output.len())
+}
+
+/// Like [`decompress`], but writes each block to `writer` as soon as it is
+/// decoded and checked, reusing one block's buffer, so memory stays around
+/// one block whatever the frame's size. Returns the bytes written. For a
+/// frame that is not all in memory, see
+/// [`copy_decode`](crate::stream::copy_decode).
+///
+/// A block that fails its checksum is not written; a bad frame checksum
+/// is only found once every block has been.
+pub fn decompress_to_writer<W: Write>(compressed: &[u8], mut writer: W) -> Result<u64, DecompressError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("decode_frame", input = compressed.len()).entered();
+    let mut emit = |block: &mut Vec<u8>| {
+        writer.write_all(block)?;
+        block.clear();
+        Ok(())
+    };
+    let written = match frame::parse_start(compressed)? {
+        FrameStart::Small(small) => decode_small(compressed, &small, usize::MAX, None, None, &mut Vec::new(), &mut emit)?,
+        FrameStart::Full(header) => {
+            let mut block = pool::take(header.block_size);
+            let written = decode_blocks(compressed, &header, usize::MAX, None, None, &mut block, &mut emit);
+            pool::give(block);
+            written?
+        }
+    };
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Like [`decompress`], calling `progress` after every block.
+pub fn decompress_with_progress(
+    compressed: &[u8],
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Vec<u8>, DecompressError> {
+    decode_frame(compressed, usize::MAX, None, Some(progress))
+}
+
+/// Like [`decompress`], checking `opts.cancel` before each block and, with
+/// `opts.threads` above one, decoding blocks on that many threads straight
+/// into their places in the output, which the block table gives. Each
+/// block's checksum is checked by the thread that decoded it; the output,
+/// and the error for a damaged frame, are the same as decoding in order.
+/// `opts.max_memory` does not apply, since the whole output is returned.
+pub fn decompress_with_options(compressed: &[u8], opts: &DecompressOptions) -> Result<Vec<u8>, DecompressError> {
+    match opts.threads {
+        0 | 1 => decode_frame(compressed, usize::MAX, opts.cancel.as_ref(), None),
+        threads => decode_frame_threaded(compressed, threads, opts.cancel.as_ref()),
+    }
+}
+
+fn decode_frame(
+    compressed: &[u8],
+    max_size: usize,
+    cancel: Option<&CancelToken>,
+    progress: ProgressFn<'_>,
+) -> Result<Vec<u8>, DecompressError> {
+    let mut output = Vec::new();
+    decode_frame_into(compressed, max_size, cancel, progress, &mut output)?;
+    Ok(output)
+}
+
+/// Decodes the frame into the empty `output`, which is grown once, to the
+/// size the frame declares or `max_size` if that is less.
+fn decode_frame_into(
+    compressed: &[u8],
+    max_size: usize,
+    cancel: Option<&CancelToken>,
+    progress: ProgressFn<'_>,
+    output: &mut Vec<u8>,
+) -> Result<(), DecompressError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("decode_frame", input = compressed.len()).entered();
+    let header = match frame::parse_start(compressed)? {
+        FrameStart::Full(header) => header,
+        FrameStart::Small(small) => {
+            decode_small(compressed, &small, max_size, cancel, progress, output, &mut |_| Ok(()))?;
+            return Ok(());
+        }
+    };
+    output.reserve_exact(declared_size(compressed, &header).min(max_size));
+    decode_blocks(compressed, &header, max_size, cancel, progress, output, &mut |_| Ok(()))?;
+    Ok(())
+}
+
+/// Decodes the blocks of the frame `header` starts, appending each to
+/// `output` and passing `output` to `emit` once the block is checked, then
+/// checks the trailer. `emit` may take the output away, so `max_size` and
+/// the sizes reported count what every block added, which is also what
+/// this returns.
+fn decode_blocks(
+    compressed: &[u8],
+    header: &Header,
+    max_size: usize,
+    cancel: Option<&CancelToken>,
+    mut progress: ProgressFn<'_>,
+    output: &mut Vec<u8>,
+    emit: &mut dyn FnMut(&mut Vec<u8>) -> Result<(), DecompressError>,
+) -> Result<u64, DecompressError> {
+    let mut idx = header.len;
+    let mut produced = 0u64;
+    let mut block_count = 0u32;
+    let mut stored_blocks = 0u32;
+    let mut content_crc = header.content_checksum().then(Crc32::new);
+
+    while let Some(block) = frame::parse_block_header(compressed, idx, header)? {
+        if is_cancelled(cancel) {
+            return Err(DecompressError::Cancelled);
+        }
+        idx += header.block_header_len();
+        let payload = compressed
+            .get(idx..idx + block.comp_len)
+            .ok_or(DecompressError::Truncated { offset: compressed.len() })?;
+        if produced + block.raw_len as u64 > max_size as u64 {
+            return Err(DecompressError::LimitExceeded { limit: max_size });
+        }
+        let start = output.len();
+        decode_payload(&block, block_count, payload, idx, output)?;
+        block.verify(block_count, &output[start..])?;
+        if let Some(crc) = &mut content_crc {
+            crc.update(&output[start..]);
+        }
+        emit(output)?;
+        idx += block.comp_len;
+        produced += block.raw_len as u64;
+        block_count += 1;
+        stored_blocks += u32::from(block.block_type == BLOCK_STORED);
+        stats::report(&mut progress, idx as u64, produced, block_count, stored_blocks);
+    }
+    idx += 1;
+
+    let trailer = frame::parse_trailer(compressed, idx, header)?;
+    trailer.verify(block_count, produced, content_crc.as_ref().map(Crc32::finish), idx)?;
+    let frame_len = idx + header.trailer_len();
+    stats::report(&mut progress, frame_len as u64, produced, block_count, stored_blocks);
+    Ok(produced)
+}
+
+/// [`decode_blocks`] for a small frame, whose one block is all there is to
+/// check.
+fn decode_small(
+    compressed: &[u8],
+    small: &SmallFrame,
+    max_size: usize,
+    cancel: Option<&CancelToken>,
+    mut progress: ProgressFn<'_>,
+    output: &mut Vec<u8>,
+    emit: &mut dyn FnMut(&mut Vec<u8>) -> Result<(), DecompressError>,
+) -> Result<u64, DecompressError> {
+    if is_cancelled(cancel) {
+        return Err(DecompressError::Cancelled);
+    }
+    let block = small.block;
+    if block.raw_len > max_size {
+        return Err(DecompressError::LimitExceeded { limit: max_size });
+    }
+    let start = output.len();
+    output.reserve_exact(block.raw_len);
+    decode_payload(&block, 0, &compressed[small.payload_at..][..block.comp_len], small.payload_at, output)?;
+    block.verify(0, &output[start..])?;
+    emit(output)?;
+    let blocks = u32::from(block.raw_len > 0);
+    let stored_blocks = blocks * u32::from(block.block_type == BLOCK_STORED);
+    stats::report(&mut progress, small.len as u64, block.raw_len as u64, blocks, stored_blocks);
+    Ok(block.raw_len as u64)
+}
+
+/// The output the block headers of `compressed` add up to, read without
+/// decoding anything. Blocks are counted up to the first that cannot be
+/// read or says it decodes to more than its payload can give, so a damaged
+/// table cannot declare more than a small multiple of the input.
+fn declared_size(compressed: &[u8], header: &Header) -> usize {
+    let mut idx = header.len;
+    let mut total = 0usize;
+    while let Ok(Some(block)) = frame::parse_block_header(compressed, idx, header) {
+        if block.block_type != BLOCK_STORED && block.raw_len > block.comp_len.saturating_mul(MAX_EXPANSION) {
+            break;
+        }
+        total = total.saturating_add(block.raw_len);
+        idx += header.block_header_len() + block.comp_len;
+    }
+    total
+}
+
+/// [`decode_frame`] on `threads` threads. The block table is read first,
+/// up to the first block that cannot be decoded or a table error; the
+/// blocks before that are decoded in parallel, and an error in one of them
+/// comes before the one that ended the table.
+fn decode_frame_threaded(
+    compressed: &[u8],
+    threads: usize,
+    cancel: Option<&CancelToken>,
+) -> Result<Vec<u8>, DecompressError> {
+    if frame::is_small_frame(compressed) {
+        return decode_frame(compressed, usize::MAX, cancel, None);
+    }
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("decode_frame", input = compressed.len(), threads).entered();
+    let header = frame::parse_header(compressed)?;
+    let mut blocks = Vec::new();
+    let mut idx = header.len;
+    let table_end = loop {
+        let block = match frame::parse_block_header(compressed, idx, &header) {
+            Ok(Some(block)) => block,
+            Ok(None) => break Ok(idx + 1),
+            Err(e) => break Err(e),
+        };
+        idx += header.block_header_len();
+        let Some(payload) = compressed.get(idx..idx + block.comp_len) else {
+            break Err(DecompressError::Truncated { offset: compressed.len() });
+        };
+        // A block declaring more output than its payload can give fails
+        // to decode anyway; decoding it here, for the same error, keeps a
+        // damaged table from sizing the output.
+        if block.block_type != BLOCK_STORED && block.raw_len > block.comp_len.saturating_mul(MAX_EXPANSION) {
+            let mut decoded = Vec::new();
+            let index = blocks.len() as u32;
+            break Err(decode_payload(&block, index, payload, idx, &mut decoded).err().unwrap_or(
+                DecompressError::Corrupt { offset: idx + block.comp_len, reason: "block shorter than declared" },
+            ));
+        }
+        blocks.push((block, idx));
+        idx += block.comp_len;
+    };
+
+    let mut output = vec![0u8; blocks.iter().map(|(block, _)| block.raw_len).sum()];
+    let mut jobs = Vec::with_capacity(blocks.len());
+    let mut rest = output.as_mut_slice();
+    for (index, &(block, payload_at)) in blocks.iter().enumerate() {
+        let (range, after) = rest.split_at_mut(block.raw_len);
+        jobs.push((index as u32, block, payload_at, range));
+        rest = after;
+    }
+    let jobs = Mutex::new(jobs.into_iter());
+    // The first block to fail, by index rather than by time.
+    let failed: Mutex<Option<(u32, DecompressError)>> = Mutex::new(None);
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(blocks.len()) {
+            scope.spawn(|| {
+                let mut decoded = pool::take(0);
+                loop {
+                    let job = jobs.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let Some((index, block, payload_at, range)) = job else { break };
+                    // Blocks after one that failed are never looked at.
+                    let failed_at = failed.lock().unwrap_or_else(PoisonError::into_inner).as_ref().map(|(at, _)| *at);
+                    if failed_at.is_some_and(|at| at < index) {
+                        continue;
+                    }
+                    let result = if is_cancelled(cancel) {
+                        Err(DecompressError::Cancelled)
+                    } else {
+                        decoded.clear();
+                        let payload = &compressed[payload_at..payload_at + block.comp_len];
+                        decode_payload(&block, index, payload, payload_at, &mut decoded)
+                            .and_then(|()| block.verify(index, &decoded))
+                    };
+                    match result {
+                        Ok(()) => range.copy_from_slice(&decoded),
+                        Err(e) => {
+                            let mut failed = failed.lock().unwrap_or_else(PoisonError::into_inner);
+                            if failed.as_ref().is_none_or(|(at, _)| index < *at) {
+                                *failed = Some((index, e));
+                            }
+                        }
+                    }
+                }
+                pool::give(decoded);
+            });
+        }
+    });
+    if let Some((_, e)) = failed.into_inner().unwrap_or_else(PoisonError::into_inner) {
+        return Err(e);
+    }
+    let idx = table_end?;
+
+    let trailer = frame::parse_trailer(compressed, idx, &header)?;
+    let content_crc = header.content_checksum().then(|| crc32(&output));
+    trailer.verify(blocks.len() as u32, output.len() as u64, content_crc, idx)?;
+    Ok(output)
+}
+
+/// Decompresses `compressed`, handing the output to `visit` in pieces instead
+/// of collecting it.
+///
+/// Literal spans are passed straight from the compressed buffer; runs are
+/// expanded into a small stack buffer, so no chunk is larger than a block and
+/// nothing is allocated. Returning `Break` stops decoding immediately and is
+/// passed back as `Ok(Break(()))`.
+///
+/// Checksums are verified as each block ends, so `visit` may already have
+/// seen the bytes of a block that then fails with `ChecksumMismatch`.
+pub fn decompress_visit<F>(compressed: &[u8], mut visit: F) -> Result<ControlFlow<()>, DecompressError>
+where
+    F: FnMut(&[u8]) -> ControlFlow<()>,
+{
+    let header = match frame::parse_start(compressed)? {
+        FrameStart::Full(header) => header,
+        FrameStart::Small(small) => {
+            let payload = &compressed[small.payload_at..][..small.block.comp_len];
+            let (flow, crc) = visit_payload(&small.block, payload, small.payload_at, &mut visit)?;
+            if flow.is_continue() {
+                small.block.verify_crc(0, crc)?;
+            }
+            return Ok(flow);
+        }
+    };
+    let mut idx = header.len;
+    let mut block_count = 0u32;
+    let mut content_size = 0u64;
+    let mut content_crc = Crc32::new();
+
+    while let Some(block) = frame::parse_block_header(compressed, idx, &header)? {
+        idx += header.block_header_len();
+        let payload = compressed
+            .get(idx..idx + block.comp_len)
+            .ok_or(DecompressError::Truncated { offset: compressed.len() })?;
+        let (flow, block_crc) = visit_payload(&block, payload, idx, &mut visit)?;
+        if flow.is_break() {
+            return Ok(flow);
+        }
+        block.verify_crc(block_count, block_crc)?;
+        content_crc.combine(block_crc, block.raw_len as u64);
+        idx += block.comp_len;
+        block_count += 1;
+        content_size += block.raw_len as u64;
+    }
+    idx += 1;
+
+    let trailer = frame::parse_trailer(compressed, idx, &header)?;
+    let content_crc = header.content_checksum().then(|| content_crc.finish());
+    trailer.verify(block_count, content_size, content_crc, idx)?;
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Passes what `block`, with `payload` at `base`, decodes to, to `visit`,
+/// returning whether it asked to stop and the CRC-32 of what it was given.
+fn visit_payload(
+    block: &BlockHeader,
+    payload: &[u8],
+    base: usize,
+    visit: &mut dyn FnMut(&[u8]) -> ControlFlow<()>,
+) -> Result<(ControlFlow<()>, u32), DecompressError> {
+    let mut crc = Crc32::new();
+    let mut visit_chunk = |chunk: &[u8]| {
+        crc.update(chunk);
+        visit(chunk)
+    };
+    let flow = match block.block_type {
+        BLOCK_STORED if payload.is_empty() => ControlFlow::Continue(()),
+        BLOCK_STORED => visit_chunk(payload),
+        _ => visit_block(payload, block.raw_len, base, &mut visit_chunk)?,
+    };
+    Ok((flow, crc.finish()))
+}
+
+/// Checks that `compressed` is a well-formed frame whose lengths and
+/// checksums all match, without keeping any decoded output.
+///
+/// Memory use does not depend on the decompressed size: runs are expanded
+/// into a small stack buffer only to feed the checksums.
+pub fn validate(compressed: &[u8]) -> Result<FrameInfo, DecompressError> {
+    let _ = decompress_visit(compressed, |_| ControlFlow::Continue(()))?;
+    FrameInfo::parse(compressed)
+}
+
+/// Walks one RLE payload like [`decode_block`], passing each literal span or
+/// expanded run to `visit` instead of appending it.
+fn visit_block(
+    payload: &[u8],
+    raw_len: usize,
+    base: usize,
+    visit: &mut dyn FnMut(&[u8]) -> ControlFlow<()>,
+) -> Result<ControlFlow<()>, DecompressError> {
+    // Offset in the frame of the first byte of `rest`.
+    let at = |rest: &[u8]| base + payload.len() - rest.len();
+    let mut run = [0u8; 255];
+    let mut produced = 0;
+    let mut rest = payload;
+
+    loop {
+        let (chunk, tail) = match rest {
+            [] => break,
+            [255, byte, tail @ ..] => {
+                // Escaped literal
+                if produced == raw_len {
+                    return Err(DecompressError::Corrupt { offset: at(tail), reason: "literal overflows block" });
+                }
+                (std::slice::from_ref(byte), tail)
+            }
+            &[254, run_len, byte, ref tail @ ..] => {
+                // RLE
+                let run_len = run_len as usize;
+                if run_len > raw_len - produced {
+                    return Err(DecompressError::Corrupt { offset: at(tail), reason: "run overflows block" });
+                }
+                run[..run_len].fill(byte);
+                (&run[..run_len], tail)
+            }
+            [254 | 255, ..] => return Err(DecompressError::Truncated { offset: base + payload.len() }),
+            _ => {
+                // Normal literals, up to the next flag
+                let (literals, tail) = rest.split_at(literal_len(rest));
+                if literals.len() > raw_len - produced {
+                    let offset = at(rest) + (raw_len - produced) + 1;
+                    return Err(DecompressError::Corrupt { offset, reason: "literal overflows block" });
+                }
+                (literals, tail)
+            }
+        };
+        produced += chunk.len();
+        if !chunk.is_empty() && visit(chunk).is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+        rest = tail;
+    }
+    if produced != raw_len {
+        let offset = base + payload.len();
+        return Err(DecompressError::Corrupt { offset, reason: "block shorter than declared" });
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Decodes the payload of block `index`, whatever its type, appending
+/// `block.raw_len` bytes to `output`.
+#[cfg_attr(not(any(feature = "tracing", feature = "log")), allow(unused_variables))]
+pub(crate) fn decode_payload(
+    block: &BlockHeader,
+    index: u32,
+    payload: &[u8],
+    base: usize,
+    output: &mut Vec<u8>,
+) -> Result<(), DecompressError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::trace_span!(
+        "decode_block",
+        index,
+        input = block.comp_len,
+        output = block.raw_len,
+        codec = frame::block_type_name(block.block_type)
+    )
+    .entered();
+    #[cfg(feature = "log")]
+    log::debug!("decoding block {}: {} -> {} bytes ({})", index, block.comp_len, block.raw_len,
+                frame::block_type_name(block.block_type));
+
+    match block.block_type {
+        BLOCK_STORED => {
+            output.extend_from_slice(payload);
+            Ok(())
+        }
+        _ => decode_block(payload, block.raw_len, base, output),
+    }
+}
+
+/// Decodes one RLE payload, appending exactly `raw_len` bytes to `output`.
+///
+/// `base` is the payload's offset in the frame, used for error reporting.
+pub(crate) fn decode_block(
+    payload: &[u8],
+    raw_len: usize,
+    base: usize,
+    output: &mut Vec<u8>,
+) -> Result<(), DecompressError> {
+    let block_end = output.len() + raw_len;
+    // Offset in the frame of the first byte of `rest`.
+    let at = |rest: &[u8]| base + payload.len() - rest.len();
+    let mut rest = payload;
+
+    // Each arm matches all the bytes its token needs at once, so nothing
+    // inside it is bounds-checked again.
+    loop {
+        rest = match rest {
+            [] => break,
+            &[255, byte, ref tail @ ..] => {
+                // Escaped literal
+                if output.len() == block_end {
+                    return Err(DecompressError::Corrupt { offset: at(tail), reason: "literal overflows block" });
+                }
+                output.push(byte);
+                tail
+            }
+            &[254, run_len, byte, ref tail @ ..] => {
+                // RLE
+                let run_len = run_len as usize;
+                if run_len > block_end - output.len() {
+                    return Err(DecompressError::Corrupt { offset: at(tail), reason: "run overflows block" });
+                }
+                output.resize(output.len() + run_len, byte);
+                tail
+            }
+            [254 | 255, ..] => return Err(DecompressError::Truncated { offset: base + payload.len() }),
+            _ => {
+                // Normal literals, copied up to the next flag
+                let (literals, tail) = rest.split_at(literal_len(rest));
+                let room = block_end - output.len();
+                if literals.len() > room {
+                    let offset = at(rest) + room + 1;
+                    return Err(DecompressError::Corrupt { offset, reason: "literal overflows block" });
+                }
+                output.extend_from_slice(literals);
+                tail
+            }
+        };
+    }
+    if output.len() != block_end {
+        let offset = base + payload.len();
+        return Err(DecompressError::Corrupt { offset, reason: "block shorter than declared" });
+    }
+    Ok(())
+}
+
+/// How many bytes `span` starts with that are plain literals, below the 254
+/// and 255 flags. Eight bytes are checked at a time: masking off each byte's
+/// low bit and inverting leaves zero exactly in the flag bytes, and the
+/// first zero byte of a word is found from its trailing zero bits; the tail
+/// shorter than a word is checked byte by byte.
+fn literal_len(span: &[u8]) -> usize {
+    const LOW: u64 = u64::from_le_bytes([0x01; 8]);
+    const HIGH: u64 = u64::from_le_bytes([0x80; 8]);
+    const FLAG_BITS: u64 = u64::from_le_bytes([0xFE; 8]);
+    let mut words = span.chunks_exact(8);
+    let mut len = 0;
+    for word in &mut words {
+        let not_flag = !u64::from_le_bytes(word.try_into().unwrap()) & FLAG_BITS;
+        let flags = not_flag.wrapping_sub(LOW) & !not_flag & HIGH;
+        if flags != 0 {
+            return len + (flags.trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    len + words.remainder().iter().take_while(|&&b| b < 254).count()
+}
+
+/// What an RLE payload is made of, for seeing why a block compressed as
+/// it did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenCounts {
+    /// Run tokens (254, length, byte), three payload bytes each.
+    pub runs: u64,
+    /// Uncompressed bytes the runs expand to.
+    pub run_bytes: u64,
+    /// Bytes copied as they are, one payload byte each.
+    pub literals: u64,
+    /// Literal 254 or 255 bytes escaped with 255, two payload bytes each.
+    pub escapes: u64,
+}
+
+impl TokenCounts {
+    pub fn add(&mut self, other: &TokenCounts) {
+        self.runs += other.runs;
+        self.run_bytes += other.run_bytes;
+        self.literals += other.literals;
+        self.escapes += other.escapes;
+    }
+}
+
+/// Counts the tokens of one RLE block payload, as decoding reads them.
+/// `base` is the payload's offset in the frame, used for error reporting.
+pub fn count_tokens(payload: &[u8], base: usize) -> Result<TokenCounts, DecompressError> {
+    let mut counts = TokenCounts::default();
+    let truncated = || DecompressError::Truncated { offset: base + payload.len() };
+    let mut idx = 0;
+    while idx < payload.len() {
+        match payload[idx] {
+            255 => {
+                payload.get(idx + 1).ok_or_else(truncated)?;
+                counts.escapes += 1;
+                idx += 2;
+            }
+            254 => {
+                let run_len = *payload.get(idx + 1).ok_or_else(truncated)?;
+                payload.get(idx + 2).ok_or_else(truncated)?;
+                counts.runs += 1;
+                counts.run_bytes += run_len as u64;
+                idx += 3;
+            }
+            _ => {
+                let literals = literal_len(&payload[idx..]);
+                counts.literals += literals as u64;
+                idx += literals;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_with_options, CompressOptions};
+
+    fn data() -> Vec<u8> {
+        (0..50_000u32).map(|i| if i % 5000 < 3000 { 0 } else { (i * 11 / 3) as u8 }).collect()
+    }
+
+    /// The CRC-32 of everything `visit` is given, and the largest chunk.
+    fn visit_all(frame: &[u8]) -> Result<(u32, usize), DecompressError> {
+        let mut crc = Crc32::new();
+        let mut largest = 0;
+        let flow = decompress_visit(frame, |chunk| {
+            crc.update(chunk);
+            largest = largest.max(chunk.len());
+            ControlFlow::Continue(())
+        })?;
+        assert!(flow.is_continue());
+        Ok((crc.finish(), largest))
+    }
+
+    #[test]
+    fn visited_bytes_hash_like_the_output() {
+        let data = data();
+        for opts in [
+            CompressOptions::default(),
+            CompressOptions { block_size: 4096, ..CompressOptions::default() },
+            CompressOptions { store_only: true, block_size: 7000, ..CompressOptions::default() },
+        ] {
+            let frame = compress_with_options(&data, &opts).unwrap();
+            let (crc, largest) = visit_all(&frame).unwrap();
+            assert_eq!(crc, crc32(&decompress(&frame).unwrap()));
+            assert!(largest <= opts.block_size);
+        }
+        let small = compress_with_options(b"short", &CompressOptions::default()).unwrap();
+        assert_eq!(visit_all(&small).unwrap().0, crc32(b"short"));
+    }
+
+    #[test]
+    fn runs_are_expanded_in_small_chunks() {
+        let opts = CompressOptions { block_size: 1 << 20, ..CompressOptions::default() };
+        let frame = compress_with_options(&vec![0; 1 << 20], &opts).unwrap();
+        assert_eq!(visit_all(&frame).unwrap(), (crc32(&vec![0; 1 << 20]), 255));
+    }
+
+    #[test]
+    fn break_stops_before_later_blocks() {
+        let data = data();
+        let opts = CompressOptions { store_only: true, block_size: 1000, ..CompressOptions::default() };
+        let mut frame = compress_with_options(&data, &opts).unwrap();
+        let header = Header::for_options(&opts);
+        // The second block's type byte.
+        frame[header.len + header.block_header_len() + 1000] = 9;
+        assert!(matches!(visit_all(&frame), Err(DecompressError::UnknownBlockType(9))));
+
+        let mut seen = Vec::new();
+        let flow = decompress_visit(&frame, |chunk| {
+            seen.extend_from_slice(chunk);
+            ControlFlow::Break(())
+        });
+        assert_eq!(flow.unwrap(), ControlFlow::Break(()));
+        assert_eq!(seen, data[..1000]);
+    }
+
+    #[test]
+    fn checksums_fail_after_the_block_is_visited() {
+        let data = data();
+        let opts = CompressOptions { store_only: true, block_size: 1000, ..CompressOptions::default() };
+        let mut frame = compress_with_options(&data, &opts).unwrap();
+        let header = Header::for_options(&opts);
+        frame[header.len + header.block_header_len() + 10] ^= 1;
+        let mut visited = 0;
+        let err = decompress_visit(&frame, |chunk| {
+            visited += chunk.len();
+            ControlFlow::Continue(())
+        })
+        .unwrap_err();
+        assert!(matches!(err, DecompressError::ChecksumMismatch { block: 0, .. }), "{:?}", err);
+        assert_eq!(visited, 1000);
+    }
+
+    #[test]
+    fn tokens_are_counted_as_the_encoder_wrote_them() {
+        let mut block = vec![0u8; 600];
+        block.extend((0..398u32).map(|i| (i % 200 + 1) as u8));
+        block.extend([254, 255]);
+        let mut payload = Vec::new();
+        crate::compression::encode_block(&block, &mut payload);
+        let counts = count_tokens(&payload, 0).unwrap();
+        assert_eq!(counts, TokenCounts { runs: 3, run_bytes: 600, literals: 398, escapes: 2 });
+        assert_eq!(payload.len() as u64, counts.runs * 3 + counts.literals + counts.escapes * 2);
+
+        let mut total = counts;
+        total.add(&counts);
+        assert_eq!(total, TokenCounts { runs: 6, run_bytes: 1200, literals: 796, escapes: 4 });
+    }
+
+    #[test]
+    fn a_token_cut_short_is_truncated() {
+        for payload in [&[1, 2, 255][..], &[254], &[254, 9]] {
+            let err = count_tokens(payload, 100).unwrap_err();
+            assert!(matches!(err, DecompressError::Truncated { offset } if offset == 100 + payload.len()), "{:?}", err);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Length-prefixed envelopes for embedding frames in other streams.
+//!
+//! ```text
+//! envelope: frame len u64 | frame crc32 u32 | frame
+//! ```
+//!
+//! The prefix lets a reader find the next envelope without parsing the frame,
+//! so envelopes can sit back to back or between unrelated records.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::checksum::crc32;
+use crate::compression::compress_with_options;
+use crate::decompression::decompress;
+use crate::error::DecompressError;
+use crate::options::CompressOptions;
+
+pub const ENVELOPE_PREFIX_LEN: usize = 12;
+
+/// Compresses `data` and writes it as one envelope, returning the bytes written.
+pub fn write_frame(out: &mut impl Write, data: &[u8], opts: &CompressOptions) -> io::Result<usize> {
+    let frame = compress_with_options(data, opts)?;
+    out.write_all(&(frame.len() as u64).to_be_bytes())?;
+    out.write_all(&crc32(&frame).to_be_bytes())?;
+    out.write_all(&frame)?;
+    Ok(ENVELOPE_PREFIX_LEN + frame.len())
+}
+
+fn read_prefix(input: &mut impl Read) -> Result<(u64, u32), DecompressError> {
+    let mut prefix = [0u8; ENVELOPE_PREFIX_LEN];
+    input.read_exact(&mut prefix).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => DecompressError::Truncated { offset: 0 },
+        _ => DecompressError::Io(e),
+    })?;
+    let len = u64::from_be_bytes(prefix[..8].try_into().unwrap());
+    let crc = u32::from_be_bytes(prefix[8..].try_into().unwrap());
+    Ok((len, crc))
+}
+
+/// Reads one envelope and decompresses its frame, leaving `input` positioned
+/// just past it.
+pub fn read_frame(input: &mut impl Read) -> Result<Vec<u8>, DecompressError> {
+    let (len, expected) = read_prefix(input)?;
+    // Grow with the data actually read so a corrupt length can't force a huge allocation.
+    let mut frame = Vec::new();
+    input.by_ref().take(len).read_to_end(&mut frame)?;
+    if (frame.len() as u64) < len {
+        return Err(DecompressError::Truncated { offset: ENVELOPE_PREFIX_LEN + frame.len() });
+    }
+    let actual = crc32(&frame);
+    if actual != expected {
+        return Err(DecompressError::FrameChecksumMismatch { expected, actual });
+    }
+    decompress(&frame)
+}
+
+/// Seeks past one envelope without reading its frame, returning the bytes skipped.
+pub fn skip_frame<R: Read + Seek>(input: &mut R) -> Result<u64, DecompressError> {
+    let (len, _) = read_prefix(input)?;
+    let offset = i64::try_from(len).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("envelope length {} is too large to skip", len))
+    })?;
+    input.seek(SeekFrom::Current(offset))?;
+    Ok(ENVELOPE_PREFIX_LEN as u64 + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn envelopes_read_and_skip_back_to_back() {
+        let mut stream = Vec::new();
+        let first = write_frame(&mut stream, b"first record", &CompressOptions::default()).unwrap();
+        write_frame(&mut stream, &[0u8; 5000], &CompressOptions::default()).unwrap();
+        let mut input = Cursor::new(&stream);
+        assert_eq!(skip_frame(&mut input).unwrap(), first as u64);
+        assert_eq!(read_frame(&mut input).unwrap(), vec![0u8; 5000]);
+        assert!(matches!(read_frame(&mut input), Err(DecompressError::Truncated { offset: 0 })));
+    }
+
+    #[test]
+    fn corrupt_envelope_is_rejected() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"payload", &CompressOptions::default()).unwrap();
+        *stream.last_mut().unwrap() ^= 1;
+        assert!(matches!(read_frame(&mut Cursor::new(&stream)), Err(DecompressError::FrameChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn length_beyond_i64_is_invalid_data() {
+        let mut stream = u64::MAX.to_be_bytes().to_vec();
+        stream.extend_from_slice(&[0; 4]);
+        match skip_frame(&mut Cursor::new(&stream)) {
+            Err(DecompressError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            other => panic!("expected InvalidData, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Reasons compression could not run.
+#[derive(Debug)]
+pub enum CompressError {
+    /// `block_size` is zero or above [`MAX_BLOCK_SIZE`](crate::frame::MAX_BLOCK_SIZE).
+    InvalidBlockSize(usize),
+    /// Stored filename or comment is longer than 65535 bytes.
+    MetadataTooLong { field: &'static str, len: usize },
+    /// Archive member path is absolute, empty or climbs out with `..`.
+    InvalidPath(String),
+    /// Encoding with these options needs `needed` bytes, more than the
+    /// `max_memory` limit.
+    MemoryLimit { needed: usize, limit: usize },
+    /// The options' [`CancelToken`](crate::CancelToken) was cancelled.
+    Cancelled,
+    /// Reading input or writing output failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressError::InvalidBlockSize(size) => write!(
+                f,
+                "invalid block size {} (must be between 1 and {} bytes)",
+                size,
+                crate::frame::MAX_BLOCK_SIZE
+            ),
+            CompressError::MetadataTooLong { field, len } => {
+                write!(f, "{} is {} bytes long (at most {} allowed)", field, len, u16::MAX)
+            }
+            CompressError::InvalidPath(path) => write!(f, "archive member path {:?} is not a safe relative path", path),
+            CompressError::MemoryLimit { needed, limit } => {
+                write!(f, "compressing needs {} bytes of memory, more than the limit of {}", needed, limit)
+            }
+            CompressError::Cancelled => write!(f, "compression cancelled"),
+            CompressError::Io(e) => write!(f, "I/O error while compressing: {}", e),
+        }
+    }
+}
+
+impl Error for CompressError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompressError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Recovers a `CompressError` that an adapter wrapped in an `io::Error`;
+/// any other I/O error becomes `Io`.
+impl From<io::Error> for CompressError {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<CompressError>()) {
+            return *err.into_inner().unwrap().downcast::<CompressError>().unwrap();
+        }
+        CompressError::Io(err)
+    }
+}
+
+impl From<CompressError> for io::Error {
+    fn from(err: CompressError) -> Self {
+        match err {
+            CompressError::Io(e) => e,
+            other @ CompressError::Cancelled => io::Error::other(other),
+            other => io::Error::new(io::ErrorKind::InvalidInput, other),
+        }
+    }
+}
+
+/// Reasons a compressed frame could not be decoded.
+#[derive(Debug)]
+pub enum DecompressError {
+    /// Input ended early; `offset` is where more bytes were expected.
+    Truncated { offset: usize },
+    /// Input ended while reading the header or trailer field `field`.
+    TruncatedField { field: &'static str, offset: usize },
+    /// Input does not start with the AAPC magic.
+    BadMagic,
+    /// Frame was written by a format version this build cannot read.
+    UnsupportedVersion(u8),
+    /// Block `block` decoded to data whose checksum differs from the stored one.
+    ChecksumMismatch { block: u32, expected: u32, actual: u32 },
+    /// A checksum covering the whole frame differs from the stored one.
+    FrameChecksumMismatch { expected: u32, actual: u32 },
+    /// Block header names a block type this build does not know.
+    UnknownBlockType(u8),
+    /// Frame is structurally invalid at `offset`.
+    Corrupt { offset: usize, reason: &'static str },
+    /// Decoded output would exceed the caller's size limit.
+    LimitExceeded { limit: usize },
+    /// Decoding the frame needs `needed` bytes at once, more than the
+    /// caller's memory limit.
+    MemoryLimit { needed: usize, limit: usize },
+    /// The caller's [`CancelToken`](crate::CancelToken) was cancelled.
+    Cancelled,
+    /// Reading compressed input or writing decoded output failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Truncated { offset } => {
+                write!(f, "compressed data truncated at byte {}", offset)
+            }
+            DecompressError::TruncatedField { field, offset } => {
+                write!(f, "compressed data truncated at byte {} while reading {}", offset, field)
+            }
+            DecompressError::BadMagic => write!(f, "not an AAPC frame (bad magic)"),
+            DecompressError::UnsupportedVersion(v) => {
+                write!(f, "unsupported AAPC format version {}", v)
+            }
+            DecompressError::ChecksumMismatch { block, expected, actual } => write!(
+                f,
+                "checksum mismatch in block {}: expected {:08x}, got {:08x}",
+                block, expected, actual
+            ),
+            DecompressError::FrameChecksumMismatch { expected, actual } => write!(
+                f,
+                "frame checksum mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            DecompressError::UnknownBlockType(t) => write!(f, "unknown block type {}", t),
+            DecompressError::Corrupt { offset, reason } => {
+                write!(f, "corrupt frame at byte {}: {}", offset, reason)
+            }
+            DecompressError::LimitExceeded { limit } => {
+                write!(f, "decompressed size exceeds limit of {} bytes", limit)
+            }
+            DecompressError::MemoryLimit { needed, limit } => {
+                write!(f, "decompressing needs {} bytes of memory, more than the limit of {}", needed, limit)
+            }
+            DecompressError::Cancelled => write!(f, "decompression cancelled"),
+            DecompressError::Io(e) => write!(f, "I/O error while decompressing: {}", e),
+        }
+    }
+}
+
+impl Error for DecompressError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecompressError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl DecompressError {
+    /// Rebases an error produced on a sub-slice that starts at `by` in the frame.
+    pub(crate) fn shifted(self, by: usize) -> Self {
+        match self {
+            DecompressError::Truncated { offset } => DecompressError::Truncated { offset: offset + by },
+            DecompressError::TruncatedField { field, offset } => {
+                DecompressError::TruncatedField { field, offset: offset + by }
+            }
+            DecompressError::Corrupt { offset, reason } => DecompressError::Corrupt { offset: offset + by, reason },
+            other => other,
+        }
+    }
+}
+
+/// Recovers a `DecompressError` that an adapter wrapped in an `io::Error`;
+/// any other I/O error becomes `Io`.
+impl From<io::Error> for DecompressError {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<DecompressError>()) {
+            return *err.into_inner().unwrap().downcast::<DecompressError>().unwrap();
+        }
+        DecompressError::Io(err)
+    }
+}
+
+impl From<DecompressError> for io::Error {
+    fn from(err: DecompressError) -> Self {
+        let kind = match err {
+            DecompressError::Io(e) => return e,
+            DecompressError::Truncated { .. } | DecompressError::TruncatedField { .. } => {
+                io::ErrorKind::UnexpectedEof
+            }
+            DecompressError::Cancelled => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Stable, serializable description of an error for logs and metrics.
+///
+/// `kind` is a snake_case variant name that will not change between releases;
+/// `message` is the `Display` text and may.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorSummary {
+    pub kind: String,
+    pub message: String,
+}
+
+impl From<&DecompressError> for ErrorSummary {
+    fn from(err: &DecompressError) -> Self {
+        let kind = match err {
+            DecompressError::Truncated { .. } => "truncated",
+            DecompressError::TruncatedField { .. } => "truncated_field",
+            DecompressError::BadMagic => "bad_magic",
+            DecompressError::UnsupportedVersion(_) => "unsupported_version",
+            DecompressError::ChecksumMismatch { .. } => "checksum_mismatch",
+            DecompressError::FrameChecksumMismatch { .. } => "frame_checksum_mismatch",
+            DecompressError::UnknownBlockType(_) => "unknown_block_type",
+            DecompressError::Corrupt { .. } => "corrupt",
+            DecompressError::LimitExceeded { .. } => "limit_exceeded",
+            DecompressError::MemoryLimit { .. } => "memory_limit",
+            DecompressError::Cancelled => "cancelled",
+            DecompressError::Io(_) => "io",
+        };
+        ErrorSummary { kind: kind.to_string(), message: err.to_string() }
+    }
+}
+
+impl From<&CompressError> for ErrorSummary {
+    fn from(err: &CompressError) -> Self {
+        let kind = match err {
+            CompressError::InvalidBlockSize(_) => "invalid_block_size",
+            CompressError::MetadataTooLong { .. } => "metadata_too_long",
+            CompressError::InvalidPath(_) => "invalid_path",
+            CompressError::MemoryLimit { .. } => "memory_limit",
+            CompressError::Cancelled => "cancelled",
+            CompressError::Io(_) => "io",
+        };
+        ErrorSummary { kind: kind.to_string(), message: err.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::{AapcReader, AapcWriter};
+    use crate::{compress_with_options, CancelToken, CompressOptions};
+    use std::io::{Read, Write};
+
+    /// A reader or writer whose every call fails with the same error.
+    struct Failing(io::ErrorKind);
+
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(self.0, "disk on fire"))
+        }
+    }
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(self.0, "disk on fire"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let opts = CompressOptions { block_checksums: true, small_frames: false, ..CompressOptions::default() };
+        compress_with_options(data, &opts).unwrap()
+    }
+
+    #[test]
+    fn display_names_the_details() {
+        let cases: [(Box<dyn Error>, &[&str]); 7] = [
+            (Box::new(DecompressError::Truncated { offset: 42 }), &["truncated", "42"]),
+            (Box::new(DecompressError::TruncatedField { field: "filename", offset: 17 }), &["17", "filename"]),
+            (Box::new(DecompressError::UnsupportedVersion(7)), &["version 7"]),
+            (
+                Box::new(DecompressError::ChecksumMismatch { block: 3, expected: 0xdead_beef, actual: 0x1234 }),
+                &["block 3", "deadbeef", "00001234"],
+            ),
+            (Box::new(DecompressError::Corrupt { offset: 9, reason: "run past block end" }), &["9", "run past"]),
+            (Box::new(CompressError::InvalidBlockSize(0)), &["block size 0", "between 1 and"]),
+            (Box::new(CompressError::MetadataTooLong { field: "comment", len: 70_000 }), &["comment", "70000"]),
+        ];
+        for (err, parts) in cases {
+            let text = err.to_string();
+            for part in parts {
+                assert!(text.contains(part), "{:?} not in {:?}", part, text);
+            }
+            assert!(err.source().is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn io_errors_are_the_source() {
+        let err = DecompressError::from(io::Error::new(io::ErrorKind::PermissionDenied, "disk on fire"));
+        assert!(err.to_string().contains("disk on fire"), "{}", err);
+        let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::PermissionDenied);
+
+        let err = CompressError::from(io::Error::new(io::ErrorKind::WriteZero, "disk on fire"));
+        assert_eq!(err.source().unwrap().downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn reader_errors_survive_the_io_round_trip() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 + i / 256) as u8).collect();
+        let mut corrupt = frame(&data);
+        // A byte of the one block's stored payload.
+        corrupt[5_000] ^= 1;
+        let err = AapcReader::new(corrupt.as_slice()).read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.get_ref().unwrap().is::<DecompressError>(), "{:?}", err);
+        let message = err.to_string();
+        let err = DecompressError::from(err);
+        assert!(matches!(err, DecompressError::ChecksumMismatch { block: 0, .. }), "{:?}", err);
+        assert_eq!(err.to_string(), message);
+
+        let whole = frame(&[5; 10_000]);
+        let err = AapcReader::new(&whole[..whole.len() - 3]).read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(matches!(DecompressError::from(err), DecompressError::Truncated { .. }));
+
+        let err = AapcReader::new(Failing(io::ErrorKind::PermissionDenied)).read(&mut [0; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "disk on fire");
+        let err = DecompressError::from(err);
+        assert!(matches!(&err, DecompressError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied), "{:?}", err);
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn writer_errors_survive_the_io_round_trip() {
+        let mut writer = AapcWriter::new(Failing(io::ErrorKind::StorageFull));
+        let err = writer.write_all(&[1; 300_000]).and_then(|_| writer.flush()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert!(matches!(CompressError::from(err), CompressError::Io(e) if e.to_string() == "disk on fire"));
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let opts = CompressOptions { cancel: Some(cancel), ..CompressOptions::default() };
+        let mut writer = AapcWriter::with_options(Vec::new(), &opts).unwrap();
+        let err = writer.write_all(&[1; 300_000]).and_then(|_| writer.flush()).unwrap_err();
+        assert!(err.get_ref().unwrap().is::<CompressError>(), "{:?}", err);
+        assert!(matches!(CompressError::from(err), CompressError::Cancelled));
+
+        let opts = CompressOptions { block_size: 0, ..CompressOptions::default() };
+        let err = io::Error::from(AapcWriter::with_options(Vec::new(), &opts).err().unwrap());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(CompressError::from(err), CompressError::InvalidBlockSize(0)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn error_summary_json_round_trip() {
+        let err = DecompressError::UnsupportedVersion(9);
+        let summary = ErrorSummary::from(&err);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json, serde_json::json!({"kind": "unsupported_version", "message": err.to_string()}));
+        assert_eq!(serde_json::from_value::<ErrorSummary>(json).unwrap(), summary);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::compression::{compress_into, encode_payload};
+use crate::frame::{small_frame_len, Header, BLOCK_RLE, BLOCK_STORED};
+use crate::options::CompressOptions;
+
+/// Size of each sampled region, unless the block size is smaller.
+const SAMPLE_CHUNK: usize = 64 * 1024;
+
+/// How far the sampled regions disagreed with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Confidence {
+    /// Whole input was compressed, or samples agree to within ~2%.
+    High,
+    /// Standard error of the mean ratio under 5%.
+    Medium,
+    /// Samples vary widely, or fewer than two were taken; treat the
+    /// estimate as a rough guide.
+    Low,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Confidence::High => "high",
+            Confidence::Medium => "medium",
+            Confidence::Low => "low",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Projected result of compressing a whole input, from [`estimate_ratio`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RatioEstimate {
+    /// Projected compressed size divided by input size.
+    pub ratio: f64,
+    /// Standard error of the per-sample ratios' mean; 0 when the whole input
+    /// was compressed or only one region was sampled.
+    pub std_error: f64,
+    pub confidence: Confidence,
+    pub input_size: u64,
+    pub estimated_size: u64,
+    /// Bytes actually run through the encoder.
+    pub sampled_bytes: u64,
+}
+
+/// Estimates the compression ratio of `reader` under the default options
+/// by encoding about `sample_bytes` taken from evenly spaced regions.
+pub fn estimate_ratio<R: Read + Seek>(reader: R, sample_bytes: usize) -> io::Result<RatioEstimate> {
+    estimate_ratio_with_options(reader, sample_bytes, &CompressOptions::default())
+}
+
+/// Like [`estimate_ratio`], projecting the frame `opts` would give, with its
+/// block size, checksums, filename and comment.
+///
+/// Inputs no larger than `sample_bytes` are compressed in full, so the
+/// estimated size is the frame's size. Larger ones are projected from the
+/// samples' payloads, each stored as is when RLE would not shrink it as the
+/// encoder does, plus the framing. The reader is left at an unspecified
+/// position.
+pub fn estimate_ratio_with_options<R: Read + Seek>(
+    mut reader: R,
+    sample_bytes: usize,
+    opts: &CompressOptions,
+) -> io::Result<RatioEstimate> {
+    opts.validate()?;
+    let input_size = reader.seek(SeekFrom::End(0))?;
+
+    if input_size <= sample_bytes as u64 {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let stats = compress_into(&data, opts, &mut Vec::new())?;
+        let ratio = if input_size == 0 { 0.0 } else { stats.output_bytes as f64 / input_size as f64 };
+        return Ok(RatioEstimate {
+            ratio,
+            std_error: 0.0,
+            confidence: Confidence::High,
+            input_size,
+            estimated_size: stats.output_bytes,
+            sampled_bytes: input_size,
+        });
+    }
+
+    let chunk = SAMPLE_CHUNK.min(opts.block_size).min(sample_bytes.max(1));
+    let count = (sample_bytes / chunk).max(1) as u64;
+    let span = input_size - chunk as u64;
+    let mut ratios = Vec::new();
+    let mut buf = vec![0u8; chunk];
+    let mut encoded = Vec::new();
+    for i in 0..count {
+        let offset = if count == 1 { span / 2 } else { span * i / (count - 1) };
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut buf)?;
+        encoded.clear();
+        encode_payload(&buf, i as u32, opts.store_only, &mut encoded);
+        ratios.push(encoded.len() as f64 / chunk as f64);
+    }
+
+    let n = ratios.len() as f64;
+    let mean = ratios.iter().sum::<f64>() / n;
+    let std_error = match ratios.len() {
+        0 | 1 => 0.0,
+        _ => (ratios.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0) / n).sqrt(),
+    };
+    let payload = ((mean * input_size as f64).round() as u64).min(input_size);
+    let estimated_size = framed_size(input_size, payload, opts);
+    let confidence = if ratios.len() < 2 {
+        Confidence::Low
+    } else if std_error < 0.02 {
+        Confidence::High
+    } else if std_error < 0.05 {
+        Confidence::Medium
+    } else {
+        Confidence::Low
+    };
+
+    Ok(RatioEstimate {
+        ratio: estimated_size as f64 / input_size as f64,
+        std_error,
+        confidence,
+        input_size,
+        estimated_size,
+        sampled_bytes: count * chunk as u64,
+    })
+}
+
+/// Size of the frame `opts` give for `input_size` bytes whose block
+/// payloads come to `payload` bytes in all.
+fn framed_size(input_size: u64, payload: u64, opts: &CompressOptions) -> u64 {
+    let len = input_size as usize;
+    if opts.small_frame_fits(len) {
+        let block_type = if opts.store_only || payload >= input_size { BLOCK_STORED } else { BLOCK_RLE };
+        let comp_len = if block_type == BLOCK_RLE { payload as usize } else { len };
+        let checksum = opts.block_checksums || opts.content_checksum;
+        return small_frame_len(block_type, len, comp_len, checksum) as u64;
+    }
+    let header = Header::for_options(opts);
+    let blocks = input_size.div_ceil(opts.block_size as u64);
+    (header.len + 1 + header.trailer_len()) as u64 + blocks * header.block_header_len() as u64 + payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_with_options;
+    use std::io::Cursor;
+
+    const LEN: usize = 4 * 1024 * 1024;
+    const SAMPLE: usize = 512 * 1024;
+
+    fn noise(len: usize, mut state: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Runs of 50 to 249 bytes of one value each.
+    fn runs(len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(len);
+        let mut i = 0usize;
+        while data.len() < len {
+            let run = 50 + i * 37 % 200;
+            data.resize(data.len() + run.min(len - data.len()), i as u8);
+            i += 1;
+        }
+        data
+    }
+
+    fn true_ratio(data: &[u8], opts: &CompressOptions) -> f64 {
+        compress_with_options(data, opts).unwrap().len() as f64 / data.len() as f64
+    }
+
+    fn assert_within(data: &[u8], sample: usize, band: f64) -> RatioEstimate {
+        let opts = CompressOptions::default();
+        let estimate = estimate_ratio(Cursor::new(data), sample).unwrap();
+        let actual = true_ratio(data, &opts);
+        assert!((estimate.ratio - actual).abs() <= band, "estimated {:?}, actual {}", estimate, actual);
+        assert!(estimate.sampled_bytes < data.len() as u64);
+        estimate
+    }
+
+    #[test]
+    fn runs_estimate_is_close_and_confident() {
+        let estimate = assert_within(&runs(LEN), SAMPLE, 0.01);
+        assert!(estimate.ratio < 0.1, "{:?}", estimate);
+        assert_eq!(estimate.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn random_estimate_counts_stored_blocks_and_framing() {
+        let data = noise(LEN, 0x2545_F491);
+        let opts = CompressOptions { filename: Some("noise.bin".to_string()), ..CompressOptions::default() };
+        let estimate = estimate_ratio_with_options(Cursor::new(&data), SAMPLE, &opts).unwrap();
+        // Every block is stored, so the projection is exact.
+        assert_eq!(estimate.estimated_size, compress_with_options(&data, &opts).unwrap().len() as u64);
+        assert_eq!(estimate.confidence, Confidence::High);
+        assert_within(&data, SAMPLE, 0.001);
+    }
+
+    #[test]
+    fn half_and_half_estimate_is_close_but_not_confident() {
+        let mut data = vec![0u8; LEN / 2];
+        data.extend(noise(LEN / 2, 7));
+        let estimate = assert_within(&data, SAMPLE, 0.1);
+        assert_eq!(estimate.confidence, Confidence::Low);
+    }
+
+    #[test]
+    fn small_sampled_input_projects_a_small_frame() {
+        let data = runs(3000);
+        let estimate = assert_within(&data, 1024, 0.02);
+        assert!(estimate.estimated_size < 100, "{:?}", estimate);
+    }
+
+    #[test]
+    fn whole_input_is_compressed_exactly() {
+        let data = runs(100_000);
+        let opts = CompressOptions { filename: Some("runs.bin".to_string()), ..CompressOptions::default() };
+        let estimate = estimate_ratio_with_options(Cursor::new(&data), data.len(), &opts).unwrap();
+        assert_eq!(estimate.estimated_size, compress_with_options(&data, &opts).unwrap().len() as u64);
+        assert_eq!(estimate.sampled_bytes, data.len() as u64);
+        assert_eq!(estimate.confidence, Confidence::High);
+
+        let empty = estimate_ratio(Cursor::new(Vec::new()), 0).unwrap();
+        assert_eq!(empty.estimated_size, compress_with_options(&[], &CompressOptions::default()).unwrap().len() as u64);
+    }
+
+    #[test]
+    fn one_sample_is_never_confident() {
+        let data = runs(LEN);
+        for sample in [0, 1, SAMPLE_CHUNK] {
+            let estimate = estimate_ratio(Cursor::new(&data), sample).unwrap();
+            assert_eq!(estimate.confidence, Confidence::Low, "sampling {} bytes: {:?}", sample, estimate);
+        }
+    }
+}
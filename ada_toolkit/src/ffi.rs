@@ -0,0 +1,249 @@
+//! C interface, built with the `ffi` feature; declarations are in
+//! `include/aapc.h`.
+//!
+//! A context compresses or decompresses one frame incrementally. Each call
+//! reports how much input it consumed and how much output it produced; when
+//! the output buffer fills up the call returns `AAPC_NEEDS_OUTPUT` and the
+//! rest is delivered by the next call, which should pass the unconsumed
+//! input again.
+//!
+//! `tests/c/ctx_harness.c` drives the API from C through one-byte and other
+//! tiny output buffers; its header says how to build and run it.
+
+use std::os::raw::c_int;
+use std::slice;
+
+use crate::error::{CompressError, DecompressError};
+use crate::options::CompressOptions;
+use crate::stream::{BlockEncoder, FrameDecoder};
+
+pub const AAPC_MODE_COMPRESS: c_int = 0;
+pub const AAPC_MODE_DECOMPRESS: c_int = 1;
+
+/// Call succeeded; all output produced so far has been delivered.
+pub const AAPC_OK: c_int = 0;
+/// Output buffer is full; call again to receive the rest.
+pub const AAPC_NEEDS_OUTPUT: c_int = 1;
+/// A pointer was null or an option was out of range.
+pub const AAPC_ERR_INVALID: c_int = -1;
+/// Input is not a valid frame.
+pub const AAPC_ERR_CORRUPT: c_int = -2;
+/// `aapc_ctx_finish` was called before the frame was complete.
+pub const AAPC_ERR_TRUNCATED: c_int = -3;
+/// Decoded data failed a checksum.
+pub const AAPC_ERR_CHECKSUM: c_int = -4;
+
+/// Encoder settings; pass null to `aapc_ctx_new` for the defaults.
+#[repr(C)]
+pub struct AapcOptions {
+    /// Uncompressed bytes per block; 0 selects the default.
+    pub block_size: usize,
+    /// Non-zero to store block and content checksums.
+    pub checksums: c_int,
+}
+
+/// Opaque to C.
+pub struct AapcCtx(Ctx);
+
+enum Ctx {
+    Compress(BlockEncoder),
+    Decompress(FrameDecoder),
+}
+
+/// Options were validated when the context was made, and contexts have no
+/// cancel token or I/O, so pushing into an encoder cannot actually fail.
+fn compress_status(_: CompressError) -> c_int {
+    AAPC_ERR_INVALID
+}
+
+fn decompress_status(err: DecompressError) -> c_int {
+    match err {
+        DecompressError::Truncated { .. } | DecompressError::TruncatedField { .. } => AAPC_ERR_TRUNCATED,
+        DecompressError::ChecksumMismatch { .. } | DecompressError::FrameChecksumMismatch { .. } => {
+            AAPC_ERR_CHECKSUM
+        }
+        _ => AAPC_ERR_CORRUPT,
+    }
+}
+
+/// Copies as much of `src` as fits into `out[*produced..]`, returning the count.
+fn deliver(src: &[u8], out: &mut [u8], produced: &mut usize) -> usize {
+    let n = src.len().min(out.len() - *produced);
+    out[*produced..*produced + n].copy_from_slice(&src[..n]);
+    *produced += n;
+    n
+}
+
+impl Ctx {
+    fn update(&mut self, input: &[u8], out: &mut [u8], consumed: &mut usize, produced: &mut usize) -> c_int {
+        match self {
+            Ctx::Compress(encoder) => loop {
+                let n = deliver(encoder.pending(), out, produced);
+                encoder.consume(n);
+                if !encoder.pending().is_empty() {
+                    return AAPC_NEEDS_OUTPUT;
+                }
+                if *consumed == input.len() {
+                    return AAPC_OK;
+                }
+                match encoder.push(&input[*consumed..]) {
+                    Ok(n) => *consumed += n,
+                    Err(e) => return compress_status(e),
+                }
+            },
+            Ctx::Decompress(decoder) => {
+                if let Err(e) = decoder.push(input) {
+                    return decompress_status(e);
+                }
+                *consumed = input.len();
+                Self::deliver_decoded(decoder, out, produced)
+            }
+        }
+    }
+
+    fn deliver_decoded(decoder: &mut FrameDecoder, out: &mut [u8], produced: &mut usize) -> c_int {
+        loop {
+            let n = deliver(decoder.output(), out, produced);
+            decoder.consume(n);
+            if !decoder.output().is_empty() {
+                return AAPC_NEEDS_OUTPUT;
+            }
+            if let Err(e) = decoder.resume() {
+                return decompress_status(e);
+            }
+            if decoder.output().is_empty() {
+                return AAPC_OK;
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut [u8], produced: &mut usize) -> c_int {
+        match self {
+            Ctx::Compress(encoder) => {
+                if let Err(e) = encoder.finish() {
+                    return compress_status(e);
+                }
+                let n = deliver(encoder.pending(), out, produced);
+                encoder.consume(n);
+                match encoder.pending().is_empty() {
+                    true => AAPC_OK,
+                    false => AAPC_NEEDS_OUTPUT,
+                }
+            }
+            Ctx::Decompress(decoder) => match Self::deliver_decoded(decoder, out, produced) {
+                AAPC_OK if !decoder.is_done() => AAPC_ERR_TRUNCATED,
+                status => status,
+            },
+        }
+    }
+}
+
+/// Treats a null pointer with zero length as an empty slice.
+unsafe fn input_slice<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+unsafe fn output_slice<'a>(ptr: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Some(&mut []),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts_mut(ptr, len)),
+    }
+}
+
+/// Creates a context for `mode`, or returns null if the mode or options are invalid.
+///
+/// # Safety
+/// `options` must be null or point to a valid `AapcOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn aapc_ctx_new(mode: c_int, options: *const AapcOptions) -> *mut AapcCtx {
+    let ctx = match mode {
+        AAPC_MODE_COMPRESS => {
+            let mut opts = CompressOptions::default();
+            if let Some(o) = options.as_ref() {
+                if o.block_size != 0 {
+                    opts.block_size = o.block_size;
+                }
+                opts.block_checksums = o.checksums != 0;
+                opts.content_checksum = o.checksums != 0;
+            }
+            match BlockEncoder::new(&opts) {
+                Ok(encoder) => Ctx::Compress(encoder),
+                Err(_) => return std::ptr::null_mut(),
+            }
+        }
+        AAPC_MODE_DECOMPRESS => Ctx::Decompress(FrameDecoder::new()),
+        _ => return std::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(AapcCtx(ctx)))
+}
+
+/// Feeds `input` and writes output into `output`, setting `consumed` and
+/// `produced`. Returns `AAPC_OK`, `AAPC_NEEDS_OUTPUT` or a negative error.
+///
+/// # Safety
+/// `ctx` must come from `aapc_ctx_new`; the buffers must be valid for their
+/// lengths; `consumed` and `produced` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn aapc_ctx_update(
+    ctx: *mut AapcCtx,
+    input: *const u8,
+    input_len: usize,
+    output: *mut u8,
+    output_cap: usize,
+    consumed: *mut usize,
+    produced: *mut usize,
+) -> c_int {
+    let (Some(ctx), Some(input), Some(output)) =
+        (ctx.as_mut(), input_slice(input, input_len), output_slice(output, output_cap))
+    else {
+        return AAPC_ERR_INVALID;
+    };
+    if consumed.is_null() || produced.is_null() {
+        return AAPC_ERR_INVALID;
+    }
+    let (mut used, mut written) = (0, 0);
+    let status = ctx.0.update(input, output, &mut used, &mut written);
+    *consumed = used;
+    *produced = written;
+    status
+}
+
+/// Ends the frame: writes the trailer when compressing, checks for a complete
+/// frame when decompressing. Repeat while it returns `AAPC_NEEDS_OUTPUT`.
+///
+/// # Safety
+/// Same requirements as `aapc_ctx_update`.
+#[no_mangle]
+pub unsafe extern "C" fn aapc_ctx_finish(
+    ctx: *mut AapcCtx,
+    output: *mut u8,
+    output_cap: usize,
+    produced: *mut usize,
+) -> c_int {
+    let (Some(ctx), Some(output)) = (ctx.as_mut(), output_slice(output, output_cap)) else {
+        return AAPC_ERR_INVALID;
+    };
+    if produced.is_null() {
+        return AAPC_ERR_INVALID;
+    }
+    let mut written = 0;
+    let status = ctx.0.finish(output, &mut written);
+    *produced = written;
+    status
+}
+
+/// Frees a context. Null is ignored.
+///
+/// # Safety
+/// `ctx` must come from `aapc_ctx_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn aapc_ctx_free(ctx: *mut AapcCtx) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
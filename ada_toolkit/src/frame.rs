@@ -0,0 +1,859 @@
+//! On-disk layout of an AAPC frame.
+//!
+//! A frame is a header, any number of blocks, an end marker and a trailer:
+//!
+//! ```text
+//! header:  magic "AAPC" | version u8 | flags u8 | block size u32
+//!          [filename len u16 | filename]   if FLAG_FILENAME
+//!          [comment len u16 | comment]     if FLAG_COMMENT
+//! block:   type u8 (BLOCK_RLE or BLOCK_STORED) | compressed len u32 | raw len u32
+//!          [crc32 of raw block u32]        if FLAG_BLOCK_CHECKSUM
+//!          payload
+//! end:     type u8 (BLOCK_END)
+//! trailer: block count u32 | content size u64
+//!          [crc32 of all content u32]      if FLAG_CONTENT_CHECKSUM
+//! ```
+//!
+//! All integers are big-endian. Every block carries both of its lengths, so a
+//! frame can be written without knowing the input size up front and walked
+//! without decoding any payload. The trailer has a fixed size for a given set
+//! of flags, so it can be read by seeking back from the end of the frame.
+//!
+//! Content shorter than [`SMALL_FRAME_LIMIT`] may instead be written as a
+//! small frame, which starts with a marker byte rather than the magic and
+//! holds the whole content as its one block:
+//!
+//! ```text
+//! small:   marker u8 (SMALL_MARKER | SMALL_RLE? | SMALL_CHECKSUM?) | content size varint
+//!          [payload len varint]           if SMALL_RLE
+//!          payload
+//!          [crc32 of all content u32]     if SMALL_CHECKSUM
+//! ```
+//!
+//! A varint holds seven bits a byte, low bits first, with the top bit set on
+//! every byte but the last; below the limit it takes one or two bytes. So
+//! content stored as is gains exactly [`small_frame_overhead`] bytes: 2 up to
+//! 127 bytes and 3 from 128, plus 4 with a checksum. The full framing of the
+//! same content with both checksums adds 40.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::checksum::crc32;
+use crate::error::DecompressError;
+use crate::options::CompressOptions;
+
+/// Identifies an AAPC frame.
+pub const MAGIC: [u8; 4] = *b"AAPC";
+/// Current format version.
+pub const VERSION: u8 = 1;
+
+/// Uncompressed bytes per block unless the caller asks otherwise.
+pub const DEFAULT_BLOCK_SIZE: usize = 256 * 1024;
+/// Largest block size a frame may declare.
+pub const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Each block header carries a CRC-32 of the block's uncompressed bytes.
+pub const FLAG_BLOCK_CHECKSUM: u8 = 0x01;
+/// The trailer carries a CRC-32 of the whole uncompressed content.
+pub const FLAG_CONTENT_CHECKSUM: u8 = 0x02;
+/// The header stores the original file name.
+pub const FLAG_FILENAME: u8 = 0x04;
+/// The header stores a free-form comment.
+pub const FLAG_COMMENT: u8 = 0x08;
+const KNOWN_FLAGS: u8 = FLAG_BLOCK_CHECKSUM | FLAG_CONTENT_CHECKSUM | FLAG_FILENAME | FLAG_COMMENT;
+
+/// Marks the end of the block sequence.
+pub const BLOCK_END: u8 = 0;
+/// Block payload is RLE-encoded.
+pub const BLOCK_RLE: u8 = 1;
+/// Block payload is the raw bytes, used when RLE would not shrink them.
+pub const BLOCK_STORED: u8 = 2;
+
+/// Content shorter than this, with no filename or comment, is written as a
+/// small frame.
+pub const SMALL_FRAME_LIMIT: usize = 4096;
+/// First byte of a small frame, with its `SMALL_*` flags in the low bits.
+pub const SMALL_MARKER: u8 = 0xA8;
+/// The small frame ends with a CRC-32 of its content.
+pub const SMALL_CHECKSUM: u8 = 0x01;
+/// The small frame's payload is RLE-encoded, with its length after the
+/// content size; otherwise it is the content as is.
+pub const SMALL_RLE: u8 = 0x02;
+const SMALL_FLAGS: u8 = SMALL_CHECKSUM | SMALL_RLE;
+/// Longest varint a small frame holds, enough for any length below 16384.
+const VARINT_MAX_LEN: usize = 2;
+
+/// Short lowercase name of a block type, for logs and traces.
+pub fn block_type_name(block_type: u8) -> &'static str {
+    match block_type {
+        BLOCK_END => "end",
+        BLOCK_RLE => "rle",
+        BLOCK_STORED => "stored",
+        _ => "unknown",
+    }
+}
+
+/// Fixed part of the header, before any optional fields.
+pub const HEADER_LEN: usize = 10;
+/// Block header without its optional checksum.
+pub const BLOCK_HEADER_LEN: usize = 9;
+/// Trailer after the end marker, without its optional checksum.
+pub const TRAILER_LEN: usize = 12;
+
+/// Parsed frame header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub flags: u8,
+    pub block_size: usize,
+    pub filename: Option<String>,
+    pub comment: Option<String>,
+    /// Encoded length of the header including optional fields.
+    pub len: usize,
+}
+
+impl Header {
+    /// Header an encoder writes for `opts`. Options must already be validated.
+    pub fn for_options(opts: &CompressOptions) -> Header {
+        let mut flags = 0;
+        let mut len = HEADER_LEN;
+        if opts.block_checksums {
+            flags |= FLAG_BLOCK_CHECKSUM;
+        }
+        if opts.content_checksum {
+            flags |= FLAG_CONTENT_CHECKSUM;
+        }
+        if let Some(name) = &opts.filename {
+            flags |= FLAG_FILENAME;
+            len += 2 + name.len();
+        }
+        if let Some(comment) = &opts.comment {
+            flags |= FLAG_COMMENT;
+            len += 2 + comment.len();
+        }
+        Header {
+            version: VERSION,
+            flags,
+            block_size: opts.block_size,
+            filename: opts.filename.clone(),
+            comment: opts.comment.clone(),
+            len,
+        }
+    }
+
+    pub fn block_checksums(&self) -> bool {
+        self.flags & FLAG_BLOCK_CHECKSUM != 0
+    }
+
+    pub fn content_checksum(&self) -> bool {
+        self.flags & FLAG_CONTENT_CHECKSUM != 0
+    }
+
+    /// Whether encoding needs each block's CRC-32, for its header or to fold
+    /// into the content checksum.
+    pub(crate) fn needs_block_crc(&self) -> bool {
+        self.block_checksums() || self.content_checksum()
+    }
+
+    /// The CRC-32 of `block`, read once for both checksums, or 0 when
+    /// neither is written.
+    pub(crate) fn block_crc(&self, block: &[u8]) -> u32 {
+        if self.needs_block_crc() { crc32(block) } else { 0 }
+    }
+
+    /// Length of each block header in this frame.
+    pub fn block_header_len(&self) -> usize {
+        BLOCK_HEADER_LEN + if self.block_checksums() { 4 } else { 0 }
+    }
+
+    /// Length of the trailer in this frame, excluding the end marker.
+    pub fn trailer_len(&self) -> usize {
+        TRAILER_LEN + if self.content_checksum() { 4 } else { 0 }
+    }
+
+    pub(crate) fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC);
+        out.push(self.version);
+        out.push(self.flags);
+        out.extend_from_slice(&(self.block_size as u32).to_be_bytes());
+        for field in [&self.filename, &self.comment].into_iter().flatten() {
+            out.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+}
+
+/// Parsed block header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockHeader {
+    pub block_type: u8,
+    pub comp_len: usize,
+    pub raw_len: usize,
+    pub checksum: Option<u32>,
+}
+
+impl BlockHeader {
+    /// Checks decoded block `index` against its stored checksum, if any.
+    pub(crate) fn verify(&self, index: u32, decoded: &[u8]) -> Result<(), DecompressError> {
+        match self.checksum {
+            Some(_) => self.verify_crc(index, crc32(decoded)),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`BlockHeader::verify`], for a CRC the caller computed incrementally.
+    pub(crate) fn verify_crc(&self, index: u32, actual: u32) -> Result<(), DecompressError> {
+        match self.checksum {
+            Some(expected) if expected != actual => {
+                Err(DecompressError::ChecksumMismatch { block: index, expected, actual })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parsed small frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SmallFrame {
+    /// The frame's one block, whose checksum, if any, is the content's.
+    pub block: BlockHeader,
+    /// Offset of the payload from the start of the frame.
+    pub payload_at: usize,
+    /// Encoded length of the whole frame.
+    pub len: usize,
+}
+
+/// Parsed trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Trailer {
+    pub block_count: u32,
+    pub content_size: u64,
+    pub content_checksum: Option<u32>,
+}
+
+impl Trailer {
+    /// Checks the trailer against what the decoder actually saw.
+    pub(crate) fn verify(
+        &self,
+        block_count: u32,
+        content_size: u64,
+        content_checksum: Option<u32>,
+        offset: usize,
+    ) -> Result<(), DecompressError> {
+        if self.block_count != block_count || self.content_size != content_size {
+            return Err(DecompressError::Corrupt { offset, reason: "trailer does not match blocks" });
+        }
+        if let (Some(expected), Some(actual)) = (self.content_checksum, content_checksum) {
+            if expected != actual {
+                return Err(DecompressError::FrameChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends the header of a block of `raw_len` bytes whose CRC-32, from
+/// [`Header::block_crc`], is `crc`.
+pub(crate) fn write_block_header(
+    out: &mut Vec<u8>,
+    header: &Header,
+    block_type: u8,
+    comp_len: usize,
+    raw_len: usize,
+    crc: u32,
+) {
+    out.push(block_type);
+    out.extend_from_slice(&(comp_len as u32).to_be_bytes());
+    out.extend_from_slice(&(raw_len as u32).to_be_bytes());
+    if header.block_checksums() {
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// Fills in the type and payload length of the block header written at
+/// `at` with a placeholder for both, once the payload after it is complete.
+pub(crate) fn patch_block_header(out: &mut [u8], at: usize, block_type: u8, comp_len: usize) {
+    out[at] = block_type;
+    out[at + 1..at + 5].copy_from_slice(&(comp_len as u32).to_be_bytes());
+}
+
+pub(crate) fn write_trailer(out: &mut Vec<u8>, header: &Header, trailer: &Trailer) {
+    out.push(BLOCK_END);
+    out.extend_from_slice(&trailer.block_count.to_be_bytes());
+    out.extend_from_slice(&trailer.content_size.to_be_bytes());
+    if header.content_checksum() {
+        out.extend_from_slice(&trailer.content_checksum.unwrap_or(0).to_be_bytes());
+    }
+}
+
+/// Whether `data` starts with a small frame's marker rather than the magic.
+pub fn is_small_frame(data: &[u8]) -> bool {
+    data.first().is_some_and(|&marker| marker & !SMALL_FLAGS == SMALL_MARKER)
+}
+
+/// Bytes a small frame adds to `content_size` bytes of content stored as is,
+/// with or without its checksum. An RLE payload adds its length's varint.
+pub const fn small_frame_overhead(content_size: usize, checksum: bool) -> usize {
+    1 + varint_len(content_size) + if checksum { 4 } else { 0 }
+}
+
+const fn varint_len(mut value: usize) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads the varint at `*offset`, moving past it.
+fn read_varint(data: &[u8], offset: &mut usize, name: &'static str) -> Result<usize, DecompressError> {
+    let mut value = 0;
+    for i in 0..VARINT_MAX_LEN {
+        let byte = field(data, *offset, 1, name)?[0];
+        *offset += 1;
+        value |= ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecompressError::Corrupt { offset: *offset - 1, reason: "small frame length too long" })
+}
+
+/// Length of the small frame [`write_small_frame`] writes for `raw_len`
+/// bytes of content whose payload is `comp_len` bytes of `block_type`.
+pub(crate) const fn small_frame_len(block_type: u8, raw_len: usize, comp_len: usize, checksum: bool) -> usize {
+    let rle = if block_type == BLOCK_RLE { varint_len(comp_len) } else { 0 };
+    small_frame_overhead(raw_len, checksum) + rle + comp_len
+}
+
+/// Appends a small frame whose content, `raw_len` bytes, is `payload`
+/// encoded as `block_type`, ending with the content's CRC-32 if given.
+pub(crate) fn write_small_frame(out: &mut Vec<u8>, block_type: u8, raw_len: usize, payload: &[u8], crc: Option<u32>) {
+    let rle = block_type == BLOCK_RLE;
+    let mut marker = SMALL_MARKER;
+    if rle {
+        marker |= SMALL_RLE;
+    }
+    if crc.is_some() {
+        marker |= SMALL_CHECKSUM;
+    }
+    out.push(marker);
+    write_varint(out, raw_len);
+    if rle {
+        write_varint(out, payload.len());
+    }
+    out.extend_from_slice(payload);
+    if let Some(crc) = crc {
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// Parses the marker and lengths of the small frame at the start of `data`,
+/// which gives the length of the whole frame; the checksum is left for
+/// [`parse_small_frame`].
+fn parse_small_lengths(data: &[u8]) -> Result<SmallFrame, DecompressError> {
+    let marker = field(data, 0, 1, "small frame marker")?[0];
+    let mut offset = 1;
+    let raw_len = read_varint(data, &mut offset, "content size")?;
+    if raw_len >= SMALL_FRAME_LIMIT {
+        return Err(DecompressError::Corrupt { offset: 1, reason: "small frame content too large" });
+    }
+    let (block_type, comp_len) = match marker & SMALL_RLE {
+        0 => (BLOCK_STORED, raw_len),
+        _ => (BLOCK_RLE, read_varint(data, &mut offset, "payload length")?),
+    };
+    let checksum = (marker & SMALL_CHECKSUM != 0).then_some(0);
+    let len = offset + comp_len + if checksum.is_some() { 4 } else { 0 };
+    Ok(SmallFrame { block: BlockHeader { block_type, comp_len, raw_len, checksum }, payload_at: offset, len })
+}
+
+/// Parses the small frame at the start of `data`, which must hold all of it.
+pub(crate) fn parse_small_frame(data: &[u8]) -> Result<SmallFrame, DecompressError> {
+    let mut small = parse_small_lengths(data)?;
+    let payload_end = small.payload_at + small.block.comp_len;
+    if data.len() < payload_end {
+        return Err(DecompressError::Truncated { offset: data.len() });
+    }
+    if small.block.checksum.is_some() {
+        let crc = field(data, payload_end, 4, "content checksum")?;
+        small.block.checksum = Some(u32::from_be_bytes(crc.try_into().unwrap()));
+    }
+    Ok(small)
+}
+
+/// How a frame starts: a full frame's header, or a small frame, all of it.
+pub(crate) enum FrameStart {
+    Full(Header),
+    Small(SmallFrame),
+}
+
+/// Parses the header at the start of `data`, or the small frame there.
+pub(crate) fn parse_start(data: &[u8]) -> Result<FrameStart, DecompressError> {
+    match is_small_frame(data) {
+        true => parse_small_frame(data).map(FrameStart::Small),
+        false => parse_header(data).map(FrameStart::Full),
+    }
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Result<u32, DecompressError> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or(DecompressError::Truncated { offset: data.len() })
+}
+
+/// Returns `data[offset..offset + n]`, or an error naming the field being read.
+fn field<'a>(data: &'a [u8], offset: usize, n: usize, name: &'static str) -> Result<&'a [u8], DecompressError> {
+    data.get(offset..offset + n)
+        .ok_or(DecompressError::TruncatedField { field: name, offset: data.len() })
+}
+
+fn read_string(data: &[u8], offset: &mut usize, name: &'static str) -> Result<String, DecompressError> {
+    let len = u16::from_be_bytes(field(data, *offset, 2, name)?.try_into().unwrap()) as usize;
+    let bytes = field(data, *offset + 2, len, name)?;
+    let value = String::from_utf8(bytes.to_vec())
+        .map_err(|_| DecompressError::Corrupt { offset: *offset + 2, reason: "header string is not UTF-8" })?;
+    *offset += 2 + len;
+    Ok(value)
+}
+
+/// Parses and validates the frame header at the start of `data`.
+pub fn parse_header(data: &[u8]) -> Result<Header, DecompressError> {
+    let magic = data.get(..MAGIC.len()).unwrap_or(data);
+    if magic != &MAGIC[..magic.len()] {
+        return Err(DecompressError::BadMagic);
+    }
+    field(data, 0, MAGIC.len(), "magic")?;
+    let version = field(data, 4, 1, "version")?[0];
+    if version != VERSION {
+        return Err(DecompressError::UnsupportedVersion(version));
+    }
+    let flags = field(data, 5, 1, "flags")?[0];
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(DecompressError::Corrupt { offset: 5, reason: "unknown header flags" });
+    }
+    let block_size = u32::from_be_bytes(field(data, 6, 4, "block size")?.try_into().unwrap()) as usize;
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(DecompressError::Corrupt { offset: 6, reason: "block size out of range" });
+    }
+    let mut len = HEADER_LEN;
+    let filename = match flags & FLAG_FILENAME {
+        0 => None,
+        _ => Some(read_string(data, &mut len, "filename")?),
+    };
+    let comment = match flags & FLAG_COMMENT {
+        0 => None,
+        _ => Some(read_string(data, &mut len, "comment")?),
+    };
+    Ok(Header { version, flags, block_size, filename, comment, len })
+}
+
+/// Parses the block header at `offset`; `None` means the end marker was reached.
+pub(crate) fn parse_block_header(
+    data: &[u8],
+    offset: usize,
+    header: &Header,
+) -> Result<Option<BlockHeader>, DecompressError> {
+    let block_type = *data.get(offset).ok_or(DecompressError::Truncated { offset: data.len() })?;
+    match block_type {
+        BLOCK_END => return Ok(None),
+        BLOCK_RLE | BLOCK_STORED => {}
+        other => return Err(DecompressError::UnknownBlockType(other)),
+    }
+    let comp_len = read_u32(data, offset + 1)? as usize;
+    let raw_len = read_u32(data, offset + 5)? as usize;
+    if raw_len > header.block_size {
+        return Err(DecompressError::Corrupt { offset, reason: "block larger than frame block size" });
+    }
+    if block_type == BLOCK_STORED && comp_len != raw_len {
+        return Err(DecompressError::Corrupt { offset, reason: "stored block lengths differ" });
+    }
+    let checksum = match header.block_checksums() {
+        true => Some(read_u32(data, offset + BLOCK_HEADER_LEN)?),
+        false => None,
+    };
+    Ok(Some(BlockHeader { block_type, comp_len, raw_len, checksum }))
+}
+
+/// Parses the trailer that follows the end marker, starting at `offset`.
+pub(crate) fn parse_trailer(data: &[u8], offset: usize, header: &Header) -> Result<Trailer, DecompressError> {
+    let block_count = u32::from_be_bytes(field(data, offset, 4, "block count")?.try_into().unwrap());
+    let content_size = u64::from_be_bytes(field(data, offset + 4, 8, "content size")?.try_into().unwrap());
+    let content_checksum = match header.content_checksum() {
+        true => Some(u32::from_be_bytes(field(data, offset + 12, 4, "content checksum")?.try_into().unwrap())),
+        false => None,
+    };
+    Ok(Trailer { block_count, content_size, content_checksum })
+}
+
+/// Checksum carried by a frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChecksumType {
+    #[default]
+    None,
+    Crc32,
+}
+
+/// Frame metadata gathered from the header and trailer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FrameInfo {
+    pub version: u8,
+    /// Raw header flags (`FLAG_*`).
+    pub flags: u8,
+    pub block_size: usize,
+    pub block_count: u32,
+    /// Uncompressed size recorded in the trailer.
+    pub content_size: u64,
+    /// Bytes from the start of the header to the end of the trailer.
+    pub compressed_size: u64,
+    pub checksum_type: ChecksumType,
+    pub block_checksums: bool,
+    /// Stored CRC-32 of the whole content, if the frame has one.
+    pub content_checksum: Option<u32>,
+    pub filename: Option<String>,
+    pub comment: Option<String>,
+    /// A small frame, which has no header or trailer of its own: its flags
+    /// are those a full frame with the same checksum would have, with its
+    /// one CRC as both the block's and the content's, and its block size
+    /// is [`SMALL_FRAME_LIMIT`].
+    pub small: bool,
+}
+
+impl Default for FrameInfo {
+    fn default() -> Self {
+        FrameInfo {
+            version: VERSION,
+            flags: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_count: 0,
+            content_size: 0,
+            compressed_size: 0,
+            checksum_type: ChecksumType::None,
+            block_checksums: false,
+            content_checksum: None,
+            filename: None,
+            comment: None,
+            small: false,
+        }
+    }
+}
+
+impl FrameInfo {
+    pub(crate) fn new(header: Header, trailer: &Trailer, compressed_size: u64) -> FrameInfo {
+        let checksum_type = match header.flags & (FLAG_BLOCK_CHECKSUM | FLAG_CONTENT_CHECKSUM) {
+            0 => ChecksumType::None,
+            _ => ChecksumType::Crc32,
+        };
+        FrameInfo {
+            version: header.version,
+            flags: header.flags,
+            block_size: header.block_size,
+            block_count: trailer.block_count,
+            content_size: trailer.content_size,
+            compressed_size,
+            checksum_type,
+            block_checksums: header.block_checksums(),
+            content_checksum: trailer.content_checksum,
+            filename: header.filename,
+            comment: header.comment,
+            small: false,
+        }
+    }
+
+    pub(crate) fn small(small: &SmallFrame) -> FrameInfo {
+        let checksum = small.block.checksum;
+        FrameInfo {
+            flags: if checksum.is_some() { FLAG_BLOCK_CHECKSUM | FLAG_CONTENT_CHECKSUM } else { 0 },
+            block_size: SMALL_FRAME_LIMIT,
+            block_count: u32::from(small.block.raw_len > 0),
+            content_size: small.block.raw_len as u64,
+            compressed_size: small.len as u64,
+            checksum_type: if checksum.is_some() { ChecksumType::Crc32 } else { ChecksumType::None },
+            block_checksums: checksum.is_some(),
+            content_checksum: checksum,
+            small: true,
+            ..FrameInfo::default()
+        }
+    }
+
+    /// Reads frame metadata by skipping over block payloads without decoding them.
+    ///
+    /// Block headers are walked to find the trailer, and its totals are checked
+    /// against them.
+    pub fn parse(data: &[u8]) -> Result<FrameInfo, DecompressError> {
+        let header = match parse_start(data)? {
+            FrameStart::Full(header) => header,
+            FrameStart::Small(small) => return Ok(FrameInfo::small(&small)),
+        };
+        let mut idx = header.len;
+        let mut block_count = 0u32;
+        let mut raw_total = 0u64;
+        while let Some(block) = parse_block_header(data, idx, &header)? {
+            idx += header.block_header_len() + block.comp_len;
+            if idx > data.len() {
+                return Err(DecompressError::Truncated { offset: data.len() });
+            }
+            block_count += 1;
+            raw_total += block.raw_len as u64;
+        }
+        idx += 1;
+        let trailer = parse_trailer(data, idx, &header)?;
+        trailer.verify(block_count, raw_total, None, idx)?;
+        let compressed_size = (idx + header.trailer_len()) as u64;
+        Ok(FrameInfo::new(header, &trailer, compressed_size))
+    }
+
+    /// Reads only the header and the trailer, seeking past the blocks.
+    ///
+    /// The frame must start at the reader's current position and end at the
+    /// end of the stream. A small frame is read whole.
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<FrameInfo, DecompressError> {
+        let start = reader.stream_position()?;
+        let header = match read_start(&mut reader, &mut Vec::new())? {
+            FrameStart::Full(header) => header,
+            FrameStart::Small(small) => return Ok(FrameInfo::small(&small)),
+        };
+        let end = reader.seek(SeekFrom::End(0))?;
+        let frame_len = end - start;
+        let tail = (1 + header.trailer_len()) as u64;
+        if frame_len < header.len as u64 + tail {
+            return Err(DecompressError::TruncatedField { field: "trailer", offset: frame_len as usize });
+        }
+        reader.seek(SeekFrom::Start(end - tail))?;
+        let mut trailer_buf = vec![0u8; tail as usize];
+        reader.read_exact(&mut trailer_buf)?;
+        if trailer_buf[0] != BLOCK_END {
+            return Err(DecompressError::Corrupt {
+                offset: (frame_len - tail) as usize,
+                reason: "no end marker before trailer",
+            });
+        }
+        let trailer = parse_trailer(&trailer_buf, 1, &header)?;
+        Ok(FrameInfo::new(header, &trailer, frame_len))
+    }
+}
+
+/// Reads a header from `reader`, consuming exactly its encoded length, or a
+/// small frame, consuming all of it and leaving its bytes in `small`.
+pub(crate) fn read_start(reader: &mut impl Read, small: &mut Vec<u8>) -> Result<FrameStart, DecompressError> {
+    let mut buf = Vec::with_capacity(HEADER_LEN);
+    if read_more(reader, &mut buf, 1)? && is_small_frame(&buf) {
+        small.clear();
+        small.extend_from_slice(&buf);
+        return read_small_frame(reader, small).map(FrameStart::Small);
+    }
+    if read_more(reader, &mut buf, HEADER_LEN - 1)? {
+        let flags = buf[5];
+        for flag in [FLAG_FILENAME, FLAG_COMMENT] {
+            if flags & flag == 0 {
+                continue;
+            }
+            if !read_more(reader, &mut buf, 2)? {
+                break;
+            }
+            let len = u16::from_be_bytes(buf[buf.len() - 2..].try_into().unwrap()) as usize;
+            if !read_more(reader, &mut buf, len)? {
+                break;
+            }
+        }
+    }
+    // Parsing what was read reports the field that came up short.
+    parse_header(&buf).map(FrameStart::Full)
+}
+
+/// Reads the rest of the small frame whose marker is in `buf`, a byte at a
+/// time up to the end of its lengths so as not to read past a short frame.
+fn read_small_frame(reader: &mut impl Read, buf: &mut Vec<u8>) -> Result<SmallFrame, DecompressError> {
+    let lengths = if buf[0] & SMALL_RLE != 0 { 2 } else { 1 };
+    for _ in 0..lengths {
+        for _ in 0..VARINT_MAX_LEN {
+            if !read_more(reader, buf, 1)? || buf[buf.len() - 1] & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    let len = parse_small_lengths(buf)?.len;
+    read_more(reader, buf, len - buf.len())?;
+    parse_small_frame(buf)
+}
+
+/// Appends up to `n` bytes to `buf`; returns whether all of them arrived.
+fn read_more(reader: &mut impl Read, buf: &mut Vec<u8>, n: usize) -> io::Result<bool> {
+    let old = buf.len();
+    buf.resize(old + n, 0);
+    let got = read_full(reader, &mut buf[old..])?;
+    buf.truncate(old + got);
+    Ok(got == n)
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::compress_with_options;
+    use std::io::Cursor;
+
+    /// A 4 KiB-block frame with no optional fields and one stored block, "abc".
+    const PLAIN: [u8; 35] = [
+        b'A', b'A', b'P', b'C', 1, 0, 0, 0, 0x10, 0, // header
+        2, 0, 0, 0, 3, 0, 0, 0, 3, b'a', b'b', b'c', // block
+        0, // end
+        0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3, // trailer
+    ];
+
+    /// Parses `frame` both ways, which must agree.
+    fn parse(frame: &[u8]) -> FrameInfo {
+        let info = FrameInfo::parse(frame).unwrap();
+        assert_eq!(FrameInfo::from_reader(Cursor::new(frame)).unwrap(), info);
+        info
+    }
+
+    #[test]
+    fn plain_fixture() {
+        let expected =
+            FrameInfo { block_size: 4096, block_count: 1, content_size: 3, compressed_size: 35, ..FrameInfo::default() };
+        assert_eq!(parse(&PLAIN), expected);
+    }
+
+    #[test]
+    fn every_optional_field() {
+        let data = vec![b'z'; 10_000];
+        let opts = CompressOptions {
+            block_size: 4096,
+            filename: Some("z.txt".to_string()),
+            comment: Some("ten thousand z".to_string()),
+            ..CompressOptions::default()
+        };
+        let frame = compress_with_options(&data, &opts).unwrap();
+        let expected = FrameInfo {
+            version: VERSION,
+            flags: FLAG_BLOCK_CHECKSUM | FLAG_CONTENT_CHECKSUM | FLAG_FILENAME | FLAG_COMMENT,
+            block_size: 4096,
+            block_count: 3,
+            content_size: 10_000,
+            compressed_size: frame.len() as u64,
+            checksum_type: ChecksumType::Crc32,
+            block_checksums: true,
+            content_checksum: Some(crc32(&data)),
+            filename: Some("z.txt".to_string()),
+            comment: Some("ten thousand z".to_string()),
+            small: false,
+        };
+        assert_eq!(parse(&frame), expected);
+    }
+
+    #[test]
+    fn content_checksum_only() {
+        let opts = CompressOptions { block_checksums: false, small_frames: false, ..CompressOptions::default() };
+        let info = parse(&compress_with_options(b"abc", &opts).unwrap());
+        assert_eq!(info.flags, FLAG_CONTENT_CHECKSUM);
+        assert_eq!(info.checksum_type, ChecksumType::Crc32);
+        assert!(!info.block_checksums);
+        assert_eq!(info.content_checksum, Some(crc32(b"abc")));
+    }
+
+    #[test]
+    fn small_frame() {
+        let frame = compress_with_options(b"abc", &CompressOptions::default()).unwrap();
+        let expected = FrameInfo {
+            flags: FLAG_BLOCK_CHECKSUM | FLAG_CONTENT_CHECKSUM,
+            block_size: SMALL_FRAME_LIMIT,
+            block_count: 1,
+            content_size: 3,
+            compressed_size: frame.len() as u64,
+            checksum_type: ChecksumType::Crc32,
+            block_checksums: true,
+            content_checksum: Some(crc32(b"abc")),
+            small: true,
+            ..FrameInfo::default()
+        };
+        assert_eq!(parse(&frame), expected);
+    }
+
+    #[test]
+    fn truncated_header_names_the_field() {
+        let opts = CompressOptions {
+            filename: Some("name".to_string()),
+            comment: Some("note".to_string()),
+            ..CompressOptions::default()
+        };
+        let frame = compress_with_options(&[1; 5000], &opts).unwrap();
+        let cuts = [(2, "magic"), (4, "version"), (5, "flags"), (8, "block size"), (11, "filename"), (14, "filename"),
+                    (16, "comment"), (21, "comment")];
+        for (len, name) in cuts {
+            let cut = &frame[..len];
+            for err in [FrameInfo::parse(cut).unwrap_err(), FrameInfo::from_reader(Cursor::new(cut)).unwrap_err()] {
+                match err {
+                    DecompressError::TruncatedField { field, offset } => {
+                        assert_eq!((field, offset), (name, len), "cut at {}", len);
+                    }
+                    other => panic!("cut at {}: {:?}", len, other),
+                }
+            }
+        }
+        let err = FrameInfo::from_reader(Cursor::new(&PLAIN[..12])).unwrap_err();
+        assert!(matches!(err, DecompressError::TruncatedField { field: "trailer", offset: 12 }), "{:?}", err);
+    }
+
+    #[test]
+    fn bad_starts() {
+        assert!(matches!(FrameInfo::parse(b"AAPX"), Err(DecompressError::BadMagic)));
+        let mut future = PLAIN;
+        future[4] = 2;
+        assert!(matches!(FrameInfo::parse(&future), Err(DecompressError::UnsupportedVersion(2))));
+        let mut flags = PLAIN;
+        flags[5] = 0x80;
+        assert!(matches!(FrameInfo::parse(&flags), Err(DecompressError::Corrupt { offset: 5, .. })));
+        let mut count = PLAIN;
+        count[26] = 2;
+        assert!(FrameInfo::parse(&count).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn frame_info_json_round_trip() {
+        let opts = CompressOptions {
+            filename: Some("a.txt".to_string()),
+            comment: Some("note".to_string()),
+            ..CompressOptions::default()
+        };
+        let info = FrameInfo::parse(&compress_with_options(&[7; 10_000], &opts).unwrap()).unwrap();
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["checksum_type"], "crc32");
+        assert_eq!(json["filename"], "a.txt");
+        assert_eq!(json["content_size"], 10_000);
+        assert_eq!(serde_json::from_value::<FrameInfo>(json).unwrap(), info);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn frame_info_ignores_unknown_fields() {
+        let info: FrameInfo = serde_json::from_str(r#"{"version": 1, "content_size": 5, "codec": "rle"}"#).unwrap();
+        assert_eq!(info, FrameInfo { version: 1, content_size: 5, ..FrameInfo::default() });
+    }
+}
@@ -0,0 +1,64 @@
+//! Seeded test data for the CLI's `test` and `crash-test` subcommands and for
+//! the benchmarks, which include this file, so both measure the same corpora.
+//! Not part of the library; `tests/generate.rs` includes it the same way.
+
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// Shapes of generated test data.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// Long runs of one byte
+    Runs,
+    /// Uniformly random bytes
+    Random,
+    /// Letters and punctuation, a few of them far more common than the rest
+    Text,
+    /// Mostly zeros with scattered random bytes
+    Sparse,
+    /// Short runs alternating with random stretches
+    Mixed,
+}
+
+/// Exactly `size` bytes of `profile` data from `rng`.
+pub fn generate(profile: Profile, size: usize, rng: &mut StdRng) -> Vec<u8> {
+    // Roughly Zipf-distributed: cubing a uniform sample favours the
+    // front of the list.
+    const TEXT: &[u8] = b" etaoinshrdlcumwfgypbvkjxqz\n.,ETAOINSHRDLCUMWFGYPBVKJXQZ0123456789;:'\"!?-()";
+    let mut data: Vec<u8> = Vec::with_capacity(size);
+    while data.len() < size {
+        match profile {
+            Profile::Runs => {
+                let byte: u8 = rng.gen();
+                let run_len = rng.gen_range(100..10_000);
+                data.resize(data.len() + run_len, byte);
+            }
+            Profile::Random => {
+                let mut chunk = [0u8; 4096];
+                rng.fill(&mut chunk[..]);
+                data.extend_from_slice(&chunk);
+            }
+            Profile::Text => {
+                let pick: f64 = rng.gen();
+                data.push(TEXT[(pick * pick * pick * TEXT.len() as f64) as usize]);
+            }
+            Profile::Sparse => {
+                let zeros = rng.gen_range(0..512);
+                data.resize(data.len() + zeros, 0);
+                data.push(rng.gen_range(1..=255));
+            }
+            // Enhanced test data: mix repetitive and random for realism
+            Profile::Mixed => {
+                let repeat_byte: u8 = rng.gen();
+                let run_len = rng.gen_range(1..100);
+                data.resize(data.len() + run_len, repeat_byte);
+                for _ in 0..rng.gen_range(1..50) {
+                    data.push(rng.gen());
+                }
+            }
+        }
+    }
+    data.truncate(size);
+    data
+}
@@ -0,0 +1,336 @@
+//! Block tables for random access, and their sidecar file format.
+//!
+//! ```text
+//! sidecar: magic "AAPX" | version u8 | entry count u32 | entries | crc32 u32
+//! entry:   block type u8 | has checksum u8 | payload offset u64
+//!          | compressed len u32 | raw len u32 | checksum u32
+//! ```
+//!
+//! The trailing CRC-32 covers every byte before it. Uncompressed offsets are
+//! not stored; they are the running sum of the raw lengths.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::checksum::crc32;
+use crate::decompression::decode_payload;
+use crate::error::DecompressError;
+use crate::frame::{self, BlockHeader, FrameStart, BLOCK_END};
+
+/// Identifies a sidecar index file.
+pub const INDEX_MAGIC: [u8; 4] = *b"AAPX";
+/// Current sidecar format version.
+pub const INDEX_VERSION: u8 = 1;
+
+const ENTRY_LEN: usize = 22;
+
+/// Location of one block in a compressed file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockEntry {
+    pub block_type: u8,
+    /// Position of the block's payload in the file the table was built from.
+    pub payload_offset: u64,
+    pub comp_len: u32,
+    pub raw_len: u32,
+    /// Position of the block's first byte in the uncompressed content.
+    pub raw_offset: u64,
+    /// Stored CRC-32 of the uncompressed block, if the frame has block checksums.
+    pub checksum: Option<u32>,
+}
+
+impl BlockEntry {
+    fn header(&self) -> BlockHeader {
+        BlockHeader {
+            block_type: self.block_type,
+            comp_len: self.comp_len as usize,
+            raw_len: self.raw_len as usize,
+            checksum: self.checksum,
+        }
+    }
+}
+
+/// Every block of a frame, in order, without any payload data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockTable {
+    entries: Vec<BlockEntry>,
+}
+
+impl BlockTable {
+    /// Scans the frame starting at the reader's position, reading only headers
+    /// and seeking over payloads. Offsets are positions in `reader`. A small
+    /// frame is read whole, and has one entry unless it is empty.
+    pub fn build<R: Read + Seek>(mut reader: R) -> Result<BlockTable, DecompressError> {
+        let start = reader.stream_position()?;
+        let header = match frame::read_start(&mut reader, &mut Vec::new())? {
+            FrameStart::Full(header) => header,
+            FrameStart::Small(small) => {
+                let block = small.block;
+                let entry = BlockEntry {
+                    block_type: block.block_type,
+                    payload_offset: start + small.payload_at as u64,
+                    comp_len: block.comp_len as u32,
+                    raw_len: block.raw_len as u32,
+                    raw_offset: 0,
+                    checksum: block.checksum,
+                };
+                return Ok(BlockTable { entries: (block.raw_len > 0).then_some(entry).into_iter().collect() });
+            }
+        };
+        let mut pos = start + header.len as u64;
+        let mut raw_offset = 0u64;
+        let mut entries = Vec::new();
+        let mut buf = vec![0u8; header.block_header_len()];
+        loop {
+            let truncated = |e: io::Error| match e.kind() {
+                io::ErrorKind::UnexpectedEof => DecompressError::Truncated { offset: (pos - start) as usize },
+                _ => DecompressError::Io(e),
+            };
+            reader.read_exact(&mut buf[..1]).map_err(truncated)?;
+            if buf[0] == BLOCK_END {
+                break;
+            }
+            reader.read_exact(&mut buf[1..]).map_err(truncated)?;
+            let block = frame::parse_block_header(&buf, 0, &header)
+                .map_err(|e| e.shifted((pos - start) as usize))?
+                .expect("not an end marker");
+            let payload_offset = pos + buf.len() as u64;
+            entries.push(BlockEntry {
+                block_type: block.block_type,
+                payload_offset,
+                comp_len: block.comp_len as u32,
+                raw_len: block.raw_len as u32,
+                raw_offset,
+                checksum: block.checksum,
+            });
+            raw_offset += block.raw_len as u64;
+            pos = reader.seek(SeekFrom::Start(payload_offset + block.comp_len as u64))?;
+        }
+        let mut trailer = vec![0u8; header.trailer_len()];
+        reader.read_exact(&mut trailer).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DecompressError::TruncatedField {
+                field: "trailer",
+                offset: (pos - start + 1) as usize,
+            },
+            _ => DecompressError::Io(e),
+        })?;
+        let trailer = frame::parse_trailer(&trailer, 0, &header)?;
+        trailer.verify(entries.len() as u32, raw_offset, None, (pos - start + 1) as usize)?;
+        Ok(BlockTable { entries })
+    }
+
+    pub fn entries(&self) -> &[BlockEntry] {
+        &self.entries
+    }
+
+    /// Uncompressed size of the whole frame.
+    pub fn content_size(&self) -> u64 {
+        self.entries.last().map_or(0, |e| e.raw_offset + e.raw_len as u64)
+    }
+
+    /// Writes the table in the sidecar format.
+    pub fn save<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(9 + self.entries.len() * ENTRY_LEN + 4);
+        buf.extend_from_slice(&INDEX_MAGIC);
+        buf.push(INDEX_VERSION);
+        buf.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            buf.push(entry.block_type);
+            buf.push(entry.checksum.is_some() as u8);
+            buf.extend_from_slice(&entry.payload_offset.to_be_bytes());
+            buf.extend_from_slice(&entry.comp_len.to_be_bytes());
+            buf.extend_from_slice(&entry.raw_len.to_be_bytes());
+            buf.extend_from_slice(&entry.checksum.unwrap_or(0).to_be_bytes());
+        }
+        let crc = crc32(&buf);
+        buf.extend_from_slice(&crc.to_be_bytes());
+        out.write_all(&buf)
+    }
+
+    /// Reads a table written by [`BlockTable::save`].
+    pub fn load<R: Read>(mut input: R) -> Result<BlockTable, DecompressError> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        if data.get(..4).is_some_and(|magic| magic != INDEX_MAGIC) {
+            return Err(DecompressError::BadMagic);
+        }
+        let field = |offset: usize, n: usize, name: &'static str| {
+            data.get(offset..offset + n)
+                .ok_or(DecompressError::TruncatedField { field: name, offset: data.len() })
+        };
+        field(0, 4, "index magic")?;
+        let version = field(4, 1, "index version")?[0];
+        if version != INDEX_VERSION {
+            return Err(DecompressError::UnsupportedVersion(version));
+        }
+        let count = u32::from_be_bytes(field(5, 4, "entry count")?.try_into().unwrap()) as usize;
+        let body_len = 9 + count * ENTRY_LEN;
+        let stored = u32::from_be_bytes(field(body_len, 4, "index checksum")?.try_into().unwrap());
+        let actual = crc32(&data[..body_len]);
+        if stored != actual {
+            return Err(DecompressError::FrameChecksumMismatch { expected: stored, actual });
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        let mut raw_offset = 0u64;
+        for raw in data[9..body_len].chunks_exact(ENTRY_LEN) {
+            let u32_at = |i: usize| u32::from_be_bytes(raw[i..i + 4].try_into().unwrap());
+            let entry = BlockEntry {
+                block_type: raw[0],
+                payload_offset: u64::from_be_bytes(raw[2..10].try_into().unwrap()),
+                comp_len: u32_at(10),
+                raw_len: u32_at(14),
+                raw_offset,
+                checksum: (raw[1] != 0).then(|| u32_at(18)),
+            };
+            raw_offset += entry.raw_len as u64;
+            entries.push(entry);
+        }
+        Ok(BlockTable { entries })
+    }
+}
+
+/// Decompresses `range` of the content, reading only the blocks that overlap it.
+///
+/// `reader` must be the file `table` was built from. The range is clamped to
+/// the content size.
+pub fn decompress_range<R: Read + Seek>(
+    mut reader: R,
+    table: &BlockTable,
+    range: Range<u64>,
+) -> Result<Vec<u8>, DecompressError> {
+    let end = range.end.min(table.content_size());
+    let start = range.start.min(end);
+    let first = table.entries.partition_point(|e| e.raw_offset + (e.raw_len as u64) <= start);
+    let mut out = Vec::with_capacity((end - start) as usize);
+    let mut payload = Vec::new();
+    let mut block = Vec::new();
+
+    for (index, entry) in table.entries.iter().enumerate().skip(first) {
+        if entry.raw_offset >= end {
+            break;
+        }
+        payload.resize(entry.comp_len as usize, 0);
+        reader.seek(SeekFrom::Start(entry.payload_offset))?;
+        reader.read_exact(&mut payload).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DecompressError::Truncated { offset: entry.payload_offset as usize },
+            _ => DecompressError::Io(e),
+        })?;
+        let header = entry.header();
+        block.clear();
+        decode_payload(&header, index as u32, &payload, entry.payload_offset as usize, &mut block)?;
+        header.verify(index as u32, &block)?;
+
+        let from = start.saturating_sub(entry.raw_offset) as usize;
+        let to = ((end - entry.raw_offset) as usize).min(block.len());
+        out.extend_from_slice(&block[from..to]);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_with_options, decompress, CompressOptions};
+    use std::io::Cursor;
+
+    fn data() -> Vec<u8> {
+        (0..200_000u32).map(|i| if i % 2500 < 1500 { (i / 2500) as u8 } else { (i * 29 / 3) as u8 }).collect()
+    }
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let opts = CompressOptions { block_size: 3000, ..CompressOptions::default() };
+        compress_with_options(data, &opts).unwrap()
+    }
+
+    #[test]
+    fn random_ranges_match_the_full_content() {
+        let data = data();
+        let frame = frame(&data);
+        let table = BlockTable::build(Cursor::new(&frame)).unwrap();
+        assert_eq!(table.entries().len(), data.len().div_ceil(3000));
+        assert_eq!(table.content_size(), data.len() as u64);
+        let full = decompress(&frame).unwrap();
+
+        let mut state = 0x2545_F491u32;
+        let mut next = |bound: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            u64::from(state % bound)
+        };
+        for _ in 0..200 {
+            let start = next(data.len() as u32);
+            let end = start + next(10_000);
+            let range = decompress_range(Cursor::new(&frame), &table, start..end).unwrap();
+            let end = end.min(data.len() as u64);
+            assert_eq!(range, full[start as usize..end as usize], "{}..{}", start, end);
+        }
+        for (start, end) in [(0, 0), (2999, 3001), (0, u64::MAX), (u64::MAX - 1, u64::MAX)] {
+            let range = decompress_range(Cursor::new(&frame), &table, start..end).unwrap();
+            let (start, end) = (start.min(full.len() as u64) as usize, end.min(full.len() as u64) as usize);
+            assert_eq!(range, full[start.min(end)..end]);
+        }
+    }
+
+    #[test]
+    fn sidecar_round_trip() {
+        let data = data();
+        // The frame starts partway into the file.
+        let mut file = b"leading bytes".to_vec();
+        file.extend(frame(&data));
+        let mut reader = Cursor::new(&file);
+        reader.set_position(13);
+        let table = BlockTable::build(&mut reader).unwrap();
+        assert_eq!(table.entries()[0].payload_offset, 13 + 10 + 13);
+
+        let mut sidecar = Vec::new();
+        table.save(&mut sidecar).unwrap();
+        assert_eq!(&sidecar[..4], &INDEX_MAGIC);
+        assert_eq!(sidecar.len(), 9 + table.entries().len() * ENTRY_LEN + 4);
+        let loaded = BlockTable::load(sidecar.as_slice()).unwrap();
+        assert_eq!(loaded, table);
+        assert_eq!(decompress_range(Cursor::new(&file), &loaded, 10_000..10_100).unwrap(), data[10_000..10_100]);
+    }
+
+    #[test]
+    fn damaged_sidecars_are_rejected() {
+        let table = BlockTable::build(Cursor::new(frame(&data()))).unwrap();
+        let mut sidecar = Vec::new();
+        table.save(&mut sidecar).unwrap();
+
+        let mut flipped = sidecar.clone();
+        flipped[20] ^= 1;
+        assert!(matches!(BlockTable::load(flipped.as_slice()), Err(DecompressError::FrameChecksumMismatch { .. })));
+        let mut renamed = sidecar.clone();
+        renamed[0] = b'Z';
+        assert!(matches!(BlockTable::load(renamed.as_slice()), Err(DecompressError::BadMagic)));
+        let mut newer = sidecar.clone();
+        newer[4] = 9;
+        assert!(matches!(BlockTable::load(newer.as_slice()), Err(DecompressError::UnsupportedVersion(9))));
+        let cut = &sidecar[..sidecar.len() - 2];
+        assert!(matches!(BlockTable::load(cut), Err(DecompressError::TruncatedField { field: "index checksum", .. })));
+    }
+
+    #[test]
+    fn small_and_empty_frames() {
+        let small = compress_with_options(b"abcabc", &CompressOptions::default()).unwrap();
+        let table = BlockTable::build(Cursor::new(&small)).unwrap();
+        assert_eq!(table.entries().len(), 1);
+        assert_eq!(decompress_range(Cursor::new(&small), &table, 2..5).unwrap(), b"cab");
+
+        let empty = compress_with_options(b"", &CompressOptions::default()).unwrap();
+        let table = BlockTable::build(Cursor::new(&empty)).unwrap();
+        assert_eq!((table.entries().len(), table.content_size()), (0, 0));
+        assert!(decompress_range(Cursor::new(&empty), &table, 0..10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_stale_table_fails_its_checksums() {
+        let data = data();
+        let table = BlockTable::build(Cursor::new(frame(&data))).unwrap();
+        let other = frame(&data.iter().map(|b| b ^ 0x55).collect::<Vec<_>>());
+        assert!(decompress_range(Cursor::new(&other), &table, 0..100).is_err());
+    }
+}
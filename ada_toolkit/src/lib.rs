@@ -0,0 +1,96 @@
+//! Ada's Adaptive Pattern Compressor (AAPC).
+//!
+//! One-shot [`compress`]/[`decompress`] plus the streaming [`stream::AapcWriter`]
+//! (resumable from a [`ResumePoint`]) and [`stream::AapcReader`].
+//! [`DecodedBlocks`] walks a frame one block at a time, and [`envelope`]
+//! wraps frames for embedding in other streams. [`archive`]
+//! packs many named files into one, and [`BatchWriter`] hands output on in
+//! few, large writes.
+//!
+//! Optional features:
+//! - `async`: tokio `AsyncAapcWriter`/`AsyncAapcReader` in [`async_stream`].
+//! - `serde`: Serialize/Deserialize for [`CompressOptions`], [`CompressionStats`],
+//!   [`frame::FrameInfo`], [`ResumePoint`], [`TokenCounts`] and
+//!   [`error::ErrorSummary`].
+//! - `tracing`: `tracing` spans around frame encode/decode (debug level) and
+//!   each block (trace level, with index, sizes and codec). Compiled out
+//!   entirely when the feature is off.
+//! - `log`: a `log` debug record per block encoded or decoded, for
+//!   embedders who collect diagnostics through the `log` facade.
+//! - `bytes`: `bytes::Bytes` helpers in [`buffers`], including zero-copy
+//!   iteration over stored blocks.
+//! - `ffi`: C streaming API in [`ffi`] (header in `include/aapc.h`); build
+//!   as a `cdylib` or `staticlib`.
+//! - `python`: PyO3 extension module `ada_compression` (build with maturin,
+//!   `module-name = "ada_compression"`).
+//! - `bench`: gzip (flate2), zstd and lz4 (lz4_flex) for the CLI's `bench`
+//!   subcommand to compare against; without it only AAPC is benchmarked.
+
+pub mod archive;
+pub mod batch;
+pub mod blocks;
+pub mod cancel;
+pub mod checksum;
+pub mod compression;
+pub mod decompression;
+pub mod envelope;
+pub mod error;
+pub mod estimate;
+pub mod frame;
+pub mod index;
+pub mod options;
+mod pool;
+pub mod stats;
+pub mod stream;
+
+#[cfg(feature = "async")]
+pub mod async_stream;
+#[cfg(feature = "bytes")]
+pub mod buffers;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
+
+pub use archive::{read_members, write_archive, write_archive_concurrent, Archive, Member, MemberKind};
+pub use batch::BatchWriter;
+pub use blocks::DecodedBlocks;
+pub use cancel::CancelToken;
+pub use compression::{
+    compress, compress_bound, compress_into, compress_with_options, compress_with_progress, compress_with_stats,
+};
+pub use decompression::{
+    count_tokens, decompress, decompress_into, decompress_to_writer, decompress_visit, decompress_with_options,
+    decompress_with_progress, validate, TokenCounts,
+};
+pub use envelope::{read_frame, skip_frame, write_frame};
+pub use error::{CompressError, DecompressError};
+pub use estimate::{estimate_ratio, estimate_ratio_with_options, RatioEstimate};
+pub use frame::{ChecksumType, FrameInfo};
+pub use index::{decompress_range, BlockTable};
+pub use options::{
+    largest_block_size_within, memory_for_archive, memory_for_block_size, memory_for_pipeline, memory_for_threads,
+    CompressOptions, DecompressOptions,
+};
+pub use stats::{CompressionStats, PhaseTimes, PoolStats, Progress};
+pub use stream::{
+    copy_decode, copy_decode_with_options, copy_decode_with_phases, copy_encode, copy_encode_pipelined, copy_encode_slice,
+    validate_reader, ResumePoint, StreamDecoder,
+};
+
+// Settings and results are plain immutable data, so one value can be shared
+// behind an `Arc` by any number of encoding threads; per-call mutable state
+// lives inside the encoders and decoders themselves.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CompressOptions>();
+    assert_send_sync::<DecompressOptions>();
+    assert_send_sync::<CancelToken>();
+    assert_send_sync::<CompressionStats>();
+    assert_send_sync::<FrameInfo>();
+    assert_send_sync::<CompressError>();
+    assert_send_sync::<DecompressError>();
+    assert_send_sync::<stream::AapcWriter<Vec<u8>>>();
+    assert_send_sync::<stream::AapcReader<&[u8]>>();
+    assert_send_sync::<StreamDecoder>();
+};
@@ -1,258 +1,1398 @@
-use clap::{Parser, Subcommand};
-use rand::Rng;
-use std::fs::{self, read, write};
-use std::io::{self};
-use std::path::Path;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
 
-mod compression;
-mod decompression;
+use ada_toolkit::{
+    estimate_ratio_with_options, frame, largest_block_size_within, memory_for_threads, BlockTable, CancelToken,
+    CompressError, CompressOptions, DecompressError,
+};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use indicatif::MultiProgress;
+
+mod checkpoint;
+mod commands;
+mod generate;
+mod output;
+mod progress;
+mod report;
+#[cfg(test)]
+mod temp_dir;
+mod workers;
+
+use generate::Profile;
+use crate::checkpoint::Checkpointing;
+use crate::commands::archive::{create_archive, extract_archive, list_archive};
+use crate::commands::batch::run_manifest;
+use crate::commands::bench::{run_bench, run_corpus_bench};
+use crate::commands::cat::cat_files;
+use crate::commands::compare::compare;
+use crate::commands::compress::{run_batch, stored_name, Writing};
+use crate::commands::crash_test::run_crash_test;
+use crate::commands::dir::{compress_dir, decompress_dir, Filter};
+use crate::commands::info::{inspect, show_info};
+use crate::commands::tar::{compress_tar, decompress_tar};
+use crate::commands::test::{run_file_test, run_generated_test};
+use crate::commands::test_folder::run_folder_test;
+use crate::commands::verify::verify_files;
+use crate::commands::watch::watch;
+use crate::output::{remove_pending, AtomicFile, Output, IO_SIZE, STDIO};
+use crate::progress::{saved, Units};
+use crate::report::json_string;
 
 #[derive(Parser)]
 #[command(name = "Ada_compression")]
 #[command(about = "Ada's Adaptive Pattern Compressor CLI", long_about = None)]
 #[command(version)]
+#[command(after_help = "Exit status:
+    0  success
+    1  I/O error (missing file, permission denied, disk full, ...)
+    2  usage error, or work that cannot fit in --max-memory
+    3  corrupt or invalid compressed data
+    4  checksum mismatch
+  130  interrupted with Ctrl+C; the output being written is discarded
+  141  stdout was closed early, as for a process killed by SIGPIPE")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    /// Enable verbose output
+    #[command(flatten)]
+    global: Global,
+}
+
+/// Options accepted by every subcommand.
+#[derive(Args)]
+struct Global {
+    /// Enable verbose output, such as a line per block compressed; the same
+    /// as --log-level debug
     #[arg(long, global = true)]
     verbose: bool,
+
+    /// Most detailed diagnostics to print on stderr; RUST_LOG, as read by
+    /// env_logger, applies when neither this nor --verbose is given
+    #[arg(long, global = true, value_enum, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// Print nothing but errors; the exit status tells how it went
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// How to print run summaries
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Show progress even when stderr is not a terminal, as plain lines
+    #[arg(long, global = true, conflicts_with = "no_progress")]
+    progress: bool,
+
+    /// Never show progress
+    #[arg(long, global = true)]
+    no_progress: bool,
+
+    /// Leave out the banner and progress bars even at a terminal, as when
+    /// output is redirected or NO_COLOR is set
+    #[arg(long, global = true, visible_alias = "no-color")]
+    plain: bool,
+
+    /// Print sizes as plain byte counts and speeds in bytes/s
+    #[arg(long, global = true)]
+    bytes: bool,
+
+    /// Most threads to use: compression encodes blocks on them, or several
+    /// files at once when given many, decompression decodes blocks on them,
+    /// and test-folder tests files on them; 0 or "auto" means one per core,
+    /// and 1 keeps everything sequential
+    #[arg(long, global = true, value_name = "N", value_parser = parse_threads, default_value = "auto")]
+    threads: usize,
+
+    /// Most memory to use at once, with an optional k, M or G suffix: the
+    /// default block size and test-folder's threads shrink to fit, and
+    /// anything that still cannot fails before it starts
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    max_memory: Option<usize>,
+
+    /// Output to collect before writing it out, with an optional k, M or G
+    /// suffix, so that it goes out in few large writes, which helps on
+    /// network filesystems; held on top of --max-memory, and 0 writes each
+    /// piece as it comes
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size, default_value = "4M")]
+    io_size: usize,
+
+    /// When another process is writing the same output, wait up to SECONDS
+    /// for it to finish instead of failing at once
+    #[arg(long, global = true, value_name = "SECONDS", value_parser = parse_seconds)]
+    wait: Option<Duration>,
+}
+
+/// Parses a thread count, where `auto` means 0.
+fn parse_threads(s: &str) -> Result<usize, String> {
+    match s {
+        "auto" => Ok(0),
+        n => n.parse().map_err(|_| format!("{:?} is not a thread count or \"auto\"", s)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Human-readable lines
+    Text,
+    /// One JSON document on stdout (stderr when stdout carries data)
+    Json,
+}
+
+/// How much `--log-level` lets through, from nothing to every block.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    Off,
+    Error,
+    /// Fallbacks and settings that had no effect (the default)
+    Warn,
+    /// A line per file
+    Info,
+    /// Details of each step, and each block with the library's `log` feature
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(self) -> log::LevelFilter {
+        match self {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// A block of labelled lines per file
+    Text,
+    /// A header row, then one row per file
+    Csv,
+    /// One JSON object per line, each tagged with the run it came from
+    Jsonl,
+}
+
+/// A codec for `bench`; all but AAPC need the `bench` feature.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Codec {
+    Aapc,
+    /// flate2 at its default level
+    Gzip,
+    /// zstd at level 3
+    Zstd,
+    /// lz4_flex block format
+    Lz4,
+}
+
+/// A set of files for `corpus-bench`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Corpus {
+    /// The Canterbury corpus, as unpacked from cantrbry.tar.gz: 11 files
+    Canterbury,
+    /// The Silesia corpus, as unpacked from silesia.zip: 12 files
+    Silesia,
+    /// Every regular file directly in the directory, whatever it holds
+    Dir,
+}
+
+impl Corpus {
+    fn name(self) -> &'static str {
+        match self {
+            Corpus::Canterbury => "Canterbury",
+            Corpus::Silesia => "Silesia",
+            Corpus::Dir => "directory",
+        }
+    }
+
+    /// The files of a standard corpus and their sizes in bytes; empty for
+    /// a plain directory.
+    fn files(self) -> &'static [(&'static str, u64)] {
+        match self {
+            Corpus::Canterbury => &[
+                ("alice29.txt", 152_089),
+                ("asyoulik.txt", 125_179),
+                ("cp.html", 24_603),
+                ("fields.c", 11_150),
+                ("grammar.lsp", 3_721),
+                ("kennedy.xls", 1_029_744),
+                ("lcet10.txt", 426_754),
+                ("plrabn12.txt", 481_861),
+                ("ptt5", 513_216),
+                ("sum", 38_240),
+                ("xargs.1", 4_227),
+            ],
+            Corpus::Silesia => &[
+                ("dickens", 10_192_446),
+                ("mozilla", 51_220_480),
+                ("mr", 9_970_564),
+                ("nci", 33_553_445),
+                ("ooffice", 6_152_192),
+                ("osdb", 10_085_684),
+                ("reymont", 6_627_202),
+                ("samba", 21_606_400),
+                ("sao", 7_251_944),
+                ("webster", 41_458_703),
+                ("x-ray", 8_474_240),
+                ("xml", 5_345_280),
+            ],
+            Corpus::Dir => &[],
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Compress a file
-    Compress {
-        /// Input file path
-        input: String,
-        /// Output file path
-        output: String,
-    },
-    /// Decompress a file
-    Decompress {
-        /// Input file path
-        input: String,
-        /// Output file path
-        output: String,
-    },
+    /// Compress files
+    Compress(CompressArgs),
+    /// Decompress files, or every .aapc file in a directory
+    Decompress(DecompressArgs),
     /// Run tests (generated data, or specify a file)
     Test {
         /// Optional: Path to a real file for testing
         file: Option<String>,
+        /// Seed for the generated data, so a run can be repeated exactly;
+        /// a random seed is chosen and printed otherwise
+        #[arg(long, conflicts_with = "file")]
+        seed: Option<u64>,
+        /// How much data to generate, with an optional k, M or G suffix
+        #[arg(long, value_parser = parse_size, default_value = "1M", conflicts_with = "file")]
+        size: usize,
+        /// What kind of data to generate
+        #[arg(long, value_enum, default_value_t = Profile::Mixed, conflicts_with = "file")]
+        profile: Profile,
+        /// Timed runs of compression and decompression; above 1, after one
+        /// warm-up run and reported as min, median, mean and spread. The
+        /// output is still verified once
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+    /// Test every file in one or more folders
+    TestFolder(FolderArgs),
+    /// Feed randomly damaged copies of a compressed input to the decoder
+    /// and check that every one is rejected or decodes to the original
+    CrashTest(CrashArgs),
+    /// Round-trip every file of a corpus kept in a local directory and
+    /// report per-file and overall ratios and speeds, for numbers that can
+    /// be set beside published results
+    CorpusBench {
+        /// Directory holding the corpus files
+        path: PathBuf,
+        /// Which corpus PATH holds; a standard one must have all of its
+        /// files at their published sizes, and other files are left out
+        #[arg(long, value_enum, default_value_t = Corpus::Dir)]
+        corpus: Corpus,
+    },
+    /// Compare AAPC with other codecs on one file
+    Bench {
+        /// Input file path
+        file: String,
+        /// Codecs to run, comma-separated [default: every codec built in]
+        #[arg(long, value_enum, value_delimiter = ',')]
+        codecs: Vec<Codec>,
+        /// Timed runs of each codec, after one warm-up run
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+    /// Estimate the compression ratio of a file by sampling it
+    Estimate {
+        /// Input file path
+        file: String,
+        /// Bytes to sample from evenly spaced regions of the file
+        #[arg(long, default_value_t = 4 * 1024 * 1024)]
+        sample_bytes: usize,
+    },
+    /// Show frame metadata without decompressing
+    Info {
+        /// Compressed file path
+        file: String,
+    },
+    /// Check a compressed file's structure and checksums without writing output
+    Verify {
+        /// Compressed file paths, or - for stdin
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Check whether two compressed files decode to the same content,
+    /// without writing either out. Exits 0 if they do and 1 if not; errors
+    /// exit 2 or more, an I/O error with 2
+    Compare {
+        /// Compressed file paths
+        a: String,
+        b: String,
+        /// Decide from the content sizes and checksums stored in both
+        /// trailers without decoding, when both frames have them; this
+        /// cannot say where the contents differ
+        #[arg(long)]
+        quick: bool,
+    },
+    /// Decompress files to stdout, one after another
+    Cat {
+        /// Compressed file paths, or - for stdin
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Compress every regular file under SRC into a mirrored tree under DST
+    CompressDir(DirArgs),
+    /// Compress each source listed in a manifest to its own destination
+    Batch(BatchArgs),
+    /// Compress files under DIR into a mirrored tree as they appear or
+    /// change, until interrupted
+    Watch(WatchArgs),
+    /// Pack files into one archive, or list or extract one
+    Archive {
+        #[command(subcommand)]
+        command: ArchiveCommand,
+    },
+    /// List every block of a compressed file: offsets, sizes, ratio, block
+    /// type and checksum
+    Inspect {
+        /// Compressed file path
+        file: String,
+        /// Also count each RLE block's run tokens, literals and escapes
+        #[arg(long)]
+        opcodes: bool,
+    },
+    /// Write a block index for random access to <file>.idx
+    Index {
+        /// Compressed file path
+        file: String,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
     },
-    /// Test all files in the 'test_data' folder
-    TestFolder,
 }
 
-fn main() -> io::Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum ArchiveCommand {
+    /// Pack files and directory trees into ARCHIVE
+    Create {
+        /// Archive file to write
+        archive: String,
+        /// Files and directories to add, each stored under its base name
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Overwrite an existing archive
+        #[arg(short = 'f', long)]
+        force: bool,
+        #[command(flatten)]
+        filter: Filter,
+        /// Leave out files that look already compressed, as for
+        /// compress --skip-compressed
+        #[arg(long)]
+        skip_compressed: bool,
+        #[command(flatten)]
+        check: WriteCheck,
+    },
+    /// Show ARCHIVE's members without decompressing them
+    List {
+        /// Archive file to read
+        archive: String,
+        /// Also show permission bits and checksums
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Restore members of ARCHIVE, all of them unless some are selected
+    Extract {
+        /// Archive file to read
+        archive: String,
+        /// Members to extract; a directory selects everything under it
+        members: Vec<String>,
+        /// Also extract members matching PATTERN (* and ? stay within one
+        /// path component, ** crosses them)
+        #[arg(long = "glob", value_name = "PATTERN")]
+        globs: Vec<String>,
+        /// Directory to extract into
+        #[arg(short = 'C', long, value_name = "DIR", default_value = ".")]
+        directory: PathBuf,
+        /// Overwrite existing files
+        #[arg(short = 'f', long)]
+        force: bool,
+        /// Also recreate symlinks with an absolute target, which can point
+        /// anywhere on this system; they are skipped with a warning otherwise
+        #[arg(long)]
+        absolute_symlinks: bool,
+    },
+}
 
-    match cli.command {
-        Commands::Compress { input, output } => {
-            if cli.verbose {
-                println!("Verbose: Reading input file {}", input);
-            }
-            let data = match read(&input) {
-                Ok(d) => d,
-                Err(e) => {
-                    eprintln!("Error reading input {}: {}", input, e);
-                    return Err(e);
-                }
-            };
-            let start = Instant::now();
-            let compressed = compression::compress(&data);
-            let duration = start.elapsed();
-            if cli.verbose {
-                println!("Verbose: Writing compressed output to {}", output);
-            }
-            match write(&output, &compressed) {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Error writing output {}: {}", output, e);
-                    return Err(e);
-                }
-            }
-            println!("Compressed {} ({} bytes) to {} ({} bytes) in {:?}. Ratio: {:.2}",
-                     input, data.len(), output, compressed.len(), duration,
-                     compressed.len() as f64 / data.len() as f64);
+/// What a run over many files does when one of them fails.
+#[derive(Args, Clone, Copy)]
+struct OnError {
+    /// Stop at the first file that fails
+    #[arg(long, conflicts_with = "continue_on_error")]
+    fail_fast: bool,
+    /// Carry on after a file fails, recording its error and failing the
+    /// run at the end; the default
+    #[arg(long)]
+    continue_on_error: bool,
+}
+
+impl OnError {
+    fn stops(self) -> bool {
+        self.fail_fast && !self.continue_on_error
+    }
+}
+
+#[derive(Args)]
+struct DirArgs {
+    /// Directory to compress
+    src: PathBuf,
+    /// Directory to write <path>.aapc files into; created if missing
+    dst: PathBuf,
+    /// Descend into symlinked directories and compress symlinked files
+    #[arg(long)]
+    follow_symlinks: bool,
+    #[command(flatten)]
+    on_error: OnError,
+    /// Overwrite existing output files
+    #[arg(short = 'f', long)]
+    force: bool,
+    /// Compress as usual but write nothing, not even DST, reporting the
+    /// sizes the outputs would have
+    #[arg(long)]
+    dry_run: bool,
+    #[command(flatten)]
+    filter: Filter,
+    #[command(flatten)]
+    incompressible: Incompressible,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// File of jobs, one per line: a source and its destination separated
+    /// by a tab, or by a comma in a .csv manifest (with CSV quoting and an
+    /// optional source,destination header). Relative paths resolve against
+    /// the manifest's directory; blank lines and lines starting with # are
+    /// skipped
+    #[arg(long, value_name = "FILE")]
+    manifest: PathBuf,
+    /// Carry on with the remaining jobs after one fails, instead of stopping
+    #[arg(long)]
+    continue_on_error: bool,
+    /// Create each destination's parent directories if missing
+    #[arg(long)]
+    parents: bool,
+    /// Overwrite existing destinations
+    #[arg(short = 'f', long)]
+    force: bool,
+    /// Compress as usual but write no destinations, reporting the sizes
+    /// they would have
+    #[arg(long)]
+    dry_run: bool,
+    /// Write a JSON report of the run to FILE: each job's outcome, sizes,
+    /// checksum and any error, the options used and totals
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    #[command(flatten)]
+    tuning: Tuning,
+    #[command(flatten)]
+    incompressible: Incompressible,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// Directory to watch, subdirectories included; files already in it
+    /// are left alone until they change
+    dir: PathBuf,
+    /// Directory to write <path>.aapc files into; created if missing
+    #[arg(long, value_name = "DIR")]
+    output_dir: PathBuf,
+    /// Seconds a file's size and modification time must stay the same
+    /// before it is compressed, so files still being written are left alone
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, default_value = "2")]
+    debounce: Duration,
+    /// Overwrite output files that already existed when watching started;
+    /// outputs written by this run are always replaced when their input changes
+    #[arg(short = 'f', long)]
+    force: bool,
+    #[command(flatten)]
+    filter: Filter,
+    #[command(flatten)]
+    tuning: Tuning,
+    #[command(flatten)]
+    incompressible: Incompressible,
+}
+
+#[derive(Args)]
+struct CrashArgs {
+    /// File whose compressed form is damaged
+    #[arg(required_unless_present = "generated")]
+    file: Option<String>,
+    /// Damage the compressed form of generated mixed data instead
+    #[arg(long, conflicts_with = "file")]
+    generated: bool,
+    /// How much data to generate, with an optional k, M or G suffix
+    #[arg(long, value_parser = parse_size, default_value = "256k", conflicts_with = "file")]
+    size: usize,
+    /// Damaged copies to try
+    #[arg(long, default_value_t = 1000)]
+    mutations: u32,
+    /// Seed for the data and the damage, so a run can be repeated exactly;
+    /// a random seed is chosen and printed otherwise
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Args)]
+struct FolderArgs {
+    /// Folders to test
+    #[arg(default_value = "test_data")]
+    folders: Vec<PathBuf>,
+    /// Also test files in subdirectories, with a subtotal per directory
+    #[arg(short, long)]
+    recursive: bool,
+    #[command(flatten)]
+    filter: Filter,
+    /// How to write the per-file log
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Where to write the per-file log
+    #[arg(long, value_name = "PATH", default_value = "test_log.txt")]
+    log_file: PathBuf,
+    /// Add this run's entries to the end of the log instead of replacing it
+    #[arg(long)]
+    log_append: bool,
+    /// Test the files symlinks point to and, with --recursive, descend into
+    /// symlinked directories; symlinks are skipped with a notice otherwise
+    #[arg(long)]
+    follow_symlinks: bool,
+    #[command(flatten)]
+    on_error: OnError,
+}
+
+#[derive(Args)]
+struct CompressArgs {
+    #[command(flatten)]
+    paths: Paths,
+    /// Compress a tar serialisation of a single input directory
+    #[arg(long, conflicts_with_all = ["rm", "output_dir"])]
+    tar: bool,
+    /// Save progress to FILE every few blocks, so that an interrupted run of
+    /// a single input file can be carried on with --resume FILE
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["tar", "stdout", "skip_compressed", "resume"])]
+    checkpoint: Option<PathBuf>,
+    /// Carry on the run saved in FILE by --checkpoint, with the same input,
+    /// output and --block-size; progress keeps being saved to FILE
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["tar", "stdout", "skip_compressed"])]
+    resume: Option<PathBuf>,
+    /// Compress as usual but write nothing, reporting the sizes the outputs
+    /// would have; no file is created, changed or removed
+    #[arg(long, conflicts_with_all = ["tar", "rm", "checkpoint", "resume", "verify_after_write"])]
+    dry_run: bool,
+    /// Map each input file into memory and encode it from there instead of
+    /// reading it; stdin, pipes and files that cannot be mapped are read as
+    /// usual. An input whose size or modification time changes meanwhile
+    /// fails, but one truncated mid-run can crash the process
+    #[arg(long, conflicts_with_all = ["tar", "checkpoint", "resume"])]
+    mmap: bool,
+    #[command(flatten)]
+    check: WriteCheck,
+    #[command(flatten)]
+    tuning: Tuning,
+    #[command(flatten)]
+    incompressible: Incompressible,
+}
+
+impl CompressArgs {
+    fn checkpointing(&self) -> Option<Checkpointing> {
+        match (&self.checkpoint, &self.resume) {
+            (_, Some(state)) => Some(Checkpointing { state: state.clone(), resume: true }),
+            (Some(state), None) => Some(Checkpointing { state: state.clone(), resume: false }),
+            (None, None) => None,
         }
-        Commands::Decompress { input, output } => {
-            if cli.verbose {
-                println!("Verbose: Reading compressed input {}", input);
-            }
-            let compressed = match read(&input) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Error reading input {}: {}", input, e);
-                    return Err(e);
-                }
-            };
-            let start = Instant::now();
-            let decompressed = decompression::decompress(&compressed);
-            let duration = start.elapsed();
-            if cli.verbose {
-                println!("Verbose: Writing decompressed output to {}", output);
+    }
+}
+
+/// What to do with inputs that are already compressed.
+#[derive(Args, Clone, Copy, Default)]
+struct Incompressible {
+    /// Leave out inputs that look already compressed: a known format such
+    /// as gzip, zip, JPEG or PNG, or random-looking data in the first 64 KiB
+    #[arg(long)]
+    skip_compressed: bool,
+    /// With --skip-compressed, store those inputs without compressing them
+    /// instead of leaving them out
+    #[arg(long, requires = "skip_compressed")]
+    store_incompressible: bool,
+    /// Compress inputs that are already AAPC frames or archives again; by
+    /// default a run over several files skips them, and a single file is
+    /// compressed again with a warning
+    #[arg(long)]
+    recompress: bool,
+}
+
+impl Incompressible {
+    /// The tail of a run's summary line saying what `--skip-compressed`
+    /// did, and how many AAPC inputs were skipped without it.
+    fn summary(&self, skipped: usize, stored: usize) -> String {
+        let mut tail = String::new();
+        if skipped > 0 || (self.skip_compressed && !self.store_incompressible) {
+            tail += &format!(", {} skipped as already compressed", skipped);
+        }
+        if self.skip_compressed && self.store_incompressible {
+            tail += &format!(", {} stored as already compressed", stored);
+        }
+        tail
+    }
+}
+
+/// Whether to read back what was written.
+#[derive(Args, Clone, Copy, Default)]
+struct WriteCheck {
+    /// Once each output is written and synced, and before it replaces the
+    /// destination, reopen it and decode all of it, checking every checksum
+    /// and that it holds exactly what was encoded; an output that fails is
+    /// removed and the destination left as it was. This catches corruption
+    /// on the way to the disk, though the system may still serve the read
+    /// from its cache
+    #[arg(long)]
+    verify_after_write: bool,
+    /// Keep an output that fails --verify-after-write under its temporary
+    /// name, for inspection
+    #[arg(long, requires = "verify_after_write")]
+    keep_corrupt: bool,
+}
+
+impl WriteCheck {
+    /// Commits `output`, reading it back with `verify` first if asked to and
+    /// returning whether it did: stdout and special files cannot be read
+    /// back.
+    fn commit(
+        &self,
+        output: Output,
+        name: &str,
+        verify: impl FnOnce(File) -> io::Result<()>,
+    ) -> Result<bool, Failure> {
+        if let Output::File(file) = output {
+            return self.commit_file(file, name, verify);
+        }
+        if self.verify_after_write {
+            let name = if name == STDIO { "stdout" } else { name };
+            log::warn!("Cannot verify {} after writing: it is not a regular file", name);
+        }
+        output.commit().map_err(|e| Failure::from(e).context("writing output", name))?;
+        Ok(false)
+    }
+
+    /// Renames `file` over `output`, reading it back with `verify` first if
+    /// asked to, while it is still locked, and returning whether it did. A
+    /// file that fails never replaces `output`, and is removed unless
+    /// `--keep-corrupt` was given.
+    fn commit_file(
+        &self,
+        mut file: AtomicFile,
+        output: &str,
+        verify: impl FnOnce(File) -> io::Result<()>,
+    ) -> Result<bool, Failure> {
+        let writing = |e: io::Error| Failure::from(e).context("writing output", output);
+        if !self.verify_after_write {
+            file.commit().map_err(writing)?;
+            return Ok(false);
+        }
+        file.sync().map_err(writing)?;
+        log::debug!("Reading back {} to verify it before it replaces {}", file.temp.display(), output);
+        let Err(e) = File::open(&file.temp).and_then(verify) else {
+            file.commit().map_err(writing)?;
+            return Ok(true);
+        };
+        let temp = file.keep();
+        let done = match self.keep_corrupt {
+            true => format!("kept the new output as {} (--keep-corrupt)", temp.display()),
+            false => match fs::remove_file(&temp) {
+                Ok(()) => "removed the new output".to_string(),
+                Err(e) => format!("could not remove the new output {}: {}", temp.display(), e),
+            },
+        };
+        let code = exit_code(&e);
+        let error = io::Error::new(e.kind(), format!("{}; left {} as it was and {}", e, output, done));
+        Err(Failure { code, error }.context("verifying output", output))
+    }
+}
+
+/// How the encoder trades speed for size.
+#[derive(Args, Default)]
+struct Tuning {
+    /// Uncompressed bytes per block, with an optional k or M suffix
+    /// (at most 16M); small blocks suit random access, big ones the ratio
+    /// [default: 256k, or less to fit --max-memory]
+    #[arg(long, value_name = "SIZE", value_parser = parse_block_size)]
+    block_size: Option<usize>,
+    /// Compression level from 1 (fastest) to 9 (smallest), also given as
+    /// -1 .. -9. Reserved: the codec has only one level, so giving any
+    /// level is a usage error
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=9))]
+    level: Option<u8>,
+    /// Same as --level 1
+    #[arg(long, conflicts_with_all = ["level", "best"])]
+    fast: bool,
+    /// Same as --level 9
+    #[arg(long, conflicts_with = "level")]
+    best: bool,
+}
+
+impl Tuning {
+    /// Encoder options for these settings under `max_memory`: --block-size,
+    /// or else the default shrunk to fit. Fails if they still need more
+    /// memory than that, as an explicit --block-size may, so callers can
+    /// check before writing anything. Exits with a usage error if a level
+    /// was given, rather than quietly ignoring it.
+    fn options(&self, max_memory: Option<usize>) -> io::Result<CompressOptions> {
+        let flag = match (self.level, self.fast, self.best) {
+            (Some(level), _, _) => Some(format!("--level {}", level)),
+            (_, true, _) => Some("--fast".to_string()),
+            (_, _, true) => Some("--best".to_string()),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            usage_error(&format!("{} has no effect: the codec has only one level; leave it out", flag));
+        }
+        let default = frame::DEFAULT_BLOCK_SIZE;
+        let block_size = match (self.block_size, max_memory) {
+            (Some(size), _) => size,
+            (None, Some(limit)) => largest_block_size_within(limit, default).unwrap_or(default),
+            (None, None) => default,
+        };
+        let opts = CompressOptions { block_size, max_memory, ..CompressOptions::default() };
+        opts.validate()?;
+        Ok(opts)
+    }
+
+    /// `options`, encoding on up to `threads` threads: fewer if the blocks
+    /// they hold at once would not fit in `max_memory`.
+    fn threaded_options(&self, max_memory: Option<usize>, threads: usize) -> io::Result<CompressOptions> {
+        let opts = self.options(max_memory)?;
+        let threads = match max_memory {
+            Some(limit) => (1..=threads).rev().find(|&n| memory_for_threads(opts.block_size, n) <= limit).unwrap_or(1),
+            None => threads,
+        };
+        Ok(CompressOptions { threads, ..opts })
+    }
+}
+
+/// Rewrites gzip-style `-1` .. `-9` as `--level N`, which clap cannot
+/// express as flags of its own.
+fn expand_level_flags(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut expanded = Vec::new();
+    let mut options_done = false;
+    for arg in args {
+        match arg.to_str() {
+            Some("--") => options_done = true,
+            Some(flag @ ("-1" | "-2" | "-3" | "-4" | "-5" | "-6" | "-7" | "-8" | "-9")) if !options_done => {
+                expanded.push(OsString::from(format!("--level={}", &flag[1..])));
+                continue;
             }
-            match write(&output, &decompressed) {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Error writing output {}: {}", output, e);
-                    return Err(e);
-                }
+            _ => {}
+        }
+        expanded.push(arg);
+    }
+    expanded
+}
+
+/// Parses a non-negative number of seconds such as `2` or `0.5`.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("expected a number of seconds, got '{}'", s))
+}
+
+/// Parses a size such as `4096`, `64k`, `1M` or `2G` (multiples of 1024).
+fn parse_size(s: &str) -> Result<usize, String> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((at, 'k' | 'K')) => (&s[..at], 1 << 10),
+        Some((at, 'm' | 'M')) => (&s[..at], 1 << 20),
+        Some((at, 'g' | 'G')) => (&s[..at], 1 << 30),
+        _ => (s, 1),
+    };
+    digits.parse::<usize>().ok().and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("{:?} is not a size like 4096, 64k or 1M", s))
+}
+
+/// Parses a block size such as `4096`, `64k` or `1M` (multiples of 1024),
+/// within the format's limits.
+fn parse_block_size(s: &str) -> Result<usize, String> {
+    let size = parse_size(s)?;
+    if size == 0 || size > frame::MAX_BLOCK_SIZE {
+        return Err(format!("block size must be between 1 byte and {}M", frame::MAX_BLOCK_SIZE >> 20));
+    }
+    Ok(size)
+}
+
+#[derive(Args)]
+struct DecompressArgs {
+    #[command(flatten)]
+    paths: Paths,
+    /// Unpack the decompressed tar stream into --directory instead of writing it
+    #[arg(long, conflicts_with_all = ["rm", "output_dir", "output", "stdout"])]
+    untar: bool,
+    /// Directory to unpack into with --untar
+    #[arg(short = 'C', long, value_name = "DIR", default_value = ".", requires = "untar")]
+    directory: PathBuf,
+    /// Leave holes in output files for long runs of zeros instead of
+    /// writing them; stdout and devices are written in full
+    #[arg(long, conflicts_with = "untar")]
+    sparse: bool,
+    /// Decode into nothing, still checking every checksum, to time
+    /// decompression without the cost of writing the output
+    #[arg(long, conflicts_with_all = ["output", "stdout", "output_dir", "rm", "untar", "sparse"])]
+    discard: bool,
+    /// With a directory input, also decompress the .aapc files in its
+    /// subdirectories, recreating them under the output directory
+    #[arg(short, long, conflicts_with = "untar")]
+    recursive: bool,
+    #[command(flatten)]
+    on_error: OnError,
+}
+
+/// Inputs and outputs of a compress or decompress run.
+#[derive(Args)]
+struct Paths {
+    /// Input file paths, or - once for stdin; name the output with -o
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Output file path, or - for stdout [default: input with .aapc added or removed]
+    #[arg(short = 'o', long, conflicts_with = "stdout")]
+    output: Option<String>,
+    /// Write to stdout; the input files are left untouched
+    #[arg(short = 'c', long)]
+    stdout: bool,
+    /// Write each output into DIR, named after the input's base name
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "stdout"])]
+    output_dir: Option<PathBuf>,
+    /// Create the --output-dir directory and its parents if missing
+    #[arg(long, requires = "output_dir")]
+    parents: bool,
+    /// Delete each input once its output is written, synced and verified
+    #[arg(long, conflicts_with = "stdout")]
+    rm: bool,
+    /// Overwrite existing output files
+    #[arg(short = 'f', long)]
+    force: bool,
+    /// Write a JSON report of the run to FILE: each input's outcome, sizes,
+    /// checksum and any error, the options used and totals. Written when
+    /// the run ends, however it ends
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+}
+
+/// Suffix added by compress and removed by decompress.
+const SUFFIX: &str = ".aapc";
+
+impl Paths {
+    /// Exits with a usage error for what reads like the old `INPUT OUTPUT`
+    /// form, which would otherwise take the output for a second input: `-`
+    /// more than once, since stdin can be read only once, or two paths of
+    /// which the second does not exist.
+    fn check_inputs(&self) {
+        if self.inputs.iter().filter(|input| *input == STDIO).count() > 1 {
+            usage_error("- can be given only once, as stdin can be read only once; to write to stdout, use -o - or -c")
+        }
+        if let [_, second] = self.inputs.as_slice() {
+            if second != STDIO && fs::symlink_metadata(second).is_err() {
+                usage_error(&format!("{} does not exist; to name the output, use -o {}", second, second))
             }
-            println!("Decompressed {} ({} bytes) to {} ({} bytes) in {:?}.",
-                     input, compressed.len(), output, decompressed.len(), duration);
         }
-        Commands::Test { file } => {
-            if let Some(input_path) = file {
-                run_file_test(&input_path, cli.verbose)?;
-            } else {
-                run_generated_test(cli.verbose);
+    }
+
+    /// The output path for each input, with "-" standing for stdout. Without
+    /// an explicit one, compress appends `.aapc` and decompress strips it;
+    /// stdin input goes to stdout. Exits with a usage error when an output
+    /// cannot be named.
+    fn outputs(&self, compressing: bool) -> Vec<String> {
+        if let Some(path) = &self.output {
+            if self.inputs.len() > 1 {
+                usage_error("-o/--output takes a single input")
             }
+            return vec![path.clone()];
         }
-        Commands::TestFolder => {
-            run_folder_test(cli.verbose)?;
+        let outputs = self.inputs.iter().map(|input| self.derived_output(input, compressing));
+        match &self.output_dir {
+            Some(dir) => outputs
+                .map(|output| match Path::new(&output).file_name() {
+                    Some(name) if output != STDIO => dir.join(name).to_string_lossy().into_owned(),
+                    _ => usage_error("--output-dir cannot name the output for stdin; use -o"),
+                })
+                .collect(),
+            None => outputs.collect(),
+        }
+    }
+
+    /// Checks that `--output-dir` exists, creating it with `--parents`
+    /// unless this is a dry run.
+    fn prepare_output_dir(&self, dry_run: bool) -> io::Result<()> {
+        let Some(dir) = &self.output_dir else {
+            return Ok(());
+        };
+        if self.parents && dry_run {
+            return Ok(());
+        }
+        if self.parents {
+            return fs::create_dir_all(dir).map_err(|e| context(e, "creating output directory", &dir.to_string_lossy()));
+        }
+        if !dir.is_dir() {
+            let msg = format!("output directory {} does not exist; use --parents to create it", dir.display());
+            return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+        }
+        Ok(())
+    }
+
+    fn derived_output(&self, input: &str, compressing: bool) -> String {
+        if self.stdout || input == STDIO {
+            return STDIO.to_string();
+        }
+        if compressing {
+            return format!("{}{}", input, SUFFIX);
+        }
+        // "dir/.aapc" has nothing left to name the output after.
+        let stripped = input.strip_suffix(SUFFIX).filter(|rest| !rest.is_empty() && !rest.ends_with(MAIN_SEPARATOR));
+        match stripped {
+            Some(rest) => rest.to_string(),
+            None => usage_error(&format!(
+                "cannot derive an output name from {} (expected a {} suffix); use -o",
+                input, SUFFIX
+            )),
         }
     }
-    Ok(())
 }
 
-fn run_generated_test(verbose: bool) {
-    println!("Continuing our symphony of compression, courtesy of Ada Lovelace!");
+/// Reports a bad combination of arguments the way clap does, exiting with 2.
+fn usage_error(msg: &str) -> ! {
+    Cli::command().error(ErrorKind::ValueValidation, msg).exit()
+}
 
-    // Enhanced test data: mix repetitive and random for realism
-    let mut rng = rand::thread_rng();
-    let mut test_data: Vec<u8> = Vec::with_capacity(1024 * 1024); // 1MB
-    for _ in 0..1024 {
-        let repeat_byte = rng.gen();
-        let run_len = rng.gen_range(1..100);
-        for _ in 0..run_len {
-            test_data.push(repeat_byte);
+/// Process exit statuses, as listed in `--help`. Most usage errors exit
+/// with 2 from clap itself.
+const EXIT_IO: u8 = 1;
+/// Also for a --max-memory limit the work cannot fit in.
+const EXIT_USAGE: u8 = 2;
+const EXIT_CORRUPT: u8 = 3;
+const EXIT_CHECKSUM: u8 = 4;
+/// From `compare`, for contents that differ; its I/O errors exit with
+/// [`EXIT_USAGE`] instead.
+const EXIT_DIFFERENT: u8 = 1;
+/// What a shell reports for a process killed by SIGINT.
+const EXIT_CANCELLED: u8 = 130;
+/// What a shell reports for a process killed by SIGPIPE.
+const EXIT_BROKEN_PIPE: u8 = 141;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse_from(expand_level_flags(std::env::args_os()));
+    init_logging(&cli.global);
+    IO_SIZE.store(cli.global.io_size, Ordering::Relaxed);
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Whoever closed the pipe already has what they wanted.
+        Err(failure) if failure.code == EXIT_BROKEN_PIPE => ExitCode::from(EXIT_BROKEN_PIPE),
+        Err(failure) if failure.code == EXIT_CANCELLED => {
+            eprintln!("interrupted — no output written");
+            ExitCode::from(EXIT_CANCELLED)
         }
-        for _ in 0..rng.gen_range(1..50) {
-            test_data.push(rng.gen());
+        Err(failure) => {
+            eprintln!("Error: {}", failure.error);
+            ExitCode::from(failure.code)
         }
     }
+}
 
-    if verbose {
-        println!("Verbose: Generated test data of {} bytes", test_data.len());
+/// Sends `log` records to stderr as "LEVEL: message", lifting any progress
+/// bars around each one. An explicit `--log-level` wins, then `--verbose`
+/// (debug) and `--quiet` (errors only), then RUST_LOG; warnings and errors
+/// are shown otherwise.
+fn init_logging(global: &Global) {
+    let mut builder = env_logger::Builder::new();
+    let level = match (global.log_level, global.verbose, global.quiet) {
+        (Some(level), _, _) => Some(level),
+        (None, true, _) => Some(LogLevel::Debug),
+        (None, false, true) => Some(LogLevel::Error),
+        (None, false, false) => None,
+    };
+    match (level, std::env::var("RUST_LOG")) {
+        (Some(level), _) => builder.filter_level(level.filter()),
+        (None, Ok(spec)) => builder.parse_filters(&spec),
+        (None, Err(_)) => builder.filter_level(log::LevelFilter::Warn),
+    };
+    builder
+        .format(|buf, record| writeln!(buf, "{}: {}", record.level(), record.args()))
+        .target(env_logger::Target::Pipe(Box::new(LogSink)))
+        .init();
+}
+
+/// The progress bars on screen, if any, for log lines to be written above.
+static BARS: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// Where log records go: stderr, one whole record per write.
+struct LogSink;
+
+impl Write for LogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bars = BARS.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        match bars {
+            Some(bars) => bars.suspend(|| io::stderr().write_all(buf))?,
+            None => io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
     }
 
-    // Compress
-    let start = Instant::now();
-    let compressed = compression::compress(&test_data);
-    let compress_time = start.elapsed();
-    let ratio = compressed.len() as f64 / test_data.len() as f64;
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
 
-    println!("Original size: {} bytes", test_data.len());
-    println!("Compressed size: {} bytes (ratio: {:.2})", compressed.len(), ratio);
-    println!("Compression time: {:?}", compress_time);
+/// An error together with the exit status it maps to, decided before any
+/// context is added to its message.
+struct Failure {
+    code: u8,
+    error: io::Error,
+}
 
-    // Decompress
-    let start = Instant::now();
-    let decompressed = decompression::decompress(&compressed);
-    let decompress_time = start.elapsed();
+impl Failure {
+    /// Prefixes the message with what was being done to which path.
+    fn context(self, action: &str, path: &str) -> Failure {
+        Failure { code: self.code, error: context(self.error, action, path) }
+    }
+}
 
-    println!("Decompressed size: {} bytes", decompressed.len());
-    println!("Decompression time: {:?}", decompress_time);
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Failure {
+        Failure { code: exit_code(&error), error }
+    }
+}
 
-    // Verify
-    assert_eq!(test_data, decompressed, "Decompression mismatch!");
-    println!("Harmony restored: Data is identical.");
+impl From<DecompressError> for Failure {
+    fn from(error: DecompressError) -> Failure {
+        io::Error::from(error).into()
+    }
 }
 
-fn run_file_test(input_path: &str, verbose: bool) -> io::Result<()> {
-    println!("Testing with real file: {}", input_path);
-    let test_data = read(input_path)?;
+/// Maps an error to its exit status, looking through the `io::Error` wrapper
+/// for the library error that caused it. This is the only place statuses
+/// are decided.
+fn exit_code(err: &io::Error) -> u8 {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        return EXIT_BROKEN_PIPE;
+    }
+    let inner = err.get_ref();
+    match inner.and_then(|e| e.downcast_ref::<CompressError>()) {
+        Some(CompressError::Cancelled) => return EXIT_CANCELLED,
+        Some(CompressError::MemoryLimit { .. }) => return EXIT_USAGE,
+        _ => {}
+    }
+    match inner.and_then(|e| e.downcast_ref::<DecompressError>()) {
+        Some(DecompressError::Cancelled) => EXIT_CANCELLED,
+        Some(DecompressError::MemoryLimit { .. }) => EXIT_USAGE,
+        Some(DecompressError::ChecksumMismatch { .. } | DecompressError::FrameChecksumMismatch { .. }) => {
+            EXIT_CHECKSUM
+        }
+        Some(DecompressError::Io(_)) => EXIT_IO,
+        Some(_) => EXIT_CORRUPT,
+        None if err.kind() == io::ErrorKind::InvalidData => EXIT_CORRUPT,
+        None => EXIT_IO,
+    }
+}
 
-    if verbose {
-        println!("Verbose: Loaded file of {} bytes", test_data.len());
+/// Cancels the returned token on the first Ctrl+C, so the running operation
+/// stops once the current block is done and its temporary output is
+/// removed. A second Ctrl+C removes any temporary output and exits at once.
+fn cancel_on_interrupt() -> CancelToken {
+    let token = CancelToken::new();
+    let handler_token = token.clone();
+    let installed = ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            remove_pending();
+            std::process::exit(EXIT_CANCELLED.into());
+        }
+        handler_token.cancel();
+    });
+    if let Err(e) = installed {
+        log::warn!("Ctrl+C will not cancel cleanly: {}", e);
     }
+    token
+}
 
-    // Compress
-    let start = Instant::now();
-    let compressed = compression::compress(&test_data);
-    let compress_time = start.elapsed();
-    let ratio = compressed.len() as f64 / test_data.len() as f64;
+impl Global {
+    fn units(&self) -> Units {
+        Units { raw: self.bytes }
+    }
 
-    println!("Original size: {} bytes", test_data.len());
-    println!("Compressed size: {} bytes (ratio: {:.2})", compressed.len(), ratio);
-    println!("Compression time: {:?}", compress_time);
+    /// The thread limit, with 0 resolved to the number of cores.
+    fn threads(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+    }
 
-    // Decompress
-    let start = Instant::now();
-    let decompressed = decompression::decompress(&compressed);
-    let decompress_time = start.elapsed();
+    /// Logs the thread limit, warning when one was asked for that this
+    /// command cannot use: only encoding or decoding `blocks` uses threads,
+    /// and every other stage runs on the calling thread.
+    fn report_threads(&self, blocks: bool) {
+        match self.threads {
+            _ if blocks => log::debug!("Coding blocks on up to {} threads", self.threads()),
+            n if n > 1 => log::warn!("--threads {} has no effect here; this work is sequential", n),
+            _ => log::debug!("Thread limit {} (this work is sequential)", self.threads()),
+        }
+    }
 
-    println!("Decompressed size: {} bytes", decompressed.len());
-    println!("Decompression time: {:?}", decompress_time);
+    /// Whether to add decoration, such as the banner and progress bars, for
+    /// a person watching a terminal: not with `--plain` or NO_COLOR set to
+    /// anything, nor when `stream` is redirected to a file or pipe, so
+    /// captured output holds only result lines.
+    fn decorated(&self, stream: &dyn IsTerminal) -> bool {
+        !self.plain && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && stream.is_terminal()
+    }
 
-    // Verify
-    assert_eq!(test_data, decompressed, "Decompression mismatch!");
-    println!("Harmony restored: Data is identical.");
+    /// Whether `stream` takes ANSI colours: decorated, and not a terminal
+    /// that declares it has none.
+    fn colored(&self, stream: &dyn IsTerminal) -> bool {
+        self.decorated(stream) && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+    }
 
-    Ok(())
+    /// Where summary lines go: stdout, unless it is carrying
+    /// data, and nowhere with `--quiet`.
+    fn status(&self, data_on_stdout: bool) -> Box<dyn Write> {
+        match (self.quiet, data_on_stdout) {
+            (true, _) => Box::new(io::sink()),
+            (false, true) => Box::new(io::stderr()),
+            (false, false) => Box::new(io::stdout()),
+        }
+    }
+}
+
+/// Prefixes an I/O error with what was being done to which path.
+fn context(e: io::Error, action: &str, path: &str) -> io::Error {
+    io::Error::new(e.kind(), format!("{} {}: {}", action, path, e))
 }
 
-fn run_folder_test(verbose: bool) -> io::Result<()> {
-    let folder_path = Path::new("test_data");
-    if !folder_path.exists() || !folder_path.is_dir() {
-        eprintln!("Error: 'test_data' folder does not exist or is not a directory.");
-        return Ok(());
+fn run(cli: Cli) -> Result<(), Failure> {
+    match cli.command {
+        Commands::Compress(args) if args.tar && args.incompressible.skip_compressed => {
+            usage_error("--skip-compressed does not apply to a --tar stream")
+        }
+        Commands::Compress(args) if args.tar => compress_tar(&args.paths, &args.tuning, &cli.global)?,
+        Commands::Compress(args) => {
+            let checkpointing = args.checkpointing();
+            let writing = Writing {
+                checkpointing: checkpointing.as_ref(),
+                dry_run: args.dry_run,
+                mmap: args.mmap,
+                check: args.check,
+                ..Writing::default()
+            };
+            run_batch(&args.paths, true, &args.tuning, args.incompressible, writing, &cli.global)?
+        }
+        Commands::Decompress(args) if args.untar => decompress_tar(&args.paths, &args.directory, &cli.global)?,
+        Commands::Decompress(args) if Path::new(&args.paths.inputs[0]).is_dir() => decompress_dir(&args, &cli.global)?,
+        Commands::Decompress(args) => {
+            let (tuning, incompressible) = (Tuning::default(), Incompressible::default());
+            let writing = Writing { sparse: args.sparse, dry_run: args.discard, ..Writing::default() };
+            run_batch(&args.paths, false, &tuning, incompressible, writing, &cli.global)?
+        }
+        Commands::CompressDir(args) => compress_dir(&args, &cli.global)?,
+        Commands::Batch(args) => run_manifest(&args, &cli.global)?,
+        Commands::Watch(args) => watch(&args, &cli.global)?,
+        Commands::Test { file, seed, size, profile, iterations } => {
+            if let Some(input_path) = file {
+                run_file_test(&input_path, iterations, &cli.global)?;
+            } else {
+                run_generated_test(seed, size, profile, iterations, &cli.global)?;
+            }
+        }
+        Commands::CrashTest(args) => run_crash_test(&args, &cli.global)?,
+        Commands::TestFolder(args) => {
+            run_folder_test(&args, &cli.global)?;
+        }
+        Commands::CorpusBench { path, corpus } => run_corpus_bench(&path, corpus, &cli.global)?,
+        Commands::Bench { file, codecs, iterations } => run_bench(&file, &codecs, iterations, &cli.global)?,
+        Commands::Estimate { file, sample_bytes } => {
+            let input = File::open(&file).map_err(|e| context(e, "reading input", &file))?;
+            // Projects the frame `compress` would write, which records the
+            // name unless the file is small.
+            let opts = CompressOptions::default();
+            let opts = CompressOptions { filename: stored_name(&file, &opts), ..opts };
+            let estimate = estimate_ratio_with_options(input, sample_bytes, &opts)?;
+            log::debug!("Sampled {} of {} bytes", estimate.sampled_bytes, estimate.input_size);
+            let mut out = cli.global.status(false);
+            match cli.global.format {
+                Format::Text => {
+                    let units = cli.global.units();
+                    writeln!(out, "Estimated ratio for {} ({}): {:.2} ± {:.3} (confidence: {})",
+                             file, units.size(estimate.input_size), estimate.ratio, estimate.std_error,
+                             estimate.confidence)?;
+                    writeln!(out, "Projected compressed size: {} ({})", units.size(estimate.estimated_size),
+                             saved(estimate.ratio))?;
+                }
+                Format::Json => writeln!(
+                    out,
+                    "{{\"operation\":\"estimate\",\"input\":{},\"input_bytes\":{},\"sampled_bytes\":{},\
+                     \"ratio\":{},\"std_error\":{},\"confidence\":\"{}\",\"estimated_size\":{}}}",
+                    json_string(&file), estimate.input_size, estimate.sampled_bytes, estimate.ratio,
+                    estimate.std_error, estimate.confidence, estimate.estimated_size)?,
+            }
+        }
+        Commands::Info { file } => show_info(&file, &cli.global)?,
+        Commands::Verify { files } => verify_files(&files, &cli.global)?,
+        Commands::Compare { a, b, quick } => compare(&a, &b, quick, &cli.global)?,
+        Commands::Cat { files } => cat_files(&files, &cli.global)?,
+        Commands::Archive {
+            command: ArchiveCommand::Create { archive, paths, force, filter, skip_compressed, check },
+        } => create_archive(&archive, &paths, force, &filter, skip_compressed, check, &cli.global)?,
+        Commands::Archive { command: ArchiveCommand::List { archive, long } } => {
+            list_archive(&archive, long, &cli.global)?
+        }
+        Commands::Archive {
+            command: ArchiveCommand::Extract { archive, members, globs, directory, force, absolute_symlinks },
+        } => extract_archive(&archive, &directory, &members, &globs, force, absolute_symlinks, &cli.global)?,
+        Commands::Inspect { file, opcodes } => inspect(&file, opcodes, &cli.global)?,
+        Commands::Index { file } => {
+            let input = File::open(&file).map_err(|e| context(e, "reading input", &file))?;
+            let table = BlockTable::build(io::BufReader::new(input))?;
+            let index_path = format!("{}.idx", file);
+            let mut out = AtomicFile::create(Path::new(&index_path))
+                .map_err(|e| context(e, "writing output", &index_path))?;
+            table.save(&mut out).and_then(|()| out.commit())
+                .map_err(|e| context(e, "writing output", &index_path))?;
+            writeln!(cli.global.status(false), "Indexed {} blocks ({} bytes uncompressed) to {}",
+                     table.entries().len(), table.content_size(), index_path)?;
+        }
+        Commands::Completions { shell } => {
+            // Complete whatever name the binary was installed under.
+            let mut command = Cli::command();
+            let name = std::env::args_os()
+                .next()
+                .and_then(|arg0| Path::new(&arg0).file_name().map(|name| name.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| command.get_name().to_string());
+            // clap_complete panics on a write error, so a closed pipe is
+            // only seen when the script is written out here.
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut command, name, &mut script);
+            io::stdout().write_all(&script)?;
+        }
     }
+    Ok(())
+}
 
-    let mut log_entries = Vec::new();
-    for entry in fs::read_dir(folder_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-            let data = read(&path)?;
-            let orig_size = data.len() as f64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
 
-            if verbose {
-                println!("Verbose: Processing file {} ({} bytes)", file_name, orig_size as usize);
-            }
+    use ada_toolkit::checksum::crc32;
+    use ada_toolkit::{compress_with_options, CompressOptions};
 
-            // Compress
-            let compress_start = Instant::now();
-            let compressed = compression::compress(&data);
-            let compress_time = compress_start.elapsed();
-            let comp_size = compressed.len() as f64;
-            let ratio = comp_size / orig_size;
-            let compress_speed = if compress_time.as_secs_f64() > 0.0 { orig_size / compress_time.as_secs_f64() } else { 0.0 };
+    use crate::output::{self, verify_frame, OutputLock};
+    use crate::temp_dir::TempDir;
 
-            // Decompress
-            let decompress_start = Instant::now();
-            let decompressed = decompression::decompress(&compressed);
-            let decompress_time = decompress_start.elapsed();
-            let decompress_speed = if decompress_time.as_secs_f64() > 0.0 { orig_size / decompress_time.as_secs_f64() } else { 0.0 };
+    /// An uncommitted output over `out.aapc` in `dir`, which holds an older
+    /// version, with `data` compressed into it and one byte flipped once it
+    /// has reached the temporary file if `flip` is given, as a faulty disk
+    /// might. It is locked, as `create_output` leaves it.
+    fn written(dir: &TempDir, data: &[u8], flip: Option<usize>) -> (AtomicFile, String) {
+        let dest = dir.write("out.aapc", "old version");
+        let opts = CompressOptions { block_size: 16 * 1024, ..CompressOptions::default() };
+        let mut file = AtomicFile::create(&dest).unwrap();
+        file.lock = Some(OutputLock::acquire(&dest, None).unwrap());
+        file.write_all(&compress_with_options(data, &opts).unwrap()).unwrap();
+        file.flush().unwrap();
+        if let Some(at) = flip {
+            let mut temp = fs::read(&file.temp).unwrap();
+            temp[at] ^= 0x10;
+            OpenOptions::new().write(true).open(&file.temp).unwrap().write_all(&temp).unwrap();
+        }
+        (file, dir.name("out.aapc"))
+    }
 
-            // Verify
-            assert_eq!(data, decompressed, "Decompression mismatch for file: {}", file_name);
+    fn data() -> Vec<u8> {
+        (0..100_000u32).map(|i| if i % 1000 < 500 { 9 } else { (i * 13 % 251) as u8 }).collect()
+    }
 
-            // Timestamp for log (basic Unix seconds)
-            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    fn entries(dir: &TempDir) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
 
-            // Log entry
-            log_entries.push(format!(
-                "Timestamp: {}s\nFile: {}\nOriginal Size: {} bytes\nCompressed Size: {} bytes\nRatio: {:.2}\nCompress Time: {:?}\nCompress Speed: {:.2} bytes/s\nDecompress Time: {:?}\nDecompress Speed: {:.2} bytes/s\n---",
-                timestamp, file_name, orig_size as usize, comp_size as usize, ratio, compress_time, compress_speed, decompress_time, decompress_speed
-            ));
+    #[test]
+    fn a_sound_output_verifies_before_it_is_moved_into_place() {
+        let tmp = TempDir::new();
+        let data = data();
+        let (file, output) = written(&tmp, &data, None);
+        let check = WriteCheck { verify_after_write: true, keep_corrupt: false };
+        let verified = check.commit_file(file, &output, |file| {
+            assert_eq!(fs::read(&output).unwrap(), b"old version");
+            assert!(OutputLock::acquire(Path::new(&output), None).is_err(), "the output was unlocked");
+            verify_frame(file, data.len() as u64, crc32(&data))
+        });
+        assert!(verified.is_ok_and(|verified| verified));
+        assert_eq!(ada_toolkit::decompress(&fs::read(&output).unwrap()).unwrap(), data);
+        assert_eq!(entries(&tmp), ["out.aapc"]);
 
-            println!("Tested {} successfully.", file_name);
-        }
+        let (file, output) = written(&tmp, &data, Some(5_000));
+        let unasked = WriteCheck::default().commit_file(file, &output, |_| panic!("read back without being asked"));
+        assert!(unasked.is_ok_and(|verified| !verified));
+        let stdout = Output::Stdout(output::batched(io::stdout().lock()));
+        let stdout = check.commit(stdout, STDIO, |_| panic!("read back stdout"));
+        assert!(stdout.is_ok_and(|verified| !verified));
     }
 
-    if log_entries.is_empty() {
-        println!("No files found in 'test_data' folder.");
-    } else {
-        let log_content = log_entries.join("\n\n");
-        write("test_log.txt", log_content)?;
-        println!("All tests complete. Log written to 'test_log.txt'.");
+    #[test]
+    fn a_byte_changed_before_the_rename_leaves_the_destination_alone() {
+        let tmp = TempDir::new();
+        let data = data();
+        let check = WriteCheck { verify_after_write: true, keep_corrupt: false };
+        let (file, output) = written(&tmp, &data, Some(5_000));
+        let failure = check.commit_file(file, &output, |file| verify_frame(file, data.len() as u64, crc32(&data)))
+            .unwrap_err();
+        assert_eq!(failure.code, EXIT_CHECKSUM, "{}", failure.error);
+        let message = failure.error.to_string();
+        assert!(message.starts_with(&format!("verifying output {}: ", output)), "{}", message);
+        assert!(message.ends_with(&format!("; left {} as it was and removed the new output", output)), "{}", message);
+        assert_eq!(fs::read(&output).unwrap(), b"old version");
+        assert_eq!(entries(&tmp), ["out.aapc"]);
+
+        let keep = WriteCheck { verify_after_write: true, keep_corrupt: true };
+        let (file, output) = written(&tmp, &data, Some(5_000));
+        let temp = file.temp.clone();
+        let failure = keep.commit_file(file, &output, |file| verify_frame(file, data.len() as u64, crc32(&data)))
+            .unwrap_err();
+        let message = failure.error.to_string();
+        assert!(message.ends_with(&format!("; left {} as it was and kept the new output as {} (--keep-corrupt)",
+                                           output, temp.display())), "{}", message);
+        assert_eq!(fs::read(&output).unwrap(), b"old version");
+        assert!(temp.exists());
+        // The output's lock went with the failed file.
+        assert!(OutputLock::acquire(Path::new(&output), None).is_ok());
     }
 
-    Ok(())
+    #[test]
+    fn a_frame_of_other_content_fails() {
+        // Sound in itself, but not what was encoded: a stale or swapped file.
+        let tmp = TempDir::new();
+        let data = data();
+        let check = WriteCheck { verify_after_write: true, keep_corrupt: false };
+        let (file, output) = written(&tmp, &data[1..], None);
+        let failure = check.commit_file(file, &output, |file| verify_frame(file, data.len() as u64, crc32(&data)))
+            .unwrap_err();
+        assert_eq!(failure.code, EXIT_CORRUPT, "{}", failure.error);
+        assert!(failure.error.to_string().contains("output holds 99999 bytes, expected 100000"), "{}", failure.error);
+
+        let mut changed = data.clone();
+        changed[0] ^= 1;
+        let (file, output) = written(&tmp, &changed, None);
+        let failure = check.commit_file(file, &output, |file| verify_frame(file, data.len() as u64, crc32(&data)))
+            .unwrap_err();
+        assert_eq!(failure.code, EXIT_CHECKSUM, "{}", failure.error);
+        assert_eq!(fs::read(&output).unwrap(), b"old version");
+    }
 }
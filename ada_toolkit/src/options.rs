@@ -0,0 +1,306 @@
+use crate::cancel::CancelToken;
+use crate::error::CompressError;
+use crate::frame::{DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE, SMALL_FRAME_LIMIT};
+
+/// Settings for the encoder.
+///
+/// Options are never modified by the encoder, so one value (or an
+/// `Arc<CompressOptions>`) can be shared by concurrent compressions.
+///
+/// With the `serde` feature this (de)serializes with its field names as keys.
+/// Missing fields take their default, so configs written before a field
+/// existed keep loading; unknown fields are ignored, so configs written by a
+/// newer version load on an older one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CompressOptions {
+    /// Uncompressed bytes per block, 1..=`MAX_BLOCK_SIZE`.
+    pub block_size: usize,
+    /// Store a CRC-32 of each block's uncompressed bytes.
+    pub block_checksums: bool,
+    /// Store a CRC-32 of the whole uncompressed content in the trailer.
+    pub content_checksum: bool,
+    /// Original file name to record in the header, at most 65535 bytes.
+    pub filename: Option<String>,
+    /// Free-form comment to record in the header, at most 65535 bytes.
+    pub comment: Option<String>,
+    /// Store every block as is without trying RLE, for content already
+    /// known not to compress. The frame decodes like any other.
+    pub store_only: bool,
+    /// Write content shorter than [`SMALL_FRAME_LIMIT`], and no longer
+    /// than a block, as a small frame when there is no filename or comment.
+    /// It gains 2 to 7 bytes, where a full frame gains around 40, but
+    /// decoders from before small frames existed cannot read it. Writers
+    /// that cannot see the end of the input before a block is done, such
+    /// as [`AapcWriter`](crate::stream::AapcWriter), always write a full
+    /// frame.
+    pub small_frames: bool,
+    /// Most bytes encoding may hold at once, as estimated by
+    /// [`memory_for_threads`]; `None` for no limit.
+    pub max_memory: Option<usize>,
+    /// Worker threads to encode blocks on; 0 or 1 encodes on the calling
+    /// thread. The frame is byte-identical whatever the count. Only the
+    /// one-shot functions and [`copy_encode`](crate::copy_encode) use
+    /// threads; [`AapcWriter`](crate::stream::AapcWriter) is sequential.
+    pub threads: usize,
+    /// Checked before each block; once cancelled, encoding fails with
+    /// `Cancelled` and no trailer is written. Never serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: Option<CancelToken>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_checksums: true,
+            content_checksum: true,
+            filename: None,
+            comment: None,
+            store_only: false,
+            small_frames: true,
+            max_memory: None,
+            threads: 0,
+            cancel: None,
+        }
+    }
+}
+
+impl CompressOptions {
+    /// Whether content of `len` bytes is written as a small frame.
+    pub fn small_frame_fits(&self, len: usize) -> bool {
+        self.small_frames
+            && len < SMALL_FRAME_LIMIT
+            && len <= self.block_size
+            && self.filename.is_none()
+            && self.comment.is_none()
+    }
+
+    /// Checks every setting against the format limits.
+    pub fn validate(&self) -> Result<(), CompressError> {
+        if self.block_size == 0 || self.block_size > MAX_BLOCK_SIZE {
+            return Err(CompressError::InvalidBlockSize(self.block_size));
+        }
+        if let Some(limit) = self.max_memory {
+            let needed = memory_for_threads(self.block_size, self.threads);
+            if needed > limit {
+                return Err(CompressError::MemoryLimit { needed, limit });
+            }
+        }
+        for (field, value) in [("filename", &self.filename), ("comment", &self.comment)] {
+            if let Some(value) = value {
+                if value.len() > u16::MAX as usize {
+                    return Err(CompressError::MetadataTooLong { field, len: value.len() });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Settings for the streaming decoder.
+#[derive(Debug, Clone, Default)]
+pub struct DecompressOptions {
+    /// Most bytes decoding may hold at once. A frame whose block size needs
+    /// more, by [`memory_for_threads`] for a single thread, fails with
+    /// `MemoryLimit` as soon as its header is read, as does a block whose
+    /// payload would not fit; with more threads, fewer are used until the
+    /// blocks they hold fit.
+    pub max_memory: Option<usize>,
+    /// Worker threads to decode blocks on; 0 or 1 decodes on the calling
+    /// thread. Every block of a frame decodes on its own, so any frame can
+    /// be split up this way, and the output and errors are the same as
+    /// sequential decoding gives.
+    pub threads: usize,
+    /// Checked before each block; once cancelled, decoding fails with
+    /// `Cancelled`.
+    pub cancel: Option<CancelToken>,
+}
+
+/// Smallest block size [`largest_block_size_within`] picks.
+const MIN_PLANNED_BLOCK_SIZE: usize = 4 * 1024;
+
+/// A bound on the bytes the streaming encoder or decoder holds at once for
+/// blocks of `block_size`, besides the caller's own buffers: a 64 KiB read
+/// buffer, one block, and its encoded form, which escapes can make up to
+/// twice as large before the encoder falls back to storing the block.
+pub fn memory_for_block_size(block_size: usize) -> usize {
+    (64 * 1024usize).saturating_add(block_size.saturating_mul(3))
+}
+
+/// Blocks a threaded encoder or decoder holds at once for each thread: one
+/// being worked on and one waiting, for a thread or for the blocks before
+/// it to be written.
+const BLOCKS_PER_THREAD: usize = 2;
+
+/// Blocks held at once when working on `threads` threads.
+pub(crate) fn blocks_in_flight(threads: usize) -> usize {
+    match threads {
+        0 | 1 => 1,
+        n => n * BLOCKS_PER_THREAD,
+    }
+}
+
+/// Blocks the pipelined encoder holds while encoding on `threads` threads:
+/// two per thread even on one, so a block can be encoded while the one
+/// before it is written.
+pub(crate) fn pipeline_blocks(threads: usize) -> usize {
+    threads.max(1) * BLOCKS_PER_THREAD
+}
+
+/// Blocks the pipelined encoder's reader thread has read and queued ahead
+/// of the encoders, besides the one it is reading.
+pub(crate) const READ_AHEAD: usize = 1;
+
+/// Like [`memory_for_block_size`], for encoding or decoding on `threads`
+/// threads, each with two blocks and their encoded forms in flight.
+pub fn memory_for_threads(block_size: usize, threads: usize) -> usize {
+    (64 * 1024usize).saturating_add(block_size.saturating_mul(3).saturating_mul(blocks_in_flight(threads)))
+}
+
+/// Like [`memory_for_threads`], for
+/// [`copy_encode_pipelined`](crate::stream::copy_encode_pipelined): its
+/// blocks in flight, plus those the reader thread holds.
+pub fn memory_for_pipeline(block_size: usize, threads: usize) -> usize {
+    let blocks = block_size.saturating_mul(3).saturating_mul(pipeline_blocks(threads));
+    (64 * 1024 + blocks).saturating_add(block_size.saturating_mul(READ_AHEAD + 1))
+}
+
+/// Encoded chunks of a member [`write_archive_concurrent`] queues for the
+/// archive besides the one it is filling, per member being compressed.
+///
+/// [`write_archive_concurrent`]: crate::archive::write_archive_concurrent
+pub(crate) const ARCHIVE_QUEUE: usize = 1;
+
+/// Like [`memory_for_threads`], for
+/// [`write_archive_concurrent`](crate::archive::write_archive_concurrent)
+/// compressing `threads` members at once: a single-threaded encoder's
+/// [`memory_for_block_size`] for each, plus its encoded chunks, each
+/// `block_size` long, waiting for the archive.
+pub fn memory_for_archive(block_size: usize, threads: usize) -> usize {
+    let chunks = block_size.saturating_mul(ARCHIVE_QUEUE + 1);
+    memory_for_block_size(block_size).saturating_add(chunks).saturating_mul(threads.max(1))
+}
+
+/// The most threads, up to `threads`, whose blocks of `block_size` fit in
+/// `max_memory` by [`memory_for_threads`]; at least one.
+pub(crate) fn threads_within(max_memory: Option<usize>, block_size: usize, threads: usize) -> usize {
+    match max_memory {
+        Some(limit) => (2..=threads).rev().find(|&n| memory_for_threads(block_size, n) <= limit).unwrap_or(1),
+        None => threads.max(1),
+    }
+}
+
+/// Like [`threads_within`], by [`memory_for_archive`].
+pub(crate) fn archive_threads_within(max_memory: Option<usize>, block_size: usize, threads: usize) -> usize {
+    match max_memory {
+        Some(limit) => (2..=threads).rev().find(|&n| memory_for_archive(block_size, n) <= limit).unwrap_or(1),
+        None => threads.max(1),
+    }
+}
+
+/// The largest power-of-two block size, at most `ceiling`, whose
+/// [`memory_for_block_size`] fits in `limit`; `None` if not even 4 KiB does.
+pub fn largest_block_size_within(limit: usize, ceiling: usize) -> Option<usize> {
+    let mut size = ceiling.min(MAX_BLOCK_SIZE);
+    if !size.is_power_of_two() {
+        size = size.next_power_of_two() / 2;
+    }
+    while size >= MIN_PLANNED_BLOCK_SIZE {
+        if memory_for_block_size(size) <= limit {
+            return Some(size);
+        }
+        size /= 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIB: usize = 1024;
+    const MIB: usize = 1024 * KIB;
+
+    #[test]
+    fn memory_grows_with_blocks_and_threads() {
+        assert_eq!(memory_for_block_size(4 * KIB), 64 * KIB + 12 * KIB);
+        assert_eq!(memory_for_threads(4 * KIB, 1), memory_for_block_size(4 * KIB));
+        assert_eq!(memory_for_threads(4 * KIB, 0), memory_for_block_size(4 * KIB));
+        assert_eq!(memory_for_threads(4 * KIB, 4), 64 * KIB + 8 * 12 * KIB);
+        assert_eq!(memory_for_block_size(usize::MAX), usize::MAX);
+        assert_eq!(memory_for_threads(usize::MAX / 2, 8), usize::MAX);
+    }
+
+    #[test]
+    fn a_small_cap_picks_smaller_blocks() {
+        let default = DEFAULT_BLOCK_SIZE;
+        assert_eq!(largest_block_size_within(usize::MAX, default), Some(default));
+        assert_eq!(largest_block_size_within(memory_for_block_size(default), default), Some(default));
+        assert_eq!(largest_block_size_within(memory_for_block_size(default) - 1, default), Some(default / 2));
+        assert_eq!(largest_block_size_within(256 * KIB, default), Some(64 * KIB));
+        assert_eq!(largest_block_size_within(memory_for_block_size(4 * KIB), default), Some(4 * KIB));
+        assert_eq!(largest_block_size_within(memory_for_block_size(4 * KIB) - 1, default), None);
+        // A ceiling that is not a power of two rounds down to one.
+        assert_eq!(largest_block_size_within(usize::MAX, 100_000), Some(64 * KIB));
+    }
+
+    #[test]
+    fn a_small_cap_picks_fewer_threads() {
+        let block = 64 * KIB;
+        assert_eq!(threads_within(None, block, 8), 8);
+        assert_eq!(threads_within(None, block, 0), 1);
+        assert_eq!(threads_within(Some(usize::MAX), block, 8), 8);
+        assert_eq!(threads_within(Some(memory_for_threads(block, 3)), block, 8), 3);
+        assert_eq!(threads_within(Some(memory_for_threads(block, 3) - 1), block, 8), 2);
+        assert_eq!(threads_within(Some(0), block, 8), 1);
+        assert_eq!(archive_threads_within(Some(memory_for_archive(block, 5)), block, 8), 5);
+    }
+
+    #[test]
+    fn options_that_cannot_fit_fail_validation() {
+        let opts = CompressOptions { block_size: 64 * KIB, threads: 4, max_memory: Some(2 * MIB),
+                                 ..CompressOptions::default() };
+        assert!(opts.validate().is_ok());
+        let tight = CompressOptions { max_memory: Some(memory_for_threads(64 * KIB, 4) - 1), ..opts.clone() };
+        let needed = memory_for_threads(64 * KIB, 4);
+        let err = tight.validate().unwrap_err();
+        assert!(matches!(err, CompressError::MemoryLimit { needed: n, limit } if n == needed && limit == needed - 1));
+        let one_big_block = CompressOptions { max_memory: Some(MIB), block_size: MIB, threads: 1, ..opts };
+        let err = one_big_block.validate().unwrap_err();
+        assert_eq!(err.to_string(), "compressing needs 3211264 bytes of memory, more than the limit of 1048576");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let opts = CompressOptions {
+            block_size: 4096,
+            content_checksum: false,
+            filename: Some("data.bin".to_string()),
+            max_memory: Some(1 << 20),
+            threads: 4,
+            ..CompressOptions::default()
+        };
+        let json = serde_json::to_string(&opts).unwrap();
+        assert_eq!(serde_json::from_str::<CompressOptions>(&json).unwrap(), opts);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn unknown_fields_are_ignored_and_missing_ones_default() {
+        let opts: CompressOptions =
+            serde_json::from_str(r#"{"block_size": 1024, "level": 9, "dictionary": "none"}"#).unwrap();
+        assert_eq!(opts, CompressOptions { block_size: 1024, ..CompressOptions::default() });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn cancel_token_is_not_serialized() {
+        let opts = CompressOptions { cancel: Some(CancelToken::new()), ..CompressOptions::default() };
+        let json = serde_json::to_value(&opts).unwrap();
+        assert!(json.get("cancel").is_none(), "{}", json);
+        assert_eq!(serde_json::from_value::<CompressOptions>(json).unwrap().cancel, None);
+    }
+}
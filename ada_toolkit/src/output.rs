@@ -0,0 +1,644 @@
+//! Where outputs go: atomic files renamed into place once complete, the
+//! locks that keep two runs from writing one output, overwrite checks and
+//! reading outputs back to verify them.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use ada_toolkit::batch::BatchWriter;
+use ada_toolkit::checksum::Crc32;
+use ada_toolkit::{copy_decode, Archive, DecompressError, Member};
+use rand::Rng;
+
+use crate::context;
+
+/// Path argument meaning stdin or stdout.
+pub const STDIO: &str = "-";
+
+/// Opens `path` for reading, or stdin for "-".
+pub fn open_input(path: &str) -> io::Result<Box<dyn Read + Send>> {
+    if path == STDIO {
+        // Unlocked, so that it can be read on a pipeline thread; every read
+        // takes the lock for itself.
+        return Ok(Box::new(io::stdin()));
+    }
+    let file = File::open(path).map_err(|e| context(e, "reading input", path))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// `--io-size`, for every output written.
+pub static IO_SIZE: AtomicUsize = AtomicUsize::new(ada_toolkit::batch::DEFAULT_IO_SIZE);
+
+/// Batches writes to `inner` into `--io-size` pieces.
+pub fn batched<W: Write>(inner: W) -> BatchWriter<W> {
+    BatchWriter::with_io_size(IO_SIZE.load(Ordering::Relaxed), inner)
+}
+
+/// Temporary files not yet renamed into place and lock files still held,
+/// for a second Ctrl+C to remove before exiting.
+static PENDING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+pub fn remove_pending() {
+    for temp in PENDING.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+        let _ = fs::remove_file(temp);
+    }
+}
+
+/// A file written under a temporary name, `.name.tmpXXXXXXXX`, in the
+/// directory of `dest` and renamed over it by [`AtomicFile::commit`] once
+/// complete, so `dest` never holds partial output: until then it keeps its
+/// old content, if it had any. Dropping it uncommitted removes the
+/// temporary file.
+pub struct AtomicFile {
+    out: BatchWriter<File>,
+    pub temp: PathBuf,
+    dest: PathBuf,
+    /// Zeros not yet written, once made sparse.
+    hole: Option<u64>,
+    /// Held until the file is committed or dropped, for outputs that other
+    /// runs may be writing too.
+    pub lock: Option<OutputLock>,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file, with the permissions of `dest` if it
+    /// already exists.
+    pub fn create(dest: &Path) -> io::Result<AtomicFile> {
+        let Some(name) = dest.file_name() else {
+            let msg = format!("{} does not name a file", dest.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        };
+        let dir = dest.parent().unwrap_or(Path::new(""));
+        loop {
+            let mut temp_name = OsString::from(".");
+            temp_name.push(name);
+            temp_name.push(format!(".tmp{:08x}", rand::thread_rng().gen::<u32>()));
+            let temp = dir.join(temp_name);
+            let file = match OpenOptions::new().write(true).create_new(true).open(&temp) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            PENDING.lock().unwrap_or_else(PoisonError::into_inner).push(temp.clone());
+            let atomic = AtomicFile { out: batched(file), temp, dest: dest.to_path_buf(), hole: None, lock: None };
+            if let Ok(meta) = fs::metadata(dest) {
+                atomic.out.get_ref().set_permissions(meta.permissions())?;
+            }
+            return Ok(atomic);
+        }
+    }
+
+    /// Takes over `file`, written under the name `temp` by other means, to
+    /// be renamed over `dest` like a file created here.
+    pub fn adopt(file: File, temp: PathBuf, dest: &Path) -> AtomicFile {
+        AtomicFile { out: batched(file), temp, dest: dest.to_path_buf(), hole: None, lock: None }
+    }
+
+    /// Flushes the file and waits until it reaches the disk: all of
+    /// [`AtomicFile::commit`] but the rename, for reading it back first.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(hole) = self.hole.filter(|&hole| hole > 0) {
+            // Extend the file over a trailing hole that nothing was written after.
+            let end = self.out.seek(SeekFrom::Current(hole as i64))?;
+            self.out.get_ref().set_len(end)?;
+            self.hole = Some(0);
+        }
+        self.out.flush()?;
+        self.out.get_ref().sync_all()
+    }
+
+    /// Flushes the file, waits until it reaches the disk and renames it over
+    /// the destination.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.sync()?;
+        fs::rename(&self.temp, &self.dest)?;
+        self.forget();
+        Ok(())
+    }
+
+    /// Leaves the temporary file where it is instead of removing it, with
+    /// the destination untouched, and returns its path.
+    pub fn keep(mut self) -> PathBuf {
+        let temp = self.temp.clone();
+        self.forget();
+        temp
+    }
+
+    fn forget(&mut self) {
+        PENDING.lock().unwrap_or_else(PoisonError::into_inner).retain(|temp| *temp != self.temp);
+        self.temp = PathBuf::new();
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.temp.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.temp);
+            self.forget();
+        }
+    }
+}
+
+/// How often a run waiting with `--wait` tries again for an output lock.
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+/// An advisory lock on an output path, held while the output is written so
+/// that two runs writing the same output cannot interleave their temporary
+/// files and renames. It is taken on `.name.lock` beside the output, which
+/// is removed again on release; on Unix a run that locked a lock file
+/// removed meanwhile tries again on the new one.
+pub struct OutputLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Locks `dest`, failing at once if another process holds the lock or,
+    /// with `wait`, once that long has passed without it being released.
+    pub fn acquire(dest: &Path, wait: Option<Duration>) -> io::Result<OutputLock> {
+        let Some(name) = dest.file_name() else {
+            let msg = format!("{} does not name a file", dest.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        };
+        let mut lock_name = OsString::from(".");
+        lock_name.push(name);
+        lock_name.push(".lock");
+        let path = dest.parent().unwrap_or(Path::new("")).join(lock_name);
+        let deadline = wait.map(|wait| Instant::now() + wait);
+        let mut waited = false;
+        loop {
+            // Failing to make the lock file beside the output, say in a
+            // directory that does not exist, is failing to write the output.
+            let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+                .map_err(|e| context(e, "writing output", &dest.to_string_lossy()))?;
+            match file.try_lock() {
+                Ok(()) if OutputLock::is_current(&file, &path) => {
+                    PENDING.lock().unwrap_or_else(PoisonError::into_inner).push(path.clone());
+                    return Ok(OutputLock { file, path });
+                }
+                Ok(()) => continue,
+                Err(fs::TryLockError::WouldBlock) => {}
+                Err(fs::TryLockError::Error(e)) => return Err(context(e, "locking", &path.to_string_lossy())),
+            }
+            match deadline {
+                Some(deadline) if Instant::now() < deadline => {
+                    if !waited {
+                        log::info!("Waiting for another process to finish writing {}", dest.display());
+                        waited = true;
+                    }
+                    std::thread::sleep(LOCK_POLL);
+                }
+                Some(_) => {
+                    let msg = format!("{} is still being written by another process after {:?}",
+                                      dest.display(), wait.unwrap_or_default());
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, msg));
+                }
+                None => {
+                    let msg = format!("{} is being written by another process; use --wait to wait for it",
+                                      dest.display());
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, msg));
+                }
+            }
+        }
+    }
+
+    /// Whether the locked `file` is still the one at `path`, rather than one
+    /// a previous holder removed before releasing it.
+    #[cfg(unix)]
+    fn is_current(file: &File, path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (file.metadata(), fs::metadata(path)) {
+            (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_current(_file: &File, _path: &Path) -> bool {
+        true
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so nobody locks it in between; elsewhere
+        // an open file cannot be removed and the lock file stays.
+        #[cfg(unix)]
+        let _ = fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+        PENDING.lock().unwrap_or_else(PoisonError::into_inner).retain(|pending| *pending != self.path);
+    }
+}
+
+/// Runs of zeros at least this long become holes in sparse output: the
+/// smallest block most filesystems allocate.
+const SPARSE_BLOCK: usize = 4096;
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(hole) = &mut self.hole else {
+            return self.out.write(buf);
+        };
+        for chunk in buf.chunks(SPARSE_BLOCK) {
+            if chunk.len() == SPARSE_BLOCK && chunk.iter().all(|&b| b == 0) {
+                *hole += SPARSE_BLOCK as u64;
+                continue;
+            }
+            if *hole > 0 {
+                self.out.seek(SeekFrom::Current(*hole as i64))?;
+                *hole = 0;
+            }
+            self.out.write_all(chunk)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.out.seek(pos)
+    }
+}
+
+/// Destination of compress and decompress output.
+pub enum Output {
+    Stdout(BatchWriter<io::StdoutLock<'static>>),
+    File(AtomicFile),
+    /// A device or pipe named as output, such as /dev/null, written in
+    /// place since it cannot be renamed over.
+    Special(BatchWriter<File>),
+}
+
+impl Output {
+    /// Seeks over runs of zeros instead of writing them, leaving holes, if
+    /// the output is a file; pipes and devices keep getting every byte.
+    pub fn make_sparse(&mut self) {
+        if let Output::File(file) = self {
+            file.hole = Some(0);
+        }
+    }
+
+    /// Flushes the output and, for files, moves it into place once it has
+    /// reached the disk.
+    pub fn commit(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut out) => out.flush(),
+            Output::File(file) => file.commit(),
+            Output::Special(mut out) => out.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(out) => out.write(buf),
+            Output::File(out) => out.write(buf),
+            Output::Special(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(out) => out.flush(),
+            Output::File(out) => out.flush(),
+            Output::Special(out) => out.flush(),
+        }
+    }
+}
+
+/// Creates `path` for writing, or uses stdout for "-". An existing file is
+/// only replaced with `force` or after confirmation at a terminal, and only
+/// once the new output is complete. A file output stays locked against
+/// other runs until then, waiting up to `wait` for one already writing it.
+pub fn create_output(path: &str, force: bool, wait: Option<Duration>) -> io::Result<Output> {
+    if path == STDIO {
+        return Ok(Output::Stdout(batched(io::stdout().lock())));
+    }
+    if fs::metadata(path).is_ok_and(|m| !m.is_file()) {
+        let file = File::create(path).map_err(|e| context(e, "writing output", path))?;
+        return Ok(Output::Special(batched(file)));
+    }
+    let lock = OutputLock::acquire(Path::new(path), wait)?;
+    check_overwrite(Path::new(path), force)?;
+    let mut file = AtomicFile::create(Path::new(path)).map_err(|e| context(e, "writing output", path))?;
+    file.lock = Some(lock);
+    Ok(Output::File(file))
+}
+
+/// Fails if `path` is an existing file or symlink, unless `force` is set or
+/// the user confirms at a terminal.
+pub fn check_overwrite(path: &Path, force: bool) -> io::Result<()> {
+    let exists = fs::symlink_metadata(path).is_ok_and(|m| m.is_file() || m.file_type().is_symlink());
+    if exists && !force && !confirm_overwrite(path)? {
+        let msg = format!("{} already exists; use -f to overwrite", path.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+    }
+    Ok(())
+}
+
+/// Fails if `output` names the same file as `input`, directly, through a
+/// symlink or by another relative path; writing it would destroy the input.
+pub fn ensure_distinct(input: &str, output: &str) -> io::Result<()> {
+    if input == STDIO || output == STDIO {
+        return Ok(());
+    }
+    let (Ok(a), Ok(b)) = (fs::metadata(input), fs::metadata(output)) else {
+        return Ok(());
+    };
+    #[cfg(unix)]
+    let same = {
+        use std::os::unix::fs::MetadataExt;
+        (a.dev(), a.ino()) == (b.dev(), b.ino())
+    };
+    #[cfg(not(unix))]
+    let same = {
+        let _ = (a, b);
+        fs::canonicalize(input)? == fs::canonicalize(output)?
+    };
+    if same {
+        let msg = format!("input and output are the same file ({} and {})", input, output);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    Ok(())
+}
+
+/// Held while asking whether to overwrite, so that files compressed at
+/// once ask one at a time.
+static PROMPT: Mutex<()> = Mutex::new(());
+
+/// Asks on the terminal whether to replace `path`; false when stdin is not
+/// interactive.
+fn confirm_overwrite(path: &Path) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    let _asking = PROMPT.lock().unwrap_or_else(PoisonError::into_inner);
+    eprint!("{} already exists; overwrite? [y/N] ", path.display());
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+/// Decodes the frame in `file`, checking every checksum it carries, and
+/// fails unless it holds `expected` bytes whose CRC-32 is `crc`, the one
+/// taken of the input as it was encoded.
+pub fn verify_frame(file: File, expected: u64, crc: u32) -> io::Result<()> {
+    let mut content = Checksummed::new(io::sink());
+    let actual = copy_decode(BufReader::new(file), &mut content, None, None)?;
+    if actual != expected {
+        let msg = format!("output holds {} bytes, expected {}", actual, expected);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    let actual = content.crc.finish();
+    if actual != crc {
+        return Err(DecompressError::FrameChecksumMismatch { expected: crc, actual }.into());
+    }
+    Ok(())
+}
+
+/// Reads back the archive in `file`: its member table must match
+/// `members` as they were written, and every member must decode to the
+/// size and checksum recorded for it.
+pub fn verify_archive(file: File, members: &[Member]) -> io::Result<()> {
+    let mut archive = Archive::open(BufReader::new(file))?;
+    if archive.members() != members {
+        let msg = "the member table does not match the members written";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    for index in 0..members.len() {
+        archive.extract(index, io::sink())?;
+    }
+    Ok(())
+}
+
+/// For `--rm`: deletes `input` once `output` has been checked to hold
+/// `expected` bytes of content. Stdin and stdout are never touched.
+pub fn remove_input(input: &str, output: &str, compressed: bool, expected: u64) -> io::Result<()> {
+    if input == STDIO || output == STDIO {
+        return Ok(());
+    }
+    let actual = if compressed {
+        let reader = BufReader::new(File::open(output)?);
+        copy_decode(reader, io::sink(), None, None).map_err(|e| context(e.into(), "verifying output", output))?
+    } else {
+        fs::metadata(output)?.len()
+    };
+    if actual != expected {
+        let msg = format!("output holds {} bytes, expected {}; keeping input", actual, expected);
+        return Err(context(io::Error::new(io::ErrorKind::InvalidData, msg), "verifying output", output));
+    }
+    fs::remove_file(input).map_err(|e| context(e, "removing input", input))
+}
+
+/// Passes bytes through, keeping their CRC-32 and the time it took.
+pub struct Checksummed<T> {
+    inner: T,
+    pub crc: Crc32,
+    pub spent: Duration,
+}
+
+impl<T> Checksummed<T> {
+    pub fn new(inner: T) -> Checksummed<T> {
+        Checksummed { inner, crc: Crc32::new(), spent: Duration::ZERO }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let start = Instant::now();
+        self.crc.update(data);
+        self.spent += start.elapsed();
+    }
+}
+
+impl<T: Write> Write for Checksummed<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use ada_toolkit::{copy_encode_slice, decompress, CompressOptions};
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Passes writes through until `left` bytes have gone, then fails them.
+    struct FailAfter<W> {
+        inner: W,
+        left: usize,
+    }
+
+    impl<W: Write> Write for FailAfter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::other("disk on fire"));
+            }
+            let n = self.inner.write(&buf[..buf.len().min(self.left)])?;
+            self.left -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    fn data() -> Vec<u8> {
+        (0..300_000u32).map(|i| if i % 1000 < 600 { 0 } else { (i * 31 % 251) as u8 }).collect()
+    }
+
+    /// Encodes `data()` into an atomic file for `dest` that starts failing
+    /// after `fail_after` bytes, committing it only if nothing failed.
+    fn encode_to(dest: &Path, fail_after: usize) -> io::Result<()> {
+        let opts = CompressOptions { block_size: 16 * 1024, ..CompressOptions::default() };
+        let mut file = FailAfter { inner: AtomicFile::create(dest)?, left: fail_after };
+        copy_encode_slice(&data(), &mut file, &opts, None).map_err(io::Error::from)?;
+        file.inner.commit()
+    }
+
+    #[test]
+    fn a_failed_write_leaves_no_destination() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let dest = dir.join("out.aapc");
+        for fail_after in [0, 1, 5000, 100_000] {
+            assert!(encode_to(&dest, fail_after).is_err(), "failing after {} bytes", fail_after);
+            assert_eq!(entries(dir), Vec::<String>::new(), "failing after {} bytes", fail_after);
+        }
+        encode_to(&dest, usize::MAX).unwrap();
+        assert_eq!(decompress(&fs::read(&dest).unwrap()).unwrap(), data());
+        assert_eq!(entries(dir), ["out.aapc"]);
+    }
+
+    #[test]
+    fn a_failed_write_keeps_the_old_version() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let dest = dir.join("out.aapc");
+        fs::write(&dest, "the old version").unwrap();
+        assert!(encode_to(&dest, 20_000).is_err());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "the old version");
+        assert_eq!(entries(dir), ["out.aapc"]);
+
+        // Nothing reaches the destination before the commit.
+        let mut file = AtomicFile::create(&dest).unwrap();
+        file.write_all(b"the new version").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "the old version");
+        let temp = file.temp.clone();
+        file.commit().unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "the new version");
+        assert!(!temp.exists());
+    }
+
+    #[test]
+    fn the_temporary_file_is_hidden_beside_the_destination() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let file = AtomicFile::create(&dir.join("out.aapc")).unwrap();
+        let name = file.temp.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(file.temp.parent(), Some(dir));
+        assert!(name.starts_with(".out.aapc.tmp") && name.len() == ".out.aapc.tmp".len() + 8, "{}", name);
+        drop(file);
+        assert_eq!(entries(dir), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_overwrite_keeps_the_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let dest = dir.join("out.aapc");
+        fs::write(&dest, "old").unwrap();
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o640)).unwrap();
+        encode_to(&dest, usize::MAX).unwrap();
+        assert_eq!(fs::metadata(&dest).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn a_held_lock_fails_a_second_writer_at_once() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let dest = dir.join("out.aapc");
+        let held = OutputLock::acquire(&dest, None).unwrap();
+        assert_eq!(entries(dir), [".out.aapc.lock"]);
+
+        let err = OutputLock::acquire(&dest, None).err().expect("locked twice");
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(err.to_string().ends_with("out.aapc is being written by another process; use --wait to wait for it"),
+                "{}", err);
+        let err = OutputLock::acquire(&dest, Some(Duration::from_millis(250))).err().expect("locked twice");
+        assert!(err.to_string().contains("is still being written by another process after 250ms"), "{}", err);
+
+        drop(held);
+        assert!(entries(dir).is_empty(), "the lock file was left behind");
+        drop(OutputLock::acquire(&dest, None).unwrap());
+    }
+
+    #[test]
+    fn a_missing_directory_names_the_output() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let err = OutputLock::acquire(&dir.join("absent/out.aapc"), None).err().expect("locked in no directory");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with(&format!("writing output {}", dir.join("absent/out.aapc").display())),
+                "{}", err);
+    }
+
+    #[test]
+    fn a_waiting_writer_gets_the_lock_once_it_is_released() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let dest = dir.join("out.aapc");
+        let held = OutputLock::acquire(&dest, None).unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(held);
+        });
+        let start = Instant::now();
+        let lock = OutputLock::acquire(&dest, Some(Duration::from_secs(10))).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300), "got the lock after {:?}", start.elapsed());
+        release.join().unwrap();
+        assert_eq!(entries(dir), [".out.aapc.lock"]);
+        drop(lock);
+    }
+
+    #[test]
+    fn a_created_output_holds_its_lock_until_finished() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let dest = dir.join("out.bin");
+        let mut output = create_output(&dest.to_string_lossy(), false, None).unwrap();
+        output.write_all(b"locked while written").unwrap();
+        let err = create_output(&dest.to_string_lossy(), true, None).err().expect("two writers");
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        output.commit().unwrap();
+        assert_eq!(entries(dir), ["out.bin"]);
+        assert_eq!(fs::read(&dest).unwrap(), b"locked while written");
+    }
+}
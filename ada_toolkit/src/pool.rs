@@ -0,0 +1,109 @@
+//! Block-sized buffers recycled between blocks and between frames, so that
+//! a long stream of blocks, or many small files one after another, goes to
+//! the allocator once per buffer rather than once per block.
+//!
+//! One pool serves every thread, since a buffer is often filled on one
+//! thread and finished with on another. It holds at most [`KEPT_BYTES`] of
+//! capacity; buffers given back beyond that are freed as usual.
+
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::stats::PoolStats;
+
+/// Most capacity the pool keeps, across all its buffers.
+const KEPT_BYTES: usize = 32 * 1024 * 1024;
+
+struct Free {
+    buffers: Vec<Vec<u8>>,
+    bytes: usize,
+}
+
+static FREE: Mutex<Free> = Mutex::new(Free { buffers: Vec::new(), bytes: 0 });
+
+#[cfg(debug_assertions)]
+static TAKEN: AtomicU64 = AtomicU64::new(0);
+#[cfg(debug_assertions)]
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+/// An empty buffer with room for at least `capacity` bytes: one from the
+/// pool if it has one that big, otherwise a new one.
+pub(crate) fn take(capacity: usize) -> Vec<u8> {
+    let reused = {
+        let mut free = FREE.lock().unwrap_or_else(PoisonError::into_inner);
+        let found = free.buffers.iter().rposition(|buf| buf.capacity() >= capacity);
+        found.map(|at| {
+            let buf = free.buffers.swap_remove(at);
+            free.bytes -= buf.capacity();
+            buf
+        })
+    };
+    #[cfg(debug_assertions)]
+    {
+        TAKEN.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(u64::from(reused.is_none()), Ordering::Relaxed);
+    }
+    reused.unwrap_or_else(|| Vec::with_capacity(capacity))
+}
+
+/// Empties `buf` and keeps it for a later [`take`], unless the pool is full.
+pub(crate) fn give(mut buf: Vec<u8>) {
+    if buf.capacity() == 0 {
+        return;
+    }
+    buf.clear();
+    let mut free = FREE.lock().unwrap_or_else(PoisonError::into_inner);
+    if free.bytes + buf.capacity() <= KEPT_BYTES {
+        free.bytes += buf.capacity();
+        free.buffers.push(buf);
+    }
+}
+
+/// A block that can be handed back once encoded: a buffer goes back to the
+/// pool, and a borrowed slice is simply let go.
+pub(crate) trait Recycle {
+    fn recycle(self);
+}
+
+impl Recycle for Vec<u8> {
+    fn recycle(self) {
+        give(self);
+    }
+}
+
+impl Recycle for &[u8] {
+    fn recycle(self) {}
+}
+
+/// The pool's counters at some moment, for reporting the activity since as
+/// [`PoolStats`]; empty outside debug builds.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PoolMark {
+    #[cfg(debug_assertions)]
+    taken: u64,
+    #[cfg(debug_assertions)]
+    allocated: u64,
+}
+
+impl PoolMark {
+    pub(crate) fn now() -> PoolMark {
+        PoolMark {
+            #[cfg(debug_assertions)]
+            taken: TAKEN.load(Ordering::Relaxed),
+            #[cfg(debug_assertions)]
+            allocated: ALLOCATED.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The activity since `self`.
+    pub(crate) fn since(&self) -> PoolStats {
+        PoolStats {
+            #[cfg(debug_assertions)]
+            taken: TAKEN.load(Ordering::Relaxed) - self.taken,
+            #[cfg(debug_assertions)]
+            allocated: ALLOCATED.load(Ordering::Relaxed) - self.allocated,
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Progress bars and the size and speed units the CLI reports in.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::sync::PoisonError;
+use std::time::{Duration, Instant};
+
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::{Global, BARS};
+use crate::output::STDIO;
+use crate::report::bytes_per_second;
+
+/// Renders sizes and speeds in text reports: binary units with two
+/// decimals, or plain byte counts with `--bytes`.
+#[derive(Clone, Copy)]
+pub struct Units {
+    pub raw: bool,
+}
+
+impl Units {
+    pub fn size(self, bytes: u64) -> String {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.raw || bytes < 1024 {
+            return format!("{} bytes", bytes);
+        }
+        let mut value = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+
+    pub fn speed(self, bytes: u64, duration: Duration) -> String {
+        self.rate(bytes_per_second(bytes, duration))
+    }
+
+    pub fn rate(self, per_sec: f64) -> String {
+        match self.raw {
+            true => format!("{:.0} bytes/s", per_sec),
+            false => format!("{:.2} MiB/s", per_sec / (1024.0 * 1024.0)),
+        }
+    }
+}
+
+/// A ratio of compressed to original size, as the share of space saved.
+pub fn saved(ratio: f64) -> String {
+    match ratio <= 1.0 {
+        true => format!("{:.1}% saved", (1.0 - ratio) * 100.0),
+        false => format!("{:.1}% larger", (ratio - 1.0) * 100.0),
+    }
+}
+
+/// How progress is shown on stderr.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    Off,
+    /// Bars redrawn in place, when stderr is a terminal and not `--plain`.
+    Bars,
+    /// A plain line about once a second, for `--progress` into a log.
+    Lines,
+}
+
+impl Global {
+    pub fn progress_mode(&self) -> ProgressMode {
+        match (self.no_progress || self.quiet, self.progress, self.decorated(&io::stderr())) {
+            (true, _, _) => ProgressMode::Off,
+            (_, _, true) => ProgressMode::Bars,
+            (_, true, false) => ProgressMode::Lines,
+            (_, false, false) => ProgressMode::Off,
+        }
+    }
+}
+
+/// Progress display for one command: a bar per file, under an overall bar
+/// when there are several files.
+pub struct Meters {
+    mode: ProgressMode,
+    multi: MultiProgress,
+    overall: Option<ProgressBar>,
+}
+
+impl Meters {
+    pub fn new(mode: ProgressMode) -> Meters {
+        let target = match mode {
+            ProgressMode::Bars => ProgressDrawTarget::stderr(),
+            _ => ProgressDrawTarget::hidden(),
+        };
+        let multi = MultiProgress::with_draw_target(target);
+        if mode == ProgressMode::Bars {
+            *BARS.lock().unwrap_or_else(PoisonError::into_inner) = Some(multi.clone());
+        }
+        Meters { mode, multi, overall: None }
+    }
+
+    /// Adds the overall bar for a run over `files` files.
+    pub fn with_overall(mut self, files: usize) -> Meters {
+        if self.mode == ProgressMode::Bars && files > 1 {
+            let style = ProgressStyle::with_template("[{pos}/{len}] files {wide_bar} {elapsed_precise}")
+                .expect("valid template");
+            self.overall = Some(self.multi.add(ProgressBar::new(files as u64).with_style(style)));
+        }
+        self
+    }
+
+    /// Starts the meter for one file of `total` bytes, if its size is known.
+    pub fn file(&self, label: &str, total: Option<u64>) -> Meter {
+        let bar = (self.mode == ProgressMode::Bars).then(|| {
+            let (bar, template) = match total {
+                Some(total) => (ProgressBar::new(total),
+                                "{prefix} [{bar:30}] {bytes}/{total_bytes} {binary_bytes_per_sec}, ETA {eta}"),
+                None => (ProgressBar::new_spinner(), "{spinner} {prefix} {bytes} {binary_bytes_per_sec}"),
+            };
+            let style = ProgressStyle::with_template(template).expect("valid template");
+            self.multi.add(bar.with_style(style).with_prefix(label.to_string()))
+        });
+        let now = Instant::now();
+        let lines = (self.mode == ProgressMode::Lines).then(|| LineMeter {
+            label: label.to_string(),
+            total,
+            start: now,
+            next: now + Duration::from_secs(1),
+        });
+        Meter { bar, overall: self.overall.clone(), lines }
+    }
+
+    /// Writes through `inner` with the bars lifted, so lines don't tear them.
+    pub fn above<W: Write>(&self, inner: W) -> AboveBars<'_, W> {
+        AboveBars { multi: &self.multi, inner }
+    }
+
+    /// Clears the overall bar.
+    pub fn finish(&self) {
+        if let Some(overall) = &self.overall {
+            overall.finish_and_clear();
+        }
+    }
+}
+
+impl Drop for Meters {
+    fn drop(&mut self) {
+        self.finish();
+        if self.mode == ProgressMode::Bars {
+            *BARS.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        }
+    }
+}
+
+/// Progress of one file. Dropping it clears its bar and counts the file
+/// towards the overall bar.
+pub struct Meter {
+    bar: Option<ProgressBar>,
+    overall: Option<ProgressBar>,
+    lines: Option<LineMeter>,
+}
+
+struct LineMeter {
+    label: String,
+    total: Option<u64>,
+    start: Instant,
+    next: Instant,
+}
+
+impl Meter {
+    pub fn update(&mut self, done: u64) {
+        if let Some(bar) = &self.bar {
+            bar.set_position(done);
+        }
+        if let Some(lines) = &mut self.lines {
+            let now = Instant::now();
+            if now >= lines.next {
+                lines.next = now + Duration::from_secs(1);
+                eprintln!("{}", lines.describe(done, now - lines.start));
+            }
+        }
+    }
+}
+
+impl LineMeter {
+    fn describe(&self, done: u64, elapsed: Duration) -> String {
+        let rate = done as f64 / elapsed.as_secs_f64();
+        match self.total {
+            Some(total) if total > 0 && rate > 0.0 => {
+                let eta = Duration::from_secs_f64(total.saturating_sub(done) as f64 / rate);
+                format!("{}: {} of {} ({:.0}%), {}/s, ETA {}", self.label, HumanBytes(done), HumanBytes(total),
+                        done as f64 * 100.0 / total as f64, HumanBytes(rate as u64), HumanDuration(eta))
+            }
+            _ => format!("{}: {}, {}/s", self.label, HumanBytes(done), HumanBytes(rate as u64)),
+        }
+    }
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        if let Some(overall) = &self.overall {
+            overall.inc(1);
+        }
+    }
+}
+
+/// Passes bytes through, feeding the running count to a meter.
+pub struct Metered<T> {
+    inner: T,
+    meter: Meter,
+    done: u64,
+}
+
+impl<T> Metered<T> {
+    pub fn new(inner: T, meter: Meter) -> Metered<T> {
+        Metered { inner, meter, done: 0 }
+    }
+}
+
+impl<T: Read> Read for Metered<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.done += n as u64;
+        self.meter.update(self.done);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Metered<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.done += n as u64;
+        self.meter.update(self.done);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// See [`Meters::above`].
+pub struct AboveBars<'a, W> {
+    multi: &'a MultiProgress,
+    inner: W,
+}
+
+impl<W: Write> Write for AboveBars<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.multi.suspend(|| self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.multi.suspend(|| self.inner.flush())
+    }
+}
+
+/// Size of the file at `path`, for sizing its progress bar.
+pub fn input_size(path: &str) -> Option<u64> {
+    match path {
+        STDIO => None,
+        path => fs::metadata(path).ok().filter(|meta| meta.is_file()).map(|meta| meta.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sizes_at_unit_boundaries() {
+        let units = Units { raw: false };
+        for (bytes, text) in [
+            (0, "0 bytes"),
+            (1023, "1023 bytes"),
+            (1024, "1.00 KiB"),
+            (1536, "1.50 KiB"),
+            ((1 << 20) - 1, "1024.00 KiB"),
+            (1 << 20, "1.00 MiB"),
+            (1 << 30, "1.00 GiB"),
+            ((1 << 30) + (1 << 29), "1.50 GiB"),
+            (1 << 40, "1.00 TiB"),
+            (1 << 50, "1024.00 TiB"),
+        ] {
+            assert_eq!(units.size(bytes), text, "{} bytes", bytes);
+        }
+        assert_eq!(Units { raw: true }.size(1 << 30), "1073741824 bytes");
+    }
+
+    #[test]
+    fn speeds_and_savings() {
+        let second = Duration::from_secs(1);
+        assert_eq!(Units { raw: false }.speed(3 << 19, second), "1.50 MiB/s");
+        assert_eq!(Units { raw: true }.speed(3 << 19, second), "1572864 bytes/s");
+        assert_eq!(Units { raw: false }.rate(0.0), "0.00 MiB/s");
+        assert_eq!(saved(0.25), "75.0% saved");
+        assert_eq!(saved(1.0), "0.0% saved");
+        assert_eq!(saved(1.5), "50.0% larger");
+    }
+
+    fn line_meter(total: Option<u64>) -> LineMeter {
+        let now = Instant::now();
+        LineMeter { label: "in.bin".to_string(), total, start: now, next: now }
+    }
+
+    #[test]
+    fn lines_give_the_share_speed_and_eta() {
+        let line = line_meter(Some(4 << 20)).describe(1 << 20, Duration::from_secs(2));
+        assert_eq!(line, "in.bin: 1.00 MiB of 4.00 MiB (25%), 512.00 KiB/s, ETA 6 seconds");
+    }
+
+    #[test]
+    fn lines_without_a_total_give_the_count_and_speed() {
+        assert_eq!(line_meter(None).describe(3 << 20, Duration::from_secs(1)), "in.bin: 3.00 MiB, 3.00 MiB/s");
+        // An empty file's meter has nothing to take a share of.
+        assert_eq!(line_meter(Some(0)).describe(0, Duration::from_secs(1)), "in.bin: 0 B, 0 B/s");
+    }
+
+    #[test]
+    fn metered_io_moves_the_bars() {
+        let meters = Meters::new(ProgressMode::Bars).with_overall(2);
+        let overall = meters.overall.clone().expect("two files have an overall bar");
+        let mut reader = Metered::new(Cursor::new(vec![7u8; 10_000]), meters.file("a", Some(10_000)));
+        let mut writer = Metered::new(Vec::new(), meters.file("b", None));
+        io::copy(&mut reader, &mut writer).unwrap();
+        assert_eq!(reader.meter.bar.as_ref().unwrap().position(), 10_000);
+        assert_eq!(writer.meter.bar.as_ref().unwrap().position(), 10_000);
+        assert_eq!(writer.inner.len(), 10_000);
+        drop(reader);
+        assert_eq!(overall.position(), 1);
+        drop(writer);
+        assert_eq!(overall.position(), 2);
+    }
+
+    #[test]
+    fn only_bars_mode_makes_bars() {
+        for mode in [ProgressMode::Off, ProgressMode::Lines] {
+            let meters = Meters::new(mode).with_overall(3);
+            assert!(meters.overall.is_none());
+            let meter = meters.file("a", Some(1));
+            assert!(meter.bar.is_none());
+            assert_eq!(meter.lines.is_some(), mode == ProgressMode::Lines);
+        }
+    }
+}
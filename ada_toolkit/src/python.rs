@@ -0,0 +1,140 @@
+//! Python extension module, built with the `python` feature.
+//!
+//! ```python
+//! import ada_compression
+//! blob = ada_compression.compress(data)
+//! assert ada_compression.decompress(blob) == data
+//! ```
+
+use std::io::Write;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::error::DecompressError;
+use crate::stream::AapcWriter;
+
+create_exception!(ada_compression, AapcError, PyValueError, "Base class for AAPC errors.");
+create_exception!(ada_compression, TruncatedError, AapcError, "Compressed data ended early.");
+create_exception!(ada_compression, FormatError, AapcError, "Input is not a valid AAPC frame.");
+create_exception!(ada_compression, LimitExceededError, AapcError, "Output exceeded max_size.");
+create_exception!(ada_compression, ChecksumError, AapcError, "Decoded data failed its checksum.");
+
+fn to_py_err(err: DecompressError) -> PyErr {
+    let msg = err.to_string();
+    match err {
+        DecompressError::Truncated { .. } | DecompressError::TruncatedField { .. } => TruncatedError::new_err(msg),
+        DecompressError::LimitExceeded { .. } | DecompressError::MemoryLimit { .. } => {
+            LimitExceededError::new_err(msg)
+        }
+        DecompressError::ChecksumMismatch { .. } | DecompressError::FrameChecksumMismatch { .. } => {
+            ChecksumError::new_err(msg)
+        }
+        DecompressError::Io(_) | DecompressError::Cancelled => AapcError::new_err(msg),
+        DecompressError::BadMagic
+        | DecompressError::UnsupportedVersion(_)
+        | DecompressError::UnknownBlockType(_)
+        | DecompressError::Corrupt { .. } => FormatError::new_err(msg),
+    }
+}
+
+fn io_to_py_err(err: std::io::Error) -> PyErr {
+    AapcError::new_err(err.to_string())
+}
+
+/// The codec has a single level for now, so, as on the command line, any
+/// level given is refused rather than ignored; the argument is there so
+/// that code passing none keeps working when levels are added.
+fn check_level(level: Option<u32>) -> PyResult<()> {
+    match level {
+        None => Ok(()),
+        Some(level) => Err(PyValueError::new_err(format!(
+            "level={} has no effect: the codec has only one level; leave it out",
+            level
+        ))),
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (data, level=None))]
+fn compress<'py>(py: Python<'py>, data: &[u8], level: Option<u32>) -> PyResult<Bound<'py, PyBytes>> {
+    check_level(level)?;
+    let out = py.allow_threads(|| crate::compression::compress(data));
+    Ok(PyBytes::new(py, &out))
+}
+
+#[pyfunction]
+#[pyo3(signature = (data, max_size=None))]
+fn decompress<'py>(py: Python<'py>, data: &[u8], max_size: Option<usize>) -> PyResult<Bound<'py, PyBytes>> {
+    let limit = max_size.unwrap_or(usize::MAX);
+    let out = py
+        .allow_threads(|| crate::decompression::decompress_limited(data, limit))
+        .map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &out))
+}
+
+/// Streaming compressor mirroring `AapcWriter`: every call returns the
+/// compressed bytes produced so far, and `finish()` returns the tail of the frame.
+#[pyclass]
+struct Compressor {
+    writer: Option<AapcWriter<Vec<u8>>>,
+}
+
+impl Compressor {
+    fn writer(&mut self) -> PyResult<&mut AapcWriter<Vec<u8>>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| AapcError::new_err("compressor already finished"))
+    }
+}
+
+#[pymethods]
+impl Compressor {
+    #[new]
+    #[pyo3(signature = (level=None))]
+    fn new(level: Option<u32>) -> PyResult<Self> {
+        check_level(level)?;
+        Ok(Compressor { writer: Some(AapcWriter::new(Vec::new())) })
+    }
+
+    fn write<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let writer = self.writer()?;
+        py.allow_threads(|| writer.write_all(data)).map_err(io_to_py_err)?;
+        let out = PyBytes::new(py, writer.get_ref());
+        writer.get_mut().clear();
+        Ok(out)
+    }
+
+    fn flush<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let writer = self.writer()?;
+        writer.flush().map_err(io_to_py_err)?;
+        let out = PyBytes::new(py, writer.get_ref());
+        writer.get_mut().clear();
+        Ok(out)
+    }
+
+    fn finish<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let writer = self
+            .writer
+            .take()
+            .ok_or_else(|| AapcError::new_err("compressor already finished"))?;
+        let out = py.allow_threads(|| writer.finish()).map_err(io_to_py_err)?;
+        Ok(PyBytes::new(py, &out))
+    }
+}
+
+#[pymodule]
+fn ada_compression(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_class::<Compressor>()?;
+    m.add("AapcError", py.get_type::<AapcError>())?;
+    m.add("TruncatedError", py.get_type::<TruncatedError>())?;
+    m.add("FormatError", py.get_type::<FormatError>())?;
+    m.add("LimitExceededError", py.get_type::<LimitExceededError>())?;
+    m.add("ChecksumError", py.get_type::<ChecksumError>())?;
+    Ok(())
+}
@@ -0,0 +1,393 @@
+//! Per-file result lines, the `--report` file, and the JSON and CSV
+//! encoding they share.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ada_toolkit::PhaseTimes;
+
+use crate::{context, Failure};
+use crate::commands::compress::{FileReport, Sniffed};
+use crate::output::AtomicFile;
+use crate::progress::{saved, Units};
+
+/// The text report of one input written to `output`, or that would have
+/// been with `dry_run`, or for a decompression, what was decoded and
+/// discarded.
+pub fn file_line(input: &str, output: &str, report: &FileReport, compressing: bool, dry_run: bool, units: Units) -> String {
+    let (stored, compressed) = if dry_run { ("Would store", "Would compress") } else { ("Stored", "Compressed") };
+    if let Some(Sniffed { kind, .. }) = report.sniffed {
+        format!("{} {} ({}) to {} ({}) in {:?} without compressing: already compressed ({})",
+                stored, input, units.size(report.input_bytes), output, units.size(report.output_bytes),
+                report.duration, kind)
+    } else if compressing {
+        let ratio = ratio(report.output_bytes, report.input_bytes);
+        format!("{} {} ({}) to {} ({}) in {:?} at {}. Ratio: {:.2} ({}){}",
+                compressed, input, units.size(report.input_bytes), output, units.size(report.output_bytes),
+                report.duration, units.speed(report.input_bytes, report.duration), ratio, saved(ratio),
+                if report.verified { ". Verified after writing" } else { "" })
+    } else if dry_run {
+        format!("Decompressed {} ({}) to {}, discarded, in {:?} at {}.",
+                input, units.size(report.input_bytes), units.size(report.output_bytes),
+                report.duration, units.speed(report.output_bytes, report.duration))
+    } else {
+        format!("Decompressed {} ({}) to {} ({}) in {:?} at {}.",
+                input, units.size(report.input_bytes), output, units.size(report.output_bytes),
+                report.duration, units.speed(report.output_bytes, report.duration))
+    }
+}
+
+/// Each phase's busy time and share of the wall-clock time, for `--verbose`.
+/// Phases that overlapped on several threads can add up to more than 100%.
+pub fn phases_line(phases: &PhaseTimes, compressing: bool) -> String {
+    let share = |phase: Duration| match phases.wall.as_secs_f64() {
+        wall if wall > 0.0 => format!("{:?} ({:.0}%)", phase, phase.as_secs_f64() / wall * 100.0),
+        _ => format!("{:?}", phase),
+    };
+    let (code, checksum) = if compressing { ("encode", "checksum") } else { ("decode", "verify") };
+    format!("wall {:?}; read {}, {} {}, {} {}, write {}; busy {}",
+            phases.wall, share(phases.read), code, share(phases.code), checksum, share(phases.checksum),
+            share(phases.write), share(phases.busy()))
+}
+
+/// One input's entry in the JSON summary and the `--report` document.
+pub fn file_json(input: &str, output: &str, result: &Result<FileReport, Failure>, compressing: bool) -> String {
+    // Discarded output has no name.
+    let output = match output {
+        "" => "null".to_string(),
+        output => json_string(output),
+    };
+    match result {
+        Ok(FileReport { sniffed: Some(Sniffed { kind, stored: false }), input_bytes, .. }) => format!(
+            "{{\"input\":{},\"output\":null,\"status\":\"skipped\",\"detected\":{},\"input_bytes\":{}}}",
+            json_string(input), json_string(kind), input_bytes),
+        Ok(report) => format!(
+            "{{\"input\":{},\"output\":{},\"status\":\"{}\",\"detected\":{},\"input_bytes\":{},\
+             \"output_bytes\":{},\"ratio\":{},\"checksum\":{},\"verified\":{},{},{}}}",
+            json_string(input), output,
+            if report.sniffed.is_some() { "stored" } else { "ok" },
+            report.sniffed.map_or("null".to_string(), |sniffed| json_string(sniffed.kind)),
+            report.input_bytes, report.output_bytes,
+            match compressing {
+                true => ratio(report.output_bytes, report.input_bytes),
+                false => ratio(report.input_bytes, report.output_bytes),
+            },
+            report.checksum.map_or("null".to_string(), |crc| format!("\"{:08x}\"", crc)),
+            report.verified,
+            json_timing("", report.input_bytes, report.duration),
+            json_phases(&report.phases, compressing)),
+        Err(failure) => format!(
+            "{{\"input\":{},\"output\":{},\"status\":\"error\",\"exit_code\":{},\"error\":{}}}",
+            json_string(input), output, failure.code,
+            json_string(&failure.error.to_string())),
+    }
+}
+
+/// The `--report` document: every input's outcome, the options used and
+/// the run's totals, written atomically when the run ends. Dropping it
+/// unfinished, as an early return does, still writes what completed, with
+/// status "aborted".
+pub struct BatchReport {
+    path: PathBuf,
+    compressing: bool,
+    options: String,
+    started: SystemTime,
+    clock: Instant,
+    inputs: usize,
+    files: Vec<String>,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    stored: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    written: bool,
+}
+
+impl BatchReport {
+    pub fn new(path: &Path, compressing: bool, inputs: usize, options: String) -> BatchReport {
+        BatchReport {
+            path: path.to_path_buf(),
+            compressing,
+            options,
+            started: SystemTime::now(),
+            clock: Instant::now(),
+            inputs,
+            files: Vec::new(),
+            succeeded: 0,
+            failed: 0,
+            skipped: 0,
+            stored: 0,
+            input_bytes: 0,
+            output_bytes: 0,
+            written: false,
+        }
+    }
+
+    pub fn add(&mut self, input: &str, output: &str, result: &Result<FileReport, Failure>) {
+        self.files.push(file_json(input, output, result, self.compressing));
+        match result {
+            Ok(FileReport { sniffed: Some(Sniffed { stored: false, .. }), .. }) => self.skipped += 1,
+            Ok(report) => {
+                self.succeeded += 1;
+                self.stored += usize::from(report.sniffed.is_some());
+                self.input_bytes += report.input_bytes;
+                self.output_bytes += report.output_bytes;
+            }
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    /// Writes the report for a run that ran to its end, or was cancelled.
+    pub fn finish(mut self, cancelled: bool) -> io::Result<()> {
+        self.written = true;
+        self.write(if cancelled { "cancelled" } else { "complete" })
+            .map_err(|e| context(e, "writing report", &self.path.to_string_lossy()))
+    }
+
+    fn write(&self, status: &str) -> io::Result<()> {
+        let secs = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (compressed, original) = match self.compressing {
+            true => (self.output_bytes, self.input_bytes),
+            false => (self.input_bytes, self.output_bytes),
+        };
+        let json = format!(
+            "{{\"report_version\":1,\"aapc_version\":\"{}\",\"operation\":\"{}\",\"status\":\"{}\",\
+             \"started\":{},\"finished\":{},\"seconds\":{},\"options\":{},\"totals\":{{\"files\":{},\
+             \"processed\":{},\"succeeded\":{},\"failed\":{},\"skipped\":{},\"stored\":{},\"input_bytes\":{},\
+             \"output_bytes\":{},\"ratio\":{}}},\"files\":[{}]}}\n",
+            env!("CARGO_PKG_VERSION"), if self.compressing { "compress" } else { "decompress" }, status,
+            secs(self.started), secs(SystemTime::now()), self.clock.elapsed().as_secs_f64(), self.options,
+            self.inputs, self.files.len(), self.succeeded, self.failed, self.skipped, self.stored,
+            self.input_bytes, self.output_bytes, ratio(compressed, original), self.files.join(","),
+        );
+        let mut file = AtomicFile::create(&self.path)?;
+        file.write_all(json.as_bytes())?;
+        file.commit()
+    }
+}
+
+impl Drop for BatchReport {
+    fn drop(&mut self) {
+        if !self.written {
+            if let Err(e) = self.write("aborted") {
+                log::warn!("Could not write report {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// What a run's summary line starts with, to say nothing was written.
+pub fn dry_run_note(dry_run: bool) -> &'static str {
+    if dry_run { "Dry run, nothing written: " } else { "" }
+}
+
+/// Lists the errors of a run's failed files after its summary, so none is
+/// lost among the per-file lines above it.
+pub fn write_failures(status: &mut dyn Write, errors: &[String]) -> io::Result<()> {
+    if !errors.is_empty() {
+        writeln!(status, "Failed:")?;
+        for error in errors {
+            writeln!(status, "  {}", error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field when it holds a comma, quote or line break.
+pub fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+/// Splits one line of CSV into its fields, undoing the quoting of
+/// [`csv_field`]; `None` if a quoted field is left open.
+pub fn csv_fields(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("there is always a field");
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(String::new()),
+            (c, _) => field.push(c),
+        }
+    }
+    (!quoted).then_some(fields)
+}
+
+/// Quotes `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The elements of a JSON array of strings, without the brackets.
+pub fn json_strings(items: &[String]) -> String {
+    items.iter().map(|item| json_string(item)).collect::<Vec<_>>().join(",")
+}
+
+/// `duration_ms` and `bytes_per_second` JSON fields, each name prefixed by
+/// `prefix`, for `bytes` processed in `duration`.
+pub fn json_timing(prefix: &str, bytes: u64, duration: Duration) -> String {
+    let ms = duration.as_secs_f64() * 1000.0;
+    format!("\"{0}duration_ms\":{1:.3},\"{0}bytes_per_second\":{2:.0}", prefix, ms, bytes_per_second(bytes, duration))
+}
+
+/// A `phases` JSON object of each phase's busy time and the wall-clock
+/// time, in milliseconds, named for compressing or decompressing.
+fn json_phases(phases: &PhaseTimes, compressing: bool) -> String {
+    let ms = |phase: Duration| phase.as_secs_f64() * 1000.0;
+    let (code, checksum) = if compressing { ("encode", "checksum") } else { ("decode", "verify") };
+    format!("\"phases\":{{\"wall_ms\":{:.3},\"read_ms\":{:.3},\"{}_ms\":{:.3},\"{}_ms\":{:.3},\
+             \"write_ms\":{:.3},\"busy_ms\":{:.3}}}",
+            ms(phases.wall), ms(phases.read), code, ms(phases.code), checksum, ms(phases.checksum),
+            ms(phases.write), ms(phases.busy()))
+}
+
+/// `bytes` over `duration`, or 0 when no time was measured.
+pub fn bytes_per_second(bytes: u64, duration: Duration) -> f64 {
+    let secs = duration.as_secs_f64();
+    if secs > 0.0 { bytes as f64 / secs } else { 0.0 }
+}
+
+/// Compressed size over original size, or 1 for empty content, which has
+/// nothing to save: an empty input is not "100.0% saved" however big its
+/// frame.
+pub fn ratio(compressed: u64, original: u64) -> f64 {
+    if original == 0 { 1.0 } else { compressed as f64 / original as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    fn compressed(input_bytes: u64, output_bytes: u64) -> Result<FileReport, Failure> {
+        Ok(FileReport {
+            input_bytes,
+            output_bytes,
+            duration: Duration::from_millis(2),
+            checksum: Some(0x0123_abcd),
+            sniffed: None,
+            verified: false,
+            phases: PhaseTimes::default(),
+        })
+    }
+
+    fn failed(message: &str) -> Result<FileReport, Failure> {
+        Err(Failure { code: 3, error: io::Error::new(io::ErrorKind::InvalidData, message.to_string()) })
+    }
+
+    #[test]
+    fn a_failed_file_keeps_its_error_and_exit_code() {
+        let entry = file_json("bad \"one\".aapc", "bad", &failed("bad magic\nat offset 0"), false);
+        assert_eq!(entry, "{\"input\":\"bad \\\"one\\\".aapc\",\"output\":\"bad\",\"status\":\"error\",\
+                           \"exit_code\":3,\"error\":\"bad magic\\nat offset 0\"}");
+        let discarded = file_json("in.aapc", "", &failed("truncated"), false);
+        assert!(discarded.contains("\"output\":null"), "{}", discarded);
+    }
+
+    #[test]
+    fn a_finished_report_has_its_totals_and_every_file() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let path = dir.join("report.json");
+        let mut report = BatchReport::new(&path, true, 3, "{\"block_size\":4096}".to_string());
+        report.add("a", "a.aapc", &compressed(1000, 100));
+        report.add("b", "b.aapc", &failed("no such file"));
+        report.add("c", "c.aapc", &compressed(3000, 300));
+        report.finish(false).unwrap();
+
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.starts_with(&format!("{{\"report_version\":1,\"aapc_version\":\"{}\",\"operation\":\"compress\",\
+                                           \"status\":\"complete\",", env!("CARGO_PKG_VERSION"))), "{}", json);
+        assert!(json.contains(",\"options\":{\"block_size\":4096},\"totals\":{\"files\":3,\"processed\":3,\
+                               \"succeeded\":2,\"failed\":1,\"skipped\":0,\"stored\":0,\"input_bytes\":4000,\
+                               \"output_bytes\":400,\"ratio\":0.1},"), "{}", json);
+        assert!(json.contains("\"checksum\":\"0123abcd\""), "{}", json);
+        assert!(json.contains("\"error\":\"no such file\""), "{}", json);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn a_dropped_report_is_written_as_aborted_with_what_completed() {
+        let tmp = TempDir::new();
+        let dir = tmp.path();
+        let path = dir.join("report.json");
+        let mut report = BatchReport::new(&path, false, 5, "{}".to_string());
+        report.add("a.aapc", "a", &compressed(100, 1000));
+        drop(report);
+
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"operation\":\"decompress\",\"status\":\"aborted\","), "{}", json);
+        assert!(json.contains("\"totals\":{\"files\":5,\"processed\":1,\"succeeded\":1,"), "{}", json);
+        assert!(json.contains("\"files\":[{\"input\":\"a.aapc\",\"output\":\"a\",\"status\":\"ok\","), "{}", json);
+
+        BatchReport::new(&path, true, 1, "{}".to_string()).finish(true).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("\"status\":\"cancelled\""));
+    }
+
+    #[test]
+    fn json_strings_escape_quotes_backslashes_and_controls() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}é"), "\"a\\\"b\\\\c\\nd\\u0001é\"");
+        assert_eq!(json_strings(&["x".to_string(), "y".to_string()]), "\"x\",\"y\"");
+    }
+
+    #[test]
+    fn empty_content_saves_nothing() {
+        assert_eq!(ratio(6, 0), 1.0);
+        assert_eq!(saved(ratio(6, 0)), "0.0% saved");
+        assert_eq!(ratio(0, 0), 1.0);
+        assert_eq!(ratio(50, 200), 0.25);
+    }
+
+    fn phases() -> PhaseTimes {
+        let ms = Duration::from_millis;
+        PhaseTimes { wall: ms(200), read: ms(10), code: ms(50), checksum: ms(100), write: ms(40) }
+    }
+
+    #[test]
+    fn the_phases_line_gives_each_share_of_the_wall_time() {
+        assert_eq!(phases_line(&phases(), true),
+                   "wall 200ms; read 10ms (5%), encode 50ms (25%), checksum 100ms (50%), write 40ms (20%); \
+                    busy 200ms (100%)");
+        let overlapped = PhaseTimes { wall: Duration::from_millis(100), ..phases() };
+        assert!(phases_line(&overlapped, false).ends_with("verify 100ms (100%), write 40ms (40%); busy 200ms (200%)"));
+        // Nothing timed has no shares to give.
+        assert_eq!(phases_line(&PhaseTimes::default(), false),
+                   "wall 0ns; read 0ns, decode 0ns, verify 0ns, write 0ns; busy 0ns");
+    }
+
+    #[test]
+    fn json_phases_are_named_for_the_direction() {
+        assert_eq!(json_phases(&phases(), true),
+                   "\"phases\":{\"wall_ms\":200.000,\"read_ms\":10.000,\"encode_ms\":50.000,\
+                    \"checksum_ms\":100.000,\"write_ms\":40.000,\"busy_ms\":200.000}");
+        let decoded = json_phases(&phases(), false);
+        assert!(decoded.contains("\"decode_ms\":50.000,\"verify_ms\":100.000,"), "{}", decoded);
+        let entry = file_json("a", "a.aapc", &compressed(1000, 100), true);
+        assert!(entry.contains(",\"phases\":{\"wall_ms\":0.000,\"read_ms\":0.000,\"encode_ms\""), "{}", entry);
+    }
+}
@@ -0,0 +1,244 @@
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+/// Totals for one compressed frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CompressionStats {
+    /// Uncompressed bytes consumed.
+    pub input_bytes: u64,
+    /// Compressed bytes produced, including header and trailer.
+    pub output_bytes: u64,
+    /// Blocks emitted.
+    pub blocks: u32,
+    /// How many of `blocks` were stored rather than RLE-encoded.
+    pub stored_blocks: u32,
+    /// CRC-32 of the content, taken as it was encoded; `None` when the
+    /// frame has neither block nor content checksums, so none was taken.
+    pub content_crc: Option<u32>,
+    /// Use of the internal buffer pool while the frame was encoded.
+    pub pool: PoolStats,
+    /// Where the time encoding the frame went.
+    pub phases: PhaseTimes,
+}
+
+/// Where the time coding one frame went. Each phase is its busy time,
+/// summed over every thread that did some of it, so when phases overlap,
+/// as they do on several threads, together they can come to more than
+/// `wall`; time spent waiting on another phase is in none of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PhaseTimes {
+    /// From start to finish, by the clock.
+    pub wall: Duration,
+    /// Reading input: uncompressed when encoding, the frame when decoding.
+    pub read: Duration,
+    /// Encoding or decoding block payloads.
+    pub code: Duration,
+    /// Taking block and content checksums when encoding; checking them
+    /// when decoding.
+    pub checksum: Duration,
+    /// Writing output, flushes included.
+    pub write: Duration,
+}
+
+impl PhaseTimes {
+    /// The phases' busy time added up.
+    pub fn busy(&self) -> Duration {
+        self.read + self.code + self.checksum + self.write
+    }
+}
+
+/// Adds the phases' busy time; `wall` is left alone.
+impl AddAssign for PhaseTimes {
+    fn add_assign(&mut self, other: PhaseTimes) {
+        self.read += other.read;
+        self.code += other.code;
+        self.checksum += other.checksum;
+        self.write += other.write;
+    }
+}
+
+/// Runs `f`, adding the time it takes to `phase`.
+pub(crate) fn timed<T>(phase: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *phase += start.elapsed();
+    result
+}
+
+/// Buffers taken from the internal buffer pool, by any thread, so frames
+/// encoded at the same time count each other's. The counts exist in debug
+/// builds only, for checking that a steady stream of blocks stops
+/// allocating once the pool is warm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PoolStats {
+    /// Buffers taken.
+    #[cfg(debug_assertions)]
+    pub taken: u64,
+    /// How many of `taken` the pool had none for, and allocated.
+    #[cfg(debug_assertions)]
+    pub allocated: u64,
+}
+
+impl CompressionStats {
+    /// Compressed size divided by original size; 0 for empty input.
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            0.0
+        } else {
+            self.output_bytes as f64 / self.input_bytes as f64
+        }
+    }
+}
+
+/// Running totals handed to a progress callback.
+///
+/// Reported after every block and once more when the frame is complete, at
+/// which point the totals equal the real input and output sizes. Each field
+/// only ever grows between calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Progress {
+    /// Bytes consumed: uncompressed when encoding, compressed when decoding.
+    pub input_bytes: u64,
+    /// Bytes produced: compressed when encoding, uncompressed when decoding.
+    pub output_bytes: u64,
+    /// Blocks completed so far.
+    pub blocks: u32,
+    /// How many of `blocks` were stored rather than RLE-encoded.
+    pub stored_blocks: u32,
+}
+
+/// Optional progress hook threaded through the encoders and decoders.
+pub type ProgressFn<'a> = Option<&'a mut dyn FnMut(Progress)>;
+
+pub(crate) fn report(progress: &mut ProgressFn<'_>, input_bytes: u64, output_bytes: u64, blocks: u32, stored_blocks: u32) {
+    if let Some(callback) = progress {
+        callback(Progress { input_bytes, output_bytes, blocks, stored_blocks });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::{compress_with_progress, compress_with_stats};
+    use crate::decompression::decompress_with_progress;
+    use crate::options::{CompressOptions, DecompressOptions};
+    use crate::stream::{copy_decode, copy_decode_with_phases, copy_encode};
+
+    const BLOCK: usize = 10_000;
+
+    fn data() -> Vec<u8> {
+        (0..95_000u32).map(|i| if i % 4000 < 2500 { 1 } else { (i * 13 / 5) as u8 }).collect()
+    }
+
+    fn opts(threads: usize) -> CompressOptions {
+        CompressOptions { block_size: BLOCK, threads, ..CompressOptions::default() }
+    }
+
+    /// Checks that `calls` only grow, cover every block one by one and end
+    /// at the given totals.
+    fn assert_sequence(calls: &[Progress], input: usize, output: usize, blocks: u32) {
+        assert!(calls.len() > blocks as usize, "{} calls for {} blocks", calls.len(), blocks);
+        for pair in calls.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert!(b.input_bytes >= a.input_bytes && b.output_bytes >= a.output_bytes, "{:?} then {:?}", a, b);
+            assert!(b.blocks == a.blocks || b.blocks == a.blocks + 1, "{:?} then {:?}", a, b);
+            assert!(b.stored_blocks >= a.stored_blocks);
+        }
+        let last = calls.last().unwrap();
+        assert_eq!((last.input_bytes, last.output_bytes, last.blocks), (input as u64, output as u64, blocks));
+    }
+
+    #[test]
+    fn encode_progress_ends_at_the_frame_size() {
+        let data = data();
+        let blocks = data.len().div_ceil(BLOCK) as u32;
+        for threads in [0, 3] {
+            let mut calls = Vec::new();
+            let (frame, stats) = compress_with_progress(&data, &opts(threads), &mut |p| calls.push(p)).unwrap();
+            assert_sequence(&calls, data.len(), frame.len(), blocks);
+            assert_eq!(calls.last().unwrap().stored_blocks, stats.stored_blocks);
+
+            let mut streamed = Vec::new();
+            let mut calls = Vec::new();
+            copy_encode(data.as_slice(), &mut streamed, &opts(threads), Some(&mut |p| calls.push(p))).unwrap();
+            assert_sequence(&calls, data.len(), streamed.len(), blocks);
+        }
+    }
+
+    #[test]
+    fn decode_progress_ends_at_the_content_size() {
+        let data = data();
+        let (frame, _) = compress_with_stats(&data, &opts(0)).unwrap();
+        let blocks = data.len().div_ceil(BLOCK) as u32;
+
+        let mut calls = Vec::new();
+        decompress_with_progress(&frame, &mut |p| calls.push(p)).unwrap();
+        assert_sequence(&calls, frame.len(), data.len(), blocks);
+
+        let mut calls = Vec::new();
+        copy_decode(frame.as_slice(), &mut Vec::new(), None, Some(&mut |p| calls.push(p))).unwrap();
+        assert_sequence(&calls, frame.len(), data.len(), blocks);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn stats_json_round_trip() {
+        let (_, stats) = compress_with_stats(&[0; 100_000], &CompressOptions::default()).unwrap();
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(serde_json::from_str::<CompressionStats>(&json).unwrap(), stats);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn phase_times_keep_their_durations() {
+        let phases =
+            PhaseTimes { wall: Duration::from_millis(1500), code: Duration::from_nanos(7), ..PhaseTimes::default() };
+        let json = serde_json::to_value(phases).unwrap();
+        assert_eq!(json["wall"], serde_json::json!({"secs": 1, "nanos": 500_000_000}));
+        assert_eq!(serde_json::from_value::<PhaseTimes>(json).unwrap(), phases);
+    }
+
+    /// Checks that one thread's `phases` account for most of its wall time
+    /// and never for more.
+    fn assert_accounted(phases: &PhaseTimes) {
+        assert!(phases.busy() <= phases.wall, "{:?}", phases);
+        assert!(phases.busy() * 2 >= phases.wall, "{:?}", phases);
+    }
+
+    #[test]
+    fn one_thread_spends_its_wall_time_in_the_phases() {
+        let data: Vec<u8> = data().repeat(10);
+        let (frame, stats) = compress_with_stats(&data, &opts(0)).unwrap();
+        assert_accounted(&stats.phases);
+        // Nothing was read or written: the input and frame are in memory.
+        assert_eq!((stats.phases.read, stats.phases.write), (Duration::ZERO, Duration::ZERO));
+        assert!(stats.phases.code > Duration::ZERO && stats.phases.checksum > Duration::ZERO, "{:?}", stats.phases);
+
+        let streamed = copy_encode(data.as_slice(), &mut Vec::new(), &opts(0), None).unwrap();
+        assert_accounted(&streamed.phases);
+        assert!(streamed.phases.read > Duration::ZERO && streamed.phases.write > Duration::ZERO);
+
+        let (written, phases) = copy_decode_with_phases(frame.as_slice(), &mut Vec::new(),
+                                                        &DecompressOptions::default(), None).unwrap();
+        assert_eq!(written, data.len() as u64);
+        assert_accounted(&phases);
+        assert!(phases.code > Duration::ZERO && phases.checksum > Duration::ZERO, "{:?}", phases);
+    }
+
+    #[test]
+    fn adding_phases_leaves_the_wall_time() {
+        let ms = Duration::from_millis;
+        let mut total = PhaseTimes { wall: ms(10), read: ms(1), code: ms(2), checksum: ms(3), write: ms(4) };
+        total += PhaseTimes { wall: ms(100), read: ms(1), code: ms(1), checksum: ms(1), write: ms(1) };
+        assert_eq!(total, PhaseTimes { wall: ms(10), read: ms(2), code: ms(3), checksum: ms(4), write: ms(5) });
+        assert_eq!(total.busy(), ms(14));
+    }
+}
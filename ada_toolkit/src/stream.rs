@@ -0,0 +1,1608 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::cancel::{is_cancelled, CancelToken};
+use crate::checksum::Crc32;
+use crate::compression::{encode_block_into, encode_in_order, encode_small_frame};
+use crate::decompression::decode_payload;
+use crate::error::{CompressError, DecompressError};
+use crate::frame::{self, FrameInfo, Header, Trailer, BLOCK_STORED, SMALL_FRAME_LIMIT};
+use crate::options::{
+    blocks_in_flight, memory_for_pipeline, memory_for_threads, pipeline_blocks, threads_within, CompressOptions,
+    DecompressOptions, READ_AHEAD,
+};
+use crate::pool::{self, PoolMark, Recycle};
+use crate::stats::{self, timed, CompressionStats, PhaseTimes, ProgressFn};
+
+/// Encoder state shared by the sync and async writers.
+///
+/// Input is collected into `block`; every completed block is encoded onto the
+/// `pending` queue, which the owning adapter drains into its inner writer.
+pub(crate) struct BlockEncoder {
+    header: Header,
+    cancel: Option<CancelToken>,
+    store_only: bool,
+    block: Vec<u8>,
+    block_size: usize,
+    pending: Vec<u8>,
+    pos: usize,
+    header_written: bool,
+    finished: bool,
+    block_count: u32,
+    stored_blocks: u32,
+    content_size: u64,
+    content_crc: Crc32,
+    produced: u64,
+    pool_mark: PoolMark,
+    /// Busy time so far; the adapters add their reading and writing.
+    phases: PhaseTimes,
+}
+
+impl BlockEncoder {
+    pub(crate) fn new(opts: &CompressOptions) -> Result<Self, CompressError> {
+        opts.validate()?;
+        let block_size = opts.block_size;
+        let pool_mark = PoolMark::now();
+        Ok(BlockEncoder {
+            header: Header::for_options(opts),
+            cancel: opts.cancel.clone(),
+            store_only: opts.store_only,
+            block: pool::take(block_size),
+            block_size,
+            pending: pool::take(block_size),
+            pos: 0,
+            header_written: false,
+            finished: false,
+            block_count: 0,
+            stored_blocks: 0,
+            content_size: 0,
+            content_crc: Crc32::new(),
+            produced: 0,
+            pool_mark,
+            phases: PhaseTimes::default(),
+        })
+    }
+
+    /// Buffers as much of `buf` as fits in the current block and returns how much was taken.
+    pub(crate) fn push(&mut self, buf: &[u8]) -> Result<usize, CompressError> {
+        self.write_header();
+        let take = buf.len().min(self.block_size - self.block.len());
+        self.block.extend_from_slice(&buf[..take]);
+        if self.block.len() == self.block_size {
+            self.emit_block()?;
+        }
+        Ok(take)
+    }
+
+    /// Ends the current block early so everything pushed so far is decodable.
+    pub(crate) fn flush_block(&mut self) -> Result<(), CompressError> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        self.emit_block()
+    }
+
+    /// Encodes the last partial block and the trailer. Idempotent.
+    ///
+    /// Once cancelled this keeps failing, so a cancelled frame never gets a trailer.
+    pub(crate) fn finish(&mut self) -> Result<(), CompressError> {
+        if self.finished {
+            return Ok(());
+        }
+        if is_cancelled(self.cancel.as_ref()) {
+            return Err(CompressError::Cancelled);
+        }
+        self.write_header();
+        if !self.block.is_empty() {
+            self.emit_block()?;
+        }
+        let before = self.pending.len();
+        let trailer = Trailer {
+            block_count: self.block_count,
+            content_size: self.content_size,
+            content_checksum: self.header.content_checksum().then(|| self.content_crc.finish()),
+        };
+        frame::write_trailer(&mut self.pending, &self.header, &trailer);
+        self.produced += (self.pending.len() - before) as u64;
+        self.finished = true;
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            input_bytes: self.content_size,
+            output_bytes: self.produced,
+            blocks: self.block_count,
+            stored_blocks: self.stored_blocks,
+            content_crc: self.header.needs_block_crc().then(|| self.content_crc.finish()),
+            pool: self.pool_mark.since(),
+            phases: self.phases,
+        }
+    }
+
+    /// Where the frame stands, if nothing is buffered between blocks.
+    pub(crate) fn resume_point(&self) -> Option<ResumePoint> {
+        if !self.header_written || self.finished || !self.block.is_empty() || self.pos < self.pending.len() {
+            return None;
+        }
+        Some(ResumePoint {
+            input_bytes: self.content_size,
+            output_bytes: self.produced,
+            blocks: self.block_count,
+            stored_blocks: self.stored_blocks,
+            content_crc: self.content_crc.finish(),
+        })
+    }
+
+    /// An encoder that carries on a frame already written up to `point`.
+    pub(crate) fn resume(opts: &CompressOptions, point: &ResumePoint) -> Result<Self, CompressError> {
+        let mut encoder = BlockEncoder::new(opts)?;
+        encoder.header_written = true;
+        encoder.block_count = point.blocks;
+        encoder.stored_blocks = point.stored_blocks;
+        encoder.content_size = point.input_bytes;
+        encoder.content_crc = Crc32::resume(point.content_crc);
+        encoder.produced = point.output_bytes;
+        Ok(encoder)
+    }
+
+    pub(crate) fn pending(&self) -> &[u8] {
+        &self.pending[self.pos..]
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.pos += n;
+        if self.pos == self.pending.len() {
+            self.pending.clear();
+            self.pos = 0;
+        }
+    }
+
+    fn write_header(&mut self) {
+        if !self.header_written {
+            self.header.write(&mut self.pending);
+            self.produced += self.header.len as u64;
+            self.header_written = true;
+        }
+    }
+
+    fn emit_block(&mut self) -> Result<(), CompressError> {
+        if is_cancelled(self.cancel.as_ref()) {
+            return Err(CompressError::Cancelled);
+        }
+        let block = std::mem::take(&mut self.block);
+        self.push_block(&block);
+        self.block = block;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Encodes and queues a whole block given by the caller, straight into
+    /// `pending`. Nothing may be buffered in `block` meanwhile.
+    pub(crate) fn push_block(&mut self, block: &[u8]) {
+        self.write_header();
+        let before = self.pending.len();
+        let crc = timed(&mut self.phases.checksum, || self.header.block_crc(block));
+        let block_type = timed(&mut self.phases.code, || {
+            encode_block_into(&mut self.pending, &self.header, block, crc, self.block_count, self.store_only)
+        });
+        self.count_block(block, block_type, crc, self.pending.len() - before);
+    }
+
+    /// Queues the next block, already encoded elsewhere as `block_type`
+    /// with `payload` and its [`Header::block_crc`] taken as `crc`, exactly
+    /// as `emit_block` would have. Nothing may be buffered in `block`
+    /// meanwhile.
+    pub(crate) fn push_encoded(&mut self, block: &[u8], block_type: u8, crc: u32, payload: &[u8]) {
+        self.write_header();
+        frame::write_block_header(&mut self.pending, &self.header, block_type, payload.len(), block.len(), crc);
+        self.pending.extend_from_slice(payload);
+        self.count_block(block, block_type, crc, self.header.block_header_len() + payload.len());
+    }
+
+    /// Adds a block just queued, `written` bytes with its header, to the
+    /// totals, folding its CRC-32 `crc` into the content checksum.
+    fn count_block(&mut self, block: &[u8], block_type: u8, crc: u32, written: usize) {
+        self.produced += written as u64;
+        self.block_count += 1;
+        self.stored_blocks += u32::from(block_type == BLOCK_STORED);
+        self.content_size += block.len() as u64;
+        self.content_crc.combine(crc, block.len() as u64);
+    }
+}
+
+impl Drop for BlockEncoder {
+    fn drop(&mut self) {
+        pool::give(std::mem::take(&mut self.block));
+        pool::give(std::mem::take(&mut self.pending));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DecodeState {
+    Header,
+    BlockHeader,
+    Payload(frame::BlockHeader),
+    Trailer,
+    Done,
+    /// An error was returned; nothing more is decoded.
+    Failed,
+}
+
+/// Incremental frame decoder shared by the sync and async readers.
+///
+/// Compressed bytes are fed with `push`; a block is decoded as soon as its
+/// whole payload is buffered, so neither buffer grows beyond about one block.
+///
+/// A decoder made for more than one thread decodes nothing itself: each
+/// buffered payload waits as a [`BlockJob`] for `take_block`, and whoever
+/// decodes it hands the output back, in frame order, to `add_decoded`.
+pub(crate) struct FrameDecoder {
+    state: DecodeState,
+    header: Option<Header>,
+    /// Set instead of `header` for a small frame.
+    small: Option<frame::SmallFrame>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    out_pos: usize,
+    offset: usize,
+    block_count: u32,
+    stored_blocks: u32,
+    content_size: u64,
+    content_crc: Crc32,
+    max_memory: Option<usize>,
+    /// Threads asked for, and those the frame's blocks fit in `max_memory` for.
+    threads: usize,
+    fitted_threads: usize,
+    /// A payload waiting to be taken, for a decoder on several threads.
+    job: Option<BlockJob>,
+    /// Blocks whose output has come back through `add_decoded`.
+    completed: u32,
+    phases: PhaseTimes,
+}
+
+/// A block for a worker thread to decode: its header, its payload, and
+/// where the payload starts in the frame.
+pub(crate) struct BlockJob {
+    index: u32,
+    block: frame::BlockHeader,
+    payload: Vec<u8>,
+    offset: usize,
+}
+
+impl BlockJob {
+    /// Decodes the block and checks its checksum, failing exactly as the
+    /// sequential decoder would.
+    /// The payload goes back to the pool either way, and the output is
+    /// taken from it. The time taken is added to `phases`.
+    fn decode(self, phases: &mut PhaseTimes) -> Result<Vec<u8>, DecompressError> {
+        let mut output = pool::take(self.block.raw_len);
+        let decoded = timed(&mut phases.code, || {
+            decode_payload(&self.block, self.index, &self.payload, self.offset, &mut output)
+        })
+        .and_then(|()| timed(&mut phases.checksum, || self.block.verify(self.index, &output)));
+        pool::give(self.payload);
+        decoded.map(|()| output)
+    }
+}
+
+impl Drop for FrameDecoder {
+    fn drop(&mut self) {
+        pool::give(std::mem::take(&mut self.input));
+        pool::give(std::mem::take(&mut self.output));
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub(crate) fn new() -> Self {
+        FrameDecoder {
+            state: DecodeState::Header,
+            header: None,
+            small: None,
+            input: pool::take(0),
+            output: pool::take(0),
+            out_pos: 0,
+            offset: 0,
+            block_count: 0,
+            stored_blocks: 0,
+            content_size: 0,
+            content_crc: Crc32::new(),
+            max_memory: None,
+            threads: 1,
+            fitted_threads: 1,
+            job: None,
+            completed: 0,
+            phases: PhaseTimes::default(),
+        }
+    }
+
+    /// A decoder that fails with `MemoryLimit` rather than hold more than
+    /// `max_memory` bytes.
+    pub(crate) fn with_memory_limit(max_memory: Option<usize>) -> Self {
+        let mut decoder = Self::new();
+        decoder.max_memory = max_memory;
+        decoder
+    }
+
+    /// A decoder for `opts`, leaving its blocks to be decoded on worker
+    /// threads if `opts.threads` is more than one.
+    pub(crate) fn with_options(opts: &DecompressOptions) -> Self {
+        let mut decoder = Self::with_memory_limit(opts.max_memory);
+        decoder.threads = opts.threads.max(1);
+        decoder
+    }
+
+    fn check_memory(&self, needed: usize) -> Result<(), DecompressError> {
+        match self.max_memory {
+            Some(limit) if needed > limit => Err(DecompressError::MemoryLimit { needed, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Buffers `data` and decodes every block it completes.
+    ///
+    /// After an error, the output of the block that failed is dropped and
+    /// every later call fails with `Corrupt`.
+    pub(crate) fn push(&mut self, data: &[u8]) -> Result<(), DecompressError> {
+        if let DecodeState::Failed = self.state {
+            return Err(self.stopped());
+        }
+        self.input.extend_from_slice(data);
+        let mut start = 0;
+        let result = self.advance(&mut start);
+        self.input.drain(..start);
+        if result.is_err() {
+            if let DecodeState::Header | DecodeState::Payload(_) = self.state {
+                self.output.clear();
+                self.out_pos = 0;
+            }
+            self.state = DecodeState::Failed;
+        }
+        result
+    }
+
+    fn advance(&mut self, start: &mut usize) -> Result<(), DecompressError> {
+        loop {
+            let avail = &self.input[*start..];
+            let used = match self.state {
+                // A small frame is decoded, or left for a worker, once all
+                // of it is buffered.
+                DecodeState::Header if frame::is_small_frame(avail) => {
+                    let small = match frame::parse_small_frame(avail) {
+                        Ok(small) => small,
+                        Err(DecompressError::Truncated { .. } | DecompressError::TruncatedField { .. }) => {
+                            return Ok(())
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let block = small.block;
+                    self.check_memory(64 * 1024 + block.comp_len + block.raw_len)?;
+                    let payload = &avail[small.payload_at..][..block.comp_len];
+                    if self.threads > 1 && block.raw_len > 0 {
+                        let mut buf = pool::take(block.comp_len);
+                        buf.extend_from_slice(payload);
+                        self.job = Some(BlockJob { index: 0, block, payload: buf, offset: small.payload_at });
+                    } else {
+                        let output = &mut self.output;
+                        timed(&mut self.phases.code, || decode_payload(&block, 0, payload, small.payload_at, output))?;
+                        timed(&mut self.phases.checksum, || {
+                            block.verify(0, output)?;
+                            self.content_crc.update(output);
+                            Ok::<_, DecompressError>(())
+                        })?;
+                    }
+                    self.block_count = u32::from(block.raw_len > 0);
+                    self.stored_blocks = self.block_count * u32::from(block.block_type == BLOCK_STORED);
+                    self.content_size = block.raw_len as u64;
+                    self.small = Some(small);
+                    self.state = DecodeState::Done;
+                    small.len
+                }
+                DecodeState::Header => {
+                    // A bad magic is rejected from its first bytes; anything
+                    // else cut short just needs more input.
+                    let header = match frame::parse_header(avail) {
+                        Ok(header) => header,
+                        Err(DecompressError::TruncatedField { .. }) => return Ok(()),
+                        Err(e) => return Err(e),
+                    };
+                    self.fitted_threads = threads_within(self.max_memory, header.block_size, self.threads);
+                    self.check_memory(memory_for_threads(header.block_size, self.fitted_threads))?;
+                    let len = header.len;
+                    self.header = Some(header);
+                    self.state = DecodeState::BlockHeader;
+                    len
+                }
+                DecodeState::BlockHeader => {
+                    let header = self.header.as_ref().expect("header parsed");
+                    match avail.first() {
+                        None => return Ok(()),
+                        Some(&frame::BLOCK_END) => {
+                            self.state = DecodeState::Trailer;
+                            1
+                        }
+                        Some(_) if avail.len() < header.block_header_len() => return Ok(()),
+                        Some(_) => {
+                            let block = frame::parse_block_header(avail, 0, header)
+                                .map_err(|e| e.shifted(self.offset))?
+                                .expect("not an end marker");
+                            self.check_memory(64 * 1024 + block.comp_len.saturating_add(block.raw_len))?;
+                            self.state = DecodeState::Payload(block);
+                            header.block_header_len()
+                        }
+                    }
+                }
+                DecodeState::Payload(block) if self.threads > 1 => {
+                    if self.job.is_some() || avail.len() < block.comp_len {
+                        return Ok(());
+                    }
+                    let mut payload = pool::take(block.comp_len);
+                    payload.extend_from_slice(&avail[..block.comp_len]);
+                    self.job = Some(BlockJob { index: self.block_count, block, payload, offset: self.offset });
+                    self.block_count += 1;
+                    self.stored_blocks += u32::from(block.block_type == BLOCK_STORED);
+                    self.content_size += block.raw_len as u64;
+                    self.state = DecodeState::BlockHeader;
+                    block.comp_len
+                }
+                DecodeState::Payload(block) => {
+                    if self.out_pos < self.output.len() || avail.len() < block.comp_len {
+                        return Ok(());
+                    }
+                    self.output.clear();
+                    self.out_pos = 0;
+                    let (index, output) = (self.block_count, &mut self.output);
+                    timed(&mut self.phases.code, || {
+                        decode_payload(&block, index, &avail[..block.comp_len], self.offset, output)
+                    })?;
+                    timed(&mut self.phases.checksum, || {
+                        block.verify(index, output)?;
+                        self.content_crc.update(output);
+                        Ok::<_, DecompressError>(())
+                    })?;
+                    self.block_count += 1;
+                    self.stored_blocks += u32::from(block.block_type == BLOCK_STORED);
+                    self.content_size += block.raw_len as u64;
+                    self.state = DecodeState::BlockHeader;
+                    block.comp_len
+                }
+                DecodeState::Trailer => {
+                    let header = self.header.as_ref().expect("header parsed");
+                    if avail.len() < header.trailer_len() || self.awaiting_blocks() {
+                        return Ok(());
+                    }
+                    let trailer = frame::parse_trailer(avail, 0, header)?;
+                    let content_crc = header.content_checksum().then(|| self.content_crc.finish());
+                    trailer.verify(self.block_count, self.content_size, content_crc, self.offset)?;
+                    self.state = DecodeState::Done;
+                    header.trailer_len()
+                }
+                DecodeState::Done | DecodeState::Failed => return Ok(()),
+            };
+            *start += used;
+            self.offset += used;
+        }
+    }
+
+    /// Decoded bytes not yet handed out.
+    pub(crate) fn output(&self) -> &[u8] {
+        &self.output[self.out_pos..]
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.out_pos += n;
+    }
+
+    /// Decodes a block that was waiting for the output buffer to drain.
+    pub(crate) fn resume(&mut self) -> Result<(), DecompressError> {
+        self.push(&[])
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.state, DecodeState::Done)
+    }
+
+    /// The payload buffered for a worker thread, if any; nothing more is
+    /// parsed until it is taken.
+    pub(crate) fn take_block(&mut self) -> Option<BlockJob> {
+        self.job.take()
+    }
+
+    /// Whether a payload is waiting for `take_block`.
+    pub(crate) fn has_block(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Whether the trailer has been reached before every block taken has
+    /// come back through `add_decoded`.
+    pub(crate) fn awaiting_blocks(&self) -> bool {
+        self.threads > 1 && self.completed < self.block_count
+    }
+
+    /// Counts the next block's output, decoded from what `take_block` gave.
+    pub(crate) fn add_decoded(&mut self, output: &[u8]) {
+        timed(&mut self.phases.checksum, || self.content_crc.update(output));
+        self.completed += 1;
+    }
+
+    /// Blocks to have in flight at once: two for each thread the frame's
+    /// block size fits `max_memory` with.
+    pub(crate) fn window(&self) -> usize {
+        blocks_in_flight(self.fitted_threads)
+    }
+
+    /// Metadata of a fully decoded and verified frame.
+    pub(crate) fn info(&self) -> Option<FrameInfo> {
+        if let Some(small) = &self.small {
+            return self.is_done().then(|| FrameInfo::small(small));
+        }
+        let header = self.header.as_ref().filter(|_| self.is_done())?;
+        let trailer = Trailer {
+            block_count: self.block_count,
+            content_size: self.content_size,
+            content_checksum: header.content_checksum().then(|| self.content_crc.finish()),
+        };
+        Some(FrameInfo::new(header.clone(), &trailer, self.offset as u64))
+    }
+
+    /// Error for any call after one that failed.
+    fn stopped(&self) -> DecompressError {
+        DecompressError::Corrupt { offset: self.offset, reason: "decoding stopped at an earlier error" }
+    }
+
+    /// Error to report when the input ends before the frame does.
+    pub(crate) fn truncated(&self) -> DecompressError {
+        if let DecodeState::Failed = self.state {
+            return self.stopped();
+        }
+        if let DecodeState::Header = self.state {
+            if let Err(e @ DecompressError::TruncatedField { .. }) = frame::parse_start(&self.input) {
+                return e;
+            }
+        }
+        DecompressError::Truncated { offset: self.offset + self.input.len() }
+    }
+}
+
+/// Push-based decoder for callers that do their own I/O.
+///
+/// Feed compressed bytes in fragments of any size with [`push`](Self::push),
+/// collect output with [`drain`](Self::drain), and call
+/// [`finish`](Self::finish) once the input is exhausted. At most about one
+/// block of input and one of output is held between calls to `drain`. The
+/// decoder cannot continue after an error: no output of the block that
+/// failed is handed out, and every later `push` or `finish` fails too.
+#[derive(Default)]
+pub struct StreamDecoder {
+    decoder: FrameDecoder,
+    error: Option<DecompressError>,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Buffers `input` and decodes whatever it completes.
+    ///
+    /// Also reports an error hit while `drain` was decoding buffered blocks.
+    pub fn push(&mut self, input: &[u8]) -> Result<(), DecompressError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.decoder.push(input)
+    }
+
+    /// Appends all output decodable from the input pushed so far to `out`,
+    /// returning how many bytes were appended.
+    ///
+    /// A decoding error stops the drain early and is returned by the next
+    /// `push` or `finish`.
+    pub fn drain(&mut self, out: &mut Vec<u8>) -> usize {
+        let mut total = 0;
+        loop {
+            let output = self.decoder.output();
+            let n = output.len();
+            out.extend_from_slice(output);
+            self.decoder.consume(n);
+            total += n;
+            if self.error.is_some() {
+                return total;
+            }
+            if let Err(e) = self.decoder.resume() {
+                self.error = Some(e);
+            }
+            if self.decoder.output().is_empty() {
+                return total;
+            }
+        }
+    }
+
+    /// True once the trailer has been read and checked.
+    pub fn is_done(&self) -> bool {
+        self.decoder.is_done()
+    }
+
+    /// Checks that the frame is complete, reporting any pending error or, if
+    /// the input stopped early, `Truncated`.
+    pub fn finish(&mut self) -> Result<(), DecompressError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match self.decoder.is_done() {
+            true => Ok(()),
+            false => Err(self.decoder.truncated()),
+        }
+    }
+}
+
+/// Streaming compressor: bytes written to it are emitted as AAPC blocks on `inner`.
+///
+/// Input is buffered until a full block is available. `flush()` ends the
+/// current block early and flushes `inner`, so a receiver can decode
+/// everything written so far; each flush costs a block header and breaks any
+/// run that spans it, so flushing every few bytes can make the output larger
+/// than the input. Call [`AapcWriter::finish`] to flush the last partial block
+/// and write the trailer; dropping the writer without finishing leaves an
+/// incomplete frame.
+pub struct AapcWriter<W: Write> {
+    inner: W,
+    encoder: BlockEncoder,
+}
+
+impl<W: Write> AapcWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_options(inner, &CompressOptions::default()).expect("default options are valid")
+    }
+
+    pub fn with_options(inner: W, opts: &CompressOptions) -> Result<Self, CompressError> {
+        Ok(AapcWriter { inner, encoder: BlockEncoder::new(opts)? })
+    }
+
+    /// Totals for the blocks emitted so far; complete once `finish` has run.
+    pub fn stats(&self) -> CompressionStats {
+        self.encoder.stats()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Mutable access to the inner writer. Writing to it directly corrupts the frame.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Carries on a frame that an earlier writer with the same `opts` left at
+    /// `point`, typically one whose process was interrupted. `inner` must
+    /// hold (or be positioned after) exactly the `point.output_bytes` bytes
+    /// written so far; the finished frame is then byte-identical to one
+    /// written in a single run.
+    pub fn resume(inner: W, opts: &CompressOptions, point: &ResumePoint) -> Result<Self, CompressError> {
+        Ok(AapcWriter { inner, encoder: BlockEncoder::resume(opts, point)? })
+    }
+
+    /// Where the frame can be resumed from, or `None` while part of a block
+    /// is still buffered. After each `write` that completes a block this is
+    /// `Some`, and everything it counts has reached the inner writer.
+    pub fn resume_point(&self) -> Option<ResumePoint> {
+        self.encoder.resume_point()
+    }
+
+    /// Emits any buffered data, writes the trailer and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finish_frame()?;
+        Ok(self.inner)
+    }
+
+    /// Like [`AapcWriter::finish`], also returning the finished frame's
+    /// totals.
+    pub fn finish_with_stats(mut self) -> io::Result<(W, CompressionStats)> {
+        self.finish_frame()?;
+        let stats = self.stats();
+        Ok((self.inner, stats))
+    }
+
+    fn finish_frame(&mut self) -> io::Result<()> {
+        self.encoder.finish()?;
+        self.drain()?;
+        timed(&mut self.encoder.phases.write, || self.inner.flush())
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        let start = Instant::now();
+        let pending = self.encoder.pending();
+        let n = pending.len();
+        self.inner.write_all(pending)?;
+        self.encoder.consume(n);
+        self.encoder.phases.write += start.elapsed();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for AapcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.encoder.push(buf)?;
+        self.drain()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush_block()?;
+        self.drain()?;
+        timed(&mut self.encoder.phases.write, || self.inner.flush())
+    }
+}
+
+/// A block boundary of a frame being written, enough to pick the frame up
+/// again with [`AapcWriter::resume`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResumePoint {
+    /// Uncompressed bytes encoded so far.
+    pub input_bytes: u64,
+    /// Frame bytes written so far, header included.
+    pub output_bytes: u64,
+    /// Blocks written so far.
+    pub blocks: u32,
+    /// How many of `blocks` were stored rather than RLE-encoded.
+    pub stored_blocks: u32,
+    /// CRC-32 of the `input_bytes` encoded so far.
+    pub content_crc: u32,
+}
+
+/// Streaming decompressor: reads an AAPC frame from `inner` and yields the original bytes.
+///
+/// Reading past the end of the frame returns 0; input that ends before the
+/// trailer is an `UnexpectedEof` error.
+pub struct AapcReader<R: Read> {
+    inner: R,
+    decoder: FrameDecoder,
+    buf: Box<[u8]>,
+}
+
+impl<R: Read> AapcReader<R> {
+    pub fn new(inner: R) -> Self {
+        AapcReader { inner, decoder: FrameDecoder::new(), buf: vec![0; 64 * 1024].into_boxed_slice() }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for AapcReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let available = self.decoder.output();
+            if !available.is_empty() {
+                let n = available.len().min(out.len());
+                out[..n].copy_from_slice(&available[..n]);
+                self.decoder.consume(n);
+                return Ok(n);
+            }
+            self.decoder.resume()?;
+            if !self.decoder.output().is_empty() {
+                continue;
+            }
+            if self.decoder.is_done() {
+                return Ok(0);
+            }
+            let n = self.inner.read(&mut self.buf)?;
+            if n == 0 {
+                return Err(self.decoder.truncated().into());
+            }
+            self.decoder.push(&self.buf[..n])?;
+        }
+    }
+}
+
+/// Compresses everything `reader` yields into one frame on `writer`, on
+/// `opts.threads` threads when that is more than one.
+///
+/// `progress`, if given, is called after every block and after the trailer.
+/// If `opts.cancel` is cancelled, returns `Cancelled` at the next block
+/// boundary; whatever reached `writer` by then has no trailer and will not
+/// decode.
+///
+/// Where `opts` allow a small frame, up to [`SMALL_FRAME_LIMIT`] bytes are
+/// read before anything is written, to see whether the input fits one.
+pub fn copy_encode<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    opts: &CompressOptions,
+    mut progress: ProgressFn<'_>,
+) -> Result<CompressionStats, CompressError> {
+    let started = Instant::now();
+    let mut read = Duration::ZERO;
+    let mut head = Vec::new();
+    if let Some(stats) = encode_if_small(&mut reader, &mut writer, opts, &mut progress, &mut head, &mut read)? {
+        return Ok(with_wall(stats, started));
+    }
+    let mut reader = head.as_slice().chain(reader);
+    let mut encoder = AapcWriter::with_options(writer, opts)?;
+    encoder.encoder.phases.read = read;
+    if opts.threads > 1 {
+        let mut read = Duration::ZERO;
+        let next = || Ok(timed(&mut read, || read_block(&mut reader, opts.block_size))?);
+        let mut stats = encode_blocks(encoder, opts.threads, blocks_in_flight(opts.threads), opts, next, progress)?;
+        stats.phases.read += read;
+        return Ok(with_wall(stats, started));
+    }
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut reported = 0;
+    loop {
+        let n = match timed(&mut encoder.encoder.phases.read, || reader.read(&mut buf)) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut chunk = &buf[..n];
+        while !chunk.is_empty() {
+            // A single write completes at most one block.
+            let taken = encoder.write(chunk)?;
+            chunk = &chunk[taken..];
+            let totals = encoder.stats();
+            if totals.blocks > reported {
+                reported = totals.blocks;
+                stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+            }
+        }
+    }
+    // Report the last block on its own, before the trailer adds to the totals.
+    encoder.encoder.flush_block()?;
+    let totals = encoder.stats();
+    if totals.blocks > reported {
+        stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+    }
+    encoder.finish_frame()?;
+    let totals = encoder.stats();
+    stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+    Ok(with_wall(totals, started))
+}
+
+/// Like [`copy_encode`], reading `reader` on a thread of its own so that
+/// reading, encoding and writing overlap, even with `opts.threads` at one:
+/// the reader thread keeps a block queued ahead, blocks are encoded on
+/// `opts.threads` workers (at least one), and the calling thread writes
+/// them to `writer` in order. The frame is byte for byte the one
+/// [`copy_encode`] writes, and memory stays at a few blocks whatever the
+/// input's size, by [`memory_for_pipeline`]; if that is over
+/// `opts.max_memory`, this is [`copy_encode`].
+///
+/// Cancelling stops the reader thread before its next block, but a read it
+/// has started, say from a pipe, is waited for.
+pub fn copy_encode_pipelined<R: Read + Send, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    opts: &CompressOptions,
+    mut progress: ProgressFn<'_>,
+) -> Result<CompressionStats, CompressError> {
+    if opts.max_memory.is_some_and(|limit| memory_for_pipeline(opts.block_size, opts.threads) > limit) {
+        return copy_encode(reader, writer, opts, progress);
+    }
+    let started = Instant::now();
+    let mut read = Duration::ZERO;
+    let mut head = Vec::new();
+    if let Some(stats) = encode_if_small(&mut reader, &mut writer, opts, &mut progress, &mut head, &mut read)? {
+        return Ok(with_wall(stats, started));
+    }
+    let reader = head.as_slice().chain(reader);
+    let mut encoder = AapcWriter::with_options(writer, opts)?;
+    encoder.encoder.phases.read = read;
+    let (block_tx, block_rx) = mpsc::sync_channel::<io::Result<Vec<u8>>>(READ_AHEAD);
+    std::thread::scope(|scope| {
+        let cancel = opts.cancel.as_ref();
+        let reading = scope.spawn(move || {
+            let mut reader = reader;
+            let mut read = Duration::ZERO;
+            while !is_cancelled(cancel) {
+                let block = match timed(&mut read, || read_block(&mut reader, opts.block_size)) {
+                    Ok(Some(block)) => Ok(block),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let failed = block.is_err();
+                // A closed channel means the encoder stopped early.
+                if block_tx.send(block).is_err() || failed {
+                    break;
+                }
+            }
+            read
+        });
+        // Moved in, so that the receiver is dropped as soon as encoding
+        // stops, freeing a reader thread blocked on a full channel.
+        let next = move || match block_rx.recv() {
+            Ok(block) => Ok(Some(block?)),
+            Err(mpsc::RecvError) => Ok(None),
+        };
+        let threads = opts.threads.max(1);
+        let mut stats = encode_blocks(encoder, threads, pipeline_blocks(threads), opts, next, progress)?;
+        // The channel is closed, so the reader thread is ending.
+        stats.phases.read += reading.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        Ok(with_wall(stats, started))
+    })
+}
+
+/// Like [`copy_encode`] for input already in memory, such as a file the
+/// caller has mapped: blocks are encoded straight from `data`, with no
+/// copy, on `opts.threads` threads when that is more than one. The frame
+/// is byte for byte the one [`copy_encode`] writes for the same bytes.
+///
+/// A caller passing a mapping must keep the file from shrinking meanwhile;
+/// on most platforms touching a page past its new end kills the process.
+pub fn copy_encode_slice<W: Write>(
+    data: &[u8],
+    writer: W,
+    opts: &CompressOptions,
+    mut progress: ProgressFn<'_>,
+) -> Result<CompressionStats, CompressError> {
+    let started = Instant::now();
+    if opts.small_frame_fits(data.len()) {
+        opts.validate()?;
+        return write_small_frame(data, writer, opts, &mut progress).map(|stats| with_wall(stats, started));
+    }
+    let mut encoder = AapcWriter::with_options(writer, opts)?;
+    let mut blocks = data.chunks(opts.block_size);
+    if opts.threads > 1 {
+        let next = || Ok(blocks.next());
+        let stats = encode_blocks(encoder, opts.threads, blocks_in_flight(opts.threads), opts, next, progress)?;
+        return Ok(with_wall(stats, started));
+    }
+    for block in blocks {
+        if is_cancelled(opts.cancel.as_ref()) {
+            return Err(CompressError::Cancelled);
+        }
+        encoder.encoder.push_block(block);
+        encoder.drain()?;
+        let totals = encoder.stats();
+        stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+    }
+    encoder.finish_frame()?;
+    let totals = encoder.stats();
+    stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+    Ok(with_wall(totals, started))
+}
+
+/// `stats` for a frame whose encoding began at `started` and is done.
+fn with_wall(mut stats: CompressionStats, started: Instant) -> CompressionStats {
+    stats.phases.wall = started.elapsed();
+    stats
+}
+
+/// Reads up to [`SMALL_FRAME_LIMIT`] bytes into the empty `head` if `opts`
+/// allow a small frame, and writes them as one if the input ends within
+/// them, returning its totals. Otherwise the frame is to start with `head`,
+/// whose reading took the time left in `read`.
+fn encode_if_small<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    opts: &CompressOptions,
+    progress: &mut ProgressFn<'_>,
+    head: &mut Vec<u8>,
+    read: &mut Duration,
+) -> Result<Option<CompressionStats>, CompressError> {
+    opts.validate()?;
+    // Whether any input could fit, before any is read.
+    if !opts.small_frame_fits(0) {
+        return Ok(None);
+    }
+    timed(read, || reader.take(SMALL_FRAME_LIMIT as u64).read_to_end(head))?;
+    if !opts.small_frame_fits(head.len()) {
+        return Ok(None);
+    }
+    let mut stats = write_small_frame(head, writer, opts, progress)?;
+    stats.phases.read += *read;
+    Ok(Some(stats))
+}
+
+/// Writes `data`, which [`CompressOptions::small_frame_fits`], to `writer`
+/// as a small frame, and flushes it.
+fn write_small_frame<W: Write>(
+    data: &[u8],
+    mut writer: W,
+    opts: &CompressOptions,
+    progress: &mut ProgressFn<'_>,
+) -> Result<CompressionStats, CompressError> {
+    if is_cancelled(opts.cancel.as_ref()) {
+        return Err(CompressError::Cancelled);
+    }
+    let mut frame = pool::take(data.len());
+    let mut stats = encode_small_frame(data, opts, &mut frame);
+    let written = timed(&mut stats.phases.write, || writer.write_all(&frame).and_then(|()| writer.flush()));
+    pool::give(frame);
+    written?;
+    stats::report(progress, stats.input_bytes, stats.output_bytes, stats.blocks, stats.stored_blocks);
+    Ok(stats)
+}
+
+/// Reads the next block of up to `block_size` bytes; `None` at the end of
+/// the input.
+fn read_block<R: Read>(reader: &mut R, block_size: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut block = pool::take(block_size);
+    reader.take(block_size as u64).read_to_end(&mut block)?;
+    if block.is_empty() {
+        pool::give(block);
+        return Ok(None);
+    }
+    Ok(Some(block))
+}
+
+/// Encodes the blocks `next` yields on `threads` threads, holding up to
+/// `in_flight` at once, and finishes `encoder`'s frame with them.
+fn encode_blocks<W, B, N>(
+    mut encoder: AapcWriter<W>,
+    threads: usize,
+    in_flight: usize,
+    opts: &CompressOptions,
+    next: N,
+    mut progress: ProgressFn<'_>,
+) -> Result<CompressionStats, CompressError>
+where
+    W: Write,
+    B: AsRef<[u8]> + Recycle + Send,
+    N: FnMut() -> Result<Option<B>, CompressError>,
+{
+    let crc = encoder.encoder.header.needs_block_crc();
+    let cancel = opts.cancel.as_ref();
+    let worked = encode_in_order(threads, in_flight, crc, opts.store_only, cancel, next, |block, block_type, crc, payload| {
+        encoder.encoder.push_encoded(block, block_type, crc, payload);
+        encoder.drain()?;
+        let totals = encoder.stats();
+        stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+        Ok(())
+    })?;
+    encoder.encoder.phases += worked;
+    encoder.finish_frame()?;
+    let totals = encoder.stats();
+    stats::report(&mut progress, totals.input_bytes, totals.output_bytes, totals.blocks, totals.stored_blocks);
+    Ok(totals)
+}
+
+/// Decompresses one frame from `reader` onto `writer`, returning the bytes written.
+///
+/// `cancel` is checked before each block is decoded; `progress`, if given, is
+/// called after every block is written and once the trailer has been checked.
+pub fn copy_decode<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    cancel: Option<&CancelToken>,
+    progress: ProgressFn<'_>,
+) -> Result<u64, DecompressError> {
+    run_decoder(&mut FrameDecoder::new(), reader, writer, cancel, progress)
+}
+
+/// Like [`copy_decode`], with the cancel token, memory limit and threads
+/// taken from `opts`. On several threads, payloads are read ahead and
+/// decoded and checked on the workers while the output is written in order.
+pub fn copy_decode_with_options<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    opts: &DecompressOptions,
+    progress: ProgressFn<'_>,
+) -> Result<u64, DecompressError> {
+    copy_decode_with_phases(reader, writer, opts, progress).map(|(written, _)| written)
+}
+
+/// Like [`copy_decode_with_options`], also returning where the time went:
+/// `code` is decoding and `checksum` verifying. With several threads the
+/// phases overlap, so they may add up to more than `wall`.
+pub fn copy_decode_with_phases<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    opts: &DecompressOptions,
+    progress: ProgressFn<'_>,
+) -> Result<(u64, PhaseTimes), DecompressError> {
+    let started = Instant::now();
+    let mut decoder = FrameDecoder::with_options(opts);
+    let written = match opts.threads {
+        0 | 1 => run_decoder(&mut decoder, reader, writer, opts.cancel.as_ref(), progress),
+        threads => run_threaded_decoder(&mut decoder, reader, writer, threads, opts.cancel.as_ref(), progress),
+    }?;
+    Ok((written, PhaseTimes { wall: started.elapsed(), ..decoder.phases }))
+}
+
+/// Like [`validate`](crate::validate), but reads the frame from `reader` in
+/// pieces, so memory stays around one block whatever the frame's size.
+/// Decoded data is checked against every block and frame checksum, then
+/// dropped.
+pub fn validate_reader<R: Read>(reader: R) -> Result<FrameInfo, DecompressError> {
+    let mut decoder = FrameDecoder::new();
+    run_decoder(&mut decoder, reader, io::sink(), None, None)?;
+    Ok(decoder.info().expect("decoder finished the frame"))
+}
+
+fn run_decoder<R: Read, W: Write>(
+    decoder: &mut FrameDecoder,
+    mut reader: R,
+    mut writer: W,
+    cancel: Option<&CancelToken>,
+    mut progress: ProgressFn<'_>,
+) -> Result<u64, DecompressError> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut written = 0u64;
+    loop {
+        // The fields rather than `output()`, so the write can be timed.
+        let output = &decoder.output[decoder.out_pos..];
+        if !output.is_empty() {
+            timed(&mut decoder.phases.write, || writer.write_all(output))?;
+            written += output.len() as u64;
+            let n = output.len();
+            decoder.consume(n);
+            stats::report(&mut progress, decoder.offset as u64, written, decoder.block_count, decoder.stored_blocks);
+        }
+        if is_cancelled(cancel) {
+            return Err(DecompressError::Cancelled);
+        }
+        decoder.resume()?;
+        if !decoder.output().is_empty() {
+            continue;
+        }
+        if decoder.is_done() {
+            break;
+        }
+        let n = timed(&mut decoder.phases.read, || reader.read(&mut buf))?;
+        if n == 0 {
+            return Err(decoder.truncated());
+        }
+        decoder.push(&buf[..n])?;
+    }
+    timed(&mut decoder.phases.write, || writer.flush())?;
+    stats::report(&mut progress, decoder.offset as u64, written, decoder.block_count, decoder.stored_blocks);
+    Ok(written)
+}
+
+/// Like [`run_decoder`], for a decoder made for several threads: `threads`
+/// workers decode the blocks it takes in, and their output is written and
+/// counted in frame order. Once the blocks before it are all written, the
+/// first error, whether from a worker or from parsing, is the one returned.
+fn run_threaded_decoder<R: Read, W: Write>(
+    decoder: &mut FrameDecoder,
+    mut reader: R,
+    mut writer: W,
+    threads: usize,
+    cancel: Option<&CancelToken>,
+    mut progress: ProgressFn<'_>,
+) -> Result<u64, DecompressError> {
+    let (job_tx, job_rx) = mpsc::channel::<BlockJob>();
+    let job_rx = Mutex::new(job_rx);
+    let (done_tx, done_rx) = mpsc::channel::<(u32, Result<Vec<u8>, DecompressError>, PhaseTimes)>();
+    std::thread::scope(|scope| {
+        // Owned here so that returning, however early, stops the workers.
+        let job_tx = job_tx;
+        for _ in 0..threads {
+            let (job_rx, done_tx) = (&job_rx, done_tx.clone());
+            scope.spawn(move || loop {
+                // Ends once the sender is dropped and the queue is empty.
+                let job = job_rx.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                let Ok(job) = job else { break };
+                let index = job.index;
+                let mut phases = PhaseTimes::default();
+                let decoded = job.decode(&mut phases);
+                if done_tx.send((index, decoded, phases)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Only the workers' senders are left, so the channel closes if
+        // they all stop, as they do when one panics.
+        drop(done_tx);
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut written = 0u64;
+        let (mut sent, mut finished) = (0u32, 0u32);
+        // Blocks decoded out of turn, until the ones before them are written.
+        let mut waiting = BTreeMap::new();
+        let mut input_ended = false;
+        // Takes in the next decoded block, writing out every block now in turn.
+        let mut collect = |decoder: &mut FrameDecoder, finished: &mut u32| -> Result<(), DecompressError> {
+            let (index, result, phases) = done_rx.recv().map_err(|_| worker_stopped())?;
+            decoder.phases += phases;
+            waiting.insert(index, result);
+            while let Some(result) = waiting.remove(finished) {
+                let output = result?;
+                timed(&mut decoder.phases.write, || writer.write_all(&output))?;
+                written += output.len() as u64;
+                decoder.add_decoded(&output);
+                pool::give(output);
+                *finished += 1;
+                stats::report(&mut progress, decoder.offset as u64, written, *finished, decoder.stored_blocks);
+            }
+            Ok(())
+        };
+        // Errors parsing or reading the frame, which may come after blocks
+        // still being decoded; an error from writing or decoding a block
+        // returns at once.
+        let parsed = loop {
+            if is_cancelled(cancel) {
+                return Err(DecompressError::Cancelled);
+            }
+            if ((sent - finished) as usize) < decoder.window() {
+                if let Some(job) = decoder.take_block() {
+                    job_tx.send(job).map_err(|_| worker_stopped())?;
+                    sent += 1;
+                    if let Err(e) = decoder.resume() {
+                        break Err(e);
+                    }
+                    continue;
+                }
+            }
+            if sent > finished && (decoder.has_block() || decoder.awaiting_blocks() || input_ended) {
+                collect(decoder, &mut finished)?;
+                if let Err(e) = decoder.resume() {
+                    break Err(e);
+                }
+                continue;
+            }
+            if decoder.is_done() {
+                break Ok(());
+            }
+            if input_ended {
+                break Err(decoder.truncated());
+            }
+            let pushed = match timed(&mut decoder.phases.read, || reader.read(&mut buf)) {
+                Ok(0) => {
+                    input_ended = true;
+                    Ok(())
+                }
+                Ok(n) => decoder.push(&buf[..n]),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = pushed {
+                break Err(e);
+            }
+        };
+        // A block before the point parsing failed at fails first, as it
+        // would have decoding in order.
+        if let Err(e) = parsed {
+            while sent > finished {
+                collect(decoder, &mut finished)?;
+            }
+            return Err(e);
+        }
+        timed(&mut decoder.phases.write, || writer.flush())?;
+        stats::report(&mut progress, decoder.offset as u64, written, decoder.block_count, decoder.stored_blocks);
+        Ok(written)
+    })
+}
+
+/// Error for the channel to or from the workers closing early. That only
+/// happens once a worker has panicked, and the scope rethrows the panic in
+/// its place.
+fn worker_stopped() -> DecompressError {
+    DecompressError::Io(io::Error::other("a decoder thread stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_with_options, decompress};
+
+    fn decode(frame: &[u8], threads: usize) -> Result<Vec<u8>, DecompressError> {
+        let mut out = Vec::new();
+        let opts = DecompressOptions { threads, ..DecompressOptions::default() };
+        copy_decode_with_options(frame, &mut out, &opts, None)?;
+        Ok(out)
+    }
+
+    /// Frames whose headers, length fields and run or escape tokens all fall
+    /// across some fragment boundary for every fragment size below.
+    fn fixtures() -> Vec<Vec<u8>> {
+        let data: Vec<u8> = (0..3000u32)
+            .map(|i| match i % 97 {
+                0..=29 => 0,
+                30..=39 => 254,
+                40..=44 => 255,
+                n => (i * n) as u8,
+            })
+            .collect();
+        let named = CompressOptions {
+            block_size: 700,
+            filename: Some("fragments.bin".to_string()),
+            comment: Some("split everywhere".to_string()),
+            small_frames: false,
+            ..CompressOptions::default()
+        };
+        let bare = CompressOptions { block_size: 1000, block_checksums: false, content_checksum: false, ..named.clone() };
+        vec![
+            compress_with_options(&data, &named).unwrap(),
+            compress_with_options(&data, &bare).unwrap(),
+            compress_with_options(&data[..200], &CompressOptions::default()).unwrap(),
+            compress_with_options(b"", &named).unwrap(),
+        ]
+    }
+
+    /// Pushes `frame` in `size`-byte fragments, draining after each.
+    fn push_in_fragments(frame: &[u8], size: usize) -> Result<Vec<u8>, DecompressError> {
+        let mut decoder = StreamDecoder::new();
+        let mut out = Vec::new();
+        for fragment in frame.chunks(size) {
+            decoder.push(fragment)?;
+            decoder.drain(&mut out);
+        }
+        decoder.finish()?;
+        decoder.drain(&mut out);
+        Ok(out)
+    }
+
+    #[test]
+    fn every_fragment_size_decodes_like_one_shot() {
+        for (i, frame) in fixtures().iter().enumerate() {
+            let expected = decompress(frame).unwrap();
+            for size in 1..=64 {
+                assert_eq!(push_in_fragments(frame, size).unwrap(), expected, "fixture {}, {} byte fragments", i, size);
+            }
+        }
+    }
+
+    #[test]
+    fn every_fragment_size_reports_a_cut_frame() {
+        let frame = &fixtures()[0];
+        for cut in [3, 12, 40, frame.len() / 2, frame.len() - 1] {
+            for size in 1..=64 {
+                let err = push_in_fragments(&frame[..cut], size).unwrap_err();
+                let offset = match err {
+                    DecompressError::Truncated { offset } | DecompressError::TruncatedField { offset, .. } => offset,
+                    other => panic!("cut at {}, {} byte fragments: {:?}", cut, size, other),
+                };
+                assert_eq!(offset, cut, "{} byte fragments", size);
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_is_decoded_after_an_error() {
+        let mut frame = fixtures()[0].clone();
+        let last = frame.len() - 30;
+        frame[last] ^= 0x20;
+        let mut decoder = StreamDecoder::new();
+        let mut out = Vec::new();
+        let mut results = frame.chunks(16).map(|fragment| {
+            let result = decoder.push(fragment);
+            decoder.drain(&mut out);
+            result
+        });
+        let first = results.find_map(Result::err).unwrap();
+        assert!(matches!(first, DecompressError::ChecksumMismatch { .. }), "{:?}", first);
+        let stopped = "decoding stopped at an earlier error";
+        assert!(results.all(|result| result.is_err_and(|e| e.to_string().contains(stopped))));
+        assert!(decoder.finish().unwrap_err().to_string().contains(stopped));
+        assert_eq!(decoder.drain(&mut out), 0);
+        // The blocks before the damaged one, and nothing of it.
+        let blocks: Vec<Vec<u8>> = crate::DecodedBlocks::new(&frame).map_while(Result::ok).collect();
+        assert_eq!(out, blocks.concat());
+    }
+
+    #[test]
+    fn each_flush_makes_the_prefix_decodable() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| if i % 300 < 200 { 1 } else { (i * 17) as u8 }).collect();
+        let opts = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        let mut writer = AapcWriter::with_options(Vec::new(), &opts).unwrap();
+        let mut decoder = StreamDecoder::new();
+        let (mut sent, mut decoded) = (0, Vec::new());
+        let mut written = 0;
+        for (i, piece) in data.chunks(1234).enumerate() {
+            writer.write_all(piece).unwrap();
+            written += piece.len();
+            if i % 3 != 2 {
+                continue;
+            }
+            writer.flush().unwrap();
+            let frame = writer.get_ref();
+            decoder.push(&frame[sent..]).unwrap();
+            sent = frame.len();
+            decoder.drain(&mut decoded);
+            assert_eq!(decoded, data[..written], "after {} bytes", written);
+            assert!(!decoder.is_done());
+
+            writer.flush().unwrap();
+            assert_eq!(writer.get_ref().len(), sent, "a second flush wrote an empty block");
+        }
+        let frame = writer.finish().unwrap();
+        decoder.push(&frame[sent..]).unwrap();
+        decoder.finish().unwrap();
+        decoder.drain(&mut decoded);
+        assert_eq!(decoded, data);
+        assert_eq!(decompress(&frame).unwrap(), data);
+    }
+
+    #[test]
+    fn threaded_decode_matches_sequential() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| if i % 900 < 500 { 9 } else { (i * 13) as u8 }).collect();
+        let opts = CompressOptions { block_size: 8 * 1024, block_checksums: true, ..CompressOptions::default() };
+        let frame = compress_with_options(&data, &opts).unwrap();
+        for threads in [1, 2, 4] {
+            assert_eq!(decode(&frame, threads).unwrap(), data, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn threaded_decode_fails_like_sequential() {
+        let data = vec![3u8; 100_000];
+        let opts = CompressOptions { block_size: 4 * 1024, block_checksums: true, ..CompressOptions::default() };
+        let mut frame = compress_with_options(&data, &opts).unwrap();
+        // The last byte of the first block's CRC.
+        frame[Header::for_options(&opts).len + 12] ^= 1;
+        let sequential = decode(&frame, 1).unwrap_err().to_string();
+        assert_eq!(decode(&frame, 4).unwrap_err().to_string(), sequential);
+    }
+
+    #[test]
+    fn a_frame_needing_more_than_the_cap_fails_before_any_output() {
+        let data = vec![9u8; 300_000];
+        let opts = CompressOptions { block_size: 1 << 20, small_frames: false, ..CompressOptions::default() };
+        let frame = compress_with_options(&data, &opts).unwrap();
+        let needed = crate::options::memory_for_block_size(1 << 20);
+
+        let mut out = Vec::new();
+        let capped = DecompressOptions { max_memory: Some(needed - 1), ..DecompressOptions::default() };
+        let err = copy_decode_with_options(frame.as_slice(), &mut out, &capped, None).unwrap_err();
+        assert!(matches!(err, DecompressError::MemoryLimit { needed: n, limit } if n == needed && limit == needed - 1));
+        assert_eq!(err.to_string(), format!("decompressing needs {} bytes of memory, more than the limit of {}",
+                                            needed, needed - 1));
+        assert!(out.is_empty());
+
+        let fits = DecompressOptions { max_memory: Some(needed), ..DecompressOptions::default() };
+        copy_decode_with_options(frame.as_slice(), &mut out, &fits, None).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn a_cap_decodes_on_fewer_threads() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i / 300) as u8).collect();
+        let opts = CompressOptions { block_size: 16 * 1024, ..CompressOptions::default() };
+        let frame = compress_with_options(&data, &opts).unwrap();
+        let limit = memory_for_threads(16 * 1024, 2);
+        let capped = DecompressOptions { max_memory: Some(limit), threads: 8, ..DecompressOptions::default() };
+        let mut decoder = FrameDecoder::with_options(&capped);
+        decoder.push(&frame[..64]).unwrap();
+        assert_eq!(decoder.fitted_threads, 2);
+
+        let mut out = Vec::new();
+        copy_decode_with_options(frame.as_slice(), &mut out, &capped, None).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn a_resumed_writer_finishes_the_same_frame() {
+        let data: Vec<u8> = (0..50_000u32).map(|i| if i % 700 < 400 { 0 } else { (i * 31) as u8 }).collect();
+        let opts = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        let whole = compress_with_options(&data, &opts).unwrap();
+        for cut in [5000, 30_000, 49_153] {
+            let mut writer = AapcWriter::with_options(Vec::new(), &opts).unwrap();
+            let mut point = None;
+            let mut rest = &data[..cut];
+            while !rest.is_empty() {
+                rest = &rest[writer.write(rest).unwrap()..];
+                point = writer.resume_point().or(point);
+            }
+            let point = point.unwrap();
+            let mut partial = writer.get_ref().clone();
+            partial.truncate(point.output_bytes as usize);
+            assert_eq!(point.input_bytes % 4096, 0, "cut at {}", cut);
+
+            let mut resumed = AapcWriter::resume(partial, &opts, &point).unwrap();
+            resumed.write_all(&data[point.input_bytes as usize..]).unwrap();
+            assert_eq!(resumed.finish().unwrap(), whole, "cut at {}", cut);
+        }
+    }
+
+    /// `data` through [`copy_encode`] on one thread and through the
+    /// pipeline on `threads`, which must write the same frame.
+    fn pipelined_like_copy_encode(data: &[u8], opts: &CompressOptions, threads: usize) -> Vec<u8> {
+        let mut expected = Vec::new();
+        copy_encode(data, &mut expected, opts, None).unwrap();
+        let mut frame = Vec::new();
+        let mut last = None;
+        let opts = CompressOptions { threads, ..opts.clone() };
+        let stats = copy_encode_pipelined(data, &mut frame, &opts, Some(&mut |p| last = Some(p))).unwrap();
+        assert_eq!(frame, expected, "{} bytes on {} threads", data.len(), threads);
+        assert_eq!((stats.input_bytes, stats.output_bytes), (data.len() as u64, frame.len() as u64));
+        assert_eq!(last.map(|p| p.output_bytes), Some(frame.len() as u64), "the trailer was not reported");
+        frame
+    }
+
+    #[test]
+    fn the_pipeline_writes_copy_encodes_frame() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| if i % 3000 < 1000 { 9 } else { (i * 13) as u8 }).collect();
+        let framed = CompressOptions { block_size: 4096, small_frames: false, ..CompressOptions::default() };
+        let small = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        for opts in [&framed, &small] {
+            for threads in [1, 3] {
+                for len in [0, 1, 4095, 4096, 4097, 40_000] {
+                    let frame = pipelined_like_copy_encode(&data[..len], opts, threads);
+                    assert_eq!(decompress(&frame).unwrap(), &data[..len]);
+                }
+            }
+        }
+        // Zero-length and one-block frames end at once.
+        let empty = pipelined_like_copy_encode(b"", &framed, 1);
+        assert_eq!(FrameInfo::parse(&empty).unwrap().block_count, 0);
+        let one = pipelined_like_copy_encode(&data[..4096], &framed, 1);
+        assert_eq!(FrameInfo::parse(&one).unwrap().block_count, 1);
+    }
+
+    #[test]
+    fn a_pipeline_over_the_memory_limit_encodes_in_place() {
+        let data = vec![3u8; 100_000];
+        let block_size = 16 * 1024;
+        let limit = memory_for_threads(block_size, 1);
+        assert!(memory_for_pipeline(block_size, 1) > limit);
+        let opts = CompressOptions { block_size, max_memory: Some(limit), ..CompressOptions::default() };
+        pipelined_like_copy_encode(&data, &opts, 1);
+    }
+
+    /// Yields `left` bytes of zeros, then fails.
+    struct FailingReader {
+        left: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::other("disk on fire"));
+            }
+            let n = buf.len().min(self.left);
+            buf[..n].fill(0);
+            self.left -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn a_read_error_ends_the_pipeline() {
+        let opts = CompressOptions { block_size: 4096, ..CompressOptions::default() };
+        for threads in [1, 4] {
+            for left in [0, 100, 50_000] {
+                let opts = CompressOptions { threads, ..opts.clone() };
+                let mut frame = Vec::new();
+                match copy_encode_pipelined(FailingReader { left }, &mut frame, &opts, None) {
+                    Err(CompressError::Io(e)) => assert_eq!(e.to_string(), "disk on fire"),
+                    other => panic!("{:?} after {} bytes", other.map(|stats| stats.input_bytes), left),
+                }
+                assert!(frame.len() < 50_000, "the frame went on past the error");
+            }
+        }
+    }
+
+    #[test]
+    fn a_slice_encodes_to_copy_encodes_frame() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| if i % 3000 < 1000 { 9 } else { (i * 13) as u8 }).collect();
+        for small_frames in [false, true] {
+            let opts = CompressOptions { block_size: 4096, small_frames, ..CompressOptions::default() };
+            for len in [0, 1, 4096, 4097, 40_000] {
+                let mut expected = Vec::new();
+                copy_encode(&data[..len], &mut expected, &opts, None).unwrap();
+                for threads in [1, 3] {
+                    let mut frame = Vec::new();
+                    let opts = CompressOptions { threads, ..opts.clone() };
+                    let stats = copy_encode_slice(&data[..len], &mut frame, &opts, None).unwrap();
+                    assert_eq!(frame, expected, "{} bytes on {} threads", len, threads);
+                    assert_eq!(stats.input_bytes, len as u64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_cancelled_slice_stops_at_a_block() {
+        let cancel = crate::CancelToken::new();
+        cancel.cancel();
+        let opts = CompressOptions { block_size: 4096, cancel: Some(cancel), ..CompressOptions::default() };
+        let result = copy_encode_slice(&[5u8; 20_000], Vec::new(), &opts, None);
+        assert!(matches!(result, Err(CompressError::Cancelled)));
+    }
+}
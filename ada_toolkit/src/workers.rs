@@ -0,0 +1,278 @@
+//! Runs per-file jobs on worker threads, handing their results back in
+//! input order, and keeps inputs that share an output from overwriting
+//! each other's result.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex, PoisonError};
+
+use ada_toolkit::{memory_for_threads, CancelToken};
+
+use crate::Failure;
+use crate::commands::compress::FileReport;
+
+/// How many of `files` files to compress at once, and on how many threads
+/// each, sharing `threads` in all between them: a file per thread while
+/// there are enough files, so that many small files keep every core busy,
+/// otherwise the threads split between the files. Fewer run at once if
+/// their blocks of `block_size` would not fit in `max_memory` together.
+pub fn file_workers(files: usize, threads: usize, block_size: usize, max_memory: Option<usize>) -> (usize, usize) {
+    let share = |workers: usize| (threads / workers).max(1);
+    let fits = |workers: usize| {
+        max_memory.is_none_or(|limit| memory_for_threads(block_size, share(workers)).saturating_mul(workers) <= limit)
+    };
+    let workers = (2..=files.min(threads)).rev().find(|&workers| fits(workers)).unwrap_or(1);
+    if workers > 1 {
+        log::debug!("Compressing up to {} files at once, each on up to {} threads", workers, share(workers));
+    }
+    (workers, share(workers))
+}
+
+/// Runs `job` on each of `items` on up to `workers` threads, handing each
+/// result to `done` on the calling thread in the order of `items`, once it
+/// and every one before it are in. After `done` returns true, or `cancel`
+/// is cancelled, no more items are started; those already started still
+/// finish and are handed over. With one worker this is a plain loop.
+pub fn run_in_order<T, R>(
+    items: &[T],
+    workers: usize,
+    cancel: &CancelToken,
+    job: impl Fn(usize, &T) -> R + Sync,
+    mut done: impl FnMut(usize, R) -> io::Result<bool>,
+) -> io::Result<()>
+where
+    T: Sync,
+    R: Send,
+{
+    if workers <= 1 {
+        for (index, item) in items.iter().enumerate() {
+            if done(index, job(index, item))? || cancel.is_cancelled() {
+                break;
+            }
+        }
+        return Ok(());
+    }
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        let (job, next, stop) = (&job, &next, &stop);
+        for _ in 0..workers.min(items.len()) {
+            let sender = sender.clone();
+            scope.spawn(move || loop {
+                if stop.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else { break };
+                // The calling thread has stopped listening if this fails.
+                if sender.send((index, job(index, item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        let mut ready = BTreeMap::new();
+        let mut handed = 0;
+        let result = (|| {
+            for (index, result) in receiver {
+                ready.insert(index, result);
+                while let Some(result) = ready.remove(&handed) {
+                    if done(handed, result)? || cancel.is_cancelled() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    handed += 1;
+                }
+            }
+            Ok(())
+        })();
+        stop.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+/// The outputs of a run over many files, so that two inputs with the same
+/// output cannot overwrite each other's result, even with --force and
+/// however many files run at once: the first of them in input order to
+/// write it wins, as if they had run one at a time.
+pub struct SharedOutputs<'a> {
+    /// Each input's output, or `None` for one that no other can share.
+    outputs: Vec<Option<&'a str>>,
+    /// For each input, the last one before it with the same output.
+    earlier: Vec<Option<usize>>,
+    state: Mutex<OutputsWritten<'a>>,
+    changed: Condvar,
+}
+
+struct OutputsWritten<'a> {
+    finished: Vec<bool>,
+    written: HashSet<&'a str>,
+}
+
+impl<'a> SharedOutputs<'a> {
+    pub fn new(outputs: Vec<Option<&'a str>>) -> SharedOutputs<'a> {
+        let mut last = HashMap::new();
+        let earlier = outputs.iter().enumerate()
+            .map(|(index, output)| output.and_then(|output| last.insert(output, index)))
+            .collect();
+        let finished = vec![false; outputs.len()];
+        let state = Mutex::new(OutputsWritten { finished, written: HashSet::new() });
+        SharedOutputs { outputs, earlier, state, changed: Condvar::new() }
+    }
+
+    /// Runs `job` for input `index`, named `input`, once any input before
+    /// it with the same output has finished, or fails without running it
+    /// if one of those wrote that output.
+    pub fn run(
+        &self,
+        index: usize,
+        input: &str,
+        job: impl FnOnce() -> Result<FileReport, Failure>,
+    ) -> Result<FileReport, Failure> {
+        let Some(output) = self.outputs[index] else {
+            return job();
+        };
+        let mut finish = FinishOutput { outputs: self, index, output, wrote: false };
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = match self.earlier[index] {
+            Some(earlier) => self.changed.wait_while(state, |state| !state.finished[earlier])
+                .unwrap_or_else(PoisonError::into_inner),
+            None => state,
+        };
+        let taken = state.written.contains(output);
+        drop(state);
+        if taken {
+            let msg = format!("{} was already written by this run; not overwriting it with {}", output, input);
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg).into());
+        }
+        let result = job();
+        finish.wrote = result.as_ref().is_ok_and(|report| report.sniffed.is_none_or(|sniffed| sniffed.stored));
+        result
+    }
+}
+
+/// Marks an input of [`SharedOutputs`] finished when dropped, so that one
+/// that fails or panics still lets the inputs waiting on it carry on.
+struct FinishOutput<'s, 'a> {
+    outputs: &'s SharedOutputs<'a>,
+    index: usize,
+    output: &'a str,
+    /// The input wrote its output.
+    wrote: bool,
+}
+
+impl Drop for FinishOutput<'_, '_> {
+    fn drop(&mut self) {
+        let mut state = self.outputs.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.finished[self.index] = true;
+        if self.wrote {
+            state.written.insert(self.output);
+        }
+        self.outputs.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use ada_toolkit::PhaseTimes;
+
+    fn report() -> FileReport {
+        FileReport {
+            input_bytes: 1,
+            output_bytes: 1,
+            duration: Duration::ZERO,
+            checksum: Some(0),
+            sniffed: None,
+            verified: false,
+            phases: PhaseTimes::default(),
+        }
+    }
+
+    #[test]
+    fn threads_go_to_files_first() {
+        assert_eq!(file_workers(50, 4, 1 << 20, None), (4, 1));
+        assert_eq!(file_workers(2, 8, 1 << 20, None), (2, 4));
+        assert_eq!(file_workers(1, 8, 1 << 20, None), (1, 8));
+        assert_eq!(file_workers(50, 1, 1 << 20, None), (1, 1));
+        // Three files' blocks fit the limit, four do not.
+        let limit = 3 * memory_for_threads(1 << 20, 1) + 1;
+        assert_eq!(file_workers(50, 4, 1 << 20, Some(limit)), (3, 1));
+        assert_eq!(file_workers(50, 4, 1 << 20, Some(1)), (1, 4));
+    }
+
+    #[test]
+    fn results_come_back_in_input_order() {
+        let items: Vec<u64> = (0..20).collect();
+        for workers in [1, 3, 8] {
+            let mut handed = Vec::new();
+            // Later items are quicker, so they finish first.
+            let job = |index: usize, item: &u64| {
+                std::thread::sleep(Duration::from_millis(20 - *item));
+                index * 10
+            };
+            run_in_order(&items, workers, &CancelToken::new(), job, |index, result| {
+                handed.push((index, result));
+                Ok(false)
+            })
+            .unwrap();
+            let expected: Vec<(usize, usize)> = (0..20).map(|index| (index, index * 10)).collect();
+            assert_eq!(handed, expected, "{} workers", workers);
+        }
+    }
+
+    #[test]
+    fn stopping_starts_nothing_more() {
+        let items: Vec<usize> = (0..100).collect();
+        let started = AtomicUsize::new(0);
+        let mut handed = 0;
+        let job = |_: usize, _: &usize| {
+            started.fetch_add(1, Ordering::Relaxed);
+            std::thread::sleep(Duration::from_millis(2));
+        };
+        run_in_order(&items, 4, &CancelToken::new(), job, |index, ()| {
+            handed += 1;
+            Ok(index == 5)
+        })
+        .unwrap();
+        let started = started.load(Ordering::Relaxed);
+        assert!(handed >= 6 && started < 20, "handed {}, started {}", handed, started);
+        assert_eq!(handed, started, "a started job was not handed over");
+    }
+
+    #[test]
+    fn a_shared_output_goes_to_the_first_input() {
+        let outputs = SharedOutputs::new(vec![Some("x.aapc"), None, Some("x.aapc"), Some("y.aapc")]);
+        let written = Mutex::new(Vec::new());
+        let items: Vec<usize> = (0..4).collect();
+        let mut results = Vec::new();
+        let job = |index: usize, _: &usize| {
+            // The second input naming x.aapc would finish first if it ran.
+            std::thread::sleep(Duration::from_millis(if index == 0 { 50 } else { 0 }));
+            outputs.run(index, &format!("in{}", index), || {
+                written.lock().unwrap().push(index);
+                Ok(report())
+            })
+        };
+        run_in_order(&items, 4, &CancelToken::new(), job, |_, result| {
+            results.push(result.map(|_| ()).map_err(|failure| failure.error.to_string()));
+            Ok(false)
+        })
+        .unwrap();
+        assert_eq!(results[..2], [Ok(()), Ok(())]);
+        assert_eq!(results[2], Err("x.aapc was already written by this run; not overwriting it with in2".to_string()));
+        assert_eq!(results[3], Ok(()));
+        assert!(!written.lock().unwrap().contains(&2));
+    }
+
+    #[test]
+    fn a_failed_input_leaves_its_output_to_the_next() {
+        let outputs = SharedOutputs::new(vec![Some("x.aapc"), Some("x.aapc")]);
+        let failed = outputs.run(0, "in0", || Err(io::Error::other("unreadable").into()));
+        assert!(failed.is_err());
+        assert!(outputs.run(1, "in1", || Ok(report())).is_ok());
+    }
+}
@@ -0,0 +1,341 @@
+//! `archive create`, `list` and `extract`, including archives crafted to
+//! write outside the extraction directory.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use ada_toolkit::checksum::crc32;
+use ada_toolkit::compress;
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+const FILE: u8 = 0;
+#[cfg(unix)]
+const SYMLINK: u8 = 2;
+
+/// An archive holding `entries` of (kind, path, content) as written, with
+/// none of the checks `write_archive` makes.
+fn crafted_archive(entries: &[(u8, &str, &[u8])]) -> Vec<u8> {
+    let table_len = 9 + entries.iter().map(|(_, path, _)| 43 + path.len()).sum::<usize>() + 4;
+    let frames: Vec<Vec<u8>> = entries.iter().map(|(_, _, content)| compress(content)).collect();
+    let mut table = b"AAPA\x01".to_vec();
+    table.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    let mut offset = table_len as u64;
+    for ((kind, path, content), frame) in entries.iter().zip(&frames) {
+        table.push(*kind);
+        table.extend_from_slice(&0o644u32.to_be_bytes());
+        table.extend_from_slice(&0i64.to_be_bytes());
+        table.extend_from_slice(&(content.len() as u64).to_be_bytes());
+        table.extend_from_slice(&crc32(content).to_be_bytes());
+        table.extend_from_slice(&offset.to_be_bytes());
+        table.extend_from_slice(&(frame.len() as u64).to_be_bytes());
+        table.extend_from_slice(&(path.len() as u16).to_be_bytes());
+        table.extend_from_slice(path.as_bytes());
+        offset += frame.len() as u64;
+    }
+    let crc = crc32(&table);
+    table.extend_from_slice(&crc.to_be_bytes());
+    assert_eq!(table.len(), table_len);
+    frames.iter().for_each(|frame| table.extend_from_slice(frame));
+    table
+}
+
+#[test]
+fn create_list_extract_round_trip() {
+    let tmp = TempDir::new();
+    let data = mixed_data(300_000);
+    tmp.write("tree/big.bin", &data);
+    tmp.write("tree/sub/small.txt", "small");
+    tmp.write("tree/sub/empty", "");
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+
+    let list = stdout(&run_ok(tmp.path(), &["archive", "list", "t.aapa"]));
+    for path in ["tree/", "tree/sub/", "tree/big.bin", "tree/sub/small.txt", "tree/sub/empty"] {
+        assert!(list.lines().any(|line| line.ends_with(path)), "{} missing from:\n{}", path, list);
+    }
+
+    run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out"]);
+    assert_eq!(fs::read(tmp.join("out/tree/big.bin")).unwrap(), data);
+    assert_eq!(fs::read(tmp.join("out/tree/sub/small.txt")).unwrap(), b"small");
+    assert_eq!(fs::read(tmp.join("out/tree/sub/empty")).unwrap(), b"");
+}
+
+#[test]
+fn one_and_eight_threads_write_the_same_archive() {
+    let tmp = TempDir::new();
+    for i in 0..24 {
+        tmp.write(&format!("tree/d{}/f{:02}.bin", i % 4, i), mixed_data(20_000 + i * 7_000));
+    }
+    tmp.write("tree/empty", "");
+    let mut times = Vec::new();
+    for (name, extra) in [("one", &["--threads", "1"][..]), ("eight", &["--threads", "8"]),
+                          ("limited", &["--threads", "8", "--max-memory", "3m"])] {
+        let start = std::time::Instant::now();
+        run_ok(tmp.path(), &[extra, &["archive", "create", &format!("{}.aapa", name), "tree"]].concat());
+        times.push(start.elapsed());
+    }
+    let one = fs::read(tmp.join("one.aapa")).unwrap();
+    for name in ["eight", "limited"] {
+        assert!(fs::read(tmp.join(&format!("{}.aapa", name))).unwrap() == one, "{}.aapa differs", name);
+    }
+    // Loosely, since on a single core eight threads cannot be quicker; they
+    // must at least not be much slower.
+    eprintln!("archive create took {:?} on one thread and {:?} on eight", times[0], times[1]);
+    assert!(times[1] < times[0] * 2 + Duration::from_secs(1), "{:?}", times);
+}
+
+#[test]
+fn extraction_restores_modification_times() {
+    let tmp = TempDir::new();
+    let when = SystemTime::UNIX_EPOCH + Duration::from_secs(981_173_106);
+    let file = tmp.write("tree/sub/old.txt", "old");
+    fs::File::options().write(true).open(&file).unwrap().set_modified(when).unwrap();
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+    run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out"]);
+    assert_eq!(fs::metadata(tmp.join("out/tree/sub/old.txt")).unwrap().modified().unwrap(), when);
+}
+
+#[test]
+fn absolute_inputs_are_stored_relative() {
+    let tmp = TempDir::new();
+    tmp.write("tree/a.txt", "a");
+    let tree = tmp.join("tree");
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", tree.to_str().unwrap()]);
+    let list = stdout(&run_ok(tmp.path(), &["archive", "list", "t.aapa"]));
+    assert!(list.lines().any(|line| line.ends_with(" tree/a.txt")), "{}", list);
+    assert!(!list.contains(tmp.path().to_str().unwrap()), "{}", list);
+}
+
+#[test]
+fn members_outside_the_destination_are_rejected() {
+    let tmp = TempDir::new();
+    for path in ["../escaped.txt", "/tmp/escaped.txt", "a/../../escaped.txt"] {
+        fs::write(tmp.join("evil.aapa"), crafted_archive(&[(FILE, path, b"escaped")])).unwrap();
+        let output = run(tmp.path(), &["archive", "extract", "evil.aapa", "-C", "out"]);
+        assert_eq!(output.status.code(), Some(3), "{}: {}", path, stderr(&output));
+        assert!(stderr(&output).contains("unsafe member path"), "{}: {}", path, stderr(&output));
+    }
+    assert!(!tmp.join("escaped.txt").exists());
+    assert!(!Path::new("/tmp/escaped.txt").exists());
+}
+
+#[test]
+fn extract_selects_members_and_reports_missing_ones() {
+    let tmp = TempDir::new();
+    tmp.write("tree/a.txt", "a");
+    tmp.write("tree/sub/b.txt", "b");
+    tmp.write("tree/sub/c.log", "c");
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+
+    run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "one", "tree/sub", "--glob", "**/*.txt"]);
+    assert!(tmp.join("one/tree/a.txt").exists());
+    assert!(tmp.join("one/tree/sub/c.log").exists());
+
+    let output = run(tmp.path(), &["archive", "extract", "t.aapa", "-C", "two", "tree/nothing"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("tree/nothing"), "{}", stderr(&output));
+}
+
+#[test]
+fn extract_one_member_of_many() {
+    let tmp = TempDir::new();
+    for i in 0..30 {
+        tmp.write(&format!("tree/etc/f{:02}", i), mixed_data(20_000 + i));
+    }
+    tmp.write("tree/etc/app/config.toml", "key = 1");
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+
+    let args = ["archive", "extract", "t.aapa", "-C", "out", "tree/etc/app/config.toml", "nope1", "nope2"];
+    let output = run(tmp.path(), &args);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("not found in t.aapa: nope1, nope2"), "{}", stderr(&output));
+    assert_eq!(fs::read(tmp.join("out/tree/etc/app/config.toml")).unwrap(), b"key = 1");
+    assert_eq!(fs::read_dir(tmp.join("out/tree/etc")).unwrap().count(), 1, "other members were extracted");
+}
+
+#[test]
+fn duplicate_member_paths_are_rejected() {
+    let tmp = TempDir::new();
+    let archive = crafted_archive(&[(FILE, "x.txt", b"one"), (FILE, "x.txt", b"two")]);
+    fs::write(tmp.join("dup.aapa"), archive).unwrap();
+    let output = run(tmp.path(), &["archive", "extract", "dup.aapa", "-C", "out"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("duplicate member path"), "{}", stderr(&output));
+    assert!(!tmp.join("out/x.txt").exists());
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn member_under_symlink_member_is_rejected() {
+        let tmp = TempDir::new();
+        fs::create_dir(tmp.join("jail")).unwrap();
+        let archive = crafted_archive(&[(SYMLINK, "a", b".."), (FILE, "a/b", b"escaped")]);
+        fs::write(tmp.join("jail/evil.aapa"), archive).unwrap();
+        let output = run(&tmp.join("jail"), &["archive", "extract", "evil.aapa", "-C", "out"]);
+        assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+        assert!(stderr(&output).contains("symlink member"), "{}", stderr(&output));
+        assert!(!tmp.join("jail/b").exists());
+        assert!(!tmp.join("jail/out/a").exists());
+    }
+
+    #[test]
+    fn forced_extract_does_not_replace_files_through_a_symlink_member() {
+        let tmp = TempDir::new();
+        let victim = tmp.write("jail/victim.txt", "keep me");
+        let archive = crafted_archive(&[(SYMLINK, "a", b".."), (FILE, "a/victim.txt", b"replaced")]);
+        fs::write(tmp.join("jail/evil.aapa"), archive).unwrap();
+        let output = run(&tmp.join("jail"), &["archive", "extract", "-f", "evil.aapa", "-C", "out"]);
+        assert!(!output.status.success());
+        assert_eq!(fs::read(&victim).unwrap(), b"keep me");
+        assert!(!fs::symlink_metadata(&victim).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn extract_refuses_symlinks_already_in_the_destination() {
+        let tmp = TempDir::new();
+        tmp.write("tree/sub/victim.txt", "from the archive");
+        run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+        let victim = tmp.write("outside/victim.txt", "keep me");
+        fs::create_dir_all(tmp.join("out/tree")).unwrap();
+        symlink(tmp.join("outside"), tmp.join("out/tree/sub")).unwrap();
+
+        let output = run(tmp.path(), &["archive", "extract", "-f", "t.aapa", "-C", "out"]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("is a symlink; not extracting through it"), "{}", stderr(&output));
+        assert_eq!(fs::read(&victim).unwrap(), b"keep me");
+    }
+
+    #[test]
+    fn symlinks_round_trip_and_absolute_ones_need_a_flag() {
+        let tmp = TempDir::new();
+        tmp.write("tree/file.txt", "target");
+        symlink("file.txt", tmp.join("tree/relative")).unwrap();
+        symlink("/etc/hostname", tmp.join("tree/absolute")).unwrap();
+        symlink("loop", tmp.join("tree/loop")).unwrap();
+        run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+
+        run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out"]);
+        assert_eq!(fs::read_link(tmp.join("out/tree/relative")).unwrap().to_str(), Some("file.txt"));
+        assert_eq!(fs::read_link(tmp.join("out/tree/loop")).unwrap().to_str(), Some("loop"));
+        assert!(fs::symlink_metadata(tmp.join("out/tree/absolute")).is_err());
+
+        run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "abs", "--absolute-symlinks"]);
+        assert_eq!(fs::read_link(tmp.join("abs/tree/absolute")).unwrap().to_str(), Some("/etc/hostname"));
+    }
+
+    #[test]
+    fn relative_symlinks_out_of_the_directory_are_skipped() {
+        let tmp = TempDir::new();
+        let archive = crafted_archive(&[
+            (FILE, "deep/f.txt", b"inside"),
+            (SYMLINK, "up", b"../../escape_target_dir"),
+            (SYMLINK, "deep/x", b"../../../../../../tmp/w"),
+            (SYMLINK, "deep/sibling", b"../deep/./f.txt"),
+            (SYMLINK, "p/q", b".."),
+            (SYMLINK, "r", b"p/q/../escape_target_dir"),
+        ]);
+        fs::write(tmp.join("evil.aapa"), archive).unwrap();
+        let output = run(tmp.path(), &["archive", "extract", "evil.aapa", "-C", "out"]);
+        assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+        let log = stderr(&output);
+        assert!(log.contains("skipped symlinks whose target leaves out: up, deep/x, r"), "{}", log);
+        assert!(log.contains("Skipping symlink out/up -> ../../escape_target_dir: its target leaves out"), "{}", log);
+        for escaped in ["out/up", "out/deep/x", "out/r"] {
+            assert!(fs::symlink_metadata(tmp.join(escaped)).is_err(), "{} was made", escaped);
+        }
+        // Links that stay inside are still made, as is the rest.
+        assert_eq!(fs::read_link(tmp.join("out/deep/sibling")).unwrap().to_str(), Some("../deep/./f.txt"));
+        assert_eq!(fs::read_link(tmp.join("out/p/q")).unwrap().to_str(), Some(".."));
+        assert_eq!(fs::read(tmp.join("out/deep/f.txt")).unwrap(), b"inside");
+    }
+
+    /// `tree/` and `tree/a.txt` holding "abc", with known modes and times.
+    fn listed_archive() -> TempDir {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        let when = SystemTime::UNIX_EPOCH + Duration::from_secs(981_173_106);
+        let file = tmp.write("tree/a.txt", "abc");
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(tmp.join("tree"), fs::Permissions::from_mode(0o755)).unwrap();
+        for path in [file, tmp.join("tree")] {
+            fs::File::open(path).unwrap().set_modified(when).unwrap();
+        }
+        run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+        tmp
+    }
+
+    #[test]
+    fn list_shows_every_field() {
+        let tmp = listed_archive();
+        let list = stdout(&run_ok(tmp.path(), &["archive", "list", "t.aapa"]));
+        assert_eq!(list, concat!(
+            "        Size   Compressed  Ratio Modified (UTC)      Path\n",
+            "           0            0      - 2001-02-03 04:05:06 tree/\n",
+            "           3            9   3.00 2001-02-03 04:05:06 tree/a.txt\n",
+        ));
+        let long = stdout(&run_ok(tmp.path(), &["archive", "list", "-l", "t.aapa"]));
+        assert_eq!(long, concat!(
+            "Mode   CRC32            Size   Compressed  Ratio Modified (UTC)      Path\n",
+            "0755   00000000            0            0      - 2001-02-03 04:05:06 tree/\n",
+            "0640   352441c2            3            9   3.00 2001-02-03 04:05:06 tree/a.txt\n",
+        ));
+    }
+
+    #[test]
+    fn list_as_json() {
+        let tmp = listed_archive();
+        let list = stdout(&run_ok(tmp.path(), &["--format", "json", "archive", "list", "t.aapa"]));
+        let list: serde_json::Value = serde_json::from_str(&list).unwrap();
+        assert_eq!(list, serde_json::json!({
+            "archive": "t.aapa",
+            "members": [
+                {"path": "tree", "kind": "directory", "size": 0, "compressed_size": 0, "mtime": 981_173_106,
+                 "mode": 0o755, "checksum": "00000000"},
+                {"path": "tree/a.txt", "kind": "file", "size": 3, "compressed_size": 9, "mtime": 981_173_106,
+                 "mode": 0o640, "checksum": "352441c2"},
+            ],
+        }));
+    }
+
+    #[test]
+    fn list_reports_a_damaged_table() {
+        let tmp = listed_archive();
+        let archive = fs::read(tmp.join("t.aapa")).unwrap();
+        tmp.write("cut.aapa", &archive[..40]);
+        let output = run(tmp.path(), &["archive", "list", "cut.aapa"]);
+        assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+        assert!(stderr(&output).contains("truncated"), "{}", stderr(&output));
+
+        let mut flipped = archive;
+        flipped[20] ^= 0x01;
+        tmp.write("bad.aapa", &flipped);
+        let output = run(tmp.path(), &["archive", "list", "bad.aapa"]);
+        assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+        assert!(stderr(&output).contains("checksum mismatch"), "{}", stderr(&output));
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn modes_and_empty_entries_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        let script = tmp.write("tree/run.sh", "#!/bin/sh");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        tmp.write("tree/empty", "");
+        fs::create_dir_all(tmp.join("tree/empty_dir/inner")).unwrap();
+        run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+        run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out"]);
+        let mode = fs::metadata(tmp.join("out/tree/run.sh")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(fs::read(tmp.join("out/tree/empty")).unwrap(), b"");
+        assert!(tmp.join("out/tree/empty_dir/inner").is_dir());
+    }
+}
@@ -0,0 +1,71 @@
+//! Outputs are written under a temporary name and renamed into place
+//! complete, so a run failing part way leaves no partial output behind.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stderr, TempDir};
+
+/// Names in `dir`, hidden ones included.
+fn entries(tmp: &TempDir, dir: &str) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(tmp.join(dir)).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// A frame of 16 KiB blocks with its last block cut short, so that decoding
+/// writes most of the content before failing.
+fn cut_frame(tmp: &TempDir) {
+    tmp.write("big.bin", mixed_data(500_000));
+    run_ok(tmp.path(), &["compress", "--block-size", "16k", "big.bin", "-o", "whole.aapc"]);
+    let frame = fs::read(tmp.join("whole.aapc")).unwrap();
+    tmp.write("out/cut.aapc", &frame[..frame.len() - 2000]);
+    fs::remove_file(tmp.join("big.bin")).unwrap();
+}
+
+#[test]
+fn a_failed_decompress_writes_nothing() {
+    let tmp = TempDir::new();
+    cut_frame(&tmp);
+    let output = run(tmp.path(), &["decompress", "out/cut.aapc", "-o", "out/big.bin"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(entries(&tmp, "out"), ["cut.aapc"]);
+}
+
+#[test]
+fn a_failed_overwrite_keeps_the_old_version() {
+    let tmp = TempDir::new();
+    cut_frame(&tmp);
+    tmp.write("out/big.bin", "the old version");
+    let output = run(tmp.path(), &["decompress", "-f", "out/cut.aapc", "-o", "out/big.bin"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(tmp.join("out/big.bin")).unwrap(), "the old version");
+    assert_eq!(entries(&tmp, "out"), ["big.bin", "cut.aapc"]);
+}
+
+#[test]
+fn a_log_that_cannot_be_renamed_into_place_leaves_no_temporary_file() {
+    let tmp = TempDir::new();
+    tmp.write("data/a.txt", "some text");
+    fs::create_dir(tmp.join("log")).unwrap();
+    let output = run(tmp.path(), &["test-folder", "--log-file", "log", "data"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("writing log log"), "{}", stderr(&output));
+    assert_eq!(entries(&tmp, "."), ["data", "log"]);
+    assert!(entries(&tmp, "log").is_empty());
+}
+
+#[test]
+fn successful_outputs_leave_no_temporary_files() {
+    let tmp = TempDir::new();
+    tmp.write("d/a.bin", mixed_data(100_000));
+    tmp.write("d/b.bin", mixed_data(3000));
+    run_ok(tmp.path(), &["compress", "d/a.bin", "d/b.bin"]);
+    run_ok(tmp.path(), &["decompress", "-f", "d/a.bin.aapc"]);
+    run_ok(tmp.path(), &["archive", "create", "d/all.aapa", "d/a.bin", "d/b.bin"]);
+    run_ok(tmp.path(), &["test-folder", "--log-file", "d/log.txt", "d"]);
+    assert_eq!(entries(&tmp, "d"), ["a.bin", "a.bin.aapc", "all.aapa", "b.bin", "b.bin.aapc", "log.txt"]);
+}
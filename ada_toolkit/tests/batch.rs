@@ -0,0 +1,91 @@
+//! `batch --manifest`: each job's source compressed to its destination,
+//! with a report of how every job went.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+/// A manifest in jobs/ with a good job, a missing source, a destination in
+/// a directory that does not exist and another good job, in that order.
+fn mixed_manifest(tmp: &TempDir, name: &str, separator: &str) {
+    tmp.write("jobs/data/a.bin", mixed_data(30_000));
+    tmp.write("jobs/data/d.bin", mixed_data(5_000));
+    let jobs = [("data/a.bin", "a.aapc"), ("data/missing.bin", "b.aapc"), ("data/a.bin", "nowhere/c.aapc"),
+                ("data/d.bin", "d.aapc")];
+    let lines: Vec<String> = jobs.iter().map(|(src, dst)| format!("{}{}{}\n", src, separator, dst)).collect();
+    tmp.write(&format!("jobs/{}", name), lines.concat());
+}
+
+fn statuses(report: &Value) -> Vec<(String, String)> {
+    report["files"].as_array().unwrap().iter()
+        .map(|file| (file["output"].as_str().unwrap().to_string(), file["status"].as_str().unwrap().to_string()))
+        .collect()
+}
+
+#[test]
+fn every_job_is_reported_with_continue_on_error() {
+    let tmp = TempDir::new();
+    mixed_manifest(&tmp, "jobs.txt", "\t");
+    let output = run(tmp.path(), &["batch", "--manifest", "jobs/jobs.txt", "--continue-on-error",
+                                   "--report", "report.json"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stdout(&output).contains("2 of 4 jobs done; 2 failed, 0 not run"), "{}", stdout(&output));
+
+    // Relative paths are the manifest's, not the working directory's.
+    let decompressed = run_ok(tmp.path(), &["decompress", "-c", "jobs/a.aapc"]).stdout;
+    assert_eq!(decompressed, mixed_data(30_000));
+    assert!(tmp.join("jobs/d.aapc").is_file());
+    assert!(!tmp.join("jobs/nowhere").exists());
+
+    let report: Value = serde_json::from_str(&fs::read_to_string(tmp.join("report.json")).unwrap()).unwrap();
+    let expected = [("jobs/a.aapc", "ok"), ("jobs/b.aapc", "error"), ("jobs/nowhere/c.aapc", "error"),
+                    ("jobs/d.aapc", "ok")];
+    assert_eq!(statuses(&report), expected.map(|(output, status)| (output.to_string(), status.to_string())));
+    let files = &report["files"];
+    assert!(files[1]["error"].as_str().unwrap().contains("missing.bin"), "{}", files[1]);
+    assert!(files[2]["error"].as_str().unwrap().contains("does not exist; use --parents to create it"), "{}", files[2]);
+    assert_eq!(report["totals"]["succeeded"], 2);
+    assert_eq!(report["totals"]["failed"], 2);
+    assert_eq!(report["options"]["continue_on_error"], true);
+}
+
+#[test]
+fn the_first_failure_stops_the_run_without_continue_on_error() {
+    let tmp = TempDir::new();
+    mixed_manifest(&tmp, "jobs.txt", "\t");
+    let args = ["--threads", "1", "batch", "--manifest", "jobs/jobs.txt", "--report", "report.json"];
+    let output = run(tmp.path(), &args);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1 of 4 jobs done; 1 failed, 2 not run"), "{}", stdout(&output));
+    assert!(!tmp.join("jobs/d.aapc").exists());
+
+    let report: Value = serde_json::from_str(&fs::read_to_string(tmp.join("report.json")).unwrap()).unwrap();
+    assert_eq!(report["totals"]["files"], 4);
+    assert_eq!(report["totals"]["processed"], 2);
+}
+
+#[test]
+fn parents_creates_missing_destination_directories() {
+    let tmp = TempDir::new();
+    mixed_manifest(&tmp, "jobs.csv", ",");
+    let output = run(tmp.path(), &["batch", "--manifest", "jobs/jobs.csv", "--parents", "--continue-on-error"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stdout(&output).contains("3 of 4 jobs done; 1 failed"), "{}", stdout(&output));
+    assert!(tmp.join("jobs/nowhere/c.aapc").is_file());
+}
+
+#[test]
+fn a_malformed_manifest_runs_no_job() {
+    let tmp = TempDir::new();
+    mixed_manifest(&tmp, "jobs.txt", "\t");
+    let mut text = fs::read_to_string(tmp.join("jobs/jobs.txt")).unwrap();
+    text.push_str("no destination\n");
+    tmp.write("jobs/jobs.txt", text);
+    let output = run(tmp.path(), &["batch", "--manifest", "jobs/jobs.txt"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("jobs.txt:5: expected source<TAB>destination"), "{}", stderr(&output));
+    assert!(!tmp.join("jobs/a.aapc").exists());
+}
@@ -0,0 +1,85 @@
+//! `bench`: a row per codec, each checked by a round trip first. Only AAPC
+//! is built in without the `bench` feature.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+fn built_in() -> &'static [&'static str] {
+    match cfg!(feature = "bench") {
+        true => &["aapc", "gzip", "zstd", "lz4"],
+        false => &["aapc"],
+    }
+}
+
+/// The codec names of a text table's rows.
+fn rows(table: &str) -> Vec<String> {
+    table.lines().skip(2).filter_map(|line| line.split_whitespace().next()).map(str::to_string).collect()
+}
+
+#[test]
+fn the_table_has_a_row_per_codec() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(64 * 1024));
+    let table = stdout(&run_ok(tmp.path(), &["bench", "in.bin", "--iterations", "2"]));
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines[0], "in.bin (64.00 KiB), median of 2 runs after a warm-up:");
+    let header: Vec<&str> = lines[1].split_whitespace().collect();
+    assert_eq!(header, ["Codec", "Compressed", "Ratio", "Compress", "Decompress"]);
+    assert_eq!(rows(&table), built_in());
+    for row in &lines[2..] {
+        assert_eq!(row.matches("/s").count(), 2, "{}", row);
+    }
+}
+
+#[test]
+fn json_has_an_entry_per_codec() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(10_000));
+    let json = stdout(&run_ok(tmp.path(), &["--format", "json", "bench", "in.bin", "--iterations", "1"]));
+    assert!(json.starts_with("{\"operation\":\"bench\",\"input\":\"in.bin\",\"input_bytes\":10000,\"iterations\":1,"),
+            "{}", json);
+    assert_eq!(json.matches("\"codec\":").count(), built_in().len(), "{}", json);
+    for codec in built_in() {
+        assert!(json.contains(&format!("{{\"codec\":\"{}\",\"compressed_bytes\":", codec)), "{}", json);
+    }
+}
+
+#[test]
+fn codecs_picks_the_rows() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(10_000));
+    let table = stdout(&run_ok(tmp.path(), &["bench", "in.bin", "--codecs", "aapc", "--iterations", "1"]));
+    assert_eq!(rows(&table), ["aapc"]);
+}
+
+#[cfg(feature = "bench")]
+#[test]
+fn codecs_keeps_the_order_asked_for() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(10_000));
+    let table = stdout(&run_ok(tmp.path(), &["bench", "in.bin", "--codecs", "lz4,aapc", "--iterations", "1"]));
+    assert_eq!(rows(&table), ["lz4", "aapc"]);
+}
+
+#[cfg(not(feature = "bench"))]
+#[test]
+fn other_codecs_need_the_feature() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    let output = run(tmp.path(), &["bench", "in.bin", "--codecs", "aapc,zstd"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("zstd needs a build with the bench feature"), "{}", stderr(&output));
+}
+
+#[test]
+fn bad_arguments_are_rejected() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    for args in [&["bench", "in.bin", "--iterations", "0"][..], &["bench", "in.bin", "--codecs", "brotli"]] {
+        assert_eq!(run(tmp.path(), args).status.code(), Some(2), "{:?}", args);
+    }
+    let output = run(tmp.path(), &["bench", "missing.bin"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("missing.bin"), "{}", stderr(&output));
+}
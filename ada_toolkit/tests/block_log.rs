@@ -0,0 +1,57 @@
+//! --verbose logs a line per block compressed, or per group of blocks for
+//! a big file, on stderr only.
+
+mod common;
+
+use common::{run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+fn block_lines(log: &str) -> Vec<&str> {
+    log.lines().filter(|line| line.starts_with("DEBUG: Block")).collect()
+}
+
+#[test]
+fn a_two_block_file_logs_two_lines_with_its_sizes() {
+    let tmp = TempDir::new();
+    let mut data = vec![0u8; 4096];
+    data.extend((0..4096u32).map(|i| (i % 200) as u8));
+    tmp.write("two.bin", &data);
+    let output = run_ok(tmp.path(), &["--verbose", "compress", "two.bin", "--block-size", "4k", "-c"]);
+    tmp.write("two.aapc", &output.stdout);
+    assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "two.aapc"]).stdout, data, "the log reached stdout");
+
+    // What each block took in the frame, its header included.
+    let inspected = run_ok(tmp.path(), &["--format", "json", "inspect", "two.aapc"]);
+    let inspect: Value = serde_json::from_str(&stdout(&inspected)).unwrap();
+    let offset = |i: usize| inspect["blocks"][i]["payload_offset"].as_u64().unwrap();
+    let comp_len = |i: usize| inspect["blocks"][i]["comp_len"].as_u64().unwrap();
+    let block_header = offset(1) - offset(0) - comp_len(0);
+    let frame_header = offset(0) - block_header;
+    let (first, second) = (comp_len(0) + block_header, comp_len(1) + block_header);
+
+    let log = stderr(&output);
+    assert_eq!(block_lines(&log), [
+        format!("DEBUG: Block 0: 4096 -> {} bytes (ratio {:.3}, rle); total 4096 -> {} bytes (ratio {:.3})",
+                first, first as f64 / 4096.0, frame_header + first, (frame_header + first) as f64 / 4096.0),
+        format!("DEBUG: Block 1: 4096 -> {} bytes (ratio {:.3}, stored); total 8192 -> {} bytes (ratio {:.3})",
+                second, second as f64 / 4096.0, frame_header + first + second,
+                (frame_header + first + second) as f64 / 8192.0),
+    ], "{}", log);
+
+    let quiet = run_ok(tmp.path(), &["compress", "two.bin", "--block-size", "4k", "-c"]);
+    assert!(block_lines(&stderr(&quiet)).is_empty(), "{}", stderr(&quiet));
+    assert_eq!(quiet.stdout, output.stdout);
+}
+
+#[test]
+fn a_big_file_logs_groups_of_blocks() {
+    let tmp = TempDir::new();
+    tmp.write("big.bin", vec![7u8; 1500 * 1024]);
+    let output = run_ok(tmp.path(), &["--verbose", "compress", "big.bin", "--block-size", "1k"]);
+    let log = stderr(&output);
+    let lines = block_lines(&log);
+    assert_eq!(lines.len(), 750, "{}", log);
+    assert!(lines[0].starts_with("DEBUG: Blocks 0-1: 2048 -> "), "{}", lines[0]);
+    assert!(lines[749].starts_with("DEBUG: Blocks 1498-1499: 2048 -> "), "{}", lines[749]);
+    assert!(lines[749].contains("; total 1536000 -> "), "{}", lines[749]);
+}
@@ -0,0 +1,31 @@
+//! `--block-size` round trips at several sizes, and `info` shows it.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+#[test]
+fn round_trips_at_each_block_size() {
+    let tmp = TempDir::new();
+    let data = mixed_data(1_500_000);
+    tmp.write("in.bin", &data);
+    for (flag, bytes, blocks) in [("4k", 4096, 367), ("64k", 65536, 23), ("1M", 1 << 20, 2), ("4M", 4 << 20, 1)] {
+        run_ok(tmp.path(), &["compress", "-f", "--block-size", flag, "in.bin"]);
+        let info = stdout(&run_ok(tmp.path(), &["info", "in.bin.aapc"]));
+        assert!(info.contains(&format!("Block size: {} bytes\n", bytes)), "{}: {}", flag, info);
+        assert!(info.contains(&format!("Blocks: {}\n", blocks)), "{}: {}", flag, info);
+        run_ok(tmp.path(), &["decompress", "-f", "in.bin.aapc", "-o", "out.bin"]);
+        assert!(std::fs::read(tmp.join("out.bin")).unwrap() == data, "{} did not round trip", flag);
+    }
+}
+
+#[test]
+fn sizes_outside_the_format_are_usage_errors() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    for flag in ["0", "17M", "1G", "big", "-4k"] {
+        let output = run(tmp.path(), &["compress", "--block-size", flag, "in.bin"]);
+        assert_eq!(output.status.code(), Some(2), "{}: {}", flag, stderr(&output));
+    }
+    assert!(!tmp.join("in.bin.aapc").exists());
+}
@@ -0,0 +1,181 @@
+/* Drives the streaming context API in include/aapc.h from C: compresses and
+ * decompresses in 64 KiB input chunks through deliberately tiny output
+ * buffers, so that every call has to resume after AAPC_NEEDS_OUTPUT, and
+ * checks the error codes for damaged and cut frames.
+ *
+ * Build the crate as a static library with the `ffi` feature, then link
+ * against it, for example:
+ *
+ *     cc -Wall -Iinclude tests/c/ctx_harness.c target/debug/libada_toolkit.a \
+ *        -lpthread -ldl -lm -o ctx_harness && ./ctx_harness
+ *
+ * It prints one line per check and exits non-zero if any fails. */
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "aapc.h"
+
+#define CHUNK (64 * 1024)
+
+static int failures = 0;
+
+static void check(int ok, const char *what) {
+    printf("%s: %s\n", ok ? "ok" : "FAILED", what);
+    if (!ok) {
+        failures++;
+    }
+}
+
+typedef struct Buf {
+    unsigned char *data;
+    size_t len;
+    size_t cap;
+} Buf;
+
+static void append(Buf *buf, const unsigned char *data, size_t len) {
+    if (len == 0) {
+        return;
+    }
+    if (buf->len + len > buf->cap) {
+        buf->cap = (buf->len + len) * 2;
+        buf->data = realloc(buf->data, buf->cap);
+        if (!buf->data) {
+            abort();
+        }
+    }
+    memcpy(buf->data + buf->len, data, len);
+    buf->len += len;
+}
+
+/* Runs `input` through a fresh context in `mode`, CHUNK bytes at a time,
+ * into an `out_cap`-byte output buffer, and returns the last status. */
+static int run(int mode, const AapcOptions *options, const unsigned char *input, size_t len, size_t out_cap,
+               Buf *out) {
+    AapcCtx *ctx = aapc_ctx_new(mode, options);
+    unsigned char *scratch = malloc(out_cap);
+    int status = AAPC_OK;
+    size_t pos = 0;
+    if (!ctx || !scratch) {
+        abort();
+    }
+    while (pos < len && status >= 0) {
+        size_t end = pos + CHUNK < len ? pos + CHUNK : len;
+        /* Resume with whatever the previous call left unconsumed. */
+        do {
+            size_t consumed = 0, produced = 0;
+            status = aapc_ctx_update(ctx, input + pos, end - pos, scratch, out_cap, &consumed, &produced);
+            append(out, scratch, produced);
+            pos += consumed;
+        } while (status == AAPC_NEEDS_OUTPUT || (status == AAPC_OK && pos < end));
+    }
+    while (status >= 0) {
+        size_t produced = 0;
+        status = aapc_ctx_finish(ctx, scratch, out_cap, &produced);
+        append(out, scratch, produced);
+        if (status == AAPC_OK) {
+            break;
+        }
+    }
+    aapc_ctx_free(ctx);
+    free(scratch);
+    return status;
+}
+
+/* Runs of one value, with noise between them. */
+static unsigned char *mixed_data(size_t len) {
+    unsigned char *data = malloc(len);
+    uint32_t state = 0x2545F491u;
+    for (size_t i = 0; i < len; i++) {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        data[i] = i % 4096 < 2500 ? (unsigned char)(i / 4096) : (unsigned char)state;
+    }
+    return data;
+}
+
+static void round_trip(const AapcOptions *options, const char *name) {
+    size_t len = 300 * 1000;
+    unsigned char *data = mixed_data(len);
+    char what[128];
+
+    for (size_t out_cap = 1; out_cap <= 4096; out_cap *= 8) {
+        Buf frame = {0}, decoded = {0};
+        int status = run(AAPC_MODE_COMPRESS, options, data, len, out_cap, &frame);
+        snprintf(what, sizeof what, "%s: compress through a %zu byte buffer", name, out_cap);
+        check(status == AAPC_OK && frame.len > 0 && frame.len < len, what);
+
+        status = run(AAPC_MODE_DECOMPRESS, NULL, frame.data, frame.len, out_cap, &decoded);
+        snprintf(what, sizeof what, "%s: decompress through a %zu byte buffer", name, out_cap);
+        check(status == AAPC_OK && decoded.len == len && memcmp(decoded.data, data, len) == 0, what);
+        free(frame.data);
+        free(decoded.data);
+    }
+    free(data);
+}
+
+static void damaged_frames(void) {
+    size_t len = 100 * 1000;
+    unsigned char *data = mixed_data(len);
+    Buf frame = {0}, out = {0};
+    check(run(AAPC_MODE_COMPRESS, NULL, data, len, 4096, &frame) == AAPC_OK, "compress the frame to damage");
+
+    check(run(AAPC_MODE_DECOMPRESS, NULL, frame.data, frame.len - 5, 4096, &out) == AAPC_ERR_TRUNCATED,
+          "a cut frame is AAPC_ERR_TRUNCATED");
+
+    /* A byte of stored noise in the first block, so only the checksum sees it. */
+    frame.data[2600] ^= 1;
+    out.len = 0;
+    check(run(AAPC_MODE_DECOMPRESS, NULL, frame.data, frame.len, 4096, &out) == AAPC_ERR_CHECKSUM,
+          "a flipped byte is AAPC_ERR_CHECKSUM");
+
+    memcpy(frame.data, "NOPE", 4);
+    out.len = 0;
+    check(run(AAPC_MODE_DECOMPRESS, NULL, frame.data, frame.len, 4096, &out) == AAPC_ERR_CORRUPT,
+          "a bad magic is AAPC_ERR_CORRUPT");
+    free(frame.data);
+    free(out.data);
+    free(data);
+}
+
+static void invalid_arguments(void) {
+    AapcOptions too_big = {64 * 1024 * 1024, 1};
+    size_t consumed, produced;
+    unsigned char byte;
+    AapcCtx *ctx;
+
+    check(aapc_ctx_new(7, NULL) == NULL, "an unknown mode gives NULL");
+    check(aapc_ctx_new(AAPC_MODE_COMPRESS, &too_big) == NULL, "an oversized block gives NULL");
+    check(aapc_ctx_update(NULL, NULL, 0, &byte, 1, &consumed, &produced) == AAPC_ERR_INVALID,
+          "a NULL context is AAPC_ERR_INVALID");
+
+    ctx = aapc_ctx_new(AAPC_MODE_DECOMPRESS, NULL);
+    check(aapc_ctx_update(ctx, NULL, 10, &byte, 1, &consumed, &produced) == AAPC_ERR_INVALID,
+          "NULL input with a length is AAPC_ERR_INVALID");
+    check(aapc_ctx_update(ctx, NULL, 0, &byte, 1, NULL, &produced) == AAPC_ERR_INVALID,
+          "NULL out-parameters are AAPC_ERR_INVALID");
+    check(aapc_ctx_finish(ctx, &byte, 1, &produced) == AAPC_ERR_TRUNCATED, "finishing with no input is truncated");
+    aapc_ctx_free(ctx);
+    aapc_ctx_free(NULL);
+}
+
+int main(void) {
+    AapcOptions small_blocks = {4096, 1};
+    AapcOptions unchecked = {0, 0};
+
+    round_trip(NULL, "default options");
+    round_trip(&small_blocks, "4 KiB blocks");
+    round_trip(&unchecked, "no checksums");
+    damaged_frames();
+    invalid_arguments();
+
+    if (failures) {
+        printf("%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
@@ -0,0 +1,53 @@
+//! `cat` writes only content to stdout, one file after another, and stops
+//! quietly when the reader goes away.
+
+mod common;
+
+use std::io::Read;
+use std::process::Stdio;
+
+use common::{cli, mixed_data, run, run_ok, stderr, TempDir};
+
+#[test]
+fn files_are_concatenated() {
+    let tmp = TempDir::new();
+    let (a, b) = (mixed_data(200_000), b"second file\n".to_vec());
+    tmp.write("a", &a);
+    tmp.write("b", &b);
+    run_ok(tmp.path(), &["compress", "a", "b"]);
+    let output = run_ok(tmp.path(), &["cat", "a.aapc", "b.aapc", "a.aapc"]);
+    assert!(output.stdout == [&a[..], &b, &a].concat(), "stdout holds more than the content");
+    assert_eq!(stderr(&output), "");
+
+    let verbose = run_ok(tmp.path(), &["--verbose", "cat", "b.aapc"]);
+    assert_eq!(verbose.stdout, b);
+    assert!(stderr(&verbose).contains("Decompressed b.aapc"), "{}", stderr(&verbose));
+}
+
+#[test]
+fn a_bad_file_is_skipped_and_counted() {
+    let tmp = TempDir::new();
+    tmp.write("a", "first\n");
+    run_ok(tmp.path(), &["compress", "a"]);
+    tmp.write("junk.aapc", "not a frame");
+    let output = run(tmp.path(), &["cat", "junk.aapc", "a.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"first\n");
+    assert!(stderr(&output).contains("1 of 2 files could not be decompressed"), "{}", stderr(&output));
+}
+
+#[test]
+fn a_reader_that_stops_early_is_not_an_error() {
+    let tmp = TempDir::new();
+    tmp.write("big", vec![b'x'; 4 * 1024 * 1024]);
+    run_ok(tmp.path(), &["compress", "big"]);
+    let mut child = cli(tmp.path()).args(["cat", "big.aapc"]).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    let mut head = [0; 4096];
+    child.stdout.take().unwrap().read_exact(&mut head).unwrap();
+    // Dropping the read end closes the pipe, like `| head -c 4096`.
+    let output = child.wait_with_output().unwrap();
+    assert!(head.iter().all(|&byte| byte == b'x'));
+    assert!(matches!(output.status.code(), Some(0 | 141)), "{:?}: {}", output.status, stderr(&output));
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
@@ -0,0 +1,103 @@
+//! --checkpoint and --resume: a compression killed part-way carries on
+//! from its last checkpoint and ends with the same bytes as one that ran
+//! straight through.
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{cli, mixed_data, run, run_ok, stderr, TempDir};
+
+const LEN: usize = 4 << 20;
+
+/// The input offset the checkpoint in `state` was saved at, if there is one.
+fn saved_offset(state: &std::path::Path) -> Option<u64> {
+    let text = fs::read_to_string(state).ok()?;
+    let at = text.find("\"input_offset\":")? + "\"input_offset\":".len();
+    text[at..].split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+}
+
+/// Starts a checkpointed compression of in.bin and kills it once it has
+/// saved a checkpoint at `offset` or later. Returns the offset it got to.
+fn kill_at(tmp: &TempDir, offset: u64) -> u64 {
+    let mut child = cli(tmp.path())
+        .args(["compress", "in.bin", "--block-size", "4k", "--checkpoint", "state.json", "-o", "out.aapc"])
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let saved = loop {
+        if let Some(saved) = saved_offset(&tmp.join("state.json")).filter(|&saved| saved >= offset) {
+            break saved;
+        }
+        assert!(child.try_wait().unwrap().is_none(), "finished before a checkpoint at {}", offset);
+        assert!(started.elapsed() < Duration::from_secs(60), "no checkpoint at {}", offset);
+        thread::sleep(Duration::from_millis(1));
+    };
+    child.kill().unwrap();
+    child.wait().unwrap();
+    saved
+}
+
+#[test]
+fn a_killed_run_resumes_to_the_same_output() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(LEN));
+    run_ok(tmp.path(), &["compress", "in.bin", "--block-size", "4k", "-o", "reference.aapc"]);
+    let reference = fs::read(tmp.join("reference.aapc")).unwrap();
+
+    for offset in [LEN as u64 / 10, LEN as u64 / 2, LEN as u64 * 9 / 10] {
+        let saved = kill_at(&tmp, offset);
+        assert!(!tmp.join("out.aapc").exists(), "killed at {} but wrote the output", saved);
+        assert!(tmp.join(".out.aapc.partial").is_file());
+
+        let output = run_ok(tmp.path(), &["compress", "in.bin", "--block-size", "4k", "--resume", "state.json",
+                                          "-o", "out.aapc", "--log-level", "info"]);
+        assert!(stderr(&output).contains(&format!("Resuming in.bin at {} of input", saved)), "{}", stderr(&output));
+        assert!(fs::read(tmp.join("out.aapc")).unwrap() == reference, "resumed from {} to different bytes", saved);
+        assert!(!tmp.join("state.json").exists());
+        assert!(!tmp.join(".out.aapc.partial").exists());
+        fs::remove_file(tmp.join("out.aapc")).unwrap();
+    }
+}
+
+#[test]
+fn a_checkpoint_is_not_resumed_for_a_changed_input() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(LEN));
+    kill_at(&tmp, 1);
+    tmp.write("in.bin", mixed_data(LEN / 2));
+
+    let args = ["compress", "in.bin", "--block-size", "4k", "--resume", "state.json", "-o", "out.aapc"];
+    let output = run(tmp.path(), &args);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("the input has changed since"), "{}", stderr(&output));
+    assert!(!tmp.join("out.aapc").exists());
+    assert!(tmp.join("state.json").is_file());
+}
+
+#[test]
+fn a_left_over_checkpoint_is_not_started_over() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(LEN));
+    kill_at(&tmp, 1);
+
+    let args = ["compress", "in.bin", "--block-size", "4k", "--checkpoint", "state.json", "-o", "out.aapc"];
+    let output = run(tmp.path(), &args);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("already holds a checkpoint; carry on with --resume"), "{}", stderr(&output));
+    assert!(tmp.join(".out.aapc.partial").is_file());
+}
+
+#[test]
+fn checkpoints_take_a_single_input_file() {
+    let tmp = TempDir::new();
+    tmp.write("a", "one");
+    tmp.write("b", "two");
+    for args in [&["compress", "a", "b", "--checkpoint", "state.json"][..], &["compress", "-", "--checkpoint", "s"],
+                 &["compress", "a", "--checkpoint", "s", "--resume", "s"]] {
+        let output = run(tmp.path(), args);
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", args, stderr(&output));
+    }
+}
@@ -0,0 +1,80 @@
+//! Block and content checksums, taken in the one encoding pass, are the
+//! CRC-32s a second pass over the input gives, whichever path encodes it,
+//! as is the content CRC-32 the totals hand back.
+
+use std::io::Write;
+
+use ada_toolkit::checksum::crc32;
+use ada_toolkit::stream::AapcWriter;
+use ada_toolkit::{
+    compress_with_stats, copy_encode, copy_encode_pipelined, copy_encode_slice, validate, BlockTable, CompressOptions,
+    CompressionStats,
+};
+
+fn input() -> Vec<u8> {
+    (0..300_000u32).map(|i| if i % 1000 < 400 { (i / 1000) as u8 } else { (i.wrapping_mul(2_654_435_761) >> 24) as u8 })
+        .collect()
+}
+
+/// Checks the checksums in `frame` against `data`, hashed again. A small
+/// frame's one CRC is both its block's and its content's.
+fn check(frame: &[u8], data: &[u8], opts: &CompressOptions, what: &str) {
+    let info = validate(frame).unwrap();
+    let (block_checksums, content_checksum) = match info.small {
+        true => (opts.block_checksums || opts.content_checksum, opts.block_checksums || opts.content_checksum),
+        false => (opts.block_checksums, opts.content_checksum),
+    };
+    let expected = content_checksum.then(|| crc32(data));
+    assert_eq!(info.content_checksum, expected, "{}", what);
+    let table = BlockTable::build(std::io::Cursor::new(frame)).unwrap();
+    for entry in table.entries() {
+        let block = &data[entry.raw_offset as usize..][..entry.raw_len as usize];
+        let expected = block_checksums.then(|| crc32(block));
+        assert_eq!(entry.checksum, expected, "{}: block at {}", what, entry.raw_offset);
+    }
+}
+
+#[test]
+fn one_pass_checksums_match_two_passes() {
+    let data = input();
+    for (block_checksums, content_checksum) in [(true, true), (true, false), (false, true), (false, false)] {
+        for threads in [1, 3] {
+            let opts = CompressOptions {
+                block_size: 16 * 1024,
+                block_checksums,
+                content_checksum,
+                threads,
+                ..CompressOptions::default()
+            };
+            let what = format!("block {} content {} threads {}", block_checksums, content_checksum, threads);
+            let taken = |stats: CompressionStats, data: &[u8], how: &str| {
+                let expected = (block_checksums || content_checksum).then(|| crc32(data));
+                assert_eq!(stats.content_crc, expected, "{}: {}", what, how);
+            };
+            let (frame, stats) = compress_with_stats(&data, &opts).unwrap();
+            check(&frame, &data, &opts, &what);
+            taken(stats, &data, "in memory");
+            let (small, stats) = compress_with_stats(&data[..1000], &opts).unwrap();
+            check(&small, &data[..1000], &opts, &format!("{}: small frame", what));
+            taken(stats, &data[..1000], "small frame");
+
+            let mut writer = AapcWriter::with_options(Vec::new(), &opts).unwrap();
+            for piece in data.chunks(7_777) {
+                writer.write_all(piece).unwrap();
+            }
+            let (streamed, stats) = writer.finish_with_stats().unwrap();
+            assert!(streamed == frame, "{}: streamed", what);
+            taken(stats, &data, "streamed");
+
+            let mut piped = Vec::new();
+            taken(copy_encode_pipelined(data.as_slice(), &mut piped, &opts, None).unwrap(), &data, "pipelined");
+            assert!(piped == frame, "{}: pipelined", what);
+            let mut copied = Vec::new();
+            taken(copy_encode(data.as_slice(), &mut copied, &opts, None).unwrap(), &data, "copied");
+            assert!(copied == frame, "{}: copied", what);
+            let mut sliced = Vec::new();
+            taken(copy_encode_slice(&data, &mut sliced, &opts, None).unwrap(), &data, "sliced");
+            assert!(sliced == frame, "{}: sliced", what);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! `compare`: exit 0 for the same content, 1 for different content and
+//! more than 1 for errors, without writing anything out.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, stderr, stdout, TempDir};
+
+/// in.bin compressed twice with different block sizes, so the frames
+/// differ but their content does not, and a copy changed at byte 70000.
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    let mut changed = mixed_data(200_000);
+    changed[70_000] ^= 1;
+    tmp.write("in.bin", mixed_data(200_000));
+    tmp.write("changed.bin", changed);
+    for args in [&["compress", "in.bin", "-o", "a.aapc"][..],
+                 &["compress", "in.bin", "-o", "b.aapc", "--block-size", "16k"], &["compress", "changed.bin"]] {
+        assert!(run(tmp.path(), args).status.success(), "{:?}", args);
+    }
+    assert_ne!(fs::read(tmp.join("a.aapc")).unwrap(), fs::read(tmp.join("b.aapc")).unwrap());
+    tmp
+}
+
+#[test]
+fn recompressed_content_is_the_same() {
+    let tmp = setup();
+    let output = run(tmp.path(), &["compare", "a.aapc", "b.aapc"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "a.aapc and b.aapc have the same content (200000 bytes)\n");
+
+    let output = run(tmp.path(), &["compare", "a.aapc", "b.aapc", "--quick"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "a.aapc and b.aapc have the same content (200000 bytes, by checksum)\n");
+}
+
+#[test]
+fn a_modified_copy_differs_at_the_changed_byte() {
+    let tmp = setup();
+    let output = run(tmp.path(), &["compare", "a.aapc", "changed.bin.aapc"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("a.aapc and changed.bin.aapc differ at byte 70000 (200000 and 200000 bytes)"),
+            "{}", stderr(&output));
+
+    let output = run(tmp.path(), &["--format", "json", "compare", "a.aapc", "changed.bin.aapc"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "{\"operation\":\"compare\",\"a\":\"a.aapc\",\"b\":\"changed.bin.aapc\",\
+                                 \"equal\":false,\"first_difference\":70000,\"a_bytes\":200000,\"b_bytes\":200000,\
+                                 \"method\":\"decoded\"}\n");
+
+    let output = run(tmp.path(), &["compare", "a.aapc", "changed.bin.aapc", "--quick"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("differ (200000 and 200000 bytes, by checksum)"), "{}", stderr(&output));
+}
+
+#[test]
+fn errors_exit_with_more_than_one() {
+    let tmp = setup();
+    let output = run(tmp.path(), &["compare", "a.aapc", "missing.aapc"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+
+    let mut frame = fs::read(tmp.join("b.aapc")).unwrap();
+    frame.truncate(frame.len() - 100);
+    tmp.write("cut.aapc", frame);
+    let output = run(tmp.path(), &["compare", "a.aapc", "cut.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("decompressing cut.aapc"), "{}", stderr(&output));
+}
@@ -0,0 +1,66 @@
+//! Shell completion scripts, generated from the CLI definition.
+
+mod common;
+
+use std::path::Path;
+use std::process::Stdio;
+
+use common::{cli, run, run_ok, stderr, stdout, TempDir};
+
+/// The subcommands `--help` lists.
+fn subcommands(dir: &Path) -> Vec<String> {
+    let help = stdout(&run_ok(dir, &["--help"]));
+    help.lines()
+        .skip_while(|line| *line != "Commands:")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| *name != "help")
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn bash_script_covers_every_subcommand_and_flag_value() {
+    let tmp = TempDir::new();
+    let script = stdout(&run_ok(tmp.path(), &["completions", "bash"]));
+    let names = subcommands(tmp.path());
+    assert!(names.len() > 15 && names.iter().any(|name| name == "completions"), "{:?}", names);
+    for name in &names {
+        assert!(script.contains(&format!("ada_toolkit,{})", name)), "no completion for {}", name);
+    }
+    assert!(script.contains(" --verbose "));
+    assert!(script.contains("compgen -W \"text json\""), "--format values are not completed");
+    assert!(script.contains("compgen -W \"runs random text sparse mixed\""), "--profile values are not completed");
+}
+
+#[test]
+fn every_shell_gets_a_script() {
+    let tmp = TempDir::new();
+    let shells = [("bash", "complete -F"), ("zsh", "#compdef ada_toolkit"), ("fish", "complete -c ada_toolkit"),
+                  ("powershell", "Register-ArgumentCompleter"), ("elvish", "set edit:completion:arg-completer")];
+    for (shell, marker) in shells {
+        let script = stdout(&run_ok(tmp.path(), &["completions", shell]));
+        assert!(script.contains(marker), "{} script lacks {:?}", shell, marker);
+        assert!(script.contains("test-folder"), "{} script lacks the subcommands", shell);
+    }
+}
+
+#[test]
+fn an_unknown_shell_is_a_usage_error() {
+    let tmp = TempDir::new();
+    let output = run(tmp.path(), &["completions", "tcsh"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("possible values: bash"), "{}", stderr(&output));
+}
+
+#[test]
+fn a_closed_pipe_does_not_panic() {
+    let tmp = TempDir::new();
+    let mut child = cli(tmp.path()).args(["completions", "zsh"]).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    assert!(matches!(output.status.code(), Some(0 | 141)), "{:?}: {}", output.status, stderr(&output));
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
@@ -0,0 +1,108 @@
+//! `compress-dir` over a nested tree: the mirrored outputs, the summary
+//! counts, links, unreadable files and the order of the walk.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+/// Three files at three depths, an empty directory and a link to the top
+/// file.
+fn nested_tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("src/top.txt", "top");
+    tmp.write("src/a/data.bin", mixed_data(100_000));
+    tmp.write("src/a/b/deep.txt", "deep");
+    fs::create_dir(tmp.join("src/empty")).unwrap();
+    symlink("../top.txt", tmp.join("src/a/link")).unwrap();
+    tmp
+}
+
+fn log(output: &std::process::Output) -> String {
+    format!("{}{}", stdout(output), stderr(output))
+}
+
+#[test]
+fn the_tree_is_mirrored_and_counted() {
+    let tmp = nested_tree();
+    let output = run_ok(tmp.path(), &["compress-dir", "src", "dst"]);
+    assert!(log(&output).contains("Compressed 3 files from src to dst"), "{}", log(&output));
+    assert!(log(&output).contains("0 failed, 1 skipped"), "{}", log(&output));
+    for name in ["top.txt", "a/data.bin", "a/b/deep.txt"] {
+        let frame = tmp.join(&format!("dst/{}.aapc", name));
+        run_ok(tmp.path(), &["decompress", frame.to_str().unwrap(), "-o", "back"]);
+        assert_eq!(fs::read(tmp.join("back")).unwrap(), fs::read(tmp.join(&format!("src/{}", name))).unwrap());
+        fs::remove_file(tmp.join("back")).unwrap();
+    }
+    assert!(fs::symlink_metadata(tmp.join("dst/a/link")).is_err());
+    assert!(fs::symlink_metadata(tmp.join("dst/a/link.aapc")).is_err());
+
+    let output = run_ok(tmp.path(), &["compress-dir", "--follow-symlinks", "src", "linked"]);
+    assert!(log(&output).contains("Compressed 4 files"), "{}", log(&output));
+    assert!(tmp.join("linked/a/link.aapc").is_file());
+}
+
+#[test]
+fn json_summary_counts_bytes() {
+    let tmp = nested_tree();
+    let output = run_ok(tmp.path(), &["--format", "json", "compress-dir", "src", "dst"]);
+    let summary: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(summary["compressed"], 3);
+    assert_eq!(summary["failed"], 0);
+    assert_eq!(summary["skipped"], 1);
+    assert_eq!(summary["input_bytes"], 100_007);
+    let written: u64 = ["top.txt", "a/data.bin", "a/b/deep.txt"].iter()
+        .map(|name| fs::metadata(tmp.join(&format!("dst/{}.aapc", name))).unwrap().len())
+        .sum();
+    assert_eq!(summary["output_bytes"], written);
+}
+
+#[test]
+fn a_failed_file_does_not_stop_the_walk_unless_fail_fast() {
+    let tmp = nested_tree();
+    symlink("/nonexistent", tmp.join("src/a/b/dangling")).unwrap();
+    let output = run(tmp.path(), &["compress-dir", "--follow-symlinks", "src", "dst"]);
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(log(&output).contains("reading src/a/b/dangling"), "{}", log(&output));
+    assert!(log(&output).contains("Compressed 4 files"), "{}", log(&output));
+    assert!(log(&output).contains("1 failed"), "{}", log(&output));
+
+    let output = run(tmp.path(), &["compress-dir", "--follow-symlinks", "--fail-fast", "src", "fast"]);
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(!tmp.join("fast/top.txt.aapc").exists(), "the walk carried on: {}", log(&output));
+}
+
+#[test]
+fn an_unreadable_file_fails_alone() {
+    let tmp = nested_tree();
+    let locked = tmp.write("src/a/locked", "secret");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read(&locked).is_ok() {
+        return; // Running privileged, which permissions do not stop.
+    }
+    let output = run(tmp.path(), &["compress-dir", "src", "dst"]);
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(log(&output).contains("Compressed 3 files"), "{}", log(&output));
+    assert!(log(&output).contains("1 failed"), "{}", log(&output));
+    assert!(!tmp.join("dst/a/locked.aapc").exists());
+}
+
+#[test]
+fn the_walk_order_is_sorted_and_stable() {
+    let tmp = nested_tree();
+    for name in ["src/z", "src/m", "src/a/c", "src/a/0"] {
+        tmp.write(name, name);
+    }
+    let order = |dst: &str| -> Vec<String> {
+        let output = run_ok(tmp.path(), &["--verbose", "compress-dir", "src", dst]);
+        stderr(&output).lines().filter_map(|line| line.strip_prefix("DEBUG: Reading input file ")).map(String::from)
+            .collect()
+    };
+    let first = order("one");
+    assert_eq!(first, ["src/a/0", "src/a/b/deep.txt", "src/a/c", "src/a/data.bin", "src/m", "src/top.txt", "src/z"]);
+    assert_eq!(order("two"), first);
+}
@@ -0,0 +1,99 @@
+//! `test-folder` and `compress-dir` carry on past a file that fails,
+//! list every failure after the summary and fail at the end; `--fail-fast`
+//! stops at the first instead.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+
+use common::{run, stderr, stdout, TempDir};
+use serde_json::Value;
+
+/// a.txt, b.txt and c.txt, with b.txt in the middle of the listing a
+/// symlink to nothing, which fails once followed.
+fn broken_middle() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("d/a.txt", "first file");
+    tmp.write("d/c.txt", "third file");
+    std::os::unix::fs::symlink("/nonexistent", tmp.join("d/b.txt")).unwrap();
+    tmp
+}
+
+#[test]
+fn test_folder_tests_the_files_after_a_failure() {
+    let tmp = broken_middle();
+    let output = run(tmp.path(), &["test-folder", "d", "--follow-symlinks"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(text.contains("Tested a.txt successfully") && text.contains("Tested c.txt successfully"), "{}", text);
+    assert!(text.contains("Failed:\n  reading d/b.txt: No such file or directory"), "{}", text);
+    assert!(stderr(&output).contains("1 of 3 files failed"), "{}", stderr(&output));
+
+    let output = run(tmp.path(), &["--format", "json", "test-folder", "d", "--follow-symlinks"]);
+    assert_eq!(output.status.code(), Some(1));
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!((report["tested"].as_u64(), report["not_tested"].as_u64()), (Some(2), Some(0)));
+    assert_eq!(report["failures"].as_array().unwrap().len(), 1);
+    assert!(report["failures"][0].as_str().unwrap().starts_with("reading d/b.txt: "), "{}", report["failures"]);
+}
+
+#[test]
+fn fail_fast_stops_test_folder_at_the_failure() {
+    let tmp = broken_middle();
+    let output = run(tmp.path(), &["test-folder", "d", "--follow-symlinks", "--fail-fast"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(!text.contains("Tested a.txt"), "{}", text);
+    assert!(text.contains("Stopped at the first failure (--fail-fast); 2 files not tested"), "{}", text);
+}
+
+#[test]
+fn compress_dir_lists_its_failures() {
+    let tmp = broken_middle();
+    let output = run(tmp.path(), &["compress-dir", "d", "out", "--follow-symlinks"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(tmp.join("out/a.txt.aapc").is_file() && tmp.join("out/c.txt.aapc").is_file());
+    let text = stdout(&output);
+    assert!(text.contains(". 1 failed, 0 skipped"), "{}", text);
+    assert!(text.contains("Failed:\n  reading d/b.txt: "), "{}", text);
+
+    let output = run(tmp.path(), &["--format", "json", "compress-dir", "d", "json", "--follow-symlinks"]);
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!((report["compressed"].as_u64(), report["failed"].as_u64()), (Some(2), Some(1)));
+    assert!(report["failures"][0].as_str().unwrap().starts_with("reading d/b.txt: "), "{}", report["failures"]);
+}
+
+#[test]
+fn a_permission_denied_file_in_the_middle_fails_alone() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new();
+    tmp.write("d/a.txt", "first file");
+    tmp.write("d/c.txt", "third file");
+    let locked = tmp.write("d/b.txt", "secret");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read(&locked).is_ok() {
+        return; // Running privileged, which permissions do not stop.
+    }
+    let output = run(tmp.path(), &["test-folder", "d"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Tested c.txt successfully"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Permission denied"), "{}", stdout(&output));
+
+    let output = run(tmp.path(), &["compress-dir", "d", "out"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(tmp.join("out/c.txt.aapc").is_file());
+}
+
+#[test]
+fn fail_fast_and_continue_on_error_conflict() {
+    let tmp = broken_middle();
+    for command in [&["test-folder", "d"][..], &["compress-dir", "d", "out"]] {
+        let output = run(tmp.path(), &[command, &["--fail-fast", "--continue-on-error"]].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", command, stderr(&output));
+        assert!(stderr(&output).contains("cannot be used with"), "{}", stderr(&output));
+    }
+    assert!(!tmp.join("out").exists());
+}
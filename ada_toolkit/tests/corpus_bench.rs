@@ -0,0 +1,93 @@
+//! `corpus-bench` end to end over the mini-corpus in tests/fixtures, and
+//! the checks that keep a partial copy of a standard corpus from passing
+//! for it.
+
+mod common;
+
+use std::path::PathBuf;
+
+use common::{run, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+fn mini_corpus() -> String {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mini-corpus").to_string_lossy().into_owned()
+}
+
+/// The Canterbury corpus's names and published sizes.
+const CANTERBURY: [(&str, usize); 11] = [
+    ("alice29.txt", 152_089), ("asyoulik.txt", 125_179), ("cp.html", 24_603), ("fields.c", 11_150),
+    ("grammar.lsp", 3_721), ("kennedy.xls", 1_029_744), ("lcet10.txt", 426_754), ("plrabn12.txt", 481_861),
+    ("ptt5", 513_216), ("sum", 38_240), ("xargs.1", 4_227),
+];
+
+#[test]
+fn every_file_is_round_tripped_and_the_ratios_weighed() {
+    let tmp = TempDir::new();
+    let output = run_ok(tmp.path(), &["--format", "json", "corpus-bench", &mini_corpus()]);
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report["operation"], "corpus-bench");
+    assert_eq!(report["corpus"], "dir");
+    let files = report["files"].as_array().unwrap();
+    let names: Vec<&str> = files.iter().map(|file| file["input"].as_str().unwrap().rsplit('/').next().unwrap())
+        .collect();
+    assert_eq!(names, ["bernoulli.c", "prose.txt", "runs.dat", "table.csv"]);
+    assert!(files.iter().all(|file| file["status"] == "ok"));
+
+    let bytes = |field: &str| files.iter().map(|file| file[field].as_u64().unwrap()).sum::<u64>();
+    assert_eq!(report["summary"]["input_bytes"].as_u64(), Some(bytes("input_bytes")));
+    let weighted = bytes("compressed_bytes") as f64 / bytes("input_bytes") as f64;
+    let mean = files.iter().map(|file| file["ratio"].as_f64().unwrap()).sum::<f64>() / 4.0;
+    assert!((report["weighted_ratio"].as_f64().unwrap() - weighted).abs() < 1e-9, "{}", report["weighted_ratio"]);
+    assert!((report["mean_ratio"].as_f64().unwrap() - mean).abs() < 1e-9, "{}", report["mean_ratio"]);
+    assert!((report["bits_per_byte"].as_f64().unwrap() - weighted * 8.0).abs() < 1e-9);
+    assert!(weighted < mean, "runs.dat, the biggest file, compresses best");
+    assert_eq!(report["failures"], serde_json::json!([]));
+}
+
+#[test]
+fn the_text_gives_a_line_per_file_and_the_corpus_ratios() {
+    let tmp = TempDir::new();
+    let text = stdout(&run_ok(tmp.path(), &["--bytes", "corpus-bench", &mini_corpus()]));
+    assert!(text.starts_with("bernoulli.c: 325 bytes to "), "{}", text);
+    assert!(text.contains("\nruns.dat: 4016 bytes to "), "{}", text);
+    assert!(text.contains("Summary: 4 files tested, 0 failed"), "{}", text);
+    assert!(text.contains("directory corpus: weighted average ratio 0."), "{}", text);
+    assert!(text.contains(" bits per byte), mean per-file ratio 0."), "{}", text);
+}
+
+#[test]
+fn a_standard_corpus_must_be_complete_and_unaltered() {
+    let tmp = TempDir::new();
+    for (name, size) in CANTERBURY {
+        tmp.write(&format!("cantrbry/{}", name), vec![b'a'; size]);
+    }
+    tmp.write("cantrbry/README", "not part of the corpus");
+    let output = run_ok(tmp.path(), &["--format", "json", "corpus-bench", "--corpus", "canterbury", "cantrbry"]);
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report["corpus"], "canterbury");
+    assert_eq!(report["files"].as_array().unwrap().len(), 11, "README was benchmarked");
+    assert_eq!(report["summary"]["input_bytes"], 2_810_784);
+
+    tmp.write("cantrbry/sum", "cut short");
+    std::fs::remove_file(tmp.join("cantrbry/ptt5")).unwrap();
+    let output = run(tmp.path(), &["corpus-bench", "--corpus", "canterbury", "cantrbry"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("cantrbry does not hold the Canterbury corpus as published: \
+                                      ptt5 is missing; sum is 9 bytes, not 38240"), "{}", stderr(&output));
+    assert!(stdout(&output).is_empty(), "{}", stdout(&output));
+
+    let output = run(tmp.path(), &["corpus-bench", "--corpus", "silesia", &mini_corpus()]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("dickens is missing"), "{}", stderr(&output));
+}
+
+#[test]
+fn a_missing_or_empty_directory_is_an_error() {
+    let tmp = TempDir::new();
+    let output = run(tmp.path(), &["corpus-bench", "nowhere"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("'nowhere' does not exist or is not a directory"), "{}", stderr(&output));
+    std::fs::create_dir(tmp.join("empty")).unwrap();
+    let output = run(tmp.path(), &["corpus-bench", "empty"]);
+    assert!(stderr(&output).contains("no files found in 'empty'"), "{}", stderr(&output));
+}
@@ -0,0 +1,64 @@
+//! `crash-test`: damaged copies of a frame are rejected, never decoded
+//! wrongly or panicked on, and the counts can be repeated from the seed.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+/// The rows of the text report, without the heading lines.
+fn table(report: &str) -> Vec<Vec<String>> {
+    report.lines()
+        .skip_while(|line| !line.starts_with("Mutation"))
+        .skip(1)
+        .map(|line| line.split_whitespace().map(str::to_string).collect())
+        .collect()
+}
+
+#[test]
+fn every_damaged_copy_is_accounted_for() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(50_000));
+    let report = stdout(&run_ok(tmp.path(), &["crash-test", "in.bin", "--mutations", "200", "--seed", "42"]));
+    assert!(report.starts_with("Trying 200 damaged copies of in.bin ("), "{}", report);
+    assert!(report.contains("; repeat with --seed 42)"), "{}", report);
+    let rows = table(&report);
+    let kinds: Vec<&str> = rows.iter().map(|row| row[0].as_str()).collect();
+    assert_eq!(kinds, ["bit-flip", "truncate", "insert"]);
+    let mut tried = 0;
+    for row in &rows {
+        let counts: Vec<u32> = row[1..].iter().map(|count| count.parse().unwrap()).collect();
+        let [kind_tried, rejected, unchanged, undetected, panicked] = counts[..] else { panic!("{:?}", row) };
+        assert_eq!(rejected + unchanged, kind_tried, "{:?}", row);
+        assert_eq!((undetected, panicked), (0, 0), "{:?}", row);
+        assert!(kind_tried > 30, "{:?}", row);
+        tried += kind_tried;
+    }
+    assert_eq!(tried, 200);
+}
+
+#[test]
+fn the_seed_repeats_a_run() {
+    let tmp = TempDir::new();
+    let args = ["--format", "json", "crash-test", "--generated", "--size", "20k", "--mutations", "100", "--seed", "7"];
+    let first = stdout(&run_ok(tmp.path(), &args));
+    assert_eq!(stdout(&run_ok(tmp.path(), &args)), first);
+    let start = "{\"operation\":\"crash-test\",\"input\":null,\"seed\":7,\"mutations\":100,";
+    assert!(first.starts_with(start), "{}", first);
+    assert!(first.contains("\"status\":\"ok\""), "{}", first);
+    for kind in ["bit-flip", "truncate", "insert"] {
+        assert!(first.contains(&format!("{{\"mutation\":\"{}\",\"tried\":", kind)), "{}", first);
+    }
+    assert_eq!(first.matches("\"undetected\":0,\"panicked\":0}").count(), 3, "{}", first);
+
+    let other = stdout(&run_ok(tmp.path(), &["--format", "json", "crash-test", "--generated", "--size", "20k",
+                                             "--mutations", "100", "--seed", "8"]));
+    assert_ne!(other, first);
+}
+
+#[test]
+fn a_missing_input_is_an_io_error() {
+    let tmp = TempDir::new();
+    let output = run(tmp.path(), &["crash-test", "missing.bin", "--mutations", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("missing.bin"), "{}", stderr(&output));
+}
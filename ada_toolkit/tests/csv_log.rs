@@ -0,0 +1,77 @@
+//! `test-folder --log-format csv` writes a row per file that a CSV reader
+//! takes back field for field.
+
+mod common;
+
+use common::{mixed_data, run_ok, TempDir};
+
+const HEADER: [&str; 11] = [
+    "timestamp", "file", "original_size", "compressed_size", "ratio", "compress_ms", "compress_mbps", "decompress_ms",
+    "decompress_mbps", "status", "folder",
+];
+
+/// Splits RFC 4180 CSV into records: fields may be quoted, with `""` for
+/// a quote and commas or line breaks inside.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let (mut records, mut record, mut field) = (Vec::new(), Vec::new(), String::new());
+    let (mut quoted, mut chars) = (false, text.chars().peekable());
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, '\r') => {}
+            (false, c) => field.push(c),
+        }
+    }
+    assert!(!quoted && field.is_empty() && record.is_empty(), "unterminated last record");
+    records
+}
+
+#[test]
+fn rows_match_the_files_tested() {
+    let tmp = TempDir::new();
+    let names = ["plain.bin", "with,comma.txt", "with \"quotes\".txt"];
+    let sizes = [100_000, 5, 0];
+    for (name, size) in names.iter().zip(sizes) {
+        tmp.write(&format!("data/{}", name), mixed_data(size));
+    }
+    run_ok(tmp.path(), &["test-folder", "data", "--log-format", "csv", "--log-file", "run.csv"]);
+    assert!(!tmp.join("test_log.txt").exists(), "the default log was written too");
+    let records = parse_csv(&std::fs::read_to_string(tmp.join("run.csv")).unwrap());
+    assert_eq!(records[0], HEADER);
+    let rows: Vec<&Vec<String>> = records[1..].iter().filter(|row| row[9] == "ok").collect();
+    assert_eq!(rows.len(), names.len());
+    for (name, size) in names.iter().zip(sizes) {
+        let row = rows.iter().find(|row| row[1] == *name).unwrap_or_else(|| panic!("no row for {}", name));
+        assert_eq!(row.len(), HEADER.len(), "{:?}", row);
+        assert!(row[0].parse::<u64>().is_ok(), "timestamp {:?}", row[0]);
+        assert_eq!(row[2], size.to_string());
+        let compressed: u64 = row[3].parse().unwrap();
+        let ratio: f64 = row[4].parse().unwrap();
+        if size > 0 {
+            assert!((ratio - compressed as f64 / size as f64).abs() < 1e-4, "{:?}", row);
+        }
+        for column in 5..=8 {
+            assert!(row[column].parse::<f64>().is_ok_and(|value| value >= 0.0), "{}: {:?}", HEADER[column], row);
+        }
+        assert_eq!(row[10], "data");
+    }
+    let summary = records.iter().find(|row| row[9] == "summary").expect("a summary row");
+    assert_eq!(summary[2], sizes.iter().sum::<usize>().to_string());
+}
+
+#[test]
+fn the_reader_handles_quoting() {
+    let records = parse_csv("a,\"b,c\",\"say \"\"hi\"\"\"\n\"two\nlines\",,x\n");
+    assert_eq!(records, [vec!["a", "b,c", "say \"hi\""], vec!["two\nlines", "", "x"]]);
+}
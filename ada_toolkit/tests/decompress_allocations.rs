@@ -0,0 +1,91 @@
+//! `decompress` sizes its output from the block headers and allocates it
+//! once, and `decompress_into` reuses a buffer that is already big enough.
+//!
+//! This file is its own test binary so that the counting allocator sees
+//! only what the one test in it allocates.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ada_toolkit::{compress_with_options, decompress, decompress_into, CompressOptions};
+
+struct Counting;
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+impl Counting {
+    fn grew(&self, by: usize) {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        let live = LIVE.fetch_add(by, Ordering::Relaxed) + by;
+        PEAK.fetch_max(live, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.grew(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.grew(new_size);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Allocations and reallocations made by `f`, and the most bytes it had
+/// allocated at once beyond what was live before it.
+fn allocations_of<T>(f: impl FnOnce() -> T) -> (T, usize, usize) {
+    let (calls, before) = (CALLS.load(Ordering::Relaxed), LIVE.load(Ordering::Relaxed));
+    PEAK.store(before, Ordering::Relaxed);
+    let result = f();
+    (result, CALLS.load(Ordering::Relaxed) - calls, PEAK.load(Ordering::Relaxed) - before)
+}
+
+const SIZE: usize = 4 << 20;
+
+/// A sparse image: 4 MiB of zeros with a few bytes set in each MiB, so
+/// that the frame is some 80 times smaller than its content.
+fn sparse_frame() -> (Vec<u8>, Vec<u8>) {
+    let mut image = vec![0u8; SIZE];
+    for (i, chunk) in image.chunks_mut(1 << 20).enumerate() {
+        chunk[..64].iter_mut().enumerate().for_each(|(j, byte)| *byte = (i * 64 + j) as u8 | 1);
+    }
+    let opts = CompressOptions { block_size: 64 * 1024, ..CompressOptions::default() };
+    let frame = compress_with_options(&image, &opts).unwrap();
+    assert!(frame.len() * 50 < SIZE, "{} bytes of frame", frame.len());
+    (frame, image)
+}
+
+#[test]
+fn the_output_is_allocated_once() {
+    let (frame, image) = sparse_frame();
+
+    let (decoded, calls, peak) = allocations_of(|| decompress(&frame).unwrap());
+    assert!(decoded == image);
+    assert_eq!(calls, 1, "decompress allocated {} times", calls);
+    assert_eq!(peak, SIZE);
+
+    let mut reused = Vec::with_capacity(SIZE);
+    let (len, calls, _) = allocations_of(|| decompress_into(&frame, &mut reused).unwrap());
+    assert_eq!((len, calls), (SIZE, 0));
+    assert!(reused == image);
+    let (_, calls, _) = allocations_of(|| decompress_into(&frame, &mut reused).unwrap());
+    assert_eq!(calls, 0, "a second decode into the same buffer allocated");
+
+    let mut small = Vec::with_capacity(1024);
+    let (_, calls, _) = allocations_of(|| decompress_into(&frame, &mut small).unwrap());
+    assert_eq!(calls, 1, "a buffer too small grew {} times", calls);
+    assert!(small == image);
+}
@@ -0,0 +1,99 @@
+//! `decompress` with a directory input: every .aapc file in it restored
+//! into the output directory under its original name, and anything else
+//! counted and left alone.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+/// backups/ made by compress-dir from src/, plus a junk file, a frame
+/// renamed so only its header knows the name (too big for a small frame,
+/// which has no room for one), and a damaged .aapc file.
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("src/a.txt", "alpha\n");
+    tmp.write("src/sub/b.log", mixed_data(30_000));
+    tmp.write("src/sub/deeper/c.bin", mixed_data(5_000));
+    run_ok(tmp.path(), &["compress-dir", "src", "backups"]);
+    tmp.write("named.csv", named_csv());
+    run_ok(tmp.path(), &["compress", "named.csv", "-o", "backups/sub/renamed.aapc"]);
+    tmp.write("backups/junk.txt", "not compressed");
+    tmp.write("backups/bad.aapc", "AAPC broken");
+    tmp
+}
+
+fn named_csv() -> String {
+    format!("x,y\n{}", "1,2\n".repeat(1100))
+}
+
+/// Every file under `dir`, relative to it.
+fn files(dir: &Path) -> Vec<String> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        match path.is_dir() {
+            true => found.extend(files(&path).into_iter().map(|file| format!("{}/{}", name, file))),
+            false => found.push(name),
+        }
+    }
+    found.sort();
+    found
+}
+
+#[test]
+fn the_tree_is_restored_with_recursive() {
+    let tmp = setup();
+    let output = run(tmp.path(), &["decompress", "backups", "restored", "--recursive"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(files(&tmp.join("restored")), ["a.txt", "sub/b.log", "sub/deeper/c.bin", "sub/named.csv"]);
+    for file in ["a.txt", "sub/b.log", "sub/deeper/c.bin"] {
+        assert_eq!(fs::read(tmp.join("restored").join(file)).unwrap(), fs::read(tmp.join("src").join(file)).unwrap());
+    }
+    assert_eq!(fs::read_to_string(tmp.join("restored/sub/named.csv")).unwrap(), named_csv());
+
+    let text = stdout(&output);
+    assert!(text.contains("Decompressed 4 files from backups to restored: "), "{}", text);
+    assert!(text.contains(". 1 failed, 0 skipped, 1 not .aapc files\nFailed:\n  decompressing backups/bad.aapc: "),
+            "{}", text);
+    assert!(stderr(&output).contains("1 files under backups failed"), "{}", stderr(&output));
+}
+
+#[test]
+fn without_recursive_only_the_top_level_is_restored() {
+    let tmp = setup();
+    fs::remove_file(tmp.join("backups/bad.aapc")).unwrap();
+    let output = run_ok(tmp.path(), &["--format", "json", "decompress", "backups", "-o", "top"]);
+    assert_eq!(files(&tmp.join("top")), ["a.txt"]);
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!((report["operation"].as_str(), report["decompressed"].as_u64(), report["not_aapc"].as_u64()),
+               (Some("decompress-dir"), Some(1), Some(1)));
+    assert_eq!(report["failures"], serde_json::json!([]));
+}
+
+#[test]
+fn fail_fast_stops_at_the_damaged_file() {
+    let tmp = setup();
+    let output = run(tmp.path(), &["--format", "json", "decompress", "backups", "out", "-r", "--fail-fast"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!((report["decompressed"].as_u64(), report["failed"].as_u64()), (Some(1), Some(1)));
+    assert_eq!(files(&tmp.join("out")), ["a.txt"]);
+}
+
+#[test]
+fn with_no_output_directory_files_are_restored_in_place() {
+    let tmp = setup();
+    fs::remove_file(tmp.join("backups/bad.aapc")).unwrap();
+    run_ok(tmp.path(), &["decompress", "backups", "-r"]);
+    assert_eq!(fs::read_to_string(tmp.join("backups/a.txt")).unwrap(), "alpha\n");
+    assert!(tmp.join("backups/a.txt.aapc").is_file(), "the input was removed without --rm");
+    assert!(tmp.join("backups/sub/deeper/c.bin").is_file());
+
+    let output = run(tmp.path(), &["decompress", "backups", "-c"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
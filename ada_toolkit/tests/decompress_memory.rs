@@ -0,0 +1,109 @@
+//! `decompress_to_writer` hands each block to the writer as it is decoded,
+//! so memory stays at about a block however big the content is.
+//!
+//! This file is its own test binary so that the allocator, which refuses
+//! anything past the cap while armed, sees only what the one test in it
+//! allocates.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ada_toolkit::{compress_with_options, decompress_to_writer, CompressOptions, DecompressError};
+
+struct Capped;
+
+/// Most bytes the test may allocate beyond what was live when armed.
+const CAP: usize = 64 * 1024;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Capped {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        if ARMED.load(Ordering::Relaxed) && live > BASE.load(Ordering::Relaxed) + CAP {
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Capped = Capped;
+
+/// Runs `f` with the cap in force.
+fn capped<T>(f: impl FnOnce() -> T) -> T {
+    BASE.store(LIVE.load(Ordering::Relaxed), Ordering::Relaxed);
+    ARMED.store(true, Ordering::Relaxed);
+    let result = f();
+    ARMED.store(false, Ordering::Relaxed);
+    result
+}
+
+const BLOCK: usize = 4096;
+const BLOCKS: u32 = 8192;
+
+/// A frame of `BLOCKS` 4 KiB blocks, each checksummed: 32 MiB of content,
+/// 512 times the cap, put together from a one-block frame, since encoding
+/// all of it would take most of the test's time.
+fn big_frame() -> Vec<u8> {
+    let data: Vec<u8> = (0..BLOCK).map(|i| if i % 1000 < 600 { 0 } else { (i % 251) as u8 }).collect();
+    let opts = CompressOptions { block_size: BLOCK, content_checksum: false, ..CompressOptions::default() };
+    let one = compress_with_options(&data, &opts).unwrap();
+    let (header, block) = (&one[..10], &one[10..one.len() - 13]);
+    let mut frame = header.to_vec();
+    for _ in 0..BLOCKS {
+        frame.extend_from_slice(block);
+    }
+    frame.push(0);
+    frame.extend_from_slice(&BLOCKS.to_be_bytes());
+    frame.extend_from_slice(&(u64::from(BLOCKS) * BLOCK as u64).to_be_bytes());
+    frame
+}
+
+/// Counts what is written and checks every block is the one repeated.
+struct Counting {
+    written: u64,
+    first: Vec<u8>,
+}
+
+impl Write for Counting {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        assert_eq!(buf.len(), BLOCK, "written in pieces other than blocks");
+        if self.first.is_empty() {
+            self.first.extend_from_slice(buf);
+        }
+        assert!(buf == self.first.as_slice(), "block at byte {} differs", self.written);
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn a_big_frame_decodes_within_the_cap() {
+    let frame = big_frame();
+    let mut out = Counting { written: 0, first: Vec::with_capacity(BLOCK) };
+    let written = capped(|| decompress_to_writer(&frame, &mut out)).unwrap();
+    assert_eq!((written, out.written), (u64::from(BLOCKS) * BLOCK as u64, written));
+
+    // The block whose checksum fails is not written, nor any after it.
+    let mut corrupt = frame;
+    let block_len = (corrupt.len() - 23) / BLOCKS as usize;
+    corrupt[10 + 100 * block_len + block_len / 2] ^= 1;
+    let mut out = Counting { written: 0, first: Vec::with_capacity(BLOCK) };
+    let result = capped(|| decompress_to_writer(&corrupt, &mut out));
+    assert!(matches!(result, Err(DecompressError::ChecksumMismatch { block: 100, .. })), "{:?}", result);
+    assert_eq!(out.written, 100 * BLOCK as u64);
+}
@@ -0,0 +1,70 @@
+//! `decompress --discard`: every block decoded and checked, its size and
+//! speed reported, and nothing written.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(300_000));
+    run_ok(tmp.path(), &["compress", "in.bin", "--block-size", "16k"]);
+    fs::remove_file(tmp.join("in.bin")).unwrap();
+    tmp
+}
+
+fn json(tmp: &TempDir, args: &[&str]) -> Value {
+    serde_json::from_str(&stdout(&run_ok(tmp.path(), &[&["--format", "json"][..], args].concat()))).unwrap()
+}
+
+#[test]
+fn the_stored_size_is_reported_and_no_file_is_made() {
+    let tmp = setup();
+    let report = json(&tmp, &["decompress", "in.bin.aapc", "--discard"]);
+    let inspect = json(&tmp, &["inspect", "in.bin.aapc"]);
+    assert_eq!(report["files"][0]["output_bytes"], inspect["totals"]["raw_bytes"]);
+    assert_eq!(report["files"][0]["output_bytes"], 300_000);
+    assert_eq!(report["files"][0]["output"], Value::Null);
+    assert!(!tmp.join("in.bin").exists());
+    assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+
+    let text = stdout(&run_ok(tmp.path(), &["--bytes", "decompress", "in.bin.aapc", "--discard"]));
+    assert!(text.starts_with("Decompressed in.bin.aapc ("), "{}", text);
+    assert!(text.contains(") to 300000 bytes, discarded, in "), "{}", text);
+    assert!(!tmp.join("in.bin").exists());
+}
+
+#[test]
+fn checksums_are_still_verified() {
+    let tmp = TempDir::new();
+    // Bytes RLE cannot shrink, so the blocks are stored and a flipped bit
+    // is caught by the checksum rather than by the decoder.
+    let mut state = 1u32;
+    let noise: Vec<u8> = (0..100_000).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (state >> 16) as u8
+    }).collect();
+    tmp.write("noise.bin", noise);
+    run_ok(tmp.path(), &["compress", "noise.bin", "-o", "good.aapc", "--block-size", "16k"]);
+    let mut frame = fs::read(tmp.join("good.aapc")).unwrap();
+    let middle = frame.len() / 2;
+    frame[middle] ^= 0x01;
+    tmp.write("bad.aapc", frame);
+    let output = run(tmp.path(), &["decompress", "bad.aapc", "--discard"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    assert!(stderr(&output).contains("checksum mismatch in block "), "{}", stderr(&output));
+    assert!(!tmp.join("bad").exists());
+}
+
+#[test]
+fn an_output_path_is_a_usage_conflict() {
+    let tmp = setup();
+    for extra in [&["-o", "out.bin"][..], &["-c"], &["--output-dir", "out"]] {
+        let output = run(tmp.path(), &[&["decompress", "in.bin.aapc", "--discard"][..], extra].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", extra, stderr(&output));
+    }
+    assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,106 @@
+//! --dry-run: the real encoder runs and its sizes are reported, but no
+//! file is created or touched.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{mixed_data, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+/// Every path under `dir` with its size and modification time.
+fn snapshot(dir: &Path) -> Vec<(String, u64, std::time::SystemTime)> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let meta = entry.metadata().unwrap();
+        if meta.is_dir() {
+            entries.extend(snapshot(&entry.path()));
+        }
+        entries.push((entry.path().to_string_lossy().into_owned(), meta.len(), meta.modified().unwrap()));
+    }
+    entries.sort();
+    entries
+}
+
+fn json(args: &[&str], tmp: &TempDir) -> Value {
+    let mut all = vec!["--format", "json"];
+    all.extend_from_slice(args);
+    serde_json::from_str(&stdout(&run_ok(tmp.path(), &all))).unwrap()
+}
+
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(300_000));
+    tmp.write("dir/a.bin", mixed_data(40_000));
+    tmp.write("dir/sub/b.log", mixed_data(9_000));
+    tmp.write("dir/c.tmp", "left out by --exclude");
+    tmp.write("jobs.txt", "in.bin\tout/in.aapc\ndir/a.bin\ta.aapc\n");
+    tmp
+}
+
+#[test]
+fn compress_reports_the_size_a_real_run_writes() {
+    let tmp = setup();
+    let before = snapshot(tmp.path());
+    for block_size in ["256k", "16k"] {
+        let dry = json(&["compress", "in.bin", "--block-size", block_size, "--dry-run"], &tmp);
+        assert_eq!(snapshot(tmp.path()), before, "--dry-run touched the tree");
+        assert_eq!(dry["dry_run"], true);
+
+        let real = json(&["compress", "in.bin", "--block-size", block_size, "-f"], &tmp);
+        let written = fs::metadata(tmp.join("in.bin.aapc")).unwrap().len();
+        assert_eq!(dry["files"][0]["output_bytes"], written, "--block-size {}", block_size);
+        assert_eq!(dry["files"][0]["checksum"], real["files"][0]["checksum"]);
+        fs::remove_file(tmp.join("in.bin.aapc")).unwrap();
+    }
+
+    // With the frame meant for stdout, the report goes to stderr and
+    // nothing to stdout.
+    let output = run_ok(tmp.path(), &["--bytes", "compress", "in.bin", "--dry-run", "-c"]);
+    assert!(output.stdout.is_empty(), "wrote {} bytes to stdout", output.stdout.len());
+    assert!(stderr(&output).starts_with("Would compress in.bin (300000 bytes) to - ("), "{}", stderr(&output));
+}
+
+#[test]
+fn compress_dir_reports_a_real_run_without_making_dst() {
+    let tmp = setup();
+    let before = snapshot(tmp.path());
+    let args = ["compress-dir", "dir", "out", "--exclude", "*.tmp"];
+    let dry = json(&[&args[..], &["--dry-run"]].concat(), &tmp);
+    assert_eq!(snapshot(tmp.path()), before);
+    assert!(!tmp.join("out").exists());
+
+    let real = json(&args, &tmp);
+    for field in ["compressed", "filtered", "input_bytes", "output_bytes"] {
+        assert_eq!(dry[field], real[field], "{}", field);
+    }
+    assert_eq!(dry["filtered"], 1);
+    let written = ["out/a.bin.aapc", "out/sub/b.log.aapc"].map(|path| fs::metadata(tmp.join(path)).unwrap().len());
+    assert_eq!(dry["output_bytes"], written.iter().sum::<u64>());
+
+    let text = stdout(&run_ok(tmp.path(), &[&args[..], &["--dry-run", "-f"]].concat()));
+    assert!(text.starts_with("Dry run, nothing written: "), "{}", text);
+}
+
+#[test]
+fn batch_reports_each_job_without_writing_it() {
+    let tmp = setup();
+    let before = snapshot(tmp.path());
+    let dry = json(&["batch", "--manifest", "jobs.txt", "--parents", "--dry-run"], &tmp);
+    assert_eq!(snapshot(tmp.path()), before);
+    assert!(!tmp.join("out").exists());
+    assert_eq!(dry["dry_run"], true);
+
+    run_ok(tmp.path(), &["batch", "--manifest", "jobs.txt", "--parents"]);
+    let sizes: Vec<u64> = dry["files"].as_array().unwrap().iter()
+        .map(|file| file["output_bytes"].as_u64().unwrap())
+        .collect();
+    let written = ["out/in.aapc", "a.aapc"].map(|path| fs::metadata(tmp.join(path)).unwrap().len());
+    assert_eq!(sizes, written);
+
+    let text = stdout(&run_ok(tmp.path(), &["batch", "--manifest", "jobs.txt", "--dry-run", "-f"]));
+    assert!(text.contains("Would compress "), "{}", text);
+    assert!(text.contains("Dry run, nothing written: 2 of 2 jobs done"), "{}", text);
+}
@@ -0,0 +1,118 @@
+//! Each kind of failure exits with its own code, and says what went wrong.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+/// A compressed copy of `data`, as `in.bin.aapc`.
+fn compressed(data: Vec<u8>) -> (TempDir, Vec<u8>) {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", data);
+    run_ok(tmp.path(), &["compress", "in.bin"]);
+    let frame = std::fs::read(tmp.join("in.bin.aapc")).unwrap();
+    (tmp, frame)
+}
+
+fn assert_exit(tmp: &TempDir, args: &[&str], code: i32, message: &str) {
+    let output = run(tmp.path(), args);
+    assert_eq!(output.status.code(), Some(code), "{:?}: {}", args, stderr(&output));
+    assert!(stderr(&output).contains(message), "{:?}: {}", args, stderr(&output));
+}
+
+#[test]
+fn missing_input_is_an_io_error() {
+    let tmp = TempDir::new();
+    assert_exit(&tmp, &["decompress", "nope.aapc"], 1, "nope.aapc");
+}
+
+#[test]
+fn unknown_flag_is_a_usage_error() {
+    let tmp = TempDir::new();
+    assert_exit(&tmp, &["decompress", "--no-such-flag"], 2, "--no-such-flag");
+}
+
+#[test]
+fn bad_magic_is_corrupt() {
+    let tmp = TempDir::new();
+    tmp.write("junk.aapc", "this is not a frame");
+    assert_exit(&tmp, &["decompress", "junk.aapc"], 3, "bad magic");
+}
+
+#[test]
+fn truncated_frame_is_corrupt() {
+    let (tmp, frame) = compressed(mixed_data(200_000));
+    tmp.write("cut.aapc", &frame[..frame.len() - 5]);
+    assert_exit(&tmp, &["decompress", "cut.aapc"], 3, "truncated");
+}
+
+#[test]
+fn flipped_payload_byte_is_a_checksum_failure() {
+    // Noise is stored as is, so the flipped byte decodes and only the
+    // checksum catches it.
+    let mut state = 0x9E37_79B9u32;
+    let noise = (0..200_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let (tmp, mut frame) = compressed(noise);
+    let middle = frame.len() / 2;
+    frame[middle] ^= 0x40;
+    tmp.write("bad.aapc", &frame);
+    assert_exit(&tmp, &["decompress", "bad.aapc"], 4, "checksum mismatch");
+    assert!(!tmp.join("bad").exists(), "a partial output was left behind");
+}
+
+#[test]
+fn a_missing_test_folder_is_an_io_error() {
+    let tmp = TempDir::new();
+    assert_exit(&tmp, &["test-folder", "nope"], 1, "'nope' folder does not exist");
+    assert_exit(&tmp, &["test-folder"], 1, "'test_data' folder does not exist");
+}
+
+#[test]
+fn quiet_prints_nothing_but_errors() {
+    let (tmp, _) = compressed(mixed_data(100_000));
+    for args in [&["--quiet", "compress", "-f", "in.bin"][..], &["-q", "decompress", "-f", "in.bin.aapc"],
+                 &["-q", "verify", "in.bin.aapc"]] {
+        let output = run_ok(tmp.path(), args);
+        assert!(output.stdout.is_empty(), "{:?}: {}", args, stdout(&output));
+        assert_eq!(stderr(&output), "", "{:?}", args);
+    }
+    assert_exit(&tmp, &["-q", "decompress", "nope.aapc"], 1, "nope.aapc");
+    assert_exit(&tmp, &["--quiet", "--verbose", "info", "in.bin.aapc"], 2, "cannot be used with");
+}
+
+#[test]
+fn quiet_silences_every_reporting_command() {
+    let (tmp, frame) = compressed(mixed_data(100_000));
+    tmp.write("tree/a.txt", "a");
+    tmp.write("copy.aapc", &frame);
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+    let commands: [&[&str]; 7] = [
+        &["info", "in.bin.aapc"],
+        &["inspect", "in.bin.aapc"],
+        &["estimate", "in.bin"],
+        &["archive", "list", "t.aapa"],
+        &["archive", "list", "--long", "t.aapa"],
+        &["verify", "in.bin.aapc"],
+        &["compare", "in.bin.aapc", "copy.aapc"],
+    ];
+    for command in commands {
+        assert!(!run_ok(tmp.path(), command).stdout.is_empty(), "{:?} printed nothing to begin with", command);
+        for format in ["text", "json"] {
+            let args: Vec<&str> = ["-q", "--format", format].iter().chain(command).copied().collect();
+            let output = run_ok(tmp.path(), &args);
+            assert!(output.stdout.is_empty(), "{:?}: {}", args, stdout(&output));
+            assert_eq!(stderr(&output), "", "{:?}", args);
+        }
+    }
+    // What goes wrong is still said.
+    tmp.write("cut.aapc", &frame[..frame.len() / 2]);
+    let output = run(tmp.path(), &["-q", "info", "cut.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(output.stdout.is_empty() && stderr(&output).contains("cut.aapc"), "{}", stderr(&output));
+}
@@ -0,0 +1,83 @@
+//! `--include`, `--exclude` and `--skip-hidden` on test-folder,
+//! compress-dir and archive create.
+
+mod common;
+
+use common::{run_ok, stderr, stdout, TempDir};
+
+/// Eight files, two of them hidden, with `tmp` and `logs` subdirectories.
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    for file in ["a.log", "B.LOG", "c.gz.log", "z.gz", "tmp/x.log", "tmp/deep/y.log", "logs/old.log", ".hidden.log",
+                 ".git/config"] {
+        tmp.write(&format!("t/{}", file), file);
+    }
+    tmp
+}
+
+fn both(output: &std::process::Output) -> String {
+    format!("{}{}", stdout(output), stderr(output))
+}
+
+#[test]
+fn test_folder_applies_every_filter() {
+    let tmp = tree();
+    let output = run_ok(tmp.path(), &["test-folder", "--recursive", "--include", "*.log", "--exclude", "*.gz*",
+                                      "--exclude", "tmp/**", "--skip-hidden", "t"]);
+    let text = both(&output);
+    let tested: Vec<&str> = text.lines().filter(|line| line.starts_with("Tested ")).collect();
+    assert_eq!(tested, ["Tested a.log successfully.", "Tested logs/old.log successfully."]);
+    // B.LOG and z.gz miss the include, c.gz.log meets an exclude, tmp is
+    // pruned whole and .hidden.log and .git are hidden.
+    assert!(text.contains("2 files tested, 0 failed, 6 filtered out, 0 skipped"), "{}", text);
+}
+
+#[test]
+fn compress_dir_mirrors_only_the_kept_files() {
+    let tmp = tree();
+    let output = run_ok(tmp.path(), &["compress-dir", "t", "out", "--include", "*.log", "--exclude", "tmp/**",
+                                      "--skip-hidden"]);
+    assert!(both(&output).contains("Compressed 3 files from t to out"), "{}", both(&output));
+    assert!(both(&output).contains("0 failed, 0 skipped, 5 filtered out"), "{}", both(&output));
+    for kept in ["a.log", "c.gz.log", "logs/old.log"] {
+        assert!(tmp.join(&format!("out/{}.aapc", kept)).is_file(), "{} missing", kept);
+    }
+    for left_out in ["B.LOG.aapc", "z.gz.aapc", "tmp", ".hidden.log.aapc", ".git"] {
+        assert!(!tmp.join(&format!("out/{}", left_out)).exists(), "{} was written", left_out);
+    }
+
+    let json = stdout(&run_ok(tmp.path(), &["--format", "json", "compress-dir", "t", "out2", "--exclude", "tmp"]));
+    assert!(json.contains("\"compressed\":7,") && json.contains("\"filtered\":1,"), "{}", json);
+}
+
+#[test]
+fn archive_create_stores_only_the_kept_files() {
+    let tmp = tree();
+    let output = run_ok(tmp.path(), &["archive", "create", "a.aapa", "t", "--include", "*.log", "--exclude", "logs",
+                                      "--skip-hidden"]);
+    assert!(both(&output).contains("Archived 4 files, 3 directories and 0 symlinks"), "{}", both(&output));
+    assert!(both(&output).contains("; 5 filtered out"), "{}", both(&output));
+    let listing = stdout(&run_ok(tmp.path(), &["archive", "list", "a.aapa"]));
+    let paths: Vec<&str> = listing.lines().skip(1).filter_map(|line| line.split_whitespace().last()).collect();
+    assert_eq!(paths, ["t/", "t/tmp/", "t/tmp/deep/", "t/a.log", "t/c.gz.log", "t/tmp/deep/y.log", "t/tmp/x.log"]);
+}
+
+#[test]
+fn patterns_match_case_sensitively() {
+    let tmp = tree();
+    let text = both(&run_ok(tmp.path(), &["test-folder", "--include", "*.LOG", "t"]));
+    assert!(text.contains("Tested B.LOG successfully."), "{}", text);
+    assert!(text.contains("1 files tested"), "{}", text);
+}
+
+#[cfg(unix)]
+#[test]
+fn an_excluded_directory_is_never_entered() {
+    let tmp = tree();
+    std::os::unix::fs::symlink("nowhere", tmp.join("t/tmp/dangling")).unwrap();
+    let walked = both(&run_ok(tmp.path(), &["test-folder", "--recursive", "t"]));
+    assert!(walked.contains("Skipping symlink t/tmp/dangling"), "{}", walked);
+    let pruned = both(&run_ok(tmp.path(), &["test-folder", "--recursive", "--exclude", "tmp", "t"]));
+    assert!(!pruned.contains("dangling"), "{}", pruned);
+    assert!(pruned.contains("7 files tested, 0 failed, 1 filtered out"), "{}", pruned);
+}
@@ -0,0 +1,14 @@
+/* Bernoulli numbers by the Akiyama-Tanigawa algorithm. */
+#include <stdio.h>
+
+int main(void)
+{
+    double a[16];
+    for (int m = 0; m < 16; m++) {
+        a[m] = 1.0 / (m + 1);
+        for (int j = m; j >= 1; j--)
+            a[j - 1] = j * (a[j - 1] - a[j]);
+        printf("B(%d) = %f\n", m, a[0]);
+    }
+    return 0;
+}
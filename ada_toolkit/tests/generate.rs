@@ -0,0 +1,29 @@
+//! The generated corpora, included from the binary's source as the
+//! benchmarks do.
+
+#[path = "../src/generate.rs"]
+mod generate;
+
+use clap::ValueEnum;
+use generate::{generate, Profile};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+#[test]
+fn a_seed_gives_the_same_data_every_time() {
+    for &profile in Profile::value_variants() {
+        let first = generate(profile, 100_000, &mut StdRng::seed_from_u64(42));
+        assert_eq!(first.len(), 100_000);
+        assert!(first == generate(profile, 100_000, &mut StdRng::seed_from_u64(42)));
+        assert!(first != generate(profile, 100_000, &mut StdRng::seed_from_u64(43)));
+    }
+}
+
+#[test]
+fn sizes_are_exact() {
+    for &profile in Profile::value_variants() {
+        for size in [0, 1, 99, 4097] {
+            assert_eq!(generate(profile, size, &mut StdRng::seed_from_u64(7)).len(), size);
+        }
+    }
+}
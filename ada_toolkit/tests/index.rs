@@ -0,0 +1,36 @@
+//! `index` writes a sidecar block table next to the compressed file.
+
+mod common;
+
+use std::fs::File;
+
+use ada_toolkit::{decompress_range, BlockTable};
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+#[test]
+fn index_writes_a_loadable_sidecar() {
+    let tmp = TempDir::new();
+    let data = mixed_data(500_000);
+    tmp.write("in.bin", &data);
+    run_ok(tmp.path(), &["compress", "in.bin", "--block-size", "16k"]);
+    let output = run_ok(tmp.path(), &["index", "in.bin.aapc"]);
+    let status = stdout(&output);
+    assert!(status.contains("Indexed 31 blocks (500000 bytes uncompressed) to in.bin.aapc.idx"), "{}", status);
+
+    let table = BlockTable::load(File::open(tmp.join("in.bin.aapc.idx")).unwrap()).unwrap();
+    assert_eq!(table.entries().len(), 31);
+    let range = decompress_range(File::open(tmp.join("in.bin.aapc")).unwrap(), &table, 123_456..234_567).unwrap();
+    assert_eq!(range, data[123_456..234_567]);
+}
+
+#[test]
+fn index_of_a_damaged_file_writes_nothing() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(100_000));
+    run_ok(tmp.path(), &["compress", "in.bin"]);
+    let frame = std::fs::read(tmp.join("in.bin.aapc")).unwrap();
+    tmp.write("cut.aapc", &frame[..frame.len() - 8]);
+    let output = run(tmp.path(), &["index", "cut.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(!tmp.join("cut.aapc.idx").exists());
+}
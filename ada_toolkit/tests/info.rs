@@ -0,0 +1,116 @@
+//! `info` reads a frame's header and trailer, as text or JSON.
+
+mod common;
+
+use ada_toolkit::checksum::crc32;
+use ada_toolkit::{compress_with_options, CompressOptions};
+use common::{run, run_ok, stderr, stdout, TempDir};
+
+/// 300000 zeros in five 64 KiB blocks, with every optional field and an
+/// index sidecar.
+fn full_fixture(tmp: &TempDir) -> Vec<u8> {
+    let opts = CompressOptions {
+        block_size: 64 * 1024,
+        filename: Some("zeros.bin".to_string()),
+        comment: Some("nightly".to_string()),
+        ..CompressOptions::default()
+    };
+    let frame = compress_with_options(&[0; 300_000], &opts).unwrap();
+    tmp.write("full.aapc", &frame);
+    run_ok(tmp.path(), &["index", "full.aapc"]);
+    frame
+}
+
+/// "abc" with nothing optional, which fits in a small frame.
+fn minimal_fixture(tmp: &TempDir) -> Vec<u8> {
+    let opts = CompressOptions { block_checksums: false, content_checksum: false, ..CompressOptions::default() };
+    let frame = compress_with_options(b"abc", &opts).unwrap();
+    tmp.write("min.aapc", &frame);
+    frame
+}
+
+#[test]
+fn full_fixture_as_text() {
+    let tmp = TempDir::new();
+    let frame = full_fixture(&tmp);
+    let text = stdout(&run_ok(tmp.path(), &["info", "full.aapc"]));
+    let expected = [
+        "File: full.aapc".to_string(),
+        "Format version: 1".to_string(),
+        "Codec: RLE (incompressible blocks stored)".to_string(),
+        "Flags: 0x0f (block-checksums, content-checksum, filename, comment)".to_string(),
+        "Block size: 65536 bytes".to_string(),
+        "Blocks: 5".to_string(),
+        "Original size: 300000 bytes".to_string(),
+        format!("Compressed size: {} bytes", frame.len()),
+        format!("Ratio: {:.2}", frame.len() as f64 / 300_000.0),
+        format!("Checksum: crc32 (per block: yes, content: 0x{:08x})", crc32(&[0; 300_000])),
+        "Stored filename: zeros.bin".to_string(),
+        "Comment: nightly".to_string(),
+        "Index: full.aapc.idx".to_string(),
+    ];
+    assert_eq!(text.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn full_fixture_as_json() {
+    let tmp = TempDir::new();
+    let frame = full_fixture(&tmp);
+    let json = stdout(&run_ok(tmp.path(), &["--format", "json", "info", "full.aapc"]));
+    let info: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(info["complete"], true);
+    assert_eq!(info["small"], false);
+    assert_eq!(info["flags"], 0x0f);
+    assert_eq!(info["flag_names"], serde_json::json!(["block-checksums", "content-checksum", "filename", "comment"]));
+    assert_eq!(info["block_size"], 65536);
+    assert_eq!(info["checksum_type"], "crc32");
+    assert_eq!(info["filename"], "zeros.bin");
+    assert_eq!(info["comment"], "nightly");
+    assert_eq!(info["index"], "full.aapc.idx");
+    assert_eq!(info["block_count"], 5);
+    assert_eq!(info["content_size"], 300_000);
+    assert_eq!(info["compressed_size"], frame.len());
+    assert_eq!(info["content_checksum"], format!("{:08x}", crc32(&[0; 300_000])));
+    assert_eq!(info["error"], serde_json::Value::Null);
+}
+
+#[test]
+fn minimal_fixture_in_both_forms() {
+    let tmp = TempDir::new();
+    let frame = minimal_fixture(&tmp);
+    let text = stdout(&run_ok(tmp.path(), &["info", "min.aapc"]));
+    assert!(text.contains("Layout: small frame"), "{}", text);
+    assert!(text.contains("Blocks: 1\nOriginal size: 3 bytes\n"), "{}", text);
+    assert!(text.contains("\nChecksum: none (per block: no, content: none)\n"), "{}", text);
+    assert!(text.contains("Index: none"), "{}", text);
+    assert!(!text.contains("Stored filename") && !text.contains("Comment"), "{}", text);
+
+    let json = stdout(&run_ok(tmp.path(), &["--format", "json", "info", "min.aapc"]));
+    let info: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(info["small"], true);
+    assert_eq!(info["filename"], serde_json::Value::Null);
+    assert_eq!(info["content_checksum"], serde_json::Value::Null);
+    assert_eq!(info["content_size"], 3);
+    assert_eq!(info["compressed_size"], frame.len());
+}
+
+#[test]
+fn a_truncated_frame_reports_its_header() {
+    let tmp = TempDir::new();
+    let frame = full_fixture(&tmp);
+    tmp.write("cut.aapc", &frame[..frame.len() / 2]);
+    let output = run(tmp.path(), &["info", "cut.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(text.contains("Stored filename: zeros.bin"), "{}", text);
+    assert!(text.contains("content: unknown)"), "{}", text);
+    assert!(text.contains("Trailer: unreadable"), "{}", text);
+    assert!(!text.contains("Original size"), "{}", text);
+
+    let output = run(tmp.path(), &["--format", "json", "info", "cut.aapc"]);
+    let info: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(info["complete"], false);
+    assert_eq!(info["comment"], "nightly");
+    assert_eq!(info["content_size"], serde_json::Value::Null);
+    assert!(info["error"].is_string());
+}
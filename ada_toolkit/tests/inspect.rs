@@ -0,0 +1,79 @@
+//! `inspect`: the block table of a frame whose blocks are known, and with
+//! --opcodes what each RLE payload is made of.
+
+mod common;
+
+use common::{run, run_ok, stderr, stdout, TempDir};
+use serde_json::{json, Value};
+
+/// Three 1000-byte blocks: 600 zeros, 398 distinct literals and two flag
+/// bytes; flag bytes only, which RLE would double so they are stored; and
+/// 500 bytes of one value.
+fn fixture(tmp: &TempDir) {
+    let mut data = vec![0u8; 600];
+    data.extend((0..398u32).map(|i| (i % 200 + 1) as u8));
+    data.extend([254, 255]);
+    data.extend([254, 255].repeat(500));
+    data.extend([b'a'; 500]);
+    tmp.write("known.bin", data);
+    run_ok(tmp.path(), &["compress", "known.bin", "--block-size", "1000"]);
+}
+
+/// A row of the table without its payload offset and checksum columns.
+fn columns<'a>(row: &[&'a str]) -> Vec<&'a str> {
+    [&row[..1], &row[2..8], &row[9..]].concat()
+}
+
+#[test]
+fn the_table_lists_each_block_and_its_tokens() {
+    let tmp = TempDir::new();
+    fixture(&tmp);
+    let text = stdout(&run_ok(tmp.path(), &["inspect", "known.bin.aapc", "--opcodes"]));
+    let rows: Vec<Vec<&str>> = text.lines().skip(1).take(3).map(|line| line.split_whitespace().collect()).collect();
+    assert_eq!(columns(&rows[0]), ["0", "0", "1000", "411", "0.411", "rle", "(1)", "3", "600", "398", "2"]);
+    assert_eq!(columns(&rows[1]), ["1", "1000", "1000", "1000", "1.000", "stored", "(2)", "-", "-", "-", "-"]);
+    assert_eq!(columns(&rows[2]), ["2", "2000", "500", "6", "0.012", "rle", "(1)", "2", "500", "0", "0"]);
+    assert_eq!(text.lines().nth(4).unwrap(), "3 blocks, 2500 bytes uncompressed in 1417 bytes of payload. \
+                                              Ratio: 0.567; 5 runs (1100 bytes), 398 literals, 2 escapes");
+
+    let plain = stdout(&run_ok(tmp.path(), &["inspect", "known.bin.aapc"]));
+    assert!(!plain.contains("runs"), "{}", plain);
+    assert!(plain.ends_with("Ratio: 0.567\n"), "{}", plain);
+}
+
+#[test]
+fn the_json_has_the_same_counts() {
+    let tmp = TempDir::new();
+    fixture(&tmp);
+    let output = run_ok(tmp.path(), &["--format", "json", "inspect", "known.bin.aapc", "--opcodes"]);
+    let inspect: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let blocks = inspect["blocks"].as_array().unwrap();
+    assert_eq!(blocks.len(), 3);
+    let sizes: Vec<(u64, u64, &str)> = blocks.iter()
+        .map(|block| (block["raw_len"].as_u64().unwrap(), block["comp_len"].as_u64().unwrap(),
+                      block["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(sizes, [(1000, 411, "rle"), (1000, 1000, "stored"), (500, 6, "rle")]);
+    assert_eq!(blocks[0]["tokens"], json!({"runs": 3, "run_bytes": 600, "literals": 398, "escapes": 2}));
+    assert_eq!(blocks[1]["tokens"], Value::Null);
+    assert_eq!(blocks[2]["tokens"], json!({"runs": 2, "run_bytes": 500, "literals": 0, "escapes": 0}));
+    for pair in blocks.windows(2) {
+        let end = pair[0]["payload_offset"].as_u64().unwrap() + pair[0]["comp_len"].as_u64().unwrap();
+        assert!(pair[1]["payload_offset"].as_u64().unwrap() > end);
+        assert!(pair[0]["checksum"].as_str().unwrap().len() == 8);
+    }
+    assert_eq!(inspect["totals"]["tokens"], json!({"runs": 5, "run_bytes": 1100, "literals": 398, "escapes": 2}));
+    assert_eq!((inspect["totals"]["raw_bytes"].as_u64(), inspect["totals"]["comp_bytes"].as_u64()),
+               (Some(2500), Some(1417)));
+}
+
+#[test]
+fn a_cut_frame_is_refused() {
+    let tmp = TempDir::new();
+    fixture(&tmp);
+    let frame = std::fs::read(tmp.join("known.bin.aapc")).unwrap();
+    tmp.write("cut.aapc", &frame[..600]);
+    let output = run(tmp.path(), &["inspect", "cut.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("reading cut.aapc"), "{}", stderr(&output));
+}
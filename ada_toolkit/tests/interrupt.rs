@@ -0,0 +1,102 @@
+//! Ctrl+C cancels the running operation instead of killing it mid-write.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{cli, mixed_data, TempDir};
+
+#[test]
+fn sigint_cancels_and_removes_the_partial_output() {
+    let tmp = TempDir::new();
+    let mut child = cli(tmp.path())
+        .args(["compress", "-", "-o", "out.aapc"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let feeder = thread::spawn(move || {
+        let chunk = mixed_data(1 << 20);
+        while stdin.write_all(&chunk).is_ok() {}
+    });
+
+    thread::sleep(Duration::from_millis(300));
+    let sent = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(sent.success());
+    let interrupted = Instant::now();
+    let output = child.wait_with_output().unwrap();
+    feeder.join().unwrap();
+
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(130), "{}", log);
+    assert!(log.contains("interrupted — no output written"), "{}", log);
+    assert!(interrupted.elapsed() < Duration::from_secs(5));
+    let left: Vec<_> = fs::read_dir(tmp.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert!(left.is_empty(), "left behind: {:?}", left);
+}
+
+fn interrupt(child: &Child) {
+    let sent = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(sent.success());
+}
+
+#[test]
+fn an_interrupted_overwrite_keeps_the_old_version() {
+    let tmp = TempDir::new();
+    tmp.write("out.aapc", "the old version");
+    let mut child = cli(tmp.path())
+        .args(["compress", "-f", "-", "-o", "out.aapc"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let feeder = thread::spawn(move || {
+        let chunk = mixed_data(1 << 20);
+        while stdin.write_all(&chunk).is_ok() {}
+    });
+
+    thread::sleep(Duration::from_millis(300));
+    interrupt(&child);
+    let output = child.wait_with_output().unwrap();
+    feeder.join().unwrap();
+    assert_eq!(output.status.code(), Some(130), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read_to_string(tmp.join("out.aapc")).unwrap(), "the old version");
+    let left: Vec<_> = fs::read_dir(tmp.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(left, ["out.aapc"]);
+}
+
+#[test]
+fn a_second_sigint_exits_at_once() {
+    let tmp = TempDir::new();
+    let mut child = cli(tmp.path())
+        .args(["compress", "-", "-o", "out.aapc"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // One block's worth, then nothing: the run waits on stdin, where the
+    // first Ctrl+C cannot stop it.
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(&mixed_data(300_000)).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    interrupt(&child);
+    thread::sleep(Duration::from_millis(300));
+    assert!(child.try_wait().unwrap().is_none(), "the first Ctrl+C did not wait for the read");
+
+    interrupt(&child);
+    let waited = Instant::now();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(waited.elapsed() < Duration::from_secs(5));
+    drop(stdin);
+    let left: Vec<_> = fs::read_dir(tmp.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert!(left.is_empty(), "left behind: {:?}", left);
+}
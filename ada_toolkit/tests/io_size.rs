@@ -0,0 +1,40 @@
+//! --io-size changes how output is written out, never what is written.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stderr, TempDir};
+
+#[test]
+fn every_io_size_writes_the_same_bytes() {
+    let tmp = TempDir::new();
+    let data = mixed_data(600_000);
+    tmp.write("in.bin", &data);
+    run_ok(tmp.path(), &["compress", "in.bin", "-o", "default.aapc", "--block-size", "16k"]);
+    let frame = fs::read(tmp.join("default.aapc")).unwrap();
+    for size in ["0", "1", "1000", "64k", "16M"] {
+        let name = format!("{}.aapc", size);
+        run_ok(tmp.path(), &["--io-size", size, "compress", "in.bin", "-o", &name, "--block-size", "16k"]);
+        assert!(fs::read(tmp.join(&name)).unwrap() == frame, "--io-size {} changed the frame", size);
+
+        let piped = run_ok(tmp.path(), &["--io-size", size, "compress", "in.bin", "-c", "--block-size", "16k"]);
+        assert!(piped.stdout == frame, "--io-size {} changed stdout", size);
+
+        let out = format!("{}.bin", size);
+        run_ok(tmp.path(), &["--io-size", size, "decompress", &name, "-o", &out]);
+        assert!(fs::read(tmp.join(&out)).unwrap() == data, "--io-size {} did not round trip", size);
+        let cat = run_ok(tmp.path(), &["--io-size", size, "cat", &name]);
+        assert!(cat.stdout == data, "--io-size {} changed cat", size);
+    }
+}
+
+#[test]
+fn a_bad_io_size_is_a_usage_error() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "x");
+    let output = run(tmp.path(), &["--io-size", "lots", "compress", "in.bin"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("--io-size"), "{}", stderr(&output));
+    assert!(!tmp.join("in.bin.aapc").exists());
+}
@@ -0,0 +1,53 @@
+//! `test --iterations`: a warm-up run, then that many timed runs of each
+//! phase summarised in the text and JSON output.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+fn timing(value: &Value) -> [f64; 5] {
+    ["min_ms", "median_ms", "mean_ms", "max_ms", "stddev_ms"].map(|field| value[field].as_f64().unwrap())
+}
+
+#[test]
+fn the_json_has_the_spread_of_each_phase() {
+    let tmp = TempDir::new();
+    let args = ["--format", "json", "test", "--size", "64k", "--seed", "1", "--iterations", "3"];
+    let report: Value = serde_json::from_str(&stdout(&run_ok(tmp.path(), &args))).unwrap();
+    assert_eq!(report["iterations"], 3);
+    for phase in ["compress_timing", "decompress_timing"] {
+        assert_eq!(report[phase]["runs"], 3, "{}", report[phase]);
+        let [min, median, mean, max, stddev] = timing(&report[phase]);
+        assert!(min <= median && median <= max && min <= mean && mean <= max, "{}", report[phase]);
+        assert!(stddev >= 0.0 && stddev <= max - min, "{}", report[phase]);
+    }
+
+    let once: Value = serde_json::from_str(&stdout(&run_ok(tmp.path(), &args[..7]))).unwrap();
+    assert_eq!(once["iterations"], 1);
+    assert_eq!(once["compress_timing"]["runs"], 1);
+    assert_eq!(timing(&once["compress_timing"])[4], 0.0);
+}
+
+#[test]
+fn the_text_gives_the_median_of_the_runs() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(50_000));
+    let text = stdout(&run_ok(tmp.path(), &["test", "in.bin", "--iterations", "3"]));
+    for phase in ["Compression time: median ", "Decompression time: median "] {
+        let line = text.lines().find(|line| line.starts_with(phase)).unwrap_or_else(|| panic!("{}", text));
+        assert!(line.contains(" of 3 runs (") && line.contains("; min ") && line.contains(", stddev "), "{}", line);
+    }
+    assert!(text.ends_with("Harmony restored: Data is identical.\n"), "{}", text);
+
+    let text = stdout(&run_ok(tmp.path(), &["test", "in.bin"]));
+    assert!(!text.contains("median"), "{}", text);
+}
+
+#[test]
+fn zero_iterations_is_a_usage_error() {
+    let tmp = TempDir::new();
+    let output = run(tmp.path(), &["test", "--size", "1k", "--iterations", "0"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stdout(&output).is_empty());
+}
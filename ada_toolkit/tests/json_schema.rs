@@ -0,0 +1,165 @@
+//! `--format json` documents deserialize into the shapes scripts rely on.
+//! Fields may be added, so unknown ones are ignored; these must stay.
+
+#![cfg(feature = "serde")]
+
+mod common;
+
+use serde::Deserialize;
+
+use common::{mixed_data, run, run_ok, stdout, TempDir};
+
+#[derive(Deserialize)]
+struct Batch {
+    operation: String,
+    succeeded: usize,
+    failed: usize,
+    files: Vec<BatchFile>,
+}
+
+#[derive(Deserialize)]
+struct BatchFile {
+    input: String,
+    output: String,
+    status: String,
+    input_bytes: Option<u64>,
+    output_bytes: Option<u64>,
+    ratio: Option<f64>,
+    duration_ms: Option<f64>,
+    bytes_per_second: Option<u64>,
+    exit_code: Option<i32>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Test {
+    operation: String,
+    status: String,
+    input_bytes: u64,
+    compressed_bytes: u64,
+    ratio: f64,
+    compress_duration_ms: f64,
+    compress_bytes_per_second: u64,
+    decompress_duration_ms: f64,
+    decompress_bytes_per_second: u64,
+}
+
+#[derive(Deserialize)]
+struct TestFolder {
+    operation: String,
+    tested: usize,
+    files: Vec<FolderFile>,
+    summary: FolderSummary,
+}
+
+#[derive(Deserialize)]
+struct FolderFile {
+    input: String,
+    status: String,
+    input_bytes: u64,
+    compressed_bytes: u64,
+    ratio: f64,
+}
+
+#[derive(Deserialize)]
+struct FolderSummary {
+    files: usize,
+    failed: usize,
+    input_bytes: u64,
+    wall_duration_ms: f64,
+}
+
+#[derive(Deserialize)]
+struct Verify {
+    operation: String,
+    ok: usize,
+    failed: usize,
+    files: Vec<VerifyFile>,
+}
+
+#[derive(Deserialize)]
+struct VerifyFile {
+    input: String,
+    status: String,
+    content_size: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Info {
+    operation: String,
+    input: String,
+    complete: bool,
+    block_size: usize,
+    block_count: Option<u64>,
+    content_size: Option<u64>,
+    compressed_size: Option<u64>,
+    error: Option<String>,
+}
+
+fn json<T: for<'de> Deserialize<'de>>(output: &std::process::Output) -> T {
+    serde_json::from_str(&stdout(output)).unwrap_or_else(|e| panic!("{}: {}", e, stdout(output)))
+}
+
+#[test]
+fn compress_and_decompress() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(100_000));
+    tmp.write("b.txt", "b");
+    let compress: Batch = json(&run(tmp.path(), &["--format", "json", "compress", "in.bin", "missing", "b.txt"]));
+    assert_eq!((compress.operation.as_str(), compress.succeeded, compress.failed), ("compress", 2, 1));
+    let ok = &compress.files[0];
+    assert_eq!((ok.input.as_str(), ok.output.as_str(), ok.status.as_str()), ("in.bin", "in.bin.aapc", "ok"));
+    assert_eq!(ok.input_bytes, Some(100_000));
+    assert!(ok.output_bytes.is_some() && ok.ratio.is_some() && ok.duration_ms.is_some());
+    assert!(ok.bytes_per_second.is_some() && ok.error.is_none());
+    let failed = &compress.files[1];
+    assert_eq!((failed.status.as_str(), failed.exit_code), ("error", Some(1)));
+    assert!(failed.error.as_deref().is_some_and(|error| error.contains("missing")));
+
+    let decompress: Batch = json(&run_ok(tmp.path(), &["--format", "json", "decompress", "in.bin.aapc", "-o", "x"]));
+    assert_eq!((decompress.operation.as_str(), decompress.succeeded), ("decompress", 1));
+    assert_eq!(decompress.files[0].output_bytes, Some(100_000));
+}
+
+#[test]
+fn test_and_test_folder() {
+    let tmp = TempDir::new();
+    tmp.write("data/a.bin", mixed_data(50_000));
+    tmp.write("data/sub/b.txt", "text");
+    let test: Test = json(&run_ok(tmp.path(), &["--format", "json", "test", "data/a.bin"]));
+    assert_eq!((test.operation.as_str(), test.status.as_str(), test.input_bytes), ("test", "ok", 50_000));
+    assert!(test.compressed_bytes > 0 && test.ratio > 0.0);
+    assert!(test.compress_duration_ms >= 0.0 && test.decompress_duration_ms >= 0.0);
+    assert!(test.compress_bytes_per_second > 0 && test.decompress_bytes_per_second > 0);
+
+    let args = ["--format", "json", "test-folder", "--recursive", "data", "--log-file", "log.txt"];
+    let folder: TestFolder = json(&run_ok(tmp.path(), &args));
+    assert_eq!((folder.operation.as_str(), folder.tested), ("test-folder", 2));
+    let mut inputs: Vec<&str> = folder.files.iter().map(|file| file.input.as_str()).collect();
+    inputs.sort();
+    assert_eq!(inputs, ["data/a.bin", "data/sub/b.txt"]);
+    assert!(folder.files.iter().all(|file| file.status == "ok" && file.compressed_bytes > 0 && file.ratio > 0.0));
+    assert_eq!(folder.files.iter().map(|file| file.input_bytes).sum::<u64>(), 50_004);
+    assert_eq!((folder.summary.files, folder.summary.failed, folder.summary.input_bytes), (2, 0, 50_004));
+    assert!(folder.summary.wall_duration_ms >= 0.0);
+}
+
+#[test]
+fn verify_and_info() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(100_000));
+    run_ok(tmp.path(), &["compress", "in.bin"]);
+    tmp.write("junk.aapc", "not a frame");
+    let verify: Verify = json(&run(tmp.path(), &["--format", "json", "verify", "in.bin.aapc", "junk.aapc"]));
+    assert_eq!((verify.operation.as_str(), verify.ok, verify.failed), ("verify", 1, 1));
+    assert_eq!((verify.files[0].input.as_str(), verify.files[0].status.as_str()), ("in.bin.aapc", "ok"));
+    assert_eq!(verify.files[0].content_size, Some(100_000));
+    assert_eq!(verify.files[1].status, "failed");
+    assert!(verify.files[1].error.is_some());
+
+    let info: Info = json(&run_ok(tmp.path(), &["--format", "json", "info", "in.bin.aapc"]));
+    assert_eq!((info.operation.as_str(), info.input.as_str(), info.complete), ("info", "in.bin.aapc", true));
+    assert_eq!((info.block_size, info.block_count, info.content_size), (262_144, Some(1), Some(100_000)));
+    assert!(info.compressed_size.is_some() && info.error.is_none());
+}
@@ -0,0 +1,95 @@
+//! test-folder's JSON-lines log, kept as a history with `--log-append`.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Stdio;
+
+use common::{cli, mixed_data, run_ok, stderr, TempDir};
+use serde_json::Value;
+
+fn folder(tmp: &TempDir, files: usize) {
+    for i in 0..files {
+        tmp.write(&format!("data/f{:02}.bin", i), mixed_data(1000 + i * 300));
+    }
+}
+
+/// Every line of the log, each of which must be a JSON object.
+fn records(tmp: &TempDir) -> Vec<Value> {
+    let log = fs::read_to_string(tmp.join("history.jsonl")).unwrap();
+    assert!(log.ends_with('\n'), "{:?}", log);
+    log.lines().map(|line| {
+        let record: Value = serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {:?}", e, line));
+        assert!(record.is_object(), "{}", line);
+        record
+    }).collect()
+}
+
+/// The records of each run, by run ID.
+fn runs(records: &[Value]) -> BTreeMap<String, Vec<&Value>> {
+    let mut runs: BTreeMap<String, Vec<&Value>> = BTreeMap::new();
+    for record in records {
+        runs.entry(record["run"].as_str().unwrap().to_string()).or_default().push(record);
+    }
+    runs
+}
+
+const APPEND: [&str; 6] = ["--log-format", "jsonl", "--log-append", "--log-file", "history.jsonl", "data"];
+
+#[test]
+fn appended_runs_keep_every_record() {
+    let tmp = TempDir::new();
+    folder(&tmp, 3);
+    let mut args = vec!["test-folder"];
+    args.extend(APPEND);
+    run_ok(tmp.path(), &args);
+    run_ok(tmp.path(), &args);
+
+    let records = records(&tmp);
+    assert_eq!(records.len(), 8);
+    let runs = runs(&records);
+    assert_eq!(runs.len(), 2, "both runs share a run ID");
+    for run in runs.values() {
+        let kinds: Vec<&str> = run.iter().map(|record| record["record"].as_str().unwrap()).collect();
+        assert_eq!(kinds, ["file", "file", "file", "summary"]);
+        let inputs: Vec<&str> = run[..3].iter().map(|record| record["input"].as_str().unwrap()).collect();
+        assert_eq!(inputs, ["data/f00.bin", "data/f01.bin", "data/f02.bin"]);
+        assert!(run.iter().all(|record| record["timestamp"].as_u64().is_some_and(|t| t > 1_600_000_000)));
+        assert!(run[..3].iter().all(|record| record["status"] == "ok"));
+        assert_eq!(run[3]["files"], 3);
+    }
+}
+
+#[test]
+fn without_append_the_log_holds_one_run() {
+    let tmp = TempDir::new();
+    folder(&tmp, 2);
+    for _ in 0..2 {
+        run_ok(tmp.path(), &["test-folder", "--log-format", "jsonl", "--log-file", "history.jsonl", "data"]);
+    }
+    let records = records(&tmp);
+    assert_eq!(records.len(), 3);
+    assert_eq!(runs(&records).len(), 1);
+}
+
+#[test]
+fn concurrent_runs_never_split_a_line() {
+    let tmp = TempDir::new();
+    folder(&tmp, 40);
+    let children: Vec<_> = (0..6).map(|_| {
+        cli(tmp.path()).arg("test-folder").args(APPEND).stdout(Stdio::null()).stderr(Stdio::piped()).spawn().unwrap()
+    }).collect();
+    for child in children {
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+    let records = records(&tmp);
+    assert_eq!(records.len(), 6 * 41);
+    let runs = runs(&records);
+    assert_eq!(runs.len(), 6);
+    for run in runs.values() {
+        assert_eq!(run.len(), 41);
+        assert_eq!(run.iter().filter(|record| record["record"] == "summary").count(), 1);
+    }
+}
@@ -0,0 +1,38 @@
+//! Compression levels, which the codec does not have yet: every way of
+//! asking for one is refused rather than ignored.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, TempDir};
+
+#[test]
+fn every_level_flag_is_a_usage_error() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(100_000));
+    for flags in [&["-1"][..], &["-9"], &["--level", "5"], &["--fast"], &["--best"]] {
+        let mut args = vec!["compress", "in.bin"];
+        args.extend_from_slice(flags);
+        let output = run(tmp.path(), &args);
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", flags, stderr(&output));
+        assert!(stderr(&output).contains("only one level"), "{:?}: {}", flags, stderr(&output));
+        assert!(!tmp.join("in.bin.aapc").exists(), "{:?} wrote output", flags);
+    }
+}
+
+#[test]
+fn conflicting_and_out_of_range_levels_are_rejected_by_the_parser() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "x");
+    for args in [&["compress", "in.bin", "--level", "3", "--best"][..], &["compress", "in.bin", "--fast", "--best"],
+                 &["compress", "in.bin", "--level", "10"]] {
+        assert_eq!(run(tmp.path(), args).status.code(), Some(2), "{:?}", args);
+    }
+}
+
+#[test]
+fn a_lone_dash_digit_after_double_dash_is_a_file_name() {
+    let tmp = TempDir::new();
+    tmp.write("-9", "not a flag");
+    run_ok(tmp.path(), &["compress", "--", "-9"]);
+    assert!(tmp.join("-9.aapc").is_file());
+}
@@ -0,0 +1,51 @@
+//! The debug records the `log` feature has the library emit, a line per
+//! block encoded or decoded.
+
+#![cfg(feature = "log")]
+
+use std::sync::Mutex;
+
+use ada_toolkit::{compress_with_options, decompress, CompressOptions};
+use log::{Level, Log, Metadata, Record};
+
+/// Every record logged, as "LEVEL target: message".
+struct Capture(Mutex<Vec<String>>);
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        self.0.lock().unwrap().push(format!("{} {}: {}", record.level(), record.target(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+// One test, as a process can only set its logger once.
+#[test]
+fn every_block_is_logged_at_debug() {
+    log::set_logger(&CAPTURE).unwrap();
+    log::set_max_level(Level::Trace.to_level_filter());
+
+    let mut data = vec![0u8; 5000];
+    data.extend((0..3000u32).map(|i| (i * 131 % 251) as u8));
+    let opts = CompressOptions { block_size: 5000, ..CompressOptions::default() };
+    let frame = compress_with_options(&data, &opts).unwrap();
+    assert_eq!(decompress(&frame).unwrap(), data);
+
+    let records = CAPTURE.0.lock().unwrap();
+    let blocks: Vec<&String> = records.iter().filter(|record| record.contains(" block ")).collect();
+    assert_eq!(blocks.len(), 4, "{:#?}", records);
+    assert!(blocks[0].starts_with("DEBUG ada_toolkit::compression: encoded block 0: 5000 -> "), "{}", blocks[0]);
+    assert!(blocks[0].ends_with(" bytes (rle)"), "{}", blocks[0]);
+    assert_eq!(*blocks[1], "DEBUG ada_toolkit::compression: encoded block 1: 3000 -> 3000 bytes (stored)");
+    assert!(blocks[2].starts_with("DEBUG ada_toolkit::decompression: decoding block 0: "), "{}", blocks[2]);
+    assert!(blocks[2].ends_with(" -> 5000 bytes (rle)"), "{}", blocks[2]);
+    assert_eq!(*blocks[3], "DEBUG ada_toolkit::decompression: decoding block 1: 3000 -> 3000 bytes (stored)");
+    let quiet = |record: &&String| record.starts_with("DEBUG ") || record.starts_with("TRACE ");
+    assert!(records.iter().all(|record| quiet(&record)), "{:#?}", records);
+}
@@ -0,0 +1,72 @@
+//! Diagnostics on stderr through the log facade: warnings by default,
+//! `--log-level`, `--verbose`, `--quiet` and RUST_LOG for the rest.
+
+mod common;
+
+use common::{cli, mixed_data, run_ok, stderr, stdout, TempDir};
+
+const WARNING: &str = "WARN: frame.aapc is already compressed (AAPC); compressing it again";
+
+/// A frame to compress again, which is a fallback worth a warning.
+fn frame() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("frame", mixed_data(50_000));
+    run_ok(tmp.path(), &["compress", "frame", "-o", "frame.aapc"]);
+    tmp
+}
+
+/// What compressing the frame again with `flags` logs, with RUST_LOG set
+/// to `rust_log` or cleared.
+fn log(tmp: &TempDir, flags: &[&str], rust_log: Option<&str>) -> String {
+    let mut command = cli(tmp.path());
+    command.args(flags).args(["compress", "-f", "frame.aapc"]).env_remove("RUST_LOG");
+    if let Some(filter) = rust_log {
+        command.env("RUST_LOG", filter);
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{:?}: {}", flags, stderr(&output));
+    assert!(!stdout(&output).contains("WARN:"), "log lines on stdout: {}", stdout(&output));
+    stderr(&output)
+}
+
+fn has(log: &str, level: &str) -> bool {
+    log.lines().any(|line| line.starts_with(&format!("{}: ", level)))
+}
+
+#[test]
+fn warnings_show_and_details_stay_hidden_by_default() {
+    let tmp = frame();
+    let log = log(&tmp, &[], None);
+    assert_eq!(log.lines().collect::<Vec<_>>(), [WARNING]);
+}
+
+#[test]
+fn the_level_can_be_raised() {
+    let tmp = frame();
+    let info = log(&tmp, &["--log-level", "info"], None);
+    assert!(info.contains(WARNING), "{}", info);
+    assert!(info.contains("INFO: Writing compressed output to frame.aapc.aapc"), "{}", info);
+    assert!(!has(&info, "DEBUG"), "{}", info);
+
+    for flags in [&["--verbose"][..], &["--log-level", "debug"]] {
+        let debug = log(&tmp, flags, None);
+        assert!(debug.contains("DEBUG: Reading input file frame.aapc"), "{:?}: {}", flags, debug);
+        assert!(has(&debug, "INFO") && has(&debug, "WARN"), "{:?}: {}", flags, debug);
+    }
+}
+
+#[test]
+fn the_level_can_be_lowered() {
+    let tmp = frame();
+    for flags in [&["--log-level", "error"][..], &["--log-level", "off"], &["--quiet"]] {
+        assert_eq!(log(&tmp, flags, None), "", "{:?}", flags);
+    }
+}
+
+#[test]
+fn rust_log_applies_only_without_a_flag() {
+    let tmp = frame();
+    assert!(has(&log(&tmp, &[], Some("debug")), "DEBUG"));
+    assert_eq!(log(&tmp, &[], Some("error")), "");
+    assert_eq!(log(&tmp, &["--log-level", "warn"], Some("debug")).lines().collect::<Vec<_>>(), [WARNING]);
+}
@@ -0,0 +1,89 @@
+//! Several inputs in one run: each gets its own output, failures do not
+//! stop the rest, and the summary counts both.
+
+mod common;
+
+use common::{run, run_ok, stderr, stdout, TempDir};
+
+/// Two good inputs, a missing one and one that cannot be read: a
+/// directory, or a file without read permission where that keeps us out.
+fn mixed_inputs() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("a.log", "first");
+    tmp.write("b.log", "second");
+    std::fs::create_dir(tmp.join("dir.log")).unwrap();
+    tmp
+}
+
+#[test]
+fn good_inputs_are_compressed_past_bad_ones() {
+    let tmp = mixed_inputs();
+    let output = run(tmp.path(), &["compress", "a.log", "missing.log", "b.log", "dir.log"]);
+    let log = format!("{}{}", stdout(&output), stderr(&output));
+    assert_eq!(output.status.code(), Some(1), "{}", log);
+    assert!(log.contains("Compressed a.log"), "{}", log);
+    assert!(stderr(&output).contains("missing.log"), "{}", log);
+    assert!(stderr(&output).contains("dir.log"), "{}", log);
+    assert!(log.contains("2 of 4 files compressed; 2 failed"), "{}", log);
+
+    std::fs::remove_file(tmp.join("a.log")).unwrap();
+    std::fs::remove_file(tmp.join("b.log")).unwrap();
+    run_ok(tmp.path(), &["decompress", "a.log.aapc", "b.log.aapc"]);
+    assert_eq!(std::fs::read_to_string(tmp.join("a.log")).unwrap(), "first");
+    assert_eq!(std::fs::read_to_string(tmp.join("b.log")).unwrap(), "second");
+}
+
+#[test]
+fn a_missing_second_path_is_taken_for_an_output_and_refused() {
+    let tmp = mixed_inputs();
+    for command in ["compress", "decompress"] {
+        let output = run(tmp.path(), &[command, "a.log", "out.aapc"]);
+        assert_eq!(output.status.code(), Some(2), "{}: {}", command, stderr(&output));
+        assert!(stderr(&output).contains("out.aapc does not exist; to name the output, use -o out.aapc"),
+                "{}", stderr(&output));
+    }
+    assert!(!tmp.join("a.log.aapc").exists() && !tmp.join("out.aapc").exists());
+
+    // With the output named, or among more inputs, a missing path is a
+    // failed input like any other.
+    run_ok(tmp.path(), &["compress", "a.log", "-o", "out.aapc"]);
+    let output = run(tmp.path(), &["compress", "a.log", "b.log", "missing.log", "-f"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+}
+
+#[cfg(unix)]
+#[test]
+fn an_unreadable_file_fails_alone() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = mixed_inputs();
+    let locked = tmp.write("locked.log", "secret");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    if std::fs::read(&locked).is_ok() {
+        return; // Running privileged, which permissions do not stop.
+    }
+    let output = run(tmp.path(), &["compress", "a.log", "locked.log"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Permission denied"), "{}", stderr(&output));
+    assert!(tmp.join("a.log.aapc").is_file());
+    assert!(!tmp.join("locked.log.aapc").exists());
+}
+
+#[test]
+fn json_summary_lists_every_input() {
+    let tmp = mixed_inputs();
+    let output = run(tmp.path(), &["--format", "json", "compress", "a.log", "missing.log", "b.log"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let summary: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(summary["operation"], "compress");
+    assert_eq!(summary["succeeded"], 2);
+    assert_eq!(summary["failed"], 1);
+    let files = summary["files"].as_array().unwrap();
+    let statuses: Vec<_> = files.iter()
+        .map(|file| (file["input"].as_str().unwrap(), file["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(statuses, [("a.log", "ok"), ("missing.log", "error"), ("b.log", "ok")]);
+    assert_eq!(files[0]["output"], "a.log.aapc");
+    assert_eq!(files[0]["input_bytes"], 5);
+    assert_eq!(files[1]["exit_code"], 1);
+}
@@ -0,0 +1,48 @@
+//! --max-memory: the default block size shrinks to fit, and work that
+//! still cannot fit fails with a usage error before writing anything.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+fn block_size(tmp: &TempDir, frame: &str) -> String {
+    let info = stdout(&run_ok(tmp.path(), &["info", frame]));
+    info.lines().find(|line| line.starts_with("Block size:")).unwrap().to_string()
+}
+
+#[test]
+fn a_small_limit_picks_smaller_blocks() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(300_000));
+    run_ok(tmp.path(), &["compress", "in.bin", "-o", "roomy.aapc"]);
+    run_ok(tmp.path(), &["--max-memory", "300k", "compress", "in.bin", "-o", "tight.aapc"]);
+    assert_eq!(block_size(&tmp, "roomy.aapc"), "Block size: 262144 bytes");
+    assert_eq!(block_size(&tmp, "tight.aapc"), "Block size: 65536 bytes");
+
+    run_ok(tmp.path(), &["--max-memory", "300k", "decompress", "tight.aapc", "-o", "out.bin"]);
+    assert_eq!(std::fs::read(tmp.join("out.bin")).unwrap(), mixed_data(300_000));
+}
+
+#[test]
+fn an_explicit_block_size_over_the_limit_fails_before_writing() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(10_000));
+    let output = run(tmp.path(), &["--max-memory", "1M", "compress", "in.bin", "--block-size", "1M"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("compressing needs 3211264 bytes of memory, more than the limit of 1048576"),
+            "{}", stderr(&output));
+    assert!(!tmp.join("in.bin.aapc").exists());
+}
+
+#[test]
+fn a_frame_with_blocks_over_the_limit_is_not_decoded() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(300_000));
+    run_ok(tmp.path(), &["compress", "in.bin", "--block-size", "4M"]);
+    let args = ["--max-memory", "1M", "--threads", "1", "decompress", "in.bin.aapc", "-o", "out.bin"];
+    let output = run(tmp.path(), &args);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("decompressing needs 12648448 bytes of memory, more than the limit of 1048576"),
+            "{}", stderr(&output));
+    assert!(!tmp.join("out.bin").exists());
+}
@@ -0,0 +1,59 @@
+//! `compress --mmap` writes the frame a read-based run writes, and reads
+//! what it cannot map.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, run_with_stdin, stderr, TempDir};
+
+#[test]
+fn a_mapped_input_gives_the_same_frame() {
+    let tmp = TempDir::new();
+    for (name, len) in [("empty.bin", 0), ("tiny.bin", 10), ("block.bin", 65_536), ("big.bin", 1_000_000)] {
+        tmp.write(name, mixed_data(len));
+        for extra in [&["--block-size", "64k"][..], &["--threads", "4", "--block-size", "16k"]] {
+            let read = run_ok(tmp.path(), &[&["compress", name, "-c"][..], extra].concat()).stdout;
+            let mapped = run_ok(tmp.path(), &[&["compress", name, "-c", "--mmap"][..], extra].concat());
+            assert_eq!(mapped.stdout, read, "{} {:?}", name, extra);
+        }
+    }
+
+    let output = run_ok(tmp.path(), &["--verbose", "compress", "big.bin", "--mmap"]);
+    assert!(stderr(&output).contains("Mapped big.bin (1000000 bytes) into memory"), "{}", stderr(&output));
+    let restored = run_ok(tmp.path(), &["decompress", "-c", "big.bin.aapc"]).stdout;
+    assert_eq!(restored, fs::read(tmp.join("big.bin")).unwrap());
+}
+
+#[test]
+fn stdin_and_pipes_are_read() {
+    let tmp = TempDir::new();
+    let data = mixed_data(200_000);
+    let output = run_with_stdin(tmp.path(), &["compress", "-", "--mmap", "-c"], &data);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, run_with_stdin(tmp.path(), &["compress", "-", "-c"], &data).stdout);
+
+    #[cfg(unix)]
+    {
+        let made = std::process::Command::new("mkfifo").arg(tmp.join("fifo")).status().unwrap();
+        assert!(made.success());
+        let path = tmp.join("fifo");
+        let feed = std::thread::spawn(move || fs::write(path, mixed_data(50_000)).unwrap());
+        let output = run(tmp.path(), &["--verbose", "compress", "fifo", "--mmap", "-o", "fifo.aapc"]);
+        feed.join().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(stderr(&output).contains("Reading fifo instead of mapping it: not a regular file"), "{}",
+                stderr(&output));
+        assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "fifo.aapc"]).stdout, mixed_data(50_000));
+    }
+}
+
+#[test]
+fn mmap_conflicts_with_tar_and_checkpoints() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "x");
+    for extra in [&["--tar"][..], &["--checkpoint", "cp"]] {
+        let output = run(tmp.path(), &[&["compress", "in.bin", "--mmap"][..], extra].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", extra, stderr(&output));
+    }
+}
@@ -0,0 +1,37 @@
+//! Output names derived from the input when no output is given.
+
+mod common;
+
+use common::{run, run_ok, stderr, TempDir};
+
+#[test]
+fn compress_appends_and_decompress_strips_the_suffix() {
+    let tmp = TempDir::new();
+    for name in ["data.bin", "noext", ".hidden", "sub/archive.tar", "twice.aapc"] {
+        tmp.write(name, format!("content of {}", name));
+        run_ok(tmp.path(), &["compress", name]);
+        let compressed = format!("{}.aapc", name);
+        assert!(tmp.join(&compressed).is_file(), "{} gave no {}", name, compressed);
+
+        std::fs::remove_file(tmp.join(name)).unwrap();
+        run_ok(tmp.path(), &["decompress", &compressed]);
+        assert_eq!(std::fs::read_to_string(tmp.join(name)).unwrap(), format!("content of {}", name));
+    }
+}
+
+#[test]
+fn decompress_needs_the_suffix_or_an_output() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    run_ok(tmp.path(), &["compress", "in.bin", "-o", "frame"]);
+    let frame = std::fs::read(tmp.join("frame")).unwrap();
+    for name in ["frame", "frame.AAPC", ".aapc", "sub/.aapc"] {
+        tmp.write(name, &frame);
+        let output = run(tmp.path(), &["decompress", name]);
+        assert_eq!(output.status.code(), Some(2), "{}: {}", name, stderr(&output));
+        assert!(stderr(&output).contains("use -o"), "{}: {}", name, stderr(&output));
+    }
+
+    run_ok(tmp.path(), &["decompress", "frame", "--output", "out.bin"]);
+    assert_eq!(std::fs::read_to_string(tmp.join("out.bin")).unwrap(), "data");
+}
@@ -0,0 +1,66 @@
+//! `--output-dir` collects each output under the input's base name.
+
+mod common;
+
+use std::fs;
+
+use common::{run, run_ok, stderr, TempDir};
+
+#[test]
+fn relative_and_absolute_output_dirs() {
+    let tmp = TempDir::new();
+    tmp.write("src/a.db", "a");
+    tmp.write("src/deep/b.db", "b");
+    fs::create_dir(tmp.join("out")).unwrap();
+    run_ok(tmp.path(), &["compress", "--output-dir", "out", "src/a.db", "src/deep/b.db"]);
+    assert!(tmp.join("out/a.db.aapc").is_file());
+    assert!(tmp.join("out/b.db.aapc").is_file());
+    assert!(tmp.join("src/a.db").is_file(), "the input was touched");
+
+    let back = tmp.join("back");
+    fs::create_dir(&back).unwrap();
+    run_ok(tmp.path(), &["decompress", "--output-dir", back.to_str().unwrap(), "out/a.db.aapc", "out/b.db.aapc"]);
+    assert_eq!(fs::read_to_string(back.join("a.db")).unwrap(), "a");
+    assert_eq!(fs::read_to_string(back.join("b.db")).unwrap(), "b");
+}
+
+#[test]
+fn a_missing_dir_needs_parents() {
+    let tmp = TempDir::new();
+    tmp.write("a.db", "a");
+    let output = run(tmp.path(), &["compress", "--output-dir", "new/today", "a.db"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("use --parents"), "{}", stderr(&output));
+    assert!(!tmp.join("new").exists());
+
+    run_ok(tmp.path(), &["compress", "--output-dir", "new/today", "--parents", "a.db"]);
+    assert!(tmp.join("new/today/a.db.aapc").is_file());
+    let output = run(tmp.path(), &["compress", "--parents", "a.db"]);
+    assert_eq!(output.status.code(), Some(2), "--parents without --output-dir: {}", stderr(&output));
+}
+
+#[test]
+fn inputs_with_the_same_base_name_collide() {
+    let tmp = TempDir::new();
+    tmp.write("x/f", "first");
+    tmp.write("y/f", "second");
+    fs::create_dir(tmp.join("out")).unwrap();
+    for force in [false, true] {
+        let mut args = vec!["compress", "--output-dir", "out", "x/f", "y/f"];
+        if force {
+            args.push("-f");
+        }
+        let output = run(tmp.path(), &args);
+        assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr(&output));
+        assert!(stderr(&output).contains("not overwriting it with y/f"), "{:?}: {}", args, stderr(&output));
+    }
+    run_ok(tmp.path(), &["decompress", "out/f.aapc", "-o", "f"]);
+    assert_eq!(fs::read_to_string(tmp.join("f")).unwrap(), "first");
+
+    // An output from an earlier run follows the usual rules.
+    let output = run(tmp.path(), &["compress", "--output-dir", "out", "y/f"]);
+    assert!(stderr(&output).contains("already exists; use -f"), "{}", stderr(&output));
+    run_ok(tmp.path(), &["compress", "--output-dir", "out", "y/f", "-f"]);
+    run_ok(tmp.path(), &["decompress", "out/f.aapc", "-o", "f", "-f"]);
+    assert_eq!(fs::read_to_string(tmp.join("f")).unwrap(), "second");
+}
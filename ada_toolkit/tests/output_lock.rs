@@ -0,0 +1,98 @@
+//! A run writing an output locks it, so another run writing the same output
+//! fails at once, or with --wait waits for the first to finish.
+
+mod common;
+
+use std::fs::{self, File};
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{cli, mixed_data, run, run_ok, stderr, TempDir};
+
+/// Takes the lock a run writing `name` in `tmp` would take.
+fn hold(tmp: &TempDir, name: &str) -> File {
+    let lock = File::create(tmp.join(&format!(".{}.lock", name))).unwrap();
+    lock.try_lock().unwrap();
+    lock
+}
+
+#[test]
+fn a_locked_output_fails_every_writer_at_once() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(50_000));
+    run_ok(tmp.path(), &["compress", "in.bin", "-o", "frame.aapc"]);
+    tmp.write("tree/a.txt", "archived");
+    let _held = [hold(&tmp, "out.aapc"), hold(&tmp, "out.bin"), hold(&tmp, "out.aapa")];
+    for args in [&["compress", "in.bin", "-o", "out.aapc"][..], &["decompress", "frame.aapc", "-o", "out.bin"],
+                 &["archive", "create", "out.aapa", "tree"]] {
+        let output = run(tmp.path(), args);
+        assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr(&output));
+        assert!(stderr(&output).contains("is being written by another process; use --wait to wait for it"),
+                "{:?}: {}", args, stderr(&output));
+    }
+    for name in ["out.aapc", "out.bin", "out.aapa"] {
+        assert!(!tmp.join(name).exists(), "{} was written", name);
+    }
+
+    let start = Instant::now();
+    let output = run(tmp.path(), &["--wait", "0.5", "compress", "in.bin", "-o", "out.aapc"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("is still being written by another process after 500ms"), "{}", stderr(&output));
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+#[test]
+fn wait_goes_on_once_the_lock_is_released() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(50_000));
+    let held = hold(&tmp, "out.aapc");
+    let release = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(500));
+        drop(held);
+    });
+    let start = Instant::now();
+    run_ok(tmp.path(), &["--wait", "30", "compress", "in.bin", "-o", "out.aapc"]);
+    assert!(start.elapsed() >= Duration::from_millis(500), "did not wait: {:?}", start.elapsed());
+    release.join().unwrap();
+    assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "out.aapc"]).stdout, mixed_data(50_000));
+    assert!(!tmp.join(".out.aapc.lock").exists());
+}
+
+/// Starts two runs compressing into out.aapc at the same time and returns
+/// their outputs.
+fn race(tmp: &TempDir, extra: &[&str]) -> [std::process::Output; 2] {
+    let children = ["a.bin", "b.bin"].map(|input| {
+        cli(tmp.path()).args(extra).args(["compress", input, "-o", "out.aapc", "-f", "--threads", "1"])
+            .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap()
+    });
+    children.map(|child| child.wait_with_output().unwrap())
+}
+
+#[test]
+fn of_two_concurrent_runs_one_fails_or_waits() {
+    let tmp = TempDir::new();
+    let (a, mut b) = (mixed_data(2 << 20), mixed_data(2 << 20));
+    b[0] ^= 1;
+    tmp.write("a.bin", &a);
+    tmp.write("b.bin", &b);
+
+    let outputs = race(&tmp, &[]);
+    let succeeded = outputs.iter().filter(|output| output.status.success()).count();
+    assert!(succeeded >= 1, "{} / {}", stderr(&outputs[0]), stderr(&outputs[1]));
+    for output in outputs.iter().filter(|output| !output.status.success()) {
+        assert!(stderr(output).contains("out.aapc is being written by another process"), "{}", stderr(output));
+    }
+    let written = run_ok(tmp.path(), &["decompress", "-c", "out.aapc"]).stdout;
+    assert!(written == a || written == b, "the frame is neither input");
+
+    // Waiting, both succeed one after the other.
+    let outputs = race(&tmp, &["--wait", "60"]);
+    for output in &outputs {
+        assert!(output.status.success(), "{}", stderr(output));
+    }
+    let written = run_ok(tmp.path(), &["decompress", "-c", "out.aapc"]).stdout;
+    assert!(written == a || written == b, "the frame is neither input");
+    let left: Vec<_> = fs::read_dir(tmp.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(left.len(), 3, "{:?}", left);
+}
@@ -0,0 +1,68 @@
+//! Existing outputs are left alone unless `-f/--force` is given.
+
+mod common;
+
+use std::fs;
+
+use common::{run, run_ok, run_with_stdin, stderr, TempDir};
+
+fn assert_refused(tmp: &TempDir, args: &[&str], existing: &str) {
+    // Piped stdin is not a terminal, so there is no prompt to answer.
+    let output = run_with_stdin(tmp.path(), args, b"y\n");
+    assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr(&output));
+    assert!(stderr(&output).contains("already exists; use -f"), "{:?}: {}", args, stderr(&output));
+    assert_eq!(fs::read(tmp.join(existing)).unwrap(), b"keep", "{:?} overwrote {}", args, existing);
+}
+
+#[test]
+fn compress_and_decompress_refuse_without_force() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    tmp.write("in.bin.aapc", "keep");
+    tmp.write("named", "keep");
+    assert_refused(&tmp, &["compress", "in.bin"], "in.bin.aapc");
+    assert_refused(&tmp, &["compress", "in.bin", "-o", "named"], "named");
+
+    run_ok(tmp.path(), &["compress", "in.bin", "-o", "frame.aapc"]);
+    tmp.write("frame", "keep");
+    assert_refused(&tmp, &["decompress", "frame.aapc"], "frame");
+    assert_refused(&tmp, &["decompress", "frame.aapc", "--output", "named"], "named");
+}
+
+#[test]
+fn force_overwrites() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    tmp.write("in.bin.aapc", "keep");
+    run_ok(tmp.path(), &["compress", "-f", "in.bin"]);
+    assert_ne!(fs::read(tmp.join("in.bin.aapc")).unwrap(), b"keep");
+
+    tmp.write("in.bin", "keep");
+    run_ok(tmp.path(), &["decompress", "--force", "in.bin.aapc"]);
+    assert_eq!(fs::read(tmp.join("in.bin")).unwrap(), b"data");
+}
+
+#[test]
+fn batch_runs_skip_existing_outputs_and_carry_on() {
+    let tmp = TempDir::new();
+    tmp.write("a", "a");
+    tmp.write("b", "b");
+    tmp.write("b.aapc", "keep");
+    let output = run(tmp.path(), &["compress", "a", "b"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("b.aapc already exists"), "{}", stderr(&output));
+    assert!(tmp.join("a.aapc").is_file());
+    assert_eq!(fs::read(tmp.join("b.aapc")).unwrap(), b"keep");
+}
+
+#[test]
+fn extraction_refuses_without_force() {
+    let tmp = TempDir::new();
+    tmp.write("tree/a.txt", "new");
+    run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+    tmp.write("out/tree/a.txt", "keep");
+    assert_refused(&tmp, &["archive", "extract", "t.aapa", "-C", "out"], "out/tree/a.txt");
+
+    run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out", "-f"]);
+    assert_eq!(fs::read(tmp.join("out/tree/a.txt")).unwrap(), b"new");
+}
@@ -0,0 +1,117 @@
+//! Many files compressed at once give what one at a time gives: the same
+//! outputs, per-file lines in input order and the same totals.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stdout, TempDir};
+use serde_json::Value;
+
+/// 50 tiny files under src and src/sub, and a manifest writing each to m.
+fn setup() -> (TempDir, Vec<String>) {
+    let tmp = TempDir::new();
+    let mut names = Vec::new();
+    for i in 0..50 {
+        let name = format!("src/{}f{:02}.bin", if i % 3 == 0 { "sub/" } else { "" }, i);
+        tmp.write(&name, &mixed_data(50 + i * 61)[i..]);
+        names.push(name);
+    }
+    let manifest: String = names.iter().enumerate().map(|(i, name)| format!("{}\tm/{:02}.aapc\n", name, i)).collect();
+    tmp.write("jobs.tsv", manifest);
+    (tmp, names)
+}
+
+/// Each line of a summary without its timing, which differs between runs.
+fn untimed(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| match (line.find(" in "), line.find(". Ratio:")) {
+            (Some(start), Some(end)) if start < end => format!("{}{}", &line[..start], &line[end..]),
+            _ => line.to_string(),
+        })
+        .collect()
+}
+
+/// `json` without the fields that hold times.
+fn untimed_json(mut json: Value) -> Value {
+    for file in json["files"].as_array_mut().into_iter().flatten() {
+        for field in ["duration_ms", "bytes_per_second", "phases"] {
+            file.as_object_mut().unwrap().remove(field);
+        }
+    }
+    json
+}
+
+fn read_all(tmp: &TempDir, paths: impl IntoIterator<Item = String>) -> Vec<Vec<u8>> {
+    paths.into_iter().map(|path| fs::read(tmp.join(&path)).unwrap()).collect()
+}
+
+#[test]
+fn multi_input_compress_matches_one_at_a_time() {
+    let (tmp, names) = setup();
+    let mut runs = Vec::new();
+    for threads in ["1", "4"] {
+        let args: Vec<&str> = ["--threads", threads, "compress", "-f"].into_iter()
+            .chain(names.iter().map(String::as_str))
+            .collect();
+        let text = untimed(&stdout(&run_ok(tmp.path(), &args)));
+        let outputs = read_all(&tmp, names.iter().map(|name| format!("{}.aapc", name)));
+        let json_args: Vec<&str> = ["--format", "json"].into_iter().chain(args.iter().copied()).collect();
+        let json = untimed_json(serde_json::from_str(&stdout(&run_ok(tmp.path(), &json_args))).unwrap());
+        runs.push((text, outputs, json));
+    }
+    let (text, _, json) = &runs[0];
+    assert_eq!(text.len(), 51);
+    assert!(text[0].starts_with("Compressed src/sub/f00.bin ("), "{}", text[0]);
+    assert_eq!(text[50], "50 of 50 files compressed; 0 failed");
+    assert_eq!(json["succeeded"], 50);
+    let files = json["files"].as_array().unwrap();
+    let inputs: Vec<&str> = files.iter().map(|file| file["input"].as_str().unwrap()).collect();
+    assert_eq!(inputs, names);
+    assert!(runs[0] == runs[1], "--threads 4 differs from --threads 1");
+}
+
+#[test]
+fn compress_dir_and_batch_match_one_at_a_time() {
+    let (tmp, names) = setup();
+    let mut runs = Vec::new();
+    for threads in ["1", "4"] {
+        let dst = format!("out{}", threads);
+        let dir_args = ["--format", "json", "--threads", threads, "compress-dir", "src", &dst];
+        let mut dir = serde_json::from_str::<Value>(&stdout(&run_ok(tmp.path(), &dir_args))).unwrap();
+        dir.as_object_mut().unwrap().remove("dst");
+        let outputs = read_all(&tmp, names.iter().map(|name| format!("{}/{}.aapc", dst, &name[4..])));
+
+        let batch_args =
+            ["--format", "json", "--threads", threads, "batch", "--manifest", "jobs.tsv", "--parents", "-f"];
+        let batch = untimed_json(serde_json::from_str(&stdout(&run_ok(tmp.path(), &batch_args))).unwrap());
+        let jobs = read_all(&tmp, (0..50).map(|i| format!("m/{:02}.aapc", i)));
+        runs.push((dir, outputs, batch, jobs));
+    }
+    let (dir, outputs, batch, jobs) = &runs[0];
+    assert_eq!((dir["compressed"].as_u64(), dir["failed"].as_u64()), (Some(50), Some(0)));
+    let total: u64 = names.iter().map(|name| fs::metadata(tmp.join(name)).unwrap().len()).sum();
+    assert_eq!(dir["input_bytes"], total);
+    assert_eq!(dir["output_bytes"], outputs.iter().map(|frame| frame.len() as u64).sum::<u64>());
+    assert_eq!(batch["succeeded"], 50);
+    assert!(jobs == outputs, "a manifest job wrote a different frame from compress-dir");
+    assert!(runs[0] == runs[1], "--threads 4 differs from --threads 1");
+
+    let text = stdout(&run_ok(tmp.path(), &["--threads", "4", "batch", "--manifest", "jobs.tsv", "-f"]));
+    assert!(text.ends_with("50 of 50 jobs done; 0 failed, 0 not run\n"), "{}", text);
+}
+
+#[test]
+fn inputs_sharing_an_output_resolve_as_one_at_a_time() {
+    let (tmp, names) = setup();
+    let duplicated = format!("{}\tm/same.aapc\n{}\tm/same.aapc\n", names[0], names[1]);
+    tmp.write("dup.tsv", duplicated);
+    for threads in ["1", "4"] {
+        let output = run(tmp.path(), &["--threads", threads, "batch", "--manifest", "dup.tsv", "--parents", "-f"]);
+        assert_eq!(output.status.code(), Some(1), "--threads {}", threads);
+        let log = format!("{}{}", stdout(&output), common::stderr(&output));
+        assert!(log.contains("m/same.aapc was already written by this run"), "{}", log);
+        let kept = run_ok(tmp.path(), &["decompress", "-c", "m/same.aapc"]).stdout;
+        assert!(kept == fs::read(tmp.join(&names[0])).unwrap(), "--threads {}: the second input won", threads);
+    }
+}
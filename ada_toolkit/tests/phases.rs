@@ -0,0 +1,88 @@
+//! Where each file's time went, phase by phase, in the JSON summary and
+//! the `--verbose` log.
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::{mixed_data, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(300_000));
+    tmp
+}
+
+/// The one file's `phases` from a JSON summary, in milliseconds by name,
+/// checked against its `duration_ms` and for a `busy_ms` that is their sum.
+fn file_phases(json: &str) -> BTreeMap<String, f64> {
+    let json: Value = serde_json::from_str(json).unwrap();
+    let file = &json["files"][0];
+    let phases = file["phases"].as_object().unwrap_or_else(|| panic!("no phases in {}", file));
+    let phases: BTreeMap<String, f64> = phases.iter().map(|(key, ms)| (key.clone(), ms.as_f64().unwrap())).collect();
+    assert!(phases["wall_ms"] > 0.0 && phases["wall_ms"] <= file["duration_ms"].as_f64().unwrap(), "{}", file);
+    let timed = phases.iter().filter(|(key, _)| !matches!(key.as_str(), "wall_ms" | "busy_ms"));
+    let sum: f64 = timed.map(|(_, ms)| ms).sum();
+    assert!((phases["busy_ms"] - sum).abs() < 0.01, "busy is not the phases' sum {}: {:?}", sum, phases);
+    phases
+}
+
+/// Checks that `phases` have `names` and, taken under `--threads 1`,
+/// account for most of the wall time and never for more than one thread's
+/// worth of it: no phase, the checksum least of all, is counted twice.
+fn assert_accounted(phases: &BTreeMap<String, f64>, names: [&str; 6]) {
+    let mut expected: Vec<&str> = names.to_vec();
+    expected.sort_unstable();
+    assert_eq!(phases.keys().map(String::as_str).collect::<Vec<_>>(), expected);
+    let (busy, wall) = (phases["busy_ms"], phases["wall_ms"]);
+    assert!(busy <= wall && busy * 2.0 >= wall, "busy {} of wall {}", busy, wall);
+}
+
+#[test]
+fn compressing_one_thread_accounts_for_its_wall_time() {
+    let tmp = setup();
+    // Read or mapped, one thread does it all, with no reader alongside.
+    for how in [&["--mmap"][..], &[]] {
+        let args: Vec<&str> = ["--format", "json", "--threads", "1", "compress", "in.bin", "-f"].iter().chain(how)
+            .copied()
+            .collect();
+        let phases = file_phases(&stdout(&run_ok(tmp.path(), &args)));
+        assert_accounted(&phases, ["wall_ms", "read_ms", "encode_ms", "checksum_ms", "write_ms", "busy_ms"]);
+    }
+
+    // With a reader thread alongside, overlapping phases may pass the wall
+    // time, but are still there.
+    let args = ["--format", "json", "compress", "in.bin", "-f"];
+    let phases = file_phases(&stdout(&run_ok(tmp.path(), &args)));
+    for phase in ["read_ms", "encode_ms", "checksum_ms", "write_ms"] {
+        assert!(phases[phase] > 0.0, "{:?}", phases);
+    }
+}
+
+#[test]
+fn decompressing_accounts_for_its_wall_time() {
+    let tmp = setup();
+    run_ok(tmp.path(), &["compress", "in.bin"]);
+    let args = ["--format", "json", "--threads", "1", "decompress", "in.bin.aapc", "-o", "out.bin"];
+    let phases = file_phases(&stdout(&run_ok(tmp.path(), &args)));
+    assert_accounted(&phases, ["wall_ms", "read_ms", "decode_ms", "verify_ms", "write_ms", "busy_ms"]);
+}
+
+#[test]
+fn verbose_logs_a_line_of_phases_per_file() {
+    let tmp = setup();
+    let log = stderr(&run_ok(tmp.path(), &["--verbose", "compress", "in.bin"]));
+    let line = log.lines().find(|line| line.contains("Phases for in.bin: wall ")).unwrap_or_else(|| panic!("{}", log));
+    for phase in ["; read ", "%), encode ", "%), checksum ", "%), write ", "%); busy "] {
+        assert!(line.contains(phase), "{}", line);
+    }
+
+    let log = stderr(&run_ok(tmp.path(), &["--verbose", "decompress", "in.bin.aapc", "-o", "out.bin"]));
+    let line = log.lines().find(|line| line.contains("Phases for in.bin.aapc: wall "));
+    let line = line.unwrap_or_else(|| panic!("{}", log));
+    assert!(line.contains("%), decode ") && line.contains("%), verify "), "{}", line);
+
+    let quiet = stderr(&run_ok(tmp.path(), &["compress", "in.bin", "-f"]));
+    assert!(!quiet.contains("Phases for"), "{}", quiet);
+}
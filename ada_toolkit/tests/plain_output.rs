@@ -0,0 +1,73 @@
+//! Decoration only at a terminal: redirected output, `--plain`,
+//! `--no-color` and NO_COLOR all leave just the result lines.
+
+mod common;
+
+use std::process::{Command, Output};
+
+use common::{mixed_data, stderr, stdout, TempDir};
+
+/// The CLI as a user would run it, without the `--plain` the other tests
+/// pass, and with NO_COLOR cleared.
+fn bare(dir: &std::path::Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ada_toolkit")).current_dir(dir).args(args).env_remove("NO_COLOR")
+        .output().unwrap()
+}
+
+fn assert_undecorated(output: &Output, what: &str) {
+    let all = format!("{}{}", stdout(output), stderr(output));
+    assert!(!all.contains('\x1b'), "{}: escape codes in {:?}", what, all);
+    assert!(!all.contains('\r'), "{}: progress redraws in {:?}", what, all);
+    assert!(!all.contains("symphony"), "{}: banner in {:?}", what, all);
+}
+
+#[test]
+fn redirected_output_is_plain() {
+    let tmp = TempDir::new();
+    tmp.write("data/in.bin", mixed_data(300_000));
+    let runs = [&["test", "--size", "100k", "--seed", "3"][..], &["compress", "data/in.bin"],
+                &["verify", "data/in.bin.aapc"], &["decompress", "-f", "data/in.bin.aapc"], &["test-folder", "data"],
+                &["info", "data/in.bin.aapc"]];
+    for args in runs {
+        let output = bare(tmp.path(), args);
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        assert_undecorated(&output, &args.join(" "));
+    }
+}
+
+#[test]
+fn errors_are_plain_too() {
+    let tmp = TempDir::new();
+    tmp.write("junk.aapc", "not a frame");
+    let output = bare(tmp.path(), &["decompress", "junk.aapc", "-o", "out"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_undecorated(&output, "decompress junk");
+}
+
+/// Runs `args` under `script`, which gives the CLI a pseudo-terminal, or
+/// `None` where `script` is missing.
+#[cfg(target_os = "linux")]
+fn at_a_terminal(dir: &std::path::Path, args: &str, env: &[(&str, &str)]) -> Option<String> {
+    let command = format!("{} {}", env!("CARGO_BIN_EXE_ada_toolkit"), args);
+    let output = Command::new("script").args(["-qec", &command, "/dev/null"]).current_dir(dir)
+        .env_remove("NO_COLOR").envs(env.iter().copied()).output().ok()?;
+    output.status.success().then(|| stdout(&output))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_terminal_gets_the_banner_unless_told_otherwise() {
+    let tmp = TempDir::new();
+    let Some(decorated) = at_a_terminal(tmp.path(), "test --size 10k --seed 1", &[]) else { return };
+    assert!(decorated.starts_with("Continuing our symphony of compression"), "{:?}", decorated);
+    let plain_runs = [("--plain test --size 10k --seed 1", &[][..]), ("--no-color test --size 10k --seed 1", &[]),
+                      ("test --size 10k --seed 1", &[("NO_COLOR", "1")])];
+    for (args, env) in plain_runs {
+        let plain = at_a_terminal(tmp.path(), args, env).unwrap();
+        assert!(plain.starts_with("Profile: mixed, 10.00 KiB (seed 1;"), "{} {:?}: {:?}", args, env, plain);
+        assert!(!plain.contains('\x1b') && !plain.contains("symphony"), "{} {:?}: {:?}", args, env, plain);
+    }
+    // An empty NO_COLOR does not count as set.
+    let empty = at_a_terminal(tmp.path(), "test --size 10k --seed 1", &[("NO_COLOR", "")]).unwrap();
+    assert!(empty.contains("symphony"), "{:?}", empty);
+}
@@ -0,0 +1,104 @@
+//! Once the internal buffer pool is warm, a stream of blocks, flushed one
+//! at a time or decoded one after another, and a run of small files go to
+//! the allocator next to never.
+//!
+//! This file is its own test binary so that the counting allocator sees
+//! only what the one test in it allocates.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ada_toolkit::stream::AapcWriter;
+use ada_toolkit::{compress_with_options, copy_decode, decompress_into, CompressOptions, CompressionStats};
+
+struct Counting;
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Allocations and reallocations made by `f`.
+fn allocations_of<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = CALLS.load(Ordering::Relaxed);
+    let result = f();
+    (result, CALLS.load(Ordering::Relaxed) - before)
+}
+
+const BLOCK: usize = 16 * 1024;
+const BLOCKS: usize = 200;
+
+fn chunk(i: usize) -> Vec<u8> {
+    (0..BLOCK).map(|j| if j % 700 < 300 { i as u8 } else { (j * 7 + i) as u8 }).collect()
+}
+
+/// Writes `BLOCKS` chunks to an `AapcWriter`, flushing after each so that
+/// each is its own block, into a buffer with room for the whole frame.
+fn flushed_frame(opts: &CompressOptions, chunks: &[Vec<u8>]) -> (Vec<u8>, CompressionStats) {
+    let mut writer = AapcWriter::with_options(Vec::with_capacity(2 * BLOCK * BLOCKS), opts).unwrap();
+    for chunk in chunks {
+        writer.write_all(chunk).unwrap();
+        writer.flush().unwrap();
+    }
+    let (frame, stats) = writer.finish_with_stats().unwrap();
+    assert_eq!(stats.blocks as usize, BLOCKS);
+    (frame, stats)
+}
+
+#[test]
+fn blocks_stop_allocating_once_the_pool_is_warm() {
+    let opts = CompressOptions { block_size: BLOCK, ..CompressOptions::default() };
+    let chunks: Vec<Vec<u8>> = (0..BLOCKS).map(chunk).collect();
+
+    // The first frame fills the pool; the second takes from it.
+    let (frame, _) = flushed_frame(&opts, &chunks);
+    let ((again, stats), calls) = allocations_of(|| flushed_frame(&opts, &chunks));
+    assert_eq!((again, stats.input_bytes as usize), (frame.clone(), BLOCK * BLOCKS));
+    assert!(calls <= 10, "{} allocations for {} flushed blocks", calls, BLOCKS);
+    #[cfg(debug_assertions)]
+    {
+        assert!(stats.pool.taken >= 2, "{:?}", stats.pool);
+        assert_eq!(stats.pool.allocated, 0, "{:?}", stats.pool);
+    }
+
+    copy_decode(frame.as_slice(), io::sink(), None, None).unwrap();
+    let (written, calls) = allocations_of(|| copy_decode(frame.as_slice(), io::sink(), None, None).unwrap());
+    assert_eq!(written as usize, BLOCK * BLOCKS);
+    assert!(calls <= 10, "{} allocations to decode {} blocks", calls, BLOCKS);
+
+    // Small files one after another: what is left is a couple of small
+    // allocations each, its frame among them, and decoding into a reused
+    // buffer needs none.
+    let small: Vec<Vec<u8>> = chunks.iter().map(|chunk| chunk[..3000].to_vec()).collect();
+    let frames: Vec<Vec<u8>> = small.iter().map(|data| compress_with_options(data, &opts).unwrap()).collect();
+    let (_, calls) = allocations_of(|| {
+        for (data, frame) in small.iter().zip(&frames) {
+            assert_eq!(&compress_with_options(data, &opts).unwrap(), frame);
+        }
+    });
+    assert!(calls <= 2 * BLOCKS, "{} allocations for {} small files", calls, BLOCKS);
+    let mut decoded = Vec::with_capacity(BLOCK);
+    let (_, calls) = allocations_of(|| {
+        for frame in &frames {
+            decompress_into(frame, &mut decoded).unwrap();
+        }
+    });
+    assert!(calls <= 10, "{} allocations to decode {} small files", calls, BLOCKS);
+}
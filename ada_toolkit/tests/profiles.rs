@@ -0,0 +1,51 @@
+//! `test --size` and `--profile`: every profile round trips at the size
+//! asked for, and the summary names both.
+
+mod common;
+
+use common::{run, run_ok, stderr, stdout, TempDir};
+
+const PROFILES: [&str; 5] = ["runs", "random", "text", "sparse", "mixed"];
+
+#[test]
+fn every_profile_round_trips_at_the_size_asked_for() {
+    let tmp = TempDir::new();
+    for profile in PROFILES {
+        for (size, bytes) in [("1", 1), ("100k", 102_400), ("1M", 1 << 20)] {
+            let args = ["--format", "json", "test", "--profile", profile, "--size", size, "--seed", "3"];
+            let report: serde_json::Value = serde_json::from_str(&stdout(&run_ok(tmp.path(), &args))).unwrap();
+            assert_eq!(report["status"], "ok", "{} {}", profile, size);
+            assert_eq!(report["profile"], profile);
+            assert_eq!(report["input_bytes"], bytes, "{} {}", profile, size);
+        }
+    }
+}
+
+#[test]
+fn the_summary_names_the_profile_and_size() {
+    let tmp = TempDir::new();
+    let text = stdout(&run_ok(tmp.path(), &["test", "--profile", "sparse", "--size", "64k", "--seed", "9"]));
+    assert!(text.contains("Profile: sparse, 64.00 KiB (seed 9;"), "{}", text);
+}
+
+#[test]
+fn profiles_differ_in_ratio() {
+    let tmp = TempDir::new();
+    let ratio = |profile| {
+        let args = ["--format", "json", "test", "--profile", profile, "--size", "256k", "--seed", "1"];
+        let report: serde_json::Value = serde_json::from_str(&stdout(&run_ok(tmp.path(), &args))).unwrap();
+        report["ratio"].as_f64().unwrap()
+    };
+    assert!(ratio("runs") < 0.05);
+    assert!(ratio("sparse") < ratio("random"));
+    assert!(ratio("random") >= 1.0);
+}
+
+#[test]
+fn unknown_profiles_and_bad_sizes_are_usage_errors() {
+    let tmp = TempDir::new();
+    for args in [&["test", "--profile", "video"][..], &["test", "--size", "lots"], &["test", "in.bin", "--size", "1k"]] {
+        let output = run(tmp.path(), args);
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", args, stderr(&output));
+    }
+}
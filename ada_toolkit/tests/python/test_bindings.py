@@ -0,0 +1,85 @@
+"""Tests for the `ada_compression` extension module.
+
+Build it into the active environment with the `python` feature, then run
+pytest on this directory:
+
+    maturin develop --features python
+    pytest tests/python
+"""
+
+import pytest
+
+import ada_compression as aapc
+
+RUNS = b"\0" * 10_000 + b"abc" * 1000 + b"\xff" * 5000
+
+
+@pytest.mark.parametrize("data", [b"", b"x", bytes(range(256)) * 40, RUNS])
+def test_round_trip(data):
+    assert aapc.decompress(aapc.compress(data)) == data
+
+
+def test_runs_shrink():
+    assert len(aapc.compress(RUNS)) < len(RUNS) // 4
+
+
+@pytest.mark.parametrize("level", [0, 1, 5, 9, 10])
+def test_every_level_is_refused_as_on_the_command_line(level):
+    with pytest.raises(ValueError, match="has no effect: the codec has only one level"):
+        aapc.compress(b"data", level)
+    with pytest.raises(ValueError, match="has no effect: the codec has only one level"):
+        aapc.Compressor(level=level)
+
+
+def test_no_level_is_the_default():
+    assert aapc.compress(RUNS, level=None) == aapc.compress(RUNS)
+    compressor = aapc.Compressor(level=None)
+    assert aapc.decompress(compressor.write(RUNS) + compressor.finish()) == RUNS
+
+
+def test_errors_share_a_base_class():
+    for error in [aapc.TruncatedError, aapc.FormatError, aapc.LimitExceededError, aapc.ChecksumError]:
+        assert issubclass(error, aapc.AapcError)
+    assert issubclass(aapc.AapcError, ValueError)
+
+
+def test_bad_magic_is_a_format_error():
+    with pytest.raises(aapc.FormatError, match="bad magic"):
+        aapc.decompress(b"not a frame at all")
+
+
+def test_truncated_frame():
+    blob = aapc.compress(RUNS)
+    with pytest.raises(aapc.TruncatedError):
+        aapc.decompress(blob[:-3])
+
+
+def test_flipped_byte_fails_the_checksum():
+    blob = bytearray(aapc.compress(bytes(range(100))))
+    blob[50] ^= 1
+    with pytest.raises(aapc.ChecksumError):
+        aapc.decompress(bytes(blob))
+
+
+def test_max_size():
+    blob = aapc.compress(RUNS)
+    assert aapc.decompress(blob, max_size=len(RUNS)) == RUNS
+    with pytest.raises(aapc.LimitExceededError):
+        aapc.decompress(blob, max_size=len(RUNS) - 1)
+
+
+def test_compressor_streams_one_frame():
+    compressor = aapc.Compressor()
+    pieces = [compressor.write(RUNS[i:i + 777]) for i in range(0, len(RUNS), 777)]
+    pieces.append(compressor.flush())
+    pieces.append(compressor.write(b"tail"))
+    pieces.append(compressor.finish())
+    assert aapc.decompress(b"".join(pieces)) == RUNS + b"tail"
+
+
+def test_compressor_after_finish():
+    compressor = aapc.Compressor()
+    compressor.finish()
+    for call in [lambda: compressor.write(b"more"), compressor.flush, compressor.finish]:
+        with pytest.raises(aapc.AapcError, match="already finished"):
+            call()
@@ -0,0 +1,95 @@
+//! Inputs that are already AAPC, told by their content whatever their
+//! name: skipped with a warning in runs over several files, compressed
+//! again with one alone, and compressed either way with --recompress.
+
+mod common;
+
+use common::{run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+/// d/ with a plain file, a frame, a small frame and an archive under
+/// names that do not give them away, and text that starts with the magic.
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("plain.bin", vec![0u8; 20_000]);
+    tmp.write("hi.txt", "hi\n");
+    tmp.write("tree/x", "x\n");
+    tmp.write("d/plain.bin", vec![0u8; 20_000]);
+    run_ok(tmp.path(), &["compress", "plain.bin", "-o", "d/frame.dat"]);
+    run_ok(tmp.path(), &["compress", "hi.txt", "-o", "d/small.dat"]);
+    run_ok(tmp.path(), &["archive", "create", "d/arch.dat", "tree"]);
+    tmp.write("d/fake.txt", "AAPC is how this text starts, and nothing more\n");
+    tmp
+}
+
+const INPUTS: [&str; 5] = ["d/plain.bin", "d/frame.dat", "d/fake.txt", "d/small.dat", "d/arch.dat"];
+
+#[test]
+fn several_inputs_skip_those_already_compressed() {
+    let tmp = setup();
+    let output = run_ok(tmp.path(), &[&["compress"][..], &INPUTS].concat());
+    let log = stderr(&output);
+    for (input, kind) in [("d/frame.dat", "AAPC"), ("d/small.dat", "AAPC"), ("d/arch.dat", "AAPC archive")] {
+        let warning = format!("WARN: Skipping {}: already compressed ({}); use --recompress to compress it again",
+                              input, kind);
+        assert!(log.contains(&warning), "{}", log);
+        assert!(!tmp.join(&format!("{}.aapc", input)).exists(), "{} was compressed", input);
+    }
+    assert!(!log.contains("fake.txt"), "{}", log);
+    assert!(tmp.join("d/plain.bin.aapc").is_file() && tmp.join("d/fake.txt.aapc").is_file());
+    assert!(stdout(&output).contains("2 of 5 files compressed; 0 failed, 3 skipped as already compressed"),
+            "{}", stdout(&output));
+
+    let output = run_ok(tmp.path(), &["--format", "json", "compress", "d/frame.dat", "d/plain.bin", "-f"]);
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report["files"][0]["status"], "skipped");
+    assert_eq!(report["files"][0]["detected"], "AAPC");
+    assert_eq!(report["skipped_compressed"], 1);
+}
+
+#[test]
+fn a_lone_input_is_compressed_again_with_a_warning() {
+    let tmp = setup();
+    let output = run_ok(tmp.path(), &["compress", "d/frame.dat"]);
+    assert!(stderr(&output).contains("WARN: d/frame.dat is already compressed (AAPC); compressing it again"),
+            "{}", stderr(&output));
+    let twice = run_ok(tmp.path(), &["decompress", "-c", "d/frame.dat.aapc"]).stdout;
+    assert_eq!(twice, std::fs::read(tmp.join("d/frame.dat")).unwrap());
+}
+
+#[test]
+fn recompress_compresses_them_all() {
+    let tmp = setup();
+    let output = run_ok(tmp.path(), &[&["compress", "--recompress"][..], &INPUTS].concat());
+    assert!(!stderr(&output).contains("WARN"), "{}", stderr(&output));
+    for input in INPUTS {
+        assert!(tmp.join(&format!("{}.aapc", input)).is_file(), "{} was skipped", input);
+    }
+
+    let output = run_ok(tmp.path(), &["compress-dir", "d", "out"]);
+    assert!(stdout(&output).contains("Compressed 2 files from d to out"), "{}", stdout(&output));
+    // The frames the run above made are skipped too.
+    assert!(stdout(&output).contains(", 8 skipped as already compressed"), "{}", stdout(&output));
+    let output = run_ok(tmp.path(), &["compress-dir", "d", "all", "--recompress"]);
+    assert!(stdout(&output).contains("Compressed 10 files from d to all"), "{}", stdout(&output));
+    assert!(tmp.join("all/arch.dat.aapc").is_file());
+}
+
+#[cfg(unix)]
+#[test]
+fn a_pipe_is_compressed_whole_without_a_look() {
+    let tmp = TempDir::new();
+    tmp.write("plain.bin", common::mixed_data(100_000));
+    run_ok(tmp.path(), &["compress", "plain.bin", "--block-size", "16k"]);
+    // A frame, which a look at the pipe would both recognise and use up.
+    let frame = std::fs::read(tmp.join("plain.bin.aapc")).unwrap();
+    let made = std::process::Command::new("mkfifo").arg(tmp.join("fifo")).status().unwrap();
+    assert!(made.success());
+    let path = tmp.join("fifo");
+    let sent = frame.clone();
+    let feed = std::thread::spawn(move || std::fs::write(path, sent).unwrap());
+    let output = run_ok(tmp.path(), &["compress", "fifo", "-o", "fifo.aapc"]);
+    feed.join().unwrap();
+    assert!(!stderr(&output).contains("already compressed"), "{}", stderr(&output));
+    assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "fifo.aapc"]).stdout, frame);
+}
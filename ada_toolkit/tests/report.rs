@@ -0,0 +1,100 @@
+//! --report: one JSON document per run, listing every file's outcome,
+//! written when the run ends however it ends.
+
+mod common;
+
+use std::fs;
+#[cfg(unix)]
+use std::fs::File;
+
+use common::{cli, mixed_data, run, stderr, TempDir};
+use serde_json::Value;
+
+fn report(tmp: &TempDir) -> Value {
+    serde_json::from_str(&fs::read_to_string(tmp.join("report.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn a_compress_report_lists_every_file_and_the_run() {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", mixed_data(50_000));
+    tmp.write("b.bin", mixed_data(20_000));
+    let output = run(tmp.path(), &["compress", "a.bin", "missing.bin", "b.bin", "--report", "report.json"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+
+    let report = report(&tmp);
+    assert_eq!(report["report_version"], 1);
+    assert_eq!(report["operation"], "compress");
+    assert_eq!(report["status"], "complete");
+    assert!(report["aapc_version"].is_string());
+    assert!(report["finished"].as_u64().unwrap() >= report["started"].as_u64().unwrap());
+    assert_eq!(report["options"]["block_size"], 262_144);
+    assert_eq!(report["options"]["max_memory"], Value::Null);
+    let totals = &report["totals"];
+    assert_eq!((totals["files"].as_u64(), totals["succeeded"].as_u64(), totals["failed"].as_u64()),
+               (Some(3), Some(2), Some(1)));
+    assert_eq!(totals["input_bytes"], 70_000);
+
+    let files = report["files"].as_array().unwrap();
+    let inputs: Vec<&str> = files.iter().map(|file| file["input"].as_str().unwrap()).collect();
+    assert_eq!(inputs, ["a.bin", "missing.bin", "b.bin"]);
+    let a = &files[0];
+    assert_eq!((a["status"].as_str(), a["output"].as_str()), (Some("ok"), Some("a.bin.aapc")));
+    assert_eq!(a["output_bytes"], fs::metadata(tmp.join("a.bin.aapc")).unwrap().len());
+    assert_eq!(a["checksum"].as_str().unwrap().len(), 8);
+    assert!(a["duration_ms"].is_f64() && a["ratio"].as_f64().unwrap() < 1.0);
+    let missing = &files[1];
+    assert_eq!(missing["status"], "error");
+    assert_eq!(missing["exit_code"], 1);
+    assert!(missing["error"].as_str().unwrap().starts_with("reading input missing.bin: "), "{}", missing);
+}
+
+#[test]
+fn a_decompress_report_names_the_corrupt_frame() {
+    let tmp = TempDir::new();
+    tmp.write("bad.aapc", "not a frame at all");
+    let output = run(tmp.path(), &["decompress", "bad.aapc", "--report", "report.json"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+
+    let report = report(&tmp);
+    assert_eq!(report["operation"], "decompress");
+    let bad = &report["files"][0];
+    assert_eq!((bad["status"].as_str(), bad["exit_code"].as_u64()), (Some("error"), Some(3)));
+    assert!(bad["error"].as_str().unwrap().contains("bad magic"), "{}", bad);
+}
+
+#[test]
+fn a_run_that_cannot_start_still_writes_an_aborted_report() {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", "data");
+    let output = run(tmp.path(), &["compress", "a.bin", "--output-dir", "absent", "--report", "report.json"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let report = report(&tmp);
+    assert_eq!(report["status"], "aborted");
+    assert_eq!(report["totals"]["processed"], 0);
+    assert_eq!(report["files"], Value::Array(Vec::new()));
+}
+
+#[cfg(unix)]
+#[test]
+fn a_run_cut_short_reports_the_file_it_finished() {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", mixed_data(1000));
+    tmp.write("b.bin", mixed_data(2000));
+    let Ok(full) = File::create("/dev/full") else { return };
+    let output = cli(tmp.path()).args(["--threads", "1", "compress", "a.bin", "b.bin", "--report", "report.json"])
+        .stdout(full)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+
+    let report = report(&tmp);
+    assert_eq!(report["status"], "aborted");
+    assert_eq!(report["files"].as_array().unwrap().len(), 1);
+    assert_eq!(report["files"][0]["input"], "a.bin");
+    let mut left: Vec<_> = fs::read_dir(tmp.path()).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["a.bin", "a.bin.aapc", "b.bin", "report.json"]);
+}
@@ -0,0 +1,66 @@
+//! `--rm` deletes the input only once its output is safely written.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, TempDir};
+
+#[test]
+fn rm_removes_the_input_after_success() {
+    let tmp = TempDir::new();
+    let data = mixed_data(400_000);
+    tmp.write("in.bin", &data);
+    run_ok(tmp.path(), &["compress", "in.bin"]);
+    assert!(tmp.join("in.bin").is_file(), "the input is kept by default");
+
+    run_ok(tmp.path(), &["compress", "--rm", "-f", "in.bin"]);
+    assert!(!tmp.join("in.bin").exists());
+    run_ok(tmp.path(), &["decompress", "--rm", "in.bin.aapc"]);
+    assert!(!tmp.join("in.bin.aapc").exists());
+    assert_eq!(std::fs::read(tmp.join("in.bin")).unwrap(), data);
+}
+
+#[test]
+fn rm_keeps_the_input_when_writing_fails() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(400_000));
+    // A read-only directory keeps out all but a privileged user.
+    std::fs::create_dir(tmp.join("ro")).unwrap();
+    let mut perms = std::fs::metadata(tmp.join("ro")).unwrap().permissions();
+    perms.set_readonly(true);
+    std::fs::set_permissions(tmp.join("ro"), perms).unwrap();
+    if std::fs::write(tmp.join("ro/probe"), "").is_err() {
+        let output = run(tmp.path(), &["compress", "--rm", "in.bin", "--output-dir", "ro"]);
+        assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+        assert!(tmp.join("in.bin").is_file(), "the input was removed");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let output = run(tmp.path(), &["compress", "--rm", "-f", "in.bin", "-o", "/dev/full"]);
+        assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+        assert!(stderr(&output).contains("No space left"), "{}", stderr(&output));
+        assert!(tmp.join("in.bin").is_file(), "the input was removed");
+    }
+}
+
+#[test]
+fn rm_keeps_a_damaged_frame() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", mixed_data(400_000));
+    run_ok(tmp.path(), &["compress", "in.bin", "-o", "good.aapc"]);
+    let frame = std::fs::read(tmp.join("good.aapc")).unwrap();
+    tmp.write("cut.aapc", &frame[..frame.len() - 10]);
+    let output = run(tmp.path(), &["decompress", "--rm", "cut.aapc"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(tmp.join("cut.aapc").is_file(), "the damaged input was removed");
+    assert!(!tmp.join("cut").exists(), "a partial output was left behind");
+}
+
+#[test]
+fn rm_never_removes_an_input_written_to_stdout() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    let output = run_ok(tmp.path(), &["compress", "--rm", "in.bin", "-o", "-"]);
+    assert!(!output.stdout.is_empty());
+    assert!(tmp.join("in.bin").is_file(), "the input was removed");
+}
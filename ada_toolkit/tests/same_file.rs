@@ -0,0 +1,47 @@
+//! An output that is the input under another name is refused before it is
+//! opened, even with `-f`.
+
+mod common;
+
+use common::{run, run_ok, stderr, TempDir};
+
+fn assert_same_file(tmp: &TempDir, args: &[&str], content: &[u8]) {
+    let output = run(tmp.path(), args);
+    assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr(&output));
+    assert!(stderr(&output).contains("input and output are the same file"), "{:?}: {}", args, stderr(&output));
+    assert_eq!(std::fs::read(tmp.join("x")).unwrap(), content, "{:?} changed the input", args);
+}
+
+#[test]
+fn the_same_path_twice() {
+    let tmp = TempDir::new();
+    tmp.write("x", "data");
+    std::fs::create_dir(tmp.join("sub")).unwrap();
+    assert_same_file(&tmp, &["compress", "x", "-o", "x", "-f"], b"data");
+    assert_same_file(&tmp, &["compress", "./x", "-o", "x", "-f"], b"data");
+    assert_same_file(&tmp, &["compress", "x", "-o", "sub/../x", "-f"], b"data");
+}
+
+#[test]
+fn decompressing_onto_the_frame() {
+    let tmp = TempDir::new();
+    tmp.write("in", "data");
+    run_ok(tmp.path(), &["compress", "in", "-o", "x"]);
+    let frame = std::fs::read(tmp.join("x")).unwrap();
+    std::fs::create_dir(tmp.join("sub")).unwrap();
+    assert_same_file(&tmp, &["decompress", "x", "-o", "./x", "-f"], &frame);
+    assert_same_file(&tmp, &["decompress", "./sub/../x", "--output", "x", "-f"], &frame);
+}
+
+#[cfg(unix)]
+#[test]
+fn links_to_the_input() {
+    let tmp = TempDir::new();
+    tmp.write("x", "data");
+    std::os::unix::fs::symlink("x", tmp.join("link")).unwrap();
+    std::fs::hard_link(tmp.join("x"), tmp.join("hard")).unwrap();
+    assert_same_file(&tmp, &["compress", "x", "-o", "link", "-f"], b"data");
+    assert_same_file(&tmp, &["compress", "link", "-o", "x", "-f"], b"data");
+    assert_same_file(&tmp, &["compress", "x", "-o", "hard", "-f"], b"data");
+    assert!(tmp.join("link").is_symlink(), "the output link was replaced");
+}
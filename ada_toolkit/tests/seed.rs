@@ -0,0 +1,33 @@
+//! `test --seed` makes a generated run repeatable.
+
+mod common;
+
+use common::{run_ok, stdout, TempDir};
+
+fn generated_run(tmp: &TempDir, seed: &str) -> serde_json::Value {
+    let args = ["--format", "json", "test", "--size", "200k", "--profile", "text", "--seed", seed];
+    serde_json::from_str(&stdout(&run_ok(tmp.path(), &args))).unwrap()
+}
+
+#[test]
+fn the_same_seed_gives_the_same_sizes() {
+    let tmp = TempDir::new();
+    let (first, again) = (generated_run(&tmp, "12345"), generated_run(&tmp, "12345"));
+    let other = generated_run(&tmp, "54321");
+    assert_eq!(first["seed"], 12345);
+    assert_eq!(first["input_bytes"], 204_800);
+    assert_eq!(first["compressed_bytes"], again["compressed_bytes"]);
+    assert_eq!(first["ratio"], again["ratio"]);
+    assert_eq!(other["seed"], 54321);
+    assert_ne!(first["compressed_bytes"], other["compressed_bytes"]);
+}
+
+#[test]
+fn a_random_seed_is_printed_for_repeating() {
+    let tmp = TempDir::new();
+    let text = stdout(&run_ok(tmp.path(), &["test", "--size", "10k"]));
+    let seed = text.split("repeat with --seed ").nth(1).and_then(|rest| rest.split(')').next())
+        .unwrap_or_else(|| panic!("no seed in {}", text));
+    let again = stdout(&run_ok(tmp.path(), &["test", "--size", "10k", "--seed", seed]));
+    assert!(again.contains(&format!("(seed {}; repeat with --seed {})", seed, seed)), "{}", again);
+}
@@ -0,0 +1,139 @@
+//! `--skip-compressed` and `--store-incompressible`: inputs that are
+//! already compressed are left out or stored, and never lost.
+
+mod common;
+
+use std::fs;
+
+use common::{run, run_ok, stderr, stdout, TempDir};
+
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x9E37_79B9u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// A gzip member's header and some deflate-looking bytes, a JPEG's SOI
+/// marker before random data, random data with no magic at all, and text.
+fn inputs(tmp: &TempDir, dir: &str) {
+    let mut gzip = vec![0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0x03];
+    gzip.extend(noise(3000));
+    tmp.write(&format!("{}/small.gz", dir), gzip);
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0];
+    jpeg.extend(noise(50_000));
+    tmp.write(&format!("{}/photo.jpg", dir), jpeg);
+    tmp.write(&format!("{}/noise.bin", dir), noise(100_000));
+    tmp.write(&format!("{}/notes.txt", dir), "all work and no play\n".repeat(5000));
+}
+
+const FILES: [&str; 4] = ["noise.bin", "notes.txt", "photo.jpg", "small.gz"];
+
+#[test]
+fn compress_skips_each_compressed_kind() {
+    let tmp = TempDir::new();
+    inputs(&tmp, ".");
+    let output = run_ok(tmp.path(), &["compress", "--skip-compressed", "small.gz", "photo.jpg", "noise.bin",
+                                      "notes.txt"]);
+    let text = stdout(&output);
+    assert!(text.contains("Skipped small.gz (2.94 KiB): already compressed (gzip)"), "{}", text);
+    assert!(text.contains("Skipped photo.jpg (48.83 KiB): already compressed (JPEG)"), "{}", text);
+    assert!(text.contains("Skipped noise.bin (97.66 KiB): already compressed (high-entropy data)"), "{}", text);
+    assert!(text.contains("1 of 4 files compressed; 0 failed, 3 skipped as already compressed"), "{}", text);
+    assert!(tmp.join("notes.txt.aapc").is_file());
+    for skipped in ["small.gz", "photo.jpg", "noise.bin"] {
+        assert!(!tmp.join(&format!("{}.aapc", skipped)).exists(), "{} was compressed", skipped);
+        assert!(tmp.join(skipped).is_file());
+    }
+}
+
+#[test]
+fn stored_inputs_round_trip_exactly() {
+    let tmp = TempDir::new();
+    inputs(&tmp, ".");
+    let mut args = vec!["--format", "json", "compress", "--skip-compressed", "--store-incompressible", "--rm"];
+    args.extend(FILES);
+    let json = stdout(&run_ok(tmp.path(), &args));
+    assert!(json.contains("\"succeeded\":4,") && json.contains("\"stored\":3,"), "{}", json);
+    assert!(json.contains("\"input\":\"notes.txt\",\"output\":\"notes.txt.aapc\",\"status\":\"ok\",\"detected\":null"),
+            "{}", json);
+    for (file, kind) in [("noise.bin", "high-entropy data"), ("photo.jpg", "JPEG"), ("small.gz", "gzip")] {
+        let entry = format!("\"input\":\"{}\",\"output\":\"{}.aapc\",\"status\":\"stored\",\"detected\":\"{}\"",
+                            file, file, kind);
+        assert!(json.contains(&entry), "{}", json);
+        let blocks = stdout(&run_ok(tmp.path(), &["inspect", &format!("{}.aapc", file)]));
+        let rows: Vec<&str> = blocks.lines().skip(1).take_while(|line| !line.contains("blocks,")).collect();
+        assert!(!rows.is_empty() && rows.iter().all(|row| row.contains("stored (2)")), "{}: {}", file, blocks);
+    }
+
+    // --rm took the originals, so only the frames can bring them back.
+    let original = tmp.join("orig");
+    inputs(&tmp, "orig");
+    for file in FILES {
+        assert!(!tmp.join(file).exists());
+        run_ok(tmp.path(), &["decompress", &format!("{}.aapc", file)]);
+        assert_eq!(fs::read(tmp.join(file)).unwrap(), fs::read(original.join(file)).unwrap(), "{} changed", file);
+    }
+}
+
+#[test]
+fn compress_dir_counts_what_it_skipped_or_stored() {
+    let tmp = TempDir::new();
+    inputs(&tmp, "d");
+    let skipped = stdout(&run_ok(tmp.path(), &["compress-dir", "--skip-compressed", "d", "o"]));
+    assert!(skipped.contains("Compressed 1 files from d to o"), "{}", skipped);
+    assert!(skipped.contains("0 filtered out, 3 skipped as already compressed"), "{}", skipped);
+    assert!(tmp.join("o/notes.txt.aapc").is_file() && !tmp.join("o/photo.jpg.aapc").exists());
+
+    let args = ["compress-dir", "--skip-compressed", "--store-incompressible", "d", "s"];
+    let stored = stdout(&run_ok(tmp.path(), &args));
+    assert!(stored.contains("Compressed 4 files from d to s"), "{}", stored);
+    assert!(stored.contains("3 stored as already compressed"), "{}", stored);
+    run_ok(tmp.path(), &["decompress", "--recursive", "s"]);
+    for file in FILES {
+        let restored = fs::read(tmp.join(&format!("s/{}", file))).unwrap();
+        assert_eq!(restored, fs::read(tmp.join(&format!("d/{}", file))).unwrap(), "{} changed", file);
+    }
+}
+
+#[test]
+fn archive_create_leaves_out_compressed_members() {
+    let tmp = TempDir::new();
+    inputs(&tmp, "d");
+    let text = stdout(&run_ok(tmp.path(), &["archive", "create", "--skip-compressed", "a.aapa", "d"]));
+    assert!(text.contains("Archived 1 files, 1 directories and 0 symlinks"), "{}", text);
+    assert!(text.contains("3 skipped as already compressed"), "{}", text);
+    let listing = stdout(&run_ok(tmp.path(), &["archive", "list", "a.aapa"]));
+    assert!(listing.contains("d/notes.txt") && !listing.contains("d/photo.jpg"), "{}", listing);
+
+    // Storing needs per-member options, which archives do not have.
+    let output = run(tmp.path(), &["archive", "create", "--store-incompressible", "b.aapa", "d"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn a_short_input_is_never_judged_by_entropy() {
+    let tmp = TempDir::new();
+    tmp.write("short.bin", noise(1000));
+    let text = stdout(&run_ok(tmp.path(), &["compress", "--skip-compressed", "short.bin"]));
+    assert!(text.contains("Compressed short.bin"), "{}", text);
+}
+
+#[cfg(unix)]
+#[test]
+fn a_pipe_is_compressed_whole_without_a_look() {
+    let tmp = TempDir::new();
+    let made = std::process::Command::new("mkfifo").arg(tmp.join("fifo")).status().unwrap();
+    assert!(made.success());
+    let path = tmp.join("fifo");
+    let feed = std::thread::spawn(move || fs::write(path, noise(200_000)).unwrap());
+    let output = run_ok(tmp.path(), &["compress", "fifo", "--skip-compressed", "-o", "fifo.aapc"]);
+    feed.join().unwrap();
+    assert!(!stderr(&output).contains("already compressed"), "{}", stderr(&output));
+    assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "fifo.aapc"]).stdout, noise(200_000));
+}
@@ -0,0 +1,123 @@
+//! Content under `SMALL_FRAME_LIMIT` is written as a small frame, whose
+//! overhead the frame docs promise, and every decoder reads it back.
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+
+use ada_toolkit::frame::{small_frame_overhead, SMALL_FRAME_LIMIT};
+use ada_toolkit::stream::{AapcReader, AapcWriter};
+use ada_toolkit::{
+    compress, compress_with_options, copy_decode, copy_encode, copy_encode_pipelined, decompress, validate,
+    CompressOptions,
+};
+use common::{run_ok, stdout, TempDir};
+
+/// Bytes with no runs and no flag bytes, so that they are stored as is.
+fn stored(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 250) as u8 + (i / 250 % 2) as u8).collect()
+}
+
+/// Decodes `frame` through the slice, reader and streaming decoders.
+fn round_trip(frame: &[u8], data: &[u8]) {
+    assert!(decompress(frame).unwrap() == data, "decompress of {} bytes", data.len());
+    let mut read = Vec::new();
+    AapcReader::new(frame).read_to_end(&mut read).unwrap();
+    assert!(read == data, "AapcReader of {} bytes", data.len());
+    let mut copied = Vec::new();
+    copy_decode(frame, &mut copied, None, None).unwrap();
+    assert!(copied == data, "copy_decode of {} bytes", data.len());
+}
+
+#[test]
+fn tiny_inputs_have_fixed_bytes() {
+    assert_eq!(compress(b""), [0xA9, 0, 0, 0, 0, 0]);
+    assert_eq!(compress(b"a"), [0xA9, 1, b'a', 0xE8, 0xB7, 0xBE, 0x43]);
+    assert_eq!(compress(&[0; 100]), [0xAB, 100, 3, 254, 100, 0, 0x99, 0x88, 0xC6, 0xCA]);
+    let opts = CompressOptions { block_checksums: false, content_checksum: false, ..CompressOptions::default() };
+    assert_eq!(compress_with_options(b"a", &opts).unwrap(), [0xA8, 1, b'a']);
+}
+
+#[test]
+fn stored_content_gains_the_promised_overhead() {
+    for len in [0, 1, 100, 127, 128, SMALL_FRAME_LIMIT - 1] {
+        let data = stored(len);
+        for checksum in [true, false] {
+            let opts = CompressOptions {
+                block_checksums: checksum,
+                content_checksum: checksum,
+                ..CompressOptions::default()
+            };
+            let frame = compress_with_options(&data, &opts).unwrap();
+            assert_eq!(frame.len(), len + small_frame_overhead(len, checksum), "{} bytes, checksum {}", len, checksum);
+            assert!(validate(&frame).unwrap().small);
+            round_trip(&frame, &data);
+        }
+    }
+    assert_eq!((small_frame_overhead(127, true), small_frame_overhead(128, true)), (6, 7));
+    assert_eq!((small_frame_overhead(127, false), small_frame_overhead(128, false)), (2, 3));
+}
+
+#[test]
+fn the_limit_and_past_it_take_a_full_frame() {
+    for len in [SMALL_FRAME_LIMIT, SMALL_FRAME_LIMIT + 1] {
+        let data = stored(len);
+        let frame = compress(&data);
+        assert_eq!(frame.len(), len + 40, "{} bytes", len);
+        assert!(!validate(&frame).unwrap().small);
+        round_trip(&frame, &data);
+    }
+    // Switched off, even a byte takes the full framing.
+    let opts = CompressOptions { small_frames: false, ..CompressOptions::default() };
+    assert_eq!(compress_with_options(b"a", &opts).unwrap().len(), 41);
+}
+
+#[test]
+fn encoding_from_a_reader_picks_the_same_frame() {
+    let opts = CompressOptions::default();
+    for len in [0, 1, 100, SMALL_FRAME_LIMIT - 1, SMALL_FRAME_LIMIT] {
+        let data = stored(len);
+        let (mut copied, mut piped) = (Vec::new(), Vec::new());
+        copy_encode(data.as_slice(), &mut copied, &opts, None).unwrap();
+        copy_encode_pipelined(data.as_slice(), &mut piped, &opts, None).unwrap();
+        assert!(copied == compress(&data), "copy_encode, {} bytes", len);
+        assert!(piped == copied, "copy_encode_pipelined, {} bytes", len);
+    }
+}
+
+#[test]
+fn the_streaming_writer_keeps_to_full_frames() {
+    // It may have written the header before it knows the content is small.
+    let mut writer = AapcWriter::new(Vec::new());
+    writer.write_all(b"a").unwrap();
+    let frame = writer.finish().unwrap();
+    assert!(!validate(&frame).unwrap().small);
+    round_trip(&frame, b"a");
+}
+
+#[test]
+fn the_command_line_writes_small_files_as_small_frames() {
+    let tmp = TempDir::new();
+    for len in [0, 3, 200, SMALL_FRAME_LIMIT - 1] {
+        let name = format!("in{}.bin", len);
+        tmp.write(&name, stored(len));
+        for to_stdout in [false, true] {
+            let frame = match to_stdout {
+                false => {
+                    run_ok(tmp.path(), &["compress", &name, "-f"]);
+                    fs::read(tmp.join(&format!("{}.aapc", name))).unwrap()
+                }
+                true => run_ok(tmp.path(), &["compress", &name, "-c"]).stdout,
+            };
+            assert_eq!(frame.len(), len + small_frame_overhead(len, true), "{} bytes, stdout {}", len, to_stdout);
+            assert!(validate(&frame).unwrap().small);
+        }
+    }
+    // With room for it, a full frame keeps the name.
+    tmp.write("big.bin", stored(SMALL_FRAME_LIMIT));
+    run_ok(tmp.path(), &["compress", "big.bin"]);
+    assert!(!validate(&fs::read(tmp.join("big.bin.aapc")).unwrap()).unwrap().small);
+    let info = stdout(&run_ok(tmp.path(), &["info", "big.bin.aapc"]));
+    assert!(info.contains("Stored filename: big.bin\n"), "{}", info);
+}
@@ -0,0 +1,71 @@
+//! `decompress --sparse`: zero runs become holes in file outputs, and
+//! everything else still gets every byte.
+
+mod common;
+
+use std::fs;
+
+use common::{run_ok, TempDir};
+
+/// 1 MiB of zeros with a little data at the start, in the middle and just
+/// before a trailing hole, so the file must be extended over it.
+fn image() -> Vec<u8> {
+    let mut data = vec![0u8; 1 << 20];
+    for (at, len) in [(0, 100), (1 << 19, 5000), ((3 << 18) + 1, 4095)] {
+        for (i, byte) in data[at..at + len].iter_mut().enumerate() {
+            *byte = 1 + (i % 250) as u8;
+        }
+    }
+    data
+}
+
+fn compressed_image() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("disk.img", image());
+    run_ok(tmp.path(), &["compress", "--rm", "disk.img"]);
+    tmp
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn zero_runs_become_holes() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmp = compressed_image();
+    run_ok(tmp.path(), &["decompress", "--sparse", "disk.img.aapc"]);
+    let meta = fs::metadata(tmp.join("disk.img")).unwrap();
+    assert_eq!(meta.len(), 1 << 20);
+    assert_eq!(fs::read(tmp.join("disk.img")).unwrap(), image());
+    // Blocks of 512 bytes; the data touches a handful of 4 KiB pages. A
+    // filesystem with no holes allocates all 1 MiB, and then there is
+    // nothing to check.
+    let probe = tmp.join("probe");
+    fs::File::create(&probe).unwrap().set_len(1 << 20).unwrap();
+    if fs::metadata(&probe).unwrap().blocks() * 512 >= 1 << 20 {
+        return;
+    }
+    assert!(meta.blocks() * 512 < 64 * 1024, "{} bytes allocated", meta.blocks() * 512);
+
+    run_ok(tmp.path(), &["decompress", "-f", "disk.img.aapc"]);
+    let dense = fs::metadata(tmp.join("disk.img")).unwrap();
+    assert!(dense.blocks() * 512 >= 1 << 20, "{} bytes allocated without --sparse", dense.blocks() * 512);
+}
+
+#[test]
+fn stdout_gets_every_byte() {
+    let tmp = compressed_image();
+    let output = run_ok(tmp.path(), &["decompress", "--sparse", "-c", "disk.img.aapc"]);
+    assert_eq!(output.stdout, image());
+}
+
+#[test]
+fn a_file_that_ends_in_data_or_is_all_zeros_keeps_its_size() {
+    let tmp = TempDir::new();
+    for (name, data) in [("ends-in-data", [vec![0u8; 100_000], vec![7; 10]].concat()), ("zeros", vec![0u8; 100_000]),
+                         ("empty", Vec::new()), ("short", vec![0u8; 10])] {
+        tmp.write(name, &data);
+        run_ok(tmp.path(), &["compress", "--rm", name]);
+        run_ok(tmp.path(), &["decompress", "--sparse", &format!("{}.aapc", name)]);
+        assert_eq!(fs::read(tmp.join(name)).unwrap(), data, "{}", name);
+    }
+}
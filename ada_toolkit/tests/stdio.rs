@@ -0,0 +1,74 @@
+//! `-` reads stdin and writes stdout, with the summary kept off stdout.
+
+mod common;
+
+use common::{mixed_data, run_ok, run_with_stdin, stderr, TempDir};
+
+#[test]
+fn piping_through_compress_and_decompress() {
+    let tmp = TempDir::new();
+    let data = mixed_data(700_000);
+    for args in [&["compress", "-"][..], &["compress", "-", "-o", "-"]] {
+        let compressed = run_with_stdin(tmp.path(), args, &data);
+        assert!(compressed.status.success(), "{:?}: {}", args, stderr(&compressed));
+        assert!(compressed.stdout.len() < data.len(), "{:?} wrote {} bytes", args, compressed.stdout.len());
+        assert!(stderr(&compressed).contains("Compressed -"), "{:?}: {}", args, stderr(&compressed));
+
+        let decompressed = run_with_stdin(tmp.path(), &["decompress", "-", "-o", "-"], &compressed.stdout);
+        assert!(decompressed.status.success(), "{}", stderr(&decompressed));
+        assert!(decompressed.stdout == data, "{:?} did not round trip", args);
+        assert!(stderr(&decompressed).contains("Decompressed -"), "{}", stderr(&decompressed));
+    }
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0, "a file was written");
+}
+
+#[test]
+fn a_compressed_file_decodes_from_stdin() {
+    let tmp = TempDir::new();
+    let data = mixed_data(300_000);
+    tmp.write("in.bin", &data);
+    run_ok(tmp.path(), &["compress", "in.bin"]);
+    let frame = std::fs::read(tmp.join("in.bin.aapc")).unwrap();
+    let output = run_with_stdin(tmp.path(), &["decompress", "-"], &frame);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout == data);
+}
+
+#[test]
+fn empty_stdin_gives_an_empty_frame() {
+    let tmp = TempDir::new();
+    let compressed = run_with_stdin(tmp.path(), &["compress", "-"], b"");
+    assert!(compressed.status.success(), "{}", stderr(&compressed));
+    let decompressed = run_with_stdin(tmp.path(), &["decompress", "-"], &compressed.stdout);
+    assert!(decompressed.status.success(), "{}", stderr(&decompressed));
+    assert!(decompressed.stdout.is_empty());
+}
+
+#[test]
+fn damaged_stdin_fails_and_says_so_on_stderr() {
+    let tmp = TempDir::new();
+    let output = run_with_stdin(tmp.path(), &["decompress", "-"], b"this is not a frame");
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("bad magic"), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn dash_dash_is_refused_rather_than_read_twice() {
+    let tmp = TempDir::new();
+    let data = mixed_data(50_000);
+    for command in ["compress", "decompress"] {
+        let output = run_with_stdin(tmp.path(), &[command, "-", "-"], &data);
+        assert_eq!(output.status.code(), Some(2), "{}: {}", command, stderr(&output));
+        assert!(stderr(&output).contains("use -o - or -c"), "{}: {}", command, stderr(&output));
+        assert!(output.stdout.is_empty(), "{} wrote {} bytes", command, output.stdout.len());
+    }
+
+    // What `- -` used to mean: stdin to stdout, one frame and back.
+    let compressed = run_with_stdin(tmp.path(), &["compress", "-", "-o", "-"], &data);
+    assert!(compressed.status.success(), "{}", stderr(&compressed));
+    let decompressed = run_with_stdin(tmp.path(), &["decompress", "-", "-c"], &compressed.stdout);
+    assert!(decompressed.status.success(), "{}", stderr(&decompressed));
+    assert!(decompressed.stdout == data, "- -o - did not round trip");
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0, "a file was written");
+}
@@ -0,0 +1,40 @@
+//! `-c/--stdout` writes the result to stdout and leaves the input alone.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, TempDir};
+
+#[test]
+fn compress_and_decompress_to_stdout() {
+    let tmp = TempDir::new();
+    let data = mixed_data(500_000);
+    tmp.write("in.bin", &data);
+    let compressed = run_ok(tmp.path(), &["compress", "-c", "in.bin"]);
+    assert!(stderr(&compressed).contains("Compressed in.bin"), "{}", stderr(&compressed));
+    assert_eq!(std::fs::read(tmp.join("in.bin")).unwrap(), data, "the input was changed");
+    assert!(!tmp.join("in.bin.aapc").exists());
+
+    tmp.write("copy.aapc", &compressed.stdout);
+    let decompressed = run_ok(tmp.path(), &["decompress", "--stdout", "copy.aapc"]);
+    assert!(decompressed.stdout == data);
+    assert!(stderr(&decompressed).contains("Decompressed copy.aapc"), "{}", stderr(&decompressed));
+    assert_eq!(std::fs::read(tmp.join("copy.aapc")).unwrap(), compressed.stdout);
+    assert!(!tmp.join("copy").exists());
+}
+
+#[test]
+fn stdout_conflicts_with_other_outputs() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    for command in ["compress", "decompress"] {
+        for flags in [&["-o", "out"][..], &["--output-dir", "dir"], &["--rm"]] {
+            let mut args = vec![command, "-c", "in.bin"];
+            args.extend_from_slice(flags);
+            let output = run(tmp.path(), &args);
+            assert_eq!(output.status.code(), Some(2), "{:?}: {}", args, stderr(&output));
+            assert!(stderr(&output).contains("cannot be used with"), "{:?}: {}", args, stderr(&output));
+            assert!(output.stdout.is_empty());
+        }
+    }
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1, "a usage error wrote something");
+}
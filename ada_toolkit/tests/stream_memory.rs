@@ -0,0 +1,117 @@
+//! Compressing and decompressing through the streaming calls the CLI uses
+//! holds a few blocks at once, not the input: an input eight times the
+//! memory ceiling still goes through.
+//!
+//! This file is its own test binary so that the allocator, which refuses
+//! anything past the ceiling while armed, sees only what the one test in
+//! it allocates.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ada_toolkit::{copy_decode_with_phases, copy_encode_pipelined, CompressOptions, DecompressOptions};
+
+struct Ceiling;
+
+const CEILING: usize = 2 * 1024 * 1024;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Ceiling {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        if ARMED.load(Ordering::Relaxed) && live > CEILING {
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+            return std::ptr::null_mut();
+        }
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Ceiling = Ceiling;
+
+const BLOCK: usize = 64 * 1024;
+const INPUT: u64 = 8 * CEILING as u64;
+
+/// The byte at `pos` of the input: runs that compress, with a stretch of
+/// literals every 4 KiB so that the blocks are not all alike.
+fn byte_at(pos: u64) -> u8 {
+    match pos % 4096 {
+        offset if offset < 512 => (pos.wrapping_mul(2_654_435_761) >> 13) as u8,
+        _ => (pos / 4096) as u8,
+    }
+}
+
+/// `INPUT` bytes of input, made as they are read.
+struct Generated {
+    pos: u64,
+}
+
+impl Read for Generated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min((INPUT - self.pos) as usize);
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            *byte = byte_at(self.pos + i as u64);
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// Checks decoded output against the input as it is written, keeping none
+/// of it.
+struct Matching {
+    pos: u64,
+}
+
+impl Write for Matching {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (i, &byte) in buf.iter().enumerate() {
+            assert_eq!(byte, byte_at(self.pos + i as u64), "decoded byte {} differs", self.pos + i as u64);
+        }
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn an_input_eight_times_the_ceiling_streams_through() {
+    let opts = CompressOptions { block_size: BLOCK, threads: 2, ..CompressOptions::default() };
+    let (reader, writer) = io::pipe().unwrap();
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    ARMED.store(true, Ordering::Relaxed);
+
+    // As `compress` and `decompress` do, through buffered files, with the
+    // two ends joined by a pipe so the frame is not held either.
+    let encoding = std::thread::spawn(move || {
+        let mut out = BufWriter::new(writer);
+        let stats = copy_encode_pipelined(BufReader::new(Generated { pos: 0 }), &mut out, &opts, None).unwrap();
+        out.flush().unwrap();
+        stats
+    });
+    let mut out = Matching { pos: 0 };
+    let decode_opts = DecompressOptions { max_memory: None, threads: 2, cancel: None };
+    let (written, _) = copy_decode_with_phases(BufReader::new(reader), &mut out, &decode_opts, None).unwrap();
+    let stats = encoding.join().unwrap();
+
+    ARMED.store(false, Ordering::Relaxed);
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    assert_eq!((stats.input_bytes, written, out.pos), (INPUT, INPUT, INPUT));
+    assert!(stats.output_bytes < INPUT, "{} bytes of frame", stats.output_bytes);
+    assert!(peak <= CEILING, "streaming peaked at {} bytes", peak);
+}
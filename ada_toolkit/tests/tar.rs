@@ -0,0 +1,78 @@
+//! `compress --tar` and `decompress --untar`, and the tar stream in between.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run_ok, stderr, stdout, TempDir};
+
+/// A long path, past the 100 bytes a plain tar header holds.
+const LONG: &str = concat!(
+    "tree/a-directory-with-a-rather-long-name/",
+    "and-another-one-inside-it-just-as-long/the-file-at-the-end.txt",
+);
+
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("tree/big.bin", mixed_data(600_000));
+    tmp.write("tree/sub/small.txt", "small");
+    tmp.write("tree/sub/empty", "");
+    tmp.write(LONG, "long");
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("sub/small.txt", tmp.join("tree/link")).unwrap();
+    tmp
+}
+
+#[test]
+fn tar_round_trip() {
+    let tmp = tree();
+    run_ok(tmp.path(), &["compress", "--tar", "tree"]);
+    assert!(tmp.join("tree.tar.aapc").is_file());
+    run_ok(tmp.path(), &["decompress", "--untar", "tree.tar.aapc", "-C", "out"]);
+    for path in ["tree/big.bin", "tree/sub/small.txt", "tree/sub/empty", LONG] {
+        let unpacked = fs::read(tmp.join(&format!("out/{}", path))).unwrap();
+        assert_eq!(unpacked, fs::read(tmp.join(path)).unwrap(), "{}", path);
+    }
+    #[cfg(unix)]
+    assert_eq!(fs::read_link(tmp.join("out/tree/link")).unwrap().to_str(), Some("sub/small.txt"));
+}
+
+#[test]
+fn plain_decompress_gives_a_valid_tar() {
+    let tmp = tree();
+    run_ok(tmp.path(), &["compress", "--tar", "tree", "-o", "t.tar.aapc"]);
+    run_ok(tmp.path(), &["decompress", "t.tar.aapc"]);
+    let mut archive = tar::Archive::new(fs::File::open(tmp.join("t.tar")).unwrap());
+    let mut files = Vec::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_string_lossy().into_owned();
+        match entry.header().entry_type() {
+            tar::EntryType::Regular => {
+                let mut content = Vec::new();
+                std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+                assert_eq!(content, fs::read(tmp.join(&path)).unwrap(), "{}", path);
+                files.push(path);
+            }
+            tar::EntryType::Symlink => {
+                assert_eq!(entry.link_name().unwrap().unwrap().to_str(), Some("sub/small.txt"));
+            }
+            kind => assert!(kind.is_dir(), "{}: {:?}", path, kind),
+        }
+    }
+    files.sort();
+    assert_eq!(files, [LONG, "tree/big.bin", "tree/sub/empty", "tree/sub/small.txt"]);
+}
+
+#[test]
+fn the_summary_has_the_streamed_sizes_for_stdout_too() {
+    let tmp = tree();
+    let to_stdout = run_ok(tmp.path(), &["--bytes", "compress", "--tar", "tree", "-c"]);
+    let to_file = run_ok(tmp.path(), &["--bytes", "compress", "--tar", "tree"]);
+    let written = fs::metadata(tmp.join("tree.tar.aapc")).unwrap().len();
+    assert_eq!(to_stdout.stdout.len() as u64, written);
+    for (summary, name) in [(stderr(&to_stdout), "-"), (stdout(&to_file), "tree.tar.aapc")] {
+        assert!(summary.contains(&format!(" to {} ({} bytes) in ", name, written)), "{}", summary);
+        assert!(summary.contains(". Ratio: "), "{}", summary);
+    }
+}
@@ -0,0 +1,174 @@
+//! `test-folder`: the folders it takes, the log it writes and its summary.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+
+#[test]
+fn several_folders_are_tested_and_logged() {
+    let tmp = TempDir::new();
+    tmp.write("one/a.txt", "first");
+    tmp.write("two/b.txt", "second");
+    let output = run_ok(tmp.path(), &["test-folder", "one", "two"]);
+    assert!(stdout(&output).contains("Tested a.txt successfully"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Tested b.txt successfully"), "{}", stdout(&output));
+    let log = fs::read_to_string(tmp.join("test_log.txt")).unwrap();
+    assert!(log.contains("Folder: one\nFile: a.txt\n"), "{}", log);
+    assert!(log.contains("Folder: two\nFile: b.txt\n"), "{}", log);
+}
+
+#[test]
+fn the_folder_defaults_to_test_data() {
+    let tmp = TempDir::new();
+    tmp.write("test_data/c.bin", "third");
+    let output = run_ok(tmp.path(), &["test-folder"]);
+    assert!(stdout(&output).contains("Tested c.bin successfully"), "{}", stdout(&output));
+    assert!(fs::read_to_string(tmp.join("test_log.txt")).unwrap().contains("Folder: test_data\n"));
+}
+
+#[test]
+fn a_missing_folder_fails_before_testing_anything() {
+    let tmp = TempDir::new();
+    tmp.write("one/a.txt", "first");
+    tmp.write("not_a_dir", "file");
+    for missing in ["nope", "not_a_dir"] {
+        let output = run(tmp.path(), &["test-folder", "one", missing]);
+        assert_eq!(output.status.code(), Some(1), "{}: {}", missing, stderr(&output));
+        let message = format!("'{}' folder does not exist or is not a directory", missing);
+        assert!(stderr(&output).contains(&message), "{}: {}", missing, stderr(&output));
+        assert!(!stdout(&output).contains("Tested"), "{}: {}", missing, stdout(&output));
+    }
+}
+
+fn json_run(tmp: &TempDir, args: &[&str]) -> serde_json::Value {
+    let mut all = vec!["--format", "json", "test-folder", "--log-file", "log.txt"];
+    all.extend_from_slice(args);
+    serde_json::from_str(&stdout(&run_ok(tmp.path(), &all))).unwrap()
+}
+
+/// Two levels under `tree`, with files of known sizes.
+fn two_level_tree(tmp: &TempDir) {
+    for (path, size) in [("tree/a", 100), ("tree/b", 2000), ("tree/x/c", 30_000), ("tree/x/d", 7), ("tree/x/y/e", 1),
+                         ("tree/z/f", 50_000)] {
+        tmp.write(path, mixed_data(size));
+    }
+}
+
+#[test]
+fn recursive_rollups_add_up() {
+    let tmp = TempDir::new();
+    two_level_tree(&tmp);
+    let flat = json_run(&tmp, &["tree"]);
+    assert_eq!(flat["tested"], 2, "only the top level without --recursive");
+
+    let report = json_run(&tmp, &["--recursive", "tree"]);
+    let files = report["files"].as_array().unwrap();
+    let inputs: Vec<&str> = files.iter().map(|file| file["input"].as_str().unwrap()).collect();
+    assert_eq!(inputs, ["tree/a", "tree/b", "tree/x/c", "tree/x/d", "tree/x/y/e", "tree/z/f"]);
+    let dirs = report["directories"].as_array().unwrap();
+    let names: Vec<&str> = dirs.iter().map(|dir| dir["folder"].as_str().unwrap()).collect();
+    assert_eq!(names, ["tree", "tree/x", "tree/x/y", "tree/z"]);
+    for dir in dirs {
+        let name = dir["folder"].as_str().unwrap();
+        let inside: Vec<_> = files.iter()
+            .filter(|file| file["input"].as_str().unwrap().rsplit_once('/').unwrap().0 == name)
+            .collect();
+        let sum = |field: &str| inside.iter().map(|file| file[field].as_u64().unwrap()).sum::<u64>();
+        assert_eq!(dir["files"], inside.len(), "{}", name);
+        assert_eq!(dir["input_bytes"], sum("input_bytes"), "{}", name);
+        assert_eq!(dir["compressed_bytes"], sum("compressed_bytes"), "{}", name);
+        let ratio = sum("compressed_bytes") as f64 / sum("input_bytes") as f64;
+        assert!((dir["ratio"].as_f64().unwrap() - ratio).abs() < 1e-3, "{}: {}", name, dir);
+    }
+    let total = |field: &str| dirs.iter().map(|dir| dir[field].as_u64().unwrap()).sum::<u64>();
+    assert_eq!(report["summary"]["input_bytes"], total("input_bytes"));
+    assert_eq!(report["summary"]["compressed_bytes"], total("compressed_bytes"));
+}
+
+#[test]
+fn recursive_text_lists_subtotals_in_order() {
+    let tmp = TempDir::new();
+    two_level_tree(&tmp);
+    let subtotals = || -> Vec<String> {
+        let text = stdout(&run_ok(tmp.path(), &["test-folder", "--recursive", "tree"]));
+        text.lines().filter_map(|line| line.strip_prefix("Subtotal for ")).map(|line| line.split(". ").next().unwrap())
+            .map(String::from).collect()
+    };
+    let first = subtotals();
+    let names: Vec<&str> = first.iter().map(|line| line.split(':').next().unwrap()).collect();
+    assert_eq!(names, ["tree", "tree/x", "tree/x/y", "tree/z"]);
+    assert!(first[1].starts_with("tree/x: 2 files, "), "{:?}", first);
+    assert_eq!(subtotals(), first);
+}
+
+/// The log without the lines that depend on timing.
+fn untimed_log(tmp: &TempDir) -> Vec<String> {
+    let log = fs::read_to_string(tmp.join("test_log.txt")).unwrap();
+    log.lines().filter(|line| !line.contains("Time") && !line.contains("Speed")).map(String::from).collect()
+}
+
+#[test]
+fn threads_change_neither_results_nor_order() {
+    let tmp = TempDir::new();
+    for i in 0..12 {
+        // Sizes that finish out of order on a pool: big files first.
+        tmp.write(&format!("data/f{:02}", i), mixed_data(200_000 / (i + 1)));
+    }
+    let one = stdout(&run_ok(tmp.path(), &["--threads", "1", "test-folder", "data"]));
+    let one_log = untimed_log(&tmp);
+    let many = stdout(&run_ok(tmp.path(), &["--threads", "6", "test-folder", "data"]));
+    assert_eq!(untimed_log(&tmp), one_log);
+    let tested = |text: &str| -> Vec<String> {
+        text.lines().filter(|line| line.starts_with("Tested ")).map(String::from).collect()
+    };
+    assert_eq!(tested(&many), tested(&one));
+    assert_eq!(tested(&one).len(), 12);
+    assert!(one.contains("on 1 threads"), "{}", one);
+    assert!(many.contains("wall-clock on 6 threads (") && many.contains("summed per-file time)"), "{}", many);
+}
+
+#[test]
+fn summaries_match_the_per_file_entries() {
+    let tmp = TempDir::new();
+    two_level_tree(&tmp);
+    let report = json_run(&tmp, &["--recursive", "tree"]);
+    let files = report["files"].as_array().unwrap();
+    let sum = |field: &str| files.iter().map(|file| file[field].as_u64().unwrap()).sum::<u64>();
+    let (input, compressed) = (sum("input_bytes"), sum("compressed_bytes"));
+    let summary = &report["summary"];
+    assert_eq!(summary["files"], files.len());
+    assert_eq!(summary["failed"], 0);
+    assert_eq!(summary["input_bytes"], input);
+    assert_eq!(summary["compressed_bytes"], compressed);
+    assert!((summary["ratio"].as_f64().unwrap() - compressed as f64 / input as f64).abs() < 1e-3, "{}", summary);
+    let by_ratio = |pick: fn(f64, f64) -> bool| {
+        let mut best = &files[0];
+        for file in files {
+            if pick(file["ratio"].as_f64().unwrap(), best["ratio"].as_f64().unwrap()) {
+                best = file;
+            }
+        }
+        best["input"].clone()
+    };
+    assert_eq!(summary["lowest_ratio"]["input"], by_ratio(|a, b| a < b));
+    assert_eq!(summary["highest_ratio"]["input"], by_ratio(|a, b| a > b));
+
+    let text = stdout(&run_ok(tmp.path(), &["--bytes", "test-folder", "--recursive", "tree"]));
+    let line = format!("Summary: 6 files tested, 0 failed, 0 filtered out, 0 skipped. {} bytes to {} bytes.",
+                       input, compressed);
+    assert!(text.contains(&line), "{}", text);
+    let log = fs::read_to_string(tmp.join("test_log.txt")).unwrap();
+    let section = &log[log.rfind("Summary\n").expect("a summary section")..];
+    let lowest = &summary["lowest_ratio"];
+    for line in [
+        "Files: 6\n".to_string(),
+        "Failed: 0\n".to_string(),
+        format!("Original Size: {} bytes\n", input),
+        format!("Compressed Size: {} bytes\n", compressed),
+        format!("Lowest Ratio: {:.2} ({})\n", lowest["ratio"].as_f64().unwrap(), lowest["input"].as_str().unwrap()),
+    ] {
+        assert!(section.contains(&line), "{:?} missing from\n{}", line, section);
+    }
+}
@@ -0,0 +1,55 @@
+//! `--threads` changes how fast frames are made, never what is in them.
+
+mod common;
+
+use std::fs;
+
+use common::{mixed_data, run, run_ok, stderr, TempDir};
+
+#[test]
+fn one_and_eight_threads_write_the_same_frame() {
+    let tmp = TempDir::new();
+    let data = mixed_data(2_000_000);
+    tmp.write("in.bin", &data);
+    let mut frames = Vec::new();
+    for threads in ["1", "8", "auto", "0"] {
+        let args = ["--verbose", "--threads", threads, "compress", "-f", "--block-size", "64k", "in.bin"];
+        let output = run_ok(tmp.path(), &args);
+        if threads != "auto" && threads != "0" {
+            let line = format!("Coding blocks on up to {} threads", threads);
+            assert!(stderr(&output).contains(&line), "--threads {}: {}", threads, stderr(&output));
+        }
+        frames.push(fs::read(tmp.join("in.bin.aapc")).unwrap());
+    }
+    assert!(frames.iter().all(|frame| *frame == frames[0]), "thread counts gave different frames");
+
+    for threads in ["1", "8"] {
+        run_ok(tmp.path(), &["--threads", threads, "decompress", "-f", "in.bin.aapc"]);
+        assert!(fs::read(tmp.join("in.bin")).unwrap() == data, "--threads {} did not round trip", threads);
+    }
+}
+
+#[test]
+fn directory_runs_match_across_thread_counts() {
+    let tmp = TempDir::new();
+    for i in 0..4 {
+        tmp.write(&format!("src/f{}", i), mixed_data(300_000 + i * 1000));
+    }
+    run_ok(tmp.path(), &["--threads", "1", "compress-dir", "src", "one"]);
+    run_ok(tmp.path(), &["--threads", "8", "compress-dir", "src", "eight"]);
+    for i in 0..4 {
+        let name = format!("f{}.aapc", i);
+        let one = fs::read(tmp.join(&format!("one/{}", name))).unwrap();
+        assert_eq!(one, fs::read(tmp.join(&format!("eight/{}", name))).unwrap(), "{}", name);
+    }
+}
+
+#[test]
+fn a_bad_thread_count_is_a_usage_error() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", "data");
+    for threads in ["-1", "many", ""] {
+        let output = run(tmp.path(), &["--threads", threads, "compress", "in.bin"]);
+        assert_eq!(output.status.code(), Some(2), "--threads {:?}: {}", threads, stderr(&output));
+    }
+}
@@ -0,0 +1,135 @@
+//! The spans and events the `tracing` feature emits for a two-block frame.
+
+#![cfg(feature = "tracing")]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use ada_toolkit::{compress_with_options, decompress, CompressOptions};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// A span or event, with the name of the span it sits in.
+#[derive(Debug, Clone, Default)]
+struct Captured {
+    name: String,
+    parent: Option<String>,
+    fields: BTreeMap<String, String>,
+}
+
+impl Captured {
+    fn field(&self, name: &str) -> &str {
+        self.fields.get(name).map_or("", String::as_str)
+    }
+}
+
+impl Visit for Captured {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields.insert(field.name().to_string(), format!("{:?}", value).trim_matches('"').to_string());
+    }
+}
+
+/// Keeps every span, with the fields recorded on it later, and every event.
+#[derive(Clone, Default)]
+struct Capture {
+    spans: Arc<Mutex<Vec<(Id, Captured)>>>,
+    events: Arc<Mutex<Vec<Captured>>>,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let parent = ctx.span(id).and_then(|span| span.parent()).map(|parent| parent.name().to_string());
+        let mut span = Captured { name: attrs.metadata().name().to_string(), parent, ..Captured::default() };
+        attrs.record(&mut span);
+        self.spans.lock().unwrap().push((id.clone(), span));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _: Context<'_, S>) {
+        let mut spans = self.spans.lock().unwrap();
+        if let Some((_, span)) = spans.iter_mut().rev().find(|(span_id, _)| span_id == id) {
+            values.record(span);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let parent = ctx.event_span(event).map(|span| span.name().to_string());
+        let mut captured = Captured { name: event.metadata().name().to_string(), parent, ..Captured::default() };
+        event.record(&mut captured);
+        self.events.lock().unwrap().push(captured);
+    }
+}
+
+/// The spans and events `f` emits.
+fn capture(f: impl FnOnce()) -> (Vec<Captured>, Vec<Captured>) {
+    let capture = Capture::default();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(capture.clone()), f);
+    let spans = capture.spans.lock().unwrap().iter().map(|(_, span)| span.clone()).collect();
+    let events = capture.events.lock().unwrap().clone();
+    (spans, events)
+}
+
+const BLOCK: usize = 8192;
+
+/// A block of runs, then one of noise that RLE cannot shrink.
+fn two_blocks() -> Vec<u8> {
+    let mut data = vec![7u8; BLOCK];
+    let mut state = 0x2545_F491u32;
+    data.extend((0..BLOCK).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }));
+    data
+}
+
+fn opts() -> CompressOptions {
+    CompressOptions { block_size: BLOCK, ..CompressOptions::default() }
+}
+
+#[test]
+fn encode_spans_nest_under_the_frame() {
+    let data = two_blocks();
+    let mut frame = Vec::new();
+    let (spans, events) = capture(|| frame = compress_with_options(&data, &opts()).unwrap());
+
+    let names: Vec<&str> = spans.iter().map(|span| span.name.as_str()).collect();
+    assert_eq!(names, ["encode_frame", "encode_block", "encode_block"]);
+    assert_eq!(spans[0].parent, None);
+    assert_eq!((spans[0].field("input"), spans[0].field("block_size")), ("16384", "8192"));
+
+    let (runs, noise) = (&spans[1], &spans[2]);
+    for (span, index) in [(runs, "0"), (noise, "1")] {
+        assert_eq!(span.parent.as_deref(), Some("encode_frame"));
+        assert_eq!((span.field("index"), span.field("input")), (index, "8192"));
+    }
+    assert_eq!(runs.field("codec"), "rle");
+    assert!(runs.field("output").parse::<usize>().unwrap() < 100, "{:?}", runs);
+    assert_eq!((noise.field("codec"), noise.field("output")), ("stored", "8192"));
+
+    let fallback: Vec<_> = events.iter().filter(|event| event.field("message").contains("storing it")).collect();
+    assert_eq!(fallback.len(), 1, "{:?}", events);
+    assert_eq!(fallback[0].parent.as_deref(), Some("encode_block"));
+    assert_eq!(fallback[0].field("index"), "1");
+}
+
+#[test]
+fn decode_spans_nest_under_the_frame() {
+    let data = two_blocks();
+    let frame = compress_with_options(&data, &opts()).unwrap();
+    let (spans, _) = capture(|| assert_eq!(decompress(&frame).unwrap(), data));
+
+    let names: Vec<&str> = spans.iter().map(|span| span.name.as_str()).collect();
+    assert_eq!(names, ["decode_frame", "decode_block", "decode_block"]);
+    assert_eq!(spans[0].field("input"), frame.len().to_string());
+    for (span, index, codec) in [(&spans[1], "0", "rle"), (&spans[2], "1", "stored")] {
+        assert_eq!(span.parent.as_deref(), Some("decode_frame"));
+        assert_eq!((span.field("index"), span.field("output"), span.field("codec")), (index, "8192", codec));
+    }
+    assert_eq!(spans[2].field("input"), "8192");
+}
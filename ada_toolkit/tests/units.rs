@@ -0,0 +1,33 @@
+//! Summaries show binary units by default and raw numbers with `--bytes`.
+
+mod common;
+
+use common::{run_ok, stdout, TempDir};
+
+#[test]
+fn bytes_forces_raw_numbers() {
+    let tmp = TempDir::new();
+    tmp.write("in.bin", vec![0; 3 << 19]);
+    let human = stdout(&run_ok(tmp.path(), &["compress", "in.bin"]));
+    assert!(human.contains("Compressed in.bin (1.50 MiB) to in.bin.aapc ("), "{}", human);
+    assert!(human.contains(" MiB/s. Ratio: "), "{}", human);
+    assert!(human.contains("% saved)"), "{}", human);
+
+    let raw = stdout(&run_ok(tmp.path(), &["--bytes", "compress", "-f", "in.bin"]));
+    assert!(raw.contains("Compressed in.bin (1572864 bytes) to in.bin.aapc ("), "{}", raw);
+    assert!(raw.contains(" bytes/s. Ratio: "), "{}", raw);
+}
+
+#[test]
+fn an_empty_input_saves_nothing() {
+    let tmp = TempDir::new();
+    tmp.write("empty", "");
+    let text = stdout(&run_ok(tmp.path(), &["compress", "empty"]));
+    assert!(text.trim_end().ends_with(". Ratio: 1.00 (0.0% saved)"), "{}", text);
+    let test = stdout(&run_ok(tmp.path(), &["test", "empty"]));
+    assert!(test.contains("(ratio: 1.00, 0.0% saved)"), "{}", test);
+
+    let json = stdout(&run_ok(tmp.path(), &["--format", "json", "compress", "-f", "empty"]));
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["files"][0]["ratio"], 1.0, "{}", json);
+}
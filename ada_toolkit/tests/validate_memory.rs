@@ -0,0 +1,79 @@
+//! Validating a frame takes memory bounded by its block size, not by the
+//! size of its content.
+//!
+//! This file is its own test binary so that the counting allocator sees
+//! only what the one test in it allocates.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ada_toolkit::{compress_with_options, validate, validate_reader, CompressOptions};
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Bytes allocated at the peak of `f`, beyond what was live before it.
+fn peak_of<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed) - before)
+}
+
+const BLOCK: usize = 512 * 1024;
+const BLOCKS: u32 = 8;
+
+/// A frame of `BLOCKS` 512 KiB blocks of zeros, each checksummed: 64 times
+/// the memory `validate` may use, in a frame of about 50 KiB. It is put
+/// together from a one-block frame, since encoding all of it would take
+/// most of the test's time.
+fn huge_frame() -> Vec<u8> {
+    let opts = CompressOptions { block_size: BLOCK, content_checksum: false, ..CompressOptions::default() };
+    let one = compress_with_options(&vec![0; BLOCK], &opts).unwrap();
+    let (header, block) = (&one[..10], &one[10..one.len() - 13]);
+    let mut frame = header.to_vec();
+    for _ in 0..BLOCKS {
+        frame.extend_from_slice(block);
+    }
+    frame.push(0);
+    frame.extend_from_slice(&BLOCKS.to_be_bytes());
+    frame.extend_from_slice(&(u64::from(BLOCKS) * BLOCK as u64).to_be_bytes());
+    frame
+}
+
+#[test]
+fn validating_a_huge_frame_stays_small() {
+    let frame = huge_frame();
+
+    let (info, peak) = peak_of(|| validate(&frame).unwrap());
+    assert_eq!((info.block_count, info.content_size), (BLOCKS, u64::from(BLOCKS) * BLOCK as u64));
+    assert!(peak <= 64 * 1024, "validate peaked at {} bytes", peak);
+
+    let (info, peak) = peak_of(|| validate_reader(frame.as_slice()).unwrap());
+    assert_eq!(info.content_size, u64::from(BLOCKS) * BLOCK as u64);
+    assert!(peak <= 4 * BLOCK, "validate_reader peaked at {} bytes", peak);
+
+    let mut corrupt = frame;
+    let last_crc = corrupt.len() - 13 - (corrupt.len() - 23) / BLOCKS as usize + 9;
+    corrupt[last_crc] ^= 1;
+    assert!(validate(&corrupt).is_err());
+    assert!(validate_reader(corrupt.as_slice()).is_err());
+}
@@ -0,0 +1,65 @@
+//! `verify` checks frames without writing their content anywhere.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, run_with_stdin, stderr, stdout, TempDir};
+
+#[test]
+fn good_and_damaged_frames() {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", mixed_data(300_000));
+    tmp.write("b.bin", "short");
+    run_ok(tmp.path(), &["compress", "a.bin", "b.bin"]);
+    run_ok(tmp.path(), &["verify", "a.bin.aapc", "b.bin.aapc"]);
+
+    let mut frame = std::fs::read(tmp.join("a.bin.aapc")).unwrap();
+    let last = frame.len() - 30;
+    frame[last] ^= 0x08;
+    tmp.write("bad.aapc", frame);
+    let output = run(tmp.path(), &["verify", "a.bin.aapc", "bad.aapc"]);
+    let log = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(matches!(output.status.code(), Some(3 | 4)), "{}", log);
+    assert!(log.contains("a.bin.aapc: OK"), "{}", log);
+    assert!(log.contains("bad.aapc: FAILED"), "{}", log);
+    assert!(log.contains("1 of 2 files failed"), "{}", log);
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 5, "verify wrote something");
+}
+
+#[test]
+fn json_statuses_per_file() {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", mixed_data(300_000));
+    run_ok(tmp.path(), &["compress", "a.bin"]);
+    let mut frame = std::fs::read(tmp.join("a.bin.aapc")).unwrap();
+    let middle = frame.len() / 2;
+    frame[middle] ^= 0x01;
+    tmp.write("bad.aapc", frame);
+    let output = run(tmp.path(), &["--format", "json", "verify", "a.bin.aapc", "bad.aapc"]);
+    assert!(matches!(output.status.code(), Some(3 | 4)), "{}", stderr(&output));
+    let summary: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(summary["ok"], 1);
+    assert_eq!(summary["failed"], 1);
+    let files = summary["files"].as_array().unwrap();
+    assert_eq!(files[0]["input"], "a.bin.aapc");
+    assert_eq!(files[0]["status"], "ok");
+    assert_eq!(files[0]["content_size"], 300_000);
+    assert_eq!(files[1]["input"], "bad.aapc");
+    assert_eq!(files[1]["status"], "failed");
+    assert_eq!(files[1]["exit_code"], output.status.code().unwrap());
+    assert!(files[1]["error"].as_str().unwrap().contains("bad.aapc"), "{}", files[1]);
+}
+
+#[test]
+fn stdin_is_verified_too() {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", mixed_data(100_000));
+    run_ok(tmp.path(), &["compress", "a.bin"]);
+    let frame = std::fs::read(tmp.join("a.bin.aapc")).unwrap();
+    let output = run_with_stdin(tmp.path(), &["verify", "-"], &frame);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("-: OK"), "{}", stdout(&output));
+
+    let output = run_with_stdin(tmp.path(), &["verify", "-"], &frame[..frame.len() - 1]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stdout(&output).contains("-: FAILED"), "{}", stdout(&output));
+}
@@ -0,0 +1,74 @@
+//! --verify-after-write reads each output back once it is written, and
+//! says so in the summary, the JSON and the --report file.
+
+mod common;
+
+use common::{mixed_data, run, run_ok, stderr, stdout, TempDir};
+use serde_json::Value;
+
+fn setup() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("a.bin", mixed_data(200_000));
+    tmp.write("tree/b.bin", mixed_data(50_000));
+    tmp.write("tree/sub/c.txt", "c");
+    tmp
+}
+
+#[test]
+fn compress_reports_each_output_verified() {
+    let tmp = setup();
+    let text = stdout(&run_ok(tmp.path(), &["compress", "a.bin", "--verify-after-write"]));
+    assert!(text.trim_end().ends_with(". Verified after writing"), "{}", text);
+    let plain = stdout(&run_ok(tmp.path(), &["compress", "a.bin", "-f"]));
+    assert!(!plain.contains("Verified"), "{}", plain);
+
+    let args = ["--format", "json", "compress", "a.bin", "-f", "--verify-after-write", "--report", "run.json"];
+    let json: Value = serde_json::from_str(&stdout(&run_ok(tmp.path(), &args))).unwrap();
+    assert_eq!(json["files"][0]["verified"], true);
+    let report: Value = serde_json::from_str(&std::fs::read_to_string(tmp.join("run.json")).unwrap()).unwrap();
+    assert_eq!(report["files"][0]["verified"], true, "{}", report);
+    assert_eq!(report["options"]["verify_after_write"], true, "{}", report);
+
+    let unverified = run_ok(tmp.path(), &["--format", "json", "compress", "a.bin", "-f"]);
+    let json: Value = serde_json::from_str(&stdout(&unverified)).unwrap();
+    assert_eq!(json["files"][0]["verified"], false);
+}
+
+#[test]
+fn a_checkpointed_run_verifies_its_partial_output() {
+    let tmp = setup();
+    let text = stdout(&run_ok(tmp.path(), &["compress", "a.bin", "--checkpoint", "state", "--verify-after-write"]));
+    assert!(text.trim_end().ends_with(". Verified after writing"), "{}", text);
+    assert!(!tmp.join(".a.bin.aapc.partial").exists());
+    assert!(!tmp.join("state").exists());
+    assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "a.bin.aapc"]).stdout, mixed_data(200_000));
+}
+
+#[test]
+fn stdout_cannot_be_read_back() {
+    let tmp = setup();
+    let output = run_ok(tmp.path(), &["compress", "a.bin", "-c", "--verify-after-write"]);
+    assert!(stderr(&output).contains("Cannot verify stdout after writing: it is not a regular file"), "{}",
+            stderr(&output));
+    assert!(!stderr(&output).contains("Verified after writing"), "{}", stderr(&output));
+    tmp.write("a.aapc", &output.stdout);
+    assert_eq!(run_ok(tmp.path(), &["decompress", "-c", "a.aapc"]).stdout, mixed_data(200_000));
+}
+
+#[test]
+fn archive_create_verifies_the_archive() {
+    let tmp = setup();
+    let text = stdout(&run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree", "--verify-after-write"]));
+    assert!(text.trim_end().ends_with("; verified after writing"), "{}", text);
+    run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out"]);
+    assert_eq!(std::fs::read(tmp.join("out/tree/b.bin")).unwrap(), mixed_data(50_000));
+}
+
+#[test]
+fn keep_corrupt_needs_verify_after_write() {
+    let tmp = setup();
+    let output = run(tmp.path(), &["compress", "a.bin", "--keep-corrupt"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("--verify-after-write"), "{}", stderr(&output));
+    assert!(!tmp.join("a.bin.aapc").exists());
+}
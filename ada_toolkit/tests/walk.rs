@@ -0,0 +1,69 @@
+//! Directory walks over trees holding symlink loops and special files.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::process::Command;
+
+use common::{run, run_ok, stderr, stdout, TempDir};
+
+/// A tree with two files, a symlink loop back to its root, a link to
+/// itself and a FIFO.
+fn awkward_tree() -> TempDir {
+    let tmp = TempDir::new();
+    tmp.write("tree/a.txt", "first file");
+    tmp.write("tree/sub/b.txt", "second file");
+    symlink("..", tmp.join("tree/sub/loop")).unwrap();
+    symlink("self", tmp.join("tree/self")).unwrap();
+    let made = Command::new("mkfifo").arg(tmp.join("tree/fifo")).status().unwrap();
+    assert!(made.success());
+    tmp
+}
+
+#[test]
+fn test_folder_skips_links_and_fifos() {
+    let tmp = awkward_tree();
+    let output = run_ok(tmp.path(), &["test-folder", "--recursive", "tree"]);
+    let log = stderr(&output);
+    assert!(log.contains("Skipping tree/fifo: a FIFO"), "{}", log);
+    assert!(log.contains("Skipping symlink tree/sub/loop"), "{}", log);
+    assert!(log.contains("Skipping symlink tree/self"), "{}", log);
+}
+
+#[test]
+fn following_a_symlink_loop_terminates() {
+    let tmp = awkward_tree();
+    let output = run(tmp.path(), &["test-folder", "--recursive", "--follow-symlinks", "tree"]);
+    let log = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(log.contains("Skipping tree/fifo: a FIFO"), "{}", log);
+    assert!(log.contains("Skipping tree/sub/loop: already visited"), "{}", log);
+    // A link to itself cannot be read, and that counts as a failed file.
+    assert!(log.contains("FAILED reading tree/self"), "{}", log);
+    assert!(log.contains("Tested sub/b.txt successfully"), "{}", log);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn compress_dir_mirrors_only_regular_files() {
+    let tmp = awkward_tree();
+    let output = run_ok(tmp.path(), &["compress-dir", "tree", "out"]);
+    assert!(stderr(&output).contains("a FIFO"), "{}", stderr(&output));
+    assert!(tmp.join("out/a.txt.aapc").is_file());
+    assert!(tmp.join("out/sub/b.txt.aapc").is_file());
+    assert!(fs::symlink_metadata(tmp.join("out/fifo.aapc")).is_err());
+    assert!(fs::symlink_metadata(tmp.join("out/sub/loop")).is_err());
+}
+
+#[test]
+fn archive_stores_links_as_links_and_skips_fifos() {
+    let tmp = awkward_tree();
+    let output = run_ok(tmp.path(), &["archive", "create", "t.aapa", "tree"]);
+    assert!(stderr(&output).contains("Skipping tree/fifo: a FIFO"), "{}", stderr(&output));
+    run_ok(tmp.path(), &["archive", "extract", "t.aapa", "-C", "out"]);
+    assert_eq!(fs::read_link(tmp.join("out/tree/sub/loop")).unwrap().to_str(), Some(".."));
+    assert_eq!(fs::read_link(tmp.join("out/tree/self")).unwrap().to_str(), Some("self"));
+    assert!(fs::symlink_metadata(tmp.join("out/tree/fifo")).is_err());
+}
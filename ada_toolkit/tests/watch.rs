@@ -0,0 +1,112 @@
+//! `watch`: files dropped into a directory are compressed once they stop
+//! changing, and watching goes on until Ctrl+C.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{cli, mixed_data, run_ok, TempDir};
+
+/// Starts watching `in` into `out`, giving the watcher time to start.
+fn watch(tmp: &TempDir, extra: &[&str]) -> Child {
+    fs::create_dir_all(tmp.join("in")).unwrap();
+    let child = cli(tmp.path())
+        .args(["watch", "in", "--output-dir", "out", "--debounce", "0.3"])
+        .args(extra)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    child
+}
+
+/// Waits up to 20 seconds for `path` to appear.
+fn wait_for(path: &Path) {
+    let started = Instant::now();
+    while !path.exists() {
+        assert!(started.elapsed() < Duration::from_secs(20), "{} never appeared", path.display());
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Stops the watcher with Ctrl+C and returns its stdout and stderr.
+fn stop(child: Child) -> (String, String) {
+    let sent = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(sent.success());
+    let output = child.wait_with_output().unwrap();
+    let (out, err) = (String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "{}{}", out, err);
+    (out.into_owned(), err.into_owned())
+}
+
+fn decompressed(tmp: &TempDir, frame: &str) -> Vec<u8> {
+    run_ok(tmp.path(), &["decompress", "-c", frame]).stdout
+}
+
+#[test]
+fn new_files_are_compressed_into_the_output_dir() {
+    let tmp = TempDir::new();
+    let child = watch(&tmp, &["--include", "*.csv"]);
+    tmp.write("in/a.csv", mixed_data(40_000));
+    tmp.write("in/sub/b.csv", mixed_data(7_000));
+    tmp.write("in/notes.txt", "not a csv");
+    wait_for(&tmp.join("out/a.csv.aapc"));
+    wait_for(&tmp.join("out/sub/b.csv.aapc"));
+    assert_eq!(decompressed(&tmp, "out/a.csv.aapc"), mixed_data(40_000));
+    assert_eq!(decompressed(&tmp, "out/sub/b.csv.aapc"), mixed_data(7_000));
+
+    // A changed input replaces the output this run wrote for it.
+    tmp.write("in/a.csv", "a new export");
+    let started = Instant::now();
+    while decompressed(&tmp, "out/a.csv.aapc") != b"a new export" {
+        assert!(started.elapsed() < Duration::from_secs(20), "the change was never compressed");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let (out, _) = stop(child);
+    assert!(out.contains("Compressed in/a.csv"), "{}", out);
+    assert!(out.contains("Stopped watching in: 3 files compressed, 0 failed"), "{}", out);
+    assert!(!tmp.join("out/notes.txt.aapc").exists());
+}
+
+#[test]
+fn a_file_still_being_written_waits_until_it_settles() {
+    let tmp = TempDir::new();
+    let child = watch(&tmp, &[]);
+    let data = mixed_data(100_000);
+    let mut file = OpenOptions::new().create(true).append(true).open(tmp.join("in/slow.bin")).unwrap();
+    for chunk in data.chunks(10_000) {
+        file.write_all(chunk).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(!tmp.join("out/slow.bin.aapc").exists(), "compressed while still being written");
+    }
+    drop(file);
+    wait_for(&tmp.join("out/slow.bin.aapc"));
+    assert_eq!(decompressed(&tmp, "out/slow.bin.aapc"), data);
+
+    let (out, _) = stop(child);
+    assert!(out.contains("1 files compressed"), "{}", out);
+}
+
+#[test]
+fn an_output_that_was_there_first_is_left_alone_without_force() {
+    let tmp = TempDir::new();
+    tmp.write("out/a.bin.aapc", "an older output");
+    let child = watch(&tmp, &["--log-level", "warn"]);
+    tmp.write("in/a.bin", "data");
+    tmp.write("in/b.bin", "more data");
+    wait_for(&tmp.join("out/b.bin.aapc"));
+
+    let (out, err) = stop(child);
+    assert_eq!(fs::read(tmp.join("out/a.bin.aapc")).unwrap(), b"an older output");
+    assert!(err.contains("already exists; use -f to overwrite"), "{}", err);
+    assert!(out.contains("1 files compressed"), "{}", out);
+}